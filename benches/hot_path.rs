@@ -0,0 +1,109 @@
+//! 热点路径基准测试
+//!
+//! 运行方式: `cargo bench --bench hot_path`
+
+use std::hint::black_box;
+
+use chrono::{Duration, NaiveDateTime, Utc};
+use criterion::{Criterion, criterion_group, criterion_main};
+use taranis::charge::Charge;
+use taranis::conf::{CONF, UpdateMode, WireEncoding};
+use taranis::detail::ChargingDetail;
+use taranis::message::{MSG, MessageType};
+use taranis::price::Prices;
+use taranis::update::UpdateEncoder;
+
+/// 状态更新的虚拟时间间隔
+const STEP: Duration = Duration::seconds(5);
+
+/// 每个充电会话模拟的更新次数，之后重新开始一个会话，即一个虚拟日
+const UPDATES_PER_SESSION: i32 = 17_280;
+
+/// 开始一个新的充电会话
+fn start_session() -> Charge {
+    let mut charge = Charge::new(CONF.charge.charge_type, CONF.charge.power, 1);
+    charge.add_detail(ChargingDetail::test_new(1)).unwrap();
+    charge.start_charging();
+    charge
+}
+
+fn bench_price(c: &mut Criterion) {
+    let mut prices = Prices::default();
+    prices.optimize().unwrap();
+    let start = NaiveDateTime::parse_from_str("2025-06-01 07:30:00", "%Y-%m-%d %H:%M:%S").unwrap();
+    c.bench_function("calc_price 1h", |b| {
+        b.iter(|| black_box(prices.calc_price(start, start + Duration::hours(1), 30.0, 0.0)).ok())
+    });
+    c.bench_function("calc_price 24h", |b| {
+        b.iter(|| black_box(prices.calc_price(start, start + Duration::hours(24), 30.0, 0.0)).ok())
+    });
+    c.bench_function("calc_price_breakdown 24h", |b| {
+        b.iter(|| {
+            black_box(prices.calc_price_breakdown(start, start + Duration::hours(24), 30.0, 0.0))
+                .ok()
+        })
+    });
+}
+
+fn bench_update(c: &mut Criterion) {
+    // 与 `taranis bench` 相同的一次完整更新：计算费用、更新详单并序列化更新消息，
+    // 会话持续一个虚拟日后重新开始，账本大小保持在实际运行的范围内
+    c.bench_function("update_charging + serialization", |b| {
+        let mut charge = start_session();
+        let mut start = charge.get_charging_detail_ref().unwrap().clone_start_time();
+        let mut encoder = UpdateEncoder::new(UpdateMode::Full, 1);
+        let mut step = 0;
+        b.iter(|| {
+            if step == UPDATES_PER_SESSION {
+                charge = start_session();
+                start = charge.get_charging_detail_ref().unwrap().clone_start_time();
+                step = 0;
+            }
+            step += 1;
+            charge.update_charging_at(start + STEP * step);
+            let msg = encoder.snapshot(charge.get_charging_detail_ref().unwrap());
+            black_box(serde_json::to_string(&msg).unwrap())
+        })
+    });
+
+    let detail = ChargingDetail::test_new(1);
+    c.bench_function("update serialization", |b| {
+        b.iter(|| {
+            let msg = MSG::with_payload(MessageType::Update, &detail);
+            black_box(serde_json::to_string(&msg).unwrap())
+        })
+    });
+}
+
+fn bench_encoding(c: &mut Criterion) {
+    // 典型的充电中状态更新，比较两种编码的编解码耗时和消息大小
+    let charge = start_session();
+    let mut update = MSG::with_payload(
+        MessageType::Update,
+        charge.get_charging_detail_ref().unwrap(),
+    );
+    update.msg_id = Some(42);
+    update.sent_at = Some(Utc::now());
+    let json = serde_json::to_string(&update).unwrap();
+    let msgpack = rmp_serde::to_vec_named(&update).unwrap();
+    println!(
+        "update size: {} bytes json, {} bytes msgpack",
+        json.len(),
+        msgpack.len()
+    );
+    c.bench_function("update encode json", |b| {
+        b.iter(|| black_box(update.to_frame(WireEncoding::Json)))
+    });
+    c.bench_function("update encode msgpack", |b| {
+        b.iter(|| black_box(update.to_frame(WireEncoding::Msgpack)))
+    });
+    c.bench_function("update decode json", |b| {
+        b.iter(|| black_box(serde_json::from_str::<MSG>(&json).unwrap()))
+    });
+    c.bench_function("update decode msgpack", |b| {
+        b.iter(|| black_box(MSG::from_msgpack(&msgpack).unwrap()))
+    });
+}
+
+criterion_group!(benches, bench_price, bench_update, bench_encoding);
+criterion_main!(benches);
@@ -0,0 +1,388 @@
+//! 协议消息审计日志
+//!
+//! 设置 `audit.path` 时，充电桩收发的每条协议消息都追加为一行 JSON（格式见 [`AuditRecord`]），
+//! 与面向人的日志分开，用于事后复盘。收发消息的循环只把记录放入有界通道，由单独的线程写入文件，
+//! 不会因为磁盘变慢而阻塞；通道满时丢弃记录并计数。
+//! 文件超过 `audit.max_size_mb` 时轮换为 `<path>.1`、`<path>.2`……，最多保留 `audit.max_files` 个旧文件。
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Mutex, OnceLock};
+use std::thread::JoinHandle;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::conf::AuditConf;
+use crate::message::{MSG, MessageType};
+use crate::persist;
+use crate::throttle;
+use crate::time::get_mock_now;
+
+/// 等待写入的记录数上限，超过时丢弃新的记录
+pub const AUDIT_CHANNEL_CAPACITY: usize = 4096;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+/// 消息方向
+pub enum Direction {
+    /// 充电桩收到的消息
+    In,
+    /// 充电桩发送的消息
+    Out,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+/// 审计日志的一行，字段名和顺序保持稳定
+pub struct AuditRecord {
+    /// 消息方向
+    pub dir: Direction,
+    /// 收发消息的充电桩
+    pub charge_id: Uuid,
+    /// 收发消息时的虚拟时间
+    pub virtual_time: DateTime<Utc>,
+    /// 收发消息时的真实时间
+    pub real_time: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// 消息数据中的详单 ID（`data.id`），便于按详单查找
+    pub detail_id: Option<u32>,
+    /// 完整的消息，MessagePack 编码的消息同样按 JSON 记录
+    pub msg: MSG,
+}
+
+impl AuditRecord {
+    /// 按当前的虚拟时间和真实时间生成记录
+    pub fn new(dir: Direction, charge_id: Uuid, msg: &MSG) -> Self {
+        AuditRecord {
+            dir,
+            charge_id,
+            virtual_time: get_mock_now(),
+            real_time: Utc::now(),
+            detail_id: msg
+                .data
+                .get("id")
+                .and_then(|id| id.as_u64())
+                .and_then(|id| u32::try_from(id).ok()),
+            msg: msg.clone(),
+        }
+    }
+}
+
+/// 按大小轮换的文件
+struct RotatingFile {
+    path: PathBuf,
+    file: BufWriter<File>,
+    /// 当前文件的大小
+    size: u64,
+    /// 单个文件的大小上限，为 0 时不轮换
+    max_size: u64,
+    /// 保留的旧文件数
+    max_files: usize,
+}
+
+impl RotatingFile {
+    /// 打开文件追加写入，截掉上次运行中断时留下的不完整行
+    fn open(path: &Path, max_size: u64, max_files: usize) -> std::io::Result<Self> {
+        let (file, _) = persist::open_jsonl(path)?;
+        let size = file.metadata()?.len();
+        Ok(RotatingFile {
+            path: path.to_path_buf(),
+            file: BufWriter::new(file),
+            size,
+            max_size,
+            max_files,
+        })
+    }
+
+    /// 旧文件的路径，`index` 从 1 开始，越大越旧
+    fn rotated_path(&self, index: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", index));
+        PathBuf::from(path)
+    }
+
+    /// 写入一行，写入后超过大小上限时先轮换
+    fn write_line(&mut self, line: &str) -> std::io::Result<()> {
+        let len = line.len() as u64 + 1;
+        if self.max_size > 0 && self.size > 0 && self.size + len > self.max_size {
+            self.rotate()?;
+        }
+        writeln!(self.file, "{}", line)?;
+        self.size += len;
+        Ok(())
+    }
+
+    /// 当前文件改名为 `.1`，已有的旧文件依次后移，超过保留数的删除
+    fn rotate(&mut self) -> std::io::Result<()> {
+        self.file.flush()?;
+        if self.max_files == 0 {
+            std::fs::remove_file(&self.path)?;
+        } else {
+            let _ = std::fs::remove_file(self.rotated_path(self.max_files));
+            for index in (1..self.max_files).rev() {
+                let from = self.rotated_path(index);
+                if from.exists() {
+                    std::fs::rename(&from, self.rotated_path(index + 1))?;
+                }
+            }
+            std::fs::rename(&self.path, self.rotated_path(1))?;
+        }
+        let (file, _) = persist::open_jsonl(&self.path)?;
+        self.file = BufWriter::new(file);
+        self.size = 0;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// 审计日志，记录由写入线程写入文件
+pub struct AuditLog {
+    /// `None` 通知写入线程写完剩余的记录后结束
+    tx: SyncSender<Option<AuditRecord>>,
+    worker: Mutex<Option<JoinHandle<()>>>,
+    /// 通道满时丢弃的记录数
+    dropped: AtomicU64,
+}
+
+impl AuditLog {
+    /// 打开审计日志文件并启动写入线程
+    pub fn open(conf: &AuditConf, path: &str) -> Result<AuditLog, String> {
+        let file = RotatingFile::open(
+            Path::new(path),
+            conf.max_size_mb * 1024 * 1024,
+            conf.max_files,
+        )
+        .map_err(|e| format!("failed to open audit log {}: {}", path, e))?;
+        let (tx, rx) = mpsc::sync_channel(AUDIT_CHANNEL_CAPACITY);
+        let worker = std::thread::Builder::new()
+            .name("audit".to_string())
+            .spawn(move || write_records(file, rx))
+            .map_err(|e| format!("failed to start audit writer: {}", e))?;
+        Ok(AuditLog {
+            tx,
+            worker: Mutex::new(Some(worker)),
+            dropped: AtomicU64::new(0),
+        })
+    }
+
+    /// 放入一条记录，不等待写入
+    pub fn record(&self, record: AuditRecord) {
+        match self.tx.try_send(Some(record)) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                let dropped = self.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+                if let Some(digest) = throttle::allow("audit.full") {
+                    tracing::warn!(virtual_time = %get_mock_now(), "审计日志写入跟不上，已丢弃 {} 条记录{}", dropped, digest);
+                }
+            }
+            // 已经结束
+            Err(TrySendError::Disconnected(_)) => {}
+        }
+    }
+
+    /// 丢弃的记录数
+    pub fn get_dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// 写完已经放入的记录后结束写入线程，之后的记录不再写入
+    pub fn finish(&self) {
+        let Some(worker) = self.worker.lock().unwrap().take() else {
+            return;
+        };
+        // 阻塞发送，保证结束标记排在所有记录之后
+        if self.tx.send(None).is_ok() {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// 写入线程，通道暂时没有记录时把缓冲的内容写出
+fn write_records(mut file: RotatingFile, rx: Receiver<Option<AuditRecord>>) {
+    let write = |file: &mut RotatingFile, record: &AuditRecord| {
+        if let Err(e) = file.write_line(&serde_json::to_string(record).unwrap())
+            && let Some(digest) = throttle::allow("audit.write")
+        {
+            tracing::warn!("写入审计日志失败: {}{}", e, digest);
+        }
+    };
+    while let Ok(Some(record)) = rx.recv() {
+        write(&mut file, &record);
+        loop {
+            match rx.try_recv() {
+                Ok(Some(record)) => write(&mut file, &record),
+                Ok(None) => {
+                    let _ = file.flush();
+                    return;
+                }
+                Err(_) => break,
+            }
+        }
+        let _ = file.flush();
+    }
+    let _ = file.flush();
+}
+
+/// 全局审计日志，没有配置时不初始化
+static AUDIT: OnceLock<AuditLog> = OnceLock::new();
+
+/// 按配置打开全局审计日志，没有设置路径时不记录，打开失败时返回错误
+pub fn init(conf: &AuditConf) -> Result<(), String> {
+    let Some(path) = &conf.path else {
+        return Ok(());
+    };
+    let log = AuditLog::open(conf, path)?;
+    if AUDIT.set(log).is_err() {
+        return Err("audit log already initialized".to_string());
+    }
+    tracing::info!("协议消息写入审计日志 {}", path);
+    Ok(())
+}
+
+/// 记录一条收发的消息，没有打开审计日志时什么也不做
+pub fn record(dir: Direction, charge_id: Uuid, msg: &MSG) {
+    if let Some(log) = AUDIT.get() {
+        log.record(AuditRecord::new(dir, charge_id, msg));
+    }
+}
+
+/// 写完全局审计日志中剩余的记录，程序结束前调用
+pub fn finish() {
+    if let Some(log) = AUDIT.get() {
+        log.finish();
+        let dropped = log.get_dropped();
+        if dropped > 0 {
+            tracing::warn!("审计日志共丢弃 {} 条记录", dropped);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+/// 查找审计记录的条件，不设置的条件不限制
+pub struct AuditFilter {
+    /// 详单 ID
+    pub detail_id: Option<u32>,
+    /// 消息类型
+    pub type_: Option<MessageType>,
+    /// 消息方向
+    pub dir: Option<Direction>,
+    /// 充电桩
+    pub charge_id: Option<Uuid>,
+}
+
+impl AuditFilter {
+    /// 记录是否满足所有条件
+    pub fn matches(&self, record: &AuditRecord) -> bool {
+        self.detail_id.is_none_or(|id| record.detail_id == Some(id))
+            && self.type_.is_none_or(|type_| record.msg.type_ == type_)
+            && self.dir.is_none_or(|dir| record.dir == dir)
+            && self.charge_id.is_none_or(|id| record.charge_id == id)
+    }
+}
+
+/// 读取审计日志文件中满足条件的行，原样返回
+pub fn search(path: &Path, filter: &AuditFilter) -> Result<Vec<String>, String> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| format!("failed to read audit log {}: {}", path.display(), e))?;
+    let mut lines = Vec::new();
+    for (line, row) in content.lines().enumerate() {
+        let record: AuditRecord = serde_json::from_str(row).map_err(|e| {
+            format!(
+                "invalid audit record at {}:{}: {}",
+                path.display(),
+                line + 1,
+                e
+            )
+        })?;
+        if filter.matches(&record) {
+            lines.push(row.to_string());
+        }
+    }
+    Ok(lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::detail::ChargingDetail;
+    use serde_json::Value;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("taranis-{}-{}.jsonl", name, Uuid::new_v4()))
+    }
+
+    #[test]
+    fn test_audit_log_records_and_search() {
+        let path = temp_path("audit");
+        let conf = AuditConf {
+            path: Some(path.to_string_lossy().into_owned()),
+            ..AuditConf::default()
+        };
+        let log = AuditLog::open(&conf, conf.path.as_deref().unwrap()).unwrap();
+        let pile = Uuid::new_v4();
+        let new = MSG::with_payload(MessageType::New, &ChargingDetail::test_new(7));
+        let mut update = MSG::with_payload(MessageType::Update, &ChargingDetail::test_new(7));
+        update.msg_id = Some(3);
+        log.record(AuditRecord::new(Direction::In, pile, &new));
+        log.record(AuditRecord::new(Direction::Out, pile, &update));
+        log.record(AuditRecord::new(
+            Direction::Out,
+            pile,
+            &MSG::empty(MessageType::Heartbeat),
+        ));
+        log.finish();
+        // 结束后的记录不再写入
+        log.record(AuditRecord::new(Direction::In, pile, &new));
+
+        let all = search(&path, &AuditFilter::default()).unwrap();
+        assert_eq!(all.len(), 3);
+        let first: Value = serde_json::from_str(&all[0]).unwrap();
+        assert_eq!(first["dir"], "in");
+        assert_eq!(first["charge_id"], pile.to_string());
+        assert_eq!(first["detail_id"], 7);
+        assert_eq!(first["msg"]["type"], "new");
+        assert!(first["virtual_time"].is_string() && first["real_time"].is_string());
+
+        let filter = AuditFilter {
+            detail_id: Some(7),
+            dir: Some(Direction::Out),
+            ..AuditFilter::default()
+        };
+        let found = search(&path, &filter).unwrap();
+        assert_eq!(found.len(), 1);
+        assert!(found[0].contains("\"msg_id\":3"));
+        let filter = AuditFilter {
+            type_: Some(MessageType::Heartbeat),
+            ..AuditFilter::default()
+        };
+        assert_eq!(search(&path, &filter).unwrap().len(), 1);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_rotate_by_size() {
+        let path = temp_path("audit-rotate");
+        let mut file = RotatingFile::open(&path, 25, 2).unwrap();
+        // 每行 10 字节，每个文件放两行
+        for index in 0..7 {
+            file.write_line(&format!("line-{:04}", index)).unwrap();
+        }
+        file.flush().unwrap();
+        let read = |path: &Path| std::fs::read_to_string(path).unwrap();
+        assert_eq!(read(&path), "line-0006\n");
+        assert_eq!(read(&file.rotated_path(1)), "line-0004\nline-0005\n");
+        assert_eq!(read(&file.rotated_path(2)), "line-0002\nline-0003\n");
+        // 超过保留数的旧文件被删除
+        assert!(!file.rotated_path(3).exists());
+        for path in [path.clone(), file.rotated_path(1), file.rotated_path(2)] {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+}
@@ -0,0 +1,99 @@
+//! 模拟速度基准测试
+//!
+//! 不连接服务器，以最快速度模拟单个充电桩一次长时间的充电会话，
+//! 每次更新执行与正常运行相同的热点路径：计算费用、更新详单并序列化完整的更新消息。
+
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use crate::charge::Charge;
+use crate::conf::{CONF, UpdateMode};
+use crate::detail::ChargingDetail;
+use crate::update::UpdateEncoder;
+
+#[derive(Debug, Clone, Copy)]
+/// 基准测试结果
+pub struct BenchReport {
+    /// 模拟的虚拟时长
+    pub virtual_duration: chrono::Duration,
+    /// 更新次数
+    pub updates: u64,
+    /// 实际耗时
+    pub elapsed: Duration,
+}
+
+impl BenchReport {
+    /// 每秒更新次数
+    pub fn updates_per_sec(&self) -> f64 {
+        self.updates as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+
+    /// 每次更新耗时，单位为微秒
+    pub fn us_per_update(&self) -> f64 {
+        self.elapsed.as_secs_f64() * 1_000_000.0 / self.updates.max(1) as f64
+    }
+}
+
+impl fmt::Display for BenchReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "模拟 {} 虚拟秒，共 {} 次更新，耗时 {:.3} 秒，{:.0} 次更新/秒，{:.2} 微秒/次更新",
+            self.virtual_duration.num_seconds(),
+            self.updates,
+            self.elapsed.as_secs_f64(),
+            self.updates_per_sec(),
+            self.us_per_update()
+        )
+    }
+}
+
+/// 模拟一次持续 `virtual_duration` 的充电会话，每隔 `step` 虚拟时间更新一次
+pub fn run(virtual_duration: chrono::Duration, step: chrono::Duration) -> BenchReport {
+    let mut charge = Charge::new(CONF.charge.charge_type, CONF.charge.power, 1);
+    charge
+        .add_detail(ChargingDetail::test_new(0))
+        .expect("benchmark detail must be accepted");
+    charge.start_charging();
+    let start = charge.get_charging_detail_ref().unwrap().clone_start_time();
+    let mut encoder = UpdateEncoder::new(UpdateMode::Full, 1);
+
+    let updates = (virtual_duration.num_milliseconds() / step.num_milliseconds().max(1)) as u64;
+    let begin = Instant::now();
+    for i in 1..=updates {
+        charge.update_charging_at(start + step * i as i32);
+        let msg = encoder.snapshot(charge.get_charging_detail_ref().unwrap());
+        std::hint::black_box(serde_json::to_string(&msg).unwrap());
+    }
+    BenchReport {
+        virtual_duration,
+        updates,
+        elapsed: begin.elapsed(),
+    }
+}
+
+/// 检查每次更新耗时是否超过上限
+pub fn check_ceiling(report: &BenchReport, max_us_per_update: f64) -> Result<(), String> {
+    if report.us_per_update() > max_us_per_update {
+        return Err(format!(
+            "{:.2} us/update exceeds the ceiling of {:.2} us/update",
+            report.us_per_update(),
+            max_us_per_update
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bench_runs_every_update() {
+        let report = run(chrono::Duration::hours(1), chrono::Duration::seconds(5));
+        assert_eq!(report.updates, 720);
+        assert!(report.us_per_update() > 0.0);
+        assert!(check_ceiling(&report, f64::MAX).is_ok());
+        assert!(check_ceiling(&report, 0.0).is_err());
+    }
+}
@@ -1,132 +1,608 @@
 use futures_util::{SinkExt, StreamExt};
+use serde_json::Value;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
 use taranis::{
-    conf::CONF,
-    detail::ChargingDetail,
-    message::{MSG, MessageType},
+    conf::{self, CONF, ConfOverrides, WireEncoding},
+    detail::{ChargingDetail, DetailDelta},
+    message::{
+        AUTH_FAILED_CLOSE_CODE, AuthErrorData, MSG, MessageType, MsgAckData, PROTOCOL_VERSION,
+        RegisterAckData, RegisterPayload, RejectData, StatusData,
+    },
+    outbox,
+    price::{FingerprintCheck, Prices},
+    scenario::{self, Scenario},
+    time::get_mock_now,
+    tls,
 };
-use tokio::{net::TcpListener, time::sleep};
-use tokio_tungstenite::tungstenite::Message;
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpListener,
+    time::{sleep, timeout},
+};
+use tokio_tungstenite::tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tokio_tungstenite::tungstenite::http::StatusCode;
+use tokio_tungstenite::tungstenite::protocol::CloseFrame;
+use tokio_tungstenite::tungstenite::{Error as WsError, Message};
+
+/// 场景失败时的退出码
+const SCENARIO_FAILED_EXIT_CODE: i32 = 1;
+
+/// 发送一条消息
+async fn send<S>(outgoing: &mut S, type_: MessageType, data: Value)
+where
+    S: SinkExt<Message> + Unpin,
+    S::Error: std::fmt::Debug,
+{
+    let msg = MSG::new(type_, data);
+    outgoing
+        .send(Message::Text(serde_json::to_string(&msg).unwrap().into()))
+        .await
+        .unwrap();
+}
+
+/// 回复注册确认，协议版本不一致时输出提示，`json_only` 时不同意充电桩请求的 MessagePack 编码
+async fn send_register_ack<S>(outgoing: &mut S, msg: &MSG, json_only: bool)
+where
+    S: SinkExt<Message> + Unpin,
+    S::Error: std::fmt::Debug,
+{
+    let register: RegisterPayload = msg
+        .payload()
+        .unwrap_or_else(|e| panic!("Invalid register message: {}", e));
+    if register.protocol_version != PROTOCOL_VERSION {
+        println!(
+            "Protocol version mismatch: pile {}, server {}",
+            register.protocol_version, PROTOCOL_VERSION
+        );
+    }
+    println!(
+        "Pile {} v{} (protocol {}, speed {}, encoding {:?}, features: {})",
+        register.charge_id,
+        register.software_version,
+        register.protocol_version,
+        register.speed,
+        register.encoding,
+        register.features.join(", ")
+    );
+    let accepted = RegisterAckData::accept(&register);
+    let ack = RegisterAckData {
+        protocol_version: PROTOCOL_VERSION,
+        server_version: Some(env!("CARGO_PKG_VERSION").to_string()),
+        encoding: if json_only {
+            WireEncoding::Json
+        } else {
+            accepted.encoding
+        },
+        server_time: Some(get_mock_now()),
+        ..accepted
+    };
+    send(
+        outgoing,
+        MessageType::RegisterAck,
+        serde_json::to_value(ack).unwrap(),
+    )
+    .await;
+}
+
+/// 向充电桩发送一批新的充电详单，所有详单放在一条 `new_batch` 消息中
+async fn send_new_details<S>(outgoing: &mut S, detail_id: &mut u32)
+where
+    S: SinkExt<Message> + Unpin,
+    S::Error: std::fmt::Debug,
+{
+    let details: Vec<ChargingDetail> = (0..CONF.charge.size)
+        .map(|_| {
+            let detail = ChargingDetail::test_new(*detail_id);
+            *detail_id += 1;
+            detail
+        })
+        .collect();
+    send(
+        outgoing,
+        MessageType::NewBatch,
+        serde_json::to_value(&details).unwrap(),
+    )
+    .await;
+}
 
+/// 用法: `cargo run --bin test -- [--listen <地址>] [--break-idle] [--break-after <次数>] [--query-after <次数>] [--tls-cert <证书> --tls-key <私钥> [--tls-client-ca <CA 证书>]] [--auth-token <令牌>] [--json-only] [--prices <文件>] [--scenario <文件>]`
+/// `--listen` 指定监听地址，不指定时使用 `websocket.url`（或 `websocket.urls` 的第一个地址）中的地址，
+/// `--break-idle` 在充电桩注册后、发送详单前发送 `break` 消息，
+/// `--break-after` 在收到指定次数的状态更新后发送 `break` 消息，
+/// `--query-after` 在收到指定次数的状态更新后发送 `query` 消息，并检查回复的状态快照，
+/// `--tls-cert <证书> --tls-key <私钥>` 改为监听 TLS 连接，再加上 `--tls-client-ca <CA 证书>` 时要求客户端提供由该 CA 签发的证书，
+/// `--auth-token` 要求充电桩提供认证令牌，握手请求头中的令牌错误时以 401 拒绝握手，
+/// 没有请求头时检查注册消息中的 `auth_token` 字段，错误或缺失时发送 `auth_error` 消息并以 4401 关闭连接，
+/// 充电桩发送的 JSON 文本帧和 MessagePack 二进制帧都可以解析，默认同意充电桩请求的编码方式，
+/// `--json-only` 模拟只支持 JSON 的服务器，注册确认中不同意 MessagePack，并检查充电桩之后不再发送二进制帧，
+/// 充电桩注册时比较注册消息中的价格表指纹，与第一个注册的充电桩（设置了 `--prices <文件>` 时与该参考价格表）不同时输出警告，
+/// 收到故障消息后先发送一个新详单（应当被以 `faulted` 拒绝），再发送 `repair` 消息，
+/// 充电桩修复后重新注册时再次发送详单，并检查状态按 ok → faulted → ok 变化，
+/// 带有消息 ID 的完成和故障消息都会回复 `ack` 确认，以上为内置的默认行为，
+/// `--scenario <文件>` 改为执行场景文件中的步骤（见 [`scenario`]），只处理第一个连接的充电桩，场景失败时以退出码 1 结束
+/// 同样接受 `--config` 等配置参数和 `TARANIS_*` 环境变量
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let url = CONF.websocket.url.clone();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let (cli, args) = ConfOverrides::from_args(&args)?;
+    conf::init_overrides(cli.or(ConfOverrides::from_env()?));
+    let listen = args.iter().position(|arg| arg == "--listen").map(|pos| {
+        args.get(pos + 1)
+            .expect("--listen requires an address")
+            .clone()
+    });
+    let break_idle = args.iter().any(|arg| arg == "--break-idle");
+    let break_after: Option<u32> = args
+        .iter()
+        .position(|arg| arg == "--break-after")
+        .map(|pos| {
+            args.get(pos + 1)
+                .and_then(|v| v.parse().ok())
+                .expect("--break-after requires a number")
+        });
+    let query_after: Option<u32> = args
+        .iter()
+        .position(|arg| arg == "--query-after")
+        .map(|pos| {
+            args.get(pos + 1)
+                .and_then(|v| v.parse().ok())
+                .expect("--query-after requires a number")
+        });
+    let arg_value = |name: &str| {
+        args.iter().position(|arg| arg == name).map(|pos| {
+            args.get(pos + 1)
+                .unwrap_or_else(|| panic!("{} requires a value", name))
+                .clone()
+        })
+    };
+    let options = Options {
+        break_idle,
+        break_after,
+        query_after,
+        auth_token: arg_value("--auth-token"),
+        json_only: args.iter().any(|arg| arg == "--json-only"),
+        scenario: arg_value("--scenario").map(|path| {
+            Scenario::load(&path).unwrap_or_else(|e| panic!("Invalid scenario: {}", e))
+        }),
+        prices: Arc::new(Mutex::new(match arg_value("--prices") {
+            Some(path) => Prices::from_path(&path)
+                .and_then(|reference| FingerprintCheck::with_reference(&reference))
+                .unwrap_or_else(|e| panic!("Invalid reference price table: {}", e)),
+            None => FingerprintCheck::default(),
+        })),
+    };
+    let acceptor = arg_value("--tls-cert").map(|cert| {
+        let key = arg_value("--tls-key").expect("--tls-cert requires --tls-key");
+        tls::acceptor(&cert, &key, arg_value("--tls-client-ca").as_deref())
+            .unwrap_or_else(|e| panic!("Invalid TLS config: {}", e))
+    });
+    let url = CONF.websocket.endpoints().remove(0);
 
-    let addr = url
-        .strip_prefix("ws://")
-        .or_else(|| url.strip_prefix("wss://"))
-        .expect("Invalid WebSocket URL format")
-        .to_string();
+    let addr = listen.unwrap_or_else(|| {
+        url.strip_prefix("ws://")
+            .or_else(|| url.strip_prefix("wss://"))
+            .expect("Invalid WebSocket URL format")
+            .split('/')
+            .next()
+            .unwrap()
+            .to_string()
+    });
 
     // Create the event loop and TCP listener we'll accept connections on.
     let try_socket = TcpListener::bind(&addr).await;
     let listener = try_socket.expect("Failed to bind");
-    println!("Listening on: {}", addr);
+    println!(
+        "Listening on: {}{}",
+        addr,
+        if acceptor.is_some() { " (TLS)" } else { "" }
+    );
 
-    while let Ok((stream, _)) = listener.accept().await {
-        tokio::spawn(async move {
-            let ws_stream = tokio_tungstenite::accept_async(stream)
+    if let Some(scenario) = options.scenario.clone() {
+        // 场景只针对第一个连接的充电桩执行，结束后以退出码报告结果
+        let run = async {
+            let (stream, peer) = listener.accept().await.map_err(|e| e.to_string())?;
+            match &acceptor {
+                Some(acceptor) => match acceptor.accept(stream).await {
+                    Ok(stream) => serve(stream, peer, options).await,
+                    Err(e) => Err(format!("TLS handshake with {} failed: {:?}", peer, e)),
+                },
+                None => serve(stream, peer, options).await,
+            }
+        };
+        let result = match scenario.timeout {
+            Some(limit) => timeout(limit, run)
                 .await
-                .expect("Error during the websocket handshake occurred");
-
-            println!(
-                "New websocket connection: {}",
-                ws_stream.get_ref().peer_addr().unwrap()
-            );
-
-            let (mut outgoing, mut incoming) = ws_stream.split();
-
-            let mut detail_id = 0;
-
-            while let Some(result) = incoming.next().await {
-                match result {
-                    Ok(message) => {
-                        // println!("Received: {:?}", message);
-                        if message.is_text() {
-                            // println!("Text message: {}", message.to_text().unwrap());
-                            let msg: MSG = serde_json::from_str(message.to_text().unwrap())
-                                .expect(format!("Failed to parse message: {:?}", message).as_str());
-                            if msg.type_ == MessageType::Register {
-                                println!("Register message received: {:?}", msg);
-                                sleep(std::time::Duration::from_secs(5)).await;
-                                // Here you can handle the register message as needed
-                                // For example, you might want to send a response back
-                                for _ in 0..CONF.charge.size {
-                                    let detail = ChargingDetail::test_new(detail_id);
-                                    detail_id += 1;
-                                    let response = MSG {
-                                        type_: MessageType::New,
-                                        data: serde_json::to_string(&detail).unwrap(),
-                                    };
-                                    outgoing
-                                        .send(Message::Text(
-                                            serde_json::to_string(&response).unwrap().into(),
-                                        ))
-                                        .await
-                                        .unwrap();
-                                }
-                            } else if msg.type_ == MessageType::Complete {
-                                let detail: Option<ChargingDetail> =
-                                    serde_json::from_str(&msg.data).ok();
-                                if let Some(detail) = detail {
-                                    println!(
-                                        "Charging Detail Completed: {}",
-                                        serde_json::to_string_pretty(&detail).unwrap()
-                                    );
-                                    let new_detail = ChargingDetail::test_new(detail_id);
-                                    detail_id += 1;
-                                    let response = MSG {
-                                        type_: MessageType::New,
-                                        data: serde_json::to_string(&new_detail).unwrap(),
-                                    };
-                                    outgoing
-                                        .send(Message::Text(
-                                            serde_json::to_string(&response).unwrap().into(),
-                                        ))
-                                        .await
-                                        .unwrap();
-                                } else {
-                                    println!("detail is None or invalid format");
+                .unwrap_or_else(|_| Err(format!("scenario timed out after {:?}", limit))),
+            None => run.await,
+        };
+        match result {
+            Ok(()) => println!("Scenario {} passed", scenario.name),
+            Err(e) => {
+                println!("Scenario {} failed: {}", scenario.name, e);
+                std::process::exit(SCENARIO_FAILED_EXIT_CODE);
+            }
+        }
+        return Ok(());
+    }
+
+    while let Ok((stream, peer)) = listener.accept().await {
+        let acceptor = acceptor.clone();
+        let options = options.clone();
+        tokio::spawn(async move {
+            let result = match acceptor {
+                Some(acceptor) => match acceptor.accept(stream).await {
+                    Ok(stream) => serve(stream, peer, options).await,
+                    Err(e) => Err(format!("TLS handshake with {} failed: {:?}", peer, e)),
+                },
+                None => serve(stream, peer, options).await,
+            };
+            if let Err(e) = result {
+                println!("{}", e);
+            }
+        });
+    }
+
+    Ok(())
+}
+
+#[derive(Clone)]
+/// 测试服务器的命令行选项
+struct Options {
+    break_idle: bool,
+    break_after: Option<u32>,
+    query_after: Option<u32>,
+    auth_token: Option<String>,
+    /// 只支持 JSON 编码
+    json_only: bool,
+    /// 执行的场景，不设置时使用内置的默认行为
+    scenario: Option<Scenario>,
+    /// 所有连接共用的价格表指纹检查
+    prices: Arc<Mutex<FingerprintCheck>>,
+}
+
+/// 检查注册消息中的价格表指纹，与其他充电桩或参考价格表不同时输出警告
+fn check_price_fingerprint(msg: &MSG, options: &Options) {
+    let Ok(register) = msg.payload::<RegisterPayload>() else {
+        return;
+    };
+    let Some(fingerprint) = register.price_fingerprint else {
+        println!(
+            "Pile {} did not report a price table fingerprint",
+            register.charge_id
+        );
+        return;
+    };
+    if let Some(warning) = options
+        .prices
+        .lock()
+        .unwrap()
+        .check(register.charge_id, &fingerprint)
+    {
+        println!(
+            "Warning: {}, compare the tables with `taranis price-diff`",
+            warning
+        );
+    }
+}
+
+/// 充电桩提供的令牌是否与要求的令牌一致，没有要求令牌时总是通过
+fn token_matches(expected: Option<&str>, provided: Option<&str>) -> bool {
+    expected.is_none_or(|expected| provided == Some(expected))
+}
+
+/// 检查注册消息中的认证令牌，错误或缺失时发送 `auth_error` 消息并关闭连接，返回是否通过
+async fn check_register_token<S>(
+    outgoing: &mut S,
+    msg: &MSG,
+    options: &Options,
+    peer: SocketAddr,
+) -> bool
+where
+    S: SinkExt<Message> + Unpin,
+    S::Error: std::fmt::Debug,
+{
+    let provided = msg.data["auth_token"].as_str();
+    if token_matches(options.auth_token.as_deref(), provided) {
+        return true;
+    }
+    println!("Rejecting register from {}: invalid token", peer);
+    let error = AuthErrorData {
+        reason: "invalid token".to_string(),
+    };
+    send(
+        outgoing,
+        MessageType::AuthError,
+        serde_json::to_value(&error).unwrap(),
+    )
+    .await;
+    let close = CloseFrame {
+        code: AUTH_FAILED_CLOSE_CODE.into(),
+        reason: "invalid token".into(),
+    };
+    outgoing.send(Message::Close(Some(close))).await.ok();
+    false
+}
+
+/// 处理一个充电桩连接，`stream` 为普通 TCP 连接或 TLS 连接
+/// 设置了场景时执行场景并返回结果，否则执行内置的默认行为
+async fn serve<S>(stream: S, peer: SocketAddr, options: Options) -> Result<(), String>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    // 握手请求头中带有令牌时在握手阶段检查，否则在注册消息中检查，通过后不再检查
+    let mut authorized = false;
+    // 回调的返回类型由 tungstenite 规定
+    #[allow(clippy::result_large_err)]
+    let check_header = |request: &Request, response: Response| {
+        let Some(header) = request.headers().get("authorization") else {
+            return Ok(response);
+        };
+        let provided = header
+            .to_str()
+            .ok()
+            .and_then(|value| value.strip_prefix("Bearer "));
+        if token_matches(options.auth_token.as_deref(), provided) {
+            authorized = true;
+            Ok(response)
+        } else {
+            println!("Rejecting handshake from {}: invalid token", peer);
+            let mut response = ErrorResponse::new(Some("invalid token".to_string()));
+            *response.status_mut() = StatusCode::UNAUTHORIZED;
+            Err(response)
+        }
+    };
+    let ws_stream = match tokio_tungstenite::accept_hdr_async(stream, check_header).await {
+        Ok(ws_stream) => ws_stream,
+        Err(e) => return Err(format!("Websocket handshake with {} failed: {:?}", peer, e)),
+    };
+
+    println!("New websocket connection: {}", peer);
+
+    let (mut outgoing, mut incoming) = ws_stream.split();
+    if let Some(scenario) = &options.scenario {
+        return run_scenario(
+            &mut outgoing,
+            &mut incoming,
+            scenario,
+            &options,
+            peer,
+            authorized,
+        )
+        .await;
+    }
+
+    let mut detail_id = 0;
+    let mut updates = 0;
+    let mut registered = false;
+    // 充电桩是否处于故障状态，收到故障消息时进入，修复后重新注册时离开
+    let mut faulted = false;
+    // 根据完整快照和增量更新重建的详单，以及下一个期望的增量序号
+    let mut reconstructed: Option<(ChargingDetail, u64)> = None;
+
+    while let Some(result) = incoming.next().await {
+        match result {
+            Ok(message) => {
+                // println!("Received: {:?}", message);
+                let decoded = match &message {
+                    Message::Text(text) => Some(
+                        serde_json::from_str::<MSG>(text)
+                            .unwrap_or_else(|_| panic!("Failed to parse message: {:?}", message)),
+                    ),
+                    Message::Binary(bytes) => {
+                        // 只支持 JSON 时充电桩只有在注册确认之前可以发送二进制帧
+                        assert!(
+                            !(options.json_only && registered),
+                            "Pile sent a binary frame after json was negotiated"
+                        );
+                        Some(
+                            MSG::from_msgpack(bytes).unwrap_or_else(|e| {
+                                panic!("Failed to parse binary message: {}", e)
+                            }),
+                        )
+                    }
+                    _ => None,
+                };
+                if let Some(msg) = decoded {
+                    if outbox::needs_ack(msg.type_)
+                        && let Some(msg_id) = msg.msg_id
+                    {
+                        let ack = MsgAckData { msg_id };
+                        send(
+                            &mut outgoing,
+                            MessageType::Ack,
+                            serde_json::to_value(ack).unwrap(),
+                        )
+                        .await;
+                    }
+                    if msg.type_ == MessageType::Register && !authorized {
+                        if !check_register_token(&mut outgoing, &msg, &options, peer).await {
+                            break;
+                        }
+                        authorized = true;
+                    }
+                    if msg.type_ == MessageType::Register {
+                        check_price_fingerprint(&msg, &options);
+                        send_register_ack(&mut outgoing, &msg, options.json_only).await;
+                    }
+                    if msg.type_ == MessageType::Register && registered {
+                        assert!(faulted, "Pile re-registered without a fault");
+                        faulted = false;
+                        println!("Pile state: faulted -> ok, re-registered after repair");
+                        send_new_details(&mut outgoing, &mut detail_id).await;
+                    } else if msg.type_ == MessageType::Register {
+                        registered = true;
+                        println!("Register message received: {:?}", msg);
+                        if options.break_idle {
+                            // 空闲时模拟损坏，等待故障消息后再发送详单
+                            println!("Sending break to idle pile");
+                            send(&mut outgoing, MessageType::Break, Value::Null).await;
+                        } else {
+                            send_new_details(&mut outgoing, &mut detail_id).await;
+                        }
+                    } else if msg.type_ == MessageType::Fault {
+                        let detail: Option<ChargingDetail> = msg.payload().ok().flatten();
+                        match detail {
+                            Some(detail) => println!("Fault reported by pile: {}", detail),
+                            None => println!("Fault reported by idle pile"),
+                        }
+                        assert!(!faulted, "Pile reported a fault while already faulted");
+                        faulted = true;
+                        println!("Pile state: ok -> faulted");
+                        // 故障期间的新详单应当被拒绝
+                        let probe = ChargingDetail::test_new(detail_id);
+                        detail_id += 1;
+                        send(
+                            &mut outgoing,
+                            MessageType::New,
+                            serde_json::to_value(&probe).unwrap(),
+                        )
+                        .await;
+                        sleep(std::time::Duration::from_secs(1)).await;
+                        send(&mut outgoing, MessageType::Repair, Value::Null).await;
+                    } else if msg.type_ == MessageType::Complete {
+                        let detail: Option<ChargingDetail> = msg.payload().ok();
+                        if let Some(detail) = detail {
+                            println!("Charging Detail Completed: {}", detail);
+                            let new_detail = ChargingDetail::test_new(detail_id);
+                            detail_id += 1;
+                            let response = MSG::with_payload(MessageType::New, &new_detail);
+                            outgoing
+                                .send(Message::Text(
+                                    serde_json::to_string(&response).unwrap().into(),
+                                ))
+                                .await
+                                .unwrap();
+                        } else {
+                            println!("detail is None or invalid format");
+                        }
+                    } else if msg.type_ == MessageType::Delta {
+                        let delta: DetailDelta = msg
+                            .payload()
+                            .unwrap_or_else(|_| panic!("Invalid delta: {}", msg.data));
+                        match reconstructed.as_mut() {
+                            Some((detail, seq)) if *seq == delta.seq => {
+                                detail
+                                    .apply_delta(&delta)
+                                    .unwrap_or_else(|e| panic!("Failed to apply delta: {}", e));
+                                *seq += 1;
+                                println!("Charging Detail (delta {}): {}", delta.seq, detail);
+                            }
+                            _ => panic!("Delta out of sync: {}", msg.data),
+                        }
+                    } else if msg.type_ == MessageType::Error {
+                        println!("Error reported by pile: {}", msg.data);
+                    } else if msg.type_ == MessageType::Reject {
+                        let reject: RejectData = msg
+                            .payload()
+                            .unwrap_or_else(|_| panic!("Invalid reject: {}", msg.data));
+                        // 批量新详单的拒绝消息列出所有被拒绝的详单
+                        let rejected = if reject.rejected.is_empty() {
+                            vec![reject]
+                        } else {
+                            reject.rejected
+                        };
+                        for reject in rejected {
+                            println!("Detail {} rejected by pile: {}", reject.id, reject.reason);
+                            if faulted {
+                                assert_eq!(reject.reason, "faulted");
+                            }
+                        }
+                    } else if msg.type_ == MessageType::Status {
+                        let status: StatusData = msg
+                            .payload()
+                            .unwrap_or_else(|_| panic!("Invalid status: {}", msg.data));
+                        // 充电中查询时应当有正在充电的详单
+                        let charging = status
+                            .charging
+                            .as_ref()
+                            .expect("Status queried mid-charge has no charging detail");
+                        assert!(status.working, "Status queried mid-charge is not working");
+                        println!(
+                            "Pile status at {}: charging {}, waiting {:?}",
+                            status.virtual_time,
+                            charging.get_id(),
+                            status.queue.iter().map(|d| d.get_id()).collect::<Vec<_>>()
+                        );
+                    } else if msg.type_ == MessageType::Ack {
+                        println!("Detail accepted by pile: {}", msg.data);
+                    } else if msg.type_ == MessageType::Heartbeat {
+                        println!("Heartbeat from pile: {}", msg.data);
+                    } else {
+                        println!("MSG type: {:?}", msg.type_);
+                        let detail: Option<ChargingDetail> = msg.payload().ok();
+                        if let Some(detail) = detail {
+                            println!("Charging Detail: {}", detail);
+                            if msg.type_ == MessageType::Update {
+                                // 完整快照重新同步，之后的增量序号从 1 开始
+                                reconstructed = Some((detail, 1));
+                                updates += 1;
+                                if options.query_after == Some(updates) {
+                                    println!("Sending query after {} updates", updates);
+                                    send(&mut outgoing, MessageType::Query, Value::Null).await;
                                 }
-                            } else {
-                                println!("MSG type: {:?}", msg.type_);
-                                let detail: Option<ChargingDetail> =
-                                    serde_json::from_str(&msg.data).ok();
-                                if let Some(detail) = detail {
-                                    println!(
-                                        "Charging Detail: {}",
-                                        serde_json::to_string_pretty(&detail).unwrap()
-                                    );
-                                    // Here you can handle the ChargingDetail as needed
-                                } else {
-                                    println!("detail is None or invalid format");
+                                if options.break_after == Some(updates) {
+                                    // 充电中模拟损坏
+                                    println!("Sending break after {} updates", updates);
+                                    send(&mut outgoing, MessageType::Break, Value::Null).await;
                                 }
                             }
-                        } else if message.is_binary() {
-                            println!("Binary message: {:?}", message.into_data());
-                        } else if message.is_ping() {
-                            println!("Ping received, sending Pong.");
-                            outgoing.send(Message::Pong("Pong!".into())).await.unwrap();
-                        } else if message.is_close() {
-                            println!("Close message received, closing connection.");
-                            outgoing
-                                .send(Message::Close(None))
-                                .await
-                                .unwrap_or_else(|e| {
-                                    println!("Error sending close message: {:?}", e);
-                                });
-                            break;
+                            // Here you can handle the ChargingDetail as needed
+                        } else {
+                            println!("detail is None or invalid format");
                         }
                     }
-                    Err(e) => {
-                        println!("Error receiving message: {:?}", e);
-                        break;
-                    }
+                } else if message.is_ping() {
+                    println!("Ping received, sending Pong.");
+                    outgoing.send(Message::Pong("Pong!".into())).await.unwrap();
+                } else if message.is_close() {
+                    println!("Close message received, closing connection.");
+                    outgoing
+                        .send(Message::Close(None))
+                        .await
+                        .unwrap_or_else(|e| {
+                            println!("Error sending close message: {:?}", e);
+                        });
+                    break;
                 }
             }
-
-            println!("Websocket connection closed.");
-        });
+            Err(e) => {
+                println!("Error receiving message: {:?}", e);
+                break;
+            }
+        }
     }
 
+    println!("Websocket connection closed.");
     Ok(())
 }
+
+/// 按场景依次执行步骤，充电桩注册并通过认证后开始计时
+async fn run_scenario<W, R>(
+    outgoing: &mut W,
+    incoming: &mut R,
+    scenario: &Scenario,
+    options: &Options,
+    peer: SocketAddr,
+    authorized: bool,
+) -> Result<(), String>
+where
+    W: SinkExt<Message> + Unpin,
+    W::Error: std::fmt::Debug,
+    R: StreamExt<Item = Result<Message, WsError>> + Unpin,
+{
+    let report = |line: String| println!("{}", line);
+    let register = loop {
+        if let Some(msg) = scenario::recv(outgoing, incoming, None, &report).await?
+            && msg.type_ == MessageType::Register
+        {
+            break msg;
+        }
+    };
+    if !authorized && !check_register_token(outgoing, &register, options, peer).await {
+        return Err("pile sent an invalid token".to_string());
+    }
+    send_register_ack(outgoing, &register, options.json_only).await;
+    println!("Pile registered, running scenario {}", scenario.name);
+    scenario::run_steps(outgoing, incoming, scenario, &report).await
+}
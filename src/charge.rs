@@ -1,10 +1,28 @@
-use crate::conf::{CONF, ChargeType};
+use crate::conf::{
+    CONF, ChargeConf, ChargeType, Conf, IdMode, OverflowPolicy, PileConf, UpdateMode,
+};
+use crate::curve::PowerCurve;
 use crate::detail::ChargingDetail;
-use crate::price::calc_price_with_tz;
-use crate::time::get_mock_now;
+use crate::event::{self, ChargeEvent, PileEvent};
+use crate::message::{
+    PROTOCOL_FEATURES, PROTOCOL_VERSION, PowerWarning, RegisterPayload, StatusData,
+};
+use crate::metrics::{Outcome, PileMetrics};
+use crate::persist;
+use crate::price::{
+    FreeWindow, PowerStep, Pricing, calc_rated_price, calc_rated_price_breakdown,
+    calc_rated_price_itemized, fingerprint_hex_using, service_fee_using,
+};
+use crate::runtime::RUNTIME;
+use crate::state::{PileState, Transition, TransitionError};
+use crate::stats::{EtaErrorStats, WaitTimeStats};
+use crate::throttle;
+use crate::time::{Clock, GlobalClock};
 use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
-use tokio::sync::Mutex;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::{Mutex, broadcast};
 use uuid::Uuid;
 
 #[derive(Serialize, Deserialize)]
@@ -17,14 +35,306 @@ pub struct Charge {
     type_: ChargeType,
     /// 充电功率，单位为kW
     power: f64,
-    /// 队列大小
-    size: u32,
-    #[serde(skip)]
-    /// 充电详单队列
+    /// 队列大小，不限长时为 `null`
+    size: Option<u32>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    /// 是否只接受预约（已注册但尚未投入使用），此时拒绝所有新详单
+    reservation_only: bool,
+    #[serde(default, skip_serializing_if = "UpdateMode::is_full")]
+    /// 充电状态更新方式，注册时告知服务器是否会发送增量更新
+    update_mode: UpdateMode,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    /// 是否处于免费充电状态，注册时告知服务器
+    free_vend: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    /// 充电详单队列，非空时（例如从状态文件恢复后）随注册消息发送，便于服务器核对
     queue: Vec<ChargingDetail>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    /// 队列已满时等待进入队列的详单，不占用队列容量
+    pending: Vec<ChargingDetail>,
+    #[serde(skip)]
+    /// 等待区容量，为 0 时队列已满直接拒绝
+    pending_capacity: usize,
+    #[serde(skip)]
+    /// 队列已满时新详单的处理方式
+    overflow_policy: OverflowPolicy,
+    #[serde(skip)]
+    /// `grow` 策略下最多可以超出队列大小的详单数量，不指定时等于队列大小
+    overflow_cap: Option<u32>,
+    #[serde(skip)]
+    /// 从等待区进入队列、尚未通知服务器的详单 ID
+    promoted: Vec<u32>,
+    #[serde(skip)]
+    /// 队首正在充电的详单数量，不超过充电枪数量，大于 0 时充电桩正在工作
+    active: usize,
+    #[serde(skip, default = "single_connector")]
+    /// 充电枪数量，最多同时为这么多个详单充电
+    connectors: usize,
+    #[serde(skip)]
+    /// 会话时长预测误差统计
+    eta_errors: EtaErrorStats,
+    #[serde(skip)]
+    /// 排队等待时长统计
+    wait_times: WaitTimeStats,
+    #[serde(skip, default = "new_event_sender")]
+    /// 状态变化事件通道
+    events: broadcast::Sender<ChargeEvent>,
+    #[serde(skip)]
+    /// 充电桩指标
+    metrics: Arc<PileMetrics>,
+    #[serde(skip)]
+    /// 故障修复后是否自动恢复被打断的详单
+    requeue_after_repair: bool,
+    #[serde(skip)]
+    /// 等待修复后恢复的详单
+    stash: Vec<ChargingDetail>,
+    #[serde(skip)]
+    /// 充电桩自己的价格表，不指定时使用全局价格表
+    pricing: Option<Pricing>,
+    #[serde(skip)]
+    /// 免费充电时间段，计算费用时跳过
+    free_windows: Vec<FreeWindow>,
+    #[serde(skip)]
+    /// 详单期望功率与充电桩功率允许的偏差，单位为kW
+    power_tolerance: f64,
+    #[serde(skip)]
+    /// 功率偏差超出范围时是否拒绝详单
+    strict_power_match: bool,
+    #[serde(skip)]
+    /// 开始充电后取消收取的违约金
+    cancellation_fee: f64,
     #[serde(skip)]
+    /// 已充电度数达到该值后取消才收取违约金
+    cancellation_fee_after_kwh: Option<f64>,
+    #[serde(skip)]
+    /// 充电桩状态，关闭或故障期间拒绝新详单和取消请求，直到收到打开或修复消息
+    state: PileState,
+    #[serde(skip, default = "tracing::Span::none")]
+    /// 充电桩的日志 span，状态变化时更新其中的 `state` 字段
+    span: tracing::Span,
+    #[serde(skip)]
+    /// 是否允许通过键盘模拟损坏
+    manual_break: bool,
+    #[serde(skip)]
+    /// 是否启用内部故障来源
+    faults_enabled: bool,
+    #[serde(skip)]
+    /// 队列状态文件路径，设置后每次状态变化时写入
+    state_path: Option<PathBuf>,
+    #[serde(skip)]
+    /// 充电桩的更新间隔，单位为毫秒，不指定时使用全局更新间隔
+    update_interval: Option<u64>,
+    #[serde(skip)]
+    /// 详单可以指定的最短更新间隔，单位为毫秒
+    min_update_interval: u64,
+    #[serde(skip)]
+    /// 详单请求度数的上限，单位为kWh，不指定时不限制
+    max_request_amount: Option<f64>,
+    #[serde(skip)]
+    /// 功率曲线，按充电进度降低实际充电功率
+    curve: PowerCurve,
+    #[serde(skip, default = "global_clock")]
+    /// 虚拟时钟，开始、更新和完成充电时从这里读取当前时间
+    clock: Arc<dyn Clock>,
+    #[serde(skip)]
+    /// 取消的详单是否使用 `canceled` 状态，为 `false` 时与中断一样使用 `interrupted`
+    canceled_status: bool,
+
+    #[serde(skip)]
+    /// 关闭充电桩时是否清空队列，为 `false` 时只中断正在充电的详单
+    drop_queue_on_close: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+/// 写入状态文件的队列状态
+struct ChargeState {
+    /// 充电详单队列，正在充电的详单在队首
+    queue: Vec<ChargingDetail>,
     /// 是否正在工作
     working: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// 正在充电的详单数量，只有一把充电枪时不写入，由 `working` 决定
+    active: Option<usize>,
+    #[serde(default)]
+    /// 是否处于故障状态
+    faulted: bool,
+    #[serde(default, with = "stash_format")]
+    /// 等待修复后恢复的详单
+    stash: Vec<ChargingDetail>,
+    #[serde(default)]
+    /// 是否处于免费充电状态
+    free_vend: bool,
+    #[serde(default)]
+    /// 免费充电时间段
+    free_windows: Vec<FreeWindow>,
+    #[serde(default)]
+    /// 等待进入队列的详单
+    pending: Vec<ChargingDetail>,
+}
+
+/// 等待恢复的详单在状态文件中的格式，没有时为 `null`，只有一个时与旧版本一样写成单个详单
+mod stash_format {
+    use super::ChargingDetail;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        Many(Vec<ChargingDetail>),
+        One(Box<ChargingDetail>),
+    }
+
+    pub fn serialize<S: Serializer>(
+        stash: &[ChargingDetail],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match stash {
+            [] => serializer.serialize_none(),
+            [detail] => detail.serialize(serializer),
+            _ => stash.serialize(serializer),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<ChargingDetail>, D::Error> {
+        Ok(match Option::<OneOrMany>::deserialize(deserializer)? {
+            None => Vec::new(),
+            Some(OneOrMany::One(detail)) => vec![*detail],
+            Some(OneOrMany::Many(details)) => details,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// 故障来源
+pub enum FaultSource {
+    /// 键盘模拟损坏，由 `charge.manual_break` 控制
+    Manual,
+    /// 充电桩内部产生的故障，由 `charge.faults_enabled` 控制
+    Internal,
+    /// 服务器发送的 `break` 消息，总是启用，用于无法使用键盘的测试环境
+    Remote,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// 充电详单被接受后所在的位置
+pub enum Admission {
+    /// 已加入队列，值为在队列中的位置，0 表示正在充电或即将开始充电
+    Queued(usize),
+    /// 队列已满，放入等待区，值为在等待区中的位置
+    Pending(usize),
+    /// 队列已满，按 `replace_last` 策略取消了最后加入队列的等待中详单后加入队列
+    Replaced {
+        /// 在队列中的位置
+        position: usize,
+        /// 被取消的详单，需要通知服务器
+        evicted: Box<ChargingDetail>,
+    },
+}
+
+/// 按 `replace_last` 策略被取消的详单的停止原因代码
+pub const QUEUE_OVERFLOW_REASON: &str = "queue_overflow";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// 充电详单无法加入队列的原因
+pub enum AddDetailError {
+    /// 充电桩已被服务器关闭
+    Closed,
+    /// 充电桩处于故障状态
+    Faulted,
+    /// 充电桩只接受预约
+    ReservationOnly,
+    /// 详单类型与充电桩类型不一致
+    TypeMismatch,
+    /// 详单期望功率与充电桩功率不一致（严格模式），值为两个功率
+    PowerMismatch(PowerWarning),
+    /// 队列已满
+    QueueFull,
+    /// 不限长队列达到安全上限
+    QueueSafetyCap,
+    /// 队列中已有相同 ID 的详单
+    DuplicateId,
+    /// 请求度数不是正数或超过上限
+    InvalidRequestAmount,
+}
+
+impl AddDetailError {
+    /// 机器可读的拒绝原因
+    pub fn reason(&self) -> &'static str {
+        match self {
+            AddDetailError::Closed => "closed",
+            AddDetailError::Faulted => "faulted",
+            AddDetailError::ReservationOnly => "reservation_only",
+            AddDetailError::TypeMismatch => "type_mismatch",
+            AddDetailError::PowerMismatch(_) => "power_mismatch",
+            AddDetailError::QueueFull => "queue_full",
+            AddDetailError::QueueSafetyCap => "queue_safety_cap",
+            AddDetailError::DuplicateId => "duplicate_id",
+            AddDetailError::InvalidRequestAmount => "invalid_request_amount",
+        }
+    }
+}
+
+/// 拒绝消息中的原因，带有数值的原因在机器可读的原因之后给出数值
+impl std::fmt::Display for AddDetailError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AddDetailError::PowerMismatch(warning) => write!(f, "{}: {}", self.reason(), warning),
+            _ => f.write_str(self.reason()),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(tag = "reason", content = "detail", rename_all = "snake_case")]
+/// 充电详单操作错误
+pub enum ChargeError {
+    /// 充电桩处于故障状态
+    Faulted,
+    /// 队列中没有指定 ID 的详单
+    NoSuchDetail(u32),
+}
+
+impl std::fmt::Display for ChargeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ChargeError::Faulted => write!(f, "faulted"),
+            ChargeError::NoSuchDetail(_) => write!(f, "no such charging detail"),
+        }
+    }
+}
+
+impl std::error::Error for ChargeError {}
+
+/// 按功率曲线充满详单尚未充电的度数所需的时长
+fn charge_duration(detail: &ChargingDetail, power: f64, curve: &PowerCurve) -> chrono::Duration {
+    chrono::Duration::milliseconds((detail.get_remaining_hours(power, curve) * 3_600_000.0) as i64)
+}
+
+/// 不限长队列的安全上限
+pub const UNLIMITED_QUEUE_CAP: usize = 10_000;
+
+fn new_event_sender() -> broadcast::Sender<ChargeEvent> {
+    broadcast::Sender::new(event::EVENT_CHANNEL_CAPACITY)
+}
+
+/// 构造带有详单快照的事件
+macro_rules! detail_event {
+    ($variant:ident) => {
+        |virtual_time, detail| ChargeEvent::$variant {
+            virtual_time,
+            detail,
+        }
+    };
+}
+
+fn single_connector() -> usize {
+    1
+}
+
+fn global_clock() -> Arc<dyn Clock> {
+    Arc::new(GlobalClock)
 }
 
 impl Charge {
@@ -34,245 +344,3220 @@ impl Charge {
             charge_id: Uuid::new_v4(),
             type_,
             power,
-            size,
+            size: Some(size),
+            reservation_only: false,
+            update_mode: UpdateMode::Full,
+            free_vend: false,
             queue: Vec::with_capacity(size as usize),
-            working: false,
+            pending: Vec::new(),
+            pending_capacity: 0,
+            overflow_policy: OverflowPolicy::Reject,
+            overflow_cap: None,
+            promoted: Vec::new(),
+            active: 0,
+            connectors: single_connector(),
+            eta_errors: EtaErrorStats::default(),
+            wait_times: WaitTimeStats::default(),
+            events: new_event_sender(),
+            metrics: Arc::default(),
+            requeue_after_repair: false,
+            stash: Vec::new(),
+            pricing: None,
+            free_windows: Vec::new(),
+            power_tolerance: 0.5, // 与配置默认值相同
+            strict_power_match: false,
+            cancellation_fee: 0.0,
+            cancellation_fee_after_kwh: None,
+            state: PileState::Idle,
+            span: tracing::Span::none(),
+            manual_break: false,
+            faults_enabled: true,
+            state_path: None,
+            update_interval: None,
+            min_update_interval: 1000, // 与配置默认值相同
+            max_request_amount: None,
+            curve: PowerCurve::default(), // 默认始终以额定功率充电
+            clock: global_clock(),        // 默认使用全局虚拟时钟
+            canceled_status: false,
+            drop_queue_on_close: true,
         }
     }
 
-    /// 添加充电详单到充电桩队列
-    pub fn add_detail(&mut self, detail: ChargingDetail) {
-        if detail.get_type() != self.type_ {
-            tracing::warn!(
-                virtual_time = %get_mock_now(),
-                "充电详单类型不匹配，无法添加到充电桩队列: {:?} != {:?}",
-                detail.get_type(),
-                self.type_
-            );
-            return;
-        } else if self.queue.len() < self.size as usize {
-            self.queue.push(detail);
-        } else {
-            tracing::warn!("充电桩队列已满，无法添加新的充电详单");
-        }
+    /// 设置详单请求度数的上限
+    pub fn with_max_request_amount(mut self, max: Option<f64>) -> Self {
+        self.max_request_amount = max;
+        self
     }
 
-    /// 开始充电
-    pub fn start_charging(&mut self) {
-        if self.queue.is_empty() {
-            tracing::warn!(virtual_time = %get_mock_now(), "充电桩队列为空，无法开始充电");
-            return;
-        }
-        if self.working {
-            tracing::warn!(virtual_time = %get_mock_now(), "充电桩正在工作，无法再次开始充电");
-            return;
+    /// 设置功率曲线
+    pub fn with_power_curve(mut self, curve: PowerCurve) -> Self {
+        self.curve = curve;
+        self
+    }
+
+    /// 获取功率曲线
+    pub fn get_power_curve(&self) -> &PowerCurve {
+        &self.curve
+    }
+
+    /// 设置虚拟时钟，测试时可以使用手动推进的时钟
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// 当前虚拟时间
+    pub fn now(&self) -> chrono::DateTime<chrono::Utc> {
+        self.clock.now()
+    }
+
+    /// 设置充电枪数量，每个正在充电的详单都使用充电桩的额定功率
+    pub fn with_connectors(mut self, connectors: u32) -> Self {
+        self.connectors = connectors.max(1) as usize;
+        self
+    }
+
+    /// 设置关闭充电桩时是否清空队列
+    pub fn with_drop_queue_on_close(mut self, drop_queue_on_close: bool) -> Self {
+        self.drop_queue_on_close = drop_queue_on_close;
+        self
+    }
+
+    /// 设置取消的详单是否使用 `canceled` 状态
+    pub fn with_canceled_status(mut self, canceled_status: bool) -> Self {
+        self.canceled_status = canceled_status;
+        self
+    }
+
+    /// 使用不限长的队列，队列长度只受安全上限限制
+    pub fn with_unlimited_queue(mut self) -> Self {
+        self.size = None;
+        self.queue = Vec::new();
+        self
+    }
+
+    /// 设置等待区容量，队列已满时新详单放入等待区，有空位时自动进入队列
+    pub fn with_pending_buffer(mut self, capacity: usize) -> Self {
+        self.pending_capacity = capacity;
+        self
+    }
+
+    /// 设置队列已满时新详单的处理方式，`cap` 为 `grow` 策略下最多可以超出队列大小的详单数量
+    pub fn with_overflow_policy(mut self, policy: OverflowPolicy, cap: Option<u32>) -> Self {
+        self.overflow_policy = policy;
+        self.overflow_cap = cap;
+        self
+    }
+
+    /// 设置是否只接受预约
+    pub fn with_reservation_only(mut self, reservation_only: bool) -> Self {
+        self.reservation_only = reservation_only;
+        self
+    }
+
+    /// 设置充电状态更新方式
+    pub fn with_update_mode(mut self, update_mode: UpdateMode) -> Self {
+        self.update_mode = update_mode;
+        self
+    }
+
+    /// 设置故障修复后是否自动恢复被打断的详单
+    pub fn with_requeue_after_repair(mut self, requeue: bool) -> Self {
+        self.requeue_after_repair = requeue;
+        self
+    }
+
+    /// 使用充电桩自己的价格表和时区，不再使用全局价格表
+    pub fn with_pricing(mut self, pricing: Pricing) -> Self {
+        self.pricing = Some(pricing);
+        self
+    }
+
+    /// 设置功率匹配规则
+    pub fn with_power_match(mut self, tolerance: f64, strict: bool) -> Self {
+        self.power_tolerance = tolerance;
+        self.strict_power_match = strict;
+        self
+    }
+
+    /// 设置取消违约金，只对已开始充电的详单收取
+    pub fn with_cancellation_fee(mut self, fee: f64, after_kwh: Option<f64>) -> Self {
+        self.cancellation_fee = fee;
+        self.cancellation_fee_after_kwh = after_kwh;
+        self
+    }
+
+    /// 设置启用的故障来源
+    pub fn with_fault_sources(mut self, manual_break: bool, faults_enabled: bool) -> Self {
+        self.manual_break = manual_break;
+        self.faults_enabled = faults_enabled;
+        self
+    }
+
+    /// 指定的故障来源是否启用
+    pub fn fault_armed(&self, source: FaultSource) -> bool {
+        match source {
+            FaultSource::Manual => self.manual_break,
+            FaultSource::Internal => self.faults_enabled,
+            FaultSource::Remote => true,
         }
+    }
+
+    /// 设置充电桩的更新间隔和详单可以指定的最短更新间隔，单位为毫秒
+    pub fn with_update_interval(mut self, interval: Option<u64>, min: u64) -> Self {
+        self.update_interval = interval;
+        self.min_update_interval = min;
+        self
+    }
+
+    /// 使用指定的充电桩ID
+    pub fn with_id(mut self, charge_id: Uuid) -> Self {
+        self.charge_id = charge_id;
+        self
+    }
 
-        self.working = true; // 设置充电桩为工作状态
+    /// 获取充电桩ID
+    pub fn get_id(&self) -> Uuid {
+        self.charge_id
+    }
 
-        let detail = self.queue.first_mut().unwrap();
+    /// 订阅充电桩的状态变化事件，订阅者处理过慢时丢失较早的事件，不会阻塞充电
+    pub fn subscribe(&self) -> broadcast::Receiver<ChargeEvent> {
+        self.events.subscribe()
+    }
 
-        detail.start(get_mock_now());
+    /// 获取充电桩指标
+    pub fn get_metrics(&self) -> Arc<PileMetrics> {
+        self.metrics.clone()
+    }
 
+    /// 从状态文件恢复队列状态，之后每次状态变化时写入该文件
+    /// 文件校验失败时使用上一代 `.bak` 文件，都无法使用时使用空队列并输出警告，返回是否恢复成功
+    pub fn restore(&mut self, path: impl AsRef<Path>) -> bool {
+        let path = path.as_ref();
+        self.state_path = Some(path.to_path_buf());
+        if !path.exists() && !persist::backup_path(path).exists() {
+            tracing::info!("状态文件 {} 不存在，使用空队列", path.display());
+            return false;
+        }
+        let (content, from) = match persist::read_with_fallback(path) {
+            Ok(read) => read,
+            Err(e) => {
+                tracing::warn!("状态文件 {} 校验失败，使用空队列: {}", path.display(), e);
+                return false;
+            }
+        };
+        let state: ChargeState = match serde_json::from_str(&content) {
+            Ok(state) => state,
+            Err(e) => {
+                tracing::warn!("状态文件 {} 无法解析，使用空队列: {}", from.display(), e);
+                return false;
+            }
+        };
+        self.queue = state.queue;
+        self.active = state
+            .active
+            .unwrap_or(state.working as usize)
+            .min(self.connectors)
+            .min(self.queue.len());
+        self.sync_metrics();
+        self.state = if state.faulted {
+            PileState::Faulted
+        } else if self.active > 0 {
+            PileState::Charging
+        } else {
+            PileState::Idle
+        };
+        self.span
+            .record("state", tracing::field::display(self.state));
+        self.stash = state.stash;
+        self.free_vend = state.free_vend;
+        self.free_windows = state.free_windows;
+        self.pending = state.pending;
         tracing::info!(
-            virtual_time = %get_mock_now(),
-            "充电桩开始充电 详单 ID: {}",
-            detail.get_id(),
+            "已从状态文件 {} 恢复 {} 个充电详单，正在工作: {}",
+            from.display(),
+            self.queue.len(),
+            self.is_working()
         );
+        true
     }
 
-    /// 更新充电状态
-    pub fn update_charging(&mut self) {
-        if self.queue.is_empty() {
-            tracing::warn!(virtual_time = %get_mock_now(), "充电桩队列为空，无法更新充电状态");
-            return;
-        }
-        if !self.working {
-            tracing::warn!(virtual_time = %get_mock_now(), "充电桩未处于工作状态，无法更新充电状态");
+    /// 队列或工作状态变化后更新指标并写入状态文件
+    fn state_changed(&self) {
+        self.sync_metrics();
+        self.persist();
+    }
+
+    /// 更新队列深度和工作状态指标
+    fn sync_metrics(&self) {
+        self.metrics.set_state(self.queue.len(), self.is_working());
+    }
+
+    /// 将队列状态写入状态文件，见 [`persist::write_atomic`]
+    fn persist(&self) {
+        let Some(path) = &self.state_path else {
             return;
+        };
+        let state = ChargeState {
+            queue: self.queue.clone(),
+            working: self.is_working(),
+            active: (self.connectors > 1).then_some(self.active),
+            faulted: self.is_faulted(),
+            stash: self.stash.clone(),
+            free_vend: self.free_vend,
+            free_windows: self.free_windows.clone(),
+            pending: self.pending.clone(),
+        };
+        if let Err(e) = persist::write_atomic(path, &serde_json::to_string(&state).unwrap())
+            && let Some(digest) = throttle::allow("charge.persist")
+        {
+            tracing::warn!("写入状态文件 {} 失败: {}{}", path.display(), e, digest);
         }
+    }
 
-        let detail = self.queue.first_mut().unwrap();
-        let now = get_mock_now();
-        let cost = calc_price_with_tz(detail.clone_start_time(), now.clone(), self.power).unwrap();
-        detail.update_state(
-            already_charged(self.power, &detail, now.clone()),
-            cost.0,
-            cost.1,
-            now.clone(),
-        );
+    /// 详单结束时记录指标并发布事件
+    fn finish(&self, outcome: Outcome, detail: &ChargingDetail) {
+        self.metrics
+            .record_finished(outcome, detail.get_already_charged());
+        let make = match outcome {
+            Outcome::Completed => detail_event!(Completed),
+            Outcome::Canceled => detail_event!(Canceled),
+            Outcome::Interrupted => detail_event!(Interrupted),
+        };
+        self.emit_detail(make, detail);
     }
 
-    /// 完成充电
-    pub fn complete_charging(&mut self) -> Option<ChargingDetail> {
-        // 检查队列是否为空或充电桩是否处于工作状态
-        // 如果队列为空或充电桩未工作，返回 None
-        if self.queue.is_empty() {
-            tracing::warn!(virtual_time = %get_mock_now(), "充电桩队列为空，无法完成充电");
-            None
-        } else if !self.working {
-            tracing::warn!(virtual_time = %get_mock_now(), "充电桩未处于工作状态，无法完成充电");
-            None
-        } else {
-            let mut detail = self.queue.remove(0);
-            self.working = false; // 完成充电时设置充电桩为非工作状态
-            let now = get_mock_now();
-            let cost =
-                calc_price_with_tz(detail.clone_start_time(), now.clone(), self.power).unwrap();
-            detail.complete(
-                already_charged(self.power, &detail, now.clone()),
-                cost.0,
-                cost.1,
-                now.clone(),
-            );
-            Some(detail)
+    /// 发布详单事件，没有订阅者时不复制详单
+    fn emit_detail(
+        &self,
+        make: fn(chrono::DateTime<chrono::Utc>, ChargingDetail) -> ChargeEvent,
+        detail: &ChargingDetail,
+    ) {
+        if self.events.receiver_count() > 0 || event::has_subscribers() {
+            self.emit(make(self.now(), detail.clone()));
         }
     }
 
-    /// 取消充电
-    pub fn cancel_charging(&mut self, detail_id: u32) -> Result<ChargingDetail, String> {
-        if let Some(pos) = self.queue.iter().position(|d| d.get_id() == detail_id) {
-            let detail = self.queue.get_mut(pos).unwrap();
-            let now = get_mock_now();
-            if pos == 0 {
-                let cost =
-                    calc_price_with_tz(detail.clone_start_time(), now.clone(), self.power).unwrap();
-                detail.interrupt(
-                    already_charged(self.power, &detail, now.clone()),
-                    cost.0,
-                    cost.1,
-                    now.clone(),
-                );
-                self.working = false; // 取消充电时设置充电桩为非工作状态
-            } else {
-                detail.interrupt(
-                    already_charged(self.power, &detail, now.clone()),
-                    0.0,
-                    0.0,
-                    now.clone(),
-                );
-            }
-            Ok(self.queue.remove(pos))
-        } else {
-            tracing::warn!(virtual_time = %get_mock_now(), "未找到指定的充电详单，无法取消充电");
-            Err("no such charging detail".to_string())
+    /// 发布事件到充电桩自己的通道和进程内共用的通道，没有订阅者时直接丢弃
+    fn emit(&self, event: ChargeEvent) {
+        if event::has_subscribers() {
+            event::publish(PileEvent {
+                charge_id: self.charge_id,
+                event: event.clone(),
+            });
         }
+        let _ = self.events.send(event);
     }
 
-    /// 获取正在充电的充电详单的引用
-    pub fn get_charging_detail_ref(&self) -> Option<&ChargingDetail> {
-        self.queue.first()
+    /// 添加充电详单到充电桩队列
+    /// 无法加入队列时返回拒绝原因，按 `replace_last` 策略取消了其他详单时返回被取消的详单
+    pub fn add_detail(
+        &mut self,
+        detail: ChargingDetail,
+    ) -> Result<Option<ChargingDetail>, AddDetailError> {
+        self.check_admission(&detail)?;
+        let evicted = self.evict_last_waiting();
+        self.enqueue(detail);
+        Ok(evicted)
     }
 
-    /// 关闭充电桩
-    pub fn close(&mut self) -> Option<ChargingDetail> {
-        self.working = false; // 设置充电桩为非工作状态
-        if self.queue.is_empty() {
-            tracing::info!(virtual_time = %get_mock_now(), "充电桩队列为空，没有被打断的充电详单");
-            None
-        } else {
-            let mut detail = self.queue.remove(0);
-            self.queue.clear(); // 清空队列
-            let now = get_mock_now();
-            let cost =
-                calc_price_with_tz(detail.clone_start_time(), now.clone(), self.power).unwrap();
-            detail.interrupt(
-                already_charged(self.power, &detail, now.clone()),
-                cost.0,
-                cost.1,
-                now.clone(),
-            );
-            Some(detail)
+    /// 接受充电详单，队列已满且等待区有空位时放入等待区
+    /// 没有设置等待区时与 [`Charge::add_detail`] 相同
+    pub fn admit(&mut self, detail: ChargingDetail) -> Result<Admission, AddDetailError> {
+        match self.check_admission(&detail) {
+            Ok(()) => {
+                let evicted = self.evict_last_waiting();
+                self.enqueue(detail);
+                let position = self.queue.len() - 1;
+                Ok(match evicted {
+                    Some(evicted) => Admission::Replaced {
+                        position,
+                        evicted: Box::new(evicted),
+                    },
+                    None => Admission::Queued(position),
+                })
+            }
+            Err(AddDetailError::QueueFull) if self.pending.len() < self.pending_capacity => {
+                tracing::info!(
+                    virtual_time = %self.now(),
+                    "充电桩队列已满，充电详单 {} 进入等待区，等待区长度: {}",
+                    detail.get_id(),
+                    self.pending.len() + 1
+                );
+                self.pending.push(detail);
+                self.state_changed();
+                Ok(Admission::Pending(self.pending.len() - 1))
+            }
+            Err(e) => Err(e),
         }
     }
 
-    /// 损坏充电桩
-    pub fn breakdown(&mut self) -> Option<ChargingDetail> {
-        self.close() // 关闭充电桩并清空队列
+    /// 队列已满时按 `replace_last` 策略取消队尾等待中的详单，为新详单腾出位置
+    /// 正在充电的详单不会被取消，队列已满但没有等待中的详单时 [`Charge::check_admission`] 已经拒绝
+    fn evict_last_waiting(&mut self) -> Option<ChargingDetail> {
+        let full = self
+            .size
+            .is_some_and(|size| self.queue.len() >= size as usize);
+        if !full || self.overflow_policy != OverflowPolicy::ReplaceLast {
+            return None;
+        }
+        if self.queue.len() <= self.active {
+            return None;
+        }
+        let mut detail = self.queue.pop()?;
+        tracing::warn!(
+            virtual_time = %self.now(),
+            reason = QUEUE_OVERFLOW_REASON,
+            "充电桩队列已满，取消最后加入队列的充电详单 {}",
+            detail.get_id()
+        );
+        cancel_detail(&mut detail, self.canceled_status, 0.0, 0.0, 0.0, self.now());
+        detail.set_stop_reason(
+            Some(QUEUE_OVERFLOW_REASON.to_string()),
+            Some("replaced by a newer detail".to_string()),
+        );
+        self.finish(Outcome::Canceled, &detail);
+        Some(detail)
     }
 
-    /// 是否正在工作
-    pub fn is_working(&self) -> bool {
-        self.working
+    /// 超出队列大小的详单数量，只有 `grow` 策略下会超出
+    pub fn get_overload(&self) -> usize {
+        self.size
+            .map_or(0, |size| self.queue.len().saturating_sub(size as usize))
     }
 
-    /// 获取队列大小
-    pub fn get_queue_size(&self) -> usize {
-        self.queue.len()
+    /// 把详单加入队列
+    fn enqueue(&mut self, mut detail: ChargingDetail) {
+        detail.set_pile_power(self.power);
+        detail.set_free_vend(self.free_vend);
+        detail.set_enqueued_at(self.now());
+        self.emit_detail(detail_event!(DetailQueued), &detail);
+        self.queue.push(detail);
+        self.estimate_tail();
+        self.state_changed();
     }
 
-    /// 获取预计完成间隔(毫秒)
-    pub fn complete_interval(&self) -> u64 {
-        if self.queue.is_empty() {
-            tracing::warn!(virtual_time = %get_mock_now(), "充电桩队列为空，无法获取完成间隔");
-            0
-        } else if !self.working {
-            tracing::warn!(virtual_time = %get_mock_now(), "充电桩未处于工作状态，无法获取完成间隔");
-            0
-        } else {
-            let time = self
-                .queue
-                .first()
-                .unwrap()
-                .get_estimated_end_time(self.power);
-            if let Some(end_time) = time {
-                let now = get_mock_now();
-                let duration = end_time.signed_duration_since(now);
-                let millis = duration.num_milliseconds() + 100; // 加100毫秒以避免精度问题
-                millis as u64 / CONF.time.speed // 考虑加速倍数
-            } else {
-                tracing::warn!(virtual_time = %get_mock_now(), "无法计算预计充电结束时间");
-                0
+    /// 只估计队尾新加入的详单，排在前一个详单之后，避免长队列每次加入都重新计算整个队列
+    fn estimate_tail(&mut self) {
+        let now = self.now();
+        let len = self.queue.len();
+        let previous_end = match len {
+            0 => return,
+            // 有多把充电枪时新详单不一定排在前一个详单之后
+            _ if len == 1 || self.connectors > 1 => None,
+            _ => self.queue[len - 2]
+                .get_schedule_estimate()
+                .map(|(_, end)| end),
+        };
+        match previous_end {
+            Some(end) if self.power > 0.0 => {
+                let start = end.max(now);
+                let tail = self.queue.last_mut().unwrap();
+                let power = tail.effective_power(self.power);
+                let end = start + charge_duration(tail, power, &self.curve);
+                tail.set_schedule_estimate(start, end);
             }
+            _ => self.estimate_schedule_at(now),
         }
     }
-}
 
-fn already_charged(
-    power: f64,
-    detail: &ChargingDetail,
-    time: chrono::DateTime<chrono::Utc>,
-) -> f64 {
-    let start_time = detail.clone_start_time();
-    let duration = time.signed_duration_since(start_time);
-    let hours = duration.num_seconds() as f64 / 3600.0; // 转换为小时
-    hours * power // 计算已充电度数
-}
+    /// 按充电桩功率依次估计队列中每个详单的开始和结束时间，并记录在详单中
+    /// 正在充电的详单按已充电度数计算剩余时长，等待中的详单依次排在最早空闲的充电枪上
+    pub fn estimate_schedule(&mut self) {
+        self.estimate_schedule_at(self.now());
+    }
 
-/// 全局充电桩实例，使用 Lazy 和 Mutex 确保线程安全和延迟初始化
-pub static CHARGE: Lazy<Mutex<Charge>> = Lazy::new(|| {
-    Mutex::new(Charge::new(
-        CONF.charge.charge_type,
-        CONF.charge.power,
-        CONF.charge.size,
-    ))
-});
+    /// 以指定的虚拟时间为当前时间估计队列中每个详单的开始和结束时间
+    fn estimate_schedule_at(&mut self, now: chrono::DateTime<chrono::Utc>) {
+        if self.power <= 0.0 {
+            return;
+        }
+        // 每把充电枪空闲的时间
+        let mut free = vec![now; self.connectors];
+        for (pos, detail) in self.queue.iter_mut().enumerate() {
+            let power = detail.effective_power(self.power);
+            let (slot, start, end) = if pos < self.active {
+                // 已充电度数是最后一次更新时的度数
+                let updated = detail.get_last_update_time().unwrap_or(now);
+                (
+                    pos,
+                    detail.clone_start_time(),
+                    updated + charge_duration(detail, power, &self.curve),
+                )
+            } else {
+                let slot = (0..free.len()).min_by_key(|&slot| free[slot]).unwrap();
+                (
+                    slot,
+                    free[slot],
+                    free[slot] + charge_duration(detail, power, &self.curve),
+                )
+            };
+            detail.set_schedule_estimate(start, end);
+            free[slot] = end.max(now);
+        }
+    }
 
-#[cfg(test)]
-mod test {
-    use super::*;
-    use crate::conf::ChargeType;
+    /// 队列有空位时把等待区中的详单按顺序移入队列
+    fn promote_pending(&mut self) {
+        while !self.pending.is_empty()
+            && self
+                .size
+                .is_none_or(|size| self.queue.len() < size as usize)
+        {
+            let detail = self.pending.remove(0);
+            tracing::info!(
+                virtual_time = %self.now(),
+                "充电详单 {} 从等待区进入队列",
+                detail.get_id()
+            );
+            self.promoted.push(detail.get_id());
+            self.enqueue(detail);
+        }
+    }
 
-    #[test]
-    fn test_charge_serialization() {
-        // v4 生成
-        let charge = Charge {
-            charge_id: Uuid::new_v4(),
-            type_: ChargeType::Fast,
-            power: 30.0,
-            size: 5,
-            queue: vec![],
-            working: false,
-        };
+    /// 取出从等待区进入队列、尚未通知服务器的详单 ID 和它们在队列中的位置
+    pub fn take_promoted(&mut self) -> Vec<(u32, usize)> {
+        let promoted = std::mem::take(&mut self.promoted);
+        promoted
+            .into_iter()
+            .filter_map(|id| {
+                let pos = self.queue.iter().position(|d| d.get_id() == id)?;
+                Some((id, pos))
+            })
+            .collect()
+    }
 
-        let serialized = serde_json::to_string_pretty(&charge).unwrap();
-        println!("Serialized Charge: {}", serialized);
+    /// 等待区中的详单数量
+    pub fn get_pending_size(&self) -> usize {
+        self.pending.len()
+    }
 
-        let deserialized: Charge = serde_json::from_str(&serialized).unwrap();
-        assert_eq!(deserialized.charge_id, charge.charge_id);
-        assert_eq!(deserialized.type_, charge.type_);
-        assert_eq!(deserialized.power, charge.power);
-        assert_eq!(deserialized.size, charge.size);
-        assert_eq!(deserialized.queue.len(), charge.queue.len());
+    /// 检查充电详单能否加入队列
+    fn check_admission(&self, detail: &ChargingDetail) -> Result<(), AddDetailError> {
+        if let Err(e) = detail.validate_request_amount(self.max_request_amount) {
+            if let Some(digest) = throttle::allow("add_detail.request_amount") {
+                tracing::warn!(
+                    virtual_time = %self.now(),
+                    reason = "invalid_request_amount",
+                    "充电详单请求度数不合法，拒绝充电详单 {}: {}{}",
+                    detail.get_id(),
+                    e,
+                    digest
+                );
+            }
+            return Err(AddDetailError::InvalidRequestAmount);
+        }
+        if self.state == PileState::Closed {
+            if let Some(digest) = throttle::allow("add_detail.closed") {
+                tracing::warn!(
+                    virtual_time = %self.now(),
+                    reason = "closed",
+                    "充电桩已关闭，拒绝充电详单: {}{}",
+                    detail.get_id(),
+                    digest
+                );
+            }
+            return Err(AddDetailError::Closed);
+        }
+        if self.is_faulted() {
+            if let Some(digest) = throttle::allow("add_detail.faulted") {
+                tracing::warn!(
+                    virtual_time = %self.now(),
+                    reason = "faulted",
+                    "充电桩处于故障状态，拒绝充电详单: {}{}",
+                    detail.get_id(),
+                    digest
+                );
+            }
+            return Err(AddDetailError::Faulted);
+        }
+        if self.reservation_only {
+            if let Some(digest) = throttle::allow("add_detail.reservation_only") {
+                tracing::warn!(
+                    virtual_time = %self.now(),
+                    reason = "reservation_only",
+                    "充电桩只接受预约，拒绝充电详单: {}{}",
+                    detail.get_id(),
+                    digest
+                );
+            }
+            return Err(AddDetailError::ReservationOnly);
+        }
+        if detail.get_type() != self.type_ {
+            if let Some(digest) = throttle::allow("add_detail.type") {
+                tracing::warn!(
+                    virtual_time = %self.now(),
+                    "充电详单类型不匹配，无法添加到充电桩队列: {:?} != {:?}{}",
+                    detail.get_type(),
+                    self.type_,
+                    digest
+                );
+            }
+            return Err(AddDetailError::TypeMismatch);
+        }
+        match self.check_power(detail, self.power_tolerance, self.strict_power_match) {
+            Ok(Some(warning)) => {
+                if let Some(digest) = throttle::allow("add_detail.power_warn") {
+                    tracing::warn!(virtual_time = %self.now(), "充电详单功率不一致: {}{}", warning, digest);
+                }
+            }
+            Ok(None) => {}
+            Err(e) => {
+                if let Some(digest) = throttle::allow("add_detail.power_reject") {
+                    tracing::warn!(
+                        virtual_time = %self.now(),
+                        "充电详单功率不一致，无法添加到充电桩队列: {}{}",
+                        e,
+                        digest
+                    );
+                }
+                return Err(AddDetailError::PowerMismatch(e));
+            }
+        }
+        let id = detail.get_id();
+        if self
+            .queue
+            .iter()
+            .chain(&self.stash)
+            .chain(&self.pending)
+            .any(|d| d.get_id() == id)
+        {
+            if let Some(digest) = throttle::allow("add_detail.duplicate") {
+                tracing::warn!(
+                    virtual_time = %self.now(),
+                    "队列中已有相同 ID 的充电详单，拒绝充电详单: {}{}",
+                    id,
+                    digest
+                );
+            }
+            return Err(AddDetailError::DuplicateId);
+        }
+        match self.size {
+            Some(size) if self.queue.len() >= size as usize => {
+                let cap = size as usize + self.overflow_cap.unwrap_or(size) as usize;
+                match self.overflow_policy {
+                    OverflowPolicy::Grow if self.queue.len() < cap => {
+                        if let Some(digest) = throttle::allow("add_detail.grow") {
+                            tracing::warn!(
+                                virtual_time = %self.now(),
+                                "充电桩队列超出队列大小 {}，当前队列长度: {}{}",
+                                size,
+                                self.queue.len() + 1,
+                                digest
+                            );
+                        }
+                    }
+                    OverflowPolicy::ReplaceLast if self.queue.len() > self.active => {}
+                    _ => {
+                        if let Some(digest) = throttle::allow("add_detail.full") {
+                            tracing::warn!("充电桩队列已满，无法添加新的充电详单{}", digest);
+                        }
+                        return Err(AddDetailError::QueueFull);
+                    }
+                }
+            }
+            None if self.queue.len() >= UNLIMITED_QUEUE_CAP => {
+                if let Some(digest) = throttle::allow("add_detail.safety_cap") {
+                    tracing::error!(
+                        "不限长队列已达到安全上限 {}，无法添加新的充电详单{}",
+                        UNLIMITED_QUEUE_CAP,
+                        digest
+                    );
+                }
+                return Err(AddDetailError::QueueSafetyCap);
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// 检查详单期望功率与充电桩功率是否一致
+    /// 偏差在允许范围内返回 `Ok(None)`，超出范围时严格模式返回错误，否则返回警告
+    pub fn check_power(
+        &self,
+        detail: &ChargingDetail,
+        tolerance: f64,
+        strict: bool,
+    ) -> Result<Option<PowerWarning>, PowerWarning> {
+        let Some(expected) = detail.get_expected_power() else {
+            return Ok(None);
+        };
+        if (expected - self.power).abs() <= tolerance {
+            return Ok(None);
+        }
+        let warning = PowerWarning {
+            expected_power: expected,
+            pile_power_kw: self.power,
+        };
+        if strict {
+            Err(warning)
+        } else {
+            Ok(Some(warning))
+        }
+    }
+
+    /// 按配置的允许偏差检查详单期望功率，非严格模式下超出范围时返回随确认消息发送的警告
+    pub fn power_warning(&self, detail: &ChargingDetail) -> Option<PowerWarning> {
+        if self.strict_power_match {
+            return None;
+        }
+        self.check_power(detail, self.power_tolerance, false)
+            .ok()
+            .flatten()
+    }
+
+    /// 是否有空闲的充电枪和等待充电的详单
+    pub fn can_start(&self) -> bool {
+        self.state.is_operating() && self.active < self.connectors && self.queue.len() > self.active
+    }
+
+    /// 开始充电，用一把空闲的充电枪为下一个等待中的详单充电，返回开始充电的详单 ID
+    pub fn start_charging(&mut self) -> Option<u32> {
+        if !self.state.is_operating() {
+            tracing::warn!(virtual_time = %self.now(), "充电桩当前为{}状态，无法开始充电", self.state.label());
+            return None;
+        }
+        if self.queue.is_empty() {
+            tracing::warn!(virtual_time = %self.now(), "充电桩队列为空，无法开始充电");
+            return None;
+        }
+        if self.active >= self.connectors {
+            tracing::warn!(virtual_time = %self.now(), "充电桩正在工作，无法再次开始充电");
+            return None;
+        }
+        if self.queue.len() <= self.active {
+            tracing::warn!(virtual_time = %self.now(), "充电桩队列中没有等待充电的详单，无法开始充电");
+            return None;
+        }
+
+        if self.active == 0 {
+            // 已结束的免费充电时间段不会再影响新的会话
+            self.free_windows.retain(|(_, end)| end.is_none());
+        }
+        let pos = self.active;
+        self.active += 1; // 占用一把充电枪，充电桩处于工作状态
+        self.sync_activity();
+
+        let now = self.now();
+        let detail = &mut self.queue[pos];
+
+        let power = detail.effective_power(self.power);
+        detail.start(now, power, &self.curve);
+        let id = detail.get_id();
+
+        tracing::info!(
+            virtual_time = %self.now(),
+            "充电桩开始充电 详单 ID: {}",
+            id,
+        );
+        self.refresh_update_interval();
+        self.estimate_schedule();
+        self.emit_detail(detail_event!(ChargingStarted), &self.queue[pos]);
+        self.state_changed();
+        Some(id)
+    }
+
+    /// 重新选择正在充电的详单的更新间隔并记录在详单中，返回其中最短的间隔，单位为毫秒
+    /// 开始充电和运行时配置变化时调用
+    pub fn refresh_update_interval(&mut self) -> u64 {
+        let (pile, min) = (self.update_interval, self.min_update_interval);
+        let active = self.active.min(self.queue.len());
+        self.queue[..active]
+            .iter_mut()
+            .map(|detail| {
+                let interval = resolve_update_interval(
+                    detail.get_update_interval_ms(),
+                    pile,
+                    RUNTIME.update_interval(),
+                    min,
+                );
+                detail.set_effective_update_interval_ms(interval);
+                interval
+            })
+            .min()
+            .unwrap_or_else(|| pile.unwrap_or(RUNTIME.update_interval()))
+    }
+
+    /// 当前使用的更新间隔，单位为毫秒，所有正在充电的详单共用一个更新计时器，使用其中最短的间隔
+    pub fn update_interval(&self) -> u64 {
+        self.get_charging_details()
+            .iter()
+            .filter_map(ChargingDetail::get_effective_update_interval_ms)
+            .min()
+            .unwrap_or_else(|| self.update_interval.unwrap_or(RUNTIME.update_interval()))
+    }
+
+    /// 更新所有正在充电的详单的充电状态
+    pub fn update_charging(&mut self) {
+        if self.queue.is_empty() {
+            tracing::warn!(virtual_time = %self.now(), "充电桩队列为空，无法更新充电状态");
+            return;
+        }
+        if !self.is_working() {
+            tracing::warn!(virtual_time = %self.now(), "充电桩未处于工作状态，无法更新充电状态");
+            return;
+        }
+
+        self.update_charging_at(self.now());
+    }
+
+    /// 按指定的虚拟时间更新所有正在充电的详单的充电状态，调用前需要确认充电桩正在工作
+    pub fn update_charging_at(&mut self, now: chrono::DateTime<chrono::Utc>) {
+        let settled = (0..self.get_charging_details().len())
+            .map(|pos| self.update_detail_at(pos, now))
+            .max()
+            .unwrap_or(now);
+        self.estimate_schedule_at(settled);
+        self.state_changed();
+    }
+
+    /// 按指定的虚拟时间更新队列中第 `pos` 个正在充电的详单，返回结算使用的时间
+    fn update_detail_at(
+        &mut self,
+        pos: usize,
+        now: chrono::DateTime<chrono::Utc>,
+    ) -> chrono::DateTime<chrono::Utc> {
+        let power = self.queue[pos].effective_power(self.power);
+        let now = settle_time(power, &self.curve, &self.queue[pos], now);
+        let detail = &mut self.queue[pos];
+        let profile = power_profile(power, &self.curve, detail, now);
+        let cost = calc_rated_price(
+            self.pricing.as_ref(),
+            &self.free_windows,
+            &profile,
+            detail.get_prior_energy(),
+        )
+        .unwrap();
+        let charged = delivered(power, &self.curve, detail, now);
+        detail.update_state(charged, cost.0, cost.1, now);
+        self.emit_detail(detail_event!(Progress), &self.queue[pos]);
+        now
+    }
+
+    /// 完成指定详单的充电
+    pub fn complete_charging(&mut self, id: u32) -> Option<ChargingDetail> {
+        self.complete_charging_at(id, self.now())
+    }
+
+    /// 按指定的虚拟时间完成指定详单的充电
+    /// 时钟发生跳变时按请求电量计算结束时间，而不是使用跳变后的时间
+    pub fn complete_charging_at(
+        &mut self,
+        id: u32,
+        now: chrono::DateTime<chrono::Utc>,
+    ) -> Option<ChargingDetail> {
+        // 检查队列是否为空或充电桩是否处于工作状态
+        // 如果队列为空、充电桩未工作或详单不在充电，返回 None
+        if self.queue.is_empty() {
+            tracing::warn!(virtual_time = %self.now(), "充电桩队列为空，无法完成充电");
+            None
+        } else if !self.is_working() {
+            tracing::warn!(virtual_time = %self.now(), "充电桩未处于工作状态，无法完成充电");
+            None
+        } else if let Some(pos) = self.charging_position(id) {
+            let mut detail = self.queue.remove(pos);
+            self.active -= 1; // 完成充电时释放充电枪，没有其他正在充电的详单时充电桩为非工作状态
+            self.sync_activity();
+            let power = detail.effective_power(self.power);
+            let now = settle_time(power, &self.curve, &detail, now);
+            let profile = power_profile(power, &self.curve, &detail, now);
+            let cost = calc_rated_price(
+                self.pricing.as_ref(),
+                &self.free_windows,
+                &profile,
+                detail.get_prior_energy(),
+            )
+            .unwrap();
+            let per_period = calc_rated_price_breakdown(
+                self.pricing.as_ref(),
+                &self.free_windows,
+                &profile,
+                detail.get_prior_energy(),
+            )
+            .unwrap();
+            let charged = delivered(power, &self.curve, &detail, now);
+            detail.complete(charged, cost.0, cost.1, now);
+            detail.set_per_period(per_period);
+            detail.set_breakdown(
+                calc_rated_price_itemized(
+                    self.pricing.as_ref(),
+                    &self.free_windows,
+                    &profile,
+                    detail.get_prior_energy(),
+                )
+                .unwrap(),
+            );
+            // 按次服务费和占位费在完成时只收取一次，免费充电时不收取
+            if !self.free_vend {
+                detail.apply_completion_fees(&service_fee_using(self.pricing.as_ref(), now));
+            }
+            if let Some(error) = detail.get_eta_error() {
+                self.eta_errors
+                    .record(error.num_milliseconds() as f64 / 1000.0);
+                if let Some(summary) = self.eta_errors.summary() {
+                    self.metrics.set_eta_errors(&summary);
+                }
+            }
+            if let Some(wait) = detail.get_wait_duration_s() {
+                self.wait_times.record(wait);
+            }
+            self.finish(Outcome::Completed, &detail);
+            self.estimate_schedule();
+            self.state_changed();
+            self.promote_pending();
+            Some(detail)
+        } else {
+            tracing::warn!(virtual_time = %self.now(), "充电详单 {} 不在充电，无法完成充电", id);
+            None
+        }
+    }
+
+    /// 正在充电的详单在队列中的位置
+    fn charging_position(&self, id: u32) -> Option<usize> {
+        self.get_charging_details()
+            .iter()
+            .position(|detail| detail.get_id() == id)
+    }
+
+    /// 获取会话时长预测误差统计
+    pub fn get_eta_error_stats(&self) -> &EtaErrorStats {
+        &self.eta_errors
+    }
+
+    /// 获取排队等待时长统计
+    pub fn get_wait_time_stats(&self) -> &WaitTimeStats {
+        &self.wait_times
+    }
+
+    /// 取消充电
+    /// 故障期间只能取消等待修复后恢复的详单
+    /// 取消原因记录在详单中，未给出原因代码时为 `unspecified`
+    pub fn cancel_charging(
+        &mut self,
+        detail_id: u32,
+        reason_code: Option<String>,
+        reason: Option<String>,
+    ) -> Result<ChargingDetail, ChargeError> {
+        if let Some(pos) = self.stash.iter().position(|d| d.get_id() == detail_id) {
+            let mut detail = self.stash.remove(pos);
+            tracing::info!(virtual_time = %self.now(), "等待恢复的充电详单 {} 被取消", detail_id);
+            cancel_detail(&mut detail, self.canceled_status, 0.0, 0.0, 0.0, self.now());
+            detail.set_stop_reason(reason_code, reason);
+            self.finish(Outcome::Canceled, &detail);
+            self.state_changed();
+            return Ok(detail);
+        }
+        if let Some(pos) = self.pending.iter().position(|d| d.get_id() == detail_id) {
+            let mut detail = self.pending.remove(pos);
+            tracing::info!(virtual_time = %self.now(), "等待区中的充电详单 {} 被取消", detail_id);
+            cancel_detail(&mut detail, self.canceled_status, 0.0, 0.0, 0.0, self.now());
+            detail.set_stop_reason(reason_code, reason);
+            self.finish(Outcome::Canceled, &detail);
+            self.state_changed();
+            return Ok(detail);
+        }
+        if self.is_faulted() {
+            tracing::warn!(virtual_time = %self.now(), reason = "faulted", "充电桩处于故障状态，拒绝取消充电详单: {}", detail_id);
+            return Err(ChargeError::Faulted);
+        }
+        if let Some(pos) = self.queue.iter().position(|d| d.get_id() == detail_id) {
+            let canceled_status = self.canceled_status;
+            let now = self.now();
+            let detail = self.queue.get_mut(pos).unwrap();
+            let started = pos < self.active;
+            if started {
+                let power = detail.effective_power(self.power);
+                let profile = power_profile(power, &self.curve, detail, now);
+                let cost = calc_rated_price(
+                    self.pricing.as_ref(),
+                    &self.free_windows,
+                    &profile,
+                    detail.get_prior_energy(),
+                )
+                .unwrap();
+                let per_period = calc_rated_price_breakdown(
+                    self.pricing.as_ref(),
+                    &self.free_windows,
+                    &profile,
+                    detail.get_prior_energy(),
+                )
+                .unwrap();
+                let already_charged = already_charged(power, &self.curve, detail, now);
+                cancel_detail(
+                    detail,
+                    canceled_status,
+                    already_charged,
+                    cost.0,
+                    cost.1,
+                    now,
+                );
+                detail.set_per_period(per_period);
+                detail.set_breakdown(
+                    calc_rated_price_itemized(
+                        self.pricing.as_ref(),
+                        &self.free_windows,
+                        &profile,
+                        detail.get_prior_energy(),
+                    )
+                    .unwrap(),
+                );
+                self.active -= 1; // 取消充电时释放充电枪
+            } else {
+                // 等待中的详单尚未开始充电
+                cancel_detail(detail, canceled_status, 0.0, 0.0, 0.0, now);
+            }
+            detail.set_stop_reason(reason_code, reason);
+            let mut detail = self.queue.remove(pos);
+            self.sync_activity();
+            // 等待中的详单取消时不收取违约金
+            if started && let Some(fee) = self.cancellation_fee_for(detail.get_already_charged()) {
+                detail.apply_penalty_fee(fee);
+                tracing::info!(virtual_time = %self.now(), "充电详单 {} 开始充电后取消，收取违约金: {}", detail_id, fee);
+            }
+            self.finish(Outcome::Canceled, &detail);
+            self.estimate_schedule();
+            self.state_changed();
+            self.promote_pending();
+            Ok(detail)
+        } else {
+            tracing::warn!(virtual_time = %self.now(), "未找到指定的充电详单，无法取消充电");
+            Err(ChargeError::NoSuchDetail(detail_id))
+        }
+    }
+
+    /// 开始充电后取消时应收取的违约金，已充电度数未达到阈值时不收取
+    fn cancellation_fee_for(&self, already_charged: f64) -> Option<f64> {
+        let reached = self
+            .cancellation_fee_after_kwh
+            .is_none_or(|kwh| already_charged >= kwh);
+        (self.cancellation_fee > 0.0 && reached).then_some(self.cancellation_fee)
+    }
+
+    /// 获取正在充电的充电详单的引用
+    pub fn get_charging_detail_ref(&self) -> Option<&ChargingDetail> {
+        self.queue.first()
+    }
+
+    /// 关闭充电桩，返回被打断的详单，没有正在充电的详单时为队首的详单
+    pub fn close(&mut self) -> Vec<ChargingDetail> {
+        let active = std::mem::take(&mut self.active); // 设置充电桩为非工作状态
+        self.sync_activity();
+        if self.queue.is_empty() {
+            tracing::info!(virtual_time = %self.now(), "充电桩队列为空，没有被打断的充电详单");
+            Vec::new()
+        } else {
+            let interrupted = (0..active.max(1))
+                .map(|pos| self.interrupt_head(pos < active))
+                .collect();
+            self.queue.clear(); // 清空队列
+            self.pending.clear();
+            self.state_changed();
+            interrupted
+        }
+    }
+
+    /// 暂停充电桩，只中断正在充电的详单，等待中的详单保留在队列中，重新打开后继续充电
+    pub fn suspend(&mut self) -> Vec<ChargingDetail> {
+        if !self.is_working() {
+            tracing::info!(virtual_time = %self.now(), "充电桩未处于工作状态，没有被打断的充电详单");
+            return Vec::new();
+        }
+        let active = std::mem::take(&mut self.active); // 设置充电桩为非工作状态
+        self.sync_activity();
+        let interrupted = (0..active).map(|_| self.interrupt_head(true)).collect();
+        self.estimate_schedule();
+        self.state_changed();
+        self.promote_pending();
+        interrupted
+    }
+
+    /// 服务器关闭充电桩，按 [`Charge::drops_queue_on_close`] 清空队列或只中断正在充电的详单，
+    /// 返回被打断的详单，被打断的详单已经各自发布了中断事件
+    /// 只能在空闲或充电中关闭，否则返回错误，队列不变
+    pub fn close_by_server(&mut self) -> Result<Vec<ChargingDetail>, TransitionError> {
+        // 先进入关闭状态，中断详单后不会再回到空闲状态
+        self.enter(Transition::Close)?;
+        let interrupted = if self.drop_queue_on_close {
+            self.close()
+        } else {
+            self.suspend()
+        };
+        self.emit(ChargeEvent::PileClosed {
+            virtual_time: self.now(),
+        });
+        Ok(interrupted)
+    }
+
+    /// 服务器重新打开充电桩，只能在关闭状态下打开，之后由调用方开始为等待中的详单充电
+    pub fn open_by_server(&mut self) -> Result<(), TransitionError> {
+        self.enter(Transition::Open)?;
+        self.emit(ChargeEvent::PileOpened {
+            virtual_time: self.now(),
+        });
+        Ok(())
+    }
+
+    /// 按事件转换状态，状态变化时记录日志并更新日志 span 中的 `state` 字段
+    fn enter(&mut self, transition: Transition) -> Result<PileState, TransitionError> {
+        let next = self.state.next(transition)?;
+        if next != self.state {
+            tracing::info!(virtual_time = %self.now(), "充电桩状态: {} -> {}", self.state, next);
+            self.state = next;
+            self.span.record("state", tracing::field::display(next));
+        }
+        Ok(next)
+    }
+
+    /// 正在充电的详单数量变化后在空闲和充电中之间切换，关闭或故障时不变
+    fn sync_activity(&mut self) {
+        let transition = match (self.state, self.active > 0) {
+            (PileState::Idle, true) => Transition::Start,
+            (PileState::Charging, false) => Transition::Finish,
+            _ => return,
+        };
+        // 空闲和充电中之间的转换总是允许的
+        let _ = self.enter(transition);
+    }
+
+    /// 关闭充电桩时是否清空队列
+    pub fn drops_queue_on_close(&self) -> bool {
+        self.drop_queue_on_close
+    }
+
+    /// 从队首取出详单并中断，`started` 为 `true` 时按已充电度数结算
+    fn interrupt_head(&mut self, started: bool) -> ChargingDetail {
+        let mut detail = self.queue.remove(0);
+        let now = self.now();
+        if started {
+            let power = detail.effective_power(self.power);
+            let profile = power_profile(power, &self.curve, &detail, now);
+            let cost = calc_rated_price(
+                self.pricing.as_ref(),
+                &self.free_windows,
+                &profile,
+                detail.get_prior_energy(),
+            )
+            .unwrap();
+            let per_period = calc_rated_price_breakdown(
+                self.pricing.as_ref(),
+                &self.free_windows,
+                &profile,
+                detail.get_prior_energy(),
+            )
+            .unwrap();
+            let charged = already_charged(power, &self.curve, &detail, now);
+            detail.interrupt(charged, cost.0, cost.1, now);
+            detail.set_per_period(per_period);
+            detail.set_breakdown(
+                calc_rated_price_itemized(
+                    self.pricing.as_ref(),
+                    &self.free_windows,
+                    &profile,
+                    detail.get_prior_energy(),
+                )
+                .unwrap(),
+            );
+        } else {
+            // 队首详单尚未开始充电（例如维护排空时）
+            detail.interrupt(0.0, 0.0, 0.0, now);
+        }
+        self.finish(Outcome::Interrupted, &detail);
+        detail
+    }
+
+    /// 损坏充电桩，返回被打断的详单
+    /// 启用修复后恢复时，被打断的详单会被保存，修复后重新开始充电
+    /// 故障来源未启用时返回错误，充电桩状态不变
+    pub fn breakdown(&mut self, source: FaultSource) -> Result<Vec<ChargingDetail>, String> {
+        if !self.fault_armed(source) {
+            tracing::warn!(virtual_time = %self.now(), "故障来源 {:?} 未启用，忽略故障", source);
+            return Err(format!("fault source {:?} is disabled", source));
+        }
+        if let Err(e) = self.enter(Transition::Fault) {
+            tracing::warn!(virtual_time = %self.now(), "充电桩当前为{}状态，忽略故障", self.state.label());
+            return Err(e.to_string());
+        }
+        let interrupted = self.close(); // 关闭充电桩并清空队列
+        self.emit(ChargeEvent::Breakdown {
+            virtual_time: self.now(),
+        });
+        if self.requeue_after_repair {
+            for resumption in interrupted.iter().filter_map(ChargingDetail::resumption) {
+                tracing::info!(virtual_time = %self.now(), "充电详单 {} 将在修复后恢复充电", resumption.get_id());
+                self.stash.push(resumption);
+            }
+        }
+        self.state_changed();
+        Ok(interrupted)
+    }
+
+    /// 修复充电桩，退出故障状态
+    /// 有等待恢复的详单时按原来的顺序放回队首并开始充电，返回恢复的详单
+    /// 不处于故障状态时返回错误
+    pub fn repair(&mut self) -> Result<Vec<ChargingDetail>, TransitionError> {
+        self.enter(Transition::Repair)?;
+        if self.stash.is_empty() {
+            self.state_changed();
+            return Ok(Vec::new());
+        }
+        let stash = std::mem::take(&mut self.stash);
+        for detail in &stash {
+            tracing::info!(virtual_time = %self.now(), "充电桩已修复，恢复充电详单 {}", detail.get_id());
+            self.emit_detail(detail_event!(DetailQueued), detail);
+        }
+        let resumed = stash.len();
+        self.queue.splice(0..0, stash);
+        while self.active < resumed && self.can_start() {
+            self.start_charging();
+        }
+        Ok(self.get_charging_details().to_vec())
+    }
+
+    /// 修改队列大小，已在队列中的详单不受影响，不限长的队列保持不限长
+    pub fn set_size(&mut self, size: u32) {
+        if self.size.is_some() {
+            self.size = Some(size);
+        }
+    }
+
+    /// 修改充电功率，只能在没有进行中的充电会话时修改
+    pub fn set_power(&mut self, power: f64) -> Result<(), String> {
+        if self.is_working() {
+            return Err("cannot change power while a session is active".to_string());
+        }
+        self.power = power;
+        self.estimate_schedule();
+        Ok(())
+    }
+
+    /// 设置是否只接受预约
+    pub fn set_reservation_only(&mut self, reservation_only: bool) {
+        self.reservation_only = reservation_only;
+    }
+
+    /// 开启或关闭免费充电，返回状态是否发生变化
+    /// 开启后正在进行的会话只累计充电度数，费用从开启时刻起不再增加；关闭后从关闭时刻起恢复计费
+    pub fn set_free_vend(&mut self, enabled: bool, now: chrono::DateTime<chrono::Utc>) -> bool {
+        if self.free_vend == enabled {
+            return false;
+        }
+        self.free_vend = enabled;
+        if enabled {
+            self.free_windows.push((now, None));
+        } else if let Some((_, end)) = self.free_windows.last_mut() {
+            *end = Some(now);
+        }
+        for detail in self.queue.iter_mut() {
+            detail.set_free_vend(enabled);
+        }
+        for detail in self.stash.iter_mut() {
+            detail.set_free_vend(enabled);
+        }
+        tracing::info!(virtual_time = %now, "免费充电已{}", if enabled { "开启" } else { "关闭" });
+        self.state_changed();
+        true
+    }
+
+    /// 是否处于免费充电状态
+    pub fn is_free_vend(&self) -> bool {
+        self.free_vend
+    }
+
+    /// 是否正在工作
+    pub fn is_working(&self) -> bool {
+        self.active > 0
+    }
+
+    /// 充电枪数量
+    pub fn get_connectors(&self) -> usize {
+        self.connectors
+    }
+
+    /// 工作状态与队列不一致时重置为非工作状态
+    pub fn reset_working(&mut self) {
+        self.active = 0;
+        self.sync_activity();
+        self.sync_metrics();
+    }
+
+    /// 当前的瞬时功率，单位为kW，为所有正在充电的详单的功率之和，每个详单按自己的最大功率限制
+    /// 并按最后一次更新时的充电进度从功率曲线上取值
+    pub fn current_power(&self) -> f64 {
+        self.get_charging_details()
+            .iter()
+            .map(|detail| {
+                self.curve.power_at(
+                    detail.effective_power(self.power),
+                    detail.get_request_amount(),
+                    detail.get_already_charged(),
+                )
+            })
+            .sum()
+    }
+
+    /// 队列中所有详单的副本，正在充电时第一个为正在充电的详单
+    pub fn get_queue_snapshot(&self) -> Vec<ChargingDetail> {
+        self.queue.clone()
+    }
+
+    /// 正在充电的详单，按开始充电的顺序排列
+    pub fn get_charging_details(&self) -> &[ChargingDetail] {
+        &self.queue[..self.active.min(self.queue.len())]
+    }
+
+    /// 队列中指定 ID 的详单的引用
+    pub fn get_detail_ref(&self, id: u32) -> Option<&ChargingDetail> {
+        self.queue.iter().find(|detail| detail.get_id() == id)
+    }
+
+    /// 生成充电桩状态快照，`now` 为快照的虚拟时间
+    pub fn status_snapshot(&self, now: chrono::DateTime<chrono::Utc>) -> StatusData {
+        let mut queue = self.get_queue_snapshot();
+        let active = self.get_charging_details().len();
+        let mut charging = queue.drain(..active).collect::<Vec<_>>().into_iter();
+        let metrics = self.metrics.snapshot();
+        StatusData {
+            charge_id: self.charge_id,
+            type_: self.type_,
+            power: self.power,
+            working: self.is_working(),
+            state: self.state,
+            free_vend: self.free_vend,
+            charging: charging.next(),
+            also_charging: charging.collect(),
+            queue,
+            pending: self.pending.clone(),
+            overload: match self.get_overload() {
+                0 => None,
+                overload => Some(overload as u32),
+            },
+            updates_generated: metrics.updates_generated,
+            updates_sent: metrics.updates_sent,
+            traffic: None,
+            virtual_time: now,
+        }
+    }
+
+    /// 生成注册消息数据，`speed` 为当前的时间加速比，认证令牌由调用方填写
+    pub fn register_payload(&self, speed: f64) -> RegisterPayload {
+        RegisterPayload {
+            charge_id: self.charge_id,
+            type_: self.type_,
+            power: self.power,
+            size: self.size,
+            connectors: self.connectors as u32,
+            reservation_only: self.reservation_only,
+            update_mode: self.update_mode,
+            free_vend: self.free_vend,
+            queue: self.queue.clone(),
+            pending: self.pending.clone(),
+            protocol_version: PROTOCOL_VERSION,
+            software_version: env!("CARGO_PKG_VERSION").to_string(),
+            speed,
+            manual_break: CONF.charge.manual_break,
+            features: PROTOCOL_FEATURES.iter().map(|f| f.to_string()).collect(),
+            encoding: CONF.websocket.encoding,
+            url: None,
+            auth_token: None,
+            price_fingerprint: fingerprint_hex_using(self.pricing.as_ref()).ok(),
+        }
+    }
+
+    /// 详单在队列中的位置，小于正在充电的详单数量时表示正在充电，不在队列中时返回 `None`
+    pub fn queue_position(&self, id: u32) -> Option<usize> {
+        self.queue.iter().position(|detail| detail.get_id() == id)
+    }
+
+    /// 正在充电的详单 ID
+    pub fn active_detail_ids(&self) -> Vec<u32> {
+        self.get_charging_details()
+            .iter()
+            .map(ChargingDetail::get_id)
+            .collect()
+    }
+
+    /// 虚拟时钟调整后平移队列中所有详单的时间，正在充电的详单从调整后的时间继续计算，
+    /// 调整的时长不计入已充电度数和费用
+    pub fn shift_times(&mut self, offset: chrono::Duration) {
+        for detail in &mut self.queue {
+            detail.shift_times(offset);
+        }
+    }
+
+    /// 是否处于故障状态
+    pub fn is_faulted(&self) -> bool {
+        self.state == PileState::Faulted
+    }
+
+    /// 是否空闲：处于空闲状态，队列和等待区中都没有详单
+    pub fn is_idle(&self) -> bool {
+        self.state == PileState::Idle && self.queue.is_empty() && self.pending.is_empty()
+    }
+
+    /// 是否已被服务器关闭
+    pub fn is_closed(&self) -> bool {
+        self.state == PileState::Closed
+    }
+
+    /// 获取充电桩状态
+    pub fn get_state(&self) -> PileState {
+        self.state
+    }
+
+    /// 设置充电桩的日志 span，span 需要声明 `state` 字段，设置时写入当前状态
+    pub fn with_span(mut self, span: tracing::Span) -> Self {
+        span.record("state", tracing::field::display(self.state));
+        self.span = span;
+        self
+    }
+
+    /// 获取队列大小
+    pub fn get_queue_size(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// 获取从现在到下一个开始的充电会话结束预计还需要的时长
+    /// 下一个会话为第一个等待中的详单，没有时为 `candidate`（正在接收的新详单），在最早空闲的充电枪上开始；
+    /// 正在充电的会话按剩余时长计算。没有下一个会话时为正在充电的会话中最长的剩余时长，都没有时为 `None`
+    pub fn projected_session_duration(
+        &self,
+        candidate: Option<&ChargingDetail>,
+    ) -> Option<chrono::Duration> {
+        let now = self.now();
+        // 每把充电枪空闲的时间
+        let mut free = vec![now; self.connectors];
+        for (slot, detail) in self.get_charging_details().iter().enumerate() {
+            let power = detail.effective_power(self.power);
+            let updated = detail.get_last_update_time().unwrap_or(now);
+            free[slot] = (updated + charge_duration(detail, power, &self.curve)).max(now);
+        }
+        let end = match self.queue.get(self.active).or(candidate) {
+            Some(next) => {
+                let start = free.iter().min().copied().unwrap_or(now);
+                start + charge_duration(next, next.effective_power(self.power), &self.curve)
+            }
+            None if self.active > 0 => free.iter().max().copied().unwrap_or(now),
+            None => return None,
+        };
+        Some(end - now)
+    }
+
+    /// 获取指定详单的预计完成间隔(毫秒)
+    pub fn complete_interval(&self, id: u32) -> u64 {
+        if self.queue.is_empty() {
+            tracing::warn!(virtual_time = %self.now(), "充电桩队列为空，无法获取完成间隔");
+            0
+        } else if !self.is_working() {
+            tracing::warn!(virtual_time = %self.now(), "充电桩未处于工作状态，无法获取完成间隔");
+            0
+        } else if let Some(pos) = self.charging_position(id) {
+            let now = self.now();
+            let detail = &self.queue[pos];
+            let time =
+                detail.get_estimated_end_time(detail.effective_power(self.power), &self.curve, now);
+            if let Some(end_time) = time {
+                let duration = end_time.signed_duration_since(now);
+                let millis = duration.num_milliseconds() + 100; // 加100毫秒以避免精度问题
+                if millis <= 0 {
+                    tracing::warn!(virtual_time = %now, "预计充电结束时间 {} 已过，完成间隔为 0", end_time);
+                    return 0;
+                }
+                (millis as f64 / RUNTIME.speed()) as u64 // 考虑加速倍数
+            } else {
+                tracing::warn!(virtual_time = %self.now(), "无法计算预计充电结束时间");
+                0
+            }
+        } else {
+            tracing::warn!(virtual_time = %self.now(), "充电详单 {} 不在充电，无法获取完成间隔", id);
+            0
+        }
+    }
+}
+
+/// 选择会话的更新间隔，单位为毫秒
+/// 详单指定的间隔优先（不小于最短间隔），其次是充电桩的间隔，最后是全局的间隔
+pub fn resolve_update_interval(
+    detail: Option<u64>,
+    pile: Option<u64>,
+    global: u64,
+    min: u64,
+) -> u64 {
+    match detail {
+        Some(interval) => interval.max(min),
+        None => pile.unwrap_or(global),
+    }
+}
+
+/// 更新计时器的最短真实间隔，单位为毫秒，避免高加速倍数下频繁发送更新
+pub const MIN_REAL_UPDATE_INTERVAL_MS: u64 = 50;
+
+/// 将虚拟毫秒的更新间隔换算为计时器使用的真实毫秒，不小于 [`MIN_REAL_UPDATE_INTERVAL_MS`]
+pub fn real_update_interval(interval: u64, speed: f64) -> u64 {
+    ((interval as f64 / speed) as u64).max(MIN_REAL_UPDATE_INTERVAL_MS)
+}
+
+/// 两次更新之间的虚拟时间超过更新周期的倍数时认为时钟发生了跳变（例如系统休眠）
+const CLOCK_JUMP_FACTOR: i64 = 5;
+
+/// 更新和完成充电时结算使用的时间
+/// 已超过按请求电量计算的充满时间时返回充满时间（完成计时器晚触发或时钟发生跳变），否则返回 `now`
+fn settle_time(
+    power: f64,
+    curve: &PowerCurve,
+    detail: &ChargingDetail,
+    now: chrono::DateTime<chrono::Utc>,
+) -> chrono::DateTime<chrono::Utc> {
+    let (Some(last), Some(end)) = (
+        detail.get_last_update_time(),
+        detail.get_energy_end_time(power, curve),
+    ) else {
+        return now;
+    };
+    let interval = detail
+        .get_effective_update_interval_ms()
+        .unwrap_or(RUNTIME.update_interval());
+    // 计时器的真实间隔有下限，换算回虚拟时间得到实际的更新周期
+    let speed = RUNTIME.speed();
+    let period = chrono::Duration::milliseconds(
+        (real_update_interval(interval, speed) as f64 * speed) as i64 * CLOCK_JUMP_FACTOR,
+    );
+    if now <= end {
+        return now;
+    }
+    if now - last <= period {
+        tracing::debug!(
+            virtual_time = %now,
+            "充电详单 {} 已超过充满时间 {} 毫秒，按充满时间 {} 结算",
+            detail.get_id(),
+            (now - end).num_milliseconds(),
+            end
+        );
+        return end;
+    }
+    tracing::warn!(
+        virtual_time = %now,
+        event = "clock_jump_detected",
+        gap_s = (now - last).num_seconds(),
+        "检测到时钟跳变，距离上次更新 {} 秒，充电详单 {} 按充满时间 {} 结算",
+        (now - last).num_seconds(),
+        detail.get_id(),
+        end
+    );
+    end
+}
+
+/// 更新和完成充电时到 `time` 为止本段充电的度数，到达充满时间后为本段请求的度数
+/// 中断和取消时按实际充电时长计算，不使用这里的结果
+fn delivered(
+    power: f64,
+    curve: &PowerCurve,
+    detail: &ChargingDetail,
+    time: chrono::DateTime<chrono::Utc>,
+) -> f64 {
+    match detail.get_energy_end_time(power, curve) {
+        Some(end) if time >= end => detail.get_leg_request_amount(),
+        _ => already_charged(power, curve, detail, time),
+    }
+}
+
+/// 取消详单，`canceled_status` 为 `false` 时按旧版本的行为标记为中断
+fn cancel_detail(
+    detail: &mut ChargingDetail,
+    canceled_status: bool,
+    already_charged: f64,
+    charge_cost: f64,
+    service_fee: f64,
+    time: chrono::DateTime<chrono::Utc>,
+) {
+    if canceled_status {
+        detail.cancel(already_charged, charge_cost, service_fee, time);
+    } else {
+        detail.interrupt(already_charged, charge_cost, service_fee, time);
+    }
+}
+
+/// 本段充电从开始到 `time` 按功率曲线充电的度数
+fn already_charged(
+    power: f64,
+    curve: &PowerCurve,
+    detail: &ChargingDetail,
+    time: chrono::DateTime<chrono::Utc>,
+) -> f64 {
+    let start_time = detail.clone_start_time();
+    let duration = time.signed_duration_since(start_time);
+    let hours = duration.num_seconds() as f64 / 3600.0; // 转换为小时
+    let (request, prior) = (detail.get_request_amount(), detail.get_prior_energy());
+    curve.energy(power, request, prior, hours) // 计算已充电度数
+}
+
+/// 本段充电从开始到 `time` 的功率变化，用于计算费用
+fn power_profile(
+    power: f64,
+    curve: &PowerCurve,
+    detail: &ChargingDetail,
+    time: chrono::DateTime<chrono::Utc>,
+) -> Vec<PowerStep> {
+    let (request, prior) = (detail.get_request_amount(), detail.get_prior_energy());
+    curve.profile(power, request, prior, detail.clone_start_time(), time)
+}
+
+/// 确定性生成充电桩ID使用的命名空间
+pub const CHARGE_ID_NAMESPACE: Uuid = Uuid::from_u128(0x6f1c_2a4e_8b3d_5c70_9e21_d4a8_3b6f_0c15);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// 充电桩ID来源
+pub enum ChargeIdSource {
+    /// 配置文件中的 `charge.id`
+    Explicit,
+    /// 根据 `charge.id_seed` 生成
+    Derived,
+    /// 随机生成
+    Random,
+}
+
+/// 根据种子生成充电桩ID，相同的种子总是得到相同的ID
+pub fn derive_charge_id(seed: &str) -> Uuid {
+    Uuid::new_v5(&CHARGE_ID_NAMESPACE, seed.as_bytes())
+}
+
+/// 确定充电桩ID
+/// 优先级: `charge.id` > `id_mode = "derived"` > 随机生成
+pub fn resolve_charge_id(conf: &ChargeConf) -> Result<(Uuid, ChargeIdSource), String> {
+    if let Some(id) = conf.id {
+        if conf.id_mode == IdMode::Derived {
+            tracing::warn!("配置文件中指定了充电桩ID，忽略 id_mode = \"derived\"");
+        }
+        return Ok((id, ChargeIdSource::Explicit));
+    }
+    match conf.id_mode {
+        IdMode::Derived => match conf.id_seed.as_deref() {
+            Some(seed) if !seed.is_empty() => Ok((derive_charge_id(seed), ChargeIdSource::Derived)),
+            _ => Err("charge.id_seed is required when charge.id_mode is \"derived\"".to_string()),
+        },
+        IdMode::Random => Ok((Uuid::new_v4(), ChargeIdSource::Random)),
+    }
+}
+
+/// 确定多充电桩配置中第 `index` 个充电桩的ID
+/// 第一个充电桩与单充电桩配置相同，其余充电桩在 derived 模式下使用 `<id_seed>#<index>` 作为种子
+pub fn resolve_pile_id(conf: &ChargeConf, index: usize) -> Result<(Uuid, ChargeIdSource), String> {
+    if index == 0 {
+        return resolve_charge_id(conf);
+    }
+    if conf.id.is_some() {
+        return Err("charge.id cannot be used with multiple piles".to_string());
+    }
+    let (_, source) = resolve_charge_id(conf)?;
+    match source {
+        ChargeIdSource::Derived => {
+            let seed = format!("{}#{}", conf.id_seed.as_deref().unwrap_or_default(), index);
+            Ok((derive_charge_id(&seed), source))
+        }
+        _ => Ok((Uuid::new_v4(), source)),
+    }
+}
+
+/// 按配置创建第 `index` 个充电桩
+pub fn build_charge(conf: &Conf, pile: &PileConf, index: usize) -> Result<Charge, String> {
+    let (charge_id, source) = resolve_pile_id(&conf.charge, index)?;
+    tracing::info!("充电桩ID: {} (来源: {:?})", charge_id, source);
+    let mut charge = Charge::new(pile.charge_type, pile.power, pile.size)
+        .with_id(charge_id)
+        .with_requeue_after_repair(conf.charge.requeue_after_repair)
+        .with_reservation_only(conf.charge.reservation_only)
+        .with_update_mode(conf.websocket.update_mode)
+        .with_fault_sources(conf.charge.manual_break, conf.charge.faults_enabled)
+        .with_power_match(conf.charge.power_tolerance, conf.charge.strict_power_match)
+        .with_update_interval(pile.update_interval, conf.time.min_update_interval)
+        .with_pending_buffer(conf.charge.pending_buffer_size as usize)
+        .with_overflow_policy(conf.charge.overflow_policy, conf.charge.overflow_cap)
+        .with_max_request_amount(conf.charge.max_request_amount)
+        .with_power_curve(conf.charge.power_curve.clone())
+        .with_canceled_status(conf.charge.canceled_status)
+        .with_drop_queue_on_close(conf.charge.drop_queue_on_close)
+        .with_connectors(conf.charge.connectors)
+        .with_cancellation_fee(
+            conf.price.cancellation_fee,
+            conf.price.cancellation_fee_after_kwh,
+        );
+    let armed: Vec<_> = [FaultSource::Manual, FaultSource::Internal]
+        .into_iter()
+        .filter(|source| charge.fault_armed(*source))
+        .collect();
+    if armed.is_empty() {
+        tracing::info!("充电桩未启用键盘和内部故障来源，只会因服务器的 break 消息进入故障状态");
+    } else {
+        tracing::info!("已启用的故障来源: {:?}", armed);
+    }
+    if conf.charge.queue_unlimited {
+        tracing::info!("充电桩队列不限长（安全上限 {}）", UNLIMITED_QUEUE_CAP);
+        charge = charge.with_unlimited_queue();
+    }
+    if charge.get_connectors() > 1 {
+        tracing::info!(
+            "充电桩有 {} 把充电枪，最多同时为 {} 个详单充电",
+            charge.get_connectors(),
+            charge.get_connectors()
+        );
+    }
+    if conf.charge.reservation_only {
+        tracing::info!("充电桩只接受预约，不会接收新的充电详单");
+    }
+    if let Some(path) = conf.charge.state_path_for(index) {
+        charge.restore(path);
+    }
+    Ok(charge)
+}
+
+/// 共享的充电桩，由调用者显式创建，嵌入到其他程序时使用
+pub type ChargeHandle = Arc<Mutex<Charge>>;
+
+/// 创建共享的充电桩
+pub fn new_handle(charge: Charge) -> ChargeHandle {
+    Arc::new(Mutex::new(charge))
+}
+
+/// 全局充电桩实例，使用 Lazy 和 Mutex 确保线程安全和延迟初始化
+/// 配置了多个充电桩时为第一个充电桩，只为兼容保留，新代码应使用 `ChargeHandle`
+pub static CHARGE: Lazy<ChargeHandle> = Lazy::new(|| {
+    let charge = build_charge(&CONF, &CONF.charge.pile_specs()[0], 0)
+        .unwrap_or_else(|e| panic!("Invalid charge id config: {}", e));
+    new_handle(charge)
+});
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::conf::ChargeType;
+    use crate::detail::ChargeStatus;
+    use crate::event::LifecycleEventType;
+    use crate::price::{FREE_VEND_LABEL, add_money, calc_price_using, round_to_precision};
+    use crate::time::ManualClock;
+    use chrono::{DateTime, Utc};
+
+    /// 手动时钟的开始时间
+    fn epoch() -> DateTime<Utc> {
+        "2025-01-01T00:00:00Z".parse().unwrap()
+    }
+
+    /// 使用从 [`epoch`] 开始的手动时钟的充电桩
+    fn manual_charge(power: f64, size: u32) -> (Charge, Arc<ManualClock>) {
+        let clock = Arc::new(ManualClock::new(epoch()));
+        let charge = Charge::new(CONF.charge.charge_type, power, size).with_clock(clock.clone());
+        (charge, clock)
+    }
+
+    #[test]
+    fn test_charge_serialization() {
+        // v4 生成
+        let charge = Charge {
+            charge_id: Uuid::new_v4(),
+            type_: ChargeType::Fast,
+            power: 30.0,
+            size: Some(5),
+            reservation_only: false,
+            update_mode: UpdateMode::Delta,
+            free_vend: false,
+            queue: vec![],
+            pending: vec![],
+            pending_capacity: 0,
+            overflow_policy: OverflowPolicy::Reject,
+            overflow_cap: None,
+            promoted: vec![],
+            active: 0,
+            connectors: 1,
+            eta_errors: EtaErrorStats::default(),
+            wait_times: WaitTimeStats::default(),
+            events: new_event_sender(),
+            metrics: Arc::default(),
+            requeue_after_repair: false,
+            stash: Vec::new(),
+            pricing: None,
+            free_windows: Vec::new(),
+            power_tolerance: 0.5, // 与配置默认值相同
+            strict_power_match: false,
+            cancellation_fee: 0.0,
+            cancellation_fee_after_kwh: None,
+            state: PileState::Idle,
+            span: tracing::Span::none(),
+            manual_break: false,
+            faults_enabled: true,
+            state_path: None,
+            update_interval: None,
+            min_update_interval: 1000,
+            max_request_amount: None,
+            curve: PowerCurve::default(),
+            clock: global_clock(),
+            canceled_status: false,
+            drop_queue_on_close: true,
+        };
+
+        let serialized = serde_json::to_string_pretty(&charge).unwrap();
+        println!("Serialized Charge: {}", serialized);
+
+        let deserialized: Charge = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(deserialized.charge_id, charge.charge_id);
+        assert_eq!(deserialized.type_, charge.type_);
+        assert_eq!(deserialized.power, charge.power);
+        assert_eq!(deserialized.size, charge.size);
+        assert_eq!(deserialized.queue.len(), charge.queue.len());
+        assert_eq!(deserialized.update_mode, UpdateMode::Delta);
+    }
+
+    #[test]
+    fn test_check_power_tolerance() {
+        let charge = Charge::new(ChargeType::Fast, 30.0, 2);
+
+        let detail = ChargingDetail::test_new(1);
+        assert_eq!(charge.check_power(&detail, 0.5, true), Ok(None));
+
+        // 恰好位于允许偏差边界
+        let detail = ChargingDetail::test_new(2).with_expected_power(30.5);
+        assert_eq!(charge.check_power(&detail, 0.5, true), Ok(None));
+
+        // 超出允许偏差
+        let detail = ChargingDetail::test_new(3).with_expected_power(30.6);
+        assert!(charge.check_power(&detail, 0.5, true).is_err());
+    }
+
+    #[test]
+    fn test_check_power_strictness() {
+        let charge = Charge::new(ChargeType::Fast, 30.0, 2);
+        let detail = ChargingDetail::test_new(1).with_expected_power(60.0);
+
+        let warning = charge.check_power(&detail, 0.5, false).unwrap().unwrap();
+        assert_eq!(
+            (warning.expected_power, warning.pile_power_kw),
+            (60.0, 30.0)
+        );
+        assert!(warning.to_string().contains("60"));
+        assert!(warning.to_string().contains("30"));
+
+        let error = charge.check_power(&detail, 0.5, true).unwrap_err();
+        assert_eq!(error, warning);
+        // 拒绝原因中给出两个功率
+        let reason = AddDetailError::PowerMismatch(error).to_string();
+        assert!(reason.starts_with("power_mismatch: "), "{}", reason);
+        assert!(reason.contains("60") && reason.contains("30"), "{}", reason);
+    }
+
+    #[test]
+    fn test_add_detail_sets_pile_power() {
+        let mut charge = Charge::new(CONF.charge.charge_type, 30.0, 2);
+        charge.add_detail(ChargingDetail::test_new(1)).unwrap();
+        let serialized = serde_json::to_string(charge.get_charging_detail_ref().unwrap()).unwrap();
+        assert!(serialized.contains("\"pile_power_kw\":30.0"));
+    }
+
+    #[test]
+    fn test_lifecycle_events() {
+        let mut charge = Charge::new(CONF.charge.charge_type, 30.0, 2);
+        let mut rx = charge.subscribe();
+        charge.add_detail(ChargingDetail::test_new(1)).unwrap();
+        charge.add_detail(ChargingDetail::test_new(2)).unwrap();
+        charge.start_charging();
+        let cancelled = charge
+            .cancel_charging(2, Some("rebalancing".to_string()), None)
+            .unwrap();
+        assert_eq!(cancelled.get_stop_reason(), Some("rebalancing"));
+        let value = serde_json::to_value(&cancelled).unwrap();
+        assert_eq!(value["stop_reason"], "rebalancing");
+        assert!(value.get("stop_reason_text").is_none());
+
+        let events: Vec<(LifecycleEventType, u32)> = std::iter::from_fn(|| rx.try_recv().ok())
+            .filter_map(|e| e.lifecycle())
+            .map(|e| (e.event, e.detail.get_id()))
+            .collect();
+        assert_eq!(
+            events,
+            vec![
+                (LifecycleEventType::Admitted, 1),
+                (LifecycleEventType::Admitted, 2),
+                (LifecycleEventType::Started, 1),
+                (LifecycleEventType::Interrupted, 2),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_charge_events() {
+        let mut charge = Charge::new(CONF.charge.charge_type, 30.0, 2);
+        let mut rx = charge.subscribe();
+        let mut all = event::subscribe();
+        charge.add_detail(ChargingDetail::test_new(1)).unwrap();
+        charge.start_charging();
+        charge.update_charging();
+        charge.close_by_server().unwrap();
+        charge.open_by_server().unwrap();
+        charge.breakdown(FaultSource::Remote).unwrap();
+        let names: Vec<String> = std::iter::from_fn(|| rx.try_recv().ok())
+            .map(|e| {
+                let value = serde_json::to_value(&e).unwrap();
+                value["event"].as_str().unwrap().to_string()
+            })
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "detail_queued",
+                "charging_started",
+                "progress",
+                "interrupted",
+                "pile_closed",
+                "pile_opened",
+                "breakdown"
+            ]
+        );
+        // 共用通道中的事件带有充电桩 ID，可能混有其他测试中充电桩的事件
+        let mine = std::iter::from_fn(|| all.try_recv().ok())
+            .filter(|e| e.charge_id == charge.get_id())
+            .count();
+        assert_eq!(mine, names.len());
+
+        // 处理过慢的订阅者丢失较早的事件，不影响充电
+        charge.repair().unwrap();
+        let mut slow = charge.subscribe();
+        for _ in 0..event::EVENT_CHANNEL_CAPACITY / 2 + 1 {
+            charge.close_by_server().unwrap();
+            charge.open_by_server().unwrap();
+        }
+        assert!(matches!(
+            slow.try_recv(),
+            Err(broadcast::error::TryRecvError::Lagged(2))
+        ));
+    }
+
+    #[test]
+    fn test_cancel_pending_detail_events() {
+        let mut charge = Charge::new(CONF.charge.charge_type, 30.0, 1).with_pending_buffer(1);
+        charge.admit(ChargingDetail::test_new(1)).unwrap();
+        assert_eq!(
+            charge.admit(ChargingDetail::test_new(2)),
+            Ok(Admission::Pending(0))
+        );
+        let mut rx = charge.subscribe();
+        charge.cancel_charging(2, None, None).unwrap();
+        // 等待区中的详单取消时与队列中的详单一样发布取消事件并计入指标
+        let events: Vec<ChargeEvent> = std::iter::from_fn(|| rx.try_recv().ok()).collect();
+        assert!(
+            matches!(&events[..], [ChargeEvent::Canceled { detail, .. }] if detail.get_id() == 2),
+            "{:?}",
+            events
+        );
+        assert_eq!(charge.get_metrics().snapshot().canceled, 1);
+    }
+
+    #[test]
+    fn test_derived_charge_id_is_stable() {
+        let a = derive_charge_id("station-3/pile-17");
+        assert_eq!(a, derive_charge_id("station-3/pile-17"));
+        assert_ne!(a, derive_charge_id("station-3/pile-18"));
+        assert_eq!(a.get_version_num(), 5);
+    }
+
+    #[test]
+    fn test_multi_pile_build() {
+        let mut conf = Conf::default();
+        conf.charge.id_mode = IdMode::Derived;
+        conf.charge.id_seed = Some("station-3".to_string());
+        conf.charge.piles = vec![
+            PileConf {
+                charge_type: ChargeType::Fast,
+                power: 30.0,
+                size: 2,
+                update_interval: None,
+            },
+            PileConf {
+                charge_type: ChargeType::Slow,
+                power: 7.0,
+                size: 4,
+                update_interval: None,
+            },
+        ];
+        let piles: Vec<Charge> = conf
+            .charge
+            .pile_specs()
+            .iter()
+            .enumerate()
+            .map(|(index, spec)| build_charge(&conf, spec, index).unwrap())
+            .collect();
+        // 第一个充电桩的ID与单充电桩配置相同，其余充电桩各不相同且可以复现
+        assert_eq!(piles[0].get_id(), derive_charge_id("station-3"));
+        assert_eq!(piles[1].get_id(), derive_charge_id("station-3#1"));
+        let value = serde_json::to_value(&piles[1]).unwrap();
+        assert_eq!(value["type"], "T");
+        assert_eq!(value["power"], 7.0);
+        assert_eq!(value["size"], 4);
+
+        conf.charge.id = Some(Uuid::new_v4());
+        assert!(conf.charge.validate().is_err());
+        assert!(resolve_pile_id(&conf.charge, 1).is_err());
+    }
+
+    #[test]
+    fn test_charge_id_precedence() {
+        let explicit = Uuid::new_v4();
+        let mut conf = ChargeConf {
+            id: Some(explicit),
+            id_mode: IdMode::Derived,
+            id_seed: Some("station-3/pile-17".to_string()),
+            ..ChargeConf::default()
+        };
+        assert_eq!(
+            resolve_charge_id(&conf).unwrap(),
+            (explicit, ChargeIdSource::Explicit)
+        );
+
+        conf.id = None;
+        assert_eq!(
+            resolve_charge_id(&conf).unwrap(),
+            (
+                derive_charge_id("station-3/pile-17"),
+                ChargeIdSource::Derived
+            )
+        );
+
+        conf.id_seed = None;
+        assert!(resolve_charge_id(&conf).is_err());
+
+        conf.id_mode = IdMode::Random;
+        assert_eq!(resolve_charge_id(&conf).unwrap().1, ChargeIdSource::Random);
+    }
+
+    #[test]
+    fn test_pile_state_follows_commands() {
+        let mut charge =
+            Charge::new(CONF.charge.charge_type, 30.0, 3).with_drop_queue_on_close(false);
+        assert_eq!(charge.get_state(), PileState::Idle);
+        charge.add_detail(ChargingDetail::test_new(1)).unwrap();
+        charge.add_detail(ChargingDetail::test_new(2)).unwrap();
+        charge.start_charging();
+        assert_eq!(charge.get_state(), PileState::Charging);
+        assert_eq!(
+            charge.status_snapshot(charge.now()).state,
+            PileState::Charging
+        );
+
+        // 关闭时中断正在充电的详单，关闭期间拒绝新详单、开始充电和损坏
+        let interrupted = charge.close_by_server().unwrap();
+        assert_eq!(interrupted[0].get_id(), 1);
+        assert!(charge.is_closed() && !charge.is_working());
+        assert_eq!(
+            charge.add_detail(ChargingDetail::test_new(3)),
+            Err(AddDetailError::Closed)
+        );
+        assert!(!charge.can_start());
+        assert_eq!(charge.start_charging(), None);
+        assert!(charge.close_by_server().is_err());
+        assert!(charge.breakdown(FaultSource::Internal).is_err());
+        assert!(charge.repair().is_err());
+
+        // 重新打开后保留的详单可以继续充电
+        charge.open_by_server().unwrap();
+        assert!(charge.open_by_server().is_err());
+        assert_eq!(charge.start_charging(), Some(2));
+        assert_eq!(charge.get_state(), PileState::Charging);
+        charge.complete_charging(2);
+        assert_eq!(charge.get_state(), PileState::Idle);
+
+        // 故障期间不能关闭或打开，只能修复
+        charge.breakdown(FaultSource::Internal).unwrap();
+        assert_eq!(charge.get_state(), PileState::Faulted);
+        assert!(charge.close_by_server().is_err() && charge.open_by_server().is_err());
+        charge.repair().unwrap();
+        assert_eq!(charge.get_state(), PileState::Idle);
+    }
+
+    #[test]
+    fn test_requeue_after_repair() {
+        let mut charge =
+            Charge::new(CONF.charge.charge_type, 30.0, 2).with_requeue_after_repair(true);
+        charge.add_detail(ChargingDetail::test_new(1)).unwrap();
+        // 尚未开始充电时故障，修复后从头开始
+        let interrupted = charge.breakdown(FaultSource::Internal).unwrap().remove(0);
+        assert_eq!(charge.get_queue_size(), 0);
+        let resumed = charge.repair().unwrap().remove(0);
+        assert_eq!(resumed.get_id(), interrupted.get_id());
+        assert!(resumed.is_resumed());
+        assert!(charge.is_working());
+        // 不再处于故障状态时不能再次修复
+        assert_eq!(
+            charge.repair().unwrap_err(),
+            TransitionError {
+                state: PileState::Charging,
+                transition: Transition::Repair
+            }
+        );
+
+        // 等待修复期间被取消的详单不再恢复
+        let mut charge =
+            Charge::new(CONF.charge.charge_type, 30.0, 2).with_requeue_after_repair(true);
+        charge.add_detail(ChargingDetail::test_new(2)).unwrap();
+        charge.breakdown(FaultSource::Internal).unwrap();
+        let cancelled = charge.cancel_charging(2, None, None).unwrap();
+        assert_eq!(cancelled.get_stop_reason(), Some("unspecified"));
+        assert!(charge.repair().unwrap().is_empty());
+
+        // 未启用时不保存详单
+        let mut charge = Charge::new(CONF.charge.charge_type, 30.0, 2);
+        charge.add_detail(ChargingDetail::test_new(3)).unwrap();
+        charge.breakdown(FaultSource::Internal).unwrap();
+        assert!(charge.repair().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_restore_state() {
+        let path = std::env::temp_dir().join(format!("taranis-state-{}.json", Uuid::new_v4()));
+        let mut charge = Charge::new(CONF.charge.charge_type, 30.0, 2);
+        // 状态文件不存在时使用空队列
+        assert!(!charge.restore(&path));
+        charge.add_detail(ChargingDetail::test_new(1)).unwrap();
+        charge.add_detail(ChargingDetail::test_new(2)).unwrap();
+        charge.start_charging();
+        let start = charge.get_charging_detail_ref().unwrap().clone_start_time();
+        charge.update_charging_at(start + chrono::Duration::minutes(10));
+
+        // 模拟进程崩溃后重新启动
+        let mut restored = Charge::new(CONF.charge.charge_type, 30.0, 2);
+        assert!(restored.restore(&path));
+        assert!(restored.is_working());
+        assert_eq!(restored.get_queue_size(), 2);
+        let detail = restored.get_charging_detail_ref().unwrap();
+        assert_eq!(detail.clone_start_time(), start);
+        assert_eq!(detail.get_already_charged(), 5.0);
+        // 注册消息中带有恢复的队列
+        let value = serde_json::to_value(&restored).unwrap();
+        assert_eq!(value["queue"].as_array().unwrap().len(), 2);
+
+        // 状态文件被截断时使用上一代文件，上一代文件中只有第一个详单未开始充电
+        let raw = std::fs::read(&path).unwrap();
+        std::fs::write(&path, &raw[..raw.len() - 10]).unwrap();
+        let mut fallback = Charge::new(CONF.charge.charge_type, 30.0, 2);
+        assert!(fallback.restore(&path));
+        assert_eq!(fallback.get_queue_size(), 2);
+        assert_eq!(
+            fallback
+                .get_charging_detail_ref()
+                .unwrap()
+                .get_already_charged(),
+            0.0
+        );
+
+        // 两代状态文件都损坏时使用空队列
+        std::fs::write(&path, "{not json").unwrap();
+        std::fs::write(persist::backup_path(&path), "").unwrap();
+        let mut fresh = Charge::new(CONF.charge.charge_type, 30.0, 2);
+        assert!(!fresh.restore(&path));
+        assert_eq!(fresh.get_queue_size(), 0);
+        assert!(!fresh.is_working());
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(persist::backup_path(&path)).unwrap();
+    }
+
+    #[test]
+    fn test_idle_fault_until_repair() {
+        let mut charge = Charge::new(CONF.charge.charge_type, 30.0, 2);
+        // 空闲时损坏也进入故障状态
+        assert!(charge.breakdown(FaultSource::Internal).unwrap().is_empty());
+        assert!(charge.is_faulted());
+        assert_eq!(
+            charge.add_detail(ChargingDetail::test_new(1)),
+            Err(AddDetailError::Faulted)
+        );
+        assert_eq!(
+            charge.cancel_charging(1, None, None).err(),
+            Some(ChargeError::Faulted)
+        );
+        assert_eq!(charge.get_queue_size(), 0);
+
+        // 修复后可以正常完成充电会话
+        assert!(charge.repair().unwrap().is_empty());
+        assert!(!charge.is_faulted());
+        charge.add_detail(ChargingDetail::test_new(2)).unwrap();
+        charge.start_charging();
+        assert!(charge.is_working());
+        let completed = charge.complete_charging(2).unwrap();
+        assert_eq!(completed.get_id(), 2);
+        assert_eq!(charge.get_queue_size(), 0);
+    }
+
+    #[test]
+    fn test_fault_sources() {
+        for manual_break in [false, true] {
+            for faults_enabled in [false, true] {
+                for (source, armed) in [
+                    (FaultSource::Manual, manual_break),
+                    (FaultSource::Internal, faults_enabled),
+                    (FaultSource::Remote, true),
+                ] {
+                    let mut charge = Charge::new(CONF.charge.charge_type, 30.0, 2)
+                        .with_fault_sources(manual_break, faults_enabled);
+                    charge.add_detail(ChargingDetail::test_new(1)).unwrap();
+                    charge.start_charging();
+                    assert_eq!(charge.fault_armed(source), armed);
+                    match charge.breakdown(source) {
+                        Ok(detail) => {
+                            assert!(armed);
+                            assert_eq!(detail[0].get_id(), 1);
+                            assert!(!charge.is_working());
+                        }
+                        Err(_) => {
+                            // 未启用的故障来源不会改变充电桩状态
+                            assert!(!armed);
+                            assert!(charge.is_working());
+                            assert_eq!(charge.get_queue_size(), 1);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_unlimited_queue() {
+        let mut charge = Charge::new(CONF.charge.charge_type, 30.0, 0).with_unlimited_queue();
+        for id in 0..100 {
+            charge.add_detail(ChargingDetail::test_new(id)).unwrap();
+        }
+        assert_eq!(charge.get_queue_size(), 100);
+        // 运行时修改队列大小不影响不限长队列
+        charge.set_size(2);
+        charge.add_detail(ChargingDetail::test_new(100)).unwrap();
+        assert!(
+            serde_json::to_string(&charge)
+                .unwrap()
+                .contains("\"size\":null")
+        );
+
+        for id in 101..UNLIMITED_QUEUE_CAP as u32 {
+            charge.add_detail(ChargingDetail::test_new(id)).unwrap();
+        }
+        assert_eq!(
+            charge.add_detail(ChargingDetail::test_new(u32::MAX)),
+            Err(AddDetailError::QueueSafetyCap)
+        );
+
+        let mut charge = Charge::new(CONF.charge.charge_type, 30.0, 1);
+        charge.add_detail(ChargingDetail::test_new(1)).unwrap();
+        assert_eq!(
+            charge.add_detail(ChargingDetail::test_new(1)),
+            Err(AddDetailError::DuplicateId)
+        );
+        assert_eq!(
+            charge.add_detail(ChargingDetail::test_new(2)),
+            Err(AddDetailError::QueueFull)
+        );
+    }
+
+    #[test]
+    fn test_clock_jump_settles_at_energy_end() {
+        let mut charge = Charge::new(CONF.charge.charge_type, 30.0, 2);
+        charge.add_detail(ChargingDetail::test_new(1)).unwrap();
+        charge.start_charging();
+        let detail = charge.get_charging_detail_ref().unwrap();
+        let start = detail.clone_start_time();
+        // 30 度电以 30kW 充电需要 1 小时
+        let end = detail
+            .get_energy_end_time(30.0, &PowerCurve::default())
+            .unwrap();
+        assert_eq!(end, start + chrono::Duration::hours(1));
+
+        // 正常更新不受影响
+        let before = start + chrono::Duration::minutes(59) + chrono::Duration::seconds(58);
+        charge.update_charging_at(before);
+        assert_eq!(
+            charge
+                .get_charging_detail_ref()
+                .unwrap()
+                .get_last_update_time(),
+            Some(before)
+        );
+
+        // 休眠 20 分钟后唤醒，完成时间和费用按充满时间计算
+        let woke = end + chrono::Duration::minutes(20);
+        let completed = charge.complete_charging_at(1, woke).unwrap();
+        let cost = calc_price_using(None, start, end, 30.0, 0.0).unwrap();
+        let value = serde_json::to_value(&completed).unwrap();
+        assert_eq!(completed.get_last_update_time(), Some(end));
+        assert_eq!(value["already_charged"], 30.0);
+        assert_eq!(value["charge_cost"], cost.0);
+        assert_eq!(value["service_fee"], cost.1);
+        assert_eq!(completed.get_eta_error(), Some(chrono::Duration::zero()));
+    }
+
+    #[test]
+    fn test_power_curve_tapers_charging() {
+        let curve = PowerCurve::new(vec![(0.0, 1.0), (0.8, 1.0), (1.0, 0.3)]).unwrap();
+        let mut charge =
+            Charge::new(CONF.charge.charge_type, 30.0, 2).with_power_curve(curve.clone());
+        charge
+            .add_detail(ChargingDetail::test_new(1).with_request_amount(30.0))
+            .unwrap();
+        charge.start_charging();
+        let detail = charge.get_charging_detail_ref().unwrap();
+        let start = detail.clone_start_time();
+        let end = detail.get_energy_end_time(30.0, &curve).unwrap();
+        // 前 80% 以额定功率充电用 48 分钟，之后功率从 30kW 线性降到 9kW 再用约 20.6 分钟
+        let expected = 0.2 / 0.7 * (1.0_f64 / 0.3).ln() * 3600.0;
+        assert_eq!(
+            end - start,
+            chrono::Duration::minutes(48) + chrono::Duration::seconds(expected as i64)
+        );
+
+        charge.update_charging_at(start + chrono::Duration::minutes(48));
+        let charged = charge
+            .get_charging_detail_ref()
+            .unwrap()
+            .get_already_charged();
+        assert!((charged - 24.0).abs() < 1e-9);
+        assert!((charge.current_power() - 30.0).abs() < 1e-9);
+        charge.update_charging_at(start + chrono::Duration::minutes(60));
+        let charged = charge
+            .get_charging_detail_ref()
+            .unwrap()
+            .get_already_charged();
+        assert!(charged > 24.0 && charged < 30.0);
+        assert!(charge.current_power() < 30.0);
+
+        // 按曲线积分到充满时间正好充入请求度数，费用按变化的功率计算
+        let completed = charge.complete_charging_at(1, end).unwrap();
+        assert_eq!(completed.get_already_charged(), 30.0);
+        let kwh: f64 = completed.get_per_period().iter().map(|u| u.kwh).sum();
+        assert!((kwh - 30.0).abs() < 0.05, "{}", kwh);
+        let breakdown = completed.get_breakdown().unwrap();
+        let itemized: f64 = breakdown.items.iter().map(|item| item.kwh).sum();
+        assert!((itemized - 30.0).abs() < 0.05, "{}", itemized);
+        let value = serde_json::to_value(&completed).unwrap();
+        assert_eq!(value["charge_cost"], breakdown.charge_cost);
+        assert_eq!(completed.get_eta_error(), Some(chrono::Duration::zero()));
+    }
+
+    #[test]
+    fn test_late_completion_caps_energy() {
+        let mut charge = Charge::new(CONF.charge.charge_type, 30.0, 2);
+        charge
+            .add_detail(ChargingDetail::test_new(1).with_request_amount(30.0))
+            .unwrap();
+        charge.start_charging();
+        let start = charge.get_charging_detail_ref().unwrap().clone_start_time();
+        let end = start + chrono::Duration::hours(1);
+
+        // 完成计时器晚了 30 秒，之前的更新和完成都不超过请求度数
+        let late = end + chrono::Duration::seconds(30);
+        charge.update_charging_at(late);
+        let value = serde_json::to_value(charge.get_charging_detail_ref().unwrap()).unwrap();
+        assert_eq!(value["already_charged"], 30.0);
+        let completed = charge.complete_charging_at(1, late).unwrap();
+        let cost = calc_price_using(None, start, end, 30.0, 0.0).unwrap();
+        let value = serde_json::to_value(&completed).unwrap();
+        assert_eq!(value["already_charged"], 30.0);
+        assert_eq!(completed.get_end_time(), Some(end));
+        assert_eq!(value["charge_cost"], cost.0);
+        assert_eq!(value["service_fee"], cost.1);
+
+        // 充满时间不是整秒时度数仍等于请求度数
+        let mut charge = Charge::new(CONF.charge.charge_type, 7.0, 2);
+        charge
+            .add_detail(ChargingDetail::test_new(2).with_request_amount(1.0))
+            .unwrap();
+        charge.start_charging();
+        let start = charge.get_charging_detail_ref().unwrap().clone_start_time();
+        let completed = charge
+            .complete_charging_at(2, start + chrono::Duration::minutes(10))
+            .unwrap();
+        assert_eq!(completed.get_already_charged(), 1.0);
+        assert_eq!(
+            completed.get_end_time(),
+            Some(start + chrono::Duration::seconds(514))
+        );
+    }
+
+    #[test]
+    fn test_shift_times_keeps_charged_energy() {
+        let mut charge = Charge::new(CONF.charge.charge_type, 30.0, 2);
+        charge.add_detail(ChargingDetail::test_new(1)).unwrap();
+        charge.start_charging();
+        let start = charge.get_charging_detail_ref().unwrap().clone_start_time();
+        charge.update_charging_at(start + chrono::Duration::minutes(10));
+
+        // 虚拟时钟向后调整 1 小时，之后的更新从调整后的时间继续计算，不会出现负数
+        let offset = -chrono::Duration::hours(1);
+        charge.shift_times(offset);
+        let detail = charge.get_charging_detail_ref().unwrap();
+        assert_eq!(detail.clone_start_time(), start + offset);
+        charge.update_charging_at(start + offset + chrono::Duration::minutes(20));
+        let value = serde_json::to_value(charge.get_charging_detail_ref().unwrap()).unwrap();
+        assert_eq!(value["already_charged"], 10.0);
+        assert!(value["total_cost"].as_f64().unwrap() > 0.0);
+    }
+
+    #[test]
+    fn test_suspend_keeps_waiting_details() {
+        let mut charge = Charge::new(CONF.charge.charge_type, 30.0, 2)
+            .with_pending_buffer(1)
+            .with_drop_queue_on_close(false);
+        assert!(!charge.drops_queue_on_close());
+        // 没有正在充电的详单时不会中断等待中的详单
+        charge.add_detail(ChargingDetail::test_new(1)).unwrap();
+        assert!(charge.suspend().is_empty());
+        assert_eq!(charge.get_queue_size(), 1);
+
+        charge.add_detail(ChargingDetail::test_new(2)).unwrap();
+        assert_eq!(
+            charge.admit(ChargingDetail::test_new(3)),
+            Ok(Admission::Pending(0))
+        );
+        charge.start_charging();
+        let interrupted = charge.suspend().remove(0);
+        assert_eq!(interrupted.get_id(), 1);
+        assert_eq!(interrupted.get_status(), ChargeStatus::Interrupted);
+        assert!(!charge.is_working());
+        // 等待中的详单保留在队列中，等待区的详单进入空出的位置
+        let ids: Vec<_> = charge
+            .get_queue_snapshot()
+            .iter()
+            .map(ChargingDetail::get_id)
+            .collect();
+        assert_eq!(ids, vec![2, 3]);
+        assert_eq!(charge.get_pending_size(), 0);
+
+        // 重新打开后继续充电
+        charge.start_charging();
+        assert_eq!(charge.active_detail_ids(), vec![2]);
+
+        // 关闭时清空队列
+        assert_eq!(charge.close()[0].get_id(), 2);
+        assert_eq!(charge.get_queue_size(), 0);
+    }
+
+    #[test]
+    fn test_projected_session_duration() {
+        let (mut charge, clock) = manual_charge(30.0, 3);
+        let hour = chrono::Duration::hours(1);
+        // 队列为空时按正在接收的新详单计算
+        assert_eq!(charge.projected_session_duration(None), None);
+        let candidate = ChargingDetail::test_new(9);
+        assert_eq!(
+            charge.projected_session_duration(Some(&candidate)),
+            Some(hour)
+        );
+
+        charge.add_detail(ChargingDetail::test_new(1)).unwrap();
+        charge.start_charging();
+        clock.advance(chrono::Duration::minutes(15));
+        charge.update_charging();
+        // 正在充电的会话按剩余时长计算，新详单在它之后开始
+        let remaining = chrono::Duration::minutes(45);
+        assert_eq!(charge.projected_session_duration(None), Some(remaining));
+        assert_eq!(
+            charge.projected_session_duration(Some(&candidate)),
+            Some(remaining + hour)
+        );
+
+        // 有等待中的详单时按它计算，不再考虑新详单
+        charge
+            .add_detail(ChargingDetail::test_new(2).with_request_amount(15.0))
+            .unwrap();
+        let next = remaining + chrono::Duration::minutes(30);
+        assert_eq!(charge.projected_session_duration(None), Some(next));
+        assert_eq!(
+            charge.projected_session_duration(Some(&candidate)),
+            Some(next)
+        );
+    }
+
+    #[test]
+    fn test_detail_max_power() {
+        let (mut charge, _clock) = manual_charge(30.0, 2);
+        charge
+            .add_detail(ChargingDetail::test_new(1).with_max_power(10.0))
+            .unwrap();
+        charge.start_charging();
+        assert_eq!(charge.current_power(), 10.0);
+        let start = charge.get_charging_detail_ref().unwrap().clone_start_time();
+        // 限制为 10kW 的详单充满 30 度需要 3 小时，是充电桩功率下的三倍
+        assert_eq!(
+            charge.projected_session_duration(None),
+            Some(chrono::Duration::hours(3))
+        );
+        // 完成间隔多加 100 毫秒，按加速倍数换算为真实时间
+        assert_eq!(
+            charge.complete_interval(1),
+            ((3 * 3600 * 1000 + 100) as f64 / RUNTIME.speed()) as u64
+        );
+
+        let hour = start + chrono::Duration::hours(1);
+        charge.update_charging_at(hour);
+        assert_eq!(
+            charge
+                .get_charging_detail_ref()
+                .unwrap()
+                .get_already_charged(),
+            10.0
+        );
+
+        let end = start + chrono::Duration::hours(3);
+        let completed = charge.complete_charging_at(1, end).unwrap();
+        let cost = calc_price_using(None, start, end, 10.0, 0.0).unwrap();
+        let value = serde_json::to_value(&completed).unwrap();
+        assert_eq!(value["already_charged"], 30.0);
+        assert_eq!(value["max_power"], 10.0);
+        assert_eq!(value["charge_cost"], cost.0);
+        assert_eq!(value["service_fee"], cost.1);
+
+        // 最大功率高于充电桩功率时按充电桩功率充电
+        let detail = ChargingDetail::test_new(2).with_max_power(60.0);
+        assert_eq!(detail.effective_power(30.0), 30.0);
+        assert_eq!(ChargingDetail::test_new(3).effective_power(30.0), 30.0);
+    }
+
+    #[test]
+    fn test_piles_with_own_pricing() {
+        let flat = |price: f64| -> Pricing {
+            let json = format!(
+                r#"{{"periods": [{{"start": "00:00:00", "end": "12:00:00", "price": {price}}}, {{"start": "12:00:00", "end": "00:00:00", "price": {price}}}], "service_fee": 0.5}}"#
+            );
+            Pricing::new(json.parse().unwrap(), chrono_tz::UTC)
+        };
+        // 会话从 epoch 开始，不跨越时段边界
+        let cheap = new_handle(manual_charge(30.0, 2).0.with_pricing(flat(1.0)));
+        let dear = new_handle(manual_charge(30.0, 2).0.with_pricing(flat(2.0)));
+        // 同一个进程中的两个充电桩按各自的价格表计费
+        for (handle, cost) in [(&cheap, 15.0), (&dear, 30.0)] {
+            let mut charge = handle.try_lock().unwrap();
+            charge.add_detail(ChargingDetail::test_new(1)).unwrap();
+            charge.start_charging();
+            let start = charge.get_charging_detail_ref().unwrap().clone_start_time();
+            charge.update_charging_at(start + chrono::Duration::minutes(30));
+            let value = serde_json::to_value(charge.get_charging_detail_ref().unwrap()).unwrap();
+            assert_eq!(value["charge_cost"], cost);
+            assert_eq!(value["service_fee"], 7.5);
+        }
+    }
+
+    #[test]
+    fn test_overflow_policies() {
+        let ids = |charge: &Charge| -> Vec<u32> {
+            charge
+                .get_queue_snapshot()
+                .iter()
+                .map(|detail| detail.get_id())
+                .collect()
+        };
+
+        // replace_last 取消队尾等待中的详单，正在充电的详单不受影响
+        let mut charge = Charge::new(CONF.charge.charge_type, 30.0, 2)
+            .with_canceled_status(true)
+            .with_overflow_policy(OverflowPolicy::ReplaceLast, None);
+        assert_eq!(charge.add_detail(ChargingDetail::test_new(1)), Ok(None));
+        charge.start_charging();
+        charge.add_detail(ChargingDetail::test_new(2)).unwrap();
+        let Ok(Admission::Replaced { position, evicted }) =
+            charge.admit(ChargingDetail::test_new(3))
+        else {
+            panic!("detail 2 should be replaced");
+        };
+        assert_eq!((position, evicted.get_id()), (1, 2));
+        assert_eq!(evicted.get_status(), ChargeStatus::Canceled);
+        assert_eq!(evicted.get_stop_reason(), Some(QUEUE_OVERFLOW_REASON));
+        assert_eq!(ids(&charge), [1, 3]);
+        let evicted = charge
+            .add_detail(ChargingDetail::test_new(4))
+            .unwrap()
+            .unwrap();
+        assert_eq!(evicted.get_id(), 3);
+        assert_eq!(charge.get_metrics().snapshot().canceled, 2);
+
+        // 只有正在充电的详单时无法替换
+        let mut charge = Charge::new(CONF.charge.charge_type, 30.0, 1)
+            .with_overflow_policy(OverflowPolicy::ReplaceLast, None);
+        charge.add_detail(ChargingDetail::test_new(1)).unwrap();
+        charge.start_charging();
+        assert_eq!(
+            charge.add_detail(ChargingDetail::test_new(2)),
+            Err(AddDetailError::QueueFull)
+        );
+
+        // grow 最多超出 overflow_cap 个详单，并在状态快照中报告
+        let mut charge = Charge::new(CONF.charge.charge_type, 30.0, 1)
+            .with_overflow_policy(OverflowPolicy::Grow, Some(1));
+        charge.add_detail(ChargingDetail::test_new(1)).unwrap();
+        assert_eq!(charge.status_snapshot(charge.now()).overload, None);
+        assert_eq!(
+            charge.admit(ChargingDetail::test_new(2)),
+            Ok(Admission::Queued(1))
+        );
+        assert_eq!(charge.get_overload(), 1);
+        assert_eq!(charge.status_snapshot(charge.now()).overload, Some(1));
+        assert_eq!(
+            charge.add_detail(ChargingDetail::test_new(3)),
+            Err(AddDetailError::QueueFull)
+        );
+    }
+
+    #[test]
+    fn test_replacement_at_idle_head_starts_charging() {
+        // 空闲的充电桩队列已满时，新详单替换队首的等待中详单，随后可以立即开始充电
+        let mut charge = Charge::new(CONF.charge.charge_type, 30.0, 1)
+            .with_overflow_policy(OverflowPolicy::ReplaceLast, None);
+        charge.add_detail(ChargingDetail::test_new(1)).unwrap();
+        assert!(!charge.is_working());
+        let Ok(Admission::Replaced { position, evicted }) =
+            charge.admit(ChargingDetail::test_new(2))
+        else {
+            panic!("detail 1 should be replaced");
+        };
+        assert_eq!((position, evicted.get_id()), (0, 1));
+        // 默认与服务器取消一样标记为中断
+        assert_eq!(evicted.get_status(), ChargeStatus::Interrupted);
+        assert!(charge.can_start());
+        assert_eq!(charge.start_charging(), Some(2));
+        assert!(!charge.can_start());
+    }
+
+    #[test]
+    fn test_completion_fee_models() {
+        let pricing = |fee: &str| -> Pricing {
+            let json = format!(
+                r#"{{"periods": [{{"start": "00:00:00", "end": "00:00:00", "price": 1.0}}], "service_fee": {fee}}}"#
+            );
+            Pricing::new(json.parse().unwrap(), chrono_tz::UTC)
+        };
+        let complete = |fee: &str, per_kwh: f64, detail: ChargingDetail| -> ChargingDetail {
+            let (charge, _clock) = manual_charge(30.0, 2);
+            let mut charge = charge.with_pricing(pricing(fee));
+            charge.add_detail(detail.with_request_amount(30.0)).unwrap();
+            charge.start_charging();
+            let start = charge.get_charging_detail_ref().unwrap().clone_start_time();
+            // 多次更新不会重复收取按次服务费
+            for minutes in [10, 20, 30] {
+                charge.update_charging_at(start + chrono::Duration::minutes(minutes));
+            }
+            let detail = charge.get_charging_detail_ref().unwrap();
+            assert_eq!(detail.get_session_fee(), None);
+            assert_eq!(detail.get_total_cost(), add_money(15.0, per_kwh * 15.0));
+            charge
+                .complete_charging_at(1, start + chrono::Duration::hours(1))
+                .unwrap()
+        };
+
+        let service_fee =
+            |detail: &ChargingDetail| serde_json::to_value(detail).unwrap()["service_fee"].clone();
+
+        // 只按度数收取
+        let detail = complete("0.5", 0.5, ChargingDetail::test_new(1));
+        assert_eq!(
+            (service_fee(&detail), detail.get_total_cost()),
+            (15.0.into(), 45.0)
+        );
+        assert_eq!(
+            (detail.get_session_fee(), detail.get_idle_fee()),
+            (None, None)
+        );
+
+        // 只按次收取，完成时收取一次
+        let detail = complete(r#"{"per_session": 3.0}"#, 0.0, ChargingDetail::test_new(1));
+        assert_eq!(
+            (service_fee(&detail), detail.get_total_cost()),
+            (3.0.into(), 33.0)
+        );
+        assert_eq!(detail.get_session_fee(), Some(3.0));
+
+        // 没有预计离场时间时不收取占位费
+        let detail = complete(
+            r#"{"per_minute_after_complete": 0.1}"#,
+            0.0,
+            ChargingDetail::test_new(1),
+        );
+        assert_eq!(
+            (detail.get_idle_fee(), detail.get_total_cost()),
+            (None, 30.0)
+        );
+
+        // 三种服务费同时收取，占位费按充满到离场的时长计算
+        let departure = epoch() + chrono::Duration::minutes(80);
+        let detail = complete(
+            r#"{"per_kwh": 0.5, "per_session": 3.0, "per_minute_after_complete": 0.1}"#,
+            0.5,
+            ChargingDetail::test_new(1).with_departure_time(departure),
+        );
+        assert_eq!(detail.get_idle_fee(), Some(2.0));
+        assert_eq!(service_fee(&detail), 18.0);
+        assert_eq!(detail.get_total_cost(), 50.0);
+    }
+
+    #[test]
+    fn test_update_interval_precedence() {
+        // 详单 > 充电桩 > 全局
+        assert_eq!(
+            resolve_update_interval(Some(2000), Some(30000), 5000, 1000),
+            2000
+        );
+        assert_eq!(
+            resolve_update_interval(None, Some(30000), 5000, 1000),
+            30000
+        );
+        assert_eq!(resolve_update_interval(None, None, 5000, 1000), 5000);
+        // 详单指定的间隔不能小于最短间隔
+        assert_eq!(resolve_update_interval(Some(10), None, 5000, 1000), 1000);
+
+        let mut conf = Conf::default();
+        conf.charge.update_interval = Some(30000);
+        conf.time.min_update_interval = 500;
+        let spec = conf.charge.pile_specs().remove(0);
+        let mut charge = build_charge(&conf, &spec, 0).unwrap();
+        let mut vip = serde_json::to_value(ChargingDetail::test_new(1)).unwrap();
+        vip["update_interval_ms"] = 100.into();
+        let vip: ChargingDetail = serde_json::from_value(vip).unwrap();
+        charge.add_detail(vip).unwrap();
+        charge.add_detail(ChargingDetail::test_new(2)).unwrap();
+        assert_eq!(charge.update_interval(), 30000);
+        charge.start_charging();
+        // 开始充电时选择更新间隔，并在状态更新中告知服务器
+        assert_eq!(charge.update_interval(), 500);
+        let value = serde_json::to_value(charge.get_charging_detail_ref().unwrap()).unwrap();
+        assert_eq!(value["effective_update_interval_ms"], 500);
+        charge.complete_charging(1).unwrap();
+        charge.start_charging();
+        assert_eq!(charge.update_interval(), 30000);
+    }
+
+    #[test]
+    fn test_free_vend_stops_billing() {
+        let mut charge = Charge::new(CONF.charge.charge_type, 30.0, 2);
+        charge.add_detail(ChargingDetail::test_new(1)).unwrap();
+        charge.start_charging();
+        let start = charge.get_charging_detail_ref().unwrap().clone_start_time();
+        let half = start + chrono::Duration::minutes(30);
+        let end = start + chrono::Duration::hours(1);
+
+        // 充到一半时开启免费充电，之后只累计度数
+        assert!(charge.set_free_vend(true, half));
+        assert!(!charge.set_free_vend(true, half));
+        charge.update_charging_at(half + chrono::Duration::minutes(10));
+        let value = serde_json::to_value(charge.get_charging_detail_ref().unwrap()).unwrap();
+        assert_eq!(value["free_vend"], true);
+        let first_half = calc_price_using(None, start, half, 30.0, 0.0).unwrap();
+        assert_eq!(value["charge_cost"], first_half.0);
+        assert_eq!(value["service_fee"], first_half.1);
+
+        let completed = charge.complete_charging_at(1, end).unwrap();
+        let value = serde_json::to_value(&completed).unwrap();
+        assert_eq!(value["already_charged"], 30.0);
+        assert_eq!(
+            completed.get_total_cost(),
+            round_to_precision(first_half.0 + first_half.1, 2)
+        );
+        let free = value["per_period"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|usage| usage["label"] == FREE_VEND_LABEL)
+            .unwrap();
+        assert_eq!(free["kwh"], 15.0);
+        assert_eq!(free["cost"], 0.0);
+        // 账单明细按时间顺序列出计费时段和免费时段
+        let breakdown = completed.get_breakdown().unwrap();
+        let last = breakdown.items.last().unwrap();
+        assert_eq!(
+            (last.label.as_str(), last.start, last.end),
+            (FREE_VEND_LABEL, half, end)
+        );
+        assert_eq!(breakdown.items[0].start, start);
+        assert_eq!(breakdown.total_cost, completed.get_total_cost());
+        // 注册消息和状态快照中带有免费充电状态
+        assert_eq!(serde_json::to_value(&charge).unwrap()["free_vend"], true);
+        assert!(charge.status_snapshot(end).free_vend);
+
+        // 关闭后从关闭时刻起恢复计费
+        assert!(charge.set_free_vend(false, end));
+        let status = serde_json::to_value(charge.status_snapshot(end)).unwrap();
+        assert!(status.get("free_vend").is_none());
+        charge.add_detail(ChargingDetail::test_new(2)).unwrap();
+        charge.start_charging();
+        let start = charge.get_charging_detail_ref().unwrap().clone_start_time();
+        charge.update_charging_at(start + chrono::Duration::minutes(30));
+        let value = serde_json::to_value(charge.get_charging_detail_ref().unwrap()).unwrap();
+        assert!(value.get("free_vend").is_none());
+        assert!(value.get("breakdown").is_none());
+        assert!(value["charge_cost"].as_f64().unwrap() > 0.0);
+    }
+
+    #[test]
+    fn test_pending_buffer_promotion() {
+        let mut charge = Charge::new(CONF.charge.charge_type, 30.0, 2).with_pending_buffer(2);
+        assert_eq!(
+            charge.admit(ChargingDetail::test_new(1)),
+            Ok(Admission::Queued(0))
+        );
+        assert_eq!(
+            charge.admit(ChargingDetail::test_new(2)),
+            Ok(Admission::Queued(1))
+        );
+        // 队列已满时进入等待区，等待区也满时拒绝
+        assert_eq!(
+            charge.admit(ChargingDetail::test_new(3)),
+            Ok(Admission::Pending(0))
+        );
+        assert_eq!(
+            charge.admit(ChargingDetail::test_new(4)),
+            Ok(Admission::Pending(1))
+        );
+        assert_eq!(
+            charge.admit(ChargingDetail::test_new(5)),
+            Err(AddDetailError::QueueFull)
+        );
+        assert_eq!(
+            charge.admit(ChargingDetail::test_new(3)),
+            Err(AddDetailError::DuplicateId)
+        );
+        // 等待区的详单不占用队列容量，但会出现在注册消息中
+        assert_eq!(charge.get_queue_size(), 2);
+        let value = serde_json::to_value(&charge).unwrap();
+        assert_eq!(value["pending"].as_array().unwrap().len(), 2);
+        // 状态快照中等待区的详单与队列分开列出
+        let status = charge.status_snapshot(charge.now());
+        let pending: Vec<_> = status.pending.iter().map(|d| d.get_id()).collect();
+        assert_eq!(pending, [3, 4]);
+        assert_eq!(status.queue.len(), 2);
+        let value = serde_json::to_value(&status).unwrap();
+        assert_eq!(value["pending"][0]["id"], 3);
+
+        // 完成一个会话后等待区的第一个详单自动进入队列
+        charge.start_charging();
+        assert!(charge.complete_charging(1).is_some());
+        assert_eq!(charge.take_promoted(), vec![(3, 1)]);
+        assert!(charge.take_promoted().is_empty());
+        assert_eq!(charge.get_queue_size(), 2);
+        assert_eq!(charge.get_pending_size(), 1);
+
+        // 等待区中的详单可以取消，不会进入队列
+        let cancelled = charge.cancel_charging(4, None, None).unwrap();
+        assert_eq!(cancelled.get_already_charged(), 0.0);
+        assert_eq!(charge.get_pending_size(), 0);
+        charge.cancel_charging(2, None, None).unwrap();
+        assert!(charge.take_promoted().is_empty());
+
+        // 未设置等待区时队列已满直接拒绝
+        let mut charge = Charge::new(CONF.charge.charge_type, 30.0, 1);
+        charge.admit(ChargingDetail::test_new(1)).unwrap();
+        assert_eq!(
+            charge.admit(ChargingDetail::test_new(2)),
+            Err(AddDetailError::QueueFull)
+        );
+    }
+
+    #[test]
+    fn test_cancellation_fee() {
+        let mut charge =
+            Charge::new(CONF.charge.charge_type, 30.0, 2).with_cancellation_fee(1.234, None);
+        charge.add_detail(ChargingDetail::test_new(1)).unwrap();
+        charge.add_detail(ChargingDetail::test_new(2)).unwrap();
+        charge.start_charging();
+
+        // 等待中的详单取消时不收取
+        let waiting = charge.cancel_charging(2, None, None).unwrap();
+        assert_eq!(waiting.get_penalty_fee(), None);
+        assert_eq!(waiting.get_total_cost(), 0.0);
+
+        // 开始充电后取消收取违约金，按精度取整并计入总费用
+        let started = charge.cancel_charging(1, None, None).unwrap();
+        assert_eq!(started.get_penalty_fee(), Some(1.23));
+        assert!(started.get_total_cost() >= 1.23);
+        assert!(!charge.is_working());
+        assert_eq!(
+            charge.cancel_charging(1, None, None).err(),
+            Some(ChargeError::NoSuchDetail(1))
+        );
+
+        // 已充电度数达到阈值时才收取
+        let charge =
+            Charge::new(CONF.charge.charge_type, 30.0, 2).with_cancellation_fee(2.0, Some(5.0));
+        assert_eq!(charge.cancellation_fee_for(4.99), None);
+        assert_eq!(charge.cancellation_fee_for(5.0), Some(2.0));
+        let charge = Charge::new(CONF.charge.charge_type, 30.0, 2);
+        assert_eq!(charge.cancellation_fee_for(10.0), None);
+    }
+
+    #[test]
+    fn test_real_update_interval() {
+        assert_eq!(real_update_interval(5000, 1.0), 5000);
+        // 更新间隔为虚拟毫秒，按加速倍数换算
+        assert_eq!(real_update_interval(60_000, 60.0), 1000);
+        // 真实间隔有下限
+        assert_eq!(
+            real_update_interval(5000, 1000.0),
+            MIN_REAL_UPDATE_INTERVAL_MS
+        );
+        assert_eq!(real_update_interval(0, 1.0), MIN_REAL_UPDATE_INTERVAL_MS);
+    }
+
+    #[test]
+    fn test_canceled_status() {
+        // 默认与旧版本一样标记为中断
+        let mut charge = Charge::new(CONF.charge.charge_type, 30.0, 2);
+        charge.add_detail(ChargingDetail::test_new(1)).unwrap();
+        let detail = charge.cancel_charging(1, None, None).unwrap();
+        assert_eq!(detail.get_status(), ChargeStatus::Interrupted);
+
+        let mut charge = Charge::new(CONF.charge.charge_type, 30.0, 2).with_canceled_status(true);
+        charge.add_detail(ChargingDetail::test_new(1)).unwrap();
+        charge.add_detail(ChargingDetail::test_new(2)).unwrap();
+        charge.start_charging();
+        let waiting = charge.cancel_charging(2, None, None).unwrap();
+        assert_eq!(waiting.get_status(), ChargeStatus::Canceled);
+        let started = charge.cancel_charging(1, None, None).unwrap();
+        assert_eq!(started.get_status(), ChargeStatus::Canceled);
+        assert_eq!(started.get_status().to_string(), "canceled");
+        // 取消的详单不会在修复后恢复
+        assert!(started.resumption().is_none());
+
+        // 故障打断的详单仍然标记为中断
+        charge.add_detail(ChargingDetail::test_new(3)).unwrap();
+        charge.start_charging();
+        let interrupted = charge.breakdown(FaultSource::Internal).unwrap().remove(0);
+        assert_eq!(interrupted.get_status(), ChargeStatus::Interrupted);
+    }
+
+    #[test]
+    fn test_cancel_active_before_completion() {
+        let mut charge = Charge::new(CONF.charge.charge_type, 30.0, 2);
+        charge.add_detail(ChargingDetail::test_new(1)).unwrap();
+        charge.start_charging();
+        let end = charge
+            .get_charging_detail_ref()
+            .and_then(ChargingDetail::get_schedule_estimate)
+            .unwrap()
+            .1;
+
+        // 结束前取消，之后到期的完成定时器不应再完成任何详单
+        charge.cancel_charging(1, None, None).unwrap();
+        assert!(!charge.is_working());
+        assert!(charge.complete_charging_at(1, end).is_none());
+
+        // 工作状态与队列不一致时可以恢复
+        charge.active = 1;
+        assert!(charge.get_charging_detail_ref().is_none());
+        charge.reset_working();
+        assert!(!charge.is_working());
+        assert!(charge.complete_charging_at(1, end).is_none());
+    }
+
+    #[test]
+    fn test_schedule_estimates() {
+        let mut charge = Charge::new(CONF.charge.charge_type, 30.0, 3);
+        for id in 1..=3 {
+            charge.add_detail(ChargingDetail::test_new(id)).unwrap();
+        }
+        charge.start_charging();
+        let schedule = |charge: &Charge| -> Vec<_> {
+            charge
+                .get_queue_snapshot()
+                .iter()
+                .map(|d| d.get_schedule_estimate().unwrap())
+                .collect()
+        };
+        let estimates = schedule(&charge);
+        assert_eq!(estimates.len(), 3);
+        // 每个详单 30 度，30 kW 充满需要一小时，依次排在前一个详单之后
+        for (start, end) in &estimates {
+            assert_eq!(*end - *start, chrono::Duration::hours(1));
+        }
+        for pair in estimates.windows(2) {
+            assert_eq!(pair[0].1, pair[1].0);
+            assert!(pair[0].0 < pair[1].0 && pair[0].1 < pair[1].1);
+        }
+
+        // 取消中间的详单后重新计算，第三个详单提前开始
+        charge.cancel_charging(2, None, None).unwrap();
+        let after_cancel = schedule(&charge);
+        assert_eq!(after_cancel.len(), 2);
+        assert_eq!(after_cancel[1].0, estimates[0].1);
+        // 离开队列的详单不再带有估计时间
+        let detail = charge.complete_charging(1).unwrap();
+        assert!(detail.get_schedule_estimate().is_none());
+        let value = serde_json::to_value(&detail).unwrap();
+        assert!(value.get("estimated_end_time").is_none());
+    }
+
+    #[test]
+    fn test_status_snapshot() {
+        let (mut charge, _clock) = manual_charge(30.0, 3);
+        for id in 1..=3 {
+            charge.add_detail(ChargingDetail::test_new(id)).unwrap();
+        }
+        let now = epoch();
+        // 未开始充电时所有详单都在排队
+        let status = charge.status_snapshot(now);
+        assert!(!status.working && status.charging.is_none());
+        assert_eq!(status.queue.len(), 3);
+
+        charge.start_charging();
+        let status = charge.status_snapshot(now);
+        assert!(status.working);
+        assert_eq!(status.charging.as_ref().map(|d| d.get_id()), Some(1));
+        let waiting: Vec<_> = status.queue.iter().map(|d| d.get_id()).collect();
+        assert_eq!(waiting, vec![2, 3]);
+        assert_eq!(status.charge_id, charge.get_id());
+        assert_eq!(status.virtual_time, now);
+        let value = serde_json::to_value(&status).unwrap();
+        assert_eq!(value["type"], serde_json::to_value(charge.type_).unwrap());
+        assert_eq!(charge.get_queue_snapshot().len(), 3);
+    }
+
+    #[test]
+    fn test_register_payload() {
+        let mut charge = Charge::new(CONF.charge.charge_type, 30.0, 3);
+        charge.add_detail(ChargingDetail::test_new(1)).unwrap();
+        let payload = serde_json::to_value(charge.register_payload(4.0)).unwrap();
+        // 充电桩字段与充电桩本身的序列化结果一致
+        let serde_json::Value::Object(fields) = serde_json::to_value(&charge).unwrap() else {
+            unreachable!()
+        };
+        for (key, value) in fields {
+            assert_eq!(payload[&key], value, "{}", key);
+        }
+        assert_eq!(payload["protocol_version"], PROTOCOL_VERSION);
+        assert_eq!(payload["software_version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(payload["speed"], 4.0);
+        assert_eq!(payload["manual_break"], CONF.charge.manual_break);
+        assert_eq!(
+            payload["features"].as_array().unwrap().len(),
+            PROTOCOL_FEATURES.len()
+        );
+        assert!(payload.get("auth_token").is_none());
+        assert_eq!(
+            payload["price_fingerprint"],
+            fingerprint_hex_using(None).unwrap()
+        );
+
+        let parsed: RegisterPayload = serde_json::from_value(payload).unwrap();
+        assert_eq!(parsed.charge_id, charge.get_id());
+        assert_eq!(parsed.queue.len(), 1);
+    }
+
+    #[test]
+    fn test_reservation_only_rejects_new() {
+        let mut charge = Charge::new(CONF.charge.charge_type, 30.0, 2).with_reservation_only(true);
+        assert_eq!(
+            charge.add_detail(ChargingDetail::test_new(1)),
+            Err(AddDetailError::ReservationOnly)
+        );
+        assert_eq!(charge.get_queue_size(), 0);
+        assert!(
+            serde_json::to_string(&charge)
+                .unwrap()
+                .contains("\"reservation_only\":true")
+        );
+
+        charge.set_reservation_only(false);
+        charge.add_detail(ChargingDetail::test_new(1)).unwrap();
+        assert_eq!(charge.get_queue_size(), 1);
+    }
+
+    #[test]
+    fn test_invalid_request_amount_rejected() {
+        let mut charge =
+            Charge::new(CONF.charge.charge_type, 30.0, 5).with_max_request_amount(Some(100.0));
+        for amount in [-5.0, 0.0, f64::NAN, f64::INFINITY, 100.5] {
+            assert_eq!(
+                charge.add_detail(ChargingDetail::test_new(1).with_request_amount(amount)),
+                Err(AddDetailError::InvalidRequestAmount),
+                "request_amount {} should be rejected",
+                amount
+            );
+        }
+        assert_eq!(charge.get_queue_size(), 0);
+        charge
+            .add_detail(ChargingDetail::test_new(1).with_request_amount(100.0))
+            .unwrap();
+        // 库调用者直接加入详单时同样检查重复 ID
+        assert_eq!(
+            charge.add_detail(ChargingDetail::test_new(1)),
+            Err(AddDetailError::DuplicateId)
+        );
+        assert_eq!(
+            AddDetailError::InvalidRequestAmount.reason(),
+            "invalid_request_amount"
+        );
+    }
+
+    #[test]
+    fn test_multiple_connectors() {
+        let ids = |details: &[ChargingDetail]| {
+            details
+                .iter()
+                .map(ChargingDetail::get_id)
+                .collect::<Vec<_>>()
+        };
+        let mut charge = Charge::new(CONF.charge.charge_type, 30.0, 4).with_connectors(2);
+        for (id, amount) in [(1, 30.0), (2, 15.0), (3, 30.0)] {
+            charge
+                .add_detail(ChargingDetail::test_new(id).with_request_amount(amount))
+                .unwrap();
+        }
+        assert_eq!(charge.start_charging(), Some(1));
+        assert_eq!(charge.start_charging(), Some(2));
+        // 两把充电枪都在使用中，第三个详单继续等待
+        assert!(!charge.can_start());
+        assert_eq!(charge.start_charging(), None);
+        assert_eq!(charge.active_detail_ids(), [1, 2]);
+        assert_eq!(charge.current_power(), 60.0);
+        // 等待中的详单排在最早空闲的充电枪上
+        let estimate = |charge: &Charge, id| {
+            charge
+                .get_detail_ref(id)
+                .and_then(ChargingDetail::get_schedule_estimate)
+                .unwrap()
+        };
+        assert_eq!(estimate(&charge, 3).0, estimate(&charge, 2).1);
+        let status = charge.status_snapshot(charge.now());
+        assert_eq!(status.charging.unwrap().get_id(), 1);
+        assert_eq!(ids(&status.also_charging), [2]);
+        assert_eq!(ids(&status.queue), [3]);
+
+        // 每个详单按自己的开始时间结算，后开始的详单可以先完成
+        let start = charge.get_detail_ref(2).unwrap().clone_start_time();
+        let half_hour = start + chrono::Duration::minutes(30);
+        charge.update_charging_at(half_hour);
+        assert_eq!(
+            charge.get_detail_ref(2).unwrap().get_already_charged(),
+            15.0
+        );
+        assert!(charge.complete_charging_at(3, half_hour).is_none());
+        let completed = charge.complete_charging_at(2, half_hour).unwrap();
+        assert_eq!(completed.get_status(), ChargeStatus::Completed);
+        assert!(charge.is_working() && charge.can_start());
+        assert_eq!(charge.start_charging(), Some(3));
+        assert_eq!(charge.active_detail_ids(), [1, 3]);
+
+        // 取消一个正在充电的详单只释放它的充电枪
+        charge.add_detail(ChargingDetail::test_new(4)).unwrap();
+        charge.cancel_charging(1, None, None).unwrap();
+        assert_eq!(charge.active_detail_ids(), [3]);
+        assert_eq!(charge.start_charging(), Some(4));
+
+        // 关闭时中断所有正在充电的详单
+        assert_eq!(ids(&charge.close()), [3, 4]);
+        assert!(!charge.is_working());
+        assert_eq!(charge.current_power(), 0.0);
+    }
+
+    #[test]
+    fn test_connectors_fault_and_restore() {
+        let ids = |details: &[ChargingDetail]| {
+            details
+                .iter()
+                .map(ChargingDetail::get_id)
+                .collect::<Vec<_>>()
+        };
+        let path = std::env::temp_dir().join(format!("taranis-state-{}.json", Uuid::new_v4()));
+        let pile = || {
+            Charge::new(CONF.charge.charge_type, 30.0, 4)
+                .with_connectors(2)
+                .with_requeue_after_repair(true)
+        };
+        let mut charge = pile();
+        charge.restore(&path);
+        for id in 1..=3 {
+            charge.add_detail(ChargingDetail::test_new(id)).unwrap();
+        }
+        charge.start_charging();
+        charge.start_charging();
+
+        // 重新启动后两个详单继续充电
+        let mut restored = pile();
+        assert!(restored.restore(&path));
+        assert_eq!(restored.active_detail_ids(), [1, 2]);
+
+        // 故障打断两个详单，修复后都恢复充电，状态文件中保存了两个等待恢复的详单
+        assert_eq!(
+            ids(&charge.breakdown(FaultSource::Internal).unwrap()),
+            [1, 2]
+        );
+        let mut restored = pile();
+        assert!(restored.restore(&path));
+        assert!(restored.is_faulted());
+        let resumed = restored.repair().unwrap();
+        assert_eq!(ids(&resumed), [1, 2]);
+        assert!(resumed.iter().all(ChargingDetail::is_resumed));
+        assert_eq!(restored.active_detail_ids(), [1, 2]);
+
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(persist::backup_path(&path)).ok();
+
+        // 只有一把充电枪时状态文件的格式与旧版本相同
+        let mut single =
+            Charge::new(CONF.charge.charge_type, 30.0, 2).with_requeue_after_repair(true);
+        single.restore(&path);
+        single.add_detail(ChargingDetail::test_new(5)).unwrap();
+        single.start_charging();
+        single.breakdown(FaultSource::Internal).unwrap();
+        let (content, _) = persist::read_with_fallback(&path).unwrap();
+        let state: serde_json::Value = serde_json::from_str(&content).unwrap();
+        assert!(state.get("active").is_none());
+        assert_eq!(state["stash"]["id"], 5);
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(persist::backup_path(&path)).ok();
+    }
+
+    #[test]
+    fn test_manual_clock_lifecycle() {
+        let pricing = Pricing::new(
+            r#"{"periods": [{"start": "00:00:00", "end": "00:30:00", "price": 1.0}, {"start": "00:30:00", "end": "00:00:00", "price": 2.0}], "service_fee": 0.5}"#
+                .parse()
+                .unwrap(),
+            chrono_tz::UTC,
+        );
+        let (charge, clock) = manual_charge(30.0, 2);
+        let mut charge = charge.with_pricing(pricing);
+        charge.add_detail(ChargingDetail::test_new(1)).unwrap();
+        charge.start_charging();
+        let detail = charge.get_charging_detail_ref().unwrap();
+        assert_eq!(detail.get_start_time(), Some(epoch()));
+        assert_eq!(
+            charge.complete_interval(1),
+            ((3600 * 1000 + 100) as f64 / RUNTIME.speed()) as u64
+        );
+
+        // 只有推进时钟时才充电，每次更新的度数和费用都是精确值
+        let costs = |charge: &Charge| {
+            let detail = charge.get_charging_detail_ref().unwrap();
+            let value = serde_json::to_value(detail).unwrap();
+            (
+                detail.get_already_charged(),
+                value["charge_cost"].as_f64().unwrap(),
+                value["service_fee"].as_f64().unwrap(),
+                detail.get_total_cost(),
+            )
+        };
+        clock.advance(chrono::Duration::minutes(20));
+        charge.update_charging();
+        assert_eq!(costs(&charge), (10.0, 10.0, 5.0, 15.0));
+        // 跨越 00:30 后按两段电价计费
+        clock.advance(chrono::Duration::minutes(20));
+        charge.update_charging();
+        assert_eq!(costs(&charge), (20.0, 25.0, 10.0, 35.0));
+
+        clock.advance(chrono::Duration::minutes(20));
+        let detail = charge.complete_charging(1).unwrap();
+        assert_eq!(detail.get_status(), ChargeStatus::Completed);
+        assert_eq!(
+            detail.get_end_time(),
+            Some(epoch() + chrono::Duration::hours(1))
+        );
+        assert_eq!(detail.get_charge_duration_s(), Some(3600.0));
+        assert_eq!(
+            (detail.get_already_charged(), detail.get_total_cost()),
+            (30.0, 60.0)
+        );
+        let usage: Vec<_> = detail
+            .get_per_period()
+            .iter()
+            .map(|usage| (usage.kwh, usage.cost, usage.fee))
+            .collect();
+        assert_eq!(usage, [(15.0, 15.0, 7.5), (15.0, 30.0, 7.5)]);
+        assert!(!charge.is_working());
     }
 }
@@ -0,0 +1,2677 @@
+//! 充电桩客户端
+//!
+//! 连接 WebSocket 服务器，注册充电桩并处理服务器消息，按计时器发送状态更新和完成消息。
+//! 主程序通过 [`run`] 按配置运行所有充电桩，测试和嵌入时可以用 [`run_client`] 在已经建立的连接上运行单个充电桩。
+
+use std::collections::HashMap;
+use std::io::IsTerminal;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicI64, Ordering};
+
+use crate::time::{self, get_mock_now, init_console_time};
+use futures_util::SinkExt;
+use futures_util::StreamExt;
+use serde_json::Value;
+use tokio::net::TcpStream;
+use tokio::sync::{Notify, broadcast, mpsc, watch};
+use tokio::time::Interval;
+use tracing::{Instrument, instrument};
+
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use tokio::task;
+
+use tokio::time::{Duration, interval_at, timeout};
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::http::{HeaderName, HeaderValue};
+use tokio_tungstenite::{
+    Connector, MaybeTlsStream, WebSocketStream, connect_async_tls_with_config,
+};
+
+use crate::audit::{self, Direction};
+use crate::charge::FaultSource;
+use crate::charge::{
+    self, Admission, Charge, ChargeError, ChargeHandle, MIN_REAL_UPDATE_INTERVAL_MS, build_charge,
+    real_update_interval,
+};
+use crate::compat::{self, CompatReport};
+use crate::conf::{self, CONF, Conf};
+use crate::conf::{HeartbeatMode, MaintenancePolicy, WireEncoding};
+use crate::detail::{ChargingDetail, UNSPECIFIED_STOP_REASON};
+use crate::event::ChargeEvent;
+use crate::failover::Endpoints;
+use crate::handshake::{self, Handshake};
+use crate::keys::{self, KeyCommand};
+use crate::maintenance::{self, MaintenancePhase};
+use crate::message::{
+    AUTH_FAILED_CLOSE_CODE, AckData, AuthErrorData, CancelData, ErrorData, FreeVendData,
+    HeartbeatData, IdleSkipGrantData, IdleSkipRequestData, MSG, MessageType, MsgAckData,
+    RegisterAckData, RejectData, SetSpeedData, StatusData, parse_binary, parse_frame,
+};
+use crate::metrics::{self, PileMetrics};
+use crate::outbound::OutboundQueue;
+use crate::outbox::Outbox;
+use crate::price;
+use crate::reload;
+use crate::runtime::{RUNTIME, RuntimeValues};
+use crate::skip::IdleSkip;
+use crate::standalone;
+use crate::throttle;
+use crate::tls;
+use crate::trace::{self, PowerSample, PowerTrace};
+use crate::traffic::{TrafficAction, TrafficStats};
+use crate::transport::{self, Inlet, JsonLines, Outlet};
+use crate::tui;
+use crate::update::UpdateEncoder;
+use crate::watchdog::{Heartbeat, HeartbeatAction, IdleWatchdog, WatchdogAction, wait_deadline};
+use crate::webhook;
+
+use tokio_tungstenite::tungstenite::Message as WsMessage;
+use tokio_tungstenite::tungstenite::error::{CapacityError, Error as WsError};
+use tokio_tungstenite::tungstenite::protocol::CloseFrame;
+use tokio_tungstenite::tungstenite::protocol::WebSocketConfig;
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+
+#[cfg(unix)]
+/// 配置重载信号和终止信号
+type ReloadSignal = tokio::signal::unix::Signal;
+#[cfg(not(unix))]
+/// 配置重载信号和终止信号，非 Unix 平台不支持
+type ReloadSignal = ();
+
+/// 单个充电桩的运行状态，每个充电桩有自己的连接、计时器和状态更新编码器
+/// 处理函数通过参数接收充电桩，不使用全局的 `CHARGE`
+struct Pile {
+    /// 充电桩在配置中的序号
+    index: usize,
+    /// 充电桩
+    charge: ChargeHandle,
+    /// 充电桩 ID，用于审计日志
+    charge_id: uuid::Uuid,
+    /// 充电状态更新编码器
+    updates: std::sync::Mutex<UpdateEncoder>,
+    /// 当前连接的消息计数
+    traffic: std::sync::Mutex<TrafficStats>,
+    /// 连接只有发送没有接收时通知断开连接
+    connection_lost: Notify,
+    /// 等待服务器确认的消息，迁移连接后继续使用
+    outbox: std::sync::Mutex<Outbox>,
+    /// 等待发送的消息，由主循环发送，迁移连接后继续使用
+    outbound: std::sync::Mutex<OutboundQueue>,
+    /// 有新消息放入出站队列时通知主循环
+    outbound_ready: Notify,
+    /// 充电桩指标
+    metrics: Arc<PileMetrics>,
+    /// 注册握手状态，等待确认时暂存新详单
+    handshake: std::sync::Mutex<Handshake>,
+    /// 发送消息使用的编码方式，服务器在注册确认中同意后才切换到 MessagePack
+    encoding: std::sync::Mutex<WireEncoding>,
+    /// 可以连接的 WebSocket 地址，连接断开时切换到其他地址
+    endpoints: std::sync::Mutex<Endpoints>,
+    /// 已经平移到详单时间中的虚拟时钟调整量，单位为毫秒
+    clock_offset_ms: AtomicI64,
+    /// 跳过空闲时间的协商状态，新详单到达时取消
+    idle_skip: std::sync::Mutex<IdleSkip>,
+    /// 充电桩的状态变化事件，由 [`forward_events`] 转换为出站消息
+    events: std::sync::Mutex<broadcast::Receiver<ChargeEvent>>,
+}
+
+impl Pile {
+    /// 按 `conf` 中的连接设置创建充电桩运行状态
+    fn new(index: usize, charge: Charge, conf: &Conf) -> Self {
+        let metrics = charge.get_metrics();
+        let events = charge.subscribe();
+        Pile {
+            index,
+            charge_id: charge.get_id(),
+            charge: charge::new_handle(charge),
+            updates: std::sync::Mutex::new(UpdateEncoder::new(
+                conf.websocket.update_mode,
+                conf.websocket.snapshot_every,
+            )),
+            traffic: std::sync::Mutex::new(TrafficStats::new(conf.websocket.max_unacked_updates)),
+            connection_lost: Notify::new(),
+            outbox: std::sync::Mutex::new(Outbox::new(conf.websocket.resend_after_s > 0)),
+            outbound: std::sync::Mutex::new(
+                OutboundQueue::new(conf.websocket.send_buffer)
+                    .with_min_gap(Duration::from_millis(conf.websocket.min_update_gap_ms)),
+            ),
+            outbound_ready: Notify::new(),
+            metrics,
+            handshake: std::sync::Mutex::new(Handshake::new(Duration::from_secs(
+                conf.websocket.register_ack_timeout_s,
+            ))),
+            encoding: std::sync::Mutex::new(WireEncoding::Json),
+            endpoints: std::sync::Mutex::new(Endpoints::new(
+                conf.websocket.endpoints(),
+                conf.websocket.failover,
+            )),
+            clock_offset_ms: AtomicI64::new(RUNTIME.values().clock_offset_ms),
+            idle_skip: std::sync::Mutex::new(IdleSkip::new(idle_skip_after(conf))),
+            events: std::sync::Mutex::new(events),
+        }
+    }
+}
+
+/// 当前连接的入站消息字段兼容性报告
+static COMPAT: std::sync::Mutex<CompatReport> = std::sync::Mutex::new(CompatReport::new());
+
+/// 键盘 'q' 请求退出，所有充电桩按收到 Ctrl+C 的方式退出
+static SHUTDOWN_REQUESTED: std::sync::LazyLock<watch::Sender<bool>> =
+    std::sync::LazyLock::new(|| watch::Sender::new(false));
+
+/// 服务器是否拒绝了认证，为真时程序以非零退出码结束
+static AUTH_FAILED: AtomicBool = AtomicBool::new(false);
+
+/// 认证失败时的退出码
+pub const AUTH_FAILED_EXIT_CODE: i32 = 3;
+
+/// 服务器是否拒绝了认证，为真时主程序应当以 [`AUTH_FAILED_EXIT_CODE`] 结束
+pub fn is_auth_failed() -> bool {
+    AUTH_FAILED.load(Ordering::Acquire)
+}
+
+/// `wss://` 连接使用的 TLS 连接器，启动时按 `websocket.tls` 生成，为 `None` 时使用内置的根证书
+static TLS_CONNECTOR: std::sync::OnceLock<Option<Connector>> = std::sync::OnceLock::new();
+
+#[instrument]
+/// 主工作函数，负责初始化充电桩，连接 WebSocket 服务器，并处理消息。
+pub async fn run() {
+    tracing::info!("程序 PID: {}", std::process::id());
+    // 加载配置后应用控制台时间格式
+    init_console_time(&CONF.log);
+    // 初始化充电桩
+    tracing::info!("充电桩服务启动");
+    let _conf = &*CONF;
+    // 证书文件在启动时加载，加载失败时拒绝启动
+    match tls::connector(&CONF.websocket.tls) {
+        Ok(connector) => {
+            if connector.is_some() {
+                tracing::info!("已加载 WebSocket TLS 配置");
+            }
+            TLS_CONNECTOR.get_or_init(|| connector);
+        }
+        Err(e) => {
+            tracing::error!("TLS 配置错误，拒绝启动: {}", e);
+            panic!("Invalid TLS config: {}", e);
+        }
+    }
+    // 键盘命令通道，只在标准输入为终端时读取按键
+    let (key_tx, key_rx) = mpsc::unbounded_channel::<KeyCommand>();
+    if std::io::stdin().is_terminal() {
+        tracing::info!(
+            "键盘命令: 'q' 退出，'s' 输出状态，'c' 立即完成当前详单，'+'/'-' 调整时间加速比，空格键暂停或继续虚拟时钟{}",
+            if CONF.charge.manual_break {
+                "，'p' 模拟充电桩损坏"
+            } else {
+                ""
+            }
+        );
+        spawn_key_reader(key_tx);
+    } else {
+        tracing::info!("标准输入不是终端，不读取键盘命令");
+    }
+    if !CONF.charge.manual_break {
+        tracing::info!("充电桩不允许手动模拟损坏");
+    }
+    let specs = CONF.charge.pile_specs();
+    if CONF.charge.is_multi_pile() {
+        tracing::info!(
+            "共配置了 {} 个充电桩，键盘命令只作用于第一个充电桩，退出和虚拟时钟相关的命令对所有充电桩生效",
+            specs.len()
+        );
+    }
+    // 指标监听地址在启动时绑定，绑定失败时拒绝启动
+    let metrics_listener = match &CONF.metrics.listen {
+        Some(addr) => match tokio::net::TcpListener::bind(addr).await {
+            Ok(listener) => Some(listener),
+            Err(e) => {
+                tracing::error!("指标服务无法监听 {}，拒绝启动: {}", addr, e);
+                panic!("Failed to bind metrics listener {}: {}", addr, e);
+            }
+        },
+        None => None,
+    };
+    // 独立运行时在启动时打开输出文件、加载场景，失败时拒绝启动
+    let standalone = match (!CONF.websocket.enabled).then(open_standalone) {
+        Some(Ok(standalone)) => Some(standalone),
+        Some(Err(e)) => {
+            tracing::error!("独立运行配置错误，拒绝启动: {}", e);
+            panic!("Invalid standalone config: {}", e);
+        }
+        None => None,
+    };
+    // 审计日志在启动时打开，打开失败时拒绝启动
+    if let Err(e) = audit::init(&CONF.audit) {
+        tracing::error!("审计日志配置错误，拒绝启动: {}", e);
+        panic!("Invalid audit config: {}", e);
+    }
+    let mut pile_metrics = Vec::new();
+    let mut pile_indices = Vec::new();
+    // 每个充电桩使用独立的任务、连接和计时器
+    let mut key_rx = Some(key_rx);
+    let mut dashboard = None;
+    let mut tasks = task::JoinSet::new();
+    for (index, spec) in specs.iter().enumerate() {
+        let charge = match build_charge(&CONF, spec, index) {
+            Ok(charge) => charge,
+            Err(e) => {
+                tracing::error!("充电桩配置错误: {}", e);
+                continue;
+            }
+        };
+        let charge_id = charge.get_id();
+        pile_indices.push(index);
+        let span = tracing::info_span!("pile", charge_id = %charge_id, state = %charge.get_state());
+        let pile = Pile::new(index, charge.with_span(span.clone()), &CONF);
+        pile_metrics.push((charge_id.to_string(), pile.metrics.clone()));
+        let key_rx = if index == 0 { key_rx.take() } else { None };
+        if index == 0 && conf::overrides().tui {
+            dashboard = start_dashboard(&pile, standalone.is_some()).await;
+        }
+        let standalone = standalone.clone();
+        tasks.spawn(
+            async move {
+                if let Some((lines, source)) = standalone {
+                    // 由本地驱动代替服务器
+                    let ((ws_sender, ws_receiver), (outgoing, incoming)) =
+                        transport::local_pair(lines);
+                    tokio::join!(
+                        run_pile(&pile, ws_sender, ws_receiver, key_rx),
+                        standalone::drive(outgoing, incoming, source, index)
+                    );
+                    return;
+                }
+                // 链接 WebSocket 服务器
+                match connect_endpoints(&pile).await {
+                    Ok((ws_sender, ws_receiver)) => {
+                        run_pile(&pile, ws_sender, ws_receiver, key_rx).await
+                    }
+                    Err(e) => tracing::error!("{}", e),
+                }
+            }
+            .instrument(span),
+        );
+    }
+    if let Some(listener) = metrics_listener {
+        if let Ok(addr) = listener.local_addr() {
+            tracing::info!("指标服务已启动: http://{}/metrics", addr);
+        }
+        metrics::spawn(listener, pile_metrics);
+    }
+    while let Some(result) = tasks.join_next().await {
+        if let Err(e) = result {
+            tracing::error!("充电桩任务异常退出: {}", e);
+        }
+    }
+    // 恢复终端后再输出结束时的汇总
+    drop(dashboard);
+    if CONF.charge.is_multi_pile() {
+        merge_power_traces(&pile_indices);
+    }
+    audit::finish();
+    report_compat();
+    report_suppressed_warnings();
+    tracing::info!(virtual_time = %get_mock_now(), "充电桩服务已停止");
+}
+
+/// 为第一个充电桩启动终端界面，标准输出不是终端或被独立运行的消息占用时不启动
+async fn start_dashboard(pile: &Pile, standalone: bool) -> Option<tui::Dashboard> {
+    if !std::io::stdout().is_terminal() {
+        tracing::warn!("标准输出不是终端，不显示终端界面");
+        return None;
+    }
+    if standalone && CONF.standalone.output.is_none() {
+        tracing::warn!(
+            "独立运行的消息写入标准输出，不显示终端界面，可以用 standalone.output 写入文件"
+        );
+        return None;
+    }
+    match tui::Dashboard::start(pile.charge.clone()).await {
+        Ok(dashboard) => {
+            tracing::info!("已启动终端界面，日志只写入文件");
+            Some(dashboard)
+        }
+        Err(e) => {
+            tracing::warn!("无法启动终端界面: {}", e);
+            None
+        }
+    }
+}
+
+/// 打开独立运行的输出并选择驱动充电桩的来源
+fn open_standalone() -> Result<(JsonLines, standalone::Source), String> {
+    let lines = match &CONF.standalone.output {
+        Some(path) => JsonLines::create(path)?,
+        None => JsonLines::stdout(),
+    };
+    let source = standalone::Source::from_conf(&CONF.standalone)?;
+    tracing::info!(
+        "独立运行，不连接 WebSocket 服务器，充电桩发送的消息写入 {}，驱动来源: {}",
+        CONF.standalone.output.as_deref().unwrap_or("标准输出"),
+        match &source {
+            standalone::Source::Scenario(scenario) => format!("场景 {}", scenario.name),
+            standalone::Source::Generator(conf) => format!("生成 {} 个详单", conf.details),
+        }
+    );
+    Ok((lines, source))
+}
+
+/// 在已经建立的连接上运行按 `conf` 中第一个充电桩定义创建的充电桩，直到连接断开
+/// 连接应按 [`ws_config`] 建立，否则消息大小上限使用 tungstenite 的默认值
+/// 用于测试和嵌入，不监听键盘、不启动指标服务。充电桩、计价和连接状态（更新模式、发送缓冲、重发、
+/// 故障转移地址等）按 `conf` 创建；消息循环中的其余设置（时间加速比、心跳、维护窗口等）仍读取全局的
+/// [`CONF`] 和 [`RUNTIME`]，测试需要不同的值时在第一次访问 [`CONF`] 之前用 [`crate::conf::init_overrides`] 指定配置文件
+pub async fn run_client(conf: &Conf, ws_stream: WebSocketStream<MaybeTlsStream<TcpStream>>) {
+    let spec = conf
+        .charge
+        .pile_specs()
+        .into_iter()
+        .next()
+        .expect("no pile configured");
+    let charge = match build_charge(conf, &spec, 0) {
+        Ok(charge) => charge,
+        Err(e) => {
+            tracing::error!("充电桩配置错误: {}", e);
+            return;
+        }
+    };
+    let span =
+        tracing::info_span!("pile", charge_id = %charge.get_id(), state = %charge.get_state());
+    let pile = Pile::new(0, charge.with_span(span.clone()), conf);
+    let (sink, stream) = ws_stream.split();
+    run_pile(&pile, Outlet::Socket(sink), Inlet::Socket(stream), None)
+        .instrument(span)
+        .await;
+}
+
+/// 运行一个充电桩，直到连接断开或程序退出
+async fn run_pile(
+    pile: &Pile,
+    mut ws_sender: Outlet,
+    mut ws_receiver: Inlet,
+    mut key_rx: Option<mpsc::UnboundedReceiver<KeyCommand>>,
+) {
+    // 启动生命周期 Webhook
+    if CONF.webhook.is_enabled() {
+        tracing::info!("已启用生命周期 Webhook");
+        webhook::spawn(CONF.webhook.clone(), pile.charge.lock().await.subscribe());
+    }
+    // 当前生效的配置，热重载时与新配置比较
+    let mut applied = CONF.clone();
+    let mut reload_signal = reload_signal();
+    let mut terminate_signal = terminate_signal();
+
+    let mut update_tiker: Option<Interval> = None;
+    let mut complete_tikers: CompleteTickers = HashMap::new();
+    let mut maintenance_tiker: Option<Interval> = None;
+    let mut resend_tiker: Option<Interval> = None;
+    let mut maintenance_phase = MaintenancePhase::Open;
+    let mut runtime_rx = RUNTIME.subscribe();
+
+    // 注册充电桩
+    register(pile).await;
+    let mut watchdog = IdleWatchdog::new(
+        Duration::from_secs(CONF.websocket.idle_after_register_s),
+        CONF.websocket.idle_probe,
+    );
+    watchdog.arm(tokio::time::Instant::now());
+
+    // 从状态文件恢复了队列时，按恢复的状态重新设置计时器
+    {
+        let mut charge = pile.charge.lock().await;
+        for id in charge.active_detail_ids() {
+            set_complete_ticker(&mut complete_tikers, &charge, id);
+        }
+        if !charge.is_faulted() {
+            not_working_check(&mut charge, &mut complete_tikers).await;
+        }
+        if charge.is_working() {
+            set_ticker(&mut update_tiker, update_period(&charge));
+        }
+    }
+
+    // 按虚拟时间间隔记录功率，启动时先记录一次
+    let mut power_trace = open_power_trace(pile.index);
+    let mut trace_tiker: Option<Interval> = None;
+    if power_trace.is_some() {
+        sample_power(pile, &mut power_trace).await;
+        set_ticker(
+            &mut trace_tiker,
+            trace::sample_period(CONF.trace.sample_interval_s, RUNTIME.speed()),
+        );
+    }
+
+    if !CONF.charge.maintenance_windows.is_empty() {
+        tracing::info!(
+            "已配置 {} 个维护时间窗口，维护策略: {:?}",
+            CONF.charge.maintenance_windows.len(),
+            CONF.charge.maintenance_policy
+        );
+        // 启动时立即检查一次，之后由 `check_maintenance` 按下一次阶段变化的时间设置计时器
+        check_maintenance(
+            pile,
+            &mut maintenance_phase,
+            &mut update_tiker,
+            &mut complete_tikers,
+            &mut maintenance_tiker,
+        )
+        .await;
+    }
+
+    // 定期发送心跳，长时间没有入站消息时断开连接
+    let mut heartbeat = Heartbeat::new(
+        Duration::from_millis(CONF.websocket.heartbeat_interval),
+        CONF.websocket.heartbeat_max_missed,
+        tokio::time::Instant::now(),
+    );
+    let mut heartbeat_tiker: Option<Interval> = None;
+    if let Some(period) = heartbeat.interval() {
+        set_ticker(&mut heartbeat_tiker, period);
+    }
+
+    // 定期重新发送超时未确认的完成和故障消息
+    if CONF.websocket.resend_after_s > 0 {
+        set_ticker(
+            &mut resend_tiker,
+            Duration::from_secs(CONF.websocket.resend_after_s),
+        );
+    }
+
+    if CONF.time.skip_idle && CONF.charge.is_multi_pile() {
+        tracing::warn!("多个充电桩共用虚拟时钟，不跳过空闲时间");
+    }
+
+    loop {
+        forward_events(pile);
+        if pile.idle_skip.lock().unwrap().is_enabled() {
+            let idle = pile.charge.lock().await.is_idle() && !time::is_paused();
+            pile.idle_skip
+                .lock()
+                .unwrap()
+                .observe(idle, tokio::time::Instant::now());
+        }
+        tokio::select! {
+            msg = ws_receiver.next() => {
+                match msg {
+                    Some(Ok(message)) => {
+                        pile.traffic.lock().unwrap().on_inbound();
+                        heartbeat.on_inbound(tokio::time::Instant::now());
+                        match message {
+                            WsMessage::Text(text) => {
+                                watchdog.on_inbound();
+                                pile.metrics.set_idle(false);
+                                if within_size_limit(pile, text.len()) {
+                                    handle(pile, text.to_string(), &mut update_tiker, &mut complete_tikers).await;
+                                }
+                            }
+                            WsMessage::Binary(bytes) => {
+                                watchdog.on_inbound();
+                                pile.metrics.set_idle(false);
+                                if within_size_limit(pile, bytes.len()) {
+                                    handle_binary(pile, &bytes, &mut update_tiker, &mut complete_tikers).await;
+                                }
+                            }
+                            WsMessage::Close(frame) => {
+                                match frame.filter(|frame| is_auth_failure(frame.code)) {
+                                    Some(frame) => auth_failed(&frame.reason),
+                                    None => tracing::info!(virtual_time = %get_mock_now(), "WebSocket 连接已关闭"),
+                                }
+                                if failover(pile, &mut ws_sender, &mut ws_receiver, &mut watchdog).await {
+                                    heartbeat.on_inbound(tokio::time::Instant::now());
+                                    continue;
+                                }
+                                break;
+                            }
+                            WsMessage::Ping(_) => {
+                                // Pong 由 tungstenite 自动排队，这里立即发送出去
+                                tracing::trace!(virtual_time = %get_mock_now(), "接收到 Ping，回复 Pong");
+                                if let Err(e) = ws_sender.flush().await {
+                                    tracing::error!(virtual_time = %get_mock_now(), "Pong 发送失败: {}", e);
+                                }
+                            }
+                            WsMessage::Pong(_) => {
+                                tracing::trace!(virtual_time = %get_mock_now(), "接收到 Pong");
+                            }
+                            // 分片的消息由 tungstenite 重新组装后才返回，接收时不会出现单独的帧
+                            _ => {
+                                if let Some(digest) = throttle::allow("ws.non_text") {
+                                    tracing::warn!(virtual_time = %get_mock_now(), "接收到非文本消息: {:?}，自动忽略{}", message, digest);
+                                }
+                            }
+                        }
+                    }
+                    Some(Err(WsError::Capacity(CapacityError::MessageTooLong { size, .. }))) => {
+                        // 协议层无法跳过过大的消息，拒绝后断开连接
+                        reject_oversized(pile, size);
+                        flush_outbound(pile, &mut ws_sender).await;
+                        if failover(pile, &mut ws_sender, &mut ws_receiver, &mut watchdog).await {
+                            heartbeat.on_inbound(tokio::time::Instant::now());
+                            continue;
+                        }
+                        break;
+                    }
+                    Some(Err(e)) => {
+                        tracing::error!(virtual_time = %get_mock_now(), "WebSocket 接收消息失败: {}", e);
+                        if failover(pile, &mut ws_sender, &mut ws_receiver, &mut watchdog).await {
+                            heartbeat.on_inbound(tokio::time::Instant::now());
+                            continue;
+                        }
+                        break;
+                    }
+                    None => {
+                        tracing::info!(virtual_time = %get_mock_now(), "WebSocket 连接已关闭");
+                        if failover(pile, &mut ws_sender, &mut ws_receiver, &mut watchdog).await {
+                            heartbeat.on_inbound(tokio::time::Instant::now());
+                            continue;
+                        }
+                        break;
+                    }
+                }
+            }
+            // 虚拟时钟暂停时不更新状态、不完成充电，继续后重新设置计时器
+            _update = wait_opt_ticker(&mut update_tiker), if !time::is_paused() => {
+                try_update_charge(pile, &mut update_tiker, &mut complete_tikers).await;
+            }
+            id = wait_complete_tickers(&mut complete_tikers), if !time::is_paused() => {
+                try_complete_charge(pile, id, &mut update_tiker, &mut complete_tikers).await;
+            }
+            _idle = wait_deadline(watchdog.deadline()) => {
+                match watchdog.expire(tokio::time::Instant::now()) {
+                    WatchdogAction::Probe => {
+                        pile.metrics.set_idle(true);
+                        tracing::warn!(virtual_time = %get_mock_now(), "注册后 {} 秒内未收到服务器消息，发送探测消息", CONF.websocket.idle_after_register_s);
+                        if let Err(e) = ws_sender.send(WsMessage::Ping(Vec::new().into())).await {
+                            tracing::error!(virtual_time = %get_mock_now(), "探测消息发送失败: {}", e);
+                        }
+                    }
+                    WatchdogAction::Warn => {
+                        pile.metrics.set_idle(true);
+                        tracing::warn!(virtual_time = %get_mock_now(), "注册后 {} 秒内未收到服务器消息", CONF.websocket.idle_after_register_s);
+                    }
+                    WatchdogAction::Teardown => {
+                        tracing::error!(virtual_time = %get_mock_now(), "服务器长时间无响应，断开连接");
+                        pile.metrics.record_idle_teardown();
+                        ws_sender.close().await.ok();
+                        if failover(pile, &mut ws_sender, &mut ws_receiver, &mut watchdog).await {
+                            heartbeat.on_inbound(tokio::time::Instant::now());
+                            continue;
+                        }
+                        break;
+                    }
+                }
+            }
+            _handshake = wait_deadline(pile.handshake.lock().unwrap().deadline()) => {
+                tracing::warn!(
+                    virtual_time = %get_mock_now(),
+                    "注册后 {} 秒内未收到服务器的注册确认，按未确认继续运行",
+                    CONF.websocket.register_ack_timeout_s
+                );
+                process_deferred(pile, &mut update_tiker, &mut complete_tikers).await;
+            }
+            _skip = wait_deadline(pile.idle_skip.lock().unwrap().deadline()) => {
+                request_idle_skip(pile);
+            }
+            _lost = pile.connection_lost.notified() => {
+                ws_sender.close().await.ok();
+                if failover(pile, &mut ws_sender, &mut ws_receiver, &mut watchdog).await {
+                    heartbeat.on_inbound(tokio::time::Instant::now());
+                    continue;
+                }
+                break;
+            }
+            _changed = runtime_rx.changed() => {
+                let values = *runtime_rx.borrow_and_update();
+                follow_clock_offset(pile, values.clock_offset_ms).await;
+                apply_runtime_change(pile, values, &mut update_tiker, &mut complete_tikers).await;
+                if trace_tiker.is_some() {
+                    set_ticker(&mut trace_tiker, trace::sample_period(CONF.trace.sample_interval_s, values.speed));
+                }
+            }
+            _trace = wait_opt_ticker(&mut trace_tiker), if !time::is_paused() => {
+                sample_power(pile, &mut power_trace).await;
+            }
+            _maintenance = wait_opt_ticker(&mut maintenance_tiker), if !time::is_paused() => {
+                check_maintenance(pile, &mut maintenance_phase, &mut update_tiker, &mut complete_tikers, &mut maintenance_tiker).await;
+            }
+            _heartbeat = wait_opt_ticker(&mut heartbeat_tiker) => {
+                if heartbeat.tick(tokio::time::Instant::now()) == HeartbeatAction::Dead {
+                    tracing::error!(
+                        virtual_time = %get_mock_now(),
+                        "连续 {} 个心跳间隔没有收到任何入站消息，认为连接已断开",
+                        CONF.websocket.heartbeat_max_missed
+                    );
+                    ws_sender.close().await.ok();
+                    if failover(pile, &mut ws_sender, &mut ws_receiver, &mut watchdog).await {
+                        heartbeat.on_inbound(tokio::time::Instant::now());
+                        continue;
+                    }
+                    break;
+                }
+                send_heartbeat(pile, &mut ws_sender).await;
+            }
+            _resend = wait_opt_ticker(&mut resend_tiker) => {
+                resend_unacked(pile, false);
+            }
+            _outbound = pile.outbound_ready.notified() => {
+                flush_outbound(pile, &mut ws_sender).await;
+            }
+            _release = wait_deadline(next_release(pile)) => {
+                flush_outbound(pile, &mut ws_sender).await;
+            }
+            _shutdown = wait_shutdown_signal(&mut terminate_signal) => {
+                tracing::warn!(virtual_time = %get_mock_now(), "接收到退出信号，正在关闭充电桩，再次按 Ctrl+C 强制退出");
+                tokio::spawn(async {
+                    if tokio::signal::ctrl_c().await.is_ok() {
+                        tracing::error!("再次接收到 Ctrl+C，强制退出");
+                        std::process::exit(130);
+                    }
+                });
+                shutdown(pile, &mut ws_sender, &mut update_tiker, &mut complete_tikers).await;
+                break;
+            }
+            _reload = wait_reload_signal(&mut reload_signal) => {
+                tracing::info!(virtual_time = %get_mock_now(), "接收到配置重载信号");
+                reload_conf(pile, &mut applied, &mut ws_sender, &mut ws_receiver, &mut watchdog).await;
+                // 迁移连接后重新计算没有入站消息的时间
+                heartbeat.on_inbound(tokio::time::Instant::now());
+            }
+            Some(command) = wait_key_command(&mut key_rx) => {
+                match command {
+                    // 与 Ctrl+C 走同一条退出路径，所有充电桩都会退出
+                    KeyCommand::Quit => request_shutdown(),
+                    KeyCommand::Breakdown => {
+                        tracing::info!(virtual_time = %get_mock_now(), "接收到充电桩损坏信号");
+                        try_breakdown_charge(pile, FaultSource::Manual, &mut update_tiker, &mut complete_tikers).await;
+                        if CONF.charge.exit_on_breakdown {
+                            flush_outbound(pile, &mut ws_sender).await;
+                            ws_sender.close().await.ok();
+                            break;
+                        }
+                    }
+                    _ => handle_key_command(pile, command, &mut update_tiker, &mut complete_tikers).await,
+                }
+            }
+        }
+    }
+    report_traffic(pile);
+    report_outbound(pile);
+    report_eta_errors(pile).await;
+}
+
+/// 输出出站队列中没有发送的消息数和丢弃的状态更新数
+fn report_outbound(pile: &Pile) {
+    let outbound = pile.outbound.lock().unwrap();
+    if !outbound.is_empty() {
+        tracing::warn!("连接断开时出站队列中还有 {} 条消息没有发送", outbound.len());
+    }
+    if outbound.dropped() > 0 {
+        tracing::warn!("出站队列满时共丢弃了 {} 条状态更新", outbound.dropped());
+    }
+}
+
+/// 等待键盘命令，没有命令来源时立即返回 `None`
+async fn wait_key_command(
+    rx: &mut Option<mpsc::UnboundedReceiver<KeyCommand>>,
+) -> Option<KeyCommand> {
+    match rx {
+        Some(rx) => rx.recv().await,
+        None => None,
+    }
+}
+
+/// 协议层允许的消息大小是 `websocket.max_message_size` 的倍数
+/// 不超过这个大小的消息完整接收后再检查，拒绝后连接继续可用；更大的消息无法跳过，只能断开连接
+const PROTOCOL_SIZE_FACTOR: usize = 4;
+
+/// 建立 WebSocket 连接使用的协议配置，只设置消息和帧的大小上限
+pub fn ws_config(conf: &Conf) -> WebSocketConfig {
+    let limit = conf
+        .websocket
+        .max_message_size
+        .max(1)
+        .saturating_mul(PROTOCOL_SIZE_FACTOR);
+    WebSocketConfig::default()
+        .max_message_size(Some(limit))
+        .max_frame_size(Some(limit))
+}
+
+/// 连接 WebSocket 服务器，握手时附加 `websocket.headers` 和认证令牌
+async fn connect(url: &str) -> Result<(Outlet, Inlet), String> {
+    let mut request = url
+        .into_client_request()
+        .map_err(|e| format!("WebSocket 地址无效: {}", e))?;
+    for (name, value) in CONF.websocket.handshake_headers() {
+        let header = HeaderName::from_bytes(name.as_bytes())
+            .map_err(|e| format!("请求头名称 {} 无效: {}", name, e))?;
+        let value = HeaderValue::from_str(&value)
+            .map_err(|e| format!("请求头 {} 的值无效: {}", name, e))?;
+        request.headers_mut().insert(header, value);
+    }
+    let connector = TLS_CONNECTOR.get().cloned().flatten();
+    let connecting =
+        connect_async_tls_with_config(request, Some(ws_config(&CONF)), false, connector);
+    match timeout(Duration::from_secs(10), connecting).await {
+        Ok(Ok((ws_stream, _))) => {
+            tracing::info!("WebSocket 连接成功: {}", url);
+            let (sink, stream) = ws_stream.split();
+            Ok((Outlet::Socket(sink), Inlet::Socket(stream)))
+        }
+        Ok(Err(tokio_tungstenite::tungstenite::Error::Http(response)))
+            if matches!(response.status().as_u16(), 401 | 403) =>
+        {
+            AUTH_FAILED.store(true, Ordering::Release);
+            Err(format!(
+                "WebSocket 认证失败: 服务器返回 {}",
+                response.status()
+            ))
+        }
+        Ok(Err(e)) => Err(format!("WebSocket 连接失败: {}", e)),
+        Err(_) => Err("WebSocket 连接超时".to_string()),
+    }
+}
+
+/// 按故障切换方式依次连接各个地址，都失败时返回最后一个错误，认证失败时不再尝试其他地址
+async fn connect_endpoints(pile: &Pile) -> Result<(Outlet, Inlet), String> {
+    let (attempts, fallback) = {
+        let endpoints = pile.endpoints.lock().unwrap();
+        (endpoints.attempts(), endpoints.has_fallback())
+    };
+    let mut last_error = "没有配置 WebSocket 地址".to_string();
+    for (index, url) in attempts {
+        match connect(&url).await {
+            Ok(connection) => {
+                pile.endpoints.lock().unwrap().connected(index);
+                return Ok(connection);
+            }
+            Err(e) if is_auth_failed() => return Err(e),
+            Err(e) => {
+                if fallback {
+                    tracing::warn!(virtual_time = %get_mock_now(), "无法连接 {}，尝试下一个地址: {}", url, e);
+                }
+                last_error = e;
+            }
+        }
+    }
+    Err(last_error)
+}
+
+/// 连接断开后切换到其他地址，与迁移连接相同，充电会话不受影响
+/// 独立运行、只配置了一个地址或认证失败时不切换，所有地址都无法连接时返回 `false`
+async fn failover(
+    pile: &Pile,
+    ws_sender: &mut Outlet,
+    ws_receiver: &mut Inlet,
+    watchdog: &mut IdleWatchdog,
+) -> bool {
+    if !CONF.websocket.enabled || !pile.endpoints.lock().unwrap().has_fallback() || is_auth_failed()
+    {
+        return false;
+    }
+    tracing::warn!(virtual_time = %get_mock_now(), "WebSocket 连接断开，尝试切换到其他地址");
+    match connect_endpoints(pile).await {
+        Ok((new_sender, new_receiver)) => {
+            pile.metrics.record_reconnect();
+            pile.metrics.set_failed_over(true);
+            report_compat();
+            adopt_connection(
+                pile,
+                new_sender,
+                new_receiver,
+                ws_sender,
+                ws_receiver,
+                watchdog,
+            )
+            .await;
+            true
+        }
+        Err(e) => {
+            tracing::error!(virtual_time = %get_mock_now(), "所有 WebSocket 地址都无法连接: {}", e);
+            false
+        }
+    }
+}
+
+#[cfg(unix)]
+/// 监听 SIGHUP 信号用于重载配置
+fn reload_signal() -> Option<ReloadSignal> {
+    use tokio::signal::unix::{SignalKind, signal};
+    match signal(SignalKind::hangup()) {
+        Ok(signal) => {
+            tracing::info!("发送 SIGHUP 信号可以重新加载配置文件");
+            Some(signal)
+        }
+        Err(e) => {
+            tracing::warn!("无法监听 SIGHUP 信号，配置热重载不可用: {}", e);
+            None
+        }
+    }
+}
+
+#[cfg(not(unix))]
+/// 非 Unix 平台不支持配置重载信号
+fn reload_signal() -> Option<ReloadSignal> {
+    None
+}
+
+/// 等待配置重载信号
+async fn wait_reload_signal(signal: &mut Option<ReloadSignal>) {
+    #[cfg(unix)]
+    if let Some(signal) = signal
+        && signal.recv().await.is_some()
+    {
+        return;
+    }
+    #[cfg(not(unix))]
+    let _ = signal;
+    futures_util::future::pending::<()>().await;
+}
+
+#[cfg(unix)]
+/// 监听 SIGTERM 信号用于正常退出
+fn terminate_signal() -> Option<ReloadSignal> {
+    use tokio::signal::unix::{SignalKind, signal};
+    signal(SignalKind::terminate())
+        .inspect_err(|e| tracing::warn!("无法监听 SIGTERM 信号: {}", e))
+        .ok()
+}
+
+#[cfg(not(unix))]
+/// 非 Unix 平台只监听 Ctrl+C
+fn terminate_signal() -> Option<ReloadSignal> {
+    None
+}
+
+/// 等待 Ctrl+C 或 SIGTERM 信号
+async fn wait_shutdown_signal(terminate: &mut Option<ReloadSignal>) {
+    tokio::select! {
+        result = tokio::signal::ctrl_c() => {
+            if result.is_err() {
+                // 无法监听 Ctrl+C 时只等待 SIGTERM
+                wait_reload_signal(terminate).await;
+            }
+        }
+        _ = wait_reload_signal(terminate) => {}
+        _ = wait_shutdown_requested() => {}
+    }
+}
+
+/// 请求所有充电桩正常退出
+fn request_shutdown() {
+    SHUTDOWN_REQUESTED.send_replace(true);
+}
+
+/// 等待键盘请求退出
+async fn wait_shutdown_requested() {
+    let mut requested = SHUTDOWN_REQUESTED.subscribe();
+    let _ = requested.wait_for(|requested| *requested).await;
+}
+
+/// 正常退出：中断当前详单并发送最后一次状态更新，然后关闭 WebSocket 连接
+async fn shutdown(
+    pile: &Pile,
+    ws_sender: &mut Outlet,
+    update_ticker: &mut Option<Interval>,
+    complete_tickers: &mut CompleteTickers,
+) {
+    let mut charge = pile.charge.lock().await;
+    for detail in charge.close() {
+        tracing::info!(virtual_time = %get_mock_now(), "充电桩退出，当前被打断的充电详单: {}", detail.get_id());
+        send_update(pile, &detail);
+    }
+    drop(charge);
+    remove_ticker(update_ticker);
+    complete_tickers.clear();
+    pile.outbound.lock().unwrap().release_all();
+    flush_outbound(pile, ws_sender).await;
+    let close = CloseFrame {
+        code: CloseCode::Away,
+        reason: "shutdown".into(),
+    };
+    if let Err(e) = ws_sender.send(WsMessage::Close(Some(close))).await {
+        tracing::warn!("关闭消息发送失败: {}", e);
+    }
+}
+
+/// 重新加载配置文件，只应用允许在运行时修改的字段
+async fn reload_conf(
+    pile: &Pile,
+    applied: &mut Conf,
+    ws_sender: &mut Outlet,
+    ws_receiver: &mut Inlet,
+    watchdog: &mut IdleWatchdog,
+) {
+    let new = match Conf::load(conf::overrides()) {
+        Ok(conf) => conf,
+        Err(e) => {
+            tracing::error!("配置重载失败，保持当前配置: {}", e);
+            return;
+        }
+    };
+    let session_active = pile.charge.lock().await.is_working();
+    let plan = reload::plan(applied, &new, session_active);
+    // 多个充电桩时价格表和运行时配置只由第一个充电桩修改
+    let primary = pile.index == 0;
+    if !plan.ignored.is_empty() {
+        tracing::warn!("配置重载时忽略以下字段: {}", plan.ignored.join(", "));
+    }
+    if plan.is_empty() {
+        tracing::info!("配置文件没有可以应用的修改");
+        return;
+    }
+    if let Some(path) = plan.price_path {
+        if !primary {
+            applied.price.path = path;
+        } else {
+            match price::reload_prices(&path) {
+                Ok(()) => applied.price.path = path,
+                Err(e) => tracing::error!("价格表重载失败，保留原价格表: {}", e),
+            }
+        }
+    }
+    if let Some(speed) = plan.speed
+        && (!primary || RUNTIME.set_speed(speed).is_ok())
+    {
+        applied.time.speed = speed;
+    }
+    if let Some(update_interval) = plan.update_interval
+        && (!primary || RUNTIME.set_update_interval(update_interval).is_ok())
+    {
+        applied.time.update_interval = update_interval;
+    }
+    if let Some(queue_size) = plan.queue_size
+        && RUNTIME.set_queue_size(queue_size).is_ok()
+    {
+        applied.charge.size = queue_size;
+    }
+    let mut reregister = false;
+    if let Some(power) = plan.power {
+        match pile.charge.lock().await.set_power(power) {
+            Ok(()) => {
+                tracing::info!("充电功率修改: {} -> {} kW", applied.charge.power, power);
+                applied.charge.power = power;
+                reregister = true;
+            }
+            Err(e) => tracing::warn!("充电功率修改失败: {}", e),
+        }
+    }
+    if let Some(urls) = plan.websocket_urls {
+        match migrate_connection(pile, urls, ws_sender, ws_receiver, watchdog).await {
+            Ok(()) => {
+                applied.websocket.url = new.websocket.url.clone();
+                applied.websocket.urls = new.websocket.urls.clone();
+                reregister = false;
+            }
+            Err(e) => tracing::error!("WebSocket 连接迁移失败，保持当前连接: {}", e),
+        }
+    }
+    if reregister {
+        register(pile).await;
+    }
+}
+
+/// 迁移到新的 WebSocket 地址列表中的第一个地址
+/// 先连接新地址，成功后关闭旧连接并重新注册，充电会话不受影响
+/// 注册消息在新连接上最先发送，之后是旧连接上没有发送出去的消息
+async fn migrate_connection(
+    pile: &Pile,
+    urls: Vec<String>,
+    ws_sender: &mut Outlet,
+    ws_receiver: &mut Inlet,
+    watchdog: &mut IdleWatchdog,
+) -> Result<(), String> {
+    let url = urls.first().ok_or("no WebSocket url configured")?;
+    tracing::info!(virtual_time = %get_mock_now(), "WebSocket 地址已修改，迁移连接到 {}", url);
+    let (new_sender, new_receiver) = connect(url).await?;
+    let mut endpoints = Endpoints::new(urls, CONF.websocket.failover);
+    endpoints.connected(0);
+    *pile.endpoints.lock().unwrap() = endpoints;
+    // 发送完出站队列中的消息后关闭旧连接
+    flush_outbound(pile, ws_sender).await;
+    ws_sender.flush().await.ok();
+    let close = CloseFrame {
+        code: CloseCode::Normal,
+        reason: "reconfiguring".into(),
+    };
+    if let Err(e) = ws_sender.send(WsMessage::Close(Some(close))).await {
+        tracing::warn!("旧连接关闭消息发送失败: {}", e);
+    }
+    report_compat();
+    pile.metrics.record_reconnect();
+    pile.metrics.set_failed_over(false);
+    adopt_connection(
+        pile,
+        new_sender,
+        new_receiver,
+        ws_sender,
+        ws_receiver,
+        watchdog,
+    )
+    .await;
+    Ok(())
+}
+
+/// 换用新的连接并重新注册，再重新发送没有得到确认的消息和正在充电的详单的完整快照
+async fn adopt_connection(
+    pile: &Pile,
+    new_sender: Outlet,
+    new_receiver: Inlet,
+    ws_sender: &mut Outlet,
+    ws_receiver: &mut Inlet,
+    watchdog: &mut IdleWatchdog,
+) {
+    report_traffic(pile);
+    *ws_sender = new_sender;
+    *ws_receiver = new_receiver;
+    let reg_msg = stamp(pile, register_msg(pile).await);
+    if let Err(e) = send_stamped(pile, ws_sender, &reg_msg).await {
+        pile.metrics.record_send_failure();
+        tracing::error!("充电桩注册消息发送失败: {}", e);
+    }
+    watchdog.arm(tokio::time::Instant::now());
+    pile.metrics.set_idle(false);
+    pile.handshake
+        .lock()
+        .unwrap()
+        .start(tokio::time::Instant::now());
+    // 旧连接上没有得到确认的消息在新连接上重新发送
+    resend_unacked(pile, true);
+    let charge = pile.charge.lock().await;
+    for detail in charge.get_charging_details() {
+        // 新连接上先发送一次完整快照
+        send_update(pile, detail);
+    }
+    drop(charge);
+    flush_outbound(pile, ws_sender).await;
+}
+
+/// 打开功率记录文件，没有配置或无法打开时不记录
+fn open_power_trace(index: usize) -> Option<PowerTrace> {
+    let path = CONF.trace.power_path_for(index)?;
+    match PowerTrace::open(std::path::Path::new(&path)) {
+        Ok(trace) => {
+            tracing::info!(
+                "功率记录写入 {}，采样间隔: {} 秒",
+                path,
+                CONF.trace.sample_interval_s
+            );
+            Some(trace)
+        }
+        Err(e) => {
+            tracing::error!("无法打开功率记录文件 {}: {}", path, e);
+            None
+        }
+    }
+}
+
+/// 多个充电桩运行结束后把各自的功率记录合并为整个充电站的功率，没有配置功率记录时不合并
+fn merge_power_traces(indices: &[usize]) {
+    let Some(out) = CONF.trace.station_path() else {
+        return;
+    };
+    let paths: Vec<String> = indices
+        .iter()
+        .filter_map(|&index| CONF.trace.power_path_for(index))
+        .collect();
+    match trace::merge_files(
+        &paths,
+        CONF.trace.sample_interval_s,
+        std::path::Path::new(&out),
+    ) {
+        Ok(samples) => tracing::info!(
+            "已合并 {} 个充电桩的功率记录，共 {} 个采样，写入 {}",
+            paths.len(),
+            samples,
+            out
+        ),
+        Err(e) => tracing::error!("合并功率记录失败: {}", e),
+    }
+}
+
+/// 记录一次功率采样
+async fn sample_power(pile: &Pile, power_trace: &mut Option<PowerTrace>) {
+    let Some(power_trace) = power_trace else {
+        return;
+    };
+    let sample = PowerSample::of(&*pile.charge.lock().await, get_mock_now());
+    if let Err(e) = power_trace.record(&sample)
+        && let Some(digest) = throttle::allow("trace.record")
+    {
+        tracing::warn!(virtual_time = %get_mock_now(), "写入功率记录失败: {}{}", e, digest);
+    }
+}
+
+/// 输出本次运行的会话时长预测误差和排队等待时长统计
+async fn report_eta_errors(pile: &Pile) {
+    let charge = pile.charge.lock().await;
+    match charge.get_eta_error_stats().summary() {
+        Some(summary) => tracing::info!(
+            eta_error = ?summary,
+            "会话时长预测误差（实际 - 预计，虚拟秒）: 样本数 {}，平均 {:.1}，P50 {:.1}，P90 {:.1}，P99 {:.1}，最大 {:.1}",
+            summary.count,
+            summary.mean,
+            summary.p50,
+            summary.p90,
+            summary.p99,
+            summary.max
+        ),
+        None => tracing::info!("本次运行没有完成的会话，无预测误差统计"),
+    }
+    if let Some(summary) = charge.get_wait_time_stats().summary() {
+        tracing::info!(
+            wait_time = ?summary,
+            "排队等待时长（虚拟秒）: 样本数 {}，平均 {:.1}，P50 {:.1}，P90 {:.1}，P99 {:.1}，最大 {:.1}",
+            summary.count,
+            summary.mean,
+            summary.p50,
+            summary.p90,
+            summary.p99,
+            summary.max
+        );
+    }
+}
+
+/// 输出本次运行中被限流省略的警告条数
+fn report_suppressed_warnings() {
+    for (key, suppressed) in throttle::THROTTLE.suppressed_totals() {
+        tracing::info!(key, suppressed, "警告 {} 累计被省略 {} 条", key, suppressed);
+    }
+}
+
+/// 运行时配置变更后重新设置计时器和队列大小
+async fn apply_runtime_change(
+    pile: &Pile,
+    values: RuntimeValues,
+    update_ticker: &mut Option<Interval>,
+    complete_tickers: &mut CompleteTickers,
+) {
+    tracing::debug!(virtual_time = %get_mock_now(), "运行时配置变更: {:?}", values);
+    let mut charge = pile.charge.lock().await;
+    // 多个充电桩时各自使用配置中的队列大小
+    if !CONF.charge.is_multi_pile() {
+        charge.set_size(values.queue_size);
+    }
+    // 充电桩或详单没有指定更新间隔时跟随全局更新间隔
+    charge.refresh_update_interval();
+    if update_ticker.is_some() {
+        set_ticker(update_ticker, update_period(&charge));
+    }
+    if !complete_tickers.is_empty() {
+        // 加速倍数变化后重新计算更新周期和完成时间
+        for id in charge.active_detail_ids() {
+            set_complete_ticker(complete_tickers, &charge, id);
+        }
+    }
+}
+
+/// 等待一个可选的计时器，如果计时器存在，则等待其 tick，否则等待直到有新的事件发生。
+async fn wait_opt_ticker(ticker: &mut Option<Interval>) {
+    if let Some(t) = ticker {
+        t.tick().await;
+    } else {
+        futures_util::future::pending::<()>().await;
+    }
+}
+
+/// 每个正在充电的详单的完成计时器，按详单 ID 索引
+type CompleteTickers = HashMap<u32, Interval>;
+
+/// 等待最早触发的完成计时器，返回对应的详单 ID，没有完成计时器时一直等待
+async fn wait_complete_tickers(tickers: &mut CompleteTickers) -> u32 {
+    std::future::poll_fn(|cx| {
+        tickers
+            .iter_mut()
+            .find_map(|(id, ticker)| ticker.poll_tick(cx).is_ready().then_some(*id))
+            .map_or(std::task::Poll::Pending, std::task::Poll::Ready)
+    })
+    .await
+}
+
+/// 充电桩当前的状态更新周期，更新间隔为虚拟毫秒，按加速倍数换算为真实时间
+fn update_period(charge: &Charge) -> Duration {
+    Duration::from_millis(real_update_interval(
+        charge.update_interval(),
+        RUNTIME.speed(),
+    ))
+}
+
+/// 按正在充电的指定详单设置完成计时器
+/// 详单已经超过结束时间时立即触发，而不是设置一个零时长的计时器
+fn set_complete_ticker(tickers: &mut CompleteTickers, charge: &Charge, id: u32) {
+    let millis = charge.complete_interval(id);
+    let ticker = if millis == 0 {
+        tracing::warn!(virtual_time = %get_mock_now(), "正在充电的详单已超过预计结束时间，立即完成充电");
+        interval_at(
+            tokio::time::Instant::now(),
+            Duration::from_millis(MIN_REAL_UPDATE_INTERVAL_MS),
+        )
+    } else {
+        new_ticker(Duration::from_millis(millis))
+    };
+    tickers.insert(id, ticker);
+}
+
+/// 时长为零的计时器立即触发一次后的间隔，足够长，相当于不再触发
+const ONE_SHOT_PERIOD: Duration = Duration::from_secs(365 * 24 * 60 * 60);
+
+/// 设置计时器
+fn set_ticker(ticker: &mut Option<Interval>, duration: Duration) {
+    *ticker = Some(new_ticker(duration));
+}
+
+/// 创建计时器，第一次在一个间隔之后触发
+/// 时长为零时立即触发一次，之后需要重新设置才会再次触发
+fn new_ticker(duration: Duration) -> Interval {
+    if duration.is_zero() {
+        tracing::warn!(virtual_time = %get_mock_now(), "设置的计时器时长为零，立即触发一次");
+        // tokio 的计时器间隔不能为零
+        interval_at(tokio::time::Instant::now(), ONE_SHOT_PERIOD)
+    } else {
+        // 计算第一个 tick 应该发生的时间
+        tracing::debug!(virtual_time = %get_mock_now(), "设置计时器，间隔: {:?}", duration);
+        let first_tick_time = tokio::time::Instant::now() + duration;
+        interval_at(first_tick_time, duration)
+    }
+}
+
+/// 移除计时器
+fn remove_ticker(ticker: &mut Option<Interval>) {
+    *ticker = None;
+}
+
+/// 在单独的线程中读取按键，转换为键盘命令发送到第一个充电桩的主循环
+/// 充电桩退出或无法读取终端事件时结束
+fn spawn_key_reader(tx: mpsc::UnboundedSender<KeyCommand>) {
+    let span = tracing::info_span!("读取键盘命令");
+    task::spawn_blocking(move || {
+        let _enter = span.enter();
+        loop {
+            match event::poll(Duration::from_millis(100)) {
+                Ok(true) => {}
+                Ok(false) => {
+                    // 第一个充电桩退出后不再需要键盘命令
+                    if tx.is_closed() {
+                        break;
+                    }
+                    continue;
+                }
+                Err(e) => {
+                    tracing::warn!("无法读取终端事件，停止读取键盘命令: {}", e);
+                    break;
+                }
+            }
+            let command = match event::read() {
+                // 终端界面使用原始模式，Ctrl+C 不会产生信号
+                Ok(Event::Key(key_event))
+                    if key_event.modifiers.contains(KeyModifiers::CONTROL)
+                        && key_event.code == KeyCode::Char('c') =>
+                {
+                    Some(KeyCommand::Quit)
+                }
+                Ok(Event::Key(key_event)) if key_event.kind == KeyEventKind::Press => {
+                    KeyCommand::from_key(key_event.code)
+                }
+                Ok(_) => None,
+                Err(e) => {
+                    tracing::warn!("无法读取终端事件，停止读取键盘命令: {}", e);
+                    break;
+                }
+            };
+            let Some(command) = command else {
+                continue;
+            };
+            tracing::info!("检测到按键，键盘命令: {:?}", command);
+            // 损坏后退出时不再等待按键
+            let exits = command == KeyCommand::Quit
+                || (command == KeyCommand::Breakdown && CONF.charge.exit_on_breakdown);
+            if tx.send(command).is_err() || exits {
+                break;
+            }
+        }
+    });
+}
+
+/// 执行不需要退出主循环的键盘命令，命令对充电桩当前的状态没有意义时只输出日志
+async fn handle_key_command(
+    pile: &Pile,
+    command: KeyCommand,
+    update_ticker: &mut Option<Interval>,
+    complete_tickers: &mut CompleteTickers,
+) {
+    match command {
+        KeyCommand::Snapshot => {
+            let mut charge = pile.charge.lock().await;
+            if charge.is_working() {
+                charge.update_charging();
+            }
+            let status = charge.status_snapshot(get_mock_now());
+            let snapshot = keys::format_snapshot(&status, time::is_paused(), RUNTIME.speed());
+            if conf::overrides().tui {
+                // 终端界面已经显示状态，快照只写入日志
+                tracing::info!("充电桩状态快照:\n{}", snapshot);
+            } else {
+                eprintln!("{}", snapshot);
+            }
+        }
+        KeyCommand::ForceComplete => {
+            let charge = pile.charge.lock().await;
+            if charge.is_closed() {
+                tracing::info!(virtual_time = %get_mock_now(), "充电桩已关闭，忽略立即完成命令");
+            } else if charge.is_faulted() {
+                tracing::info!(virtual_time = %get_mock_now(), "充电桩处于故障状态，忽略立即完成命令");
+            } else if !charge.is_working() {
+                tracing::info!(virtual_time = %get_mock_now(), "没有正在充电的详单，忽略立即完成命令");
+            } else {
+                let id = charge.active_detail_ids()[0];
+                drop(charge);
+                tracing::info!(virtual_time = %get_mock_now(), "按当前虚拟时间立即完成正在充电的详单 {}", id);
+                try_complete_charge(pile, id, update_ticker, complete_tickers).await;
+            }
+        }
+        KeyCommand::SpeedUp | KeyCommand::SpeedDown => {
+            let speed = keys::bumped_speed(RUNTIME.speed(), command == KeyCommand::SpeedUp);
+            // 修改失败时 RUNTIME 已经输出警告
+            let _ = RUNTIME.set_speed(speed);
+        }
+        KeyCommand::TogglePause => {
+            tracing::info!(
+                "{}虚拟时钟",
+                if time::is_paused() {
+                    "继续"
+                } else {
+                    "暂停"
+                }
+            );
+            if !time::pause() {
+                time::resume();
+            }
+        }
+        KeyCommand::Quit | KeyCommand::Breakdown => {}
+    }
+}
+
+/// 注册充电桩到 WebSocket 服务器
+async fn register(pile: &Pile) {
+    let reg_msg = register_msg(pile).await;
+    send_msg(pile, &reg_msg);
+    pile.handshake
+        .lock()
+        .unwrap()
+        .start(tokio::time::Instant::now());
+}
+
+/// 生成注册消息，`register` 认证方式下附加 `auth_token` 字段
+async fn register_msg(pile: &Pile) -> MSG {
+    let mut payload = pile.charge.lock().await.register_payload(RUNTIME.speed());
+    payload.auth_token = CONF.websocket.register_token().map(str::to_string);
+    payload.url = pile
+        .endpoints
+        .lock()
+        .unwrap()
+        .current_url()
+        .map(str::to_string);
+    MSG::with_payload(MessageType::Register, &payload)
+}
+
+/// 处理接收到的消息
+/// 一个消息帧中可能包含多个 JSON 文档，按顺序逐个处理
+async fn handle(
+    pile: &Pile,
+    message: String,
+    update_ticker: &mut Option<Interval>,
+    complete_tickers: &mut CompleteTickers,
+) {
+    tracing::debug!(virtual_time = %get_mock_now(), "接收到消息: {}", message);
+    handle_parsed(pile, parse_frame(&message), update_ticker, complete_tickers).await;
+}
+
+/// 处理接收到的 MessagePack 编码的二进制消息
+async fn handle_binary(
+    pile: &Pile,
+    bytes: &[u8],
+    update_ticker: &mut Option<Interval>,
+    complete_tickers: &mut CompleteTickers,
+) {
+    tracing::debug!(virtual_time = %get_mock_now(), "接收到二进制消息: {} 字节", bytes.len());
+    handle_parsed(pile, parse_binary(bytes), update_ticker, complete_tickers).await;
+}
+
+/// 入站消息是否不超过 `websocket.max_message_size`，超过时回复拒绝消息
+fn within_size_limit(pile: &Pile, size: usize) -> bool {
+    if size <= CONF.websocket.max_message_size {
+        return true;
+    }
+    reject_oversized(pile, size);
+    false
+}
+
+/// 拒绝过大的入站消息，消息没有被解析，拒绝消息中的详单 ID 为 0
+fn reject_oversized(pile: &Pile, size: usize) {
+    let limit = CONF.websocket.max_message_size;
+    tracing::error!(
+        virtual_time = %get_mock_now(),
+        "接收到的消息大小为 {} 字节，超过上限 {} 字节（websocket.max_message_size），已丢弃",
+        size,
+        limit
+    );
+    send_reject(
+        pile,
+        0,
+        &format!(
+            "message too large: {} bytes exceeds the limit of {} bytes",
+            size, limit
+        ),
+    );
+}
+
+/// 按顺序处理从一个消息帧中解析出的消息，解析失败时回复错误消息
+async fn handle_parsed(
+    pile: &Pile,
+    (messages, error): (Vec<MSG>, Option<ErrorData>),
+    update_ticker: &mut Option<Interval>,
+    complete_tickers: &mut CompleteTickers,
+) {
+    for msg in messages {
+        audit::record(Direction::In, pile.charge_id, &msg);
+        pile.traffic.lock().unwrap().record_received(msg.type_);
+        pile.metrics.record_message_received(msg.type_);
+        handle_msg(pile, msg, update_ticker, complete_tickers).await;
+    }
+    if let Some(error) = error {
+        if let Some(digest) = throttle::allow("handle.parse") {
+            tracing::warn!(
+                virtual_time = %get_mock_now(),
+                "消息解析失败（偏移 {:?}）: {}{}",
+                error.offset,
+                error.reason,
+                digest
+            );
+        }
+        send_error(pile, &error);
+    }
+}
+
+/// 处理单条消息
+async fn handle_msg(
+    pile: &Pile,
+    msg: MSG,
+    update_ticker: &mut Option<Interval>,
+    complete_tickers: &mut CompleteTickers,
+) {
+    match msg.type_ {
+        MessageType::New | MessageType::NewBatch => {
+            if pile.idle_skip.lock().unwrap().cancel() {
+                tracing::info!(virtual_time = %get_mock_now(), "收到新详单，取消跳过空闲时间");
+            }
+            let msg = {
+                let mut handshake = pile.handshake.lock().unwrap();
+                if handshake.is_waiting() {
+                    tracing::debug!(virtual_time = %get_mock_now(), "服务器尚未确认注册，暂存新详单");
+                    handshake.defer(msg);
+                    return;
+                }
+                msg
+            };
+            dispatch_new(pile, msg, update_ticker, complete_tickers).await;
+        }
+        MessageType::RegisterAck => {
+            if let Some(ack) = parse_inbound(pile, msg.data, RegisterAckData::FIELDS) {
+                handle_register_ack(pile, ack, update_ticker, complete_tickers).await;
+            }
+        }
+        MessageType::Ack => handle_msg_ack(pile, msg.data),
+        MessageType::Cancel => {
+            if pile.charge.lock().await.is_closed() {
+                if let Some(digest) = throttle::allow("handle.closed_cancel") {
+                    tracing::warn!(virtual_time = %get_mock_now(), "充电桩已关闭，无法取消充电{}", digest);
+                }
+                return;
+            }
+            handle_cancel(pile, msg.data, update_ticker, complete_tickers).await
+        }
+        MessageType::Close => handle_close(pile, update_ticker, complete_tickers).await,
+        MessageType::Open => handle_open(pile, update_ticker, complete_tickers).await,
+        MessageType::Repair => handle_repair(pile, update_ticker, complete_tickers).await,
+        MessageType::FreeVend => handle_free_vend(pile, msg.data).await,
+        MessageType::ReloadPrices => handle_reload_prices(pile),
+        MessageType::SetSpeed => handle_set_speed(pile, msg.data),
+        MessageType::IdleSkipGrant => handle_idle_skip_grant(pile, msg.data).await,
+        MessageType::Query => handle_query(pile).await,
+        MessageType::AuthError => {
+            let reason = msg
+                .payload::<AuthErrorData>()
+                .map(|data| data.reason)
+                .unwrap_or_else(|_| msg.data.to_string());
+            auth_failed(&reason);
+            pile.connection_lost.notify_one();
+        }
+        MessageType::Pause => handle_pause(true),
+        MessageType::Resume => handle_pause(false),
+        MessageType::Break => {
+            tracing::info!(virtual_time = %get_mock_now(), "接收到服务器模拟损坏请求");
+            try_breakdown_charge(pile, FaultSource::Remote, update_ticker, complete_tickers).await
+        }
+        _ => {
+            if let Some(digest) = throttle::allow("handle.illegal_type") {
+                tracing::warn!(virtual_time = %get_mock_now(), "非法消息类型: {:?}{}", msg.type_, digest);
+            }
+        }
+    }
+}
+
+/// 处理重新加载价格表请求，加载失败时保留原价格表并回复错误消息
+/// 正在充电的详单在下一次状态更新时按新价格表计算
+fn handle_reload_prices(pile: &Pile) {
+    tracing::info!(virtual_time = %get_mock_now(), "接收到重新加载价格表请求");
+    if let Err(e) = price::reload_current_prices() {
+        tracing::error!(virtual_time = %get_mock_now(), "价格表重新加载失败，保留原价格表: {}", e);
+        let error = ErrorData {
+            reason: format!("reload_prices: {}", e),
+            offset: None,
+        };
+        send_error(pile, &error);
+    }
+}
+
+/// 处理修改时间加速比请求
+/// 虚拟时钟以当前时刻为锚点重新计算，各充电桩的计时器在收到运行时配置变更后按新倍数重新设置
+fn handle_set_speed(pile: &Pile, msg: Value) {
+    let data: SetSpeedData = match parse_inbound(pile, msg, SetSpeedData::FIELDS) {
+        Some(d) => d,
+        None => return,
+    };
+    tracing::info!(virtual_time = %get_mock_now(), "接收到修改时间加速比请求: {}", data.speed);
+    if let Err(e) = RUNTIME.set_speed(data.speed) {
+        let error = ErrorData {
+            reason: format!("set_speed: {}", e),
+            offset: None,
+        };
+        send_error(pile, &error);
+    }
+}
+
+/// 启用跳过空闲时间时空闲多久后请求跳过，多个充电桩共用虚拟时钟时不启用
+fn idle_skip_after(conf: &Conf) -> Option<Duration> {
+    (conf.time.skip_idle && !conf.charge.is_multi_pile())
+        .then(|| Duration::from_millis(conf.time.skip_idle_after_ms))
+}
+
+/// 充电桩空闲了足够长的时间，请求服务器跳过空闲时间
+fn request_idle_skip(pile: &Pile) {
+    pile.idle_skip
+        .lock()
+        .unwrap()
+        .expire(tokio::time::Instant::now());
+    let data = IdleSkipRequestData {
+        virtual_time: get_mock_now(),
+        speed: RUNTIME.speed(),
+    };
+    tracing::info!(virtual_time = %data.virtual_time, "充电桩空闲，请求跳过空闲时间");
+    send_msg(
+        pile,
+        &MSG::with_payload(MessageType::IdleSkipRequest, &data),
+    );
+}
+
+/// 处理同意跳过空闲时间请求，把虚拟时钟推进到服务器给出的时间
+/// 没有等待同意的请求（已被新详单取消）或充电桩已不空闲时忽略
+async fn handle_idle_skip_grant(pile: &Pile, msg: Value) {
+    let data: IdleSkipGrantData = match parse_inbound(pile, msg, IdleSkipGrantData::FIELDS) {
+        Some(d) => d,
+        None => return,
+    };
+    if !pile.idle_skip.lock().unwrap().grant() {
+        tracing::info!(virtual_time = %get_mock_now(), "没有等待同意的跳过请求，忽略跳过到 {}", data.until);
+        return;
+    }
+    // 持有队列锁直到跳过完成，期间不会有详单开始充电
+    let charge = pile.charge.lock().await;
+    if !charge.is_idle() {
+        tracing::info!(virtual_time = %get_mock_now(), "充电桩已不空闲，取消跳过空闲时间");
+        return;
+    }
+    if RUNTIME.skip_to(data.until).is_none() {
+        tracing::info!(virtual_time = %get_mock_now(), "跳过的目标时间 {} 不晚于当前虚拟时间，忽略", data.until);
+    }
+}
+
+/// 处理服务器的注册确认，检查协议版本后处理暂存的新详单
+/// 版本不一致且配置了 `abort_on_version_mismatch` 时断开连接
+async fn handle_register_ack(
+    pile: &Pile,
+    ack: RegisterAckData,
+    update_ticker: &mut Option<Interval>,
+    complete_tickers: &mut CompleteTickers,
+) {
+    tracing::info!(
+        virtual_time = %get_mock_now(),
+        "服务器已确认注册，协议版本: {}，服务器版本: {}",
+        ack.protocol_version,
+        ack.server_version.as_deref().unwrap_or("未知")
+    );
+    if let Err(e) = handshake::check_version(&ack) {
+        tracing::error!(virtual_time = %get_mock_now(), "!!! 协议版本不一致 !!! {}", e);
+        if CONF.websocket.abort_on_version_mismatch {
+            tracing::error!(virtual_time = %get_mock_now(), "协议版本不一致，断开连接");
+            pile.connection_lost.notify_one();
+            return;
+        }
+    }
+    select_encoding(pile, ack.encoding);
+    if let Some(server_time) = ack.server_time
+        && CONF.time.server_sync
+        && RUNTIME
+            .sync_clock(server_time, CONF.time.max_sync_offset_s)
+            .is_ok()
+    {
+        follow_clock_offset(pile, RUNTIME.values().clock_offset_ms).await;
+    }
+    let missing = handshake::missing_features(&ack);
+    if !missing.is_empty() {
+        tracing::warn!(virtual_time = %get_mock_now(), "服务器不支持以下功能: {}", missing.join(", "));
+    }
+    process_deferred(pile, update_ticker, complete_tickers).await;
+}
+
+/// 虚拟时钟按服务器时间调整后，把还没有平移的调整量平移到队列中的详单，
+/// 正在充电的详单不会因为时钟跳变多算或倒扣电量和费用
+async fn follow_clock_offset(pile: &Pile, clock_offset_ms: i64) {
+    let previous = pile.clock_offset_ms.swap(clock_offset_ms, Ordering::AcqRel);
+    if previous == clock_offset_ms {
+        return;
+    }
+    let offset = chrono::Duration::milliseconds(clock_offset_ms - previous);
+    let mut charge = pile.charge.lock().await;
+    charge.shift_times(offset);
+    tracing::info!(
+        virtual_time = %get_mock_now(),
+        "虚拟时钟调整了 {} 毫秒，队列中 {} 个详单的时间同样平移",
+        offset.num_milliseconds(),
+        charge.get_queue_size()
+    );
+}
+
+/// 按服务器在注册确认中同意的编码方式发送之后的消息
+fn select_encoding(pile: &Pile, accepted: WireEncoding) {
+    let requested = CONF.websocket.encoding;
+    let encoding = match (requested, accepted) {
+        (WireEncoding::Msgpack, WireEncoding::Msgpack) => {
+            tracing::info!(virtual_time = %get_mock_now(), "服务器同意使用 MessagePack 编码");
+            WireEncoding::Msgpack
+        }
+        (WireEncoding::Msgpack, WireEncoding::Json) => {
+            tracing::info!(virtual_time = %get_mock_now(), "服务器只支持 JSON 编码，继续使用 JSON");
+            WireEncoding::Json
+        }
+        (WireEncoding::Json, WireEncoding::Msgpack) => {
+            tracing::warn!(virtual_time = %get_mock_now(), "服务器确认了没有请求的 MessagePack 编码，继续使用 JSON");
+            WireEncoding::Json
+        }
+        (WireEncoding::Json, WireEncoding::Json) => WireEncoding::Json,
+    };
+    *pile.encoding.lock().unwrap() = encoding;
+}
+
+/// 结束注册握手，按收到的顺序处理暂存的新详单
+async fn process_deferred(
+    pile: &Pile,
+    update_ticker: &mut Option<Interval>,
+    complete_tickers: &mut CompleteTickers,
+) {
+    let deferred = pile.handshake.lock().unwrap().finish();
+    for msg in deferred {
+        dispatch_new(pile, msg, update_ticker, complete_tickers).await;
+    }
+}
+
+/// 按消息类型处理单个或批量新详单消息
+async fn dispatch_new(
+    pile: &Pile,
+    msg: MSG,
+    update_ticker: &mut Option<Interval>,
+    complete_tickers: &mut CompleteTickers,
+) {
+    if msg.type_ == MessageType::NewBatch {
+        handle_new_batch(pile, msg.data, msg.msg_id, update_ticker, complete_tickers).await;
+    } else {
+        handle_new(pile, msg.data, msg.msg_id, update_ticker, complete_tickers).await;
+    }
+}
+
+/// 处理服务器的确认消息，从发件箱中移除被确认的消息
+fn handle_msg_ack(pile: &Pile, msg: Value) {
+    let data: MsgAckData = match parse_inbound(pile, msg, MsgAckData::FIELDS) {
+        Some(d) => d,
+        None => return,
+    };
+    if pile.outbox.lock().unwrap().ack(data.msg_id) {
+        tracing::debug!(virtual_time = %get_mock_now(), "消息 {} 已被服务器确认", data.msg_id);
+    } else if let Some(digest) = throttle::allow("handle.unknown_ack") {
+        tracing::warn!(virtual_time = %get_mock_now(), "确认的消息 {} 不在等待确认的消息中，自动忽略{}", data.msg_id, digest);
+    }
+}
+
+/// 发送一次心跳，按配置发送 WebSocket Ping 或 `heartbeat` 消息
+async fn send_heartbeat(pile: &Pile, ws_sender: &mut Outlet) {
+    match CONF.websocket.heartbeat_mode {
+        HeartbeatMode::Ping => {
+            if let Err(e) = ws_sender.send(WsMessage::Ping(Vec::new().into())).await {
+                tracing::error!(virtual_time = %get_mock_now(), "心跳发送失败: {}", e);
+            }
+        }
+        HeartbeatMode::Message => {
+            let data = {
+                let charge = pile.charge.lock().await;
+                HeartbeatData {
+                    charge_id: charge.get_id(),
+                    working: charge.is_working(),
+                    state: charge.get_state(),
+                    queue_len: charge.get_queue_size(),
+                    virtual_time: get_mock_now(),
+                }
+            };
+            send_msg(pile, &MSG::with_payload(MessageType::Heartbeat, &data));
+        }
+    }
+}
+
+/// 处理状态查询请求，回复状态快照
+async fn handle_query(pile: &Pile) {
+    tracing::info!(virtual_time = %get_mock_now(), "接收到状态查询请求");
+    send_status(pile).await;
+}
+
+/// 发送充电桩状态快照，正在充电时先把详单更新到当前时刻
+async fn send_status(pile: &Pile) {
+    let status = {
+        let mut charge = pile.charge.lock().await;
+        if charge.is_working() {
+            charge.update_charging();
+        }
+        charge.status_snapshot(get_mock_now())
+    };
+    let status = StatusData {
+        traffic: Some(pile.traffic.lock().unwrap().snapshot()),
+        ..status
+    };
+    let status_msg = MSG::with_payload(MessageType::Status, &status);
+    send_msg(pile, &status_msg);
+}
+
+/// 服务器是否以认证失败关闭连接
+fn is_auth_failure(code: CloseCode) -> bool {
+    code == CloseCode::Policy || u16::from(code) == AUTH_FAILED_CLOSE_CODE
+}
+
+/// 记录认证失败，连接断开后不再继续运行
+/// 服务器通常先发送 `auth_error` 消息再关闭连接，只记录第一次
+fn auth_failed(reason: &str) {
+    if !AUTH_FAILED.swap(true, Ordering::AcqRel) {
+        tracing::error!(virtual_time = %get_mock_now(), "服务器拒绝认证: {}", reason);
+    }
+}
+
+/// 处理暂停或继续虚拟时钟请求，计时器在收到运行时配置变更后暂停或重新设置
+fn handle_pause(paused: bool) {
+    tracing::info!(virtual_time = %get_mock_now(), "接收到{}虚拟时钟请求", if paused { "暂停" } else { "继续" });
+    let changed = if paused {
+        time::pause()
+    } else {
+        time::resume()
+    };
+    if !changed && let Some(digest) = throttle::allow("handle.pause_unchanged") {
+        tracing::warn!(virtual_time = %get_mock_now(), "虚拟时钟状态未变化，忽略请求{}", digest);
+    }
+}
+
+/// 计算充电桩当前的维护阶段，`candidate` 为正在接收的新详单，排空时间按它加入队列后的下一个会话计算
+fn maintenance_phase(charge: &Charge, candidate: Option<&ChargingDetail>) -> MaintenancePhase {
+    maintenance::phase(
+        &CONF.charge.maintenance_windows,
+        get_mock_now(),
+        &CONF.time.tz,
+        charge.projected_session_duration(candidate),
+    )
+}
+
+/// 检查维护阶段是否变化，并根据维护策略排空、中断或恢复充电桩
+async fn check_maintenance(
+    pile: &Pile,
+    current: &mut MaintenancePhase,
+    update_ticker: &mut Option<Interval>,
+    complete_tickers: &mut CompleteTickers,
+    maintenance_ticker: &mut Option<Interval>,
+) {
+    let mut charge = pile.charge.lock().await;
+    let new_phase = maintenance_phase(&charge, None);
+    if new_phase != *current {
+        match new_phase {
+            MaintenancePhase::Draining { start, .. } => {
+                tracing::info!(virtual_time = %get_mock_now(), "即将进入维护时间 {}，充电桩开始排空", start);
+            }
+            MaintenancePhase::InWindow { end } => {
+                tracing::info!(virtual_time = %get_mock_now(), "进入维护时间，预计结束时间: {}", end);
+                if CONF.charge.maintenance_policy == MaintenancePolicy::Interrupt {
+                    for detail in charge.close() {
+                        tracing::info!(virtual_time = %get_mock_now(), "维护开始，充电详单 {} 被打断", detail.get_id());
+                        send_update(pile, &detail);
+                    }
+                    remove_ticker(update_ticker);
+                    complete_tickers.clear();
+                }
+            }
+            MaintenancePhase::Open => {
+                tracing::info!(virtual_time = %get_mock_now(), "维护结束，充电桩恢复服务");
+                let reopened = matches!(*current, MaintenancePhase::InWindow { .. });
+                if reopened {
+                    drop(charge);
+                    register(pile).await;
+                    charge = pile.charge.lock().await;
+                }
+                start_waiting(pile, &mut charge, update_ticker, complete_tickers).await;
+                if reopened {
+                    // 恢复服务后发送一次状态快照，服务器不需要查询就能知道恢复后的队列
+                    drop(charge);
+                    send_status(pile).await;
+                    charge = pile.charge.lock().await;
+                }
+            }
+        }
+        *current = new_phase;
+    }
+
+    // 下一次检查的时间，最长不超过更新间隔，以便跟随会话预计时长的变化
+    let max_wait = RUNTIME.update_interval_duration();
+    let wait = maintenance::next_transition(
+        &CONF.charge.maintenance_windows,
+        get_mock_now(),
+        &CONF.time.tz,
+        charge.projected_session_duration(None),
+    )
+    .and_then(|next| (next - get_mock_now()).to_std().ok())
+    .map(|virtual_wait| RUNTIME.real_duration(virtual_wait).min(max_wait))
+    .unwrap_or(max_wait);
+    set_ticker(maintenance_ticker, wait.max(Duration::from_millis(50)));
+}
+
+/// 检查充电桩是否有空闲的充电枪，有空闲的充电枪且队列中有等待的充电详单时开始充电并设置完成计时器
+/// 返回开始充电的详单 ID
+async fn not_working_check(
+    charge: &mut Charge,
+    complete_tickers: &mut CompleteTickers,
+) -> Vec<u32> {
+    let mut started = Vec::new();
+    while charge.can_start() {
+        if !maintenance_phase(charge, None).accepts_new() {
+            tracing::info!(virtual_time = %get_mock_now(), "充电桩处于维护或排空阶段，暂不开始新的充电");
+            break;
+        }
+        if charge.is_working() {
+            tracing::info!(virtual_time = %get_mock_now(), "充电桩有空闲的充电枪，开始为下一个详单充电");
+        } else {
+            tracing::info!(virtual_time = %get_mock_now(), "充电桩未工作，开始工作");
+        }
+        let Some(id) = charge.start_charging() else {
+            break;
+        };
+        set_complete_ticker(complete_tickers, charge, id);
+        started.push(id);
+    }
+    started
+}
+
+/// 在空闲的充电枪上开始充电，发送开始充电的详单的完整更新并重新设置更新计时器
+async fn start_waiting(
+    pile: &Pile,
+    charge: &mut Charge,
+    update_ticker: &mut Option<Interval>,
+    complete_tickers: &mut CompleteTickers,
+) {
+    let started = not_working_check(charge, complete_tickers).await;
+    for id in &started {
+        send_update(pile, charge.get_detail_ref(*id).unwrap());
+    }
+    if !started.is_empty() {
+        set_ticker(update_ticker, update_period(charge));
+    }
+}
+
+/// 发送充电详单完整更新消息
+fn send_update(pile: &Pile, detail: &ChargingDetail) {
+    let update_msg = pile.updates.lock().unwrap().snapshot(detail);
+    send_update_msg(pile, update_msg, detail.get_id());
+}
+
+/// 发送充电详单定期更新消息，增量模式下只发送变化的字段
+fn send_progress(pile: &Pile, detail: &ChargingDetail) {
+    let update_msg = pile.updates.lock().unwrap().progress(detail);
+    send_update_msg(pile, update_msg, detail.get_id());
+}
+
+/// 发送已编码的充电详单更新消息，同一详单还没有发送的更新会被替换
+fn send_update_msg(pile: &Pile, update_msg: MSG, id: u32) {
+    pile.metrics.record_update_generated();
+    enqueue(pile, stamp(pile, update_msg), Some(id));
+}
+
+/// 分配消息 ID 后放入出站队列，之后由主循环发送
+fn send_msg(pile: &Pile, msg: &MSG) {
+    enqueue(pile, stamp(pile, msg.clone()), None);
+}
+
+/// 分配消息 ID 和发送时间，需要确认的消息同时加入发件箱
+fn stamp(pile: &Pile, mut msg: MSG) -> MSG {
+    pile.outbox
+        .lock()
+        .unwrap()
+        .stamp(&mut msg, get_mock_now(), std::time::Instant::now());
+    msg
+}
+
+/// 把已分配消息 ID 的消息放入出站队列并通知主循环发送
+/// 队列满时丢弃最早的状态更新，丢弃的是增量更新时下一次更新改为发送完整快照
+fn enqueue(pile: &Pile, msg: MSG, detail: Option<u32>) {
+    let (dropped, total) = {
+        let mut outbound = pile.outbound.lock().unwrap();
+        (outbound.push(msg, detail), outbound.dropped())
+    };
+    if let Some(dropped) = dropped {
+        if dropped.type_ == MessageType::Delta {
+            pile.updates.lock().unwrap().resync();
+        }
+        if let Some(digest) = throttle::allow("outbound.overflow") {
+            tracing::warn!(
+                virtual_time = %get_mock_now(),
+                "出站队列已满（容量 {}），丢弃最早的状态更新 {:?}，累计丢弃 {} 条{}",
+                CONF.websocket.send_buffer,
+                dropped.msg_id,
+                total,
+                digest
+            );
+        }
+    }
+    pile.outbound_ready.notify_one();
+}
+
+/// 出站队列中被推迟的状态更新最早可以发送的时间
+fn next_release(pile: &Pile) -> Option<tokio::time::Instant> {
+    pile.outbound
+        .lock()
+        .unwrap()
+        .next_release(tokio::time::Instant::now())
+}
+
+/// 按顺序发送出站队列中的消息，发送失败时消息留在队首，重新连接后继续发送
+/// 按最小更新间隔被推迟的状态更新留在队列中，到时间后再发送
+/// 返回可以发送的消息是否已经发送完
+async fn flush_outbound(pile: &Pile, ws_sender: &mut Outlet) -> bool {
+    loop {
+        let now = tokio::time::Instant::now();
+        let Some(msg) = pile.outbound.lock().unwrap().front_ready(now).cloned() else {
+            return true;
+        };
+        if let Err(e) = send_stamped(pile, ws_sender, &msg).await {
+            pile.metrics.record_send_failure();
+            if let Some(digest) = throttle::allow("outbound.send") {
+                tracing::error!(
+                    virtual_time = %get_mock_now(),
+                    "消息 {:?} 发送失败，{} 条消息留在出站队列中: {}{}",
+                    msg.msg_id,
+                    pile.outbound.lock().unwrap().len(),
+                    e,
+                    digest
+                );
+            }
+            return false;
+        }
+        pile.outbound.lock().unwrap().pop_ready(now);
+        if matches!(msg.type_, MessageType::Update | MessageType::Delta) {
+            pile.metrics.record_update_sent();
+        }
+        match msg.type_ {
+            MessageType::Complete | MessageType::Fault | MessageType::Register => {
+                tracing::info!(virtual_time = %get_mock_now(), "{:?} 消息发送成功", msg.type_)
+            }
+            _ => tracing::debug!(virtual_time = %get_mock_now(), "{:?} 消息发送成功", msg.type_),
+        }
+    }
+}
+
+/// 重新发送没有得到确认的消息，`all` 为 `true` 时不等待超时（例如迁移连接后）
+/// 重新发送的消息保留原来的消息 ID，仍在出站队列中的消息不会重复放入
+fn resend_unacked(pile: &Pile, all: bool) {
+    let now = std::time::Instant::now();
+    let messages = {
+        let mut outbox = pile.outbox.lock().unwrap();
+        if all {
+            outbox.all(now)
+        } else {
+            outbox.due(now, Duration::from_secs(CONF.websocket.resend_after_s))
+        }
+    };
+    if messages.is_empty() {
+        return;
+    }
+    if let Some(digest) = throttle::allow("outbox.resend") {
+        tracing::warn!(virtual_time = %get_mock_now(), "{} 条消息没有收到服务器确认，重新发送{}", messages.len(), digest);
+    }
+    for msg in messages {
+        enqueue(pile, msg, None);
+    }
+}
+
+/// 发送已分配消息 ID 的消息，发送成功时记录消息计数
+/// 连续发送的状态更新一直没有得到响应时先发送探测消息，探测后仍无响应则通知断开连接
+async fn send_stamped(
+    pile: &Pile,
+    ws_sender: &mut Outlet,
+    msg: &MSG,
+) -> Result<(), tokio_tungstenite::tungstenite::Error> {
+    let encoding = if msg.type_ == MessageType::Register {
+        // 注册消息总是使用 JSON，服务器重新确认编码方式前之后的消息也使用 JSON
+        *pile.encoding.lock().unwrap() = WireEncoding::Json;
+        WireEncoding::Json
+    } else {
+        *pile.encoding.lock().unwrap()
+    };
+    ws_sender.send(msg.to_frame(encoding)).await?;
+    audit::record(Direction::Out, pile.charge_id, msg);
+    let (real, virtual_time) = (chrono::Utc::now(), get_mock_now());
+    pile.metrics
+        .record_message_sent(msg.type_, real, virtual_time);
+    let action = pile
+        .traffic
+        .lock()
+        .unwrap()
+        .record_sent(msg.type_, real, virtual_time);
+    match action {
+        TrafficAction::None => {}
+        TrafficAction::Probe => {
+            tracing::warn!(
+                virtual_time = %get_mock_now(),
+                "连续 {} 次状态更新没有收到任何入站消息，发送探测消息",
+                CONF.websocket.max_unacked_updates
+            );
+            if let Err(e) = ws_sender.send(WsMessage::Ping(Vec::new().into())).await {
+                tracing::error!(virtual_time = %get_mock_now(), "探测消息发送失败: {}", e);
+            }
+        }
+        TrafficAction::Teardown => {
+            tracing::error!(virtual_time = %get_mock_now(), "探测后仍然没有收到任何入站消息，认为连接已断开");
+            pile.connection_lost.notify_one();
+        }
+    }
+    Ok(())
+}
+
+/// 输出并清空当前连接的消息计数
+fn report_traffic(pile: &Pile) {
+    let mut traffic = pile.traffic.lock().unwrap();
+    let last_sent = traffic.last_sent();
+    tracing::info!(
+        sent = ?traffic.sent(),
+        received = ?traffic.received(),
+        last_sent_real = ?last_sent.map(|(real, _)| real),
+        last_sent_virtual = ?last_sent.map(|(_, mock)| mock),
+        "本次连接的消息计数"
+    );
+    traffic.reset();
+}
+
+/// 把充电桩的状态变化事件转换为出站消息
+/// 目前只有完成事件生成消息，状态更新、故障和取消的消息由处理函数直接发送；
+/// 主循环每次处理消息前都会调用，事件通道不会积压到丢失事件
+fn forward_events(pile: &Pile) {
+    loop {
+        let event = pile.events.lock().unwrap().try_recv();
+        match event {
+            Ok(ChargeEvent::Completed { detail, .. }) => send_complete(pile, &detail),
+            Ok(_) => {}
+            Err(broadcast::error::TryRecvError::Lagged(missed)) => {
+                tracing::error!(virtual_time = %get_mock_now(), "充电桩事件积压，丢失了 {} 个事件", missed);
+            }
+            Err(_) => break,
+        }
+    }
+}
+
+/// 发送充电详单完成消息
+fn send_complete(pile: &Pile, detail: &ChargingDetail) {
+    let complete_msg = MSG::with_payload(MessageType::Complete, detail);
+    enqueue(pile, stamp(pile, complete_msg), Some(detail.get_id()));
+}
+
+/// 发送充电详单故障消息
+fn send_fault(pile: &Pile, detail: Option<&ChargingDetail>) {
+    let fault_msg = MSG::with_payload(MessageType::Fault, &detail);
+    enqueue(
+        pile,
+        stamp(pile, fault_msg),
+        detail.map(ChargingDetail::get_id),
+    );
+}
+
+/// 发送拒绝新详单消息
+fn send_reject(pile: &Pile, id: u32, reason: &str) {
+    let reject_msg = MSG::with_payload(
+        MessageType::Reject,
+        &RejectData {
+            id,
+            reason: reason.to_string(),
+            rejected: Vec::new(),
+        },
+    );
+    send_msg(pile, &reject_msg);
+    tracing::info!(virtual_time = %get_mock_now(), "拒绝充电详单 {}，原因: {}", id, reason);
+}
+
+/// 发送新详单已加入队列（`ack`）或进入等待区（`pending`）消息
+fn send_ack(pile: &Pile, type_: MessageType, ack: AckData) {
+    let ack_msg = MSG::with_payload(type_, &ack);
+    send_msg(pile, &ack_msg);
+}
+
+/// 通知服务器从等待区进入队列的详单
+fn send_promoted(pile: &Pile, charge: &mut Charge) {
+    for (id, position) in charge.take_promoted() {
+        let ack = AckData {
+            id,
+            position,
+            msg_id: None,
+            duplicate: false,
+            warning: None,
+        };
+        send_ack(pile, MessageType::Ack, ack);
+    }
+}
+
+/// 发送错误消息
+fn send_error(pile: &Pile, error: &ErrorData) {
+    let error_msg = MSG::with_payload(MessageType::Error, error);
+    send_msg(pile, &error_msg);
+}
+
+/// 检查字段并解析入站消息，严格模式下包含未知字段时回复错误消息
+fn parse_inbound<T: serde::de::DeserializeOwned>(
+    pile: &Pile,
+    msg: Value,
+    known: &[&str],
+) -> Option<T> {
+    let strict = CONF.websocket.strict_fields;
+    let result = compat::parse_checked(msg, known, strict, &mut COMPAT.lock().unwrap());
+    match result {
+        Ok(value) => Some(value),
+        Err(e) => {
+            if let Some(digest) = throttle::allow("inbound.parse") {
+                tracing::warn!(virtual_time = %get_mock_now(), "充电详单解析失败: {}{}", e, digest);
+            }
+            if strict && e.starts_with("unknown fields") {
+                send_error(
+                    pile,
+                    &ErrorData {
+                        reason: e,
+                        offset: None,
+                    },
+                );
+            }
+            None
+        }
+    }
+}
+
+/// 输出并清空当前连接的字段兼容性报告
+fn report_compat() {
+    let mut report = COMPAT.lock().unwrap();
+    if !report.is_empty() {
+        tracing::warn!("本次连接收到的未知字段: {}", report);
+        report.clear();
+    }
+}
+
+/// 处理新的充电详单消息
+/// 带有消息 ID 或功率不一致的 `new` 消息总是回复 `ack`，已经加入过队列的详单只回复 `ack`，不再次加入队列
+async fn handle_new(
+    pile: &Pile,
+    msg: Value,
+    msg_id: Option<u64>,
+    update_ticker: &mut Option<Interval>,
+    complete_tickers: &mut CompleteTickers,
+) {
+    let raw_id = msg["id"].as_u64().and_then(|id| u32::try_from(id).ok());
+    let detail: ChargingDetail = match parse_inbound(pile, msg, ChargingDetail::FIELDS) {
+        Some(d) => d,
+        None => {
+            // 无法解析的详单能读出 ID 时回复拒绝消息，服务器不必等待
+            if let Some(id) = raw_id {
+                send_reject(pile, id, "invalid_detail");
+            }
+            return;
+        }
+    };
+    let id = detail.get_id();
+    tracing::info!(virtual_time = %get_mock_now(), "接收到新的充电详单: {}", id);
+
+    let mut charge = pile.charge.lock().await;
+    match admit_new(pile, &mut charge, detail, msg_id) {
+        Ok(true) => start_waiting(pile, &mut charge, update_ticker, complete_tickers).await,
+        Ok(false) => {}
+        Err(reason) => send_reject(pile, id, &reason),
+    }
+}
+
+/// 处理批量新详单消息，数据为详单数组
+/// 所有详单在同一次加锁中按数组顺序加入队列，被拒绝的详单汇总在一条拒绝消息中，
+/// 之后只检查一次是否开始充电
+async fn handle_new_batch(
+    pile: &Pile,
+    msg: Value,
+    msg_id: Option<u64>,
+    update_ticker: &mut Option<Interval>,
+    complete_tickers: &mut CompleteTickers,
+) {
+    let Value::Array(items) = msg else {
+        send_error(
+            pile,
+            &ErrorData {
+                reason: "new_batch data must be an array".to_string(),
+                offset: None,
+            },
+        );
+        return;
+    };
+    tracing::info!(virtual_time = %get_mock_now(), "接收到 {} 个新的充电详单", items.len());
+
+    let mut charge = pile.charge.lock().await;
+    let mut rejected = Vec::new();
+    let mut changed = false;
+    for item in items {
+        let raw_id = item["id"].as_u64().and_then(|id| u32::try_from(id).ok());
+        let result = match parse_inbound::<ChargingDetail>(pile, item, ChargingDetail::FIELDS) {
+            Some(detail) => {
+                let id = detail.get_id();
+                admit_new(pile, &mut charge, detail, msg_id).map_err(|reason| (id, reason))
+            }
+            // 读不出 ID 的详单无法在拒绝消息中列出，只输出日志
+            None => match raw_id {
+                Some(id) => Err((id, "invalid_detail".to_string())),
+                None => Ok(false),
+            },
+        };
+        match result {
+            Ok(queued) => changed |= queued,
+            Err((id, reason)) => {
+                tracing::info!(virtual_time = %get_mock_now(), "拒绝充电详单 {}，原因: {}", id, reason);
+                rejected.push(RejectData {
+                    id,
+                    reason,
+                    rejected: Vec::new(),
+                });
+            }
+        }
+    }
+    // `id` 和 `reason` 为第一个被拒绝的详单，只读取这两个字段的服务器也能收到拒绝
+    if let Some(first) = rejected.first() {
+        let reject_msg = MSG::with_payload(
+            MessageType::Reject,
+            &RejectData {
+                id: first.id,
+                reason: first.reason.clone(),
+                rejected,
+            },
+        );
+        send_msg(pile, &reject_msg);
+    }
+    if changed {
+        start_waiting(pile, &mut charge, update_ticker, complete_tickers).await;
+    }
+}
+
+/// 在持有充电桩锁时处理一个已解析的新详单，加入队列或等待区时回复 `ack` 或 `pending`
+/// 返回详单是否加入了队列（需要检查是否开始充电），被拒绝时返回拒绝原因
+fn admit_new(
+    pile: &Pile,
+    charge: &mut Charge,
+    detail: ChargingDetail,
+    msg_id: Option<u64>,
+) -> Result<bool, String> {
+    let id = detail.get_id();
+    if msg_id.is_some() && pile.outbox.lock().unwrap().seen_new(id) {
+        tracing::info!(virtual_time = %get_mock_now(), "充电详单 {} 已经加入过队列，忽略重复的新详单消息", id);
+        let position = charge.queue_position(id).unwrap_or(0);
+        let ack = AckData {
+            id,
+            position,
+            msg_id,
+            duplicate: true,
+            warning: None,
+        };
+        send_ack(pile, MessageType::Ack, ack);
+        return Ok(false);
+    }
+    if charge.is_closed() {
+        if let Some(digest) = throttle::allow("handle.closed_new") {
+            tracing::warn!(virtual_time = %get_mock_now(), "充电桩已关闭，无法处理新充电请求{}", digest);
+        }
+        return Err("closed".to_string());
+    }
+    if !detail.is_ready() {
+        if let Some(digest) = throttle::allow("handle_new.not_ready") {
+            tracing::warn!(virtual_time = %get_mock_now(), "充电详单格式异常，无法加入队列{}", digest);
+        }
+        return Err("not_ready".to_string());
+    }
+    if !maintenance_phase(charge, Some(&detail)).accepts_new() {
+        tracing::warn!(
+            virtual_time = %get_mock_now(),
+            reason = "maintenance",
+            "充电桩处于维护或排空阶段，拒绝充电详单: {}",
+            id
+        );
+        return Err("maintenance".to_string());
+    }
+    // 功率不一致时详单仍然被接受，确认消息中带有警告
+    let warning = charge.power_warning(&detail);
+    let admission = charge.admit(detail).map_err(|e| e.to_string())?;
+    pile.outbox.lock().unwrap().mark_new(id);
+    let ack = |position| AckData {
+        id,
+        position,
+        msg_id,
+        duplicate: false,
+        warning,
+    };
+    let ack_new = CONF.websocket.ack_new || msg_id.is_some() || warning.is_some();
+    match admission {
+        Admission::Queued(position) => {
+            tracing::info!(
+                virtual_time = %get_mock_now(), "充电详单已加入队列，当前队列长度: {}",
+                charge.get_queue_size()
+            );
+            if ack_new {
+                send_ack(pile, MessageType::Ack, ack(position));
+            }
+            Ok(true)
+        }
+        Admission::Replaced { position, evicted } => {
+            // 先通知服务器被取消的详单，再确认新详单
+            send_update(pile, &evicted);
+            if ack_new {
+                send_ack(pile, MessageType::Ack, ack(position));
+            }
+            Ok(true)
+        }
+        Admission::Pending(position) => {
+            send_ack(pile, MessageType::Pending, ack(position));
+            Ok(false)
+        }
+    }
+}
+
+/// 处理取消充电详单消息
+async fn handle_cancel(
+    pile: &Pile,
+    msg: Value,
+    update_ticker: &mut Option<Interval>,
+    complete_tickers: &mut CompleteTickers,
+) {
+    let cancel: CancelData = match parse_inbound(pile, msg, &CancelData::fields()) {
+        Some(d) => d,
+        None => return,
+    };
+    let detail_id = cancel.detail.get_id();
+    tracing::info!(
+        virtual_time = %get_mock_now(),
+        "接收到取消充电详单请求: {}，原因: {} {}",
+        detail_id,
+        cancel.reason_code.as_deref().unwrap_or(UNSPECIFIED_STOP_REASON),
+        cancel.reason.as_deref().unwrap_or_default()
+    );
+
+    let mut charge = pile.charge.lock().await;
+    let active = charge.active_detail_ids().contains(&detail_id);
+    match charge.cancel_charging(detail_id, cancel.reason_code, cancel.reason) {
+        Ok(detail) => {
+            tracing::info!(virtual_time = %get_mock_now(), "充电详单 {} 已取消", detail_id);
+            if active {
+                // 定时器仍按被取消详单的结束时间运行，开始下一个详单时重新设置
+                complete_tickers.remove(&detail_id);
+                if !charge.is_working() {
+                    remove_ticker(update_ticker);
+                }
+            }
+            send_update(pile, &detail);
+            send_promoted(pile, &mut charge);
+            start_waiting(pile, &mut charge, update_ticker, complete_tickers).await;
+        }
+        Err(e @ ChargeError::Faulted) => {
+            // 故障期间的取消请求和新详单一样回复拒绝消息
+            send_reject(pile, detail_id, &e.to_string());
+        }
+        Err(e) => {
+            tracing::error!(virtual_time = %get_mock_now(), "取消充电详单失败: {}", e);
+        }
+    }
+}
+
+/// 处理关闭充电桩请求
+async fn handle_close(
+    pile: &Pile,
+    update_ticker: &mut Option<Interval>,
+    complete_tickers: &mut CompleteTickers,
+) {
+    tracing::info!(virtual_time = %get_mock_now(), "接收到关闭充电桩请求");
+    let mut charge = pile.charge.lock().await;
+    let interrupted = match charge.close_by_server() {
+        Ok(interrupted) => interrupted,
+        Err(e) => {
+            if let Some(digest) = throttle::allow("handle.close_rejected") {
+                tracing::warn!(virtual_time = %get_mock_now(), "充电桩当前为{}状态，无法关闭: {}{}", e.state.label(), e, digest);
+            }
+            return;
+        }
+    };
+    let drop_queue = charge.drops_queue_on_close();
+    if interrupted.is_empty() {
+        tracing::info!(virtual_time = %get_mock_now(), "充电桩没有正在充电的详单，没有被打断的充电详单");
+    }
+    for detail in interrupted {
+        tracing::info!(virtual_time = %get_mock_now(), "充电桩已关闭，当前被打断的充电详单: {}", detail.get_id());
+        send_update(pile, &detail);
+    }
+    if !drop_queue {
+        tracing::info!(virtual_time = %get_mock_now(), "充电桩队列中保留 {} 个详单，重新打开后继续充电", charge.get_queue_size());
+        send_promoted(pile, &mut charge);
+    }
+    remove_ticker(update_ticker);
+    complete_tickers.clear();
+}
+
+/// 处理打开充电桩请求
+/// 关闭时保留了队列时重新开始充电，并发送状态快照告知服务器充电桩已重新打开
+async fn handle_open(
+    pile: &Pile,
+    update_ticker: &mut Option<Interval>,
+    complete_tickers: &mut CompleteTickers,
+) {
+    tracing::info!(virtual_time = %get_mock_now(), "接收到打开充电桩请求");
+    {
+        let mut charge = pile.charge.lock().await;
+        if let Err(e) = charge.open_by_server() {
+            if let Some(digest) = throttle::allow("handle.open_rejected") {
+                tracing::warn!(virtual_time = %get_mock_now(), "充电桩当前为{}状态，无法重新打开: {}{}", e.state.label(), e, digest);
+            }
+            return;
+        }
+        remove_ticker(update_ticker);
+        complete_tickers.clear();
+        start_waiting(pile, &mut charge, update_ticker, complete_tickers).await;
+    }
+    send_status(pile).await;
+}
+
+/// 尝试更新充电状态
+async fn try_update_charge(
+    pile: &Pile,
+    update_ticker: &mut Option<Interval>,
+    complete_tickers: &mut CompleteTickers,
+) {
+    let mut charge = pile.charge.lock().await;
+    if charge.is_working() {
+        charge.update_charging();
+        let details = charge.get_charging_details();
+        if details.is_empty() {
+            recover_inconsistent(&mut charge, update_ticker, complete_tickers);
+        } else {
+            pile.updates
+                .lock()
+                .unwrap()
+                .retain(&charge.active_detail_ids());
+            for detail in details {
+                send_progress(pile, detail);
+            }
+        }
+    } else {
+        tracing::error!(virtual_time = %get_mock_now(), "充电桩未处于工作状态，无法更新充电状态");
+        remove_ticker(update_ticker);
+    }
+}
+
+/// 充电桩处于工作状态但没有正在充电的详单时，清除定时器并重置为非工作状态，继续处理消息
+fn recover_inconsistent(
+    charge: &mut Charge,
+    update_ticker: &mut Option<Interval>,
+    complete_tickers: &mut CompleteTickers,
+) {
+    tracing::error!(
+        virtual_time = %get_mock_now(),
+        queue_size = charge.get_queue_size(),
+        working = charge.is_working(),
+        "充电桩状态不一致：处于工作状态但没有正在充电的详单，重置为非工作状态"
+    );
+    remove_ticker(update_ticker);
+    complete_tickers.clear();
+    charge.reset_working();
+}
+
+/// 尝试完成指定详单的充电
+async fn try_complete_charge(
+    pile: &Pile,
+    id: u32,
+    update_ticker: &mut Option<Interval>,
+    complete_tickers: &mut CompleteTickers,
+) {
+    let mut charge = pile.charge.lock().await;
+    if charge.is_working() {
+        if let Some(detail) = charge.complete_charging(id) {
+            // 完成消息由完成事件生成，在提前开始的详单的确认和更新之前发送
+            forward_events(pile);
+            complete_tickers.remove(&id);
+            if !charge.is_working() {
+                remove_ticker(update_ticker);
+            }
+            tracing::info!(virtual_time = %get_mock_now(), "充电详单 {} 已完成", detail.get_id());
+            send_promoted(pile, &mut charge);
+            start_waiting(pile, &mut charge, update_ticker, complete_tickers).await;
+        } else {
+            recover_inconsistent(&mut charge, update_ticker, complete_tickers);
+        }
+    } else {
+        tracing::error!(virtual_time = %get_mock_now(), "充电桩未处于工作状态，无法完成充电");
+        complete_tickers.clear();
+        remove_ticker(update_ticker);
+    }
+}
+
+/// 尝试打断充电
+/// 键盘模拟损坏和服务器的 `break` 消息都通过这里进入故障状态
+async fn try_breakdown_charge(
+    pile: &Pile,
+    source: FaultSource,
+    update_ticker: &mut Option<Interval>,
+    complete_tickers: &mut CompleteTickers,
+) {
+    let mut charge = pile.charge.lock().await;
+    if !charge.fault_armed(source) {
+        tracing::warn!(virtual_time = %get_mock_now(), "故障来源 {:?} 未启用，忽略损坏信号", source);
+        return;
+    }
+    if !charge.get_state().is_operating() {
+        tracing::warn!(virtual_time = %get_mock_now(), "充电桩当前为{}状态，忽略损坏信号", charge.get_state().label());
+        return;
+    }
+    tracing::error!(virtual_time = %get_mock_now(),"充电桩损坏");
+    match charge.breakdown(source) {
+        Ok(interrupted) if interrupted.is_empty() => {
+            tracing::info!(virtual_time = %get_mock_now(), "充电桩未处于工作状态，没有被打断的充电详单");
+            send_fault(pile, None);
+        }
+        Ok(interrupted) => {
+            for detail in interrupted {
+                send_fault(pile, Some(&detail));
+                tracing::info!(virtual_time = %get_mock_now(), "充电详单 {} 已被打断", detail.get_id());
+            }
+        }
+        Err(_) => return,
+    }
+    complete_tickers.clear();
+    remove_ticker(update_ticker);
+    if !CONF.charge.exit_on_breakdown {
+        tracing::info!(virtual_time = %get_mock_now(), "充电桩进入故障状态，等待服务器发送修复消息");
+    }
+}
+
+/// 处理修复充电桩请求
+async fn handle_repair(
+    pile: &Pile,
+    update_ticker: &mut Option<Interval>,
+    complete_tickers: &mut CompleteTickers,
+) {
+    tracing::info!(virtual_time = %get_mock_now(), "接收到修复充电桩请求");
+    let mut charge = pile.charge.lock().await;
+    let resumed = match charge.repair() {
+        Ok(resumed) => resumed,
+        Err(e) => {
+            if let Some(digest) = throttle::allow("handle.repair_not_faulted") {
+                tracing::warn!(virtual_time = %get_mock_now(), "充电桩未处于故障状态，忽略修复请求: {}{}", e, digest);
+            }
+            return;
+        }
+    };
+    if resumed.is_empty() {
+        tracing::info!(virtual_time = %get_mock_now(), "充电桩已修复，等待新的充电请求");
+    } else {
+        set_ticker(update_ticker, update_period(&charge));
+        for detail in &resumed {
+            set_complete_ticker(complete_tickers, &charge, detail.get_id());
+        }
+    }
+    // 重新注册，服务器据此知道充电桩已恢复并可以继续下发详单
+    drop(charge);
+    register(pile).await;
+    for detail in &resumed {
+        send_update(pile, detail);
+    }
+}
+
+/// 处理免费充电请求
+/// 开启后正在进行的会话继续充电但不再计费，关闭后恢复计费
+async fn handle_free_vend(pile: &Pile, msg: Value) {
+    let data: FreeVendData = match parse_inbound(pile, msg, FreeVendData::FIELDS) {
+        Some(d) => d,
+        None => return,
+    };
+    tracing::info!(virtual_time = %get_mock_now(), "接收到免费充电请求: {}", data.enabled);
+    let mut charge = pile.charge.lock().await;
+    if charge.is_working() {
+        // 发送状态前先把详单更新到切换时刻
+        charge.update_charging();
+    }
+    if !charge.set_free_vend(data.enabled, get_mock_now()) {
+        if let Some(digest) = throttle::allow("handle.free_vend_unchanged") {
+            tracing::warn!(virtual_time = %get_mock_now(), "免费充电状态未变化，忽略请求{}", digest);
+        }
+        return;
+    }
+    for detail in charge.get_charging_details() {
+        send_update(pile, detail);
+    }
+}
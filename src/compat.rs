@@ -0,0 +1,178 @@
+//! 入站消息字段兼容性检查
+//!
+//! 不同服务器实现的字段名可能不一致（如 `requestAmount` 与 `request_amount`），
+//! serde 会忽略未知字段并使用默认值，导致难以察觉的错误。
+//! 这里先把消息解析为 `serde_json::Value` 检查字段名，再解析为具体类型。
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+#[derive(Debug, Default, Clone)]
+/// 兼容性报告，按字段名统计每个连接收到的未知字段次数
+pub struct CompatReport {
+    counts: BTreeMap<String, u64>,
+}
+
+impl CompatReport {
+    /// 创建空的报告
+    pub const fn new() -> Self {
+        CompatReport {
+            counts: BTreeMap::new(),
+        }
+    }
+
+    /// 记录一次出现的未知字段
+    pub fn record(&mut self, keys: &[String]) {
+        for key in keys {
+            *self.counts.entry(key.clone()).or_default() += 1;
+        }
+    }
+
+    /// 指定字段出现的次数
+    pub fn count(&self, key: &str) -> u64 {
+        self.counts.get(key).copied().unwrap_or(0)
+    }
+
+    /// 是否没有出现过未知字段
+    pub fn is_empty(&self) -> bool {
+        self.counts.is_empty()
+    }
+
+    /// 清空报告
+    pub fn clear(&mut self) {
+        self.counts.clear();
+    }
+}
+
+impl fmt::Display for CompatReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let entries: Vec<String> = self
+            .counts
+            .iter()
+            .map(|(key, count)| format!("{} x{}", key, count))
+            .collect();
+        write!(f, "{}", entries.join(", "))
+    }
+}
+
+/// 找出对象中不在已知字段列表中的字段，非对象返回空列表
+pub fn unknown_keys(value: &Value, known: &[&str]) -> Vec<String> {
+    match value {
+        Value::Object(map) => map
+            .keys()
+            .filter(|key| !known.contains(&key.as_str()))
+            .cloned()
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// 检查字段后解析消息
+/// 未知字段总是记录到报告中；严格模式下存在未知字段时返回错误
+pub fn parse_checked<T: DeserializeOwned>(
+    value: Value,
+    known: &[&str],
+    strict: bool,
+    report: &mut CompatReport,
+) -> Result<T, String> {
+    let unknown = unknown_keys(&value, known);
+    if !unknown.is_empty() {
+        report.record(&unknown);
+        if strict {
+            return Err(format!("unknown fields: {}", unknown.join(", ")));
+        }
+    }
+    from_value(value)
+}
+
+/// 把值解析为具体类型
+/// serde 的类型错误不包含字段名，失败时把对象按每个字段一行重新解析，按出错的行找出字段名
+pub fn from_value<T: DeserializeOwned>(value: Value) -> Result<T, String> {
+    let error = match serde_json::from_value::<T>(value.clone()) {
+        Ok(parsed) => return Ok(parsed),
+        Err(e) => e.to_string(),
+    };
+    if !value.is_object() || error.starts_with("missing field") {
+        return Err(error);
+    }
+    let pretty = serde_json::to_string_pretty(&value).map_err(|e| e.to_string())?;
+    let Err(e) = serde_json::from_str::<T>(&pretty) else {
+        return Err(error);
+    };
+    match field_at_line(&pretty, e.line()) {
+        Some(field) => Err(format!("invalid field `{}`: {}", field, error)),
+        None => Err(error),
+    }
+}
+
+/// 格式化后的对象中第 `line` 行（从 1 开始）所属的顶层字段名
+fn field_at_line(pretty: &str, line: usize) -> Option<String> {
+    pretty
+        .lines()
+        .take(line)
+        .filter_map(|text| {
+            // 顶层字段缩进两个空格
+            let rest = text.strip_prefix("  \"")?;
+            let end = rest.find("\": ")?;
+            serde_json::from_str::<String>(&format!("\"{}\"", &rest[..end])).ok()
+        })
+        .last()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::detail::ChargingDetail;
+
+    /// 把详单中的字段名改为 camelCase
+    fn camel_case_detail() -> Value {
+        let mut value = serde_json::to_value(ChargingDetail::test_new(1)).unwrap();
+        let map = value.as_object_mut().unwrap();
+        let amount = map.remove("request_amount").unwrap();
+        map.insert("requestAmount".to_string(), amount);
+        map.insert("request_amount".to_string(), 30.0.into());
+        map.insert("alreadyCharged".to_string(), 0.0.into());
+        value
+    }
+
+    #[test]
+    fn test_strict_rejects_camel_case() {
+        let mut report = CompatReport::default();
+        let result = parse_checked::<ChargingDetail>(
+            camel_case_detail(),
+            ChargingDetail::FIELDS,
+            true,
+            &mut report,
+        );
+        let error = result.err().unwrap();
+        assert!(error.contains("requestAmount"));
+        assert!(error.contains("alreadyCharged"));
+        assert_eq!(report.count("requestAmount"), 1);
+    }
+
+    #[test]
+    fn test_lenient_counts_unknown_keys() {
+        let mut report = CompatReport::default();
+        for _ in 0..3 {
+            let detail = parse_checked::<ChargingDetail>(
+                camel_case_detail(),
+                ChargingDetail::FIELDS,
+                false,
+                &mut report,
+            );
+            assert!(detail.is_ok());
+        }
+        let clean = serde_json::to_value(ChargingDetail::test_new(2)).unwrap();
+        assert!(
+            parse_checked::<ChargingDetail>(clean, ChargingDetail::FIELDS, true, &mut report)
+                .is_ok()
+        );
+        assert_eq!(report.count("requestAmount"), 3);
+        assert_eq!(report.count("alreadyCharged"), 3);
+        assert_eq!(report.count("request_amount"), 0);
+        assert_eq!(report.to_string(), "alreadyCharged x3, requestAmount x3");
+    }
+}
@@ -1,18 +1,32 @@
 //! 保存配置
 
-use std::sync::LazyLock;
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use std::sync::{LazyLock, OnceLock};
 
-use chrono::DateTime;
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, Datelike, NaiveDate, NaiveTime, Weekday};
 use serde::{Deserialize, Serialize};
 
 use chrono_tz::Tz;
+use uuid::Uuid;
+
+use crate::curve::PowerCurve;
+use crate::event::LifecycleEventType;
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
 /// 价格配置
 pub struct PriceConf {
     #[serde(default = "price_conf_path")]
     /// 价格配置文件路径
     pub path: String,
+    #[serde(default)]
+    /// 开始充电后取消时收取的违约金，为 0 时不收取
+    pub cancellation_fee: f64,
+    #[serde(default)]
+    /// 已充电度数达到该值后取消才收取违约金，单位为kWh，不设置时开始充电后取消即收取
+    pub cancellation_fee_after_kwh: Option<f64>,
 }
 
 fn price_conf_path() -> String {
@@ -23,7 +37,26 @@ impl Default for PriceConf {
     fn default() -> Self {
         PriceConf {
             path: "prices.json".to_string(),
+            cancellation_fee: 0.0,            // 默认不收取违约金
+            cancellation_fee_after_kwh: None, // 默认开始充电后取消即收取
+        }
+    }
+}
+
+impl PriceConf {
+    /// 检查价格配置是否合法
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.cancellation_fee.is_finite() || self.cancellation_fee < 0.0 {
+            return Err("price.cancellation_fee must be a non-negative number".to_string());
+        }
+        if let Some(kwh) = self.cancellation_fee_after_kwh
+            && (!kwh.is_finite() || kwh < 0.0)
+        {
+            return Err(
+                "price.cancellation_fee_after_kwh must be a non-negative number".to_string(),
+            );
         }
+        Ok(())
     }
 }
 
@@ -38,7 +71,100 @@ pub enum ChargeType {
     Slow,
 }
 
+impl FromStr for ChargeType {
+    type Err = String;
+
+    /// 解析充电类型，可以使用 `F`/`T` 或 `fast`/`slow`
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "F" | "fast" => Ok(ChargeType::Fast),
+            "T" | "slow" => Ok(ChargeType::Slow),
+            _ => Err(format!("invalid charge type: {:?}", s)),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+/// 维护时间窗口，时间为本地时间
+pub struct MaintenanceWindow {
+    /// 维护开始时间
+    pub start: NaiveTime,
+    /// 维护结束时间，早于开始时间时表示跨越 0 点
+    pub end: NaiveTime,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// 生效的星期，不设置则每天生效
+    pub days: Option<Vec<Weekday>>,
+}
+
+impl MaintenanceWindow {
+    /// 判断维护窗口是否在指定日期开始
+    pub fn applies_on(&self, date: NaiveDate) -> bool {
+        self.days
+            .as_ref()
+            .is_none_or(|days| days.contains(&date.weekday()))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+/// 维护策略
+pub enum MaintenancePolicy {
+    #[default]
+    #[serde(rename = "drain")]
+    /// 提前排空，等待正在进行的充电完成
+    Drain,
+    #[serde(rename = "interrupt")]
+    /// 维护开始时直接中断充电
+    Interrupt,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+/// 队列已满时新详单的处理方式
+pub enum OverflowPolicy {
+    #[default]
+    #[serde(rename = "reject")]
+    /// 拒绝新详单
+    Reject,
+    #[serde(rename = "replace_last")]
+    /// 取消最后加入队列的等待中详单，新详单排在队尾
+    ReplaceLast,
+    #[serde(rename = "grow")]
+    /// 允许超出队列大小，最多超出 `overflow_cap` 个详单
+    Grow,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+/// 充电桩ID生成方式
+pub enum IdMode {
+    #[default]
+    #[serde(rename = "random")]
+    /// 每次启动随机生成
+    Random,
+    #[serde(rename = "derived")]
+    /// 根据 `id_seed` 确定性生成
+    Derived,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+/// 单个充电桩的定义，用于在一个进程中模拟多个充电桩
+pub struct PileConf {
+    /// 充电类型
+    #[serde(default = "default_charge_type")]
+    pub charge_type: ChargeType,
+    /// 充电功率，单位为kW
+    #[serde(default = "default_power")]
+    pub power: f64,
+    #[serde(default = "default_size")]
+    /// 队列大小
+    pub size: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// 充电桩的更新间隔，单位为毫秒，不指定时使用 `charge.update_interval`
+    pub update_interval: Option<u64>,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
 /// 充电配置
 pub struct ChargeConf {
     /// 充电类型
@@ -50,9 +176,81 @@ pub struct ChargeConf {
     #[serde(default = "default_size")]
     /// 队列大小
     pub size: u32,
-    #[serde(default = "disallow_break")]
-    /// 是否允许中断充电
-    pub allow_break: bool,
+    #[serde(default = "default_connectors")]
+    /// 充电枪数量，最多同时为这么多个详单充电，每个详单都使用充电桩的额定功率
+    pub connectors: u32,
+    #[serde(default = "disallow_manual_break")]
+    /// 是否允许通过键盘模拟充电桩损坏
+    pub manual_break: bool,
+    #[serde(default = "enable_faults")]
+    /// 是否启用充电桩内部的故障来源
+    pub faults_enabled: bool,
+    #[serde(default, skip_serializing)]
+    /// 已弃用，等同于 `manual_break`
+    pub allow_break: Option<bool>,
+    #[serde(default = "default_power_tolerance")]
+    /// 详单期望功率与充电桩功率允许的偏差，单位为kW
+    pub power_tolerance: f64,
+    #[serde(default = "disallow_strict_power_match")]
+    /// 功率偏差超出范围时是否拒绝详单
+    pub strict_power_match: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    /// 维护时间窗口
+    pub maintenance_windows: Vec<MaintenanceWindow>,
+    #[serde(default)]
+    /// 维护策略
+    pub maintenance_policy: MaintenancePolicy,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// 指定的充电桩ID，优先级最高
+    pub id: Option<Uuid>,
+    #[serde(default)]
+    /// 充电桩ID生成方式
+    pub id_mode: IdMode,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// 确定性生成充电桩ID使用的种子
+    pub id_seed: Option<String>,
+    #[serde(default)]
+    /// 队列是否不限长，为 true 时忽略 `size`
+    pub queue_unlimited: bool,
+    #[serde(default)]
+    /// 是否只接受预约（已注册但尚未投入使用），此时拒绝所有新详单
+    pub reservation_only: bool,
+    #[serde(default = "disallow_requeue_after_repair")]
+    /// 故障修复后是否自动恢复被打断的详单
+    pub requeue_after_repair: bool,
+    #[serde(default = "stay_after_breakdown")]
+    /// 手动模拟损坏后是否退出程序，为 false 时保持连接并等待修复
+    pub exit_on_breakdown: bool,
+    #[serde(default)]
+    /// 多个充电桩的定义，为空时只运行一个由上面的类型、功率和队列大小定义的充电桩
+    pub piles: Vec<PileConf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// 队列状态文件路径，设置后每次状态变化时写入，启动时从中恢复
+    pub state_path: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// 充电桩的更新间隔，单位为毫秒，设置后优先于 `time.update_interval`
+    pub update_interval: Option<u64>,
+    #[serde(default)]
+    /// 队列已满时等待进入队列的详单数量上限，为 0 时直接拒绝
+    pub pending_buffer_size: u32,
+    #[serde(default)]
+    /// 队列已满时新详单的处理方式，策略无法接受新详单时才使用等待区
+    pub overflow_policy: OverflowPolicy,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// `grow` 策略下最多可以超出队列大小的详单数量，不指定时等于队列大小
+    pub overflow_cap: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// 详单请求度数的上限，单位为kWh，超过时拒绝详单，不指定时不限制
+    pub max_request_amount: Option<f64>,
+    #[serde(default)]
+    /// 取消的详单是否使用 `canceled` 状态，默认与旧版本一样使用 `interrupted`
+    pub canceled_status: bool,
+    #[serde(default)]
+    /// 功率曲线，每个断点为（充电进度，额定功率的倍数），断点之间线性插值
+    pub power_curve: PowerCurve,
+    #[serde(default = "drop_queue_on_close")]
+    /// 收到关闭请求时是否清空队列，为 `false` 时只中断正在充电的详单，重新打开后继续充电
+    pub drop_queue_on_close: bool,
 }
 
 fn default_charge_type() -> ChargeType {
@@ -67,52 +265,561 @@ fn default_size() -> u32 {
     2 // 默认队列大小为2
 }
 
-fn disallow_break() -> bool {
-    false // 默认不允许中断充电
+fn default_connectors() -> u32 {
+    1 // 默认只有一把充电枪
+}
+
+fn disallow_manual_break() -> bool {
+    false // 默认不允许手动模拟损坏
+}
+
+fn enable_faults() -> bool {
+    true // 默认启用内部故障来源
+}
+
+fn disallow_requeue_after_repair() -> bool {
+    false // 默认故障打断的详单不自动恢复
+}
+
+fn stay_after_breakdown() -> bool {
+    false // 默认损坏后保持连接，等待服务器修复
+}
+
+fn drop_queue_on_close() -> bool {
+    true // 默认关闭时清空队列
+}
+
+fn default_power_tolerance() -> f64 {
+    0.5 // 默认允许 0.5kW 的功率偏差
+}
+
+fn disallow_strict_power_match() -> bool {
+    false // 默认功率不一致时仅警告
 }
 
 impl Default for ChargeConf {
     fn default() -> Self {
         ChargeConf {
-            charge_type: default_charge_type(), // 默认充电类型为快速充电
-            power: default_power(),             // 默认功率为30kW
-            size: default_size(),               // 默认队列大小为2
-            allow_break: false,                 // 默认允许中断充电
+            charge_type: default_charge_type(),    // 默认充电类型为快速充电
+            power: default_power(),                // 默认功率为30kW
+            size: default_size(),                  // 默认队列大小为2
+            connectors: default_connectors(),      // 默认只有一把充电枪
+            manual_break: disallow_manual_break(), // 默认不允许手动模拟损坏
+            faults_enabled: enable_faults(),       // 默认启用内部故障来源
+            allow_break: None,
+            power_tolerance: default_power_tolerance(),
+            strict_power_match: disallow_strict_power_match(),
+            maintenance_windows: Vec::new(), // 默认没有维护窗口
+            maintenance_policy: MaintenancePolicy::default(), // 默认提前排空
+            id: None,                        // 默认不指定充电桩ID
+            id_mode: IdMode::default(),      // 默认随机生成充电桩ID
+            id_seed: None,
+            requeue_after_repair: disallow_requeue_after_repair(),
+            exit_on_breakdown: stay_after_breakdown(),
+            piles: Vec::new(),                          // 默认只运行一个充电桩
+            state_path: None,                           // 默认不保存队列状态
+            update_interval: None,                      // 默认使用全局更新间隔
+            pending_buffer_size: 0,                     // 默认队列已满时直接拒绝
+            overflow_policy: OverflowPolicy::default(), // 默认队列已满时拒绝新详单
+            overflow_cap: None,                         // 默认最多超出一倍队列大小
+            max_request_amount: None,                   // 默认不限制请求度数
+            queue_unlimited: false,                     // 默认队列有长度限制
+            reservation_only: false,                    // 默认接收新详单
+            canceled_status: false,                     // 默认取消的详单标记为中断
+            power_curve: PowerCurve::default(),         // 默认始终以额定功率充电
+            drop_queue_on_close: drop_queue_on_close(),
         }
     }
 }
 
+impl ChargeConf {
+    /// 将已弃用的配置项迁移到新的配置项
+    pub fn migrate_deprecated(&mut self) {
+        if let Some(allow_break) = self.allow_break.take() {
+            tracing::warn!(
+                "配置项 charge.allow_break 已弃用，请改用 charge.manual_break（仅控制键盘模拟损坏）"
+            );
+            self.manual_break |= allow_break;
+        }
+    }
+
+    /// 指定充电桩的状态文件路径，第一个充电桩使用配置的路径，其余的在路径后加上序号
+    pub fn state_path_for(&self, index: usize) -> Option<String> {
+        self.state_path.as_ref().map(|path| match index {
+            0 => path.clone(),
+            _ => format!("{}.{}", path, index),
+        })
+    }
+
+    /// 是否配置了多个充电桩
+    pub fn is_multi_pile(&self) -> bool {
+        !self.piles.is_empty()
+    }
+
+    /// 需要运行的充电桩，没有配置 `piles` 时只有一个充电桩
+    pub fn pile_specs(&self) -> Vec<PileConf> {
+        if self.is_multi_pile() {
+            self.piles
+                .iter()
+                .map(|pile| PileConf {
+                    update_interval: pile.update_interval.or(self.update_interval),
+                    ..pile.clone()
+                })
+                .collect()
+        } else {
+            vec![PileConf {
+                charge_type: self.charge_type,
+                power: self.power,
+                size: self.size,
+                update_interval: self.update_interval,
+            }]
+        }
+    }
+
+    /// 检查充电配置是否合法
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(max) = self.max_request_amount
+            && !(max.is_finite() && max > 0.0)
+        {
+            return Err(format!(
+                "charge.max_request_amount must be a positive number, got {}",
+                max
+            ));
+        }
+        if self.size == 0 && !self.queue_unlimited {
+            return Err(
+                "charge.size must be greater than 0 (set charge.queue_unlimited = true for an unlimited queue, or charge.reservation_only = true to reject new details)"
+                    .to_string(),
+            );
+        }
+        if self.connectors == 0 {
+            return Err("charge.connectors must be greater than 0".to_string());
+        }
+        if let Some(index) = self.piles.iter().position(|pile| pile.size == 0)
+            && !self.queue_unlimited
+        {
+            return Err(format!(
+                "charge.piles[{}].size must be greater than 0",
+                index
+            ));
+        }
+        if self.piles.len() > 1 && self.id.is_some() {
+            return Err(
+                "charge.id cannot be used with multiple piles (use charge.id_mode = \"derived\" instead)"
+                    .to_string(),
+            );
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+/// 配置了多个 WebSocket 地址时每次连接的尝试顺序
+pub enum FailoverMode {
+    #[default]
+    #[serde(rename = "ordered")]
+    /// 总是从第一个地址开始依次尝试，优先使用靠前的地址
+    Ordered,
+    #[serde(rename = "round_robin")]
+    /// 从上一次连接的地址的下一个开始依次尝试
+    RoundRobin,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+/// 充电状态更新方式
+pub enum UpdateMode {
+    #[default]
+    #[serde(rename = "full")]
+    /// 每次发送完整详单
+    Full,
+    #[serde(rename = "delta")]
+    /// 定期更新只发送变化的字段，并定期发送完整快照
+    Delta,
+}
+
+impl UpdateMode {
+    /// 是否为完整更新
+    pub fn is_full(&self) -> bool {
+        *self == UpdateMode::Full
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+/// 协议消息的编码方式
+pub enum WireEncoding {
+    #[default]
+    #[serde(rename = "json")]
+    /// JSON 文本帧
+    Json,
+    #[serde(rename = "msgpack")]
+    /// MessagePack 二进制帧
+    Msgpack,
+}
+
+impl WireEncoding {
+    /// 是否为 JSON 编码
+    pub fn is_json(&self) -> bool {
+        *self == WireEncoding::Json
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+/// 心跳方式
+pub enum HeartbeatMode {
+    #[default]
+    #[serde(rename = "ping")]
+    /// 发送 WebSocket Ping
+    Ping,
+    #[serde(rename = "message")]
+    /// 发送 `heartbeat` 消息，附带充电桩状态
+    Message,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
 /// WebSocket配置
 pub struct WebSocketConf {
+    #[serde(default = "enable_websocket")]
+    /// 是否连接 WebSocket 服务器，为 `false` 时独立运行，由 `standalone` 配置的本地来源驱动充电桩
+    pub enabled: bool,
     #[serde(default = "default_websocket_url")]
     /// WebSocket URL
     pub url: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    /// 按顺序尝试的多个 WebSocket 地址，设置时代替 `url`
+    pub urls: Vec<String>,
+    #[serde(default)]
+    /// 配置了多个地址时每次连接的尝试顺序
+    pub failover: FailoverMode,
+    #[serde(default = "default_idle_after_register_s")]
+    /// 注册后等待服务器第一条消息的时间，单位为秒，为 0 时不检查
+    pub idle_after_register_s: u64,
+    #[serde(default = "default_idle_probe")]
+    /// 等待超时后是否发送探测消息
+    pub idle_probe: bool,
+    #[serde(default)]
+    /// 充电状态更新方式
+    pub update_mode: UpdateMode,
+    #[serde(default)]
+    /// 希望使用的消息编码方式，服务器在注册确认中同意后才切换，注册消息总是使用 JSON
+    pub encoding: WireEncoding,
+    #[serde(default = "default_snapshot_every")]
+    /// 增量模式下每隔多少次更新发送一次完整快照
+    pub snapshot_every: u32,
+    #[serde(default)]
+    /// 是否拒绝包含未知字段的入站消息
+    pub strict_fields: bool,
+    #[serde(default)]
+    /// 新详单加入队列后是否回复 `ack` 消息
+    pub ack_new: bool,
+    #[serde(default = "default_max_unacked_updates")]
+    /// 没有收到任何入站消息时最多连续发送多少次状态更新，超过后发送探测消息，仍无响应则断开连接，为 0 时不检查
+    pub max_unacked_updates: u32,
+    #[serde(default = "default_resend_after_s")]
+    /// 完成和故障消息多少秒内没有收到 `ack` 确认时重新发送，重新连接后也会重新发送，为 0 时不等待确认
+    pub resend_after_s: u64,
+    #[serde(default = "default_send_buffer")]
+    /// 出站消息队列的容量，满时丢弃最早的状态更新，完成和故障消息不会被丢弃
+    pub send_buffer: usize,
+    #[serde(default)]
+    /// 同一详单两次状态更新之间的最小间隔，单位为毫秒（真实时间），间隔内只发送最新的状态，为 0 时不限制
+    pub min_update_gap_ms: u64,
+    #[serde(default = "default_max_message_size")]
+    /// 入站消息的最大字节数，超过时回复拒绝消息并丢弃，超过四倍时无法跳过，只能断开连接
+    pub max_message_size: usize,
+    #[serde(default)]
+    /// 心跳间隔，单位为毫秒（真实时间），为 0 时不发送心跳
+    pub heartbeat_interval: u64,
+    #[serde(default)]
+    /// 心跳方式
+    pub heartbeat_mode: HeartbeatMode,
+    #[serde(default = "default_heartbeat_max_missed")]
+    /// 连续多少个心跳间隔没有收到任何入站消息（包括 Pong）时认为连接已断开，为 0 时不检查
+    pub heartbeat_max_missed: u32,
+    #[serde(default)]
+    /// 注册后等待服务器 `register_ack` 确认的时间，单位为秒（真实时间），确认前收到的新详单暂不处理，为 0 时不等待
+    pub register_ack_timeout_s: u64,
+    #[serde(default)]
+    /// 服务器确认的协议版本与充电桩不一致时是否断开连接
+    pub abort_on_version_mismatch: bool,
+    #[serde(default)]
+    /// `wss://` 连接使用的 TLS 配置
+    pub tls: TlsConf,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// 连接服务器使用的认证令牌
+    pub auth_token: Option<String>,
+    #[serde(default)]
+    /// 认证令牌的发送方式
+    pub auth_mode: AuthMode,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    /// 握手时附加的 HTTP 请求头
+    pub headers: BTreeMap<String, String>,
+}
+
+impl WebSocketConf {
+    /// 连接时尝试的所有地址，设置了 `urls` 时为 `urls`，否则只有 `url`
+    pub fn endpoints(&self) -> Vec<String> {
+        if self.urls.is_empty() {
+            vec![self.url.clone()]
+        } else {
+            self.urls.clone()
+        }
+    }
+
+    /// 握手时附加的请求头，`header` 方式下认证令牌以 `Authorization: Bearer <令牌>` 发送并覆盖同名请求头
+    pub fn handshake_headers(&self) -> Vec<(String, String)> {
+        let token = match self.auth_mode {
+            AuthMode::Header => self.auth_token.as_deref(),
+            AuthMode::Register => None,
+        };
+        let mut headers: Vec<(String, String)> = self
+            .headers
+            .iter()
+            .filter(|(name, _)| token.is_none() || !name.eq_ignore_ascii_case("authorization"))
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+        if let Some(token) = token {
+            headers.push(("Authorization".to_string(), format!("Bearer {}", token)));
+        }
+        headers
+    }
+
+    /// `register` 方式下注册消息中携带的认证令牌
+    pub fn register_token(&self) -> Option<&str> {
+        match self.auth_mode {
+            AuthMode::Register => self.auth_token.as_deref(),
+            AuthMode::Header => None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+/// 认证令牌的发送方式
+pub enum AuthMode {
+    #[default]
+    #[serde(rename = "header")]
+    /// 握手时通过 `Authorization` 请求头发送
+    Header,
+    #[serde(rename = "register")]
+    /// 通过注册消息的 `auth_token` 字段发送
+    Register,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(deny_unknown_fields)]
+/// WebSocket TLS 配置，都不设置时使用内置的根证书且不提供客户端证书
+pub struct TlsConf {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// 验证服务器证书使用的 CA 证书（PEM）路径，设置后只信任其中的证书
+    pub ca_cert_path: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// 客户端证书链（PEM）路径，需要与 `client_key_path` 同时设置
+    pub client_cert_path: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// 客户端私钥（PEM）路径
+    pub client_key_path: Option<String>,
+    #[serde(default)]
+    /// 是否跳过服务器证书验证，仅用于测试环境
+    pub insecure_skip_verify: bool,
+}
+
+impl TlsConf {
+    /// 是否使用默认的 TLS 配置
+    pub fn is_default(&self) -> bool {
+        self.ca_cert_path.is_none()
+            && self.client_cert_path.is_none()
+            && self.client_key_path.is_none()
+            && !self.insecure_skip_verify
+    }
+
+    /// 检查客户端证书和私钥是否同时设置
+    pub fn validate(&self) -> Result<(), String> {
+        if self.client_cert_path.is_some() != self.client_key_path.is_some() {
+            return Err(
+                "websocket.tls.client_cert_path and websocket.tls.client_key_path must be set together"
+                    .to_string(),
+            );
+        }
+        Ok(())
+    }
+}
+
+fn enable_websocket() -> bool {
+    true // 默认连接服务器
 }
 
 fn default_websocket_url() -> String {
     "ws://localhost:8080/ws".to_string() // 默认WebSocket URL
 }
 
+fn default_idle_after_register_s() -> u64 {
+    0 // 默认不检查
+}
+
+fn default_idle_probe() -> bool {
+    true // 默认发送探测消息
+}
+
+fn default_snapshot_every() -> u32 {
+    10 // 默认每 10 次更新发送一次完整快照
+}
+
+fn default_max_unacked_updates() -> u32 {
+    0 // 默认不检查
+}
+
+fn default_resend_after_s() -> u64 {
+    0 // 默认不等待确认，兼容不发送确认的服务器
+}
+
+fn default_send_buffer() -> usize {
+    256 // 默认最多缓存 256 条消息
+}
+
+fn default_max_message_size() -> usize {
+    64 << 20 // 默认与 tungstenite 相同，最大 64 MiB
+}
+
+fn default_heartbeat_max_missed() -> u32 {
+    3 // 默认 3 个心跳间隔没有响应时断开连接
+}
+
 impl Default for WebSocketConf {
     fn default() -> Self {
         WebSocketConf {
+            enabled: enable_websocket(),
             url: default_websocket_url(),
+            urls: Vec::new(),                  // 默认只使用 url
+            failover: FailoverMode::default(), // 默认按顺序尝试
+            idle_after_register_s: default_idle_after_register_s(),
+            idle_probe: default_idle_probe(),
+            update_mode: UpdateMode::default(),
+            encoding: WireEncoding::default(), // 默认使用 JSON
+            snapshot_every: default_snapshot_every(),
+            strict_fields: false, // 默认只记录未知字段
+            ack_new: false,       // 默认只在拒绝时回复
+            max_unacked_updates: default_max_unacked_updates(),
+            resend_after_s: default_resend_after_s(),
+            send_buffer: default_send_buffer(),
+            min_update_gap_ms: 0, // 默认不限制
+            max_message_size: default_max_message_size(),
+            heartbeat_interval: 0, // 默认不发送心跳
+            heartbeat_mode: HeartbeatMode::default(),
+            heartbeat_max_missed: default_heartbeat_max_missed(),
+            register_ack_timeout_s: 0, // 默认不等待确认，兼容不发送确认的服务器
+            abort_on_version_mismatch: false, // 默认只记录错误
+            tls: TlsConf::default(),   // 默认使用内置的根证书
+            auth_token: None,          // 默认不认证
+            auth_mode: AuthMode::default(),
+            headers: BTreeMap::new(),
         }
     }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+/// 生命周期 Webhook 配置
+pub struct WebhookConf {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// 详单加入队列时通知的 URL
+    pub on_admitted: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// 开始充电时通知的 URL
+    pub on_started: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// 充电完成时通知的 URL
+    pub on_completed: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// 充电中断时通知的 URL
+    pub on_interrupted: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// 认证令牌，设置后以 `Authorization: Bearer <token>` 发送
+    pub auth_token: Option<String>,
+    #[serde(default = "default_webhook_retries")]
+    /// 发送失败后的重试次数
+    pub retries: u32,
+    #[serde(default = "default_webhook_retry_delay")]
+    /// 重试间隔，单位为毫秒
+    pub retry_delay: u64,
+    #[serde(default = "default_webhook_timeout")]
+    /// 单次请求超时时间，单位为毫秒
+    pub timeout: u64,
+}
+
+fn default_webhook_retries() -> u32 {
+    3 // 默认重试 3 次
+}
+
+fn default_webhook_retry_delay() -> u64 {
+    1000 // 默认重试间隔 1 秒
+}
+
+fn default_webhook_timeout() -> u64 {
+    5000 // 默认超时 5 秒
+}
+
+impl Default for WebhookConf {
+    fn default() -> Self {
+        WebhookConf {
+            on_admitted: None,
+            on_started: None,
+            on_completed: None,
+            on_interrupted: None,
+            auth_token: None,
+            retries: default_webhook_retries(),
+            retry_delay: default_webhook_retry_delay(),
+            timeout: default_webhook_timeout(),
+        }
+    }
+}
+
+impl WebhookConf {
+    /// 获取指定事件的通知 URL
+    pub fn url_for(&self, event: LifecycleEventType) -> Option<&str> {
+        match event {
+            LifecycleEventType::Admitted => self.on_admitted.as_deref(),
+            LifecycleEventType::Started => self.on_started.as_deref(),
+            LifecycleEventType::Completed => self.on_completed.as_deref(),
+            LifecycleEventType::Interrupted => self.on_interrupted.as_deref(),
+        }
+    }
+
+    /// 是否配置了任意一个通知 URL
+    pub fn is_enabled(&self) -> bool {
+        self.on_admitted.is_some()
+            || self.on_started.is_some()
+            || self.on_completed.is_some()
+            || self.on_interrupted.is_some()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct TimeConf {
     #[serde(default = "default_update_interval")]
     /// 更新间隔，单位为毫秒
     pub update_interval: u64,
+    #[serde(default = "default_min_update_interval")]
+    /// 详单可以指定的最短更新间隔，单位为毫秒
+    pub min_update_interval: u64,
     #[serde(default = "default_tz")]
     /// 时区
     pub tz: Tz,
     #[serde(default = "default_speed")]
     /// 加速倍数
-    pub speed: u64,
+    pub speed: f64,
+    #[serde(default = "default_server_sync")]
+    /// 是否按服务器在注册确认中给出的虚拟时间调整虚拟时钟
+    pub server_sync: bool,
+    #[serde(default)]
+    /// 按服务器时间调整虚拟时钟时允许的最大偏移，单位为秒，为 0 时不限制
+    pub max_sync_offset_s: u64,
+    #[serde(default)]
+    /// 是否在空闲时请求服务器跳过空闲时间，只在一个充电桩时生效
+    pub skip_idle: bool,
+    #[serde(default = "default_skip_idle_after")]
+    /// 空闲多久（真实时间）后请求跳过，单位为毫秒
+    pub skip_idle_after_ms: u64,
     #[serde(skip_serializing_if = "Option::is_none")]
     /// 开始时间
     pub start_time: Option<DateTime<chrono::Utc>>,
@@ -122,26 +829,336 @@ fn default_update_interval() -> u64 {
     5000 // 默认更新间隔为5000毫秒（5秒）
 }
 
+fn default_min_update_interval() -> u64 {
+    1000 // 默认详单最短每秒更新一次
+}
+
 fn default_tz() -> Tz {
     "Asia/Shanghai".parse().unwrap() // 默认时区为上海
 }
 
-fn default_speed() -> u64 {
-    1 // 默认加速倍数为1
+fn default_speed() -> f64 {
+    1.0 // 默认加速倍数为1
+}
+
+fn default_server_sync() -> bool {
+    true // 默认按服务器时间调整虚拟时钟
+}
+
+fn default_skip_idle_after() -> u64 {
+    2000 // 默认空闲 2 秒后请求跳过
 }
 
 impl Default for TimeConf {
     fn default() -> Self {
         TimeConf {
             update_interval: default_update_interval(),
+            min_update_interval: default_min_update_interval(),
             tz: default_tz(),
             speed: default_speed(),
+            server_sync: default_server_sync(),
+            max_sync_offset_s: 0, // 默认不限制调整的偏移
+            skip_idle: false,     // 默认不跳过空闲时间
+            skip_idle_after_ms: default_skip_idle_after(),
             start_time: None, // 默认没有开始时间（开始时间为系统当前时间）
         }
     }
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+/// 协议消息审计日志配置
+pub struct AuditConf {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// 审计日志文件路径，不设置时不记录
+    pub path: Option<String>,
+    #[serde(default = "default_audit_max_size_mb")]
+    /// 单个文件的大小上限，单位为 MB，超过时轮换，为 0 时不轮换
+    pub max_size_mb: u64,
+    #[serde(default = "default_audit_max_files")]
+    /// 轮换后保留的旧文件数
+    pub max_files: usize,
+}
+
+fn default_audit_max_size_mb() -> u64 {
+    100 // 默认每个文件最大 100 MB
+}
+
+fn default_audit_max_files() -> usize {
+    5 // 默认保留 5 个旧文件
+}
+
+impl Default for AuditConf {
+    fn default() -> Self {
+        AuditConf {
+            path: None, // 默认不记录
+            max_size_mb: default_audit_max_size_mb(),
+            max_files: default_audit_max_files(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+/// 功率记录配置
+pub struct TraceConf {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// 功率记录文件路径，不设置时不记录
+    pub power_path: Option<String>,
+    #[serde(default = "default_sample_interval_s")]
+    /// 采样间隔，单位为虚拟时间秒
+    pub sample_interval_s: u64,
+}
+
+fn default_sample_interval_s() -> u64 {
+    60 // 默认每虚拟分钟采样一次
+}
+
+impl Default for TraceConf {
+    fn default() -> Self {
+        TraceConf {
+            power_path: None,
+            sample_interval_s: default_sample_interval_s(),
+        }
+    }
+}
+
+impl TraceConf {
+    /// 检查采样间隔是否合法
+    pub fn validate(&self) -> Result<(), String> {
+        if self.sample_interval_s == 0 {
+            return Err("trace.sample_interval_s must be greater than 0".to_string());
+        }
+        Ok(())
+    }
+
+    /// 指定充电桩的功率记录文件路径，第一个充电桩使用配置的路径，其余的在路径后加上序号
+    pub fn power_path_for(&self, index: usize) -> Option<String> {
+        self.power_path.as_ref().map(|path| match index {
+            0 => path.clone(),
+            _ => format!("{}.{}", path, index),
+        })
+    }
+
+    /// 多个充电桩时整个充电站的功率记录文件路径，在路径后加上 `.station`
+    pub fn station_path(&self) -> Option<String> {
+        self.power_path
+            .as_ref()
+            .map(|path| format!("{}.station", path))
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+/// 独立运行配置，`websocket.enabled = false` 时使用
+pub struct StandaloneConf {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// 场景文件路径，设置时按场景驱动充电桩，否则使用内置的详单生成器
+    pub scenario: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// 充电桩发送的消息按 JSON 行写入的文件，不设置时写入标准输出
+    pub output: Option<String>,
+    #[serde(default = "default_standalone_details")]
+    /// 内置生成器生成的详单数
+    pub details: u32,
+    #[serde(default = "default_standalone_seed")]
+    /// 内置生成器的随机数种子，种子相同时生成的详单相同
+    pub seed: u64,
+    #[serde(default = "default_standalone_min_amount")]
+    /// 生成的详单的最小请求充电量，单位为kWh
+    pub min_amount: f64,
+    #[serde(default = "default_standalone_max_amount")]
+    /// 生成的详单的最大请求充电量，单位为kWh
+    pub max_amount: f64,
+}
+
+fn default_standalone_details() -> u32 {
+    10 // 默认生成 10 个详单
+}
+
+fn default_standalone_seed() -> u64 {
+    1 // 默认使用固定的种子，每次运行结果相同
+}
+
+fn default_standalone_min_amount() -> f64 {
+    5.0 // 默认最少请求 5 kWh
+}
+
+fn default_standalone_max_amount() -> f64 {
+    30.0 // 默认最多请求 30 kWh
+}
+
+impl Default for StandaloneConf {
+    fn default() -> Self {
+        StandaloneConf {
+            scenario: None, // 默认使用内置生成器
+            output: None,   // 默认写入标准输出
+            details: default_standalone_details(),
+            seed: default_standalone_seed(),
+            min_amount: default_standalone_min_amount(),
+            max_amount: default_standalone_max_amount(),
+        }
+    }
+}
+
+impl StandaloneConf {
+    /// 检查生成的请求充电量范围是否合法
+    pub fn validate(&self) -> Result<(), String> {
+        if !(self.min_amount.is_finite() && self.min_amount > 0.0) {
+            return Err(format!(
+                "standalone.min_amount must be positive: {}",
+                self.min_amount
+            ));
+        }
+        if !(self.max_amount.is_finite() && self.max_amount >= self.min_amount) {
+            return Err(format!(
+                "standalone.max_amount must be at least min_amount: {}",
+                self.max_amount
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+/// 指标配置
+pub struct MetricsConf {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// `/metrics` 监听地址，例如 `127.0.0.1:9100`，不设置时不启动
+    pub listen: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+/// 日志文件轮换周期，按 UTC 时间轮换
+pub enum LogRotation {
+    #[default]
+    #[serde(rename = "daily")]
+    /// 每天一个文件
+    Daily,
+    #[serde(rename = "hourly")]
+    /// 每小时一个文件
+    Hourly,
+    #[serde(rename = "never")]
+    /// 不轮换，始终写入同一个文件
+    Never,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+/// 文件日志格式
+pub enum LogFormat {
+    #[default]
+    #[serde(rename = "json")]
+    /// 每行一个 JSON 对象，包括 span 的创建和关闭事件
+    Json,
+    #[serde(rename = "plain")]
+    /// 与控制台相同的文本格式，不带颜色
+    Plain,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+/// 日志配置，时间格式只影响控制台输出，文件日志始终使用 UTC
+pub struct LogConf {
+    #[serde(default = "default_log_dir")]
+    /// 日志文件目录，文件名为 `app_<进程 ID>.log` 加上轮换周期的后缀
+    pub dir: String,
+    #[serde(default = "default_file_level")]
+    /// 文件日志级别，使用 `EnvFilter` 语法（如 `info,taranis::client=debug`）
+    pub file_level: String,
+    #[serde(default = "default_console_level")]
+    /// 控制台日志级别，语法与 `file_level` 相同
+    pub console_level: String,
+    #[serde(default = "enable_console_log")]
+    /// 是否输出控制台日志，为 `false` 时不输出到标准错误
+    pub console: bool,
+    #[serde(default)]
+    /// 日志文件轮换周期
+    pub rotation: LogRotation,
+    #[serde(default)]
+    /// 日志目录中最多保留的日志文件数，包括其他进程的文件，为 0 时不删除
+    pub max_files: usize,
+    #[serde(default)]
+    /// 文件日志格式
+    pub format: LogFormat,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// 控制台时间格式，使用 strftime 语法
+    pub console_time_format: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// 控制台时区，不设置时日志时间使用系统时区，虚拟时间使用 UTC
+    pub console_time_zone: Option<Tz>,
+    #[serde(default = "default_warn_throttle_s")]
+    /// 相同警告的限流窗口，单位为秒，为 0 时不限流
+    pub warn_throttle_s: u64,
+}
+
+fn default_warn_throttle_s() -> u64 {
+    30 // 默认相同警告 30 秒内只输出一次
+}
+
+fn default_log_dir() -> String {
+    "logs".to_string() // 默认在当前目录的 logs 下
+}
+
+fn default_file_level() -> String {
+    "trace".to_string() // 默认文件记录所有日志
+}
+
+fn default_console_level() -> String {
+    if cfg!(debug_assertions) {
+        "debug".to_string() // 调试构建默认输出调试日志
+    } else {
+        "info".to_string()
+    }
+}
+
+fn enable_console_log() -> bool {
+    true // 默认输出控制台日志
+}
+
+impl Default for LogConf {
+    fn default() -> Self {
+        LogConf {
+            dir: default_log_dir(),
+            file_level: default_file_level(),
+            console_level: default_console_level(),
+            console: enable_console_log(),
+            rotation: LogRotation::default(),
+            max_files: 0,
+            format: LogFormat::default(),
+            console_time_format: None,
+            console_time_zone: None,
+            warn_throttle_s: default_warn_throttle_s(),
+        }
+    }
+}
+
+impl LogConf {
+    /// 检查时间格式是否合法
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(format) = &self.console_time_format
+            && StrftimeItems::new(format).any(|item| matches!(item, Item::Error))
+        {
+            return Err(format!("invalid console_time_format: {:?}", format));
+        }
+        Ok(())
+    }
+
+    /// 检查日志级别是否合法
+    pub fn validate_levels(&self) -> Result<(), String> {
+        for (name, level) in [
+            ("file_level", &self.file_level),
+            ("console_level", &self.console_level),
+        ] {
+            tracing_subscriber::EnvFilter::try_new(level)
+                .map_err(|e| format!("invalid log.{} {:?}: {}", name, level, e))?;
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Default, Clone)]
+#[serde(deny_unknown_fields)]
 /// 全局配置
 pub struct Conf {
     #[serde(rename = "price", default = "PriceConf::default")]
@@ -156,21 +1173,315 @@ pub struct Conf {
     #[serde(rename = "time", default = "TimeConf::default")]
     /// 时间配置
     pub time: TimeConf,
+    #[serde(rename = "webhook", default = "WebhookConf::default")]
+    /// Webhook 配置
+    pub webhook: WebhookConf,
+    #[serde(rename = "log", default = "LogConf::default")]
+    /// 日志配置
+    pub log: LogConf,
+    #[serde(rename = "audit", default = "AuditConf::default")]
+    /// 协议消息审计日志配置
+    pub audit: AuditConf,
+    #[serde(rename = "trace", default = "TraceConf::default")]
+    /// 功率记录配置
+    pub trace: TraceConf,
+    #[serde(rename = "metrics", default = "MetricsConf::default")]
+    /// 指标配置
+    pub metrics: MetricsConf,
+    #[serde(rename = "standalone", default = "StandaloneConf::default")]
+    /// 独立运行配置
+    pub standalone: StandaloneConf,
 }
 
-/// 静态配置实例，使用 LazyLock 确保在第一次访问时加载配置文件
+/// 配置文件路径
+pub const CONF_PATH: &str = "config.toml";
+
+impl Conf {
+    /// 读取并检查配置文件，文件不存在或解析失败时返回错误而不是使用默认配置
+    pub fn from_path(path: &str) -> Result<Conf, String> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("failed to read config file {}: {}", path, e))?;
+        let mut conf = Conf::parse(&content)
+            .map_err(|e| format!("failed to parse config file {}: {}", path, e))?;
+        conf.charge.migrate_deprecated();
+        conf.price.validate()?;
+        conf.charge.validate()?;
+        conf.trace.validate()?;
+        conf.standalone.validate()?;
+        conf.log.validate_levels()?;
+        Ok(conf)
+    }
+}
+
+/// 使用默认配置的命令行参数及对应的环境变量
+const ALLOW_DEFAULT_CONFIG: (&str, &str) =
+    ("--allow-default-config", "TARANIS_ALLOW_DEFAULT_CONFIG");
+
+/// 独立运行的命令行参数及对应的环境变量
+const STANDALONE: (&str, &str) = ("--standalone", "TARANIS_STANDALONE");
+
+/// 终端界面的命令行参数及对应的环境变量
+const TUI: (&str, &str) = ("--tui", "TARANIS_TUI");
+
+/// 命令行参数及对应的环境变量
+const OVERRIDE_OPTIONS: &[(&str, &str)] = &[
+    ("--config", "TARANIS_CONFIG"),
+    ("--ws-url", "TARANIS_WS_URL"),
+    ("--charge-type", "TARANIS_CHARGE_TYPE"),
+    ("--power", "TARANIS_POWER"),
+    ("--size", "TARANIS_SIZE"),
+    ("--speed", "TARANIS_SPEED"),
+    ("--seed-prefix", "TARANIS_SEED_PREFIX"),
+];
+
+#[derive(Debug, Default, Clone, PartialEq)]
+/// 命令行参数和环境变量中的配置覆盖项
+/// 优先级为命令行 > 环境变量 > 配置文件 > 默认值，充电桩相关的覆盖项只作用于 `charge` 本身，不影响 `charge.piles`
+pub struct ConfOverrides {
+    /// 配置文件路径
+    pub config: Option<String>,
+    /// WebSocket URL
+    pub ws_url: Option<String>,
+    /// 充电类型
+    pub charge_type: Option<ChargeType>,
+    /// 充电功率，单位为kW
+    pub power: Option<f64>,
+    /// 队列大小
+    pub size: Option<u32>,
+    /// 时间加速比
+    pub speed: Option<f64>,
+    /// 充电桩ID的种子前缀，设置后所有充电桩都按该前缀确定性生成ID
+    pub seed_prefix: Option<String>,
+    /// 配置文件无法读取或解析时使用默认配置而不是拒绝启动
+    pub allow_default_config: bool,
+    /// 不连接服务器，独立运行
+    pub standalone: bool,
+    /// 在终端中显示充电桩状态，此时日志只写入文件
+    pub tui: bool,
+}
+
+impl ConfOverrides {
+    /// 从命令行参数中取出配置覆盖项，返回覆盖项和其余参数
+    pub fn from_args(args: &[String]) -> Result<(Self, Vec<String>), String> {
+        let mut overrides = ConfOverrides::default();
+        let mut rest = Vec::new();
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            if arg == ALLOW_DEFAULT_CONFIG.0 {
+                overrides.allow_default_config = true;
+                continue;
+            }
+            if arg == STANDALONE.0 {
+                overrides.standalone = true;
+                continue;
+            }
+            if arg == TUI.0 {
+                overrides.tui = true;
+                continue;
+            }
+            if !OVERRIDE_OPTIONS.iter().any(|(option, _)| option == arg) {
+                rest.push(arg.clone());
+                continue;
+            }
+            let value = iter
+                .next()
+                .ok_or_else(|| format!("{} requires a value", arg))?;
+            overrides.set(arg, value)?;
+        }
+        Ok((overrides, rest))
+    }
+
+    /// 从 `TARANIS_*` 环境变量读取配置覆盖项
+    pub fn from_env() -> Result<Self, String> {
+        Self::from_lookup(|key| std::env::var(key).ok())
+    }
+
+    /// 按环境变量名查找值读取配置覆盖项
+    pub fn from_lookup(lookup: impl Fn(&str) -> Option<String>) -> Result<Self, String> {
+        let mut overrides = ConfOverrides::default();
+        for (option, var) in OVERRIDE_OPTIONS {
+            if let Some(value) = lookup(var) {
+                overrides
+                    .set(option, &value)
+                    .map_err(|e| format!("{}: {}", var, e))?;
+            }
+        }
+        overrides.allow_default_config = lookup(ALLOW_DEFAULT_CONFIG.1)
+            .is_some_and(|value| matches!(value.as_str(), "1" | "true"));
+        overrides.standalone =
+            lookup(STANDALONE.1).is_some_and(|value| matches!(value.as_str(), "1" | "true"));
+        overrides.tui = lookup(TUI.1).is_some_and(|value| matches!(value.as_str(), "1" | "true"));
+        Ok(overrides)
+    }
+
+    fn set(&mut self, option: &str, value: &str) -> Result<(), String> {
+        let invalid = |e: &dyn std::fmt::Display| format!("invalid value for {}: {}", option, e);
+        match option {
+            "--config" => self.config = Some(value.to_string()),
+            "--ws-url" => self.ws_url = Some(value.to_string()),
+            "--charge-type" => self.charge_type = Some(value.parse()?),
+            "--power" => self.power = Some(value.parse().map_err(|e| invalid(&e))?),
+            "--size" => self.size = Some(value.parse().map_err(|e| invalid(&e))?),
+            "--speed" => self.speed = Some(value.parse().map_err(|e| invalid(&e))?),
+            "--seed-prefix" if value.is_empty() => return Err(invalid(&"empty seed prefix")),
+            "--seed-prefix" => self.seed_prefix = Some(value.to_string()),
+            _ => return Err(format!("unknown option {}", option)),
+        }
+        Ok(())
+    }
+
+    /// 未设置的覆盖项使用 `lower` 中的值
+    pub fn or(self, lower: ConfOverrides) -> ConfOverrides {
+        ConfOverrides {
+            config: self.config.or(lower.config),
+            ws_url: self.ws_url.or(lower.ws_url),
+            charge_type: self.charge_type.or(lower.charge_type),
+            power: self.power.or(lower.power),
+            size: self.size.or(lower.size),
+            speed: self.speed.or(lower.speed),
+            seed_prefix: self.seed_prefix.or(lower.seed_prefix),
+            allow_default_config: self.allow_default_config || lower.allow_default_config,
+            standalone: self.standalone || lower.standalone,
+            tui: self.tui || lower.tui,
+        }
+    }
+
+    /// 配置文件路径，未指定时为 `config.toml`
+    pub fn config_path(&self) -> &str {
+        self.config.as_deref().unwrap_or(CONF_PATH)
+    }
+
+    /// 把覆盖项写入配置
+    pub fn apply(&self, conf: &mut Conf) {
+        if let Some(url) = &self.ws_url {
+            conf.websocket.url = url.clone();
+            // 命令行或环境变量指定的地址代替配置文件中的地址列表
+            conf.websocket.urls.clear();
+        }
+        if let Some(charge_type) = self.charge_type {
+            conf.charge.charge_type = charge_type;
+        }
+        if let Some(power) = self.power {
+            conf.charge.power = power;
+        }
+        if let Some(size) = self.size {
+            conf.charge.size = size;
+        }
+        if let Some(speed) = self.speed {
+            conf.time.speed = speed;
+        }
+        if let Some(prefix) = &self.seed_prefix {
+            // 命令行或环境变量指定的种子前缀代替配置文件中的充电桩ID
+            conf.charge.id = None;
+            conf.charge.id_mode = IdMode::Derived;
+            conf.charge.id_seed = Some(prefix.clone());
+        }
+        if self.standalone {
+            conf.websocket.enabled = false;
+        }
+        if self.tui {
+            // 控制台日志会与终端界面争用终端
+            conf.log.console = false;
+        }
+    }
+}
+
+/// 启动时设置的配置覆盖项
+static OVERRIDES: OnceLock<ConfOverrides> = OnceLock::new();
+
+/// 设置配置覆盖项，必须在第一次访问 `CONF` 之前调用
+pub fn init_overrides(overrides: ConfOverrides) {
+    if OVERRIDES.set(overrides).is_err() {
+        tracing::warn!("配置覆盖项已经设置，忽略重复设置");
+    }
+}
+
+/// 当前的配置覆盖项，没有调用 `init_overrides` 时只读取环境变量
+pub fn overrides() -> &'static ConfOverrides {
+    OVERRIDES.get_or_init(|| {
+        ConfOverrides::from_env().unwrap_or_else(|e| {
+            tracing::error!("环境变量配置错误: {}", e);
+            panic!("Invalid environment override: {}", e);
+        })
+    })
+}
+
+impl Conf {
+    /// 从 TOML 字符串解析配置，未知字段视为错误，错误信息包含出错的行列和字段
+    pub fn parse(content: &str) -> Result<Conf, String> {
+        toml::from_str(content).map_err(|e: toml::de::Error| e.to_string())
+    }
+
+    /// 启动时读取配置文件，默认的配置文件不存在时使用默认配置
+    /// 指定的配置文件不存在或配置文件解析失败时返回错误
+    fn read_startup(overrides: &ConfOverrides) -> Result<Conf, String> {
+        let path = overrides.config_path();
+        match std::fs::read_to_string(path) {
+            Ok(content) => {
+                tracing::info!("加载配置文件: {}", path);
+                Conf::parse(&content)
+                    .map_err(|e| format!("failed to parse config file {}: {}", path, e))
+            }
+            Err(e) if overrides.config.is_none() && e.kind() == std::io::ErrorKind::NotFound => {
+                tracing::debug!("配置文件不存在: {}，使用默认配置", path);
+                Ok(Conf::default())
+            }
+            Err(e) => Err(format!("failed to read config file {}: {}", path, e)),
+        }
+    }
+
+    /// 读取配置文件并应用覆盖项，用于配置重载
+    pub fn load(overrides: &ConfOverrides) -> Result<Conf, String> {
+        let mut conf = Conf::from_path(overrides.config_path())?;
+        overrides.apply(&mut conf);
+        conf.charge.validate()?;
+        Ok(conf)
+    }
+}
+
+/// 静态配置实例，使用 LazyLock 确保在第一次访问时加载配置文件，并应用命令行参数和环境变量中的覆盖项
 pub static CONF: LazyLock<Conf> = LazyLock::new(|| {
-    let path = "config.toml";
-    let conf = if let Ok(content) = std::fs::read_to_string(path) {
-        tracing::info!("加载配置文件: {}", path);
-        toml::from_str(&content).unwrap_or_else(|_| {
-            tracing::warn!("配置文件解析失败，使用默认配置");
+    let overrides = overrides();
+    let mut conf = match Conf::read_startup(overrides) {
+        Ok(conf) => conf,
+        Err(e) if overrides.allow_default_config => {
+            tracing::warn!("{}，使用默认配置", e);
             Conf::default()
-        })
-    } else {
-        tracing::debug!("配置文件不存在: {}，使用默认配置", path);
-        Conf::default()
+        }
+        Err(e) => {
+            tracing::error!(
+                "配置文件加载失败，拒绝启动（可以使用 --allow-default-config 改为使用默认配置）: {}",
+                e
+            );
+            panic!("Failed to load config: {}", e);
+        }
     };
+    conf.charge.migrate_deprecated();
+    overrides.apply(&mut conf);
+    if let Err(e) = conf.price.validate() {
+        tracing::error!("价格配置错误: {}", e);
+        panic!("Invalid price config: {}", e);
+    }
+    if let Err(e) = conf.charge.validate() {
+        tracing::error!("充电配置错误: {}", e);
+        panic!("Invalid charge config: {}", e);
+    }
+    if let Err(e) = conf.trace.validate() {
+        tracing::error!("功率记录配置错误: {}", e);
+        panic!("Invalid trace config: {}", e);
+    }
+    if let Err(e) = conf.standalone.validate() {
+        tracing::error!("独立运行配置错误: {}", e);
+        panic!("Invalid standalone config: {}", e);
+    }
+    if let Err(e) = conf.log.validate_levels() {
+        tracing::error!("日志配置错误: {}", e);
+        panic!("Invalid log config: {}", e);
+    }
+    if let Err(e) = conf.log.validate() {
+        tracing::error!("{}，使用默认时间格式", e);
+        conf.log.console_time_format = None;
+    }
     tracing::debug!("配置文件内容: {:?}", conf);
     tracing::info!("充电桩类型: {:?}", conf.charge.charge_type);
     tracing::info!("充电功率: {} kW", conf.charge.power);
@@ -185,9 +1496,10 @@ pub static CONF: LazyLock<Conf> = LazyLock::new(|| {
             conf.time.update_interval
         );
     }
-    if conf.time.speed == 0 {
-        tracing::error!("时间加速比为 0，可能会导致严重的运行问题");
-    } else if conf.time.speed > 1 {
+    if !(conf.time.speed.is_finite() && conf.time.speed > 0.0) {
+        tracing::error!("时间加速比必须为正数: {}，使用 1", conf.time.speed);
+        conf.time.speed = 1.0;
+    } else if conf.time.speed > 1.0 {
         tracing::warn!(
             "时间加速比为 {}，过高的加速可能会导致不准确的时间计算",
             conf.time.speed
@@ -200,6 +1512,190 @@ pub static CONF: LazyLock<Conf> = LazyLock::new(|| {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_override_precedence() {
+        let path = std::env::temp_dir().join(format!("taranis-conf-{}.toml", Uuid::new_v4()));
+        std::fs::write(
+            &path,
+            "[charge]\ncharge_type = \"T\"\npower = 7.0\nsize = 4\n\n[time]\nspeed = 10\n\n[websocket]\nurls = [\"ws://a/ws\", \"ws://b/ws\"]\n",
+        )
+        .unwrap();
+        let args: Vec<String> = ["bench", "--power", "11", "--config"]
+            .iter()
+            .map(|s| s.to_string())
+            .chain([path.display().to_string()])
+            .chain(["--virtual-secs".to_string(), "60".to_string()])
+            .collect();
+        let (cli, rest) = ConfOverrides::from_args(&args).unwrap();
+        assert_eq!(rest, ["bench", "--virtual-secs", "60"]);
+        let env = ConfOverrides::from_lookup(|key| match key {
+            "TARANIS_POWER" => Some("22".to_string()),
+            "TARANIS_CHARGE_TYPE" => Some("fast".to_string()),
+            "TARANIS_WS_URL" => Some("ws://127.0.0.1:9000/ws".to_string()),
+            _ => None,
+        })
+        .unwrap();
+        // 命令行 > 环境变量 > 配置文件 > 默认值
+        let conf = Conf::load(&cli.or(env)).unwrap();
+        assert_eq!(conf.charge.power, 11.0);
+        assert_eq!(conf.charge.charge_type, ChargeType::Fast);
+        assert_eq!(conf.websocket.url, "ws://127.0.0.1:9000/ws");
+        // 覆盖的地址代替配置文件中的地址列表
+        assert_eq!(conf.websocket.endpoints(), ["ws://127.0.0.1:9000/ws"]);
+        assert_eq!(conf.charge.size, 4);
+        assert_eq!(conf.time.speed, 10.0);
+        assert_eq!(
+            conf.time.update_interval,
+            TimeConf::default().update_interval
+        );
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(ConfOverrides::from_args(&["--size".to_string()]).is_err());
+
+        // 种子前缀代替配置文件中的充电桩ID，改为 derived 模式
+        let args: Vec<String> = ["--seed-prefix", "station-3"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let (cli, _) = ConfOverrides::from_args(&args).unwrap();
+        let mut conf = Conf::default();
+        conf.charge.id = Some(Uuid::new_v4());
+        cli.apply(&mut conf);
+        assert_eq!(conf.charge.id, None);
+        assert_eq!(conf.charge.id_mode, IdMode::Derived);
+        assert_eq!(conf.charge.id_seed.as_deref(), Some("station-3"));
+        assert!(
+            ConfOverrides::from_lookup(|key| (key == "TARANIS_SEED_PREFIX").then(String::new))
+                .is_err()
+        );
+        assert!(
+            ConfOverrides::from_lookup(|key| (key == "TARANIS_SPEED").then(|| "x".into())).is_err()
+        );
+    }
+
+    #[test]
+    fn test_malformed_config_errors() {
+        let cases = [
+            // 拼错的字段名
+            (
+                "[time]\nupadte_interval = 100\n",
+                &["line 2, column 1", "unknown field `upadte_interval`"][..],
+            ),
+            // 无效的时区
+            (
+                "[time]\ntz = \"Mars/Olympus\"\n",
+                &["line 2, column 6", "Mars/Olympus"][..],
+            ),
+            // 类型错误
+            (
+                "[charge]\npower = \"fast\"\n",
+                &["line 2, column 9", "invalid type"][..],
+            ),
+            // 未知的配置节
+            ("[chrage]\npower = 7.0\n", &["unknown field `chrage`"][..]),
+            // TOML 语法错误
+            ("[charge\n", &["line 1, column 8"][..]),
+        ];
+        for (content, expected) in cases {
+            let error = Conf::parse(content).unwrap_err();
+            for part in expected {
+                assert!(error.contains(part), "{:?} not in {}", part, error);
+            }
+        }
+        assert!(Conf::parse("[charge]\npower = 7.0\n").is_ok());
+
+        // 指定的配置文件不存在时拒绝启动
+        let missing = ConfOverrides {
+            config: Some("/nonexistent/taranis.toml".to_string()),
+            ..ConfOverrides::default()
+        };
+        assert!(Conf::read_startup(&missing).is_err());
+        let (cli, _) = ConfOverrides::from_args(&["--allow-default-config".to_string()]).unwrap();
+        assert!(cli.allow_default_config);
+        // 独立运行参数不带值，关闭 WebSocket 连接
+        let (cli, rest) =
+            ConfOverrides::from_args(&["--standalone".to_string(), "bench".to_string()]).unwrap();
+        assert!(cli.standalone && rest == ["bench"]);
+        let mut conf = Conf::default();
+        assert!(conf.websocket.enabled);
+        cli.apply(&mut conf);
+        assert!(!conf.websocket.enabled);
+        let env = ConfOverrides::from_lookup(|key| {
+            (key == "TARANIS_STANDALONE").then(|| "1".to_string())
+        })
+        .unwrap();
+        assert!(env.standalone);
+        // 终端界面关闭控制台日志
+        let (cli, _) = ConfOverrides::from_args(&["--tui".to_string()]).unwrap();
+        assert!(cli.tui);
+        cli.apply(&mut conf);
+        assert!(!conf.log.console);
+        let standalone = Conf::parse("[standalone]\nmin_amount = 10\nmax_amount = 5\n").unwrap();
+        assert!(standalone.standalone.validate().is_err());
+    }
+
+    #[test]
+    fn test_log_conf() {
+        // 不配置时保持原来的行为：每天轮换、不删除旧文件、JSON 格式、输出控制台日志
+        let conf = Conf::parse("").unwrap().log;
+        assert_eq!(conf.dir, "logs");
+        assert_eq!(conf.file_level, "trace");
+        assert_eq!(conf.rotation, LogRotation::Daily);
+        assert_eq!(
+            (conf.max_files, conf.format, conf.console),
+            (0, LogFormat::Json, true)
+        );
+        assert!(conf.validate_levels().is_ok());
+
+        let conf = Conf::parse(
+            "[log]\ndir = \"/var/log/taranis\"\nconsole = false\nrotation = \"hourly\"\nmax_files = 24\nformat = \"plain\"\nconsole_level = \"warn,taranis::client=debug\"\n",
+        )
+        .unwrap()
+        .log;
+        assert_eq!(conf.rotation, LogRotation::Hourly);
+        assert_eq!(
+            (conf.max_files, conf.format, conf.console),
+            (24, LogFormat::Plain, false)
+        );
+        assert!(conf.validate_levels().is_ok());
+
+        let invalid = LogConf {
+            file_level: "taranis=loud".to_string(),
+            ..LogConf::default()
+        };
+        assert!(
+            invalid
+                .validate_levels()
+                .unwrap_err()
+                .contains("log.file_level")
+        );
+        assert!(Conf::parse("[log]\nrotation = \"weekly\"\n").is_err());
+    }
+
+    #[test]
+    fn test_zero_size_requires_unlimited() {
+        let mut conf = ChargeConf {
+            size: 0,
+            ..ChargeConf::default()
+        };
+        assert!(conf.validate().is_err());
+        conf.queue_unlimited = true;
+        assert!(conf.validate().is_ok());
+        conf.connectors = 0;
+        assert!(conf.validate().unwrap_err().contains("charge.connectors"));
+    }
+
+    #[test]
+    fn test_allow_break_maps_to_manual_break() {
+        let mut conf: Conf = toml::from_str("[charge]\nallow_break = true").unwrap();
+        assert!(!conf.charge.manual_break);
+        conf.charge.migrate_deprecated();
+        assert!(conf.charge.manual_break);
+        assert!(conf.charge.faults_enabled);
+        assert!(conf.charge.allow_break.is_none());
+        assert!(!toml::to_string(&conf).unwrap().contains("allow_break ="));
+    }
+
     #[test]
     fn test_conf_serialization() {
         let conf = Conf::default();
@@ -209,4 +1705,50 @@ mod tests {
             toml::from_str(&toml_str).expect("Failed to deserialize from TOML");
         assert_eq!(conf.price.path, deserialized_conf.price.path);
     }
+
+    #[test]
+    fn test_maintenance_windows_deserialization() {
+        let toml_str = r#"
+            [charge]
+            maintenance_policy = "interrupt"
+
+            [[charge.maintenance_windows]]
+            start = "02:00:00"
+            end = "02:30:00"
+            days = ["Mon", "Sat"]
+        "#;
+        let conf: Conf = toml::from_str(toml_str).unwrap();
+        assert_eq!(conf.charge.maintenance_policy, MaintenancePolicy::Interrupt);
+        let window = &conf.charge.maintenance_windows[0];
+        assert_eq!(window.start, NaiveTime::from_hms_opt(2, 0, 0).unwrap());
+        // 2025-06-02 为周一，2025-06-03 为周二
+        assert!(window.applies_on(NaiveDate::from_ymd_opt(2025, 6, 2).unwrap()));
+        assert!(!window.applies_on(NaiveDate::from_ymd_opt(2025, 6, 3).unwrap()));
+    }
+
+    #[test]
+    fn test_websocket_auth() {
+        let toml_str = r#"
+            [websocket]
+            auth_token = "secret"
+
+            [websocket.headers]
+            Authorization = "Basic old"
+            X-Pile-Group = "north"
+        "#;
+        let mut conf: Conf = toml::from_str(toml_str).unwrap();
+        assert_eq!(conf.websocket.auth_mode, AuthMode::Header);
+        assert_eq!(
+            conf.websocket.handshake_headers(),
+            vec![
+                ("X-Pile-Group".to_string(), "north".to_string()),
+                ("Authorization".to_string(), "Bearer secret".to_string()),
+            ]
+        );
+        assert_eq!(conf.websocket.register_token(), None);
+
+        conf.websocket.auth_mode = AuthMode::Register;
+        assert_eq!(conf.websocket.handshake_headers().len(), 2);
+        assert_eq!(conf.websocket.register_token(), Some("secret"));
+    }
 }
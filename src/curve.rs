@@ -0,0 +1,376 @@
+//! 充电功率曲线
+//!
+//! 电池接近充满时充电功率逐渐降低。[`PowerCurve`] 按充电进度（会话已充电度数占请求度数的比例）
+//! 给出额定功率的倍数，断点之间线性插值，最后一个断点之后保持最后的倍数。
+//! 充电度数按曲线积分：倍数在一段内随进度线性变化时，进度随时间按指数变化，可以直接求出解析解。
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::price::PowerStep;
+
+/// 计算费用时一段功率变化的曲线按进度拆分的最大步长，每一步使用这一步的平均功率
+const PROFILE_STEP: f64 = 0.01;
+
+/// 斜率小于该值时按恒定倍数计算，避免除以接近零的数
+const FLAT_SLOPE: f64 = 1e-12;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(try_from = "Vec<(f64, f64)>", into = "Vec<(f64, f64)>")]
+/// 充电功率曲线，每个断点为（充电进度，功率倍数）
+pub struct PowerCurve {
+    points: Vec<(f64, f64)>,
+}
+
+impl Default for PowerCurve {
+    fn default() -> Self {
+        PowerCurve {
+            points: vec![(0.0, 1.0), (1.0, 1.0)], // 默认始终以额定功率充电
+        }
+    }
+}
+
+impl TryFrom<Vec<(f64, f64)>> for PowerCurve {
+    type Error = String;
+
+    fn try_from(points: Vec<(f64, f64)>) -> Result<Self, Self::Error> {
+        PowerCurve::new(points)
+    }
+}
+
+impl From<PowerCurve> for Vec<(f64, f64)> {
+    fn from(curve: PowerCurve) -> Self {
+        curve.points
+    }
+}
+
+impl PowerCurve {
+    /// 按断点创建功率曲线
+    /// 进度从 0 开始、到 1 结束并且严格递增，倍数为正数
+    pub fn new(points: Vec<(f64, f64)>) -> Result<Self, String> {
+        if points.len() < 2 {
+            return Err("power curve needs at least two points".to_string());
+        }
+        if points.first().unwrap().0 != 0.0 || points.last().unwrap().0 != 1.0 {
+            return Err("power curve must start at progress 0.0 and end at 1.0".to_string());
+        }
+        if let Some(pair) = points
+            .windows(2)
+            .find(|pair| pair[0].0.partial_cmp(&pair[1].0) != Some(std::cmp::Ordering::Less))
+        {
+            return Err(format!(
+                "power curve progress must be strictly increasing, got {} after {}",
+                pair[1].0, pair[0].0
+            ));
+        }
+        if let Some(&(progress, multiplier)) = points
+            .iter()
+            .find(|(_, multiplier)| !(multiplier.is_finite() && *multiplier > 0.0))
+        {
+            return Err(format!(
+                "power curve multiplier must be a positive number, got {} at progress {}",
+                multiplier, progress
+            ));
+        }
+        Ok(PowerCurve { points })
+    }
+
+    /// 所有断点的倍数相同时返回该倍数
+    fn flat(&self) -> Option<f64> {
+        let first = self.points[0].1;
+        self.points
+            .iter()
+            .all(|&(_, multiplier)| multiplier == first)
+            .then_some(first)
+    }
+
+    /// 指定进度下的功率倍数
+    pub fn multiplier(&self, progress: f64) -> f64 {
+        let last = self.points.len() - 1;
+        if progress >= self.points[last].0 {
+            return self.points[last].1;
+        }
+        let index = self.section(progress);
+        self.section_multiplier(index, progress)
+    }
+
+    /// 进度所在的一段的序号，第 `i` 段从第 `i` 个断点到第 `i + 1` 个断点
+    fn section(&self, progress: f64) -> usize {
+        self.points[1..]
+            .iter()
+            .position(|&(end, _)| progress < end)
+            .unwrap_or(self.points.len() - 2)
+    }
+
+    /// 第 `index` 段倍数随进度变化的斜率
+    fn slope(&self, index: usize) -> f64 {
+        let ((x0, m0), (x1, m1)) = (self.points[index], self.points[index + 1]);
+        (m1 - m0) / (x1 - x0)
+    }
+
+    /// 在第 `index` 段内按线性插值计算的倍数
+    fn section_multiplier(&self, index: usize, progress: f64) -> f64 {
+        let (x0, m0) = self.points[index];
+        m0 + self.slope(index) * (progress - x0)
+    }
+
+    /// 在第 `index` 段内（或最后一个断点之后，`index` 为断点数减一）从进度 `from` 充到 `to` 所需的小时数
+    fn section_hours(&self, index: usize, power: f64, request: f64, from: f64, to: f64) -> f64 {
+        if index + 1 >= self.points.len() {
+            return request * (to - from) / (power * self.points[index].1);
+        }
+        let slope = self.slope(index);
+        let start = self.section_multiplier(index, from);
+        if slope.abs() < FLAT_SLOPE {
+            return request * (to - from) / (power * start);
+        }
+        let end = self.section_multiplier(index, to);
+        request / (power * slope) * (end / start).ln()
+    }
+
+    /// 额定功率为 `power` 时从会话已充电 `from_kwh` 度充到 `to_kwh` 度所需的小时数
+    pub fn hours(&self, power: f64, request: f64, from_kwh: f64, to_kwh: f64) -> f64 {
+        if to_kwh <= from_kwh {
+            return 0.0;
+        }
+        if let Some(multiplier) = self.flat()
+            && request > 0.0
+        {
+            return (to_kwh - from_kwh) / (power * multiplier);
+        }
+        if request <= 0.0 {
+            return (to_kwh - from_kwh) / (power * self.points[0].1);
+        }
+        let (mut progress, target) = (from_kwh / request, to_kwh / request);
+        let mut hours = 0.0;
+        while progress < target {
+            let index = self.position(progress);
+            let end = self
+                .points
+                .get(index + 1)
+                .map_or(target, |p| p.0.min(target));
+            hours += self.section_hours(index, power, request, progress, end);
+            progress = end;
+        }
+        hours
+    }
+
+    /// 进度所在的一段的序号，最后一个断点之后为断点数减一
+    fn position(&self, progress: f64) -> usize {
+        if progress >= self.points.last().unwrap().0 {
+            self.points.len() - 1
+        } else {
+            self.section(progress)
+        }
+    }
+
+    /// 额定功率为 `power` 时从会话已充电 `from_kwh` 度开始充电 `hours` 小时充入的度数
+    pub fn energy(&self, power: f64, request: f64, from_kwh: f64, hours: f64) -> f64 {
+        if let Some(multiplier) = self.flat() {
+            return hours * power * multiplier;
+        }
+        if request <= 0.0 {
+            return hours * power * self.points[0].1;
+        }
+        if hours <= 0.0 || power <= 0.0 {
+            return 0.0;
+        }
+        let mut progress = from_kwh / request;
+        let mut remaining = hours;
+        loop {
+            let index = self.position(progress);
+            let Some(&(end, _)) = self.points.get(index + 1) else {
+                // 最后一个断点之后倍数不变
+                progress += power * self.points[index].1 * remaining / request;
+                break;
+            };
+            let needed = self.section_hours(index, power, request, progress, end);
+            if needed >= remaining {
+                let slope = self.slope(index);
+                let start = self.section_multiplier(index, progress);
+                progress = if slope.abs() < FLAT_SLOPE {
+                    progress + power * start * remaining / request
+                } else {
+                    // 倍数 m 满足 dm/dt = slope * power * m / request，按指数变化
+                    let multiplier = start * (slope * power * remaining / request).exp();
+                    self.points[index].0 + (multiplier - self.points[index].1) / slope
+                };
+                break;
+            }
+            remaining -= needed;
+            progress = end;
+        }
+        progress * request - from_kwh
+    }
+
+    /// 会话已充电 `charged_kwh` 度时的瞬时功率
+    pub fn power_at(&self, power: f64, request: f64, charged_kwh: f64) -> f64 {
+        match self.flat() {
+            Some(multiplier) => power * multiplier,
+            None if request > 0.0 => power * self.multiplier(charged_kwh / request),
+            None => power * self.points[0].1,
+        }
+    }
+
+    /// 从 `start` 到 `end` 按曲线充电的功率变化，拆分为功率恒定的几段，用于计算费用
+    /// 每一段的边界为整秒，功率为这一段的平均功率，各段度数之和等于 [`PowerCurve::energy`]
+    pub fn profile(
+        &self,
+        power: f64,
+        request: f64,
+        from_kwh: f64,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> Vec<PowerStep> {
+        let total = (end - start).num_seconds();
+        let flat = match self.flat() {
+            Some(multiplier) => Some(power * multiplier),
+            None if request <= 0.0 || power <= 0.0 || total <= 0 => {
+                Some(self.power_at(power, request, from_kwh))
+            }
+            None => None,
+        };
+        if let Some(power) = flat {
+            return vec![(start, end, power)];
+        }
+        // 功率变化的一段内按进度等分，倍数不变的一段不拆分
+        let mut marks = Vec::new();
+        let from = from_kwh / request;
+        for index in 0..self.points.len() - 1 {
+            let (x0, x1) = (self.points[index].0.max(from), self.points[index + 1].0);
+            if x0 >= x1 {
+                continue;
+            }
+            let steps = if self.slope(index).abs() < FLAT_SLOPE {
+                1
+            } else {
+                ((x1 - x0) / PROFILE_STEP).ceil() as usize
+            };
+            marks.extend((1..=steps).map(|step| x0 + (x1 - x0) * step as f64 / steps as f64));
+        }
+        let mut seconds = vec![0];
+        for mark in marks {
+            let at = (self.hours(power, request, from_kwh, mark * request) * 3600.0).round() as i64;
+            if at >= total {
+                break;
+            }
+            if at > *seconds.last().unwrap() {
+                seconds.push(at);
+            }
+        }
+        seconds.push(total);
+        let energy = |at: i64| self.energy(power, request, from_kwh, at as f64 / 3600.0);
+        seconds
+            .windows(2)
+            .map(|pair| {
+                let (from, to) = (pair[0], pair[1]);
+                let average = (energy(to) - energy(from)) * 3600.0 / (to - from) as f64;
+                let step_end = if to == total {
+                    end
+                } else {
+                    start + chrono::Duration::seconds(to)
+                };
+                (start + chrono::Duration::seconds(from), step_end, average)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 进度 80% 之后线性降到 30%
+    fn taper() -> PowerCurve {
+        PowerCurve::new(vec![(0.0, 1.0), (0.8, 1.0), (1.0, 0.3)]).unwrap()
+    }
+
+    #[test]
+    fn test_curve_validation() {
+        assert!(PowerCurve::new(vec![(0.0, 1.0)]).is_err());
+        assert!(PowerCurve::new(vec![(0.1, 1.0), (1.0, 1.0)]).is_err());
+        assert!(PowerCurve::new(vec![(0.0, 1.0), (0.5, 1.0), (0.5, 0.5), (1.0, 0.5)]).is_err());
+        assert!(PowerCurve::new(vec![(0.0, 1.0), (1.0, 0.0)]).is_err());
+        let curve: PowerCurve =
+            serde_json::from_str("[[0.0, 1.0], [0.8, 1.0], [1.0, 0.3]]").unwrap();
+        assert_eq!(curve, taper());
+        assert_eq!(
+            serde_json::to_value(PowerCurve::default()).unwrap(),
+            serde_json::json!([[0.0, 1.0], [1.0, 1.0]])
+        );
+        assert!((taper().multiplier(0.9) - 0.65).abs() < 1e-12);
+        assert_eq!(taper().multiplier(1.5), 0.3);
+    }
+
+    #[test]
+    fn test_flat_curve_matches_constant_power() {
+        let curve = PowerCurve::default();
+        assert_eq!(curve.hours(30.0, 10.0, 0.0, 10.0), 10.0 / 30.0);
+        assert_eq!(curve.energy(30.0, 10.0, 0.0, 0.25), 0.25 * 30.0);
+        let start = DateTime::parse_from_rfc3339("2025-06-01T00:00:00Z")
+            .unwrap()
+            .to_utc();
+        let end = start + chrono::Duration::minutes(20);
+        assert_eq!(
+            curve.profile(30.0, 10.0, 0.0, start, end),
+            [(start, end, 30.0)]
+        );
+    }
+
+    #[test]
+    fn test_energy_conservation() {
+        let curve = taper();
+        let (power, request) = (30.0, 50.0);
+        for from_kwh in [0.0, 20.0, 45.0] {
+            let hours = curve.hours(power, request, from_kwh, request);
+            // 平缓段之后功率降低，比恒定功率慢
+            assert!(hours > (request - from_kwh) / power);
+            let delivered = curve.energy(power, request, from_kwh, hours);
+            assert!(
+                (delivered - (request - from_kwh)).abs() < 1e-9,
+                "{}",
+                delivered
+            );
+
+            // 按瞬时功率数值积分得到相同的度数
+            let steps = 100_000;
+            let dt = hours / steps as f64;
+            let mut charged = from_kwh;
+            for _ in 0..steps {
+                let half = charged + curve.power_at(power, request, charged) * dt / 2.0;
+                charged += curve.power_at(power, request, half) * dt;
+            }
+            assert!((charged - request).abs() < 1e-6, "{}", charged);
+        }
+    }
+
+    #[test]
+    fn test_profile_sums_to_energy() {
+        let curve = taper();
+        let (power, request) = (30.0, 50.0);
+        let start = DateTime::parse_from_rfc3339("2025-06-01T00:00:00Z")
+            .unwrap()
+            .to_utc();
+        let hours = curve.hours(power, request, 10.0, request);
+        let end = start + chrono::Duration::seconds((hours * 3600.0) as i64);
+        let profile = curve.profile(power, request, 10.0, start, end);
+        assert!(profile.len() > 20);
+        assert_eq!(profile.first().unwrap().0, start);
+        assert_eq!(profile.last().unwrap().1, end);
+        assert!(profile.windows(2).all(|pair| pair[0].1 == pair[1].0));
+        // 功率随进度单调不增
+        assert!(profile.windows(2).all(|pair| pair[0].2 >= pair[1].2 - 1e-9));
+        let kwh: f64 = profile
+            .iter()
+            .map(|(from, to, power)| power * (*to - *from).num_seconds() as f64 / 3600.0)
+            .sum();
+        let expected = curve.energy(
+            power,
+            request,
+            10.0,
+            (end - start).num_seconds() as f64 / 3600.0,
+        );
+        assert!((kwh - expected).abs() < 1e-9);
+        assert!((kwh - (request - 10.0)).abs() < 0.01);
+    }
+}
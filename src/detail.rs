@@ -1,10 +1,17 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
-use crate::{conf::{ChargeType, CONF}, price::round_to_precision};
+use crate::{
+    conf::{CONF, ChargeType},
+    curve::PowerCurve,
+    price::{
+        FeeModel, PeriodUsage, PriceBreakdown, add_money, merge_period_usages, round_to_precision,
+    },
+};
 
-#[derive(Serialize, Deserialize, Clone, PartialEq, Eq)]
-enum ChargeStatus {
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+/// 充电详单状态
+pub enum ChargeStatus {
     #[serde(rename = "waiting")]
     /// 充电等待中
     Waiting,
@@ -17,9 +24,72 @@ enum ChargeStatus {
     #[serde(rename = "interrupted")]
     /// 充电中断
     Interrupted,
+    #[serde(rename = "canceled")]
+    /// 充电取消
+    Canceled,
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+impl ChargeStatus {
+    /// 消息中使用的状态名
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChargeStatus::Waiting => "waiting",
+            ChargeStatus::Charging => "charging",
+            ChargeStatus::Completed => "completed",
+            ChargeStatus::Interrupted => "interrupted",
+            ChargeStatus::Canceled => "canceled",
+        }
+    }
+}
+
+impl std::fmt::Display for ChargeStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq)]
+/// 故障前已完成的充电段
+pub struct ChargeLeg {
+    /// 已充电度数
+    already_charged: f64,
+    /// 充电费用
+    charge_cost: f64,
+    /// 服务费
+    service_fee: f64,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    /// 按价格时段统计的用电量和费用
+    per_period: Vec<PeriodUsage>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// 账单明细
+    breakdown: Option<PriceBreakdown>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+/// 充电详单增量更新，只包含与上一次发送相比变化的字段
+pub struct DetailDelta {
+    /// 充电详单ID
+    pub id: u32,
+    /// 自上一次完整快照以来的序号，从 1 开始
+    pub seq: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// 已经充电度数
+    pub already_charged: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// 充电费用
+    pub charge_cost: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// 服务费
+    pub service_fee: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// 总费用
+    pub total_cost: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// 充电最后更新时间
+    pub last_update_time: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
 /// 充电详单
 pub struct ChargingDetail {
     /// 充电详单ID
@@ -45,13 +115,155 @@ pub struct ChargingDetail {
     total_cost: f64,
     /// 充电状态
     status: ChargeStatus,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// 服务器期望的充电功率，单位为kW
+    expected_power: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// 车辆可以接受的最大充电功率，单位为kW，实际功率为它与充电桩功率中较小的一个
+    max_power: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// 充电桩实际功率，单位为kW，由充电桩在接收详单时填写
+    pile_power_kw: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// 开始充电时预计的结束时间
+    initial_estimated_end_time: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// 按当前队列估计的开始时间，在队列中时填写
+    estimated_start_time: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// 按当前队列估计的结束时间，在队列中时填写
+    estimated_end_time: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    /// 按价格时段统计的用电量和费用，充电完成或中断时填写
+    per_period: Vec<PeriodUsage>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// 按时间顺序逐项列出的账单明细，充电完成或中断时填写
+    breakdown: Option<PriceBreakdown>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    /// 是否为故障修复后恢复的详单
+    resumed: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// 恢复前已完成的充电段，本次充电的度数和费用在此基础上累加
+    prior_leg: Option<ChargeLeg>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// 加入充电桩队列的时间
+    enqueued_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    /// 排队等待时长，单位为虚拟秒，充电完成时填写，无法计算时为 `null`
+    wait_duration_s: Option<f64>,
+    #[serde(default)]
+    /// 充电时长，单位为虚拟秒，充电完成时填写，无法计算时为 `null`
+    charge_duration_s: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// 停止原因代码，服务器取消时填写，未给出原因时为 `unspecified`
+    stop_reason: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// 停止原因的文字说明
+    stop_reason_text: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// 开始充电后取消收取的违约金，已计入总费用
+    penalty_fee: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// 按次收取的服务费，充电完成时填写，已计入服务费和总费用
+    session_fee: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// 服务器填写的预计离场时间，用于计算充电完成后的占位费
+    departure_time: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// 充电完成到离场之间按分钟收取的占位费，充电完成时填写，已计入总费用
+    idle_fee: Option<f64>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    /// 是否处于免费充电状态，此时只累计充电度数，不累计费用
+    free_vend: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// 服务器为该详单指定的更新间隔，单位为毫秒，不能小于 `time.min_update_interval`
+    update_interval_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// 充电桩实际使用的更新间隔，单位为毫秒，开始充电时填写
+    effective_update_interval_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// 服务器填写的用户 ID，原样回传
+    user_id: Option<String>,
+    #[serde(flatten)]
+    /// 服务器附加的其他字段（如 `order_no`、`plate`），原样回传
+    extra: serde_json::Map<String, serde_json::Value>,
+}
+
+impl std::fmt::Display for ChargingDetail {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "详单 {} [{}] {:.2}/{:.2} kWh ({:.1}%) 总费用 {:.2}",
+            self.id,
+            self.status,
+            self.already_charged,
+            self.request_amount,
+            self.get_progress() * 100.0,
+            self.total_cost
+        )
+    }
+}
+
+impl std::fmt::Debug for ChargingDetail {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ChargingDetail")
+            .field("id", &self.id)
+            .field("type", &self.type_)
+            .field("status", &self.status)
+            .field("request_amount", &self.request_amount)
+            .field("already_charged", &self.already_charged)
+            .field("total_cost", &self.total_cost)
+            .field("start_time", &self.start_time)
+            .field("end_time", &self.end_time)
+            .finish_non_exhaustive()
+    }
 }
 
+/// 未给出停止原因时使用的原因代码
+pub const UNSPECIFIED_STOP_REASON: &str = "unspecified";
+
 impl ChargingDetail {
+    /// 详单的所有字段名，用于检查入站消息中的未知字段
+    pub const FIELDS: &'static [&'static str] = &[
+        "id",
+        "request_amount",
+        "type",
+        "already_charged",
+        "start_time",
+        "last_update_time",
+        "end_time",
+        "charge_cost",
+        "service_fee",
+        "total_cost",
+        "status",
+        "expected_power",
+        "max_power",
+        "pile_power_kw",
+        "initial_estimated_end_time",
+        "estimated_start_time",
+        "estimated_end_time",
+        "per_period",
+        "breakdown",
+        "resumed",
+        "prior_leg",
+        "enqueued_at",
+        "wait_duration_s",
+        "charge_duration_s",
+        "stop_reason",
+        "stop_reason_text",
+        "penalty_fee",
+        "session_fee",
+        "departure_time",
+        "idle_fee",
+        "free_vend",
+        "update_interval_ms",
+        "effective_update_interval_ms",
+        "user_id",
+    ];
 
     pub fn test_new(id: u32) -> Self {
         ChargingDetail {
-            id: id,
+            id,
             request_amount: 30.0,
             type_: CONF.charge.charge_type,
             already_charged: 0.0,
@@ -62,81 +274,367 @@ impl ChargingDetail {
             service_fee: 0.0,
             total_cost: 0.0,
             status: ChargeStatus::Waiting,
+            expected_power: None,
+            max_power: None,
+            pile_power_kw: None,
+            initial_estimated_end_time: None,
+            estimated_start_time: None,
+            estimated_end_time: None,
+            per_period: Vec::new(),
+            breakdown: None,
+            resumed: false,
+            prior_leg: None,
+            enqueued_at: None,
+            wait_duration_s: None,
+            charge_duration_s: None,
+            stop_reason: None,
+            stop_reason_text: None,
+            penalty_fee: None,
+            session_fee: None,
+            departure_time: None,
+            idle_fee: None,
+            free_vend: false,
+            update_interval_ms: None,
+            effective_update_interval_ms: None,
+            user_id: None,
+            extra: serde_json::Map::new(),
         }
     }
 
     /// 判断充电详单是否已准备好
+    /// 用户 ID 和服务器附加的其他字段不影响判断
     pub fn is_ready(&self) -> bool {
-        return self.already_charged == 0.0
+        self.already_charged == 0.0
             && self.start_time.is_none()
             && self.last_update_time.is_none()
             && self.end_time.is_none()
+            && self.initial_estimated_end_time.is_none()
             && self.charge_cost == 0.0
             && self.service_fee == 0.0
             && self.total_cost == 0.0
-            && self.status == ChargeStatus::Waiting;
+            && self.status == ChargeStatus::Waiting
+            && !self.resumed
+            && self.prior_leg.is_none()
+            && self.penalty_fee.is_none()
+            && self.session_fee.is_none()
+            && self.idle_fee.is_none()
+            && !self.free_vend
+            && self.effective_update_interval_ms.is_none()
+    }
+
+    /// 生成故障修复后继续充电的详单，保留已充电度数和费用
+    /// 只有未充满的中断详单可以恢复
+    pub fn resumption(&self) -> Option<ChargingDetail> {
+        if self.status != ChargeStatus::Interrupted || self.already_charged >= self.request_amount {
+            return None;
+        }
+        Some(ChargingDetail {
+            start_time: None,
+            last_update_time: None,
+            end_time: None,
+            status: ChargeStatus::Waiting,
+            initial_estimated_end_time: None,
+            estimated_start_time: None,
+            estimated_end_time: None,
+            per_period: Vec::new(),
+            breakdown: None,
+            resumed: true,
+            enqueued_at: None,
+            wait_duration_s: None,
+            charge_duration_s: None,
+            prior_leg: Some(ChargeLeg {
+                already_charged: self.already_charged,
+                charge_cost: self.charge_cost,
+                service_fee: self.service_fee,
+                per_period: self.per_period.clone(),
+                breakdown: self.breakdown.clone(),
+            }),
+            ..self.clone()
+        })
+    }
+
+    /// 是否为故障修复后恢复的详单
+    pub fn is_resumed(&self) -> bool {
+        self.resumed
+    }
+
+    /// 将本次充电段的度数和费用加上恢复前已完成的充电段，费用按分相加
+    fn with_prior_leg(
+        &self,
+        already_charged: f64,
+        charge_cost: f64,
+        service_fee: f64,
+    ) -> (f64, f64, f64) {
+        match &self.prior_leg {
+            Some(prior) => (
+                prior.already_charged + already_charged,
+                add_money(prior.charge_cost, charge_cost),
+                add_money(prior.service_fee, service_fee),
+            ),
+            None => (
+                already_charged,
+                round_to_precision(charge_cost, 2),
+                round_to_precision(service_fee, 2),
+            ),
+        }
     }
 
-    /// 启动充电详单
-    pub fn start(&mut self, time: DateTime<Utc>) {
+    /// 启动充电详单，并按充电功率记录预计结束时间
+    pub fn start(&mut self, time: DateTime<Utc>, power: f64, curve: &PowerCurve) {
         if self.status != ChargeStatus::Waiting {
             tracing::error!("无法在非等待状态下开始充电详单");
             panic!("Cannot start charging details when not in waiting state");
         }
         self.start_time = Some(time);
         self.last_update_time = Some(time);
+        self.initial_estimated_end_time = Some(time + self.get_estimated_duration(power, curve));
         self.status = ChargeStatus::Charging;
     }
 
     /// 更新充电详单状态
-    pub fn update_state(&mut self, already_charged: f64, charge_cost: f64, service_fee: f64, time: DateTime<Utc>) {
+    pub fn update_state(
+        &mut self,
+        already_charged: f64,
+        charge_cost: f64,
+        service_fee: f64,
+        time: DateTime<Utc>,
+    ) {
         if self.status != ChargeStatus::Charging {
             tracing::error!("无法在非充电状态下更新充电详单");
             panic!("Cannot update charging details when not in charging state");
         }
+        let (already_charged, charge_cost, service_fee) =
+            self.with_prior_leg(already_charged, charge_cost, service_fee);
         self.last_update_time = Some(time);
         self.already_charged = already_charged;
         self.charge_cost = charge_cost;
         self.service_fee = service_fee;
-        self.total_cost = round_to_precision(charge_cost + service_fee, 2);
+        self.total_cost = add_money(charge_cost, service_fee);
     }
 
     /// 完成充电详单
-    pub fn complete(&mut self, already_charged: f64, charge_coost: f64, service_fee: f64, time: DateTime<Utc>) {
+    pub fn complete(
+        &mut self,
+        already_charged: f64,
+        charge_coost: f64,
+        service_fee: f64,
+        time: DateTime<Utc>,
+    ) {
         if self.status != ChargeStatus::Charging {
             tracing::error!("无法在非充电状态下完成充电详单");
             panic!("Cannot complete charging details when not in charging state");
         }
+        let (already_charged, charge_coost, service_fee) =
+            self.with_prior_leg(already_charged, charge_coost, service_fee);
         self.last_update_time = Some(time);
         self.already_charged = already_charged;
         self.charge_cost = charge_coost;
         self.service_fee = service_fee;
-        self.total_cost = round_to_precision(charge_coost + service_fee, 2);
+        self.total_cost = add_money(charge_coost, service_fee);
         self.end_time = Some(time);
         self.status = ChargeStatus::Completed;
+        self.clear_schedule_estimate();
+        // 恢复的详单跨越了故障，排队和充电时长没有意义
+        if let (false, Some(start)) = (self.resumed, self.start_time) {
+            self.wait_duration_s = self.enqueued_at.map(|enqueued| secs(start - enqueued));
+            self.charge_duration_s = Some(secs(time - start));
+        }
     }
 
     /// 中断充电详单
-    pub fn interrupt(&mut self, already_charged: f64, charge_coost: f64, service_fee: f64, time: DateTime<Utc>) {
+    pub fn interrupt(
+        &mut self,
+        already_charged: f64,
+        charge_coost: f64,
+        service_fee: f64,
+        time: DateTime<Utc>,
+    ) {
         if self.status != ChargeStatus::Charging && self.status != ChargeStatus::Waiting {
             tracing::error!("无法在除充电或等待外状态下中断充电详单");
             panic!("Cannot interrupt charging details when not in charging or waiting state");
         }
+        self.stop(already_charged, charge_coost, service_fee, time);
+        self.status = ChargeStatus::Interrupted;
+    }
+
+    /// 取消充电详单，与中断的区别只在于状态
+    pub fn cancel(
+        &mut self,
+        already_charged: f64,
+        charge_cost: f64,
+        service_fee: f64,
+        time: DateTime<Utc>,
+    ) {
+        if self.status != ChargeStatus::Charging && self.status != ChargeStatus::Waiting {
+            tracing::error!("无法在除充电或等待外状态下取消充电详单");
+            panic!("Cannot cancel charging details when not in charging or waiting state");
+        }
+        self.stop(already_charged, charge_cost, service_fee, time);
+        self.status = ChargeStatus::Canceled;
+    }
+
+    /// 记录停止充电时的度数和费用
+    fn stop(
+        &mut self,
+        already_charged: f64,
+        charge_cost: f64,
+        service_fee: f64,
+        time: DateTime<Utc>,
+    ) {
+        let (already_charged, charge_cost, service_fee) =
+            self.with_prior_leg(already_charged, charge_cost, service_fee);
         self.last_update_time = Some(time);
         self.already_charged = already_charged;
-        self.charge_cost = charge_coost;
+        self.charge_cost = charge_cost;
         self.service_fee = service_fee;
-        self.total_cost = round_to_precision(charge_coost + service_fee, 2);
-        self.status = ChargeStatus::Interrupted;
+        self.total_cost = add_money(charge_cost, service_fee);
+        self.clear_schedule_estimate();
+    }
+
+    /// 收取取消违约金，计入总费用
+    pub fn apply_penalty_fee(&mut self, fee: f64) {
+        let fee = round_to_precision(fee, 2);
+        self.penalty_fee = Some(fee);
+        self.total_cost = add_money(self.total_cost, fee);
+    }
+
+    /// 充电完成时收取按次服务费和占位费，每个详单只收取一次
+    /// 按次服务费计入服务费，占位费按结束时间到预计离场时间的时长计算，两者都计入总费用
+    pub fn apply_completion_fees(&mut self, fee: &FeeModel) {
+        if self.status != ChargeStatus::Completed {
+            tracing::error!("无法在非完成状态下收取按次服务费和占位费");
+            panic!("Cannot apply completion fees when not in completed state");
+        }
+        if self.session_fee.is_some() || self.idle_fee.is_some() {
+            return;
+        }
+        if fee.per_session > 0.0 {
+            let session = round_to_precision(fee.per_session, 2);
+            self.session_fee = Some(session);
+            self.service_fee = add_money(self.service_fee, session);
+            self.total_cost = add_money(self.total_cost, session);
+        }
+        if let (Some(end), Some(departure)) = (self.end_time, self.departure_time)
+            && fee.per_minute_after_complete > 0.0
+            && departure > end
+        {
+            let idle = fee.idle_fee(departure - end);
+            self.idle_fee = Some(idle);
+            self.total_cost = add_money(self.total_cost, idle);
+        }
+    }
+
+    /// 获取按次收取的服务费
+    pub fn get_session_fee(&self) -> Option<f64> {
+        self.session_fee
+    }
+
+    /// 获取占位费
+    pub fn get_idle_fee(&self) -> Option<f64> {
+        self.idle_fee
+    }
+
+    /// 设置预计离场时间
+    pub fn with_departure_time(mut self, time: DateTime<Utc>) -> Self {
+        self.departure_time = Some(time);
+        self
+    }
+
+    /// 服务器为该详单指定的更新间隔，单位为毫秒
+    pub fn get_update_interval_ms(&self) -> Option<u64> {
+        self.update_interval_ms
+    }
+
+    /// 设置充电桩实际使用的更新间隔，单位为毫秒
+    pub fn set_effective_update_interval_ms(&mut self, interval: u64) {
+        self.effective_update_interval_ms = Some(interval);
+    }
+
+    /// 充电桩实际使用的更新间隔，单位为毫秒
+    pub fn get_effective_update_interval_ms(&self) -> Option<u64> {
+        self.effective_update_interval_ms
+    }
+
+    /// 设置是否处于免费充电状态
+    pub fn set_free_vend(&mut self, free_vend: bool) {
+        self.free_vend = free_vend;
+    }
+
+    /// 是否处于免费充电状态
+    pub fn is_free_vend(&self) -> bool {
+        self.free_vend
+    }
+
+    /// 获取取消违约金
+    pub fn get_penalty_fee(&self) -> Option<f64> {
+        self.penalty_fee
+    }
+
+    /// 获取总费用
+    pub fn get_total_cost(&self) -> f64 {
+        self.total_cost
+    }
+
+    /// 获取已充电度数
+    pub fn get_already_charged(&self) -> f64 {
+        self.already_charged
+    }
+
+    /// 获取充电请求度数
+    pub fn get_request_amount(&self) -> f64 {
+        self.request_amount
+    }
+
+    /// 检查请求度数是否为不超过 `max` 的正数
+    pub fn validate_request_amount(&self, max: Option<f64>) -> Result<(), String> {
+        let amount = self.request_amount;
+        if !amount.is_finite() || amount <= 0.0 {
+            return Err(format!(
+                "request_amount must be a positive number, got {}",
+                amount
+            ));
+        }
+        if let Some(max) = max
+            && amount > max
+        {
+            return Err(format!(
+                "request_amount {} exceeds the maximum of {}",
+                amount, max
+            ));
+        }
+        Ok(())
+    }
+
+    /// 获取充电进度，为已充电度数占请求度数的比例，限制在 0 到 1 之间
+    pub fn get_progress(&self) -> f64 {
+        if self.request_amount <= 0.0 {
+            return 0.0;
+        }
+        (self.already_charged / self.request_amount).clamp(0.0, 1.0)
+    }
+
+    /// 获取充电详单状态
+    pub fn get_status(&self) -> ChargeStatus {
+        self.status
+    }
+
+    /// 获取充电开始时间，尚未开始充电时为 `None`
+    pub fn get_start_time(&self) -> Option<DateTime<Utc>> {
+        self.start_time
+    }
+
+    /// 获取充电结束时间，尚未完成充电时为 `None`
+    pub fn get_end_time(&self) -> Option<DateTime<Utc>> {
+        self.end_time
     }
 
     /// 获取充电详单的起始时间
     pub fn clone_start_time(&self) -> DateTime<Utc> {
-        if self.status == ChargeStatus::Waiting{
+        if self.status == ChargeStatus::Waiting {
             tracing::error!("无法在等待状态下获取充电开始时间");
             panic!("Cannot get start time when not in charging state");
         }
-        self.start_time.clone().unwrap()
+        self.start_time.unwrap()
     }
 
     /// 获取充电详单的ID
@@ -145,40 +643,322 @@ impl ChargingDetail {
     }
 
     /// 获取预计充电结束时间
-    pub fn get_estimated_end_time(&self, power: f64) -> Option<DateTime<Utc>> {
+    /// 尚未充电的度数从 `now` 和最后更新时间中较晚的一个开始按功率曲线计算
+    pub fn get_estimated_end_time(
+        &self,
+        power: f64,
+        curve: &PowerCurve,
+        now: DateTime<Utc>,
+    ) -> Option<DateTime<Utc>> {
         if self.status != ChargeStatus::Charging {
             tracing::error!("无法在非充电状态下获取预计充电结束时间");
             return None;
         }
-        let remaining_amount = self.request_amount - self.already_charged;
-        let estimated_duration = remaining_amount / power; // 假设 power 是单位时间内充电的度数
-        Some(self.start_time.unwrap() + chrono::Duration::seconds((estimated_duration * 3600.0) as i64))
+        let base = self.last_update_time.map_or(now, |last| last.max(now));
+        let estimated_duration = self.get_remaining_hours(power, curve);
+        Some(base + chrono::Duration::seconds((estimated_duration * 3600.0) as i64))
+    }
+
+    /// 按功率曲线充满尚未充电的请求度数所需的小时数
+    pub fn get_remaining_hours(&self, power: f64, curve: &PowerCurve) -> f64 {
+        let request = self.request_amount;
+        curve.hours(power, request, self.already_charged, request)
+    }
+
+    /// 获取尚未充电的请求度数
+    pub fn get_remaining_amount(&self) -> f64 {
+        (self.request_amount - self.already_charged).max(0.0)
+    }
+
+    /// 记录按当前队列估计的开始和结束时间
+    pub fn set_schedule_estimate(&mut self, start: DateTime<Utc>, end: DateTime<Utc>) {
+        self.estimated_start_time = Some(start);
+        self.estimated_end_time = Some(end);
+    }
+
+    /// 获取按当前队列估计的开始和结束时间，不在队列中时为 `None`
+    pub fn get_schedule_estimate(&self) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+        Some((self.estimated_start_time?, self.estimated_end_time?))
+    }
+
+    /// 虚拟时钟调整后把详单中的时间平移相同的偏移量，已充电度数和费用保持不变
+    pub fn shift_times(&mut self, offset: chrono::Duration) {
+        for time in [
+            &mut self.start_time,
+            &mut self.last_update_time,
+            &mut self.initial_estimated_end_time,
+            &mut self.estimated_start_time,
+            &mut self.estimated_end_time,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            *time += offset;
+        }
+    }
+
+    /// 离开队列后清除估计的开始和结束时间
+    fn clear_schedule_estimate(&mut self) {
+        self.estimated_start_time = None;
+        self.estimated_end_time = None;
+    }
+
+    /// 获取实际结束时间与开始时预计结束时间的差值，仅对已完成的详单有效
+    pub fn get_eta_error(&self) -> Option<chrono::Duration> {
+        if self.status != ChargeStatus::Completed {
+            return None;
+        }
+        Some(self.end_time? - self.initial_estimated_end_time?)
+    }
+
+    /// 获取恢复前已经充电的度数，不是恢复的详单时为 0
+    pub fn get_prior_energy(&self) -> f64 {
+        self.prior_leg
+            .as_ref()
+            .map_or(0.0, |prior| prior.already_charged)
+    }
+
+    /// 获取按指定功率和功率曲线充满请求度数所需的时长，恢复的详单只计算剩余度数
+    pub fn get_estimated_duration(&self, power: f64, curve: &PowerCurve) -> chrono::Duration {
+        let request = self.request_amount;
+        let hours = curve.hours(power, request, self.get_prior_energy(), request);
+        chrono::Duration::seconds((hours * 3600.0) as i64)
+    }
+
+    /// 获取本段充电需要充的度数，恢复的详单不包括恢复前已经充电的度数
+    pub fn get_leg_request_amount(&self) -> f64 {
+        self.request_amount - self.get_prior_energy()
+    }
+
+    /// 获取按请求电量计算的充满时间，尚未开始充电时为 `None`
+    pub fn get_energy_end_time(&self, power: f64, curve: &PowerCurve) -> Option<DateTime<Utc>> {
+        Some(self.start_time? + self.get_estimated_duration(power, curve))
+    }
+
+    /// 获取充电最后更新时间
+    pub fn get_last_update_time(&self) -> Option<DateTime<Utc>> {
+        self.last_update_time
     }
 
     /// 获取充电详单的类型
     pub fn get_type(&self) -> ChargeType {
         self.type_
     }
+
+    /// 设置充电请求度数
+    pub fn with_request_amount(mut self, amount: f64) -> Self {
+        self.request_amount = amount;
+        self
+    }
+
+    /// 设置充电类型
+    pub fn with_type(mut self, type_: ChargeType) -> Self {
+        self.type_ = type_;
+        self
+    }
+
+    /// 获取服务器填写的用户 ID
+    pub fn get_user_id(&self) -> Option<&str> {
+        self.user_id.as_deref()
+    }
+
+    /// 获取服务器附加的其他字段
+    pub fn get_extra(&self) -> &serde_json::Map<String, serde_json::Value> {
+        &self.extra
+    }
+
+    /// 设置服务器期望的充电功率
+    pub fn with_expected_power(mut self, power: f64) -> Self {
+        self.expected_power = Some(power);
+        self
+    }
+
+    /// 获取服务器期望的充电功率
+    pub fn get_expected_power(&self) -> Option<f64> {
+        self.expected_power
+    }
+
+    /// 设置车辆可以接受的最大充电功率
+    pub fn with_max_power(mut self, power: f64) -> Self {
+        self.max_power = Some(power);
+        self
+    }
+
+    /// 获取车辆可以接受的最大充电功率
+    pub fn get_max_power(&self) -> Option<f64> {
+        self.max_power
+    }
+
+    /// 按车辆最大充电功率限制后的实际充电功率，最大功率不是正数时忽略
+    pub fn effective_power(&self, pile_power: f64) -> f64 {
+        match self.max_power {
+            Some(max) if max.is_finite() && max > 0.0 => max.min(pile_power),
+            _ => pile_power,
+        }
+    }
+
+    /// 设置充电桩实际功率
+    pub fn set_pile_power(&mut self, power: f64) {
+        self.pile_power_kw = Some(power);
+    }
+
+    /// 记录加入充电桩队列的时间
+    pub fn set_enqueued_at(&mut self, time: DateTime<Utc>) {
+        self.enqueued_at = Some(time);
+    }
+
+    /// 记录停止原因，未给出原因代码时使用 `unspecified`
+    pub fn set_stop_reason(&mut self, code: Option<String>, text: Option<String>) {
+        self.stop_reason = Some(code.unwrap_or_else(|| UNSPECIFIED_STOP_REASON.to_string()));
+        self.stop_reason_text = text;
+    }
+
+    /// 获取停止原因代码
+    pub fn get_stop_reason(&self) -> Option<&str> {
+        self.stop_reason.as_deref()
+    }
+
+    /// 获取停止原因的文字说明
+    pub fn get_stop_reason_text(&self) -> Option<&str> {
+        self.stop_reason_text.as_deref()
+    }
+
+    /// 获取排队等待时长，单位为虚拟秒
+    pub fn get_wait_duration_s(&self) -> Option<f64> {
+        self.wait_duration_s
+    }
+
+    /// 获取充电时长，单位为虚拟秒
+    pub fn get_charge_duration_s(&self) -> Option<f64> {
+        self.charge_duration_s
+    }
+
+    /// 设置本次充电段按价格时段统计的用电量和费用，会与恢复前的充电段合并
+    pub fn set_per_period(&mut self, per_period: Vec<PeriodUsage>) {
+        self.per_period = match &self.prior_leg {
+            Some(prior) => merge_period_usages(&prior.per_period, per_period),
+            None => per_period,
+        };
+    }
+
+    /// 获取按价格时段统计的用电量和费用
+    pub fn get_per_period(&self) -> &[PeriodUsage] {
+        &self.per_period
+    }
+
+    /// 设置本次充电段的账单明细，接在恢复前充电段的明细之后
+    pub fn set_breakdown(&mut self, breakdown: PriceBreakdown) {
+        self.breakdown = match self.prior_leg.as_ref().and_then(|p| p.breakdown.clone()) {
+            Some(mut prior) => {
+                prior.append(breakdown);
+                Some(prior)
+            }
+            None => Some(breakdown),
+        };
+    }
+
+    /// 获取账单明细
+    pub fn get_breakdown(&self) -> Option<&PriceBreakdown> {
+        self.breakdown.as_ref()
+    }
+
+    /// 计算与上一次发送的详单相比的增量
+    pub fn delta_since(&self, previous: &ChargingDetail, seq: u64) -> DetailDelta {
+        fn changed<T: PartialEq + Copy>(current: T, previous: T) -> Option<T> {
+            (current != previous).then_some(current)
+        }
+        DetailDelta {
+            id: self.id,
+            seq,
+            already_charged: changed(self.already_charged, previous.already_charged),
+            charge_cost: changed(self.charge_cost, previous.charge_cost),
+            service_fee: changed(self.service_fee, previous.service_fee),
+            total_cost: changed(self.total_cost, previous.total_cost),
+            last_update_time: changed(self.last_update_time, previous.last_update_time).flatten(),
+        }
+    }
+
+    /// 应用增量更新，ID 不一致时返回错误
+    pub fn apply_delta(&mut self, delta: &DetailDelta) -> Result<(), String> {
+        if delta.id != self.id {
+            return Err(format!(
+                "delta for detail {} applied to detail {}",
+                delta.id, self.id
+            ));
+        }
+        if let Some(already_charged) = delta.already_charged {
+            self.already_charged = already_charged;
+        }
+        if let Some(charge_cost) = delta.charge_cost {
+            self.charge_cost = charge_cost;
+        }
+        if let Some(service_fee) = delta.service_fee {
+            self.service_fee = service_fee;
+        }
+        if let Some(total_cost) = delta.total_cost {
+            self.total_cost = total_cost;
+        }
+        if let Some(time) = delta.last_update_time {
+            self.last_update_time = Some(time);
+        }
+        Ok(())
+    }
+}
+
+/// 将时间差转换为秒
+fn secs(duration: chrono::Duration) -> f64 {
+    duration.num_milliseconds() as f64 / 1000.0
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::time::{Clock, ManualClock};
+
+    /// 从固定时间开始的手动时钟
+    fn manual_clock() -> ManualClock {
+        ManualClock::new("2025-06-01T10:00:00Z".parse().unwrap())
+    }
 
     #[test]
     fn test_serialization() {
+        let clock = manual_clock();
         let details = ChargingDetail {
             id: 1,
             request_amount: 100.0,
             type_: ChargeType::Fast,
             already_charged: 50.0,
-            start_time: Some(Utc::now()),
-            last_update_time: Some(Utc::now()),
+            start_time: Some(clock.now()),
+            last_update_time: Some(clock.now()),
             end_time: None,
             charge_cost: 10.0,
             service_fee: 2.0,
             total_cost: 12.0,
             status: ChargeStatus::Charging,
+            expected_power: None,
+            max_power: None,
+            pile_power_kw: Some(30.0),
+            initial_estimated_end_time: None,
+            estimated_start_time: None,
+            estimated_end_time: None,
+            per_period: Vec::new(),
+            breakdown: None,
+            resumed: false,
+            prior_leg: None,
+            enqueued_at: None,
+            wait_duration_s: None,
+            charge_duration_s: None,
+            stop_reason: None,
+            stop_reason_text: None,
+            penalty_fee: None,
+            session_fee: None,
+            departure_time: None,
+            idle_fee: None,
+            free_vend: false,
+            update_interval_ms: None,
+            effective_update_interval_ms: None,
+            user_id: None,
+            extra: serde_json::Map::new(),
         };
 
         let serialized = serde_json::to_string_pretty(&details).unwrap();
@@ -188,4 +968,261 @@ mod tests {
         assert_eq!(details.id, deserialized.id);
         assert_eq!(details.request_amount, deserialized.request_amount);
     }
+
+    #[test]
+    fn test_fields_cover_serialized_keys() {
+        let mut detail = ChargingDetail::test_new(1).with_expected_power(30.0);
+        detail.set_pile_power(30.0);
+        let clock = manual_clock();
+        detail.set_enqueued_at(clock.now());
+        detail.start(
+            clock.advance(chrono::Duration::minutes(1)),
+            30.0,
+            &PowerCurve::default(),
+        );
+        detail.interrupt(1.0, 1.0, 1.0, clock.advance(chrono::Duration::minutes(2)));
+        detail.set_per_period(vec![PeriodUsage {
+            label: "peak".to_string(),
+            kwh: 1.0,
+            cost: 1.0,
+            fee: 1.0,
+        }]);
+        detail.set_stop_reason(None, Some("user requested".to_string()));
+        let resumed = detail.resumption().unwrap();
+        for detail in [detail, resumed] {
+            let value = serde_json::to_value(&detail).unwrap();
+            for key in value.as_object().unwrap().keys() {
+                assert!(ChargingDetail::FIELDS.contains(&key.as_str()), "{}", key);
+            }
+        }
+    }
+
+    #[test]
+    fn test_extra_fields_round_trip() {
+        let mut value = serde_json::to_value(ChargingDetail::test_new(1)).unwrap();
+        let map = value.as_object_mut().unwrap();
+        map.insert("user_id".to_string(), "u-42".into());
+        map.insert("order_no".to_string(), "A-1001".into());
+        map.insert(
+            "plate".to_string(),
+            serde_json::json!({"number": "京A12345"}),
+        );
+        let mut detail: ChargingDetail = serde_json::from_value(value).unwrap();
+        assert_eq!(detail.get_user_id(), Some("u-42"));
+        assert_eq!(detail.get_extra().len(), 2);
+        // 附加字段不影响是否可以接收
+        assert!(detail.is_ready());
+
+        // 状态变化和恢复后附加字段仍然原样回传
+        let clock = manual_clock();
+        detail.start(clock.now(), 30.0, &PowerCurve::default());
+        detail.interrupt(1.0, 1.0, 1.0, clock.advance(chrono::Duration::minutes(2)));
+        let resumed = detail.resumption().unwrap();
+        for detail in [detail, resumed] {
+            let value = serde_json::to_value(&detail).unwrap();
+            assert_eq!(value["user_id"], "u-42");
+            assert_eq!(value["order_no"], "A-1001");
+            assert_eq!(value["plate"]["number"], "京A12345");
+        }
+    }
+
+    #[test]
+    fn test_eta_error() {
+        let start = DateTime::parse_from_rfc3339("2025-06-01T10:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+
+        // 按时完成的会话误差为 0
+        let mut clean = ChargingDetail::test_new(1);
+        clean.start(start, 30.0, &PowerCurve::default());
+        assert_eq!(
+            clean.initial_estimated_end_time,
+            Some(start + chrono::Duration::hours(1))
+        );
+        clean.complete(30.0, 0.0, 0.0, start + chrono::Duration::hours(1));
+        assert_eq!(clean.get_eta_error(), Some(chrono::Duration::zero()));
+
+        // 中途暂停 10 分钟的会话误差为正
+        let mut paused = ChargingDetail::test_new(2);
+        paused.start(start, 30.0, &PowerCurve::default());
+        assert!(paused.get_eta_error().is_none());
+        paused.complete(30.0, 0.0, 0.0, start + chrono::Duration::minutes(70));
+        assert_eq!(paused.get_eta_error(), Some(chrono::Duration::minutes(10)));
+    }
+
+    #[test]
+    fn test_estimated_end_time() {
+        let start = DateTime::parse_from_rfc3339("2025-06-01T10:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let mut detail = ChargingDetail::test_new(1);
+        assert!(
+            detail
+                .get_estimated_end_time(30.0, &PowerCurve::default(), start)
+                .is_none()
+        );
+        detail.start(start, 30.0, &PowerCurve::default());
+        assert_eq!(
+            detail.get_estimated_end_time(30.0, &PowerCurve::default(), start),
+            Some(start + chrono::Duration::hours(1))
+        );
+
+        // 充到一半时剩余的 15 度从最后更新时间开始计算，而不是从开始时间
+        let half = start + chrono::Duration::minutes(30);
+        detail.update_state(15.0, 10.0, 2.0, half);
+        let expected = half + chrono::Duration::minutes(30);
+        assert_eq!(
+            detail.get_estimated_end_time(30.0, &PowerCurve::default(), half),
+            Some(expected)
+        );
+        // 恢复后从当前时间开始计算
+        let restored = half + chrono::Duration::minutes(20);
+        assert_eq!(
+            detail.get_estimated_end_time(30.0, &PowerCurve::default(), restored),
+            Some(restored + chrono::Duration::minutes(30))
+        );
+        // 当前时间早于最后更新时间时使用最后更新时间
+        assert_eq!(
+            detail.get_estimated_end_time(30.0, &PowerCurve::default(), start),
+            Some(expected)
+        );
+    }
+
+    #[test]
+    fn test_accessors() {
+        let start = DateTime::parse_from_rfc3339("2025-06-01T10:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let mut detail = ChargingDetail::test_new(7);
+        assert_eq!(detail.get_status(), ChargeStatus::Waiting);
+        assert_eq!(detail.get_progress(), 0.0);
+        assert!(detail.get_start_time().is_none() && detail.get_end_time().is_none());
+
+        detail.start(start, 30.0, &PowerCurve::default());
+        detail.update_state(12.0, 10.0, 2.0, start + chrono::Duration::minutes(24));
+        assert_eq!(detail.get_status(), ChargeStatus::Charging);
+        assert_eq!(detail.get_request_amount(), 30.0);
+        assert_eq!(detail.get_remaining_amount(), 18.0);
+        assert!((detail.get_progress() - 0.4).abs() < 1e-9);
+        assert_eq!(detail.get_start_time(), Some(start));
+        assert_eq!(
+            detail.to_string(),
+            "详单 7 [charging] 12.00/30.00 kWh (40.0%) 总费用 12.00"
+        );
+        assert!(format!("{:?}", detail).contains("already_charged: 12.0"));
+
+        // 超出请求度数时进度不超过 1
+        let end = start + chrono::Duration::hours(2);
+        detail.complete(31.0, 20.0, 4.0, end);
+        assert_eq!(detail.get_progress(), 1.0);
+        assert_eq!(detail.get_remaining_amount(), 0.0);
+        assert_eq!(detail.get_end_time(), Some(end));
+        assert_eq!(detail.get_status().to_string(), "completed");
+    }
+
+    #[test]
+    fn test_session_timing() {
+        let enqueued = DateTime::parse_from_rfc3339("2025-06-01T10:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        // 两个详单同时加入队列，第二个等待第一个充满
+        let mut first = ChargingDetail::test_new(1);
+        let mut second = ChargingDetail::test_new(2);
+        first.set_enqueued_at(enqueued);
+        second.set_enqueued_at(enqueued);
+        first.start(enqueued, 30.0, &PowerCurve::default());
+        let first_end = enqueued + chrono::Duration::minutes(60);
+        first.complete(30.0, 0.0, 0.0, first_end);
+        second.start(
+            first_end + chrono::Duration::milliseconds(5),
+            30.0,
+            &PowerCurve::default(),
+        );
+        second.complete(30.0, 0.0, 0.0, first_end + chrono::Duration::minutes(60));
+
+        assert_eq!(first.get_wait_duration_s(), Some(0.0));
+        assert_eq!(first.get_charge_duration_s(), Some(3600.0));
+        assert_eq!(second.get_wait_duration_s(), Some(3600.005));
+
+        // 没有加入队列时间的详单只报告充电时长
+        let mut unknown = ChargingDetail::test_new(3);
+        unknown.start(enqueued, 30.0, &PowerCurve::default());
+        unknown.complete(30.0, 0.0, 0.0, first_end);
+        assert!(unknown.get_wait_duration_s().is_none());
+        let value = serde_json::to_value(&unknown).unwrap();
+        assert!(value["wait_duration_s"].is_null());
+        assert_eq!(value["charge_duration_s"], 3600.0);
+    }
+
+    #[test]
+    fn test_resumption_legs() {
+        let start = DateTime::parse_from_rfc3339("2025-06-01T10:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+
+        // 第一段充电 20 分钟后故障
+        let mut detail = ChargingDetail::test_new(1);
+        detail.start(start, 30.0, &PowerCurve::default());
+        detail.interrupt(10.0, 7.0, 8.0, start + chrono::Duration::minutes(20));
+        let mut resumed = detail.resumption().unwrap();
+        assert!(resumed.is_resumed());
+        assert!(!resumed.is_ready());
+        assert_eq!(resumed.already_charged, 10.0);
+
+        // 修复后只需要充剩余的度数
+        let repaired = start + chrono::Duration::minutes(30);
+        resumed.start(repaired, 30.0, &PowerCurve::default());
+        assert_eq!(
+            resumed.initial_estimated_end_time,
+            Some(repaired + chrono::Duration::minutes(40))
+        );
+        resumed.complete(20.0, 14.0, 16.0, repaired + chrono::Duration::minutes(40));
+
+        let prior = resumed.prior_leg.as_ref().unwrap();
+        assert_eq!(resumed.already_charged, resumed.request_amount);
+        assert_eq!(resumed.charge_cost, prior.charge_cost + 14.0);
+        assert_eq!(resumed.service_fee, prior.service_fee + 16.0);
+        assert_eq!(resumed.total_cost, 45.0);
+
+        // 已完成的详单不能恢复
+        assert!(resumed.resumption().is_none());
+    }
+
+    #[test]
+    fn test_completion_fees_apply_once() {
+        let start = manual_clock().now();
+        let fee = FeeModel {
+            per_kwh: 0.0,
+            per_session: 3.0,
+            per_minute_after_complete: 0.25,
+        };
+        // 恢复的详单跨越两段充电，只在最后完成时收取一次
+        let mut detail =
+            ChargingDetail::test_new(1).with_departure_time(start + chrono::Duration::minutes(80));
+        detail.start(start, 30.0, &PowerCurve::default());
+        detail.interrupt(10.0, 7.0, 0.0, start + chrono::Duration::minutes(20));
+        assert_eq!(detail.get_session_fee(), None);
+        let mut resumed = detail.resumption().unwrap();
+        resumed.start(
+            start + chrono::Duration::minutes(20),
+            30.0,
+            &PowerCurve::default(),
+        );
+        resumed.complete(20.0, 14.0, 0.0, start + chrono::Duration::minutes(60));
+        resumed.apply_completion_fees(&fee);
+        resumed.apply_completion_fees(&fee);
+        assert_eq!(resumed.get_session_fee(), Some(3.0));
+        assert_eq!(resumed.get_idle_fee(), Some(5.0));
+        assert_eq!(resumed.service_fee, 3.0);
+        assert_eq!(resumed.get_total_cost(), 21.0 + 3.0 + 5.0);
+        assert!(!resumed.is_ready());
+
+        // 在预计离场时间之后才充满时没有占位费
+        let mut late = ChargingDetail::test_new(2).with_departure_time(start);
+        late.start(start, 30.0, &PowerCurve::default());
+        late.complete(30.0, 21.0, 0.0, start + chrono::Duration::hours(1));
+        late.apply_completion_fees(&fee);
+        assert_eq!(late.get_idle_fee(), None);
+        assert_eq!(late.get_total_cost(), 24.0);
+    }
 }
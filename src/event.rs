@@ -0,0 +1,216 @@
+//! 充电详单生命周期事件和充电桩状态变化事件
+//!
+//! 每个充电桩通过 [`crate::charge::Charge::subscribe`] 发布自己的 [`ChargeEvent`]，
+//! 同时转发到进程内共用的通道，嵌入程序通过 [`subscribe`] 接收所有充电桩的事件。
+
+use std::sync::LazyLock;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+use uuid::Uuid;
+
+use crate::detail::ChargingDetail;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+/// 生命周期事件类型
+pub enum LifecycleEventType {
+    #[serde(rename = "admitted")]
+    /// 详单加入队列
+    Admitted,
+    #[serde(rename = "started")]
+    /// 开始充电
+    Started,
+    #[serde(rename = "completed")]
+    /// 充电完成
+    Completed,
+    #[serde(rename = "interrupted")]
+    /// 充电中断
+    Interrupted,
+}
+
+impl LifecycleEventType {
+    /// 是否为详单的最后一个事件
+    pub fn is_terminal(&self) -> bool {
+        matches!(
+            self,
+            LifecycleEventType::Completed | LifecycleEventType::Interrupted
+        )
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+/// 生命周期事件
+pub struct LifecycleEvent {
+    /// 事件类型
+    pub event: LifecycleEventType,
+    /// 事件发生的虚拟时间
+    pub virtual_time: DateTime<Utc>,
+    /// 事件发生时的详单快照
+    pub detail: ChargingDetail,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "event", rename_all = "snake_case")]
+/// 充电桩状态变化事件，供嵌入程序和界面订阅，详单事件带有变化后的详单快照
+pub enum ChargeEvent {
+    /// 详单加入队列
+    DetailQueued {
+        virtual_time: DateTime<Utc>,
+        detail: ChargingDetail,
+    },
+    /// 详单开始充电
+    ChargingStarted {
+        virtual_time: DateTime<Utc>,
+        detail: ChargingDetail,
+    },
+    /// 正在充电的详单更新了充电状态
+    Progress {
+        virtual_time: DateTime<Utc>,
+        detail: ChargingDetail,
+    },
+    /// 详单充电完成
+    Completed {
+        virtual_time: DateTime<Utc>,
+        detail: ChargingDetail,
+    },
+    /// 详单被取消
+    Canceled {
+        virtual_time: DateTime<Utc>,
+        detail: ChargingDetail,
+    },
+    /// 详单被关闭或故障打断
+    Interrupted {
+        virtual_time: DateTime<Utc>,
+        detail: ChargingDetail,
+    },
+    /// 充电桩被关闭
+    PileClosed { virtual_time: DateTime<Utc> },
+    /// 充电桩重新打开
+    PileOpened { virtual_time: DateTime<Utc> },
+    /// 充电桩发生故障
+    Breakdown { virtual_time: DateTime<Utc> },
+}
+
+impl ChargeEvent {
+    /// 事件发生的虚拟时间
+    pub fn virtual_time(&self) -> DateTime<Utc> {
+        match self {
+            ChargeEvent::DetailQueued { virtual_time, .. }
+            | ChargeEvent::ChargingStarted { virtual_time, .. }
+            | ChargeEvent::Progress { virtual_time, .. }
+            | ChargeEvent::Completed { virtual_time, .. }
+            | ChargeEvent::Canceled { virtual_time, .. }
+            | ChargeEvent::Interrupted { virtual_time, .. }
+            | ChargeEvent::PileClosed { virtual_time }
+            | ChargeEvent::PileOpened { virtual_time }
+            | ChargeEvent::Breakdown { virtual_time } => *virtual_time,
+        }
+    }
+
+    /// 事件中的详单快照，充电桩事件没有详单
+    pub fn detail(&self) -> Option<&ChargingDetail> {
+        match self {
+            ChargeEvent::DetailQueued { detail, .. }
+            | ChargeEvent::ChargingStarted { detail, .. }
+            | ChargeEvent::Progress { detail, .. }
+            | ChargeEvent::Completed { detail, .. }
+            | ChargeEvent::Canceled { detail, .. }
+            | ChargeEvent::Interrupted { detail, .. } => Some(detail),
+            ChargeEvent::PileClosed { .. }
+            | ChargeEvent::PileOpened { .. }
+            | ChargeEvent::Breakdown { .. } => None,
+        }
+    }
+
+    /// 对应的生命周期事件，取消与打断都视为中断，充电进度和充电桩事件没有对应的生命周期事件
+    pub fn lifecycle(&self) -> Option<LifecycleEvent> {
+        let event = match self {
+            ChargeEvent::DetailQueued { .. } => LifecycleEventType::Admitted,
+            ChargeEvent::ChargingStarted { .. } => LifecycleEventType::Started,
+            ChargeEvent::Completed { .. } => LifecycleEventType::Completed,
+            ChargeEvent::Canceled { .. } | ChargeEvent::Interrupted { .. } => {
+                LifecycleEventType::Interrupted
+            }
+            _ => return None,
+        };
+        Some(LifecycleEvent {
+            event,
+            virtual_time: self.virtual_time(),
+            detail: self.detail()?.clone(),
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+/// 带有充电桩 ID 的事件，通过 [`subscribe`] 接收进程内所有充电桩的事件
+pub struct PileEvent {
+    /// 发生事件的充电桩
+    pub charge_id: Uuid,
+    #[serde(flatten)]
+    /// 事件
+    pub event: ChargeEvent,
+}
+
+/// 事件通道容量，订阅者处理过慢时会丢失较早的事件，不会阻塞充电
+pub const EVENT_CHANNEL_CAPACITY: usize = 256;
+
+/// 进程内所有充电桩共用的事件通道
+static EVENTS: LazyLock<broadcast::Sender<PileEvent>> =
+    LazyLock::new(|| broadcast::Sender::new(EVENT_CHANNEL_CAPACITY));
+
+/// 订阅进程内所有充电桩的事件，可以有任意多个订阅者
+/// 订阅者处理过慢时 `recv` 返回 `Lagged` 并跳过丢失的事件
+pub fn subscribe() -> broadcast::Receiver<PileEvent> {
+    EVENTS.subscribe()
+}
+
+/// 是否有事件订阅者
+pub(crate) fn has_subscribers() -> bool {
+    EVENTS.receiver_count() > 0
+}
+
+/// 发布事件，没有订阅者时直接丢弃
+pub(crate) fn publish(event: PileEvent) {
+    let _ = EVENTS.send(event);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_format_and_lifecycle() {
+        let now = Utc::now();
+        let queued = ChargeEvent::DetailQueued {
+            virtual_time: now,
+            detail: ChargingDetail::test_new(3),
+        };
+        let value = serde_json::to_value(PileEvent {
+            charge_id: Uuid::nil(),
+            event: queued.clone(),
+        })
+        .unwrap();
+        assert_eq!(value["event"], "detail_queued");
+        assert_eq!(value["detail"]["id"], 3);
+        assert!(value["charge_id"].is_string());
+        let lifecycle = queued.lifecycle().unwrap();
+        assert_eq!(lifecycle.event, LifecycleEventType::Admitted);
+        assert_eq!(lifecycle.virtual_time, now);
+
+        let canceled = ChargeEvent::Canceled {
+            virtual_time: now,
+            detail: ChargingDetail::test_new(3),
+        };
+        assert_eq!(
+            canceled.lifecycle().unwrap().event,
+            LifecycleEventType::Interrupted
+        );
+        let closed = ChargeEvent::PileClosed { virtual_time: now };
+        assert!(closed.detail().is_none() && closed.lifecycle().is_none());
+        assert_eq!(
+            serde_json::to_value(&closed).unwrap()["event"],
+            "pile_closed"
+        );
+    }
+}
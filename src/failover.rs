@@ -0,0 +1,101 @@
+//! WebSocket 地址故障切换
+//!
+//! `websocket.urls` 配置了多个地址时，首次连接和连接断开后的重新连接都按 [`FailoverMode`]
+//! 依次尝试各个地址，每个地址使用相同的连接超时，失败后尝试下一个。
+
+use crate::conf::FailoverMode;
+
+#[derive(Debug, Clone)]
+/// 一个充电桩可以连接的所有地址，以及当前连接的地址
+pub struct Endpoints {
+    urls: Vec<String>,
+    mode: FailoverMode,
+    /// 当前（或上一次）连接的地址的序号
+    current: Option<usize>,
+}
+
+impl Endpoints {
+    /// 按配置的顺序创建，还没有连接任何地址
+    pub fn new(urls: Vec<String>, mode: FailoverMode) -> Self {
+        Endpoints {
+            urls,
+            mode,
+            current: None,
+        }
+    }
+
+    /// 是否有可以切换的其他地址
+    pub fn has_fallback(&self) -> bool {
+        self.urls.len() > 1
+    }
+
+    /// 当前连接的地址，还没有连接时为 `None`
+    pub fn current_url(&self) -> Option<&str> {
+        self.current.map(|index| self.urls[index].as_str())
+    }
+
+    /// 下一次连接时依次尝试的地址及其序号，每个地址只尝试一次
+    pub fn attempts(&self) -> Vec<(usize, String)> {
+        let start = match (self.mode, self.current) {
+            (FailoverMode::RoundRobin, Some(current)) => current + 1,
+            _ => 0,
+        };
+        (0..self.urls.len())
+            .map(|offset| {
+                let index = (start + offset) % self.urls.len();
+                (index, self.urls[index].clone())
+            })
+            .collect()
+    }
+
+    /// 记录连接成功的地址
+    pub fn connected(&mut self, index: usize) {
+        self.current = Some(index);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn urls() -> Vec<String> {
+        ["ws://a/ws", "ws://b/ws", "ws://c/ws"]
+            .iter()
+            .map(|url| url.to_string())
+            .collect()
+    }
+
+    fn order(endpoints: &Endpoints) -> Vec<usize> {
+        endpoints
+            .attempts()
+            .into_iter()
+            .map(|(index, _)| index)
+            .collect()
+    }
+
+    #[test]
+    fn test_ordered_always_starts_from_first() {
+        let mut endpoints = Endpoints::new(urls(), FailoverMode::Ordered);
+        assert!(endpoints.has_fallback());
+        assert_eq!(endpoints.current_url(), None);
+        assert_eq!(order(&endpoints), [0, 1, 2]);
+        endpoints.connected(1);
+        assert_eq!(endpoints.current_url(), Some("ws://b/ws"));
+        // 备用地址断开后仍然优先尝试第一个地址
+        assert_eq!(order(&endpoints), [0, 1, 2]);
+    }
+
+    #[test]
+    fn test_round_robin_starts_after_current() {
+        let mut endpoints = Endpoints::new(urls(), FailoverMode::RoundRobin);
+        assert_eq!(order(&endpoints), [0, 1, 2]);
+        endpoints.connected(0);
+        assert_eq!(order(&endpoints), [1, 2, 0]);
+        endpoints.connected(2);
+        assert_eq!(order(&endpoints), [0, 1, 2]);
+
+        let single = Endpoints::new(vec!["ws://a/ws".to_string()], FailoverMode::RoundRobin);
+        assert!(!single.has_fallback());
+        assert_eq!(order(&single), [0]);
+    }
+}
@@ -0,0 +1,133 @@
+//! 注册握手
+//!
+//! 充电桩注册后等待服务器的 `register_ack` 消息，确认前收到的新详单先暂存，
+//! 收到确认或等待超时后按收到的顺序处理。确认中的协议版本与 [`PROTOCOL_VERSION`] 比较。
+
+use tokio::time::{Duration, Instant};
+
+use crate::message::{MSG, PROTOCOL_FEATURES, PROTOCOL_VERSION, RegisterAckData};
+
+#[derive(Debug)]
+/// 注册握手状态
+pub struct Handshake {
+    /// 等待确认的时间，为零时不等待
+    timeout: Duration,
+    /// 等待确认的截止时间，没有在等待时为 `None`
+    deadline: Option<Instant>,
+    /// 确认前收到的新详单消息
+    deferred: Vec<MSG>,
+}
+
+impl Handshake {
+    /// 创建握手状态，`timeout` 为零时不等待确认
+    pub fn new(timeout: Duration) -> Self {
+        Handshake {
+            timeout,
+            deadline: None,
+            deferred: Vec::new(),
+        }
+    }
+
+    /// 发送注册消息后开始等待确认，重新注册时重新计时，已经暂存的消息保留
+    pub fn start(&mut self, now: Instant) {
+        if !self.timeout.is_zero() {
+            self.deadline = Some(now + self.timeout);
+        }
+    }
+
+    /// 是否正在等待确认
+    pub fn is_waiting(&self) -> bool {
+        self.deadline.is_some()
+    }
+
+    /// 等待确认的截止时间
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// 暂存确认前收到的消息
+    pub fn defer(&mut self, msg: MSG) {
+        self.deferred.push(msg);
+    }
+
+    /// 收到确认或等待超时，结束等待并按收到的顺序返回暂存的消息
+    pub fn finish(&mut self) -> Vec<MSG> {
+        self.deadline = None;
+        std::mem::take(&mut self.deferred)
+    }
+}
+
+/// 检查服务器确认的协议版本，不一致时返回说明
+pub fn check_version(ack: &RegisterAckData) -> Result<(), String> {
+    if ack.protocol_version == PROTOCOL_VERSION {
+        return Ok(());
+    }
+    Err(format!(
+        "server protocol version {} does not match pile protocol version {}",
+        ack.protocol_version, PROTOCOL_VERSION
+    ))
+}
+
+/// 充电桩支持但服务器没有在确认中列出的功能，服务器没有列出任何功能时视为全部支持
+pub fn missing_features(ack: &RegisterAckData) -> Vec<&'static str> {
+    if ack.features.is_empty() {
+        return Vec::new();
+    }
+    PROTOCOL_FEATURES
+        .iter()
+        .copied()
+        .filter(|feature| !ack.features.iter().any(|f| f == feature))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conf::WireEncoding;
+    use crate::message::MessageType;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_defers_until_ack_or_timeout() {
+        let mut handshake = Handshake::new(Duration::from_secs(5));
+        assert!(!handshake.is_waiting());
+        let start = Instant::now();
+        handshake.start(start);
+        assert_eq!(handshake.deadline(), Some(start + Duration::from_secs(5)));
+        handshake.defer(MSG::empty(MessageType::New));
+        handshake.defer(MSG {
+            msg_id: Some(2),
+            ..MSG::empty(MessageType::New)
+        });
+        let deferred = handshake.finish();
+        assert_eq!(
+            deferred.iter().map(|msg| msg.msg_id).collect::<Vec<_>>(),
+            vec![None, Some(2)]
+        );
+        assert!(!handshake.is_waiting() && handshake.finish().is_empty());
+
+        // 不等待确认时注册后直接处理
+        let mut handshake = Handshake::new(Duration::ZERO);
+        handshake.start(start);
+        assert!(!handshake.is_waiting() && handshake.deadline().is_none());
+    }
+
+    #[test]
+    fn test_version_and_features() {
+        let mut ack = RegisterAckData {
+            protocol_version: PROTOCOL_VERSION,
+            features: Vec::new(),
+            server_version: None,
+            encoding: WireEncoding::Json,
+            server_time: None,
+        };
+        assert!(check_version(&ack).is_ok());
+        assert!(missing_features(&ack).is_empty());
+        ack.features = vec!["ack".to_string(), "query".to_string()];
+        assert_eq!(
+            missing_features(&ack),
+            vec!["reject", "pending", "register_ack", "new_batch"]
+        );
+        ack.protocol_version = PROTOCOL_VERSION + 1;
+        assert!(check_version(&ack).unwrap_err().contains("does not match"));
+    }
+}
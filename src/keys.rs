@@ -0,0 +1,151 @@
+//! 键盘命令
+//!
+//! 终端中的按键由单独的线程读取，转换为 [`KeyCommand`] 后通过通道发送到第一个充电桩的主循环，
+//! 由主循环按充电桩当前的状态执行或忽略。
+
+use crossterm::event::KeyCode;
+
+use crate::message::StatusData;
+
+/// 每次按 '+' 或 '-' 时加速倍数乘以或除以的比例
+pub const SPEED_STEP: f64 = 2.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// 键盘命令
+pub enum KeyCommand {
+    /// 'p'：模拟充电桩损坏
+    Breakdown,
+    /// 'q'：正常退出，与 Ctrl+C 相同
+    Quit,
+    /// 's'：在标准错误输出充电桩状态和虚拟时间
+    Snapshot,
+    /// 'c'：按当前虚拟时间立即完成正在充电的详单
+    ForceComplete,
+    /// '+'：加快虚拟时钟
+    SpeedUp,
+    /// '-'：减慢虚拟时钟
+    SpeedDown,
+    /// 空格：暂停或继续虚拟时钟
+    TogglePause,
+}
+
+impl KeyCommand {
+    /// 按键对应的命令，字母不区分大小写，'=' 和 '_' 分别等同于 '+' 和 '-'，其他按键返回 `None`
+    pub fn from_key(code: KeyCode) -> Option<Self> {
+        let KeyCode::Char(c) = code else {
+            return None;
+        };
+        match c.to_ascii_lowercase() {
+            'p' => Some(KeyCommand::Breakdown),
+            'q' => Some(KeyCommand::Quit),
+            's' => Some(KeyCommand::Snapshot),
+            'c' => Some(KeyCommand::ForceComplete),
+            '+' | '=' => Some(KeyCommand::SpeedUp),
+            '-' | '_' => Some(KeyCommand::SpeedDown),
+            ' ' => Some(KeyCommand::TogglePause),
+            _ => None,
+        }
+    }
+}
+
+/// 按一次 '+' 或 '-' 后的加速倍数
+pub fn bumped_speed(speed: f64, up: bool) -> f64 {
+    if up {
+        speed * SPEED_STEP
+    } else {
+        speed / SPEED_STEP
+    }
+}
+
+/// 把状态快照格式化为多行文本，`paused` 为虚拟时钟是否暂停，`speed` 为当前的加速倍数
+pub fn format_snapshot(status: &StatusData, paused: bool, speed: f64) -> String {
+    let mode = status.state.label();
+    let mut lines = vec![
+        format!(
+            "充电桩 {} [{:?}] {} kW，状态: {}",
+            status.charge_id, status.type_, status.power, mode
+        ),
+        format!(
+            "虚拟时间: {}，加速倍数: {}{}",
+            status.virtual_time,
+            speed,
+            if paused { "（已暂停）" } else { "" }
+        ),
+    ];
+    match &status.charging {
+        Some(detail) => lines.push(format!("正在充电: {}", detail)),
+        None => lines.push("正在充电: 无".to_string()),
+    }
+    for detail in &status.also_charging {
+        lines.push(format!("同时充电: {}", detail));
+    }
+    if status.queue.is_empty() {
+        lines.push("排队: 无".to_string());
+    } else {
+        lines.push(format!("排队: {} 个详单", status.queue.len()));
+        for (position, detail) in status.queue.iter().enumerate() {
+            lines.push(format!("  {}. {}", position + 1, detail));
+        }
+    }
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::charge::Charge;
+    use crate::charge::FaultSource;
+    use crate::conf::CONF;
+    use crate::detail::ChargingDetail;
+    use crate::time::get_mock_now;
+
+    #[test]
+    fn test_key_mapping() {
+        assert_eq!(
+            KeyCommand::from_key(KeyCode::Char('P')),
+            Some(KeyCommand::Breakdown)
+        );
+        assert_eq!(
+            KeyCommand::from_key(KeyCode::Char('q')),
+            Some(KeyCommand::Quit)
+        );
+        assert_eq!(
+            KeyCommand::from_key(KeyCode::Char('=')),
+            Some(KeyCommand::SpeedUp)
+        );
+        assert_eq!(
+            KeyCommand::from_key(KeyCode::Char('-')),
+            Some(KeyCommand::SpeedDown)
+        );
+        assert_eq!(
+            KeyCommand::from_key(KeyCode::Char(' ')),
+            Some(KeyCommand::TogglePause)
+        );
+        assert_eq!(KeyCommand::from_key(KeyCode::Char('x')), None);
+        assert_eq!(KeyCommand::from_key(KeyCode::Enter), None);
+        assert_eq!(bumped_speed(3.0, true), 6.0);
+        assert_eq!(bumped_speed(3.0, false), 1.5);
+    }
+
+    #[test]
+    fn test_format_snapshot() {
+        let mut charge = Charge::new(CONF.charge.charge_type, 30.0, 3);
+        charge.add_detail(ChargingDetail::test_new(1)).unwrap();
+        charge.add_detail(ChargingDetail::test_new(2)).unwrap();
+        charge.start_charging();
+        let text = format_snapshot(&charge.status_snapshot(get_mock_now()), true, 4.0);
+        assert!(text.contains("状态: 充电中"), "{}", text);
+        assert!(text.contains("加速倍数: 4（已暂停）"), "{}", text);
+        assert!(text.contains("正在充电: 详单 1"), "{}", text);
+        assert!(text.contains("排队: 1 个详单\n  1. 详单 2"), "{}", text);
+
+        let mut faulted = Charge::new(CONF.charge.charge_type, 30.0, 3);
+        faulted.breakdown(FaultSource::Internal).unwrap();
+        let text = format_snapshot(&faulted.status_snapshot(get_mock_now()), false, 1.0);
+        assert!(
+            text.contains("状态: 故障") && text.contains("排队: 无"),
+            "{}",
+            text
+        );
+    }
+}
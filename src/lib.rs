@@ -1,6 +1,38 @@
+pub mod audit;
+pub mod bench;
 pub mod charge;
+pub mod client;
+pub mod compat;
 pub mod conf;
+pub mod curve;
 pub mod detail;
+pub mod event;
+pub mod failover;
+pub mod handshake;
+pub mod keys;
+pub mod logging;
+pub mod maintenance;
 pub mod message;
+pub mod metrics;
+pub mod outbound;
+pub mod outbox;
+pub mod persist;
 pub mod price;
-pub mod time;
\ No newline at end of file
+pub mod reconcile;
+pub mod reload;
+pub mod runtime;
+pub mod scenario;
+pub mod skip;
+pub mod standalone;
+pub mod state;
+pub mod stats;
+pub mod throttle;
+pub mod time;
+pub mod tls;
+pub mod trace;
+pub mod traffic;
+pub mod transport;
+pub mod tui;
+pub mod update;
+pub mod watchdog;
+pub mod webhook;
@@ -0,0 +1,273 @@
+//! 日志输出
+//!
+//! 按 [`LogConf`] 建立控制台和文件两个日志层。文件按轮换周期切分，
+//! 配置了 `max_files` 时在启动和每次轮换后删除日志目录中多余的旧文件。
+
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use chrono::{DurationRound, TimeDelta, Utc};
+use tracing::span::Record;
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::field::RecordFields;
+use tracing_subscriber::fmt::FormatFields;
+use tracing_subscriber::fmt::FormattedFields;
+use tracing_subscriber::fmt::format::{DefaultFields, Writer};
+use tracing_subscriber::{EnvFilter, Layer, Registry};
+use tracing_subscriber::{fmt::format::FmtSpan, layer::SubscriberExt, util::SubscriberInitExt};
+
+use crate::conf::{LogConf, LogFormat, LogRotation};
+use crate::time::{ConsoleTimer, console_fields};
+
+/// 日志文件名前缀，后面是进程 ID
+const FILE_PREFIX: &str = "app_";
+
+/// 本进程的日志文件名，轮换时加上日期后缀
+fn file_name() -> String {
+    format!("{}{}.log", FILE_PREFIX, std::process::id())
+}
+
+type BoxedLayer = Box<dyn Layer<Registry> + Send + Sync>;
+
+/// 重新记录 span 字段时替换同名字段的旧值，而不是追加在后面
+/// 充电桩 span 的 `state` 字段随状态变化，每行日志只显示当前状态；只适用于值中不含空格的字段
+struct ReplaceFields<F>(F);
+
+impl<'writer, F> FormatFields<'writer> for ReplaceFields<F>
+where
+    F: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_fields<R: RecordFields>(
+        &self,
+        writer: Writer<'writer>,
+        fields: R,
+    ) -> std::fmt::Result {
+        self.0.format_fields(writer, fields)
+    }
+
+    fn add_fields(
+        &self,
+        current: &'writer mut FormattedFields<Self>,
+        fields: &Record<'_>,
+    ) -> std::fmt::Result {
+        let mut added = String::new();
+        self.0.format_fields(Writer::new(&mut added), fields)?;
+        current.fields = replace_fields(&current.fields, &added);
+        Ok(())
+    }
+}
+
+/// 把 `added` 中的 `name=value` 字段合并到 `current`，同名字段使用新值，字段之间用空格分隔
+fn replace_fields(current: &str, added: &str) -> String {
+    let name = |field: &str| {
+        field
+            .split_once('=')
+            .map_or(field, |(name, _)| name)
+            .to_string()
+    };
+    let mut fields: Vec<&str> = current.split(' ').filter(|f| !f.is_empty()).collect();
+    for field in added.split(' ').filter(|f| !f.is_empty()) {
+        match fields.iter_mut().find(|old| name(old) == name(field)) {
+            Some(old) => *old = field,
+            None => fields.push(field),
+        }
+    }
+    fields.join(" ")
+}
+
+/// 控制台日志层，输出到标准错误
+fn console_layer(conf: &LogConf) -> BoxedLayer {
+    tracing_subscriber::fmt::layer()
+        .with_writer(std::io::stderr)
+        .with_timer(ConsoleTimer)
+        .fmt_fields(ReplaceFields(console_fields()))
+        .with_ansi(true)
+        .with_level(true)
+        .with_target(false)
+        .with_filter(EnvFilter::new(&conf.console_level))
+        .boxed()
+}
+
+/// 文件日志层
+fn file_layer(conf: &LogConf, writer: tracing_appender::non_blocking::NonBlocking) -> BoxedLayer {
+    let filter = EnvFilter::new(&conf.file_level);
+    let layer = tracing_subscriber::fmt::layer()
+        .with_writer(writer)
+        .with_ansi(false)
+        .with_level(true)
+        .with_target(true)
+        .with_thread_ids(true)
+        .with_thread_names(true);
+    match conf.format {
+        LogFormat::Json => layer
+            .json()
+            .with_span_events(FmtSpan::CLOSE | FmtSpan::NEW)
+            .with_filter(filter)
+            .boxed(),
+        LogFormat::Plain => layer
+            .fmt_fields(ReplaceFields(DefaultFields::new()))
+            .with_filter(filter)
+            .boxed(),
+    }
+}
+
+/// 初始化全局日志，返回的守卫需要保留到程序结束，否则文件日志可能丢失
+/// 日志目录无法创建时只输出控制台日志
+pub fn init(conf: &LogConf) -> Option<WorkerGuard> {
+    let rotation = match conf.rotation {
+        LogRotation::Daily => Rotation::DAILY,
+        LogRotation::Hourly => Rotation::HOURLY,
+        LogRotation::Never => Rotation::NEVER,
+    };
+    let appender = RollingFileAppender::builder()
+        .rotation(rotation)
+        .filename_prefix(file_name())
+        .build(&conf.dir);
+    let mut layers: Vec<BoxedLayer> = Vec::new();
+    if conf.console {
+        layers.push(console_layer(conf));
+    }
+    let (guard, error) = match appender {
+        Ok(appender) => {
+            let (writer, guard) = tracing_appender::non_blocking(appender);
+            layers.push(file_layer(conf, writer));
+            (Some(guard), None)
+        }
+        Err(e) => (None, Some(e)),
+    };
+    tracing_subscriber::registry().with(layers).init();
+    match error {
+        Some(e) => tracing::error!("无法打开日志目录 {}: {}，不写入日志文件", conf.dir, e),
+        None => spawn_cleanup(conf),
+    }
+    guard
+}
+
+/// 启动时清理一次旧日志文件，之后在每次轮换后清理
+fn spawn_cleanup(conf: &LogConf) {
+    if conf.max_files == 0 {
+        return;
+    }
+    let dir = PathBuf::from(&conf.dir);
+    let max_files = conf.max_files;
+    cleanup_logged(&dir, max_files);
+    let period = match conf.rotation {
+        LogRotation::Daily => TimeDelta::days(1),
+        LogRotation::Hourly => TimeDelta::hours(1),
+        LogRotation::Never => return,
+    };
+    tokio::spawn(async move {
+        loop {
+            // 轮换发生在下一次写入时，留出一点时间再清理
+            let now = Utc::now();
+            let next = now.duration_trunc(period).unwrap_or(now) + period;
+            let wait = (next - now).to_std().unwrap_or_default();
+            tokio::time::sleep(wait + std::time::Duration::from_secs(1)).await;
+            cleanup_logged(&dir, max_files);
+        }
+    });
+}
+
+/// 清理旧日志文件并记录结果
+fn cleanup_logged(dir: &Path, max_files: usize) {
+    match cleanup(dir, max_files) {
+        Ok(removed) if !removed.is_empty() => {
+            tracing::info!("已删除 {} 个旧日志文件", removed.len());
+        }
+        Ok(_) => {}
+        Err(e) => tracing::warn!("清理日志目录 {} 失败: {}", dir.display(), e),
+    }
+}
+
+/// 按修改时间只保留最新的 `max_files` 个日志文件，返回删除的文件
+/// 只处理文件名以 `app_` 开头的日志文件，其他进程的日志文件也会计入
+pub fn cleanup(dir: &Path, max_files: usize) -> Result<Vec<PathBuf>, String> {
+    let mut files: Vec<(SystemTime, PathBuf)> = std::fs::read_dir(dir)
+        .map_err(|e| e.to_string())?
+        .filter_map(Result::ok)
+        .filter(|entry| {
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            name.starts_with(FILE_PREFIX) && name.contains(".log")
+        })
+        .filter_map(|entry| {
+            let metadata = entry.metadata().ok()?;
+            if !metadata.is_file() {
+                return None;
+            }
+            Some((metadata.modified().ok()?, entry.path()))
+        })
+        .collect();
+    if max_files == 0 || files.len() <= max_files {
+        return Ok(Vec::new());
+    }
+    // 最新的文件在前，相同修改时间按文件名倒序，轮换后缀较新的文件在前
+    files.sort_by(|a, b| b.cmp(a));
+    let mut removed = Vec::new();
+    for (_, path) in files.into_iter().skip(max_files) {
+        match std::fs::remove_file(&path) {
+            Ok(()) => removed.push(path),
+            Err(e) => tracing::warn!("无法删除日志文件 {}: {}", path.display(), e),
+        }
+    }
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_replace_fields() {
+        assert_eq!(replace_fields("", "state=idle"), "state=idle");
+        assert_eq!(
+            replace_fields("charge_id=1 state=idle", "state=charging"),
+            "charge_id=1 state=charging"
+        );
+        assert_eq!(
+            replace_fields("charge_id=1", "state=closed extra=2"),
+            "charge_id=1 state=closed extra=2"
+        );
+    }
+
+    #[test]
+    fn test_cleanup_keeps_newest() {
+        let dir = std::env::temp_dir().join(format!("taranis-logs-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let base = SystemTime::now() - Duration::from_secs(3600);
+        let names = [
+            "app_1.log.2025-06-01",
+            "app_1.log.2025-06-02",
+            "app_2.log.2025-06-02",
+            "app_3.log",
+        ];
+        for (i, name) in names.iter().enumerate() {
+            let file = std::fs::File::create(dir.join(name)).unwrap();
+            file.set_modified(base + Duration::from_secs(60 * i as u64))
+                .unwrap();
+        }
+        // 不是日志文件的文件不会被删除
+        std::fs::write(dir.join("notes.txt"), "keep").unwrap();
+
+        assert!(cleanup(&dir, 0).unwrap().is_empty());
+        assert!(cleanup(&dir, 4).unwrap().is_empty());
+        let mut removed = cleanup(&dir, 2).unwrap();
+        removed.sort();
+        assert_eq!(
+            removed,
+            vec![
+                dir.join("app_1.log.2025-06-01"),
+                dir.join("app_1.log.2025-06-02")
+            ]
+        );
+        let mut left: Vec<String> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        left.sort();
+        assert_eq!(left, ["app_2.log.2025-06-02", "app_3.log", "notes.txt"]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
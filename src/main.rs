@@ -1,545 +1,354 @@
-use futures_util::SinkExt;
-use futures_util::StreamExt;
-use futures_util::stream::SplitSink;
-use taranis::time::get_mock_now;
-use tokio::net::TcpStream;
-use tokio::sync::oneshot;
-use tokio::time::Interval;
-use tracing::Instrument;
-use tracing::instrument;
-use tracing_subscriber::fmt::time::ChronoLocal;
-use tracing_subscriber::{EnvFilter, Layer};
-use tracing_subscriber::{fmt::format::FmtSpan, layer::SubscriberExt, util::SubscriberInitExt};
+use std::sync::LazyLock;
 
-use crossterm::event::{self, Event, KeyCode};
-use tokio::task;
+use taranis::time::ConsoleTimer;
+use taranis::time::console_fields;
 
-use tokio::time::{Duration, interval, interval_at, timeout};
-use tokio_tungstenite::{MaybeTlsStream, WebSocketStream, connect_async};
-
-use taranis::charge::CHARGE;
-use taranis::charge::Charge;
-use taranis::conf::CONF;
-use taranis::detail::ChargingDetail;
-use taranis::message::{MSG, MessageType};
-
-use tokio_tungstenite::tungstenite::Message as WsMessage;
-type WsSender = SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, WsMessage>;
-
-/// 结束全局原子变量
-static IS_CLOSED: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+use taranis::audit::{self, AuditFilter};
+use taranis::bench;
+use taranis::client;
+use taranis::conf::{self, CONF, ConfOverrides};
+use taranis::logging;
+use taranis::price::Prices;
+use taranis::reconcile;
+use taranis::standalone;
+use taranis::trace;
 
 #[tokio::main]
 async fn main() {
-    // 打开日志文件
-    let pid = std::process::id();
-    let file_appender = tracing_appender::rolling::daily("logs", format!("app_{}.log", pid));
-    let (non_blocking, _guard) = tracing_appender::non_blocking(file_appender);
-
-    // 设置日志过滤器
-    let console_filter = if cfg!(debug_assertions) {
-        EnvFilter::new("debug")
-    } else {
-        EnvFilter::new("info")
+    // 命令行参数优先于环境变量，两者都优先于配置文件
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let (overrides, args) = match ConfOverrides::from_args(&args)
+        .and_then(|(cli, rest)| Ok((cli.or(ConfOverrides::from_env()?), rest)))
+    {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            eprintln!("无法解析配置参数: {}", e);
+            std::process::exit(2);
+        }
     };
-    let file_filter = EnvFilter::new("trace");
-
-    // 设置控制台日志格式
-    let console_layer = tracing_subscriber::fmt::layer()
+    conf::init_overrides(overrides);
+    // 加载配置时日志还没有按配置初始化，期间的日志只输出到控制台
+    let bootstrap = tracing_subscriber::fmt()
         .with_writer(std::io::stderr)
-        .with_timer(ChronoLocal::new("%Y-%m-%d %H:%M:%S%.3f".to_string()))
-        .with_ansi(true)
-        .with_level(true)
+        .with_timer(ConsoleTimer)
+        .fmt_fields(console_fields())
         .with_target(false)
-        .with_filter(console_filter);
-
-    // 设置文件日志格式
-    let file_layer = tracing_subscriber::fmt::layer()
-        .json()
-        .with_span_events(FmtSpan::CLOSE | FmtSpan::NEW)
-        .with_writer(non_blocking)
-        .with_ansi(false)
-        .with_level(true)
-        .with_target(true)
-        .with_thread_ids(true)
-        .with_thread_names(true)
-        .with_filter(file_filter);
-
-    // 初始化日志订阅者
-    tracing_subscriber::registry()
-        .with(console_layer)
-        .with(file_layer)
-        .init();
-
-    work().await;
-}
-
-#[instrument]
-/// 主工作函数，负责初始化充电桩，连接 WebSocket 服务器，并处理消息。
-async fn work() {
-    tracing::info!("程序 PID: {}", std::process::id());
-    // 初始化充电桩
-    tracing::info!("充电桩服务启动");
-    let _conf = &*CONF;
-    // 打断通道
-    let (breakdown_tx, mut breakdown_rx) = oneshot::channel::<()>();
-    // 检测是否允许充电桩被打断
-    if CONF.charge.allow_break {
-        tracing::info!("充电桩允许被打断, 按 'p' 键可以模拟充电桩损坏");
-        wait_for_p_key(breakdown_tx).await;
-    } else {
-        tracing::info!("充电桩不允许被打断");
+        .with_max_level(if cfg!(debug_assertions) {
+            tracing::Level::DEBUG
+        } else {
+            tracing::Level::INFO
+        })
+        .finish();
+    tracing::subscriber::with_default(bootstrap, || LazyLock::force(&CONF));
+    let _guard = logging::init(&CONF.log);
+    match args.first().map(String::as_str) {
+        Some("audit") => return run_audit(&args[1..]),
+        Some("bench") => return run_bench(&args[1..]),
+        Some("check-prices") => return run_check_prices(&args[1..]),
+        Some("price-diff") => return run_price_diff(&args[1..]),
+        Some("reconcile") => return run_reconcile(&args[1..]),
+        Some("trace-merge") => return run_trace_merge(&args[1..]),
+        _ => {}
     }
-    // 链接 WebSocket 服务器
-    let result = timeout(
-        Duration::from_secs(10),
-        connect_async(CONF.websocket.url.clone()),
-    )
-    .await;
-    let (ws_stream, _) = match result {
-        Ok(Ok(val)) => val,
-        Ok(Err(e)) => {
-            tracing::error!("WebSocket 连接失败: {}", e);
-            IS_CLOSED.store(true, std::sync::atomic::Ordering::Release);
-            return;
-        }
-        Err(_) => {
-            tracing::error!("WebSocket 连接超时");
-            IS_CLOSED.store(true, std::sync::atomic::Ordering::Release);
-            return;
-        }
-    };
-    let (mut ws_sender, mut ws_receiver) = ws_stream.split();
-    tracing::info!("WebSocket 连接成功: {}", CONF.websocket.url);
 
-    let mut update_tiker: Option<Interval> = None;
-    let mut complete_tiker: Option<Interval> = None;
-
-    // 注册充电桩
-    register(&mut ws_sender).await;
-
-    loop {
-        tokio::select! {
-            msg = ws_receiver.next() => {
-                match msg {
-                    Some(Ok(message)) => {
-                        match message {
-                            WsMessage::Text(text) => {
-                                handle(text.to_string(), &mut ws_sender, &mut update_tiker, &mut complete_tiker).await;
-                            }
-                            WsMessage::Close(_) => {
-                                tracing::info!(virtual_time = %get_mock_now(), "WebSocket 连接已关闭");
-                                break;
-                            }
-                            _ => {
-                                tracing::warn!(virtual_time = %get_mock_now(), "接收到非文本消息: {:?}，自动忽略", message);
-                            }
-                        }
-                    }
-                    Some(Err(e)) => {
-                        tracing::error!(virtual_time = %get_mock_now(), "WebSocket 接收消息失败: {}", e);
-                        break;
-                    }
-                    None => {
-                        tracing::info!(virtual_time = %get_mock_now(), "WebSocket 连接已关闭");
-                        break;
-                    }
-                }
-            }
-            _update = wait_opt_ticker(&mut update_tiker)=> {
-                try_update_charge(&mut ws_sender, &mut update_tiker).await;
-            }
-            _complete = wait_opt_ticker(&mut complete_tiker) => {
-                try_complete_charge(&mut ws_sender, &mut update_tiker, &mut complete_tiker).await;
-            }
-            _break = &mut breakdown_rx => {
-                match _break {
-                    Ok(_) => {
-                        tracing::info!(virtual_time = %get_mock_now(), "接收到充电桩损坏信号");
-                        try_breakdown_charge(&mut ws_sender, &mut update_tiker, &mut complete_tiker).await;
-                        ws_sender.close().await.ok();
-                        break;
-                    }
-                    Err(_) => {
-                        tracing::warn!(virtual_time = %get_mock_now(), "充电桩损坏信号已被取消");
-                        break;
-                    }
-                }
-            }
-        }
+    client::run().await;
+    if client::is_auth_failed() {
+        tracing::error!(
+            "服务器拒绝认证，程序以退出码 {} 结束",
+            client::AUTH_FAILED_EXIT_CODE
+        );
+        drop(_guard);
+        std::process::exit(client::AUTH_FAILED_EXIT_CODE);
     }
-    tracing::info!(virtual_time = %get_mock_now(), "充电桩服务已停止");
-    IS_CLOSED.store(true, std::sync::atomic::Ordering::Release);
-}
-
-/// 等待一个可选的计时器，如果计时器存在，则等待其 tick，否则等待直到有新的事件发生。
-async fn wait_opt_ticker(ticker: &mut Option<Interval>) {
-    if let Some(t) = ticker {
-        t.tick().await;
-    } else {
-        futures_util::future::pending::<()>().await;
+    if standalone::is_failed() {
+        drop(_guard);
+        std::process::exit(standalone::FAILED_EXIT_CODE);
     }
 }
 
-/// 设置计时器
-fn set_ticker(ticker: &mut Option<Interval>, duration: Duration) {
-    if duration.is_zero() {
-        tracing::warn!(
-            virtual_time = %get_mock_now(), "设置的计时器时长为零，将使用 tokio::time::interval (可能立即触发): {:?}",
-            duration
-        );
-        // 对于零时长，如果期望立即触发，原始的 interval() 行为是符合的
-        *ticker = Some(interval(duration));
-    } else {
-        // 计算第一个 tick 应该发生的时间
-        tracing::debug!(virtual_time = %get_mock_now(), "设置计时器，间隔: {:?}", duration);
-        let first_tick_time = tokio::time::Instant::now() + duration;
-        *ticker = Some(interval_at(first_tick_time, duration));
+/// 检查价格表，每行输出一个错误或警告
+/// 用法: `taranis check-prices [文件]`，默认检查配置中的 `price.path`
+/// 有错误时以退出码 1 结束，只有警告（没有时段覆盖的时间）时以退出码 0 结束
+fn run_check_prices(args: &[String]) {
+    let path = args
+        .first()
+        .cloned()
+        .unwrap_or_else(|| CONF.price.path.clone());
+    let prices = Prices::read_unchecked(&path).unwrap_or_else(|e| {
+        tracing::error!("价格表加载失败: {}", e);
+        std::process::exit(2);
+    });
+    let report = prices.validate();
+    for error in &report.errors {
+        println!("error: {}", error);
     }
-}
-
-/// 移除计时器
-fn remove_ticker(ticker: &mut Option<Interval>) {
-    *ticker = None;
-}
-
-/// 等待 'p' 键被按下，如果允许充电桩被打断，则模拟充电桩损坏。
-async fn wait_for_p_key(tx: oneshot::Sender<()>) {
-    let _ = task::spawn_blocking(move || {
-        loop {
-            if event::poll(Duration::from_millis(100)).unwrap() {
-                if let Event::Key(key_event) = event::read().unwrap() {
-                    if key_event.code == KeyCode::Char('p') || key_event.code == KeyCode::Char('P')
-                    {
-                        tracing::info!("检测到 'p' 键被按下，模拟充电桩损坏");
-                        let _ = tx.send(()); // 发送打断信号
-                        break;
-                    }
-                }
-            } else if IS_CLOSED.load(std::sync::atomic::Ordering::Acquire) {
-                break;
-            }
-        }
-    })
-    .instrument(tracing::info_span!("等待 'p' 键被按下"));
-}
-/// 注册充电桩到 WebSocket 服务器
-async fn register(ws_sender: &mut WsSender) {
-    let reg_msg = MSG {
-        type_: MessageType::Register,
-        data: serde_json::to_string(&*CHARGE.lock().await).unwrap(),
-    };
-    match ws_sender
-        .send(WsMessage::Text(
-            serde_json::to_string(&reg_msg).unwrap().into(),
-        ))
-        .await
-    {
-        Ok(_) => tracing::info!("充电桩注册消息发送成功"),
-        Err(e) => tracing::error!("充电桩注册消息发送失败: {}", e),
+    for warning in &report.warnings {
+        println!("warning: {}", warning);
+    }
+    tracing::info!(
+        "价格表 {} 共有 {} 个错误，{} 个警告",
+        path,
+        report.errors.len(),
+        report.warnings.len()
+    );
+    if !report.is_ok() {
+        std::process::exit(1);
     }
 }
 
-/// 处理接收到的消息
-async fn handle(
-    message: String,
-    ws_sender: &mut WsSender,
-    update_ticker: &mut Option<Interval>,
-    complete_ticker: &mut Option<Interval>,
-) {
-    static IS_CLOSED: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
-
-    tracing::debug!(virtual_time = %get_mock_now(), "接收到消息: {}", message);
-    let msg: MSG = match serde_json::from_str(&message) {
-        Ok(m) => m,
-        Err(e) => {
-            tracing::error!(virtual_time = %get_mock_now(), "消息解析失败: {}", e);
-            return;
-        }
-    };
-
-    match msg.type_ {
-        MessageType::New => {
-            if IS_CLOSED.load(std::sync::atomic::Ordering::SeqCst) {
-                tracing::warn!(virtual_time = %get_mock_now(), "充电桩已关闭，无法处理新充电请求");
-                return;
-            }
-            handle_new(msg.data, ws_sender, update_ticker, complete_ticker).await;
-        }
-        MessageType::Cancel => {
-            if IS_CLOSED.load(std::sync::atomic::Ordering::SeqCst) {
-                tracing::warn!(virtual_time = %get_mock_now(), "充电桩已关闭，无法取消充电");
-                return;
-            }
-            handle_cancel(msg.data, ws_sender, update_ticker, complete_ticker).await
-        }
-        MessageType::Close => {
-            if IS_CLOSED.load(std::sync::atomic::Ordering::SeqCst) {
-                tracing::warn!(virtual_time = %get_mock_now(), "充电桩已关闭，无法再次关闭");
-                return;
+/// 比较价格表与参考价格表
+/// 用法: `taranis price-diff --reference <文件> [--prices <文件>] [--tolerance <偏差>]`
+/// 有差异超过允许偏差时以退出码 1 结束
+fn run_price_diff(args: &[String]) {
+    let mut reference: Option<String> = None;
+    let mut prices_path = CONF.price.path.clone();
+    let mut tolerance = 0.0;
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        let value = iter.next();
+        let parsed = match (arg.as_str(), value) {
+            ("--reference", Some(v)) => {
+                reference = Some(v.clone());
+                true
             }
-            handle_close(ws_sender, update_ticker, complete_ticker).await;
-            IS_CLOSED.store(true, std::sync::atomic::Ordering::SeqCst);
-        }
-        MessageType::Open => {
-            if !IS_CLOSED.load(std::sync::atomic::Ordering::SeqCst) {
-                tracing::warn!(virtual_time = %get_mock_now(), "充电桩未关闭，无法重新打开");
-                return;
+            ("--prices", Some(v)) => {
+                prices_path = v.clone();
+                true
             }
-            handle_open(update_ticker, complete_ticker).await;
-            IS_CLOSED.store(false, std::sync::atomic::Ordering::SeqCst);
-        }
-        _ => {
-            tracing::warn!(virtual_time = %get_mock_now(), "非法消息类型: {:?}", msg.type_);
+            ("--tolerance", Some(v)) => v.parse().map(|v| tolerance = v).is_ok(),
+            _ => false,
+        };
+        if !parsed {
+            tracing::error!("无法解析价格比较参数: {} {:?}", arg, value);
+            std::process::exit(2);
         }
     }
-}
-
-/// 检查充电桩是否未工作，如果未工作且队列中有充电详单，则开始工作并设置计时器。
-async fn not_working_check(charge: &mut Charge, complete_ticker: &mut Option<Interval>) -> bool {
-    if !charge.is_working() && charge.get_queue_size() > 0 {
-        tracing::info!(virtual_time = %get_mock_now(), "充电桩未工作，开始工作");
-        charge.start_charging();
-        // println!("{:?}", Duration::from_secs(charge.complete_interval()));
-        set_ticker(
-            complete_ticker,
-            Duration::from_millis(charge.complete_interval()),
-        );
-        true
-    } else {
-        false
-    }
-}
-
-/// 发送充电详单更新消息
-async fn send_update(ws_sender: &mut WsSender, detail: &ChargingDetail) {
-    let update_msg = MSG {
-        type_: MessageType::Update,
-        data: serde_json::to_string(detail).unwrap(),
+    let Some(reference) = reference else {
+        tracing::error!("缺少参数 --reference");
+        std::process::exit(2);
     };
-    match ws_sender
-        .send(WsMessage::Text(
-            serde_json::to_string(&update_msg).unwrap().into(),
-        ))
-        .await
-    {
-        Ok(_) => {
-            tracing::debug!(virtual_time = %get_mock_now(), "充电详单更新消息发送成功: {}", detail.get_id())
-        }
-        Err(e) => {
-            tracing::error!(virtual_time = %get_mock_now(), "充电详单更新消息发送失败: {}", e)
-        }
-    }
-}
-
-/// 发送充电详单完成消息
-async fn send_complete(ws_sender: &mut WsSender, detail: &ChargingDetail) {
-    let complete_msg = MSG {
-        type_: MessageType::Complete,
-        data: serde_json::to_string(detail).unwrap(),
+    let load = |path: &str| {
+        Prices::from_path(path).unwrap_or_else(|e| {
+            tracing::error!("价格表加载失败: {}", e);
+            std::process::exit(2);
+        })
     };
-    match ws_sender
-        .send(WsMessage::Text(
-            serde_json::to_string(&complete_msg).unwrap().into(),
-        ))
-        .await
-    {
-        Ok(_) => tracing::info!(virtual_time = %get_mock_now(), "充电详单完成消息发送成功"),
+    let diffs = match load(&prices_path).diff(&load(&reference)) {
+        Ok(diffs) => diffs,
         Err(e) => {
-            tracing::error!(virtual_time = %get_mock_now(), "充电详单完成消息发送失败: {}", e)
+            tracing::error!("价格表比较失败: {}", e);
+            std::process::exit(2);
         }
-    }
-}
-
-/// 发送充电详单故障消息
-async fn send_fault(ws_sender: &mut WsSender, detail: Option<&ChargingDetail>) {
-    let fault_msg = MSG {
-        type_: MessageType::Fault,
-        data: serde_json::to_string(&detail).unwrap(),
     };
-    match ws_sender
-        .send(WsMessage::Text(
-            serde_json::to_string(&fault_msg).unwrap().into(),
-        ))
-        .await
-    {
-        Ok(_) => tracing::info!(virtual_time = %get_mock_now(), "充电详单故障消息发送成功"),
-        Err(e) => {
-            tracing::error!(virtual_time = %get_mock_now(), "充电详单故障消息发送失败: {}", e)
-        }
+    for diff in &diffs {
+        println!("{}", serde_json::to_string(diff).unwrap());
+    }
+    let exceeded = diffs.iter().filter(|d| d.magnitude() > tolerance).count();
+    tracing::info!(
+        "价格表 {} 与参考价格表 {} 共有 {} 处差异，其中 {} 处超过允许偏差 {}",
+        prices_path,
+        reference,
+        diffs.len(),
+        exceeded,
+        tolerance
+    );
+    if exceeded > 0 {
+        std::process::exit(1);
     }
 }
 
-/// 处理新的充电详单消息
-async fn handle_new(
-    msg: String,
-    ws_sender: &mut WsSender,
-    update_ticker: &mut Option<Interval>,
-    complete_ticker: &mut Option<Interval>,
-) {
-    let detail: ChargingDetail = match serde_json::from_str(&msg) {
-        Ok(d) => d,
-        Err(e) => {
-            tracing::error!(virtual_time = %get_mock_now(), "充电详单解析失败: {}", e);
-            return;
+/// 核对充电桩记录的会话、服务器导出的会话和电表读数
+/// 用法: `taranis reconcile --archive <文件> --server-dump <文件> [--meter <kWh>] [--tolerance <偏差>] [--json]`
+/// 有不一致时以退出码 1 结束
+fn run_reconcile(args: &[String]) {
+    let mut archive_path: Option<String> = None;
+    let mut dump_path: Option<String> = None;
+    let mut meter_kwh: Option<f64> = None;
+    let mut tolerance = 0.01;
+    let mut json = false;
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if arg == "--json" {
+            json = true;
+            continue;
+        }
+        let value = iter.next();
+        let parsed = match (arg.as_str(), value) {
+            ("--archive", Some(v)) => {
+                archive_path = Some(v.clone());
+                true
+            }
+            ("--server-dump", Some(v)) => {
+                dump_path = Some(v.clone());
+                true
+            }
+            ("--meter", Some(v)) => v.parse().map(|v| meter_kwh = Some(v)).is_ok(),
+            ("--tolerance", Some(v)) => v.parse().map(|v| tolerance = v).is_ok(),
+            _ => false,
+        };
+        if !parsed {
+            tracing::error!("无法解析对账参数: {} {:?}", arg, value);
+            std::process::exit(2);
         }
+    }
+    let (Some(archive_path), Some(dump_path)) = (archive_path, dump_path) else {
+        tracing::error!("缺少参数 --archive 或 --server-dump");
+        std::process::exit(2);
     };
-    tracing::info!(virtual_time = %get_mock_now(), "接收到新的充电详单: {}", detail.get_id());
-
-    if !detail.is_ready() {
-        tracing::warn!(virtual_time = %get_mock_now(), "充电详单格式异常，无法加入队列");
-        return;
+    let load = |path: &str, parse: fn(&str) -> Result<Vec<reconcile::SessionRecord>, String>| {
+        std::fs::read_to_string(path)
+            .map_err(|e| e.to_string())
+            .and_then(|content| parse(&content))
+            .unwrap_or_else(|e| {
+                tracing::error!("会话记录 {} 加载失败: {}", path, e);
+                std::process::exit(2);
+            })
+    };
+    let archive = load(&archive_path, reconcile::parse_archive);
+    let server = load(&dump_path, reconcile::parse_server_dump);
+    let report = reconcile::reconcile(&archive, &server, meter_kwh, tolerance);
+    if json {
+        println!("{}", serde_json::to_string(&report).unwrap());
     } else {
-        let mut charge = CHARGE.lock().await;
-        charge.add_detail(detail);
-        tracing::info!(
-            virtual_time = %get_mock_now(), "充电详单已加入队列，当前队列长度: {}",
-            charge.get_queue_size()
+        print!("{}", report);
+    }
+    if !report.is_consistent() {
+        tracing::error!(
+            "对账不一致: {} 处数值差异，{} 个会话只在充电桩记录中，{} 个会话只在服务器记录中",
+            report.mismatches.len(),
+            report.only_in_archive.len(),
+            report.only_in_server.len()
         );
-        if not_working_check(&mut charge, complete_ticker).await {
-            send_update(ws_sender, charge.get_charging_detail_ref().unwrap()).await;
-            set_ticker(
-                update_ticker,
-                Duration::from_millis(CONF.time.update_interval),
-            );
-        }
+        std::process::exit(1);
     }
 }
 
-/// 处理取消充电详单消息
-async fn handle_cancel(
-    msg: String,
-    ws_sender: &mut WsSender,
-    update_ticker: &mut Option<Interval>,
-    complete_ticker: &mut Option<Interval>,
-) {
-    let detail: ChargingDetail = match serde_json::from_str(&msg) {
-        Ok(d) => d,
-        Err(e) => {
-            tracing::error!(virtual_time = %get_mock_now(), "充电详单解析失败: {}", e);
-            return;
-        }
-    };
-    let detail_id = detail.get_id();
-    tracing::info!(virtual_time = %get_mock_now(), "接收到取消充电详单请求: {}", detail_id);
-
-    let mut charge = CHARGE.lock().await;
-    match charge.cancel_charging(detail_id) {
-        Ok(detail) => {
-            tracing::info!(virtual_time = %get_mock_now(), "充电详单 {} 已取消", detail_id);
-            send_update(ws_sender, &detail).await;
-            if not_working_check(&mut charge, complete_ticker).await {
-                send_update(ws_sender, charge.get_charging_detail_ref().unwrap()).await;
-                set_ticker(
-                    update_ticker,
-                    Duration::from_millis(CONF.time.update_interval),
-                );
+/// 合并多个充电桩的功率记录为整个充电站的功率，按行输出到标准输出
+/// 用法: `taranis trace-merge [--interval <秒>] <文件>...`
+fn run_trace_merge(args: &[String]) {
+    let mut interval = CONF.trace.sample_interval_s;
+    let mut paths = Vec::new();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if arg == "--interval" {
+            match iter.next().and_then(|v| v.parse().ok()) {
+                Some(v) if v > 0 => interval = v,
+                value => {
+                    tracing::error!("无法解析采样间隔: {:?}", value);
+                    std::process::exit(2);
+                }
             }
-        }
-        Err(e) => {
-            tracing::error!(virtual_time = %get_mock_now(), "取消充电详单失败: {}", e);
+        } else {
+            paths.push(arg.clone());
         }
     }
-}
-
-/// 处理关闭充电桩请求
-async fn handle_close(
-    ws_sender: &mut WsSender,
-    update_ticker: &mut Option<Interval>,
-    complete_ticker: &mut Option<Interval>,
-) {
-    tracing::info!(virtual_time = %get_mock_now(), "接收到关闭充电桩请求");
-    let mut charge = CHARGE.lock().await;
-    if let Some(detail) = charge.close() {
-        tracing::info!(virtual_time = %get_mock_now(), "充电桩已关闭，当前被打断的充电详单: {}", detail.get_id());
-        send_update(ws_sender, &detail).await;
-    } else {
-        tracing::info!(virtual_time = %get_mock_now(), "充电桩队列为空，没有被打断的充电详单");
+    if paths.is_empty() {
+        tracing::error!("缺少功率记录文件");
+        std::process::exit(2);
+    }
+    let traces: Vec<_> = paths
+        .iter()
+        .map(|path| {
+            trace::read_trace(std::path::Path::new(path)).unwrap_or_else(|e| {
+                tracing::error!("功率记录加载失败: {}", e);
+                std::process::exit(2);
+            })
+        })
+        .collect();
+    for sample in trace::aggregate(&traces, interval) {
+        println!("{}", serde_json::to_string(&sample).unwrap());
     }
-    remove_ticker(update_ticker);
-    remove_ticker(complete_ticker);
-}
-
-/// 处理打开充电桩请求
-async fn handle_open(update_ticker: &mut Option<Interval>, complete_ticker: &mut Option<Interval>) {
-    tracing::info!(virtual_time = %get_mock_now(), "接收到打开充电桩请求");
-    remove_ticker(update_ticker);
-    remove_ticker(complete_ticker);
 }
 
-/// 尝试更新充电状态
-async fn try_update_charge(ws_sender: &mut WsSender, update_ticker: &mut Option<Interval>) {
-    let mut charge = CHARGE.lock().await;
-    if charge.is_working() {
-        charge.update_charging();
-        if let Some(detail) = charge.get_charging_detail_ref() {
-            send_update(ws_sender, detail).await;
-        } else {
-            unreachable!(
-                "It should never happen that there is no charging detail when the charge is working"
-            );
+/// 按详单、消息类型、方向或充电桩查找审计日志，满足条件的行原样输出到标准输出
+/// 用法: `taranis audit [--detail <ID>] [--type <消息类型>] [--dir in|out] [--pile <充电桩 ID>] <文件>...`
+/// 轮换后的旧文件按给出的顺序读取，例如 `audit.jsonl.2 audit.jsonl.1 audit.jsonl`
+fn run_audit(args: &[String]) {
+    let mut filter = AuditFilter::default();
+    let mut paths = Vec::new();
+    let mut iter = args.iter();
+    // 消息类型和方向按协议中的名称解析
+    fn parse_name<T: serde::de::DeserializeOwned>(value: &str) -> Option<T> {
+        serde_json::from_value(serde_json::Value::String(value.to_string())).ok()
+    }
+    while let Some(arg) = iter.next() {
+        let parsed = match arg.as_str() {
+            "--detail" => iter
+                .next()
+                .and_then(|v| v.parse().ok())
+                .map(|v| filter.detail_id = Some(v)),
+            "--type" => iter
+                .next()
+                .and_then(|v| parse_name(v))
+                .map(|v| filter.type_ = Some(v)),
+            "--dir" => iter
+                .next()
+                .and_then(|v| parse_name(v))
+                .map(|v| filter.dir = Some(v)),
+            "--pile" => iter
+                .next()
+                .and_then(|v| v.parse().ok())
+                .map(|v| filter.charge_id = Some(v)),
+            _ => {
+                paths.push(arg.clone());
+                Some(())
+            }
+        };
+        if parsed.is_none() {
+            tracing::error!("无法解析审计日志查找参数: {}", arg);
+            std::process::exit(2);
         }
-    } else {
-        tracing::error!(virtual_time = %get_mock_now(), "充电桩未处于工作状态，无法更新充电状态");
-        remove_ticker(update_ticker);
     }
-}
-
-/// 尝试完成充电
-async fn try_complete_charge(
-    ws_sender: &mut WsSender,
-    update_ticker: &mut Option<Interval>,
-    complete_ticker: &mut Option<Interval>,
-) {
-    let mut charge = CHARGE.lock().await;
-    if charge.is_working() {
-        if let Some(detail) = charge.complete_charging() {
-            send_complete(ws_sender, &detail).await;
-            remove_ticker(complete_ticker);
-            remove_ticker(update_ticker);
-            tracing::info!(virtual_time = %get_mock_now(), "充电详单 {} 已完成", detail.get_id());
-            if not_working_check(&mut charge, complete_ticker).await {
-                send_update(ws_sender, charge.get_charging_detail_ref().unwrap()).await;
-                set_ticker(
-                    update_ticker,
-                    Duration::from_millis(CONF.time.update_interval),
-                );
+    if paths.is_empty() {
+        tracing::error!("缺少审计日志文件");
+        std::process::exit(2);
+    }
+    for path in &paths {
+        match audit::search(std::path::Path::new(path), &filter) {
+            Ok(lines) => lines.iter().for_each(|line| println!("{}", line)),
+            Err(e) => {
+                tracing::error!("审计日志读取失败: {}", e);
+                std::process::exit(2);
             }
-        } else {
-            unreachable!(
-                "It should never happen that there is no charging detail when the charge is working"
-            );
         }
-    } else {
-        tracing::error!(virtual_time = %get_mock_now(), "充电桩未处于工作状态，无法完成充电");
-        remove_ticker(complete_ticker);
-        remove_ticker(update_ticker);
     }
 }
 
-/// 尝试打断充电
-async fn try_breakdown_charge(
-    ws_sender: &mut WsSender,
-    update_ticker: &mut Option<Interval>,
-    complete_ticker: &mut Option<Interval>,
-) {
-    tracing::error!(virtual_time = %get_mock_now(),"充电桩损坏");
-    let mut charge = CHARGE.lock().await;
-    if charge.is_working() {
-        if let Some(detail) = charge.breakdown() {
-            send_fault(ws_sender, Some(&detail)).await;
-            remove_ticker(complete_ticker);
-            remove_ticker(update_ticker);
-            tracing::info!(virtual_time = %get_mock_now(), "充电详单 {} 已被打断", detail.get_id());
-        } else {
-            unreachable!(
-                "It should never happen that there is no charging detail when the charge is working"
-            );
+/// 运行模拟速度基准测试
+/// 用法: `taranis bench [--virtual-secs <秒>] [--max-us-per-update <微秒>]`
+fn run_bench(args: &[String]) {
+    let mut virtual_secs: i64 = 24 * 3600;
+    let mut ceiling: Option<f64> = None;
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        let value = iter.next();
+        let parsed = match arg.as_str() {
+            "--virtual-secs" => value.and_then(|v| v.parse().ok()).map(|v| virtual_secs = v),
+            "--max-us-per-update" => value
+                .and_then(|v| v.parse().ok())
+                .map(|v| ceiling = Some(v)),
+            _ => None,
+        };
+        if parsed.is_none() {
+            tracing::error!("无法解析基准测试参数: {} {:?}", arg, value);
+            std::process::exit(2);
+        }
+    }
+    let step = chrono::Duration::milliseconds(CONF.time.update_interval as i64);
+    tracing::info!(
+        "开始基准测试: 虚拟时长 {} 秒，每 {} 虚拟毫秒更新一次",
+        virtual_secs,
+        step.num_milliseconds()
+    );
+    let report = bench::run(chrono::Duration::seconds(virtual_secs), step);
+    tracing::info!("基准测试结果: {}", report);
+    if let Some(ceiling) = ceiling {
+        match bench::check_ceiling(&report, ceiling) {
+            Ok(()) => tracing::info!("每次更新耗时未超过上限 {} 微秒", ceiling),
+            Err(e) => {
+                tracing::error!("基准测试未通过: {}", e);
+                std::process::exit(1);
+            }
         }
-    } else {
-        tracing::info!(virtual_time = %get_mock_now(), "充电桩未处于工作状态，没有被打断的充电详单");
-        send_fault(ws_sender, None).await;
-        remove_ticker(complete_ticker);
-        remove_ticker(update_ticker);
     }
 }
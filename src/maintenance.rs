@@ -0,0 +1,224 @@
+//! 维护时间窗口
+
+use chrono::{DateTime, Duration, NaiveDate, TimeZone, Utc};
+use chrono_tz::Tz;
+
+use crate::conf::MaintenanceWindow;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// 维护阶段
+pub enum MaintenancePhase {
+    /// 正常营业
+    Open,
+    /// 排空中，不再接收新的充电请求，也不再开始新的充电
+    Draining {
+        /// 维护开始时间
+        start: DateTime<Utc>,
+        /// 维护结束时间
+        end: DateTime<Utc>,
+    },
+    /// 维护中
+    InWindow {
+        /// 维护结束时间
+        end: DateTime<Utc>,
+    },
+}
+
+impl MaintenancePhase {
+    /// 是否允许接收新的充电请求
+    pub fn accepts_new(&self) -> bool {
+        matches!(self, MaintenancePhase::Open)
+    }
+}
+
+/// 将本地日期和时间转换为 UTC 时间，遇到夏令时跳过的时间时取之后最近的时间
+fn local_to_utc(tz: &Tz, date: NaiveDate, time: chrono::NaiveTime) -> Option<DateTime<Utc>> {
+    let naive = date.and_time(time);
+    tz.from_local_datetime(&naive)
+        .earliest()
+        .or_else(|| {
+            tz.from_local_datetime(&(naive + Duration::hours(1)))
+                .earliest()
+        })
+        .map(|t| t.with_timezone(&Utc))
+}
+
+/// 计算包含当前时间或在当前时间之后最近的维护窗口
+pub fn next_window(
+    windows: &[MaintenanceWindow],
+    now: DateTime<Utc>,
+    tz: &Tz,
+) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+    let today = now.with_timezone(tz).date_naive();
+    let mut best: Option<(DateTime<Utc>, DateTime<Utc>)> = None;
+    // 从前一天开始检查，以覆盖跨越 0 点的窗口
+    for offset in -1..=7 {
+        let date = today + Duration::days(offset);
+        for window in windows {
+            if !window.applies_on(date) {
+                continue;
+            }
+            let end_date = if window.end <= window.start {
+                date.succ_opt()?
+            } else {
+                date
+            };
+            let (Some(start), Some(end)) = (
+                local_to_utc(tz, date, window.start),
+                local_to_utc(tz, end_date, window.end),
+            ) else {
+                continue;
+            };
+            if end <= now {
+                continue;
+            }
+            if best.is_none_or(|(s, _)| start < s) {
+                best = Some((start, end));
+            }
+        }
+    }
+    best
+}
+
+/// 计算当前的维护阶段
+/// `session_duration` 为从现在到下一个开始的充电会话结束预计还需要的时长，排空会提前到足以让其完成的时刻
+pub fn phase(
+    windows: &[MaintenanceWindow],
+    now: DateTime<Utc>,
+    tz: &Tz,
+    session_duration: Option<Duration>,
+) -> MaintenancePhase {
+    let Some((start, end)) = next_window(windows, now, tz) else {
+        return MaintenancePhase::Open;
+    };
+    if now >= start {
+        MaintenancePhase::InWindow { end }
+    } else if now >= drain_trigger_time(start, session_duration) {
+        MaintenancePhase::Draining { start, end }
+    } else {
+        MaintenancePhase::Open
+    }
+}
+
+/// 计算进入排空模式的时间，即该时长的会话仍能在维护开始前完成的最晚开始时间
+pub fn drain_trigger_time(
+    window_start: DateTime<Utc>,
+    session_duration: Option<Duration>,
+) -> DateTime<Utc> {
+    match session_duration {
+        Some(duration) if duration > Duration::zero() => window_start - duration,
+        _ => window_start,
+    }
+}
+
+/// 计算下一次维护阶段可能变化的时间
+pub fn next_transition(
+    windows: &[MaintenanceWindow],
+    now: DateTime<Utc>,
+    tz: &Tz,
+    session_duration: Option<Duration>,
+) -> Option<DateTime<Utc>> {
+    match phase(windows, now, tz, session_duration) {
+        MaintenancePhase::Open => next_window(windows, now, tz)
+            .map(|(start, _)| drain_trigger_time(start, session_duration)),
+        MaintenancePhase::Draining { start, .. } => Some(start),
+        MaintenancePhase::InWindow { end } => Some(end),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{NaiveTime, Weekday};
+
+    fn nightly() -> Vec<MaintenanceWindow> {
+        vec![MaintenanceWindow {
+            start: NaiveTime::from_hms_opt(2, 0, 0).unwrap(),
+            end: NaiveTime::from_hms_opt(2, 30, 0).unwrap(),
+            days: None,
+        }]
+    }
+
+    fn local(tz: &Tz, s: &str) -> DateTime<Utc> {
+        let naive = chrono::NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S").unwrap();
+        tz.from_local_datetime(&naive).unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn test_evening_queue_approaching_window() {
+        let tz: Tz = "Asia/Shanghai".parse().unwrap();
+        let windows = nightly();
+        // 当前会话预计时长 1 小时
+        let duration = Some(Duration::hours(1));
+
+        let now = local(&tz, "2025-06-01 22:00:00");
+        assert_eq!(phase(&windows, now, &tz, duration), MaintenancePhase::Open);
+        assert_eq!(
+            next_transition(&windows, now, &tz, duration),
+            Some(local(&tz, "2025-06-02 01:00:00"))
+        );
+
+        // 01:00 之后开始的 1 小时会话无法在 02:00 前完成，进入排空
+        let (start, end) = next_window(&windows, now, &tz).unwrap();
+        assert_eq!(start, local(&tz, "2025-06-02 02:00:00"));
+        assert_eq!(end, local(&tz, "2025-06-02 02:30:00"));
+        assert_eq!(
+            drain_trigger_time(start, duration),
+            local(&tz, "2025-06-02 01:00:00")
+        );
+        let before = local(&tz, "2025-06-02 00:59:59");
+        assert!(phase(&windows, before, &tz, duration).accepts_new());
+        let trigger = local(&tz, "2025-06-02 01:00:00");
+        assert_eq!(
+            phase(&windows, trigger, &tz, duration),
+            MaintenancePhase::Draining { start, end }
+        );
+        assert!(!phase(&windows, trigger, &tz, duration).accepts_new());
+        assert_eq!(
+            next_transition(&windows, trigger, &tz, duration),
+            Some(start)
+        );
+
+        // 空闲时到维护开始才进入维护
+        let idle = local(&tz, "2025-06-02 01:59:59");
+        assert!(phase(&windows, idle, &tz, None).accepts_new());
+    }
+
+    #[test]
+    fn test_rejections_and_reopen() {
+        let tz: Tz = "Asia/Shanghai".parse().unwrap();
+        let windows = nightly();
+
+        let during = local(&tz, "2025-06-02 02:10:00");
+        let end = local(&tz, "2025-06-02 02:30:00");
+        assert_eq!(
+            phase(&windows, during, &tz, None),
+            MaintenancePhase::InWindow { end }
+        );
+        assert!(!phase(&windows, during, &tz, None).accepts_new());
+        assert_eq!(next_transition(&windows, during, &tz, None), Some(end));
+
+        // 维护结束时自动恢复
+        assert_eq!(phase(&windows, end, &tz, None), MaintenancePhase::Open);
+    }
+
+    #[test]
+    fn test_days_of_week_and_midnight_cross() {
+        let tz: Tz = "Asia/Shanghai".parse().unwrap();
+        let windows = vec![MaintenanceWindow {
+            start: NaiveTime::from_hms_opt(23, 30, 0).unwrap(),
+            end: NaiveTime::from_hms_opt(0, 30, 0).unwrap(),
+            days: Some(vec![Weekday::Sun]),
+        }];
+        // 2025-06-01 为周日
+        let now = local(&tz, "2025-06-02 00:10:00");
+        assert_eq!(
+            phase(&windows, now, &tz, None),
+            MaintenancePhase::InWindow {
+                end: local(&tz, "2025-06-02 00:30:00")
+            }
+        );
+        let (start, _) = next_window(&windows, local(&tz, "2025-06-02 01:00:00"), &tz).unwrap();
+        assert_eq!(start, local(&tz, "2025-06-08 23:30:00"));
+    }
+}
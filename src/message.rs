@@ -1,6 +1,18 @@
-use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
-#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+use chrono::{DateTime, Utc};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::Value;
+use tokio_tungstenite::tungstenite::Message as WsMessage;
+use uuid::Uuid;
+
+use crate::compat;
+use crate::conf::{ChargeType, UpdateMode, WireEncoding};
+use crate::detail::ChargingDetail;
+use crate::state::PileState;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 /// 消息类型枚举
 pub enum MessageType {
     #[serde(rename = "register")]
@@ -18,6 +30,15 @@ pub enum MessageType {
     #[serde(rename = "new")]
     /// 新消息
     New,
+    #[serde(rename = "new_batch")]
+    /// 批量新消息，数据为详单数组
+    NewBatch,
+    #[serde(rename = "reject")]
+    /// 拒绝新详单消息
+    Reject,
+    #[serde(rename = "ack")]
+    /// 确认消息，充电桩发送时表示新详单已加入队列，服务器发送时确认收到完成或故障消息
+    Ack,
     #[serde(rename = "cancel")]
     /// 取消消息
     Cancel,
@@ -27,16 +48,537 @@ pub enum MessageType {
     #[serde(rename = "open")]
     /// 打开消息
     Open,
+    #[serde(rename = "error")]
+    /// 错误消息
+    Error,
+    #[serde(rename = "delta")]
+    /// 增量更新消息
+    Delta,
+    #[serde(rename = "repair")]
+    /// 修复消息
+    Repair,
+    #[serde(rename = "free_vend")]
+    /// 免费充电消息
+    FreeVend,
+    #[serde(rename = "break")]
+    /// 模拟损坏消息
+    Break,
+    #[serde(rename = "reload_prices")]
+    /// 重新加载价格表消息
+    ReloadPrices,
+    #[serde(rename = "pending")]
+    /// 新详单进入等待区消息
+    Pending,
+    #[serde(rename = "set_speed")]
+    /// 修改时间加速比消息
+    SetSpeed,
+    #[serde(rename = "pause")]
+    /// 暂停虚拟时钟消息
+    Pause,
+    #[serde(rename = "resume")]
+    /// 继续虚拟时钟消息
+    Resume,
+    #[serde(rename = "query")]
+    /// 查询充电桩状态消息
+    Query,
+    #[serde(rename = "status")]
+    /// 充电桩状态快照消息
+    Status,
+    #[serde(rename = "auth_error")]
+    /// 认证失败消息
+    AuthError,
+    #[serde(rename = "heartbeat")]
+    /// 心跳消息
+    Heartbeat,
+    #[serde(rename = "register_ack")]
+    /// 注册确认消息
+    RegisterAck,
+    #[serde(rename = "idle_skip_request")]
+    /// 请求跳过空闲时间消息
+    IdleSkipRequest,
+    #[serde(rename = "idle_skip_grant")]
+    /// 同意跳过空闲时间消息
+    IdleSkipGrant,
 }
 
+/// 服务器拒绝认证时使用的关闭码，策略违规关闭码 1008 同样视为认证失败
+pub const AUTH_FAILED_CLOSE_CODE: u16 = 4401;
+
+/// 充电桩使用的协议版本，消息格式有不兼容的修改时加一
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// 充电桩支持的可选协议功能，随注册消息发送
+pub const PROTOCOL_FEATURES: &[&str] = &[
+    "ack",
+    "reject",
+    "pending",
+    "query",
+    "register_ack",
+    "new_batch",
+];
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 /// 消息结构体
 pub struct MSG {
     #[serde(rename = "type")]
     /// 消息类型
-    pub type_: MessageType, 
-    /// 消息数据
-    pub data: String,
+    pub type_: MessageType,
+    #[serde(default, deserialize_with = "deserialize_data")]
+    /// 消息数据，没有数据时为 `null`
+    pub data: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// 消息 ID，同一个充电桩发送的消息单调递增，用于确认和去重
+    pub msg_id: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// 发送时的虚拟时间
+    pub sent_at: Option<DateTime<Utc>>,
+}
+
+impl MSG {
+    /// 创建不带消息 ID 的消息，发送时再分配消息 ID
+    pub fn new(type_: MessageType, data: Value) -> Self {
+        MSG {
+            type_,
+            data,
+            msg_id: None,
+            sent_at: None,
+        }
+    }
+
+    /// 创建不带数据的消息
+    pub fn empty(type_: MessageType) -> Self {
+        MSG::new(type_, Value::Null)
+    }
+
+    /// 创建以 `payload` 为数据的消息
+    pub fn with_payload<T: Serialize>(type_: MessageType, payload: &T) -> Self {
+        MSG::new(type_, serde_json::to_value(payload).unwrap())
+    }
+
+    /// 把消息数据解析为具体类型，类型不匹配时错误信息中指出字段名，见 [`compat::from_value`]
+    pub fn payload<T: DeserializeOwned>(&self) -> Result<T, String> {
+        compat::from_value(self.data.clone())
+    }
+}
+
+/// 解析消息数据，兼容旧版本中包含 JSON 文本的字符串
+/// 空字符串视为没有数据，内容为 JSON 对象、数组或 `null` 的字符串解开为对应的值，其他字符串保持不变
+fn deserialize_data<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Value, D::Error> {
+    let value = Value::deserialize(deserializer)?;
+    let Value::String(text) = &value else {
+        return Ok(value);
+    };
+    if text.trim().is_empty() {
+        return Ok(Value::Null);
+    }
+    match serde_json::from_str::<Value>(text) {
+        Ok(inner @ (Value::Object(_) | Value::Array(_) | Value::Null)) => Ok(inner),
+        _ => Ok(value),
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+/// 错误消息数据
+pub struct ErrorData {
+    /// 错误原因
+    pub reason: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// 出错位置在消息中的字节偏移
+    pub offset: Option<usize>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+/// 认证失败消息数据
+pub struct AuthErrorData {
+    /// 失败原因
+    pub reason: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+/// 服务器确认收到消息的数据
+pub struct MsgAckData {
+    /// 被确认的消息 ID
+    pub msg_id: u64,
+}
+
+impl MsgAckData {
+    /// 确认消息的所有字段名
+    pub const FIELDS: &'static [&'static str] = &["msg_id"];
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+/// 免费充电消息数据
+pub struct FreeVendData {
+    /// 是否开启免费充电
+    pub enabled: bool,
+}
+
+impl FreeVendData {
+    /// 免费充电消息的所有字段名
+    pub const FIELDS: &'static [&'static str] = &["enabled"];
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+/// 修改时间加速比消息数据
+pub struct SetSpeedData {
+    /// 新的加速倍数，必须为正数，可以小于 1
+    pub speed: f64,
+}
+
+impl SetSpeedData {
+    /// 修改时间加速比消息的所有字段名
+    pub const FIELDS: &'static [&'static str] = &["speed"];
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+/// 请求跳过空闲时间消息数据
+pub struct IdleSkipRequestData {
+    /// 充电桩当前的虚拟时间
+    pub virtual_time: DateTime<Utc>,
+    /// 充电桩当前的加速倍数
+    pub speed: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+/// 同意跳过空闲时间消息数据
+pub struct IdleSkipGrantData {
+    /// 虚拟时钟跳到的时间
+    pub until: DateTime<Utc>,
+}
+
+impl IdleSkipGrantData {
+    /// 同意跳过空闲时间消息的所有字段名
+    pub const FIELDS: &'static [&'static str] = &["until"];
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+/// 充电桩状态快照消息数据
+pub struct StatusData {
+    /// 充电桩ID
+    pub charge_id: Uuid,
+    #[serde(rename = "type")]
+    /// 充电类型
+    pub type_: ChargeType,
+    /// 充电功率，单位为kW
+    pub power: f64,
+    /// 是否正在充电
+    pub working: bool,
+    #[serde(default)]
+    /// 充电桩状态
+    pub state: PileState,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    /// 是否处于免费充电状态，不是时省略
+    pub free_vend: bool,
+    /// 正在充电的详单，有多个时为最早开始充电的详单
+    pub charging: Option<ChargingDetail>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    /// 在其他充电枪上同时充电的详单，按开始充电的顺序排列
+    pub also_charging: Vec<ChargingDetail>,
+    /// 按顺序排队等待的详单，不包括正在充电的详单
+    pub queue: Vec<ChargingDetail>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    /// 队列已满时在等待区中等待的详单，按进入等待区的顺序排列，没有时省略
+    pub pending: Vec<ChargingDetail>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// 按 `grow` 策略超出队列大小的详单数量，没有超出时省略
+    pub overload: Option<u32>,
+    #[serde(default)]
+    /// 产生的状态更新数
+    pub updates_generated: u64,
+    #[serde(default)]
+    /// 实际发送的状态更新数，被替换、合并或丢弃的更新不计入
+    pub updates_sent: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// 当前连接的消息计数，只在回复状态查询时填写
+    pub traffic: Option<TrafficSnapshot>,
+    /// 生成快照时的虚拟时间
+    pub virtual_time: DateTime<Utc>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+/// 单个连接上的消息计数
+pub struct TrafficSnapshot {
+    /// 每种消息的发送次数
+    pub sent: BTreeMap<MessageType, u64>,
+    /// 每种消息的接收次数
+    pub received: BTreeMap<MessageType, u64>,
+    /// 最后一次发送成功的真实时间，还没有发送过时为 `None`
+    pub last_sent_real: Option<DateTime<Utc>>,
+    /// 最后一次发送成功的虚拟时间，还没有发送过时为 `None`
+    pub last_sent_virtual: Option<DateTime<Utc>>,
+}
+
+fn single_connector() -> u32 {
+    1
+}
+
+fn is_single_connector(connectors: &u32) -> bool {
+    *connectors == 1
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+/// 注册消息数据，由充电桩状态和配置组成
+pub struct RegisterPayload {
+    /// 充电桩ID
+    pub charge_id: Uuid,
+    #[serde(rename = "type")]
+    /// 充电类型
+    pub type_: ChargeType,
+    /// 充电功率，单位为kW
+    pub power: f64,
+    /// 队列大小，不限长时为 `null`
+    pub size: Option<u32>,
+    #[serde(
+        default = "single_connector",
+        skip_serializing_if = "is_single_connector"
+    )]
+    /// 充电枪数量，只有一把时不发送
+    pub connectors: u32,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    /// 是否只接受预约
+    pub reservation_only: bool,
+    #[serde(default, skip_serializing_if = "UpdateMode::is_full")]
+    /// 充电状态更新方式
+    pub update_mode: UpdateMode,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    /// 是否处于免费充电状态
+    pub free_vend: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    /// 队列中的详单，队首为正在充电的详单（有多把充电枪时可能有多个）
+    pub queue: Vec<ChargingDetail>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    /// 等待进入队列的详单
+    pub pending: Vec<ChargingDetail>,
+    #[serde(default)]
+    /// 协议版本
+    pub protocol_version: u32,
+    #[serde(default)]
+    /// 充电桩程序的版本号
+    pub software_version: String,
+    #[serde(default)]
+    /// 注册时的时间加速比
+    pub speed: f64,
+    #[serde(default)]
+    /// 是否允许在键盘上手动模拟损坏，服务器发送的 `break` 消息总是支持
+    pub manual_break: bool,
+    #[serde(default)]
+    /// 支持的可选协议功能
+    pub features: Vec<String>,
+    #[serde(default, skip_serializing_if = "WireEncoding::is_json")]
+    /// 希望使用的消息编码方式，使用 JSON 时不发送
+    pub encoding: WireEncoding,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// 当前连接的 WebSocket 地址
+    pub url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// `register` 认证方式下携带的认证令牌
+    pub auth_token: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// 充电桩使用的价格表指纹，用于发现加载了不同价格表的充电桩
+    pub price_fingerprint: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+/// 服务器的注册确认消息数据
+pub struct RegisterAckData {
+    /// 服务器使用的协议版本
+    pub protocol_version: u32,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    /// 服务器支持的可选协议功能
+    pub features: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// 服务器程序的版本号
+    pub server_version: Option<String>,
+    #[serde(default, skip_serializing_if = "WireEncoding::is_json")]
+    /// 服务器同意的消息编码方式，只支持 JSON 的服务器不发送该字段
+    pub encoding: WireEncoding,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// 服务器发送确认时的虚拟时间，充电桩按它调整虚拟时钟
+    pub server_time: Option<DateTime<Utc>>,
+}
+
+impl RegisterAckData {
+    /// 注册确认消息的所有字段名
+    pub const FIELDS: &'static [&'static str] = &[
+        "protocol_version",
+        "features",
+        "server_version",
+        "encoding",
+        "server_time",
+    ];
+
+    /// 按充电桩的协议版本生成确认，服务器支持充电桩的所有功能和请求的编码方式
+    pub fn accept(register: &RegisterPayload) -> Self {
+        RegisterAckData {
+            protocol_version: register.protocol_version,
+            features: register.features.clone(),
+            server_version: None,
+            encoding: register.encoding,
+            server_time: None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+/// 心跳消息数据
+pub struct HeartbeatData {
+    /// 充电桩ID
+    pub charge_id: Uuid,
+    /// 是否正在充电
+    pub working: bool,
+    #[serde(default)]
+    /// 充电桩状态
+    pub state: PileState,
+    /// 队列中的详单数，包括正在充电的详单
+    pub queue_len: usize,
+    /// 发送时的虚拟时间
+    pub virtual_time: DateTime<Utc>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+/// 取消充电详单消息数据，在详单的基础上附带取消原因
+pub struct CancelData {
+    #[serde(flatten)]
+    /// 被取消的详单
+    pub detail: ChargingDetail,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// 取消原因的文字说明
+    pub reason: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// 机器可读的取消原因代码
+    pub reason_code: Option<String>,
+}
+
+impl CancelData {
+    /// 取消消息的所有字段名，包括详单字段
+    pub fn fields() -> Vec<&'static str> {
+        let mut fields = ChargingDetail::FIELDS.to_vec();
+        fields.extend(["reason", "reason_code"]);
+        fields
+    }
+}
+
+impl MSG {
+    /// 按编码方式生成 WebSocket 消息帧，JSON 为文本帧，MessagePack 为二进制帧
+    pub fn to_frame(&self, encoding: WireEncoding) -> WsMessage {
+        match encoding {
+            WireEncoding::Json => WsMessage::Text(serde_json::to_string(self).unwrap().into()),
+            // 按字段名编码，省略的可选字段不会打乱字段的顺序
+            WireEncoding::Msgpack => {
+                WsMessage::Binary(rmp_serde::to_vec_named(self).unwrap().into())
+            }
+        }
+    }
+
+    /// 解析 MessagePack 编码的消息
+    pub fn from_msgpack(bytes: &[u8]) -> Result<MSG, String> {
+        rmp_serde::from_slice(bytes).map_err(|e| e.to_string())
+    }
+}
+
+/// 解析一个 WebSocket 二进制帧，每个帧只包含一条 MessagePack 编码的消息
+/// 内容是以 `{` 开头的 UTF-8 文本时按 JSON 文本帧解析，兼容把 JSON 放在二进制帧中发送的网关
+/// 返回值与 [`parse_frame`] 相同
+pub fn parse_binary(bytes: &[u8]) -> (Vec<MSG>, Option<ErrorData>) {
+    if let Ok(text) = std::str::from_utf8(bytes)
+        && text.trim_start().starts_with('{')
+    {
+        return parse_frame(text);
+    }
+    match MSG::from_msgpack(bytes) {
+        Ok(msg) => (vec![msg], None),
+        Err(reason) => (
+            Vec::new(),
+            Some(ErrorData {
+                reason,
+                offset: None,
+            }),
+        ),
+    }
+}
+
+/// 解析一个 WebSocket 文本帧
+/// 帧中可以包含多个以空白分隔的 JSON 文档，按顺序返回所有有效的消息，以及第一个解析错误（如果有）
+/// 语法正确但不是合法消息的文档被跳过，之后的文档照常解析；
+/// 遇到语法错误时无法确定下一个文档从哪里开始，丢弃帧中剩余的内容
+pub fn parse_frame(text: &str) -> (Vec<MSG>, Option<ErrorData>) {
+    let mut stream = serde_json::Deserializer::from_str(text).into_iter::<Value>();
+    let mut messages = Vec::new();
+    let mut error = None;
+    loop {
+        let offset = stream.byte_offset();
+        let reason = match stream.next() {
+            Some(Ok(value)) => match serde_json::from_value(value) {
+                Ok(msg) => {
+                    messages.push(msg);
+                    continue;
+                }
+                Err(e) => e.to_string(),
+            },
+            Some(Err(e)) => {
+                // 语法错误之后的内容无法可靠地分隔，不再继续解析
+                error.get_or_insert(ErrorData {
+                    reason: e.to_string(),
+                    offset: Some(offset),
+                });
+                return (messages, error);
+            }
+            None => return (messages, error),
+        };
+        error.get_or_insert(ErrorData {
+            reason,
+            offset: Some(offset),
+        });
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+/// 拒绝新详单消息数据
+pub struct RejectData {
+    /// 被拒绝的详单 ID
+    pub id: u32,
+    /// 机器可读的拒绝原因
+    pub reason: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    /// 批量新详单中所有被拒绝的详单，按数组顺序排列，单个详单被拒绝时为空
+    pub rejected: Vec<RejectData>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+/// 新详单期望功率与充电桩功率的偏差超出 `charge.power_tolerance` 时的警告
+pub struct PowerWarning {
+    /// 详单中服务器期望的充电功率，单位为kW
+    pub expected_power: f64,
+    /// 充电桩配置的功率，单位为kW
+    pub pile_power_kw: f64,
+}
+
+impl std::fmt::Display for PowerWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "expected power {} kW differs from pile power {} kW",
+            self.expected_power, self.pile_power_kw
+        )
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+/// 新详单已加入队列消息数据
+pub struct AckData {
+    /// 加入队列的详单 ID
+    pub id: u32,
+    /// 在队列中的位置，0 表示正在充电
+    pub position: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// 被确认的 `new` 消息的消息 ID，`new` 消息没有消息 ID 时为 `None`
+    pub msg_id: Option<u64>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    /// 是否为重复的 `new` 消息，重复的详单不会再次加入队列
+    pub duplicate: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// 详单期望功率与充电桩功率不一致时的警告，非严格模式下详单仍然被接受
+    pub warning: Option<PowerWarning>,
 }
 
 #[cfg(test)]
@@ -45,13 +587,39 @@ mod tests {
 
     #[test]
     fn test_message_serialization() {
-        let message = MSG {
-            type_: MessageType::Register,
-            data: "Test data".to_string(),
-        };
+        let message = MSG::with_payload(MessageType::Ack, &MsgAckData { msg_id: 7 });
         let serialized = serde_json::to_string(&message).unwrap();
-        assert!(serialized.contains("\"type\":\"register\""));
-        assert!(serialized.contains("\"data\":\"Test data\""));
+        assert!(serialized.contains("\"type\":\"ack\""));
+        assert!(serialized.contains("\"data\":{\"msg_id\":7}"));
+        // 没有消息 ID 时不输出信封字段
+        let value: Value = serde_json::from_str(&serialized).unwrap();
+        assert!(value.get("msg_id").is_none() && value.get("sent_at").is_none());
+    }
+
+    #[test]
+    fn test_ack_data_warning() {
+        let ack = AckData {
+            id: 1,
+            position: 0,
+            msg_id: None,
+            duplicate: false,
+            warning: None,
+        };
+        assert_eq!(
+            serde_json::to_string(&ack).unwrap(),
+            r#"{"id":1,"position":0}"#
+        );
+
+        let ack = AckData {
+            warning: Some(PowerWarning {
+                expected_power: 60.0,
+                pile_power_kw: 30.0,
+            }),
+            ..ack
+        };
+        let serialized = serde_json::to_string(&ack).unwrap();
+        assert!(serialized.contains(r#""warning":{"expected_power":60.0,"pile_power_kw":30.0}"#));
+        assert_eq!(serde_json::from_str::<AckData>(&serialized).unwrap(), ack);
     }
 
     #[test]
@@ -60,5 +628,216 @@ mod tests {
         let message: MSG = serde_json::from_str(json).unwrap();
         assert_eq!(message.type_, MessageType::Update);
         assert_eq!(message.data, "Update data");
+        assert!(message.msg_id.is_none() && message.sent_at.is_none());
+
+        let json =
+            r#"{"type":"ack","data":"{\"msg_id\":7}","msg_id":3,"sent_at":"2025-01-01T00:00:00Z"}"#;
+        let message: MSG = serde_json::from_str(json).unwrap();
+        assert_eq!(message.msg_id, Some(3));
+        assert!(message.sent_at.is_some());
+        let ack: MsgAckData = message.payload().unwrap();
+        assert_eq!(ack.msg_id, 7);
+    }
+
+    #[test]
+    fn test_message_data_forms() {
+        // 新格式直接使用 JSON 值
+        let json = r#"{"type":"ack","data":{"msg_id":7}}"#;
+        let message: MSG = serde_json::from_str(json).unwrap();
+        assert_eq!(message.payload::<MsgAckData>().unwrap().msg_id, 7);
+
+        // 旧格式的空字符串和缺少的数据都视为 null
+        for json in [r#"{"type":"close","data":""}"#, r#"{"type":"close"}"#] {
+            let message: MSG = serde_json::from_str(json).unwrap();
+            assert!(message.data.is_null());
+        }
+        let json = r#"{"type":"fault","data":"null"}"#;
+        let message: MSG = serde_json::from_str(json).unwrap();
+        assert!(
+            message
+                .payload::<Option<ChargingDetail>>()
+                .unwrap()
+                .is_none()
+        );
+
+        // 详单字段错误时错误信息中指出字段名
+        let mut detail = serde_json::to_value(ChargingDetail::test_new(1)).unwrap();
+        detail["request_amount"] = "lots".into();
+        let message = MSG::new(MessageType::New, detail.clone());
+        let error = message.payload::<ChargingDetail>().unwrap_err();
+        assert!(error.contains("`request_amount`"), "{}", error);
+        detail["request_amount"] = 10.0.into();
+        detail.as_object_mut().unwrap().remove("id");
+        let error = MSG::new(MessageType::New, detail)
+            .payload::<ChargingDetail>()
+            .unwrap_err();
+        assert!(error.contains("missing field `id`"), "{}", error);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_cancel_data_reason() {
+        let detail = serde_json::to_value(ChargingDetail::test_new(3)).unwrap();
+        let mut payload = detail.clone();
+        payload["reason"] = "payment failed".into();
+        payload["reason_code"] = "payment_failed".into();
+        let cancel: CancelData = serde_json::from_value(payload).unwrap();
+        assert_eq!(cancel.detail.get_id(), 3);
+        assert_eq!(cancel.reason.as_deref(), Some("payment failed"));
+        assert_eq!(cancel.reason_code.as_deref(), Some("payment_failed"));
+
+        // 不带原因的旧格式仍然可以解析
+        let cancel: CancelData = serde_json::from_value(detail).unwrap();
+        assert!(cancel.reason.is_none() && cancel.reason_code.is_none());
+    }
+
+    #[test]
+    fn test_reject_data_rejected_list() {
+        let single = RejectData {
+            id: 2,
+            reason: "type_mismatch".to_string(),
+            rejected: Vec::new(),
+        };
+        // 单个详单的拒绝消息不带 `rejected` 字段，与旧格式相同
+        let value = serde_json::to_value(&single).unwrap();
+        assert_eq!(
+            value,
+            serde_json::json!({"id": 2, "reason": "type_mismatch"})
+        );
+        assert_eq!(serde_json::from_value::<RejectData>(value).unwrap(), single);
+
+        let batch = RejectData {
+            rejected: vec![single.clone()],
+            ..single.clone()
+        };
+        let value = serde_json::to_value(&batch).unwrap();
+        assert_eq!(value["rejected"][0]["id"], 2);
+        assert!(value["rejected"][0].get("rejected").is_none());
+        assert_eq!(serde_json::from_value::<RejectData>(value).unwrap(), batch);
+    }
+
+    #[test]
+    fn test_parse_frame_single() {
+        let (messages, error) = parse_frame(r#"{"type":"update","data":"Update data"}"#);
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].type_, MessageType::Update);
+        assert!(error.is_none());
+    }
+
+    #[test]
+    fn test_parse_frame_two_documents() {
+        let frame = r#"{
+    "type": "new",
+    "data": "first"
+}
+{"type":"cancel","data":"second"}"#;
+        let (messages, error) = parse_frame(frame);
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].type_, MessageType::New);
+        assert_eq!(messages[0].data, "first");
+        assert_eq!(messages[1].type_, MessageType::Cancel);
+        assert_eq!(messages[1].data, "second");
+        assert!(error.is_none());
+    }
+
+    #[test]
+    fn test_parse_frame_trailing_garbage() {
+        let first = r#"{"type":"new","data":"first"}"#;
+        let second = r#" {"type":"close","data":""}"#;
+        let frame = format!("{}{} garbage", first, second);
+        let (messages, error) = parse_frame(&frame);
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[1].type_, MessageType::Close);
+        let error = error.unwrap();
+        assert_eq!(error.offset, Some(first.len() + second.len()));
+    }
+
+    #[test]
+    fn test_parse_frame_resync_after_invalid_message() {
+        let first = r#"{"type":"new","data":"first"}"#;
+        let invalid = r#" {"type":"no_such_type","data":{"type":"cancel"}}"#;
+        let third = r#" {"type":"cancel","data":"third"}"#;
+        let frame = format!("{}{}{}", first, invalid, third);
+        let (messages, error) = parse_frame(&frame);
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].data, "first");
+        assert_eq!(messages[1].type_, MessageType::Cancel);
+        assert_eq!(messages[1].data, "third");
+        assert_eq!(error.unwrap().offset, Some(first.len()));
+    }
+
+    #[test]
+    fn test_parse_frame_syntax_error_stops() {
+        let first = r#"{"type":"new","data":"first"}"#;
+        let frame = format!(
+            r#"{} {{"type": bad}} {{"type":"cancel","data":"third"}}"#,
+            first
+        );
+        let (messages, error) = parse_frame(&frame);
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].data, "first");
+        assert_eq!(error.unwrap().offset, Some(first.len()));
+    }
+    #[test]
+    fn test_msgpack_round_trip() {
+        let detail = ChargingDetail::test_new(3).with_request_amount(12.5);
+        let mut message = MSG::with_payload(MessageType::Update, &detail);
+        message.msg_id = Some(9);
+        message.sent_at = Some(Utc::now());
+        let WsMessage::Binary(bytes) = message.to_frame(WireEncoding::Msgpack) else {
+            panic!("msgpack must be sent as a binary frame");
+        };
+        let decoded = MSG::from_msgpack(&bytes).unwrap();
+        assert_eq!(decoded.type_, MessageType::Update);
+        assert_eq!(decoded.data, message.data);
+        assert_eq!(decoded.msg_id, Some(9));
+        assert_eq!(decoded.sent_at, message.sent_at);
+        assert_eq!(decoded.payload::<ChargingDetail>().unwrap().get_id(), 3);
+        assert!(message.to_frame(WireEncoding::Json).is_text());
+
+        let (messages, error) = parse_binary(&bytes);
+        assert_eq!(messages.len(), 1);
+        assert!(error.is_none());
+        let (messages, error) = parse_binary(b"\xc1 not msgpack");
+        assert!(messages.is_empty());
+        assert!(error.is_some_and(|error| error.offset.is_none()));
+
+        // 二进制帧中的 JSON 文本按文本帧解析
+        let (messages, error) = parse_binary(b" {\"type\": \"query\"} {\"type\": \"pause\"}");
+        assert_eq!(
+            messages.iter().map(|msg| msg.type_).collect::<Vec<_>>(),
+            [MessageType::Query, MessageType::Pause]
+        );
+        assert!(error.is_none());
+        let (messages, error) = parse_binary(b"{\"type\": ");
+        assert!(messages.is_empty());
+        assert!(error.is_some_and(|error| error.offset == Some(0)));
+    }
+
+    #[test]
+    fn test_register_encoding() {
+        let register: RegisterPayload = serde_json::from_value(serde_json::json!({
+            "charge_id": Uuid::nil(),
+            "type": "F",
+            "power": 30.0,
+            "size": 2,
+            "protocol_version": PROTOCOL_VERSION,
+            "software_version": "0.1.0",
+            "speed": 1.0,
+            "encoding": "msgpack"
+        }))
+        .unwrap();
+        assert_eq!(register.encoding, WireEncoding::Msgpack);
+        let ack = serde_json::to_value(RegisterAckData::accept(&register)).unwrap();
+        assert_eq!(ack["encoding"], "msgpack");
+        // 只支持 JSON 的服务器不发送该字段
+        let ack: RegisterAckData =
+            serde_json::from_value(serde_json::json!({"protocol_version": 1})).unwrap();
+        assert_eq!(ack.encoding, WireEncoding::Json);
+        assert!(
+            serde_json::to_value(&ack)
+                .unwrap()
+                .get("encoding")
+                .is_none()
+        );
+    }
+}
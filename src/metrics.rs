@@ -0,0 +1,714 @@
+//! Prometheus 指标
+//!
+//! 每个充电桩有一组 [`PileMetrics`]，详单结束和队列变化时由 `Charge` 更新，连接相关的计数由主程序更新。
+//! 配置了 `metrics.listen` 时启动一个简单的 HTTP 服务，在 `/metrics` 以 Prometheus 文本格式输出所有充电桩的指标。
+//! 抓取时只读取原子变量和按消息类型的计数表，不会锁住充电桩，也不会阻塞充电循环。
+//! 日志限流是全进程共用的，按警告类别省略的条数不带 `pile` 标签。
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Utc};
+
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+use tokio::time::{Duration, timeout};
+
+use crate::message::MessageType;
+use crate::stats::EtaErrorSummary;
+use crate::throttle;
+
+/// 导出的会话时长预测误差分位数，与 [`MetricsSnapshot::eta_error_quantiles`] 一一对应
+const ETA_ERROR_QUANTILES: [&str; 3] = ["0.5", "0.9", "0.99"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// 详单的结束方式
+pub enum Outcome {
+    /// 充电完成
+    Completed,
+    /// 被服务器取消
+    Canceled,
+    /// 因关闭、故障或维护中断
+    Interrupted,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// 连接的健康状态
+pub enum Health {
+    /// 连接正常
+    Connected,
+    /// 注册后服务器超过 `websocket.idle_after_register_s` 没有发送任何消息
+    Idle,
+    /// 连接断开后已切换到备用地址
+    FailedOver,
+}
+
+impl Health {
+    /// 所有健康状态
+    pub const ALL: [Health; 3] = [Health::Connected, Health::Idle, Health::FailedOver];
+
+    /// 指标标签中的名称
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Health::Connected => "connected",
+            Health::Idle => "idle",
+            Health::FailedOver => "failed_over",
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+/// 单个充电桩的指标
+pub struct PileMetrics {
+    /// 已结束详单的累计充电度数，以 `f64` 的二进制表示保存
+    delivered_kwh: AtomicU64,
+    /// 完成的详单数
+    completed: AtomicU64,
+    /// 取消的详单数
+    canceled: AtomicU64,
+    /// 中断的详单数
+    interrupted: AtomicU64,
+    /// 当前队列中的详单数，包括正在充电的详单
+    queue_depth: AtomicU64,
+    /// 是否正在充电
+    working: AtomicBool,
+    /// WebSocket 重新连接次数
+    reconnects: AtomicU64,
+    /// 消息发送失败次数
+    send_failures: AtomicU64,
+    /// 服务器注册后长时间无响应而断开连接的次数
+    idle_teardowns: AtomicU64,
+    /// 服务器注册后是否长时间没有发送任何消息
+    idle: AtomicBool,
+    /// 当前连接是否为故障切换后的备用地址
+    failed_over: AtomicBool,
+    /// 产生的状态更新数
+    updates_generated: AtomicU64,
+    /// 实际发送的状态更新数，被替换、合并或丢弃的更新不计入
+    updates_sent: AtomicU64,
+    /// 每种消息的累计发送次数，重新连接后继续累计
+    messages_sent: Mutex<BTreeMap<MessageType, u64>>,
+    /// 每种消息的累计接收次数，重新连接后继续累计
+    messages_received: Mutex<BTreeMap<MessageType, u64>>,
+    /// 最后一次发送成功的真实时间，Unix 毫秒，为 0 时还没有发送过
+    last_sent_real_ms: AtomicI64,
+    /// 最后一次发送成功的虚拟时间，Unix 毫秒，为 0 时还没有发送过
+    last_sent_virtual_ms: AtomicI64,
+    /// 会话时长预测误差的样本数
+    eta_error_count: AtomicU64,
+    /// 会话时长预测误差的 50、90、99 分位数，单位为虚拟秒，以 `f64` 的二进制表示保存
+    eta_error_quantiles: [AtomicU64; 3],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// 某一时刻的指标值
+pub struct MetricsSnapshot {
+    pub delivered_kwh: f64,
+    pub completed: u64,
+    pub canceled: u64,
+    pub interrupted: u64,
+    pub queue_depth: u64,
+    pub working: bool,
+    pub reconnects: u64,
+    pub send_failures: u64,
+    pub idle_teardowns: u64,
+    pub health: Health,
+    pub updates_generated: u64,
+    pub updates_sent: u64,
+    pub last_sent_real: Option<DateTime<Utc>>,
+    pub last_sent_virtual: Option<DateTime<Utc>>,
+    pub eta_error_count: u64,
+    /// 50、90、99 分位数，没有样本时为 0
+    pub eta_error_quantiles: [f64; 3],
+}
+
+/// 把保存的 Unix 毫秒转换为时间，为 0 时返回 `None`
+fn from_millis(ms: i64) -> Option<DateTime<Utc>> {
+    (ms != 0)
+        .then(|| DateTime::from_timestamp_millis(ms))
+        .flatten()
+}
+
+/// 消息类型在协议中的名称，用作指标标签
+fn type_label(type_: MessageType) -> String {
+    match serde_json::to_value(type_) {
+        Ok(serde_json::Value::String(name)) => name,
+        _ => format!("{:?}", type_),
+    }
+}
+
+impl PileMetrics {
+    /// 记录一个结束的详单及其充电度数
+    pub fn record_finished(&self, outcome: Outcome, kwh: f64) {
+        let counter = match outcome {
+            Outcome::Completed => &self.completed,
+            Outcome::Canceled => &self.canceled,
+            Outcome::Interrupted => &self.interrupted,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        if kwh.is_finite() && kwh > 0.0 {
+            let _ = self
+                .delivered_kwh
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bits| {
+                    Some((f64::from_bits(bits) + kwh).to_bits())
+                });
+        }
+    }
+
+    /// 更新队列深度和工作状态
+    pub fn set_state(&self, queue_depth: usize, working: bool) {
+        self.queue_depth
+            .store(queue_depth as u64, Ordering::Relaxed);
+        self.working.store(working, Ordering::Relaxed);
+    }
+
+    /// 记录一次重新连接
+    pub fn record_reconnect(&self) {
+        self.reconnects.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// 记录一次消息发送失败
+    pub fn record_send_failure(&self) {
+        self.send_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// 记录一次服务器长时间无响应而断开连接
+    pub fn record_idle_teardown(&self) {
+        self.idle_teardowns.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// 设置服务器注册后是否长时间没有发送任何消息，收到消息或换用新连接时清除
+    pub fn set_idle(&self, idle: bool) {
+        self.idle.store(idle, Ordering::Relaxed);
+    }
+
+    /// 设置当前连接是否为故障切换后的备用地址
+    pub fn set_failed_over(&self, failed_over: bool) {
+        self.failed_over.store(failed_over, Ordering::Relaxed);
+    }
+
+    /// 当前的健康状态，服务器无响应优先于故障切换
+    pub fn health(&self) -> Health {
+        if self.idle.load(Ordering::Relaxed) {
+            Health::Idle
+        } else if self.failed_over.load(Ordering::Relaxed) {
+            Health::FailedOver
+        } else {
+            Health::Connected
+        }
+    }
+
+    /// 记录产生了一次状态更新
+    pub fn record_update_generated(&self) {
+        self.updates_generated.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// 记录发送了一次状态更新
+    pub fn record_update_sent(&self) {
+        self.updates_sent.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// 记录一条发送成功的消息及其发送时的真实时间和虚拟时间
+    pub fn record_message_sent(
+        &self,
+        type_: MessageType,
+        real: DateTime<Utc>,
+        virtual_time: DateTime<Utc>,
+    ) {
+        *self.messages_sent.lock().unwrap().entry(type_).or_default() += 1;
+        self.last_sent_real_ms
+            .store(real.timestamp_millis(), Ordering::Relaxed);
+        self.last_sent_virtual_ms
+            .store(virtual_time.timestamp_millis(), Ordering::Relaxed);
+    }
+
+    /// 记录一条收到的应用消息
+    pub fn record_message_received(&self, type_: MessageType) {
+        *self
+            .messages_received
+            .lock()
+            .unwrap()
+            .entry(type_)
+            .or_default() += 1;
+    }
+
+    /// 每种消息的累计发送次数和接收次数
+    pub fn message_counts(&self) -> (BTreeMap<MessageType, u64>, BTreeMap<MessageType, u64>) {
+        (
+            self.messages_sent.lock().unwrap().clone(),
+            self.messages_received.lock().unwrap().clone(),
+        )
+    }
+
+    /// 更新会话时长预测误差的分布
+    pub fn set_eta_errors(&self, summary: &EtaErrorSummary) {
+        for (quantile, value) in
+            self.eta_error_quantiles
+                .iter()
+                .zip([summary.p50, summary.p90, summary.p99])
+        {
+            quantile.store(value.to_bits(), Ordering::Relaxed);
+        }
+        self.eta_error_count
+            .store(summary.count as u64, Ordering::Relaxed);
+    }
+
+    /// 读取当前的指标值
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        MetricsSnapshot {
+            delivered_kwh: f64::from_bits(self.delivered_kwh.load(Ordering::Relaxed)),
+            completed: self.completed.load(Ordering::Relaxed),
+            canceled: self.canceled.load(Ordering::Relaxed),
+            interrupted: self.interrupted.load(Ordering::Relaxed),
+            queue_depth: self.queue_depth.load(Ordering::Relaxed),
+            working: self.working.load(Ordering::Relaxed),
+            reconnects: self.reconnects.load(Ordering::Relaxed),
+            send_failures: self.send_failures.load(Ordering::Relaxed),
+            idle_teardowns: self.idle_teardowns.load(Ordering::Relaxed),
+            health: self.health(),
+            updates_generated: self.updates_generated.load(Ordering::Relaxed),
+            updates_sent: self.updates_sent.load(Ordering::Relaxed),
+            last_sent_real: from_millis(self.last_sent_real_ms.load(Ordering::Relaxed)),
+            last_sent_virtual: from_millis(self.last_sent_virtual_ms.load(Ordering::Relaxed)),
+            eta_error_count: self.eta_error_count.load(Ordering::Relaxed),
+            eta_error_quantiles: self
+                .eta_error_quantiles
+                .each_ref()
+                .map(|bits| f64::from_bits(bits.load(Ordering::Relaxed))),
+        }
+    }
+}
+
+/// 以 Prometheus 文本格式输出所有充电桩的指标，`piles` 为充电桩 ID 及其指标
+pub fn render(piles: &[(String, Arc<PileMetrics>)]) -> String {
+    let snapshots: Vec<(&str, MetricsSnapshot)> = piles
+        .iter()
+        .map(|(pile, metrics)| (pile.as_str(), metrics.snapshot()))
+        .collect();
+    let mut out = String::new();
+    let mut family =
+        |name: &str, kind: &str, help: &str, value: &dyn Fn(&MetricsSnapshot) -> String| {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} {}", name, kind);
+            for (pile, snapshot) in &snapshots {
+                let _ = writeln!(out, "{}{{pile=\"{}\"}} {}", name, pile, value(snapshot));
+            }
+        };
+    family(
+        "taranis_delivered_kwh_total",
+        "counter",
+        "Energy delivered by finished details in kWh.",
+        &|s| s.delivered_kwh.to_string(),
+    );
+    family(
+        "taranis_queue_depth",
+        "gauge",
+        "Details in the queue, including the one charging.",
+        &|s| s.queue_depth.to_string(),
+    );
+    family(
+        "taranis_working",
+        "gauge",
+        "Whether the pile is charging (1) or idle (0).",
+        &|s| u8::from(s.working).to_string(),
+    );
+    family(
+        "taranis_reconnects_total",
+        "counter",
+        "WebSocket reconnections.",
+        &|s| s.reconnects.to_string(),
+    );
+    family(
+        "taranis_send_failures_total",
+        "counter",
+        "Outgoing WebSocket messages that failed to send.",
+        &|s| s.send_failures.to_string(),
+    );
+    family(
+        "taranis_idle_teardowns_total",
+        "counter",
+        "Connections torn down because the server stayed silent after registering.",
+        &|s| s.idle_teardowns.to_string(),
+    );
+    family(
+        "taranis_updates_generated_total",
+        "counter",
+        "Detail updates generated by the charging loop.",
+        &|s| s.updates_generated.to_string(),
+    );
+    family(
+        "taranis_updates_sent_total",
+        "counter",
+        "Detail updates sent after coalescing and rate limiting.",
+        &|s| s.updates_sent.to_string(),
+    );
+    let seconds = |time: Option<DateTime<Utc>>| {
+        time.map_or(0.0, |time| time.timestamp_millis() as f64 / 1000.0)
+            .to_string()
+    };
+    family(
+        "taranis_last_sent_timestamp_seconds",
+        "gauge",
+        "Wall-clock Unix time of the last message sent, 0 before the first one.",
+        &|s| seconds(s.last_sent_real),
+    );
+    family(
+        "taranis_last_sent_virtual_timestamp_seconds",
+        "gauge",
+        "Virtual Unix time of the last message sent, 0 before the first one.",
+        &|s| seconds(s.last_sent_virtual),
+    );
+    family(
+        "taranis_eta_error_samples",
+        "gauge",
+        "Completed details with a session length prediction.",
+        &|s| s.eta_error_count.to_string(),
+    );
+    // 没有样本的充电桩不输出分位数
+    let _ = writeln!(
+        out,
+        "# HELP taranis_eta_error_seconds Actual minus predicted end time of completed details, in virtual seconds."
+    );
+    let _ = writeln!(out, "# TYPE taranis_eta_error_seconds gauge");
+    for (pile, snapshot) in &snapshots {
+        if snapshot.eta_error_count == 0 {
+            continue;
+        }
+        for (quantile, value) in ETA_ERROR_QUANTILES.iter().zip(snapshot.eta_error_quantiles) {
+            let _ = writeln!(
+                out,
+                "taranis_eta_error_seconds{{pile=\"{}\",quantile=\"{}\"}} {}",
+                pile, quantile, value
+            );
+        }
+    }
+    let _ = writeln!(
+        out,
+        "# HELP taranis_health Connection health, 1 for the current state."
+    );
+    let _ = writeln!(out, "# TYPE taranis_health gauge");
+    for (pile, snapshot) in &snapshots {
+        for health in Health::ALL {
+            let _ = writeln!(
+                out,
+                "taranis_health{{pile=\"{}\",state=\"{}\"}} {}",
+                pile,
+                health.as_str(),
+                u8::from(snapshot.health == health)
+            );
+        }
+    }
+    let _ = writeln!(
+        out,
+        "# HELP taranis_messages_total WebSocket messages by direction and type."
+    );
+    let _ = writeln!(out, "# TYPE taranis_messages_total counter");
+    for (pile, metrics) in piles {
+        let (sent, received) = metrics.message_counts();
+        for (direction, counts) in [("sent", sent), ("received", received)] {
+            for (type_, value) in counts {
+                let _ = writeln!(
+                    out,
+                    "taranis_messages_total{{pile=\"{}\",direction=\"{}\",type=\"{}\"}} {}",
+                    pile,
+                    direction,
+                    type_label(type_),
+                    value
+                );
+            }
+        }
+    }
+    let _ = writeln!(
+        out,
+        "# HELP taranis_details_total Finished details by outcome."
+    );
+    let _ = writeln!(out, "# TYPE taranis_details_total counter");
+    for (pile, snapshot) in &snapshots {
+        for (outcome, value) in [
+            ("completed", snapshot.completed),
+            ("canceled", snapshot.canceled),
+            ("interrupted", snapshot.interrupted),
+        ] {
+            let _ = writeln!(
+                out,
+                "taranis_details_total{{pile=\"{}\",outcome=\"{}\"}} {}",
+                pile, outcome, value
+            );
+        }
+    }
+    let _ = writeln!(
+        out,
+        "# HELP taranis_suppressed_warnings_total Warnings dropped by the log throttle, by warning key."
+    );
+    let _ = writeln!(out, "# TYPE taranis_suppressed_warnings_total counter");
+    for (key, suppressed) in throttle::THROTTLE.suppressed_totals() {
+        let _ = writeln!(
+            out,
+            "taranis_suppressed_warnings_total{{key=\"{}\"}} {}",
+            key, suppressed
+        );
+    }
+    out
+}
+
+/// 启动指标服务，`listener` 已经绑定到监听地址
+pub fn spawn(listener: TcpListener, piles: Vec<(String, Arc<PileMetrics>)>) -> JoinHandle<()> {
+    let piles = Arc::new(piles);
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    let piles = piles.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = serve(stream, &piles).await {
+                            tracing::debug!("指标请求处理失败: {}", e);
+                        }
+                    });
+                }
+                Err(e) => tracing::warn!("指标服务接受连接失败: {}", e),
+            }
+        }
+    })
+}
+
+/// 处理一个 HTTP 请求，只支持 `GET /metrics`，响应后关闭连接
+async fn serve(stream: TcpStream, piles: &[(String, Arc<PileMetrics>)]) -> Result<(), String> {
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+    let mut request_line = String::new();
+    let read_request = async {
+        reader.read_line(&mut request_line).await?;
+        // 忽略请求头
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if reader.read_line(&mut line).await? == 0 || line.trim().is_empty() {
+                return Ok::<(), std::io::Error>(());
+            }
+        }
+    };
+    timeout(Duration::from_secs(5), read_request)
+        .await
+        .map_err(|_| "request timed out".to_string())?
+        .map_err(|e| e.to_string())?;
+    let mut parts = request_line.split_whitespace();
+    let (status, content_type, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => ("200 OK", "text/plain; version=0.0.4", render(piles)),
+        (Some("GET"), Some(_)) => ("404 Not Found", "text/plain", "not found\n".to_string()),
+        _ => (
+            "405 Method Not Allowed",
+            "text/plain",
+            "method not allowed\n".to_string(),
+        ),
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    );
+    writer
+        .write_all(response.as_bytes())
+        .await
+        .map_err(|e| e.to_string())?;
+    writer.shutdown().await.map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::charge::Charge;
+    use crate::conf::CONF;
+    use crate::detail::ChargingDetail;
+    use crate::stats::EtaErrorStats;
+    use crate::time::get_mock_now;
+    use tokio::io::AsyncReadExt;
+
+    /// 抓取一次指标
+    async fn scrape(addr: std::net::SocketAddr) -> String {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+        response
+    }
+
+    /// 找出指定指标行的值
+    fn value(body: &str, series: &str) -> f64 {
+        body.lines()
+            .find_map(|line| line.strip_prefix(series))
+            .and_then(|rest| rest.trim().parse().ok())
+            .unwrap_or_else(|| panic!("missing series {}", series))
+    }
+
+    #[tokio::test]
+    async fn test_scrape_during_charge() {
+        let mut charge = Charge::new(CONF.charge.charge_type, 30.0, 2);
+        let pile = charge.get_id().to_string();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        spawn(listener, vec![(pile.clone(), charge.get_metrics())]);
+
+        let series = |name: &str| format!("{}{{pile=\"{}\"}}", name, pile);
+        let completed = format!(
+            "taranis_details_total{{pile=\"{}\",outcome=\"completed\"}}",
+            pile
+        );
+        let canceled = format!(
+            "taranis_details_total{{pile=\"{}\",outcome=\"canceled\"}}",
+            pile
+        );
+        let body = scrape(addr).await;
+        assert_eq!(value(&body, &series("taranis_queue_depth")), 0.0);
+        assert_eq!(value(&body, &completed), 0.0);
+
+        charge.add_detail(ChargingDetail::test_new(1)).unwrap();
+        charge.add_detail(ChargingDetail::test_new(2)).unwrap();
+        charge.start_charging();
+        let body = scrape(addr).await;
+        assert_eq!(value(&body, &series("taranis_queue_depth")), 2.0);
+        assert_eq!(value(&body, &series("taranis_working")), 1.0);
+
+        let detail = charge
+            .complete_charging_at(1, get_mock_now() + chrono::Duration::hours(10))
+            .unwrap();
+        charge.cancel_charging(2, None, None).unwrap();
+        let body = scrape(addr).await;
+        assert_eq!(value(&body, &completed), 1.0);
+        assert_eq!(value(&body, &canceled), 1.0);
+        assert_eq!(value(&body, &series("taranis_queue_depth")), 0.0);
+        assert_eq!(value(&body, &series("taranis_working")), 0.0);
+        assert!(detail.get_already_charged() > 0.0);
+        assert_eq!(
+            value(&body, &series("taranis_delivered_kwh_total")),
+            detail.get_already_charged()
+        );
+        // 完成的详单带有预测结束时间，误差分布随抓取一起输出
+        let error = detail.get_eta_error().unwrap().num_milliseconds() as f64 / 1000.0;
+        assert_eq!(value(&body, &series("taranis_eta_error_samples")), 1.0);
+        for quantile in ETA_ERROR_QUANTILES {
+            let series = format!(
+                "taranis_eta_error_seconds{{pile=\"{}\",quantile=\"{}\"}}",
+                pile, quantile
+            );
+            assert_eq!(value(&body, &series), error);
+        }
+
+        // 服务器长时间无响应后断开连接并切换到备用地址，限流省略的警告按类别计数
+        let metrics = charge.get_metrics();
+        let health =
+            |state: &str| format!("taranis_health{{pile=\"{}\",state=\"{}\"}}", pile, state);
+        assert_eq!(value(&body, &health("connected")), 1.0);
+        metrics.set_idle(true);
+        assert_eq!(value(&scrape(addr).await, &health("idle")), 1.0);
+        metrics.record_idle_teardown();
+        metrics.set_failed_over(true);
+        metrics.set_idle(false);
+        let key = "metrics.test_scrape_during_charge";
+        for _ in 0..3 {
+            throttle::allow(key);
+        }
+        let body = scrape(addr).await;
+        assert_eq!(value(&body, &series("taranis_idle_teardowns_total")), 1.0);
+        assert_eq!(value(&body, &health("failed_over")), 1.0);
+        assert_eq!(value(&body, &health("idle")), 0.0);
+        let suppressed = format!("taranis_suppressed_warnings_total{{key=\"{}\"}}", key);
+        assert_eq!(value(&body, &suppressed), 2.0);
+    }
+
+    #[test]
+    fn test_render_format() {
+        let metrics = Arc::new(PileMetrics::default());
+        metrics.record_finished(Outcome::Interrupted, 1.5);
+        metrics.record_send_failure();
+        metrics.record_idle_teardown();
+        metrics.set_failed_over(true);
+        metrics.set_idle(true);
+        metrics.record_update_generated();
+        metrics.record_update_generated();
+        metrics.record_update_sent();
+        let sent_at: DateTime<Utc> = "2025-01-01T00:00:01.500Z".parse().unwrap();
+        let virtual_time: DateTime<Utc> = "2025-06-01T08:00:00Z".parse().unwrap();
+        metrics.record_message_sent(MessageType::Update, sent_at, virtual_time);
+        metrics.record_message_sent(MessageType::Update, sent_at, virtual_time);
+        metrics.record_message_received(MessageType::New);
+        let mut eta_errors = EtaErrorStats::default();
+        for error in [0.0, 10.0, 20.0, 30.0, 600.0] {
+            eta_errors.record(error);
+        }
+        metrics.set_eta_errors(&eta_errors.summary().unwrap());
+        let body = render(&[("a".to_string(), metrics)]);
+        assert!(body.contains("# TYPE taranis_delivered_kwh_total counter\n"));
+        assert!(body.contains("taranis_delivered_kwh_total{pile=\"a\"} 1.5\n"));
+        assert!(body.contains("taranis_details_total{pile=\"a\",outcome=\"interrupted\"} 1\n"));
+        assert!(body.contains("taranis_send_failures_total{pile=\"a\"} 1\n"));
+        assert!(body.contains("taranis_idle_teardowns_total{pile=\"a\"} 1\n"));
+        assert!(body.contains("taranis_health{pile=\"a\",state=\"idle\"} 1\n"));
+        assert!(body.contains("taranis_health{pile=\"a\",state=\"connected\"} 0\n"));
+        assert!(body.contains("taranis_updates_generated_total{pile=\"a\"} 2\n"));
+        assert!(body.contains("taranis_updates_sent_total{pile=\"a\"} 1\n"));
+        assert!(body.contains("# TYPE taranis_messages_total counter\n"));
+        assert!(
+            body.contains(
+                "taranis_messages_total{pile=\"a\",direction=\"sent\",type=\"update\"} 2\n"
+            )
+        );
+        assert!(body.contains(
+            "taranis_messages_total{pile=\"a\",direction=\"received\",type=\"new\"} 1\n"
+        ));
+        assert!(body.contains(&format!(
+            "taranis_last_sent_timestamp_seconds{{pile=\"a\"}} {}\n",
+            sent_at.timestamp() as f64 + 0.5
+        )));
+        assert!(body.contains(&format!(
+            "taranis_last_sent_virtual_timestamp_seconds{{pile=\"a\"}} {}\n",
+            virtual_time.timestamp()
+        )));
+        assert!(body.contains("taranis_eta_error_samples{pile=\"a\"} 5\n"));
+        assert!(body.contains("taranis_eta_error_seconds{pile=\"a\",quantile=\"0.5\"} 20\n"));
+        assert!(body.contains("taranis_eta_error_seconds{pile=\"a\",quantile=\"0.99\"} 600\n"));
+        // 还没有发送过消息时时间为 0，没有预测误差样本时不输出分位数
+        let body = render(&[("b".to_string(), Arc::new(PileMetrics::default()))]);
+        assert!(body.contains("taranis_last_sent_timestamp_seconds{pile=\"b\"} 0\n"));
+        assert!(!body.contains("taranis_eta_error_seconds{"));
+        assert!(body.contains("taranis_health{pile=\"b\",state=\"connected\"} 1\n"));
+    }
+
+    #[test]
+    fn test_health() {
+        let metrics = PileMetrics::default();
+        assert_eq!(metrics.health(), Health::Connected);
+        metrics.set_failed_over(true);
+        assert_eq!(metrics.health(), Health::FailedOver);
+        // 服务器无响应时优先报告无响应，收到消息后恢复
+        metrics.set_idle(true);
+        assert_eq!(metrics.snapshot().health, Health::Idle);
+        metrics.set_idle(false);
+        assert_eq!(metrics.health(), Health::FailedOver);
+        metrics.set_failed_over(false);
+        assert_eq!(metrics.health(), Health::Connected);
+    }
+
+    #[test]
+    fn test_render_suppressed_warnings() {
+        // 限流窗口内的第二条和第三条警告被省略
+        let key = "metrics.test_render_suppressed_warnings";
+        assert!(throttle::allow(key).is_some());
+        assert!(throttle::allow(key).is_none());
+        assert!(throttle::allow(key).is_none());
+        let body = render(&[]);
+        assert!(body.contains("# TYPE taranis_suppressed_warnings_total counter\n"));
+        assert!(body.contains(&format!(
+            "taranis_suppressed_warnings_total{{key=\"{}\"}} 2\n",
+            key
+        )));
+    }
+}
@@ -0,0 +1,276 @@
+//! 出站消息队列
+//!
+//! 处理函数把分配了消息 ID 的消息放入队列，由充电桩的主循环按顺序发送到 WebSocket 连接，
+//! 发送失败的消息留在队首，重新连接后继续发送。
+//! 同一详单的完整更新会替换队列中该详单还没有发送的更新和增量更新，只保留最新的状态。
+//! 队列满时丢弃最早的状态更新，完成、故障等其他消息不会被丢弃，此时队列可以超过容量。
+//!
+//! 设置了最小更新间隔时，同一详单的状态更新在间隔（真实时间）内只发送一条，
+//! 间隔内产生的完整更新互相替换，间隔结束后只发送最新的状态。被推迟的状态更新不阻塞其他消息，
+//! 详单的完成和故障消息立即发送，并替换该详单还没有发送的状态更新。
+
+use std::collections::{HashMap, VecDeque};
+
+use tokio::time::{Duration, Instant};
+
+use crate::message::{MSG, MessageType};
+
+/// 是否为可以被替换或丢弃的状态更新
+fn is_progress(type_: MessageType) -> bool {
+    matches!(type_, MessageType::Update | MessageType::Delta)
+}
+
+/// 是否为详单的最终状态，之前还没有发送的状态更新不再需要
+fn is_final(type_: MessageType) -> bool {
+    matches!(type_, MessageType::Complete | MessageType::Fault)
+}
+
+#[derive(Debug)]
+/// 队列中的消息
+struct Outbound {
+    /// 消息
+    msg: MSG,
+    /// 状态更新对应的详单 ID
+    detail: Option<u32>,
+}
+
+#[derive(Debug)]
+/// 单个充电桩的出站消息队列，重新连接后继续使用
+pub struct OutboundQueue {
+    /// 队列容量
+    capacity: usize,
+    /// 等待发送的消息
+    queue: VecDeque<Outbound>,
+    /// 因队列满而丢弃的状态更新数
+    dropped: u64,
+    /// 同一详单两次状态更新之间的最小间隔（真实时间），为零时不限制
+    min_gap: Duration,
+    /// 每个详单最近一次发送状态更新的时间
+    last_sent: HashMap<u32, Instant>,
+}
+
+impl OutboundQueue {
+    /// 创建队列，容量至少为 1
+    pub fn new(capacity: usize) -> Self {
+        OutboundQueue {
+            capacity: capacity.max(1),
+            queue: VecDeque::new(),
+            dropped: 0,
+            min_gap: Duration::ZERO,
+            last_sent: HashMap::new(),
+        }
+    }
+
+    /// 设置同一详单两次状态更新之间的最小间隔，为零时不限制
+    pub fn with_min_gap(mut self, min_gap: Duration) -> Self {
+        self.min_gap = min_gap;
+        self
+    }
+
+    /// 放入一条消息，`detail` 为状态更新、完成或故障消息对应的详单 ID
+    /// 已经在队列中的消息 ID（例如重新发送未确认的消息）不会重复放入
+    /// 队列满时返回被丢弃的状态更新
+    pub fn push(&mut self, msg: MSG, detail: Option<u32>) -> Option<MSG> {
+        if msg.msg_id.is_some()
+            && self
+                .queue
+                .iter()
+                .any(|entry| entry.msg.msg_id == msg.msg_id)
+        {
+            return None;
+        }
+        if (msg.type_ == MessageType::Update || is_final(msg.type_)) && detail.is_some() {
+            self.queue
+                .retain(|entry| !(is_progress(entry.msg.type_) && entry.detail == detail));
+        }
+        if is_final(msg.type_)
+            && let Some(id) = detail
+        {
+            self.last_sent.remove(&id);
+        }
+        self.queue.push_back(Outbound { msg, detail });
+        if self.queue.len() <= self.capacity {
+            return None;
+        }
+        let oldest = self
+            .queue
+            .iter()
+            .position(|entry| is_progress(entry.msg.type_))?;
+        self.dropped += 1;
+        self.queue.remove(oldest).map(|entry| entry.msg)
+    }
+
+    /// 状态更新还要等待到什么时候才能发送，不需要等待时为 `None`
+    fn held_until(&self, entry: &Outbound) -> Option<Instant> {
+        if !is_progress(entry.msg.type_) || self.min_gap.is_zero() {
+            return None;
+        }
+        let last = *self.last_sent.get(&entry.detail?)?;
+        Some(last + self.min_gap)
+    }
+
+    /// `now` 时可以发送的第一条消息的位置
+    fn ready_index(&self, now: Instant) -> Option<usize> {
+        self.queue.iter().position(|entry| {
+            self.held_until(entry)
+                .is_none_or(|deadline| deadline <= now)
+        })
+    }
+
+    /// `now` 时下一条要发送的消息，被推迟的状态更新不阻塞之后的消息
+    pub fn front_ready(&self, now: Instant) -> Option<&MSG> {
+        self.ready_index(now).map(|index| &self.queue[index].msg)
+    }
+
+    /// 移除已经发送的消息，即 `now` 时 [`Self::front_ready`] 返回的消息
+    pub fn pop_ready(&mut self, now: Instant) -> Option<MSG> {
+        let entry = self.queue.remove(self.ready_index(now)?)?;
+        if is_progress(entry.msg.type_)
+            && let Some(id) = entry.detail
+        {
+            self.last_sent.insert(id, now);
+        }
+        Some(entry.msg)
+    }
+
+    /// 不再推迟任何状态更新，例如退出前发送所有详单的最后状态
+    pub fn release_all(&mut self) {
+        self.last_sent.clear();
+    }
+
+    /// `now` 之后被推迟的状态更新中最早可以发送的时间，没有时为 `None`
+    /// 已经可以发送但发送失败的消息不计入，避免连接断开时反复重试
+    pub fn next_release(&self, now: Instant) -> Option<Instant> {
+        self.queue
+            .iter()
+            .filter_map(|entry| self.held_until(entry))
+            .filter(|deadline| *deadline > now)
+            .min()
+    }
+
+    /// 等待发送的消息数量
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// 是否没有等待发送的消息
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    /// 因队列满而丢弃的状态更新数
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn msg(type_: MessageType, msg_id: u64) -> MSG {
+        MSG {
+            msg_id: Some(msg_id),
+            ..MSG::empty(type_)
+        }
+    }
+
+    fn ids(queue: &OutboundQueue) -> Vec<u64> {
+        queue
+            .queue
+            .iter()
+            .map(|entry| entry.msg.msg_id.unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_updates_are_coalesced_per_detail() {
+        let mut queue = OutboundQueue::new(10);
+        queue.push(msg(MessageType::Update, 1), Some(1));
+        queue.push(msg(MessageType::Delta, 2), Some(1));
+        queue.push(msg(MessageType::Update, 3), Some(2));
+        queue.push(msg(MessageType::Complete, 4), None);
+        // 详单 1 的完整更新替换之前的更新和增量更新，其他详单和消息保持顺序
+        queue.push(msg(MessageType::Update, 5), Some(1));
+        assert_eq!(ids(&queue), vec![3, 4, 5]);
+        // 增量更新依赖之前的消息，不替换
+        queue.push(msg(MessageType::Delta, 6), Some(1));
+        assert_eq!(ids(&queue), vec![3, 4, 5, 6]);
+        // 重新发送的消息已经在队列中时不重复放入
+        queue.push(msg(MessageType::Complete, 4), None);
+        assert_eq!(queue.len(), 4);
+        assert_eq!(queue.dropped(), 0);
+    }
+
+    #[test]
+    fn test_overflow_drops_oldest_update_only() {
+        let mut queue = OutboundQueue::new(2);
+        queue.push(msg(MessageType::Complete, 1), None);
+        queue.push(msg(MessageType::Update, 2), Some(1));
+        let dropped = queue.push(msg(MessageType::Update, 3), Some(2));
+        assert_eq!(dropped.and_then(|msg| msg.msg_id), Some(2));
+        assert_eq!(ids(&queue), vec![1, 3]);
+
+        // 没有状态更新可以丢弃时超过容量，完成和故障消息不会被丢弃
+        let dropped = queue.push(msg(MessageType::Fault, 4), None);
+        assert_eq!(dropped.and_then(|msg| msg.msg_id), Some(3));
+        assert!(queue.push(msg(MessageType::Complete, 5), None).is_none());
+        assert_eq!(ids(&queue), vec![1, 4, 5]);
+        assert_eq!(queue.dropped(), 2);
+    }
+
+    #[test]
+    fn test_failed_send_keeps_head() {
+        let now = Instant::now();
+        let mut queue = OutboundQueue::new(4);
+        queue.push(msg(MessageType::Complete, 1), None);
+        queue.push(msg(MessageType::Update, 2), Some(1));
+        // 发送失败时不移除队首，下次从同一条消息继续发送
+        assert_eq!(queue.front_ready(now).unwrap().msg_id, Some(1));
+        assert_eq!(queue.front_ready(now).unwrap().msg_id, Some(1));
+        assert_eq!(queue.pop_ready(now).unwrap().msg_id, Some(1));
+        assert_eq!(queue.front_ready(now).unwrap().msg_id, Some(2));
+        queue.pop_ready(now);
+        assert!(queue.is_empty() && queue.front_ready(now).is_none());
+    }
+
+    #[test]
+    fn test_min_gap_coalesces_updates() {
+        const GAP: Duration = Duration::from_millis(500);
+        let start = Instant::now();
+        let mut queue = OutboundQueue::new(10).with_min_gap(GAP);
+        queue.push(msg(MessageType::Update, 1), Some(1));
+        assert_eq!(queue.pop_ready(start).unwrap().msg_id, Some(1));
+
+        // 间隔内的更新被推迟并互相替换，不阻塞其他详单和其他消息
+        queue.push(msg(MessageType::Update, 2), Some(1));
+        queue.push(msg(MessageType::Update, 3), Some(1));
+        queue.push(msg(MessageType::Update, 4), Some(2));
+        queue.push(msg(MessageType::Register, 5), None);
+        let soon = start + GAP / 2;
+        assert_eq!(queue.pop_ready(soon).unwrap().msg_id, Some(4));
+        assert_eq!(queue.pop_ready(soon).unwrap().msg_id, Some(5));
+        assert!(queue.front_ready(soon).is_none());
+        assert_eq!(queue.next_release(soon), Some(start + GAP));
+
+        // 间隔结束后只发送最新的状态
+        assert_eq!(queue.pop_ready(start + GAP).unwrap().msg_id, Some(3));
+        assert!(queue.is_empty() && queue.next_release(start + GAP).is_none());
+    }
+
+    #[test]
+    fn test_final_messages_bypass_min_gap() {
+        let start = Instant::now();
+        let mut queue = OutboundQueue::new(10).with_min_gap(Duration::from_secs(1));
+        queue.push(msg(MessageType::Update, 1), Some(1));
+        queue.pop_ready(start);
+        queue.push(msg(MessageType::Update, 2), Some(1));
+        // 完成消息立即发送，并替换被推迟的状态更新
+        queue.push(msg(MessageType::Complete, 3), Some(1));
+        assert_eq!(ids(&queue), vec![3]);
+        assert_eq!(queue.pop_ready(start).unwrap().msg_id, Some(3));
+        // 详单结束后重新使用同一 ID 时不受之前的间隔限制
+        queue.push(msg(MessageType::Update, 4), Some(1));
+        assert_eq!(queue.pop_ready(start).unwrap().msg_id, Some(4));
+    }
+}
@@ -0,0 +1,152 @@
+//! 待确认的出站消息
+//!
+//! 每条出站消息在发送时分配单调递增的消息 ID 和虚拟发送时间。
+//! 完成和故障消息保留在发件箱中，直到收到带有相同消息 ID 的 `ack` 消息，
+//! 超时或重新连接后按原来的消息 ID 重新发送，服务器可以据此去重。
+//! 发件箱同时记录已经加入队列的新详单，用于识别重复的 `new` 消息。
+
+use std::collections::{BTreeMap, HashSet};
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+
+use crate::message::{MSG, MessageType};
+
+/// 是否需要等待服务器确认
+pub fn needs_ack(type_: MessageType) -> bool {
+    matches!(type_, MessageType::Complete | MessageType::Fault)
+}
+
+#[derive(Debug, Default)]
+/// 单个充电桩的发件箱，重新连接后继续使用
+pub struct Outbox {
+    /// 是否保留需要确认的消息，为 `false` 时只分配消息 ID
+    tracking: bool,
+    /// 上一个分配的消息 ID
+    last_id: u64,
+    /// 未确认的消息及最后一次发送的真实时间，按消息 ID 排序
+    unacked: BTreeMap<u64, (MSG, Instant)>,
+    /// 已经加入队列的新详单 ID
+    seen_new: HashSet<u32>,
+}
+
+impl Outbox {
+    /// 创建发件箱
+    pub fn new(tracking: bool) -> Self {
+        Outbox {
+            tracking,
+            ..Outbox::default()
+        }
+    }
+
+    /// 为出站消息分配消息 ID 和发送时间，需要确认的消息加入发件箱
+    pub fn stamp(&mut self, msg: &mut MSG, sent_at: DateTime<Utc>, now: Instant) {
+        self.last_id += 1;
+        msg.msg_id = Some(self.last_id);
+        msg.sent_at = Some(sent_at);
+        if self.tracking && needs_ack(msg.type_) {
+            self.unacked.insert(self.last_id, (msg.clone(), now));
+        }
+    }
+
+    /// 确认消息，消息不在发件箱中时返回 `false`
+    pub fn ack(&mut self, msg_id: u64) -> bool {
+        self.unacked.remove(&msg_id).is_some()
+    }
+
+    /// 取出超过 `timeout` 仍未确认的消息，并把它们的发送时间更新为 `now`
+    pub fn due(&mut self, now: Instant, timeout: Duration) -> Vec<MSG> {
+        self.unacked
+            .values_mut()
+            .filter(|(_, sent)| now.duration_since(*sent) >= timeout)
+            .map(|(msg, sent)| {
+                *sent = now;
+                msg.clone()
+            })
+            .collect()
+    }
+
+    /// 取出所有未确认的消息，重新连接后按发送顺序重新发送
+    pub fn all(&mut self, now: Instant) -> Vec<MSG> {
+        self.due(now, Duration::ZERO)
+    }
+
+    /// 未确认的消息数量
+    pub fn len(&self) -> usize {
+        self.unacked.len()
+    }
+
+    /// 是否没有未确认的消息
+    pub fn is_empty(&self) -> bool {
+        self.unacked.is_empty()
+    }
+
+    /// 新详单是否已经加入过队列
+    pub fn seen_new(&self, id: u32) -> bool {
+        self.seen_new.contains(&id)
+    }
+
+    /// 记录已经加入队列的新详单
+    pub fn mark_new(&mut self, id: u32) {
+        self.seen_new.insert(id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unacked_messages_are_resent() {
+        let mut outbox = Outbox::new(true);
+        let start = Instant::now();
+        let mut messages: Vec<MSG> = [
+            MessageType::Update,
+            MessageType::Complete,
+            MessageType::Fault,
+        ]
+        .into_iter()
+        .map(MSG::empty)
+        .collect();
+        for msg in &mut messages {
+            outbox.stamp(msg, Utc::now(), start);
+        }
+        let ids: Vec<_> = messages.iter().map(|msg| msg.msg_id.unwrap()).collect();
+        assert_eq!(ids, vec![1, 2, 3]);
+        // 只有完成和故障消息需要确认
+        assert_eq!(outbox.len(), 2);
+
+        let timeout = Duration::from_secs(5);
+        assert!(
+            outbox
+                .due(start + Duration::from_secs(1), timeout)
+                .is_empty()
+        );
+        let later = start + timeout;
+        let due = outbox.due(later, timeout);
+        assert_eq!(due.len(), 2);
+        // 重新发送时保留原来的消息 ID
+        assert_eq!(due[0].msg_id, Some(2));
+        assert!(outbox.due(later, timeout).is_empty());
+
+        assert!(outbox.ack(2));
+        assert!(!outbox.ack(2));
+        let all = outbox.all(later);
+        assert_eq!(all.len(), 1);
+        assert_eq!(all[0].type_, MessageType::Fault);
+        assert!(outbox.ack(3) && outbox.is_empty());
+    }
+
+    #[test]
+    fn test_tracking_disabled() {
+        let mut outbox = Outbox::new(false);
+        let mut msg = MSG::empty(MessageType::Complete);
+        outbox.stamp(&mut msg, Utc::now(), Instant::now());
+        assert_eq!(msg.msg_id, Some(1));
+        assert!(outbox.is_empty());
+
+        assert!(!outbox.seen_new(4));
+        outbox.mark_new(4);
+        assert!(outbox.seen_new(4));
+    }
+}
@@ -0,0 +1,244 @@
+//! 崩溃安全的文件写入
+//!
+//! 状态文件整体替换写入，末尾带有长度和 CRC32 校验；
+//! 追加写入的 JSONL 文件每次写入一整行，启动时截掉进程被强制结束时留下的不完整行。
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// 校验行前缀
+const FOOTER_PREFIX: &str = "#taranis";
+
+/// 计算 CRC32 (IEEE 802.3)
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+/// 在路径后追加后缀
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+/// 上一代文件的路径
+pub fn backup_path(path: &Path) -> PathBuf {
+    with_suffix(path, ".bak")
+}
+
+/// 原子地写入文件，内容末尾追加长度和 CRC32 校验行
+/// 先写入临时文件并同步到磁盘，再把旧文件保留为 `.bak`，最后替换
+pub fn write_atomic(path: &Path, content: &str) -> io::Result<()> {
+    let tmp = with_suffix(path, ".tmp");
+    {
+        let mut file = File::create(&tmp)?;
+        file.write_all(content.as_bytes())?;
+        writeln!(
+            file,
+            "\n{} len={} crc={:08x}",
+            FOOTER_PREFIX,
+            content.len(),
+            crc32(content.as_bytes())
+        )?;
+        file.sync_all()?;
+    }
+    if path.exists() {
+        std::fs::rename(path, backup_path(path))?;
+    }
+    std::fs::rename(&tmp, path)
+}
+
+/// 读取 `write_atomic` 写入的文件并校验，返回去掉校验行的内容
+pub fn read_verified(path: &Path) -> Result<String, String> {
+    let raw = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let body = raw.strip_suffix('\n').ok_or("missing footer")?;
+    let (content, footer) = body.rsplit_once('\n').ok_or("missing footer")?;
+    let mut fields = footer
+        .strip_prefix(FOOTER_PREFIX)
+        .ok_or("missing footer")?
+        .split_whitespace();
+    let len: usize = fields
+        .next()
+        .and_then(|f| f.strip_prefix("len="))
+        .and_then(|f| f.parse().ok())
+        .ok_or("invalid footer length")?;
+    let crc = fields
+        .next()
+        .and_then(|f| f.strip_prefix("crc="))
+        .and_then(|f| u32::from_str_radix(f, 16).ok())
+        .ok_or("invalid footer checksum")?;
+    if content.len() != len {
+        return Err(format!(
+            "length mismatch: expected {}, got {}",
+            len,
+            content.len()
+        ));
+    }
+    if crc32(content.as_bytes()) != crc {
+        return Err("checksum mismatch".to_string());
+    }
+    Ok(content.to_string())
+}
+
+/// 读取并校验文件，校验失败时依次尝试上一代 `.bak` 文件
+/// 返回内容和实际读取的文件路径，两者都失败时返回两者的错误
+pub fn read_with_fallback(path: &Path) -> Result<(String, PathBuf), String> {
+    let current = match read_verified(path) {
+        Ok(content) => return Ok((content, path.to_path_buf())),
+        Err(e) => e,
+    };
+    let backup = backup_path(path);
+    match read_verified(&backup) {
+        Ok(content) => {
+            tracing::warn!(
+                "文件 {} 校验失败 ({})，使用上一代文件 {}",
+                path.display(),
+                current,
+                backup.display()
+            );
+            Ok((content, backup))
+        }
+        Err(e) => Err(format!("{}; backup: {}", current, e)),
+    }
+}
+
+/// 打开用于追加写入的 JSONL 文件
+/// 如果文件末尾有不完整的行（最后一个字节不是换行符），截掉该行并输出警告，返回截掉的字节数
+pub fn open_jsonl(path: &Path) -> io::Result<(File, u64)> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .read(true)
+        .append(true)
+        .open(path)?;
+    let dropped = truncate_partial_line(&mut file)?;
+    if dropped > 0 {
+        tracing::warn!(
+            "文件 {} 末尾有不完整的行，已截掉 {} 字节",
+            path.display(),
+            dropped
+        );
+    }
+    Ok((file, dropped))
+}
+
+/// 截掉文件末尾不完整的行，返回截掉的字节数
+fn truncate_partial_line(file: &mut File) -> io::Result<u64> {
+    let len = file.metadata()?.len();
+    // 从后往前按块查找最后一个换行符
+    let mut end = len;
+    let mut buf = [0u8; 4096];
+    while end > 0 {
+        let start = end.saturating_sub(buf.len() as u64);
+        let chunk = &mut buf[..(end - start) as usize];
+        file.seek(SeekFrom::Start(start))?;
+        file.read_exact(chunk)?;
+        if let Some(pos) = chunk.iter().rposition(|&b| b == b'\n') {
+            let keep = start + pos as u64 + 1;
+            if keep < len {
+                file.set_len(keep)?;
+            }
+            return Ok(len - keep);
+        }
+        end = start;
+    }
+    // 整个文件都没有换行符
+    if len > 0 {
+        file.set_len(0)?;
+    }
+    Ok(len)
+}
+
+/// 追加写入一整行，一次写入带换行符的完整内容
+pub fn append_line(file: &mut File, line: &str) -> io::Result<()> {
+    let mut buf = String::with_capacity(line.len() + 1);
+    buf.push_str(line);
+    buf.push('\n');
+    file.write_all(buf.as_bytes())?;
+    file.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("taranis-{}-{}", name, Uuid::new_v4()))
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn test_atomic_write_falls_back_to_backup() {
+        let path = temp_path("state");
+        write_atomic(&path, "{\"generation\":1}").unwrap();
+        write_atomic(&path, "{\"generation\":2}").unwrap();
+        assert_eq!(read_verified(&path).unwrap(), "{\"generation\":2}");
+
+        // 截断的文件校验失败，使用上一代文件
+        let raw = std::fs::read(&path).unwrap();
+        std::fs::write(&path, &raw[..raw.len() / 2]).unwrap();
+        assert!(read_verified(&path).is_err());
+        let (content, from) = read_with_fallback(&path).unwrap();
+        assert_eq!(content, "{\"generation\":1}");
+        assert_eq!(from, backup_path(&path));
+
+        // 内容被修改但长度不变时校验和不匹配
+        let tampered = String::from_utf8(raw).unwrap().replace("2}", "3}");
+        std::fs::write(&path, tampered).unwrap();
+        assert_eq!(read_verified(&path).unwrap_err(), "checksum mismatch");
+
+        // 两代文件都损坏时返回错误
+        std::fs::write(backup_path(&path), "garbage").unwrap();
+        assert!(read_with_fallback(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(backup_path(&path)).unwrap();
+    }
+
+    #[test]
+    fn test_jsonl_recovery_drops_partial_line() {
+        let path = temp_path("archive.jsonl");
+        let (mut file, dropped) = open_jsonl(&path).unwrap();
+        assert_eq!(dropped, 0);
+        append_line(&mut file, "{\"id\":1}").unwrap();
+        append_line(&mut file, "{\"id\":2}").unwrap();
+        drop(file);
+
+        // 模拟写入最后一行时进程被强制结束
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"{\"id\":3,\"pri").unwrap();
+        drop(file);
+
+        let (mut file, dropped) = open_jsonl(&path).unwrap();
+        assert_eq!(dropped, 12);
+        append_line(&mut file, "{\"id\":3}").unwrap();
+        drop(file);
+        let content = std::fs::read_to_string(&path).unwrap();
+        let ids: Vec<serde_json::Value> = content
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(ids.len(), 3);
+        assert_eq!(ids[2]["id"], 3);
+
+        // 没有任何完整行时清空文件
+        std::fs::write(&path, "{\"id\"").unwrap();
+        let (_, dropped) = open_jsonl(&path).unwrap();
+        assert_eq!(dropped, 5);
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), 0);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
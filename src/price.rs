@@ -1,25 +1,650 @@
-use std::sync::LazyLock;
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use std::sync::{Arc, LazyLock, RwLock};
 
-use chrono::{DateTime, NaiveDateTime, NaiveTime, Utc};
+use chrono::{
+    DateTime, Datelike, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Timelike, Utc, Weekday,
+};
+use chrono_tz::Tz;
 use serde::{Deserialize, Serialize};
+use uuid::Uuid;
 
 use crate::conf::CONF;
+use crate::time::get_mock_now;
 
-#[derive(Serialize, Deserialize, Clone, Copy)]
+#[derive(Serialize, Deserialize, Clone)]
 /// 时间段结构体
 struct TimePeriod {
     start: NaiveTime,
     end: NaiveTime,
     price: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// 时段标签，如 "peak"、"flat"、"valley"
+    label: Option<String>,
+}
+
+impl TimePeriod {
+    /// 获取时段标签，未设置标签时使用时间范围
+    fn label(&self) -> String {
+        self.label.clone().unwrap_or_else(|| {
+            format!(
+                "{}-{}",
+                self.start.format("%H:%M"),
+                self.end.format("%H:%M")
+            )
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(try_from = "String", into = "String")]
+/// 不含年份的日期，格式为 "MM-DD"，允许 "02-29"
+pub struct MonthDay {
+    month: u32,
+    day: u32,
+}
+
+impl MonthDay {
+    /// 日期对应的月和日
+    fn of(date: NaiveDate) -> Self {
+        MonthDay {
+            month: date.month(),
+            day: date.day(),
+        }
+    }
+
+    /// 闰年中的所有日期，按时间顺序排列
+    fn all() -> impl Iterator<Item = MonthDay> {
+        NaiveDate::from_ymd_opt(2000, 1, 1)
+            .unwrap()
+            .iter_days()
+            .take_while(|date| date.year() == 2000)
+            .map(MonthDay::of)
+    }
+}
+
+impl TryFrom<String> for MonthDay {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, String> {
+        let invalid = || format!("invalid month-day {:?}, expected MM-DD", value);
+        let (month, day) = value.split_once('-').ok_or_else(invalid)?;
+        let month = month.parse().map_err(|_| invalid())?;
+        let day = day.parse().map_err(|_| invalid())?;
+        // 按闰年检查，允许 2 月 29 日
+        NaiveDate::from_ymd_opt(2000, month, day).ok_or_else(invalid)?;
+        Ok(MonthDay { month, day })
+    }
+}
+
+impl From<MonthDay> for String {
+    fn from(value: MonthDay) -> String {
+        value.to_string()
+    }
+}
+
+impl std::fmt::Display for MonthDay {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:02}-{:02}", self.month, self.day)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(from = "FeeModelRepr", into = "FeeModelRepr")]
+/// 服务费的收取方式，价格表中写一个数字时只按度数收取
+pub struct FeeModel {
+    /// 每度电的服务费，计入每个时段的费用
+    pub per_kwh: f64,
+    /// 每次充电完成时收取一次的服务费
+    pub per_session: f64,
+    /// 充电完成后到离场前每分钟收取的占位费
+    pub per_minute_after_complete: f64,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+/// 服务费在价格表文件中的写法
+enum FeeModelRepr {
+    PerKwh(f64),
+    Model(FeeModelFields),
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+/// 分别指定的各项服务费，未写出的为 0，拼错的名称视为格式错误
+struct FeeModelFields {
+    #[serde(default, skip_serializing_if = "is_zero")]
+    per_kwh: f64,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    per_session: f64,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    per_minute_after_complete: f64,
+}
+
+fn is_zero(value: &f64) -> bool {
+    *value == 0.0
+}
+
+impl From<FeeModelRepr> for FeeModel {
+    fn from(repr: FeeModelRepr) -> Self {
+        match repr {
+            FeeModelRepr::PerKwh(per_kwh) => FeeModel::per_kwh(per_kwh),
+            FeeModelRepr::Model(fields) => FeeModel {
+                per_kwh: fields.per_kwh,
+                per_session: fields.per_session,
+                per_minute_after_complete: fields.per_minute_after_complete,
+            },
+        }
+    }
+}
+
+impl From<FeeModel> for FeeModelRepr {
+    fn from(fee: FeeModel) -> Self {
+        if fee.per_session == 0.0 && fee.per_minute_after_complete == 0.0 {
+            FeeModelRepr::PerKwh(fee.per_kwh)
+        } else {
+            FeeModelRepr::Model(FeeModelFields {
+                per_kwh: fee.per_kwh,
+                per_session: fee.per_session,
+                per_minute_after_complete: fee.per_minute_after_complete,
+            })
+        }
+    }
+}
+
+impl FeeModel {
+    /// 只按度数收取的服务费
+    pub fn per_kwh(per_kwh: f64) -> Self {
+        FeeModel {
+            per_kwh,
+            ..FeeModel::default()
+        }
+    }
+
+    /// 指定占位时长的占位费，按秒折算，保留两位小数
+    pub fn idle_fee(&self, idle: chrono::Duration) -> f64 {
+        let minutes = idle.num_milliseconds().max(0) as f64 / 60_000.0;
+        round_to_precision(self.per_minute_after_complete * minutes, 2)
+    }
+
+    /// 各项服务费的名称和数值，用于检查和比较价格表
+    fn items(&self) -> [(&'static str, f64); 3] {
+        [
+            ("per_kwh", self.per_kwh),
+            ("per_session", self.per_session),
+            ("per_minute_after_complete", self.per_minute_after_complete),
+        ]
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone)]
-/// 价格表结构体
-pub struct Prices {
+/// 按日期范围选择的季节价格表
+struct Season {
+    /// 季节名称
+    name: String,
+    /// 开始日期（含）
+    from: MonthDay,
+    /// 结束日期（含），早于开始日期时跨越年末
+    to: MonthDay,
     /// 时间段列表
     periods: Vec<TimePeriod>,
     /// 服务费
-    service_fee: f64,
+    service_fee: FeeModel,
+}
+
+impl Season {
+    /// 是否包含指定的日期
+    fn contains(&self, day: MonthDay) -> bool {
+        if self.from <= self.to {
+            self.from <= day && day <= self.to
+        } else {
+            day >= self.from || day <= self.to
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+/// 按会话累计充电度数划分的电价阶梯，只影响电费，不影响服务费
+struct Tier {
+    /// 会话累计充电度数超过该值后使用本阶梯
+    above_kwh: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// 时段电价乘以的倍数
+    multiplier: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// 代替时段电价的固定电价
+    price: Option<f64>,
+}
+
+impl Tier {
+    /// 本阶梯下的电价
+    fn apply(&self, price: f64) -> f64 {
+        match (self.multiplier, self.price) {
+            (_, Some(fixed)) => fixed,
+            // 与 [`Accumulator`] 的电价精度一致，避免明细中的单价出现浮点误差
+            (Some(multiplier), None) => round_to_precision(price * multiplier, 6),
+            (None, None) => price,
+        }
+    }
+
+    /// 是否有效：阈值为正数，倍数和固定电价有且只有一个且不为负数
+    fn is_valid(&self) -> bool {
+        let non_negative = |value: f64| value.is_finite() && value >= 0.0;
+        self.above_kwh.is_finite()
+            && self.above_kwh > 0.0
+            && match (self.multiplier, self.price) {
+                (Some(value), None) | (None, Some(value)) => non_negative(value),
+                _ => false,
+            }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+/// 单个价格时段内的用电量和费用
+pub struct PeriodUsage {
+    /// 时段标签，未设置标签时为时间范围
+    pub label: String,
+    /// 用电量，单位为kWh
+    pub kwh: f64,
+    /// 电费
+    pub cost: f64,
+    /// 服务费
+    pub fee: f64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+/// 账单明细中的一项，对应一个价格时段内的一段连续充电
+pub struct PriceLineItem {
+    /// 开始时间
+    pub start: DateTime<Utc>,
+    /// 结束时间
+    pub end: DateTime<Utc>,
+    /// 时段标签，未设置标签时为时间范围
+    pub label: String,
+    /// 电费单价
+    pub unit_price: f64,
+    /// 用电量，单位为kWh
+    pub kwh: f64,
+    /// 电费
+    pub cost: f64,
+    /// 服务费
+    pub fee: f64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+/// 按时间顺序逐项列出的账单明细
+/// 总费用由未舍入的用电量统一舍入得到，与各项舍入后的和可能相差几分
+pub struct PriceBreakdown {
+    /// 明细项
+    pub items: Vec<PriceLineItem>,
+    /// 充电费用
+    pub charge_cost: f64,
+    /// 服务费
+    pub service_fee: f64,
+    /// 总费用
+    pub total_cost: f64,
+}
+
+impl PriceBreakdown {
+    /// 接在另一份明细之后，明细项依次排列，费用按分相加
+    pub fn append(&mut self, other: PriceBreakdown) {
+        self.items.extend(other.items);
+        self.charge_cost = add_money(self.charge_cost, other.charge_cost);
+        self.service_fee = add_money(self.service_fee, other.service_fee);
+        self.total_cost = add_money(self.charge_cost, self.service_fee);
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+/// 重叠时段的冲突内容
+pub enum PeriodConflict {
+    /// 价格不一致
+    Price,
+    /// 标签不一致
+    Label,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+/// 价格表中的一个时段，用于在诊断信息中指出有问题的时段
+pub struct PeriodRef {
+    /// 所在的时段列表，如 `periods`、`schedules.weekend`、`seasons.summer`
+    pub table: String,
+    /// 在时段列表中的序号，从 0 开始
+    pub index: usize,
+    /// 开始时间
+    pub start: NaiveTime,
+    /// 结束时间
+    pub end: NaiveTime,
+    /// 电价
+    pub price: f64,
+}
+
+impl std::fmt::Display for PeriodRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}[{}] {}-{} (price {})",
+            self.table,
+            self.index,
+            self.start.format("%H:%M"),
+            self.end.format("%H:%M"),
+            self.price
+        )
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "reason", content = "detail", rename_all = "snake_case")]
+/// 价格表处理和价格计算错误
+pub enum PriceError {
+    /// 价格表未经过优化
+    NotOptimized,
+    /// 开始时间不早于结束时间
+    StartAfterEnd,
+    /// 重叠的时段价格或标签不一致
+    OverlappingPeriods {
+        /// 先定义的时段
+        first: PeriodRef,
+        /// 后定义的时段
+        second: PeriodRef,
+        /// 不一致的内容
+        conflict: PeriodConflict,
+    },
+    /// 多个时段跨越 0 点
+    MultipleMidnightCross {
+        /// 所在的时段列表
+        table: String,
+        /// 跨越 0 点的时段的序号
+        indices: Vec<usize>,
+    },
+    /// 时段的电价为负数
+    NegativePrice(PeriodRef),
+    /// 服务费为负数
+    InvalidServiceFee {
+        /// 所在的价格表，`service_fee` 或 `seasons.<名称>`
+        table: String,
+        /// 服务费
+        fee: f64,
+    },
+    /// 引用了不存在的日程
+    UnknownSchedule(String),
+    /// 日程没有任何时段
+    EmptySchedule(String),
+    /// 同一个星期被分配给多个日程
+    WeekdayReused(Weekday),
+    /// 季节没有任何时段
+    EmptySeason(String),
+    /// 两个季节的日期范围重叠
+    SeasonOverlap {
+        /// 先定义的季节
+        first: String,
+        /// 后定义的季节
+        second: String,
+        /// 第一个重叠的日期
+        date: MonthDay,
+    },
+    /// 没有默认价格表时，有日期不属于任何季节
+    SeasonGap(MonthDay),
+    /// 电价阶梯无效，内容为阶梯的序号（从 0 开始）
+    InvalidTier(usize),
+}
+
+impl std::fmt::Display for PriceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PriceError::NotOptimized => {
+                write!(f, "Prices have not been optimized, cannot calculate price")
+            }
+            PriceError::StartAfterEnd => write!(f, "Start time must be before end time"),
+            PriceError::OverlappingPeriods {
+                first,
+                second,
+                conflict,
+            } => write!(
+                f,
+                "Overlapping time periods with different {}: {} and {}",
+                match conflict {
+                    PeriodConflict::Price => "prices",
+                    PeriodConflict::Label => "labels",
+                },
+                first,
+                second
+            ),
+            PriceError::MultipleMidnightCross { table, indices } => write!(
+                f,
+                "{} time periods cross midnight in {}: {}",
+                indices.len(),
+                table,
+                indices
+                    .iter()
+                    .map(|index| format!("{}[{}]", table, index))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            PriceError::NegativePrice(period) => write!(f, "Negative price in {}", period),
+            PriceError::InvalidServiceFee { table, fee } => write!(
+                f,
+                "Service fee {} of {} must be a non-negative number",
+                fee, table
+            ),
+            PriceError::UnknownSchedule(name) => write!(f, "Unknown price schedule: {}", name),
+            PriceError::EmptySchedule(name) => {
+                write!(f, "Price schedule {} has no time periods", name)
+            }
+            PriceError::WeekdayReused(day) => {
+                write!(f, "{} is assigned to more than one price schedule", day)
+            }
+            PriceError::EmptySeason(name) => {
+                write!(f, "Price season {} has no time periods", name)
+            }
+            PriceError::SeasonOverlap {
+                first,
+                second,
+                date,
+            } => write!(
+                f,
+                "Price seasons {} and {} overlap on {}",
+                first, second, date
+            ),
+            PriceError::SeasonGap(date) => write!(
+                f,
+                "{} is not covered by any price season and there are no default periods",
+                date
+            ),
+            PriceError::InvalidTier(index) => write!(
+                f,
+                "Price tier {} must have a threshold above the previous tier and exactly one non-negative multiplier or price",
+                index
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PriceError {}
+
+impl From<PriceError> for String {
+    fn from(e: PriceError) -> String {
+        e.to_string()
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "reason", content = "detail", rename_all = "snake_case")]
+/// 价格表检查发现的不影响加载的问题
+pub enum PriceWarning {
+    /// 没有任何时段覆盖的时间，按 0 元计价
+    Gap {
+        /// 所在的时段列表
+        table: String,
+        /// 开始时间
+        start: NaiveTime,
+        /// 结束时间，0 点表示到当天结束
+        end: NaiveTime,
+    },
+}
+
+impl std::fmt::Display for PriceWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PriceWarning::Gap { table, start, end } => write!(
+                f,
+                "No period in {} covers {}-{}, charging then is free",
+                table,
+                start.format("%H:%M"),
+                if *end == MIDNIGHT {
+                    "24:00".to_string()
+                } else {
+                    end.format("%H:%M").to_string()
+                }
+            ),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+/// 价格表检查结果，有错误时价格表无法加载
+pub struct PriceReport {
+    /// 错误
+    pub errors: Vec<PriceError>,
+    /// 警告
+    pub warnings: Vec<PriceWarning>,
+}
+
+impl PriceReport {
+    /// 是否没有错误
+    pub fn is_ok(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+/// 一天中的一段时间，单位为从 0 点开始的秒数，0 点结束表示 86400
+type DayRange = (u32, u32);
+
+/// 时段覆盖的时间，跨越 0 点的时段分为两段
+fn day_ranges(period: &TimePeriod) -> Vec<DayRange> {
+    let seconds = |time: NaiveTime| time.num_seconds_from_midnight();
+    let end = if period.end == MIDNIGHT {
+        24 * 3600
+    } else {
+        seconds(period.end)
+    };
+    if period.start > period.end && period.end != MIDNIGHT {
+        vec![(seconds(period.start), 24 * 3600), (0, end)]
+    } else {
+        vec![(seconds(period.start), end)]
+    }
+}
+
+/// 一天中的秒数换算为时间，86400 换算为 0 点
+fn day_time(seconds: u32) -> NaiveTime {
+    NaiveTime::from_num_seconds_from_midnight_opt(seconds % (24 * 3600), 0).unwrap()
+}
+
+/// 检查一个时段列表：跨越 0 点的时段数、负数电价、价格或标签不一致的重叠时段和没有覆盖的时间
+fn check_periods(table: &str, periods: &[TimePeriod], report: &mut PriceReport) {
+    let period_ref = |index: usize| {
+        let period = &periods[index];
+        PeriodRef {
+            table: table.to_string(),
+            index,
+            start: period.start,
+            end: period.end,
+            price: period.price,
+        }
+    };
+    let crossing: Vec<usize> = (0..periods.len())
+        .filter(|&i| periods[i].start > periods[i].end && periods[i].end != MIDNIGHT)
+        .collect();
+    if crossing.len() > 1 {
+        report.errors.push(PriceError::MultipleMidnightCross {
+            table: table.to_string(),
+            indices: crossing,
+        });
+    }
+    for (index, period) in periods.iter().enumerate() {
+        if !(period.price >= 0.0 && period.price.is_finite()) {
+            report
+                .errors
+                .push(PriceError::NegativePrice(period_ref(index)));
+        }
+    }
+    let ranges: Vec<Vec<DayRange>> = periods.iter().map(day_ranges).collect();
+    for first in 0..periods.len() {
+        for second in first + 1..periods.len() {
+            let overlaps = ranges[first]
+                .iter()
+                .any(|a| ranges[second].iter().any(|b| a.0.max(b.0) < a.1.min(b.1)));
+            let conflict = if periods[first].price != periods[second].price {
+                PeriodConflict::Price
+            } else if periods[first].label != periods[second].label {
+                PeriodConflict::Label
+            } else {
+                continue;
+            };
+            if overlaps {
+                report.errors.push(PriceError::OverlappingPeriods {
+                    first: period_ref(first),
+                    second: period_ref(second),
+                    conflict,
+                });
+            }
+        }
+    }
+    let mut covered: Vec<DayRange> = ranges.into_iter().flatten().collect();
+    covered.sort();
+    let mut cursor = 0;
+    for (start, end) in covered.into_iter().chain([(24 * 3600, 24 * 3600)]) {
+        if start > cursor {
+            report.warnings.push(PriceWarning::Gap {
+                table: table.to_string(),
+                start: day_time(cursor),
+                end: day_time(start),
+            });
+        }
+        cursor = cursor.max(end);
+    }
+}
+
+/// 检查各项服务费都不是负数，按度数以外的服务费在价格表名称后加上服务费名称
+fn check_service_fee(table: &str, fee: &FeeModel, report: &mut PriceReport) {
+    for (name, value) in fee.items() {
+        if !(value >= 0.0 && value.is_finite()) {
+            report.errors.push(PriceError::InvalidServiceFee {
+                table: match name {
+                    "per_kwh" => table.to_string(),
+                    _ => format!("{}.{}", table, name),
+                },
+                fee: value,
+            });
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+/// 价格表结构体
+pub struct Prices {
+    #[serde(default)]
+    /// 时间段列表，不属于任何季节的日期使用，配置了覆盖全年的季节时可以省略
+    periods: Vec<TimePeriod>,
+    #[serde(default)]
+    /// 服务费，可以是每度电的服务费，也可以分别指定按度数、按次和按占位时长收取的服务费
+    service_fee: FeeModel,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    /// 按日期范围选择的季节价格表，日期范围不能重叠
+    seasons: Vec<Season>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    /// 按会话累计充电度数划分的电价阶梯，按阈值从小到大排列，对所有日程和季节生效
+    tiers: Vec<Tier>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    /// 其他命名日程，每个日程是一组时间段，与 `periods` 使用相同的服务费
+    schedules: BTreeMap<String, Vec<TimePeriod>>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    /// 按星期选择日程，键为日程名称，未列出的星期使用 `periods`
+    weekdays: BTreeMap<String, Vec<Weekday>>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    /// 节假日使用的日程，优先于按星期选择
+    holidays: BTreeMap<NaiveDate, String>,
     #[serde(default = "not_optimized", skip)]
     /// 是否经过优化
     is_optimized: bool,
@@ -35,7 +660,12 @@ impl Prices {
     pub fn new() -> Self {
         Prices {
             periods: Vec::new(),
-            service_fee: 0.0,    // 默认服务费为 0
+            service_fee: FeeModel::default(), // 默认服务费为 0
+            seasons: Vec::new(),
+            tiers: Vec::new(),
+            schedules: BTreeMap::new(),
+            weekdays: BTreeMap::new(),
+            holidays: BTreeMap::new(),
             is_optimized: false, // 默认未优化
         }
     }
@@ -43,74 +673,212 @@ impl Prices {
     #[allow(unused)]
     /// 添加一个时间段
     pub fn add_period(&mut self, start: NaiveTime, end: NaiveTime, price: f64) {
-        let period = TimePeriod { start, end, price };
+        let period = TimePeriod {
+            start,
+            end,
+            price,
+            label: None,
+        };
         self.periods.push(period);
     }
 
     /// 优化时间段，排序、合并重叠时间段、处理跨越 0 点的时间段
-    /// 对于价格不一致的重叠时间段会报错
-    pub fn optimize(&mut self) -> Result<&mut Self, String> {
+    /// 对于价格不一致的重叠时间段会报错，所有命名日程和季节都会检查
+    /// 检查结果中的第一个错误会导致优化失败
+    pub fn optimize(&mut self) -> Result<&mut Self, PriceError> {
+        if let Some(error) = self.validate().errors.into_iter().next() {
+            return Err(error);
+        }
+        for season in &mut self.seasons {
+            season.periods = Self::optimize_periods(&season.periods);
+        }
+        for periods in self.schedules.values_mut() {
+            *periods = Self::optimize_periods(periods);
+        }
+
         if self.periods.is_empty() {
+            // 季节覆盖全年时不需要默认时段
+            self.is_optimized = !self.seasons.is_empty();
             return Ok(self);
         }
+        self.periods = Self::optimize_periods(&self.periods);
+        self.is_optimized = true; // 标记为已优化
+
+        Ok(self)
+    }
+
+    /// 检查价格表，列出所有错误和没有时段覆盖的时间
+    /// 时段的序号为价格表文件中的顺序，已经优化过的价格表按合并后的时段计算
+    pub fn validate(&self) -> PriceReport {
+        let mut report = PriceReport::default();
+        for season in &self.seasons {
+            let table = format!("seasons.{}", season.name);
+            if season.periods.is_empty() {
+                report
+                    .errors
+                    .push(PriceError::EmptySeason(season.name.clone()));
+            } else {
+                check_periods(&table, &season.periods, &mut report);
+            }
+            check_service_fee(&table, &season.service_fee, &mut report);
+        }
+        if let Err(e) = self.check_seasons() {
+            report.errors.push(e);
+        }
+        let mut threshold = 0.0;
+        for (index, tier) in self.tiers.iter().enumerate() {
+            if !tier.is_valid() || tier.above_kwh <= threshold {
+                report.errors.push(PriceError::InvalidTier(index));
+            }
+            threshold = tier.above_kwh.max(threshold);
+        }
+        for (name, periods) in &self.schedules {
+            if periods.is_empty() {
+                report.errors.push(PriceError::EmptySchedule(name.clone()));
+            } else {
+                check_periods(&format!("schedules.{}", name), periods, &mut report);
+            }
+        }
+        let mut assigned: Vec<Weekday> = Vec::new();
+        for (name, days) in &self.weekdays {
+            if !self.schedules.contains_key(name) {
+                report
+                    .errors
+                    .push(PriceError::UnknownSchedule(name.clone()));
+            }
+            for day in days {
+                if assigned.contains(day) {
+                    report.errors.push(PriceError::WeekdayReused(*day));
+                }
+                assigned.push(*day);
+            }
+        }
+        let mut unknown: Vec<&String> = self
+            .holidays
+            .values()
+            .filter(|name| !self.schedules.contains_key(*name))
+            .collect();
+        unknown.dedup();
+        for name in unknown {
+            report
+                .errors
+                .push(PriceError::UnknownSchedule(name.clone()));
+        }
+        check_service_fee("service_fee", &self.service_fee, &mut report);
+        if !self.periods.is_empty() {
+            check_periods("periods", &self.periods, &mut report);
+        }
+        report
+    }
+
+    /// 检查季节的日期范围不重叠，没有默认时段时还要覆盖全年（包括 2 月 29 日）
+    fn check_seasons(&self) -> Result<(), PriceError> {
+        if self.seasons.is_empty() {
+            return Ok(());
+        }
+        for day in MonthDay::all() {
+            let mut matching = self.seasons.iter().filter(|season| season.contains(day));
+            match (matching.next(), matching.next()) {
+                (Some(first), Some(second)) => {
+                    return Err(PriceError::SeasonOverlap {
+                        first: first.name.clone(),
+                        second: second.name.clone(),
+                        date: day,
+                    });
+                }
+                (None, _) if self.periods.is_empty() => return Err(PriceError::SeasonGap(day)),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// 指定日期使用的时间段和服务费
+    /// 节假日优先，其次按星期选择日程，都没有时使用日期所在季节的时段，不属于任何季节时使用默认时段
+    /// 服务费按日期所在的季节选择
+    fn day_prices(&self, date: NaiveDate) -> (&[TimePeriod], f64) {
+        let season = self
+            .seasons
+            .iter()
+            .find(|season| season.contains(MonthDay::of(date)));
+        let (periods, service_fee) = match season {
+            Some(season) => (&season.periods, season.service_fee.per_kwh),
+            None => (&self.periods, self.service_fee.per_kwh),
+        };
+        let name = self.holidays.get(&date).or_else(|| {
+            self.weekdays
+                .iter()
+                .find(|(_, days)| days.contains(&date.weekday()))
+                .map(|(name, _)| name)
+        });
+        let periods = name
+            .and_then(|name| self.schedules.get(name))
+            .unwrap_or(periods);
+        (periods, service_fee)
+    }
 
-        // 遍历时间段，统计跨越 0 点的时间段
-        let mut cnt = 0;
+    /// 指定日期的服务费，按日期所在的季节选择
+    pub fn service_fee_on(&self, date: NaiveDate) -> FeeModel {
+        self.seasons
+            .iter()
+            .find(|season| season.contains(MonthDay::of(date)))
+            .map_or(self.service_fee, |season| season.service_fee)
+    }
+
+    /// 优化一个日程的时间段，返回覆盖一整天的时间段
+    /// 调用前需要先通过 [`Prices::validate`] 的检查
+    fn optimize_periods(periods: &[TimePeriod]) -> Vec<TimePeriod> {
+        // 拆分跨越 0 点的时间段
         let mut new_periods = Vec::new();
-        for period in &self.periods {
+        for period in periods {
             if period.start > period.end && period.end != MIDNIGHT {
-                cnt += 1;
                 new_periods.push(TimePeriod {
                     start: period.start,
                     end: MIDNIGHT,
                     price: period.price,
+                    label: period.label.clone(),
                 });
                 new_periods.push(TimePeriod {
                     start: MIDNIGHT,
                     end: period.end,
                     price: period.price,
+                    label: period.label.clone(),
                 });
             } else {
-                new_periods.push(*period);
+                new_periods.push(period.clone());
             }
         }
-        if cnt > 1 {
-            return Err(format!(
-                "{} time periods cross midnight, please check your input",
-                cnt
-            ));
-        }
-
         // 按照开始时间排序
-        new_periods.sort_by(|a, b| a.start.cmp(&b.start));
+        new_periods.sort_by_key(|a| a.start);
 
-        // 合并重叠的时间段，给空出的时间段补零，对于价格不一致重叠的时间段报错
+        // 合并重叠的时间段，给空出的时间段补零
         let mut merged_periods = Vec::new();
         let mut current_period: Option<TimePeriod> = None;
 
         for period in new_periods {
             if let Some(ref mut current) = current_period {
                 if period.start < current.end {
-                    // 重叠或相连的时间段
-                    if period.price != current.price {
-                        return Err(
-                            "Overlapping time periods with different prices found".to_string()
-                        );
+                    // 重叠的时间段价格和标签相同
+                    // 扩展当前时间段的结束时间，0 点结束表示到当天结束
+                    if current.end != MIDNIGHT
+                        && (period.end == MIDNIGHT || period.end > current.end)
+                    {
+                        current.end = period.end;
                     }
-                    current.end = period.end; // 扩展当前时间段的结束时间
                 } else if period.start > current.end {
                     // 有空隙的时间段
-                    merged_periods.push(*current); // 添加当前时间段
+                    merged_periods.push(current.clone()); // 添加当前时间段
                     // 添加一个空的时间段
                     merged_periods.push(TimePeriod {
                         start: current.end,
                         end: period.start,
                         price: 0.0, // 空隙时间段的价格为 0
+                        label: None,
                     });
                     current_period = Some(period); // 更新当前时间段为新的时间段
                 } else {
                     // 相等的时间段，直接添加
-                    merged_periods.push(*current);
+                    merged_periods.push(current.clone());
                     current_period = Some(period);
                 }
             } else {
@@ -120,6 +888,7 @@ impl Prices {
                         start: MIDNIGHT,
                         end: period.start,
                         price: 0.0, // 空隙时间段的价格为 0
+                        label: None,
                     });
                 }
                 current_period = Some(period);
@@ -137,14 +906,11 @@ impl Prices {
                 start: merged_periods.last().unwrap().end,
                 end: MIDNIGHT,
                 price: 0.0, // 空隙时间段的价格为 0
+                label: None,
             });
         }
 
-        // 更新价格列表
-        self.periods = merged_periods;
-        self.is_optimized = true; // 标记为已优化
-
-        Ok(self)
+        merged_periods
     }
 }
 
@@ -160,45 +926,57 @@ static DEFAULT_PRICES: LazyLock<Prices> = LazyLock::new(|| {
                 start: MIDNIGHT,
                 end: NaiveTime::from_hms_opt(7, 0, 0).unwrap(),
                 price: 0.4,
+                label: Some("valley".to_string()),
             },
             TimePeriod {
                 // 平时
                 start: NaiveTime::from_hms_opt(7, 0, 0).unwrap(),
                 end: NaiveTime::from_hms_opt(10, 0, 0).unwrap(),
                 price: 0.7,
+                label: Some("flat".to_string()),
             },
             TimePeriod {
                 // 峰时
                 start: NaiveTime::from_hms_opt(10, 0, 0).unwrap(),
                 end: NaiveTime::from_hms_opt(15, 0, 0).unwrap(),
                 price: 1.0,
+                label: Some("peak".to_string()),
             },
             TimePeriod {
                 // 平时
                 start: NaiveTime::from_hms_opt(15, 0, 0).unwrap(),
                 end: NaiveTime::from_hms_opt(18, 0, 0).unwrap(),
                 price: 0.7,
+                label: Some("flat".to_string()),
             },
             TimePeriod {
                 // 峰时
                 start: NaiveTime::from_hms_opt(18, 0, 0).unwrap(),
                 end: NaiveTime::from_hms_opt(21, 0, 0).unwrap(),
                 price: 1.0,
+                label: Some("peak".to_string()),
             },
             TimePeriod {
                 // 平时
                 start: NaiveTime::from_hms_opt(21, 0, 0).unwrap(),
                 end: NaiveTime::from_hms_opt(23, 0, 0).unwrap(),
                 price: 0.7,
+                label: Some("flat".to_string()),
             },
             TimePeriod {
                 // 谷时
                 start: NaiveTime::from_hms_opt(23, 0, 0).unwrap(),
                 end: MIDNIGHT,
                 price: 0.4,
+                label: Some("valley".to_string()),
             },
         ],
-        service_fee: 0.8,   // 默认服务费为 0.8
+        service_fee: FeeModel::per_kwh(0.8), // 默认服务费为每度 0.8
+        seasons: Vec::new(),
+        tiers: Vec::new(),
+        schedules: BTreeMap::new(),
+        weekdays: BTreeMap::new(),
+        holidays: BTreeMap::new(),
         is_optimized: true, // 默认已优化
     }
 });
@@ -209,11 +987,39 @@ impl Default for Prices {
     }
 }
 
-/// 计算从指定时间到午夜的小时数
-fn hours_to_midnight(time: NaiveTime) -> f64 {
-    let midnight = NaiveTime::from_hms_opt(0, 0, 0).unwrap();
-    let duration = midnight.signed_duration_since(time); // 计算从指定时间到午夜的持续时间
-    24.0 + duration.num_seconds() as f64 / 3600.0 // 转换为小时
+/// 本地时间对应的时刻，夏令时结束时重复的本地时间取第一次出现的时刻，
+/// 夏令时开始时跳过的本地时间取跳变后的第一个时刻
+fn local_instant(tz: &Tz, local: NaiveDateTime) -> DateTime<Utc> {
+    let resolve = |local: NaiveDateTime| {
+        tz.from_local_datetime(&local)
+            .earliest()
+            .map(|t| t.with_timezone(&Utc))
+    };
+    resolve(local).unwrap_or_else(|| {
+        // 时区跳变发生在整分钟，逐分钟向后查找跳变后的第一个有效时间
+        let minute = local.with_second(0).unwrap().with_nanosecond(0).unwrap();
+        (1..=24 * 60)
+            .find_map(|m| resolve(minute + chrono::Duration::minutes(m)))
+            .unwrap_or_else(|| local.and_utc())
+    })
+}
+
+/// 一段按同一时段计价的充电，时间为 UTC 时间，最后一项为服务费单价
+type Segment<'a> = (DateTime<Utc>, DateTime<Utc>, &'a TimePeriod, f64);
+
+/// 逐项列出费用时的一项，依次为开始和结束时间、价格时段、电价阶梯、单价和累加结果
+type Piece<'a> = (
+    DateTime<Utc>,
+    DateTime<Utc>,
+    &'a TimePeriod,
+    Option<&'a Tier>,
+    f64,
+    Accumulator,
+);
+
+/// 计算从指定时间到午夜的秒数
+fn seconds_to_midnight(time: NaiveTime) -> i64 {
+    24 * 3600 - i64::from(time.num_seconds_from_midnight())
 }
 
 /// 将浮点数四舍五入到指定的小数位数
@@ -222,135 +1028,1383 @@ pub fn round_to_precision(value: f64, decimal_places: u32) -> f64 {
     (value * multiplier).round() / multiplier
 }
 
+/// 金额转换为分
+pub fn to_cents(value: f64) -> i64 {
+    (value * 100.0).round() as i64
+}
+
+/// 分转换为金额
+pub fn from_cents(cents: i64) -> f64 {
+    cents as f64 / 100.0
+}
+
+/// 按分相加两个金额，结果保留两位小数
+pub fn add_money(a: f64, b: f64) -> f64 {
+    from_cents(to_cents(a) + to_cents(b))
+}
+
+/// 单价和功率换算为整数时的精度
+const PRICE_SCALE: i128 = 1_000_000;
+const POWER_SCALE: i128 = 1_000;
+
+/// 除法，四舍五入（远离零）
+fn div_round(n: i128, d: i128) -> i128 {
+    if n >= 0 {
+        (n + d / 2) / d
+    } else {
+        (n - d / 2) / d
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+/// 用整数累加的用电量和费用，单价精确到 1e-6，功率精确到 1e-3 kW，时间精确到秒
+/// 全部累加完后才四舍五入到分，避免浮点误差随充电时长累积
+struct Accumulator {
+    /// 功率 * 秒
+    energy: i128,
+    /// 单价 * 功率 * 秒
+    cost: i128,
+    /// 服务费单价 * 功率 * 秒
+    fee: i128,
+}
+
+impl Accumulator {
+    /// 累加一段时间的用电量和费用
+    fn add(&mut self, seconds: i64, price: f64, service_fee: f64, power: f64) {
+        let energy = i128::from(seconds) * (power * POWER_SCALE as f64).round() as i128;
+        self.energy += energy;
+        self.cost += energy * (price * PRICE_SCALE as f64).round() as i128;
+        self.fee += energy * (service_fee * PRICE_SCALE as f64).round() as i128;
+    }
+
+    /// 合并另一段累加结果
+    fn merge(&mut self, other: Accumulator) {
+        self.energy += other.energy;
+        self.cost += other.cost;
+        self.fee += other.fee;
+    }
+
+    /// 用电量，单位为 0.01kWh
+    fn energy_cents(&self) -> i64 {
+        div_round(self.energy * 100, 3600 * POWER_SCALE) as i64
+    }
+
+    /// 充电费用，单位为分
+    fn cost_cents(&self) -> i64 {
+        div_round(self.cost * 100, 3600 * POWER_SCALE * PRICE_SCALE) as i64
+    }
+
+    /// 服务费，单位为分
+    fn fee_cents(&self) -> i64 {
+        div_round(self.fee * 100, 3600 * POWER_SCALE * PRICE_SCALE) as i64
+    }
+}
+
+/// 按会话累计充电度数选择电价阶梯
+/// 依次累加各段充电时记录累计的用电量，跨越阶梯阈值的一段在阈值处拆分
+struct TierMeter<'a> {
+    /// 电价阶梯
+    tiers: &'a [Tier],
+    /// 会话累计用电量，单位与 [`Accumulator`] 相同
+    energy: i128,
+}
+
+impl<'a> TierMeter<'a> {
+    /// 从会话已经充电 `charged_kwh` 度开始计量
+    fn new(tiers: &'a [Tier], charged_kwh: f64) -> Self {
+        TierMeter {
+            tiers,
+            energy: kwh_to_energy(charged_kwh.max(0.0)),
+        }
+    }
+
+    /// 把一段充电按阶梯阈值拆分，返回每一部分的秒数和所在的阶梯
+    fn split(&mut self, seconds: i64, power: f64) -> Vec<(i64, Option<&'a Tier>)> {
+        let rate = (power * POWER_SCALE as f64).round() as i128;
+        let mut pieces = Vec::new();
+        let mut remaining = seconds;
+        while remaining > 0 {
+            let tier = self
+                .tiers
+                .iter()
+                .rev()
+                .find(|tier| self.energy >= kwh_to_energy(tier.above_kwh));
+            let next = self
+                .tiers
+                .iter()
+                .map(|tier| kwh_to_energy(tier.above_kwh))
+                .find(|&threshold| threshold > self.energy);
+            let take = match next {
+                Some(threshold) if rate > 0 => {
+                    // 至少前进 1 秒，避免阈值落在两秒之间时停滞
+                    let until = div_round(threshold - self.energy, rate).max(1) as i64;
+                    until.min(remaining)
+                }
+                _ => remaining,
+            };
+            self.energy += i128::from(take) * rate;
+            remaining -= take;
+            match pieces.last_mut() {
+                Some((last, last_tier)) if same_tier(*last_tier, tier) => *last += take,
+                _ => pieces.push((take, tier)),
+            }
+        }
+        pieces
+    }
+
+    /// 累加一段时间的用电量和费用，电价按所在的阶梯计算
+    fn add(
+        &mut self,
+        total: &mut Accumulator,
+        seconds: i64,
+        price: f64,
+        service_fee: f64,
+        power: f64,
+    ) {
+        if self.tiers.is_empty() {
+            total.add(seconds, price, service_fee, power);
+            return;
+        }
+        for (seconds, tier) in self.split(seconds, power) {
+            let price = tier.map_or(price, |tier| tier.apply(price));
+            total.add(seconds, price, service_fee, power);
+        }
+    }
+}
+
+/// 两部分是否属于同一个阶梯
+fn same_tier(a: Option<&Tier>, b: Option<&Tier>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => std::ptr::eq(a, b),
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+/// 度数换算为 [`Accumulator`] 的用电量单位
+fn kwh_to_energy(kwh: f64) -> i128 {
+    (kwh * 3600.0 * POWER_SCALE as f64).round() as i128
+}
+
 impl Prices {
     /// 计算指定时间段的价格
     /// 时间段结尾不能是 0 点
     fn calc_day_price(
         &self,
+        (periods, service_fee): (&[TimePeriod], f64),
         start: NaiveTime,
         end: NaiveTime,
         power: f64,
-    ) -> Result<(f64, f64), String> {
+        meter: &mut TierMeter,
+    ) -> Result<Accumulator, PriceError> {
         if !self.is_optimized {
-            return Err("Prices have not been optimized, cannot calculate day price".to_string());
+            return Err(PriceError::NotOptimized);
         }
         if start >= end {
-            return Err("Start time must be before end time".to_string());
+            return Err(PriceError::StartAfterEnd);
         }
-        let mut charge_amount = 0.0;
-        let mut service_fee = 0.0;
-        for period in &self.periods[..self.periods.len() - 1] {
+        let mut total = Accumulator::default();
+        for period in &periods[..periods.len() - 1] {
             if period.start < end && period.end > start {
                 // 计算重叠时间段的价格
                 let overlap_start = start.max(period.start);
                 let overlap_end = end.min(period.end);
-                let duration = (overlap_end - overlap_start).num_seconds() as f64 / 3600.0; // 转换为小时
-                charge_amount += duration * period.price * power;
-                service_fee += self.service_fee * power * duration; // 添加服务费
+                let seconds = (overlap_end - overlap_start).num_seconds();
+                meter.add(&mut total, seconds, period.price, service_fee, power);
             }
         }
         // 特判最后一段到 0 点的时间段
-        if end > self.periods.last().unwrap().start {
-            let overlap_start = start.max(self.periods.last().unwrap().start);
-            let duration = (end - overlap_start).num_seconds() as f64 / 3600.0; // 转换为小时
-            charge_amount += duration * self.periods.last().unwrap().price * power;
-            service_fee += self.service_fee * power * duration; // 添加服务费
+        if end > periods.last().unwrap().start {
+            let overlap_start = start.max(periods.last().unwrap().start);
+            let seconds = (end - overlap_start).num_seconds();
+            meter.add(
+                &mut total,
+                seconds,
+                periods.last().unwrap().price,
+                service_fee,
+                power,
+            );
         }
-        Ok((charge_amount, service_fee))
+        Ok(total)
     }
 
     /// 计算从指定时间到午夜的价格
     fn calc_day_price_until_midnight(
         &self,
+        (periods, service_fee): (&[TimePeriod], f64),
         start: NaiveTime,
         power: f64,
-    ) -> Result<(f64, f64), String> {
+        meter: &mut TierMeter,
+    ) -> Result<Accumulator, PriceError> {
         if !self.is_optimized {
-            return Err(
-                "Prices have not been optimized, cannot calculate day price until midnight"
-                    .to_string(),
-            );
+            return Err(PriceError::NotOptimized);
         }
-        let mut charge_amount = 0.0;
-        let mut service_fee = 0.0;
-        for period in &self.periods[..self.periods.len() - 1] {
+        let mut total = Accumulator::default();
+        for period in &periods[..periods.len() - 1] {
             if period.end > start {
                 // 计算重叠时间段的价格
                 let overlap_start = start.max(period.start);
-                let duration = (period.end - overlap_start).num_seconds() as f64 / 3600.0; // 转换为小时
-                charge_amount += duration * period.price * power;
-                service_fee += self.service_fee * power * duration; // 添加服务费
+                let seconds = (period.end - overlap_start).num_seconds();
+                meter.add(&mut total, seconds, period.price, service_fee, power);
             }
         }
 
         // 特判最后一段到 0 点的时间段
-        let overlap_start = start.max(self.periods.last().unwrap().start);
-        let duration = hours_to_midnight(overlap_start);
-        charge_amount += duration * self.periods.last().unwrap().price * power;
-        service_fee += self.service_fee * power * duration; // 添加服务费
+        let overlap_start = start.max(periods.last().unwrap().start);
+        let seconds = seconds_to_midnight(overlap_start);
+        meter.add(
+            &mut total,
+            seconds,
+            periods.last().unwrap().price,
+            service_fee,
+            power,
+        );
 
-        Ok((charge_amount, service_fee))
+        Ok(total)
     }
 
     /// 计算指定时间段的价格
-    /// 如果时间段跨越多天，会自动处理每一天的价格
+    /// 如果时间段跨越多天，会自动处理每一天的价格，每天按 24 小时计算，
+    /// 时区有夏令时时使用 [`Prices::calc_price_in`]
+    /// `charged_kwh` 为开始时会话已经充电的度数，用于选择电价阶梯
     pub fn calc_price(
         &self,
         start: NaiveDateTime,
         end: NaiveDateTime,
         power: f64,
-    ) -> Result<(f64, f64), String> {
+        charged_kwh: f64,
+    ) -> Result<(f64, f64), PriceError> {
         if !self.is_optimized {
-            return Err("Prices not have been optimized, cannot calculate price".to_string());
+            return Err(PriceError::NotOptimized);
         }
         if start >= end {
-            return Err("Start time must be before end time".to_string());
+            return Err(PriceError::StartAfterEnd);
         }
         let mut start_time = start.time();
         let end_time = end.time();
         let mut date = start.date();
-        let mut charge_amount = 0.0;
-        let mut service_fee = 0.0;
+        let mut total = Accumulator::default();
+        let mut meter = TierMeter::new(&self.tiers, charged_kwh);
         while date < end.date() {
-            let (amount, fee) = self.calc_day_price_until_midnight(start_time, power)?;
-            charge_amount += amount;
-            service_fee += fee;
+            total.merge(self.calc_day_price_until_midnight(
+                self.day_prices(date),
+                start_time,
+                power,
+                &mut meter,
+            )?);
             date = date.succ_opt().unwrap(); // 前进到下一天
             start_time = MIDNIGHT; // 重置开始时间为午夜
         }
         // 处理最后一天的时间段
         if end_time != MIDNIGHT {
-            let (amount, fee) = self.calc_day_price(start_time, end_time, power)?;
-            charge_amount += amount;
-            service_fee += fee;
+            total.merge(self.calc_day_price(
+                self.day_prices(date),
+                start_time,
+                end_time,
+                power,
+                &mut meter,
+            )?);
         }
 
         Ok((
-            round_to_precision(charge_amount, 2),
-            round_to_precision(service_fee, 2),
+            from_cents(total.cost_cents()),
+            from_cents(total.fee_cents()),
         ))
     }
-}
 
-/// 静态加载价格表
-static PRICESS: LazyLock<Prices> = LazyLock::new(|| {
-    let path = &CONF.price.path;
-    match std::fs::read_to_string(path) {
-        Ok(content) => {
-            let mut prices = serde_json::from_str(&content).unwrap_or_else(|e| {
-                tracing::warn!("无法解析价格配置文件 {}: {}，使用默认价格表", path, e);
-                let default_prices = Prices::default();
-                if let Err(we) =
-                    std::fs::write(path, serde_json::to_string_pretty(&default_prices).unwrap())
-                {
-                    tracing::error!("无法写入默认价格到 {}: {}", path, we);
-                    panic!("Unable to write default prices to {}: {}", path, we);
+    /// 把指定时间段按 `tz` 时区每一天的价格时段拆分，按时间顺序排列
+    /// 每一天从当地的 0 点开始，夏令时开始和结束的当天分别只有 23 小时和有 25 小时，
+    /// 每一段的时长按真实时刻计算
+    fn local_segments(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        tz: &Tz,
+    ) -> Vec<Segment<'_>> {
+        let mut segments = Vec::new();
+        let mut date = start.with_timezone(tz).date_naive();
+        let last = end.with_timezone(tz).date_naive();
+        while date <= last {
+            let (periods, service_fee) = self.day_prices(date);
+            let next = date.succ_opt().unwrap();
+            for period in periods {
+                let period_start = local_instant(tz, date.and_time(period.start));
+                let period_end = if period.end == MIDNIGHT {
+                    local_instant(tz, next.and_time(MIDNIGHT))
                 } else {
-                    tracing::info!("默认价格已写入 {}", path);
+                    local_instant(tz, date.and_time(period.end))
+                };
+                let from = start.max(period_start);
+                let to = end.min(period_end);
+                if from < to {
+                    segments.push((from, to, period, service_fee));
+                }
+            }
+            date = next; // 前进到下一天
+        }
+        segments
+    }
+
+    /// 计算指定时间段的价格，价格表按 `tz` 时区的本地时间计算，正确处理夏令时的切换
+    /// `charged_kwh` 为开始时会话已经充电的度数，用于选择电价阶梯
+    pub fn calc_price_in(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        power: f64,
+        charged_kwh: f64,
+        tz: &Tz,
+    ) -> Result<(f64, f64), PriceError> {
+        self.calc_profile_price_in(&[(start, end, power)], charged_kwh, tz)
+    }
+
+    /// 检查功率变化的各段是否可以计算价格
+    fn check_profile(&self, profile: &[PowerStep]) -> Result<(), PriceError> {
+        if !self.is_optimized {
+            return Err(PriceError::NotOptimized);
+        }
+        if profile.is_empty() || profile.iter().any(|(start, end, _)| start >= end) {
+            return Err(PriceError::StartAfterEnd);
+        }
+        Ok(())
+    }
+
+    /// 计算按 `profile` 的功率变化充电的价格，价格表按 `tz` 时区的本地时间计算
+    pub fn calc_profile_price_in(
+        &self,
+        profile: &[PowerStep],
+        charged_kwh: f64,
+        tz: &Tz,
+    ) -> Result<(f64, f64), PriceError> {
+        self.check_profile(profile)?;
+        let mut total = Accumulator::default();
+        let mut meter = TierMeter::new(&self.tiers, charged_kwh);
+        for &(start, end, power) in profile {
+            for (from, to, period, service_fee) in self.local_segments(start, end, tz) {
+                let seconds = (to - from).num_seconds();
+                meter.add(&mut total, seconds, period.price, service_fee, power);
+            }
+        }
+        Ok((
+            from_cents(total.cost_cents()),
+            from_cents(total.fee_cents()),
+        ))
+    }
+
+    /// 按时段标签统计指定时间段的用电量和费用
+    /// 相同标签的时段合并统计，按第一次出现的顺序排列
+    pub fn calc_price_breakdown(
+        &self,
+        start: NaiveDateTime,
+        end: NaiveDateTime,
+        power: f64,
+        charged_kwh: f64,
+    ) -> Result<Vec<PeriodUsage>, PriceError> {
+        if !self.is_optimized {
+            return Err(PriceError::NotOptimized);
+        }
+        if start >= end {
+            return Err(PriceError::StartAfterEnd);
+        }
+        let mut usages: Vec<(String, Accumulator)> = Vec::new();
+        let mut meter = TierMeter::new(&self.tiers, charged_kwh);
+        let mut date = start.date();
+        while date <= end.date() {
+            let (periods, service_fee) = self.day_prices(date);
+            for period in periods {
+                let period_start = date.and_time(period.start);
+                let period_end = if period.end == MIDNIGHT {
+                    date.succ_opt().unwrap().and_time(MIDNIGHT)
+                } else {
+                    date.and_time(period.end)
+                };
+                let overlap_start = start.max(period_start);
+                let overlap_end = end.min(period_end);
+                if overlap_start >= overlap_end {
+                    continue;
+                }
+                let seconds = (overlap_end - overlap_start).num_seconds();
+                let label = period.label();
+                let index = match usages.iter().position(|(l, _)| *l == label) {
+                    Some(index) => index,
+                    None => {
+                        usages.push((label, Accumulator::default()));
+                        usages.len() - 1
+                    }
+                };
+                meter.add(
+                    &mut usages[index].1,
+                    seconds,
+                    period.price,
+                    service_fee,
+                    power,
+                );
+            }
+            date = date.succ_opt().unwrap(); // 前进到下一天
+        }
+        Ok(usages
+            .into_iter()
+            .map(|(label, total)| PeriodUsage {
+                label,
+                kwh: from_cents(total.energy_cents()),
+                cost: from_cents(total.cost_cents()),
+                fee: from_cents(total.fee_cents()),
+            })
+            .collect())
+    }
+
+    /// 按时段标签统计指定时间段的用电量和费用，价格表按 `tz` 时区的本地时间计算
+    pub fn calc_price_breakdown_in(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        power: f64,
+        charged_kwh: f64,
+        tz: &Tz,
+    ) -> Result<Vec<PeriodUsage>, PriceError> {
+        self.calc_profile_breakdown_in(&[(start, end, power)], charged_kwh, tz)
+    }
+
+    /// 按时段标签统计按 `profile` 的功率变化充电的用电量和费用
+    pub fn calc_profile_breakdown_in(
+        &self,
+        profile: &[PowerStep],
+        charged_kwh: f64,
+        tz: &Tz,
+    ) -> Result<Vec<PeriodUsage>, PriceError> {
+        self.check_profile(profile)?;
+        let mut usages: Vec<(String, Accumulator)> = Vec::new();
+        let mut meter = TierMeter::new(&self.tiers, charged_kwh);
+        let segments = profile.iter().flat_map(|&(start, end, power)| {
+            self.local_segments(start, end, tz)
+                .into_iter()
+                .map(move |segment| (segment, power))
+        });
+        for ((from, to, period, service_fee), power) in segments {
+            let label = period.label();
+            let index = match usages.iter().position(|(l, _)| *l == label) {
+                Some(index) => index,
+                None => {
+                    usages.push((label, Accumulator::default()));
+                    usages.len() - 1
+                }
+            };
+            let seconds = (to - from).num_seconds();
+            meter.add(
+                &mut usages[index].1,
+                seconds,
+                period.price,
+                service_fee,
+                power,
+            );
+        }
+        Ok(usages
+            .into_iter()
+            .map(|(label, total)| PeriodUsage {
+                label,
+                kwh: from_cents(total.energy_cents()),
+                cost: from_cents(total.cost_cents()),
+                fee: from_cents(total.fee_cents()),
+            })
+            .collect())
+    }
+
+    /// 按时间顺序逐项列出指定时间段的用电量和费用，每个价格时段内的连续充电为一项
+    /// 价格表按 `tz` 时区的本地时间计算，明细项的时间为 UTC 时间
+    /// 跨越电价阶梯阈值的一项在阈值处拆分为两项
+    pub fn calc_price_itemized(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        power: f64,
+        charged_kwh: f64,
+        tz: &Tz,
+    ) -> Result<PriceBreakdown, PriceError> {
+        self.calc_profile_itemized(&[(start, end, power)], charged_kwh, tz)
+    }
+
+    /// 逐项列出按 `profile` 的功率变化充电的用电量和费用，一项内功率可以变化
+    pub fn calc_profile_itemized(
+        &self,
+        profile: &[PowerStep],
+        charged_kwh: f64,
+        tz: &Tz,
+    ) -> Result<PriceBreakdown, PriceError> {
+        self.check_profile(profile)?;
+        let (start, end) = (profile[0].0, profile[profile.len() - 1].1);
+        let mut segments: Vec<Segment> = Vec::new();
+        for (from, to, period, service_fee) in self.local_segments(start, end, tz) {
+            // 跨越 0 点的同一时段合并为一项，跨越季节时服务费不同则分为两项
+            match segments.last_mut() {
+                Some((_, last_end, last, last_fee))
+                    if *last_end == from
+                        && last.price == period.price
+                        && last.label() == period.label()
+                        && *last_fee == service_fee =>
+                {
+                    *last_end = to;
+                }
+                _ => segments.push((from, to, period, service_fee)),
+            }
+        }
+        let mut meter = TierMeter::new(&self.tiers, charged_kwh);
+        let mut pieces: Vec<Piece> = Vec::new();
+        for (from, to, period, service_fee) in segments {
+            // 同一个价格时段内同一阶梯的连续充电为一项，功率变化时分别累加
+            let first = pieces.len();
+            for (step_from, step_to, power) in clip_profile(profile, from, to) {
+                let mut cursor = step_from;
+                for (seconds, tier) in meter.split((step_to - step_from).num_seconds(), power) {
+                    let next = cursor + chrono::Duration::seconds(seconds);
+                    let price = tier.map_or(period.price, |tier| tier.apply(period.price));
+                    let same_segment = pieces.len() > first;
+                    match pieces.last_mut() {
+                        Some((_, last_end, _, last_tier, _, item))
+                            if same_segment && same_tier(*last_tier, tier) =>
+                        {
+                            *last_end = next;
+                            item.add(seconds, price, service_fee, power);
+                        }
+                        _ => {
+                            let mut item = Accumulator::default();
+                            item.add(seconds, price, service_fee, power);
+                            pieces.push((cursor, next, period, tier, price, item));
+                        }
+                    }
+                    cursor = next;
+                }
+            }
+        }
+        let mut total = Accumulator::default();
+        let items = pieces
+            .into_iter()
+            .map(|(from, to, period, _, price, item)| {
+                total.merge(item);
+                PriceLineItem {
+                    start: from,
+                    end: to,
+                    label: period.label(),
+                    unit_price: price,
+                    kwh: from_cents(item.energy_cents()),
+                    cost: from_cents(item.cost_cents()),
+                    fee: from_cents(item.fee_cents()),
+                }
+            })
+            .collect();
+        let (charge_cost, service_fee) = (total.cost_cents(), total.fee_cents());
+        Ok(PriceBreakdown {
+            items,
+            charge_cost: from_cents(charge_cost),
+            service_fee: from_cents(service_fee),
+            total_cost: from_cents(charge_cost + service_fee),
+        })
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+/// 两个价格表之间的差异，时间为本地时间，`end` 为 0 点表示到当天结束
+pub enum PriceDiff {
+    /// 每度电的服务费不同
+    ServiceFee { ours: f64, theirs: f64 },
+    /// 按次或按占位时长收取的服务费不同，`item` 为服务费名称
+    FeeModel {
+        item: String,
+        ours: f64,
+        theirs: f64,
+    },
+    /// 只有本价格表定义了该时段
+    OnlyInOurs { start: NaiveTime, end: NaiveTime },
+    /// 只有对方价格表定义了该时段
+    OnlyInTheirs { start: NaiveTime, end: NaiveTime },
+    /// 该时段电价不同
+    Price {
+        start: NaiveTime,
+        end: NaiveTime,
+        ours: f64,
+        theirs: f64,
+    },
+    /// 该时段电价相同但标签不同
+    Label {
+        start: NaiveTime,
+        end: NaiveTime,
+        ours: Option<String>,
+        theirs: Option<String>,
+    },
+    /// 同名季节的定义不同，或者只有一个价格表定义了该季节
+    Season { name: String },
+    /// 电价阶梯不同
+    Tiers,
+}
+
+impl PriceDiff {
+    /// 差异大小，用于与允许的偏差比较
+    /// 缺失的时段为无穷大，标签差异为 0
+    pub fn magnitude(&self) -> f64 {
+        match self {
+            PriceDiff::ServiceFee { ours, theirs }
+            | PriceDiff::FeeModel { ours, theirs, .. }
+            | PriceDiff::Price { ours, theirs, .. } => (ours - theirs).abs(),
+            PriceDiff::OnlyInOurs { .. }
+            | PriceDiff::OnlyInTheirs { .. }
+            | PriceDiff::Season { .. }
+            | PriceDiff::Tiers => f64::INFINITY,
+            PriceDiff::Label { .. } => 0.0,
+        }
+    }
+
+    /// 与上一个差异相连且内容相同时合并
+    fn merge(&mut self, next: &PriceDiff) -> bool {
+        use PriceDiff::*;
+        match (self, next) {
+            (OnlyInOurs { end, .. }, OnlyInOurs { start, end: e })
+            | (OnlyInTheirs { end, .. }, OnlyInTheirs { start, end: e })
+                if end == start =>
+            {
+                *end = *e;
+                true
+            }
+            (
+                Price {
+                    end, ours, theirs, ..
+                },
+                Price {
+                    start,
+                    end: e,
+                    ours: o,
+                    theirs: t,
+                },
+            ) if end == start && ours == o && theirs == t => {
+                *end = *e;
+                true
+            }
+            (
+                Label {
+                    end, ours, theirs, ..
+                },
+                Label {
+                    start,
+                    end: e,
+                    ours: o,
+                    theirs: t,
+                },
+            ) if end == start && ours == o && theirs == t => {
+                *end = *e;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+impl std::fmt::Display for PriceDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let range = |start: &NaiveTime, end: &NaiveTime| {
+            format!("{}-{}", start.format("%H:%M"), end.format("%H:%M"))
+        };
+        match self {
+            PriceDiff::ServiceFee { ours, theirs } => {
+                write!(f, "服务费不同: {} != {}", ours, theirs)
+            }
+            PriceDiff::FeeModel { item, ours, theirs } => {
+                write!(f, "服务费 {} 不同: {} != {}", item, ours, theirs)
+            }
+            PriceDiff::OnlyInOurs { start, end } => {
+                write!(f, "{} 只在本价格表中定义", range(start, end))
+            }
+            PriceDiff::OnlyInTheirs { start, end } => {
+                write!(f, "{} 只在参考价格表中定义", range(start, end))
+            }
+            PriceDiff::Price {
+                start,
+                end,
+                ours,
+                theirs,
+            } => write!(f, "{} 电价不同: {} != {}", range(start, end), ours, theirs),
+            PriceDiff::Label {
+                start,
+                end,
+                ours,
+                theirs,
+            } => write!(
+                f,
+                "{} 标签不同: {:?} != {:?}",
+                range(start, end),
+                ours,
+                theirs
+            ),
+            PriceDiff::Season { name } => write!(f, "季节 {} 的定义不同", name),
+            PriceDiff::Tiers => write!(f, "电价阶梯不同"),
+        }
+    }
+}
+
+impl Prices {
+    /// 查找包含指定时间的时段，需要先优化价格表
+    fn period_at(&self, time: NaiveTime) -> Option<&TimePeriod> {
+        self.periods
+            .iter()
+            .find(|p| p.start <= time && (time < p.end || p.end == MIDNIGHT))
+    }
+
+    /// 价格表指纹，内容相同的价格表指纹相同
+    /// 未优化的价格表先按优化后的结果计算
+    pub fn fingerprint(&self) -> Result<u64, String> {
+        use std::hash::{DefaultHasher, Hash, Hasher};
+        let mut prices = self.clone();
+        prices.optimize()?;
+        let mut hasher = DefaultHasher::new();
+        serde_json::to_string(&prices)
+            .map_err(|e| e.to_string())?
+            .hash(&mut hasher);
+        Ok(hasher.finish())
+    }
+
+    /// 比较两个价格表，返回所有差异
+    /// 两个价格表都会先优化，优化时补齐的空隙时段视为未定义
+    pub fn diff(&self, other: &Prices) -> Result<Vec<PriceDiff>, String> {
+        let mut ours = self.clone();
+        ours.optimize()?;
+        let mut theirs = other.clone();
+        theirs.optimize()?;
+
+        let mut diffs = Vec::new();
+        for ((item, ours), (_, theirs)) in ours
+            .service_fee
+            .items()
+            .into_iter()
+            .zip(theirs.service_fee.items())
+        {
+            if ours != theirs {
+                diffs.push(match item {
+                    "per_kwh" => PriceDiff::ServiceFee { ours, theirs },
+                    _ => PriceDiff::FeeModel {
+                        item: item.to_string(),
+                        ours,
+                        theirs,
+                    },
+                });
+            }
+        }
+        if ours.fingerprint()? == theirs.fingerprint()? {
+            return Ok(diffs);
+        }
+        // 季节按名称整体比较，默认时段按下面的方式逐段比较
+        let season_json = |prices: &Prices, name: &str| {
+            prices
+                .seasons
+                .iter()
+                .find(|season| season.name == name)
+                .map(|season| serde_json::to_string(season).unwrap_or_default())
+        };
+        let mut names: Vec<&str> = ours
+            .seasons
+            .iter()
+            .chain(&theirs.seasons)
+            .map(|season| season.name.as_str())
+            .collect();
+        names.sort();
+        names.dedup();
+        for name in names {
+            if season_json(&ours, name) != season_json(&theirs, name) {
+                diffs.push(PriceDiff::Season {
+                    name: name.to_string(),
+                });
+            }
+        }
+
+        if ours.tiers != theirs.tiers {
+            diffs.push(PriceDiff::Tiers);
+        }
+
+        // 按两个价格表的所有时段边界切分一天，逐段比较
+        let mut bounds: Vec<NaiveTime> = ours
+            .periods
+            .iter()
+            .chain(&theirs.periods)
+            .map(|p| p.start)
+            .collect();
+        bounds.sort();
+        bounds.dedup();
+        // 空价格表没有任何时段，整天视为未定义
+        let is_gap = |p: Option<&TimePeriod>| p.is_none_or(|p| p.price == 0.0 && p.label.is_none());
+        let mut windows: Vec<PriceDiff> = Vec::new();
+        for (i, &start) in bounds.iter().enumerate() {
+            let end = bounds.get(i + 1).copied().unwrap_or(MIDNIGHT);
+            let (p, q) = (ours.period_at(start), theirs.period_at(start));
+            let diff = match (is_gap(p), is_gap(q), p, q) {
+                (false, true, _, _) => PriceDiff::OnlyInOurs { start, end },
+                (true, false, _, _) => PriceDiff::OnlyInTheirs { start, end },
+                (false, false, Some(p), Some(q)) if p.price != q.price => PriceDiff::Price {
+                    start,
+                    end,
+                    ours: p.price,
+                    theirs: q.price,
+                },
+                (false, false, Some(p), Some(q)) if p.label != q.label => PriceDiff::Label {
+                    start,
+                    end,
+                    ours: p.label.clone(),
+                    theirs: q.label.clone(),
+                },
+                _ => continue,
+            };
+            if !windows.last_mut().is_some_and(|last| last.merge(&diff)) {
+                windows.push(diff);
+            }
+        }
+        diffs.extend(windows);
+        Ok(diffs)
+    }
+}
+
+/// 注册消息中的价格表指纹，即 [`Prices::fingerprint`] 的十六进制形式
+/// 指纹只在同一版本的程序之间可比
+pub fn fingerprint_hex(prices: &Prices) -> Result<String, String> {
+    Ok(format!("{:016x}", prices.fingerprint()?))
+}
+
+/// 按充电桩在注册消息中报告的价格表指纹，发现加载了不同价格表的充电桩
+/// 有参考价格表时与参考价格表比较，否则与第一个报告指纹的充电桩比较
+#[derive(Debug, Clone, Default)]
+pub struct FingerprintCheck {
+    /// 作为基准的指纹和它的来源
+    expected: Option<(String, String)>,
+}
+
+impl FingerprintCheck {
+    /// 以参考价格表为基准
+    pub fn with_reference(reference: &Prices) -> Result<Self, String> {
+        Ok(FingerprintCheck {
+            expected: Some(("reference table".to_string(), fingerprint_hex(reference)?)),
+        })
+    }
+
+    /// 检查充电桩报告的指纹，与基准不同时返回说明
+    /// 还没有基准时以该充电桩的指纹为基准
+    pub fn check(&mut self, charge_id: Uuid, fingerprint: &str) -> Option<String> {
+        match &self.expected {
+            Some((source, expected)) if expected != fingerprint => Some(format!(
+                "pile {} price table {} differs from {} ({})",
+                charge_id, fingerprint, source, expected
+            )),
+            Some(_) => None,
+            None => {
+                self.expected = Some((format!("pile {}", charge_id), fingerprint.to_string()));
+                None
+            }
+        }
+    }
+}
+
+impl Prices {
+    /// 从文件加载价格表，文件不存在或格式错误时返回错误
+    pub fn from_path(path: &str) -> Result<Prices, String> {
+        let mut prices = Self::read_unchecked(path)?;
+        prices
+            .optimize()
+            .map_err(|e| format!("failed to parse price file {}: {}", path, e))?;
+        Ok(prices)
+    }
+
+    /// 从文件读取价格表但不检查和优化，用于检查价格表
+    pub fn read_unchecked(path: &str) -> Result<Prices, String> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("failed to read price file {}: {}", path, e))?;
+        serde_json::from_str(&content)
+            .map_err(|e| format!("failed to parse price file {}: {}", path, e))
+    }
+}
+
+impl FromStr for Prices {
+    type Err = String;
+
+    /// 从 JSON 字符串解析价格表
+    fn from_str(content: &str) -> Result<Prices, String> {
+        let mut prices: Prices = serde_json::from_str(content).map_err(|e| e.to_string())?;
+        prices.optimize()?;
+        Ok(prices)
+    }
+}
+
+#[derive(Clone)]
+/// 充电桩自己的价格表和时区，嵌入到其他程序时可以为每个充电桩单独指定
+pub struct Pricing {
+    /// 价格表
+    prices: Arc<Prices>,
+    /// 计算价格使用的时区
+    tz: Tz,
+}
+
+impl Pricing {
+    /// 使用指定的价格表和时区
+    pub fn new(prices: Prices, tz: Tz) -> Self {
+        Pricing {
+            prices: Arc::new(prices),
+            tz,
+        }
+    }
+
+    /// 计算指定时间段的价格
+    pub fn calc_price(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        power: f64,
+        charged_kwh: f64,
+    ) -> Result<(f64, f64), PriceError> {
+        self.prices
+            .calc_price_in(start, end, power, charged_kwh, &self.tz)
+    }
+
+    /// 按时段标签统计指定时间段的用电量和费用
+    pub fn calc_price_breakdown(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        power: f64,
+        charged_kwh: f64,
+    ) -> Result<Vec<PeriodUsage>, PriceError> {
+        self.prices
+            .calc_price_breakdown_in(start, end, power, charged_kwh, &self.tz)
+    }
+
+    /// 指定时间按本地日期使用的服务费
+    pub fn service_fee_at(&self, time: DateTime<Utc>) -> FeeModel {
+        self.prices
+            .service_fee_on(time.with_timezone(&self.tz).date_naive())
+    }
+
+    /// 逐项列出指定时间段的用电量和费用
+    pub fn calc_price_itemized(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        power: f64,
+        charged_kwh: f64,
+    ) -> Result<PriceBreakdown, PriceError> {
+        self.prices
+            .calc_price_itemized(start, end, power, charged_kwh, &self.tz)
+    }
+}
+
+/// 指定时间使用的服务费，没有指定价格表时使用全局价格表和配置的时区
+pub fn service_fee_using(pricing: Option<&Pricing>, time: DateTime<Utc>) -> FeeModel {
+    match pricing {
+        Some(pricing) => pricing.service_fee_at(time),
+        None => PRICESS
+            .read()
+            .unwrap()
+            .service_fee_on(time.with_timezone(&CONF.time.tz).date_naive()),
+    }
+}
+
+/// 计算指定时间段的价格，没有指定价格表时使用全局价格表和配置的时区
+pub fn calc_price_using(
+    pricing: Option<&Pricing>,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    power: f64,
+    charged_kwh: f64,
+) -> Result<(f64, f64), PriceError> {
+    match pricing {
+        Some(pricing) => pricing.calc_price(start, end, power, charged_kwh),
+        None => calc_price_with_tz(start, end, power, charged_kwh),
+    }
+}
+
+/// 按时段统计指定时间段的用电量和费用，没有指定价格表时使用全局价格表和配置的时区
+pub fn calc_price_breakdown_using(
+    pricing: Option<&Pricing>,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    power: f64,
+    charged_kwh: f64,
+) -> Result<Vec<PeriodUsage>, PriceError> {
+    match pricing {
+        Some(pricing) => pricing.calc_price_breakdown(start, end, power, charged_kwh),
+        None => calc_price_breakdown_with_tz(start, end, power, charged_kwh),
+    }
+}
+
+/// 逐项列出指定时间段的用电量和费用，没有指定价格表时使用全局价格表和配置的时区
+pub fn calc_price_itemized_using(
+    pricing: Option<&Pricing>,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    power: f64,
+    charged_kwh: f64,
+) -> Result<PriceBreakdown, PriceError> {
+    match pricing {
+        Some(pricing) => pricing.calc_price_itemized(start, end, power, charged_kwh),
+        None => PRICESS.read().unwrap().calc_price_itemized(
+            start,
+            end,
+            power,
+            charged_kwh,
+            &CONF.time.tz,
+        ),
+    }
+}
+
+/// 免费充电时间段，结束时间为 `None` 表示仍在免费充电
+pub type FreeWindow = (DateTime<Utc>, Option<DateTime<Utc>>);
+
+/// 免费充电时间段在按时段统计中的标签
+pub const FREE_VEND_LABEL: &str = "free_vend";
+
+/// 去掉免费充电时间段后需要计费的时间段，免费时间段需要按开始时间排列
+fn rated_segments(
+    free: &[FreeWindow],
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+) -> Vec<(DateTime<Utc>, DateTime<Utc>)> {
+    let mut segments = Vec::new();
+    let mut cursor = start;
+    for &(from, to) in free {
+        let to = to.unwrap_or(end);
+        if to <= cursor || from >= end {
+            continue;
+        }
+        if from > cursor {
+            segments.push((cursor, from));
+        }
+        cursor = to;
+    }
+    if cursor < end {
+        segments.push((cursor, end));
+    }
+    segments
+}
+
+/// 一段以恒定功率充电的时间，最后一项为功率，单位为kW
+pub type PowerStep = (DateTime<Utc>, DateTime<Utc>, f64);
+
+/// 功率变化在 `from` 到 `to` 之间的部分
+fn clip_profile(profile: &[PowerStep], from: DateTime<Utc>, to: DateTime<Utc>) -> Vec<PowerStep> {
+    profile
+        .iter()
+        .map(|&(start, end, power)| (start.max(from), end.min(to), power))
+        .filter(|(start, end, _)| start < end)
+        .collect()
+}
+
+/// 按功率变化充电的度数
+fn profile_kwh(profile: &[PowerStep]) -> f64 {
+    profile
+        .iter()
+        .map(|(start, end, power)| power * (*end - *start).num_seconds() as f64 / 3600.0)
+        .sum()
+}
+
+/// 价格表指纹的十六进制形式，没有指定价格表时使用全局价格表
+pub fn fingerprint_hex_using(pricing: Option<&Pricing>) -> Result<String, String> {
+    with_prices(pricing, |prices, _| fingerprint_hex(prices))
+}
+
+/// 使用指定的价格表和时区计算，没有指定价格表时使用全局价格表和配置的时区
+fn with_prices<T>(pricing: Option<&Pricing>, calc: impl FnOnce(&Prices, &Tz) -> T) -> T {
+    match pricing {
+        Some(pricing) => calc(&pricing.prices, &pricing.tz),
+        None => calc(&PRICESS.read().unwrap(), &CONF.time.tz),
+    }
+}
+
+/// 使用 `table` 计算，`table` 为 `None` 时与 [`with_prices`] 相同
+fn with_table<T>(
+    pricing: Option<&Pricing>,
+    table: Option<&Prices>,
+    calc: impl FnOnce(&Prices, &Tz) -> T,
+) -> T {
+    match table {
+        Some(prices) => calc(prices, &CONF.time.tz),
+        None => with_prices(pricing, calc),
+    }
+}
+
+/// 计费时使用的一段时间及当时生效的价格表，价格表为 `None` 时使用当前价格表
+type BilledSegment = (DateTime<Utc>, DateTime<Utc>, Option<Arc<Prices>>);
+
+/// 全局价格表在 `start` 之后是否更换过，更换前的充电需要按原价格表计费
+fn reloaded_since(pricing: Option<&Pricing>, start: DateTime<Utc>) -> bool {
+    pricing.is_none()
+        && RETIRED_PRICES
+            .read()
+            .unwrap()
+            .iter()
+            .any(|retired| retired.until > start)
+}
+
+/// 在全局价格表更换的时刻切开 `from` 到 `to`，每段带有当时生效的价格表
+/// 指定了充电桩自己的价格表时不切开
+fn price_epochs(
+    pricing: Option<&Pricing>,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Vec<BilledSegment> {
+    if pricing.is_some() {
+        return vec![(from, to, None)];
+    }
+    let mut epochs = Vec::new();
+    let mut cursor = from;
+    for retired in RETIRED_PRICES.read().unwrap().iter() {
+        if retired.until <= cursor {
+            continue;
+        }
+        if retired.until >= to {
+            epochs.push((cursor, to, Some(retired.prices.clone())));
+            return epochs;
+        }
+        epochs.push((cursor, retired.until, Some(retired.prices.clone())));
+        cursor = retired.until;
+    }
+    epochs.push((cursor, to, None));
+    epochs
+}
+
+/// 需要计费的时间段：去掉免费充电时间段，并在全局价格表更换的时刻切开
+fn billed_segments(
+    pricing: Option<&Pricing>,
+    free: &[FreeWindow],
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+) -> Vec<BilledSegment> {
+    rated_segments(free, start, end)
+        .into_iter()
+        .flat_map(|(from, to)| price_epochs(pricing, from, to))
+        .collect()
+}
+
+/// 去掉免费充电时间段后剩下的免费充电时间段，与 [`rated_segments`] 互补
+fn free_segments(
+    rated: &[(DateTime<Utc>, DateTime<Utc>)],
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+) -> Vec<(DateTime<Utc>, DateTime<Utc>)> {
+    let mut segments = Vec::new();
+    let mut cursor = start;
+    for &(from, to) in rated {
+        if from > cursor {
+            segments.push((cursor, from));
+        }
+        cursor = to;
+    }
+    if cursor < end {
+        segments.push((cursor, end));
+    }
+    segments
+}
+
+/// 功率变化覆盖的时间段，没有任何一段时为 `None`
+fn profile_span(profile: &[PowerStep]) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+    Some((profile.first()?.0, profile.last()?.1))
+}
+
+/// 从开始充电到 `from` 时会话累计的度数，免费充电的度数也计入电价阶梯
+fn charged_before(profile: &[PowerStep], from: DateTime<Utc>, charged_kwh: f64) -> f64 {
+    let start = profile.first().map_or(from, |step| step.0);
+    charged_kwh + profile_kwh(&clip_profile(profile, start, from))
+}
+
+/// 计算按 `profile` 的功率变化充电去掉免费充电时间段后的价格
+pub fn calc_rated_price(
+    pricing: Option<&Pricing>,
+    free: &[FreeWindow],
+    profile: &[PowerStep],
+    charged_kwh: f64,
+) -> Result<(f64, f64), PriceError> {
+    let span = profile_span(profile).filter(|(start, end)| start < end);
+    let Some((start, end)) =
+        span.filter(|&(start, _)| !free.is_empty() || reloaded_since(pricing, start))
+    else {
+        return with_prices(pricing, |prices, tz| {
+            prices.calc_profile_price_in(profile, charged_kwh, tz)
+        });
+    };
+    let (mut cost, mut fee) = (0.0, 0.0);
+    for (from, to, table) in billed_segments(pricing, free, start, end) {
+        let offset = charged_before(profile, from, charged_kwh);
+        let rated = clip_profile(profile, from, to);
+        let price = with_table(pricing, table.as_deref(), |prices, tz| {
+            prices.calc_profile_price_in(&rated, offset, tz)
+        })?;
+        cost = add_money(cost, price.0);
+        fee = add_money(fee, price.1);
+    }
+    Ok((cost, fee))
+}
+
+/// 按时段统计按 `profile` 的功率变化充电的用电量和费用，免费充电的用电量单独记为费用为零的时段
+pub fn calc_rated_price_breakdown(
+    pricing: Option<&Pricing>,
+    free: &[FreeWindow],
+    profile: &[PowerStep],
+    charged_kwh: f64,
+) -> Result<Vec<PeriodUsage>, PriceError> {
+    let span = profile_span(profile).filter(|(start, end)| start < end);
+    let Some((start, end)) =
+        span.filter(|&(start, _)| !free.is_empty() || reloaded_since(pricing, start))
+    else {
+        return with_prices(pricing, |prices, tz| {
+            prices.calc_profile_breakdown_in(profile, charged_kwh, tz)
+        });
+    };
+    let mut usages = Vec::new();
+    let billed = billed_segments(pricing, free, start, end);
+    for (from, to, table) in &billed {
+        let offset = charged_before(profile, *from, charged_kwh);
+        let segment = clip_profile(profile, *from, *to);
+        let usage = with_table(pricing, table.as_deref(), |prices, tz| {
+            prices.calc_profile_breakdown_in(&segment, offset, tz)
+        })?;
+        usages = merge_period_usages(&usages, usage);
+    }
+    let rated: Vec<_> = billed.into_iter().map(|(from, to, _)| (from, to)).collect();
+    let free_segments = free_segments(&rated, start, end);
+    let free_seconds: i64 = free_segments
+        .iter()
+        .map(|(from, to)| (*to - *from).num_seconds())
+        .sum();
+    if free_seconds > 0 {
+        let free_kwh: f64 = free_segments
+            .into_iter()
+            .map(|(from, to)| profile_kwh(&clip_profile(profile, from, to)))
+            .sum();
+        usages.push(PeriodUsage {
+            label: FREE_VEND_LABEL.to_string(),
+            kwh: round_to_precision(free_kwh, 2),
+            cost: 0.0,
+            fee: 0.0,
+        });
+    }
+    Ok(usages)
+}
+
+/// 逐项列出按 `profile` 的功率变化充电的用电量和费用，免费充电的时间段单独列为单价和费用为零的一项
+pub fn calc_rated_price_itemized(
+    pricing: Option<&Pricing>,
+    free: &[FreeWindow],
+    profile: &[PowerStep],
+    charged_kwh: f64,
+) -> Result<PriceBreakdown, PriceError> {
+    let span = profile_span(profile).filter(|(start, end)| start < end);
+    let Some((start, end)) =
+        span.filter(|&(start, _)| !free.is_empty() || reloaded_since(pricing, start))
+    else {
+        return with_prices(pricing, |prices, tz| {
+            prices.calc_profile_itemized(profile, charged_kwh, tz)
+        });
+    };
+    let mut breakdown = PriceBreakdown::default();
+    let mut cursor = start;
+    let free_item = |from: DateTime<Utc>, to: DateTime<Utc>| PriceLineItem {
+        start: from,
+        end: to,
+        label: FREE_VEND_LABEL.to_string(),
+        unit_price: 0.0,
+        kwh: round_to_precision(profile_kwh(&clip_profile(profile, from, to)), 2),
+        cost: 0.0,
+        fee: 0.0,
+    };
+    for (from, to, table) in billed_segments(pricing, free, start, end) {
+        if from > cursor {
+            breakdown.items.push(free_item(cursor, from));
+        }
+        let offset = charged_before(profile, from, charged_kwh);
+        let segment = clip_profile(profile, from, to);
+        breakdown.append(with_table(pricing, table.as_deref(), |prices, tz| {
+            prices.calc_profile_itemized(&segment, offset, tz)
+        })?);
+        cursor = to;
+    }
+    if cursor < end {
+        breakdown.items.push(free_item(cursor, end));
+    }
+    Ok(breakdown)
+}
+
+/// 从新的路径重新加载价格表，加载失败时保留原价格表
+/// 新价格表从当前虚拟时间开始生效，之前已经充电的部分仍按原价格表计费
+pub fn reload_prices(path: &str) -> Result<(), String> {
+    reload_prices_at(path, get_mock_now())
+}
+
+/// 从新的路径重新加载价格表，新价格表从虚拟时间 `at` 开始生效
+pub fn reload_prices_at(path: &str, at: DateTime<Utc>) -> Result<(), String> {
+    let prices = Prices::from_path(path)?;
+    let mut current = PRICESS.write().unwrap();
+    let previous = std::mem::replace(&mut *current, prices);
+    let mut retired = RETIRED_PRICES.write().unwrap();
+    // 时钟向回调整后，晚于 `at` 的更换不再有意义
+    retired.retain(|retired| retired.until < at);
+    retired.push(RetiredPrices {
+        until: at,
+        prices: Arc::new(previous),
+    });
+    if retired.len() > MAX_RETIRED_PRICES {
+        retired.remove(0);
+    }
+    drop(retired);
+    drop(current);
+    *PRICES_PATH.write().unwrap() = path.to_string();
+    tracing::info!("价格表已从 {} 重新加载", path);
+    Ok(())
+}
+
+/// 从当前价格表路径重新读取价格表，加载失败时保留原价格表
+pub fn reload_current_prices() -> Result<(), String> {
+    let path = PRICES_PATH.read().unwrap().clone();
+    reload_prices(&path)
+}
+
+/// 当前价格表的路径，配置重载修改路径后随之更新
+static PRICES_PATH: LazyLock<RwLock<String>> =
+    LazyLock::new(|| RwLock::new(CONF.price.path.clone()));
+
+/// 静态加载价格表
+static PRICESS: LazyLock<RwLock<Prices>> = LazyLock::new(|| RwLock::new(initial_prices()));
+
+/// 保留的已更换价格表数量，超过时丢弃最早的价格表
+const MAX_RETIRED_PRICES: usize = 16;
+
+/// 被重新加载替换的价格表
+struct RetiredPrices {
+    /// 价格表停止生效的虚拟时间
+    until: DateTime<Utc>,
+    /// 价格表
+    prices: Arc<Prices>,
+}
+
+/// 已更换的价格表，按停止生效的时间排列，用于按原价格表计算更换前已经充电的部分
+static RETIRED_PRICES: LazyLock<RwLock<Vec<RetiredPrices>>> =
+    LazyLock::new(|| RwLock::new(Vec::new()));
+
+/// 启动时加载价格表，文件不存在或无法解析时写入默认价格表
+fn initial_prices() -> Prices {
+    let path = &CONF.price.path;
+    match std::fs::read_to_string(path) {
+        Ok(content) => {
+            let mut prices = serde_json::from_str(&content).unwrap_or_else(|e| {
+                tracing::warn!("无法解析价格配置文件 {}: {}，使用默认价格表", path, e);
+                let default_prices = Prices::default();
+                if let Err(we) =
+                    std::fs::write(path, serde_json::to_string_pretty(&default_prices).unwrap())
+                {
+                    tracing::error!("无法写入默认价格到 {}: {}", path, we);
+                    panic!("Unable to write default prices to {}: {}", path, we);
+                } else {
+                    tracing::info!("默认价格已写入 {}", path);
+                }
+                default_prices
+            });
+            let report = prices.validate();
+            for warning in &report.warnings {
+                tracing::warn!("价格配置文件 {}: {}", path, warning);
+            }
+            if let Err(e) = prices.optimize() {
+                for error in &report.errors {
+                    tracing::error!("价格配置文件 {}: {}", path, error);
                 }
-                default_prices
-            });
-            if let Err(e) = prices.optimize() {
                 tracing::error!("价格配置文件 {} 处理失败: {}", path, e);
                 panic!("Failed to optimize prices from {}: {}", path, e);
             }
@@ -370,7 +2424,7 @@ static PRICESS: LazyLock<Prices> = LazyLock::new(|| {
             default_prices
         }
     }
-});
+}
 
 /// 计算指定时间段的价格
 /// 使用设置的价格表
@@ -378,20 +2432,56 @@ pub fn calc_price(
     start: NaiveDateTime,
     end: NaiveDateTime,
     power: f64,
-) -> Result<(f64, f64), String> {
-    PRICESS.calc_price(start, end, power)
+    charged_kwh: f64,
+) -> Result<(f64, f64), PriceError> {
+    PRICESS
+        .read()
+        .unwrap()
+        .calc_price(start, end, power, charged_kwh)
 }
 
 /// 计算指定时间段的价格
-/// 使用设置的价格表和时区
+/// 使用设置的价格表和时区，`charged_kwh` 为开始时会话已经充电的度数
 pub fn calc_price_with_tz(
     start: DateTime<Utc>,
     end: DateTime<Utc>,
     power: f64,
-) -> Result<(f64, f64), String> {
-    let start_naive = start.with_timezone(&CONF.time.tz);
-    let end_naive = end.with_timezone(&CONF.time.tz);
-    calc_price(start_naive.naive_local(), end_naive.naive_local(), power)
+    charged_kwh: f64,
+) -> Result<(f64, f64), PriceError> {
+    PRICESS
+        .read()
+        .unwrap()
+        .calc_price_in(start, end, power, charged_kwh, &CONF.time.tz)
+}
+
+/// 合并两段充电按时段统计的用电量和费用，相同标签的时段相加
+pub fn merge_period_usages(first: &[PeriodUsage], second: Vec<PeriodUsage>) -> Vec<PeriodUsage> {
+    let mut merged = first.to_vec();
+    for usage in second {
+        match merged.iter_mut().find(|u| u.label == usage.label) {
+            Some(u) => {
+                u.kwh = add_money(u.kwh, usage.kwh);
+                u.cost = add_money(u.cost, usage.cost);
+                u.fee = add_money(u.fee, usage.fee);
+            }
+            None => merged.push(usage),
+        }
+    }
+    merged
+}
+
+/// 按时段标签统计指定时间段的用电量和费用
+/// 使用设置的价格表和时区
+pub fn calc_price_breakdown_with_tz(
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    power: f64,
+    charged_kwh: f64,
+) -> Result<Vec<PeriodUsage>, PriceError> {
+    PRICESS
+        .read()
+        .unwrap()
+        .calc_price_breakdown_in(start, end, power, charged_kwh, &CONF.time.tz)
 }
 
 #[cfg(test)]
@@ -403,6 +2493,7 @@ mod tests {
             start: NaiveTime::from_hms_opt(9, 0, 0).unwrap(),
             end: NaiveTime::from_hms_opt(17, 0, 0).unwrap(),
             price: 100.0,
+            label: None,
         };
 
         let serialized = serde_json::to_string_pretty(&period).unwrap();
@@ -423,15 +2514,18 @@ mod tests {
                     start: NaiveTime::from_hms_opt(9, 0, 0).unwrap(),
                     end: NaiveTime::from_hms_opt(12, 0, 0).unwrap(),
                     price: 50.0,
+                    label: None,
                 },
                 TimePeriod {
                     start: NaiveTime::from_hms_opt(13, 0, 0).unwrap(),
                     end: NaiveTime::from_hms_opt(17, 0, 0).unwrap(),
                     price: 75.0,
+                    label: None,
                 },
             ],
-            service_fee: 0.0,    // 默认服务费为 0
-            is_optimized: false, // 默认未优化
+            service_fee: FeeModel::default(), // 默认服务费为 0
+            is_optimized: false,              // 默认未优化
+            ..Prices::new()
         };
         let serialized = serde_json::to_string_pretty(&prices).unwrap();
         println!("Serialized: \n{}", serialized);
@@ -450,20 +2544,24 @@ mod tests {
                     start: NaiveTime::from_hms_opt(9, 0, 0).unwrap(),
                     end: NaiveTime::from_hms_opt(12, 0, 0).unwrap(),
                     price: 50.0,
+                    label: None,
                 },
                 TimePeriod {
                     start: NaiveTime::from_hms_opt(11, 0, 0).unwrap(),
                     end: NaiveTime::from_hms_opt(15, 0, 0).unwrap(),
                     price: 50.0,
+                    label: None,
                 },
                 TimePeriod {
                     start: NaiveTime::from_hms_opt(16, 0, 0).unwrap(),
                     end: NaiveTime::from_hms_opt(18, 0, 0).unwrap(),
                     price: 75.0,
+                    label: None,
                 },
             ],
-            service_fee: 0.0,
+            service_fee: FeeModel::default(),
             is_optimized: false, // 默认未优化
+            ..Prices::new()
         }; // 默认服务费为 0
         let result = prices.optimize();
         assert!(result.is_ok());
@@ -474,6 +2572,397 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_price_errors() {
+        use super::*;
+        let time = |h| NaiveTime::from_hms_opt(h, 0, 0).unwrap();
+        let period = |start, end, price| TimePeriod {
+            start: time(start),
+            end: time(end),
+            price,
+            label: None,
+        };
+        let mut prices = Prices::new();
+        prices.periods = vec![period(9, 12, 50.0), period(11, 15, 60.0)];
+        let err = prices.optimize().err().unwrap();
+        assert!(matches!(
+            err,
+            PriceError::OverlappingPeriods {
+                conflict: PeriodConflict::Price,
+                ..
+            }
+        ));
+        assert_eq!(
+            err.to_string(),
+            "Overlapping time periods with different prices: periods[0] 09:00-12:00 (price 50) and periods[1] 11:00-15:00 (price 60)"
+        );
+        assert_eq!(
+            serde_json::to_value(&err).unwrap()["detail"]["second"],
+            serde_json::json!({
+                "table": "periods",
+                "index": 1,
+                "start": "11:00:00",
+                "end": "15:00:00",
+                "price": 60.0,
+            })
+        );
+
+        prices.periods = vec![period(22, 2, 50.0), period(23, 1, 50.0)];
+        assert_eq!(
+            prices.optimize().err(),
+            Some(PriceError::MultipleMidnightCross {
+                table: "periods".to_string(),
+                indices: vec![0, 1],
+            })
+        );
+
+        let start =
+            NaiveDateTime::parse_from_str("2023-10-01 08:00:00", "%Y-%m-%d %H:%M:%S").unwrap();
+        assert_eq!(
+            Prices::new().calc_price(start, start, 1.0, 0.0),
+            Err(PriceError::NotOptimized)
+        );
+        assert_eq!(
+            Prices::default().calc_price(start, start, 1.0, 0.0),
+            Err(PriceError::StartAfterEnd)
+        );
+    }
+
+    #[test]
+    fn test_validate_reports_all_issues() {
+        use super::*;
+        let prices: Prices = serde_json::from_str(
+            r#"{"periods": [
+                {"start": "00:00:00", "end": "08:00:00", "price": 0.5},
+                {"start": "07:00:00", "end": "12:00:00", "price": 1.0},
+                {"start": "10:00:00", "end": "12:00:00", "price": 1.0, "label": "peak"},
+                {"start": "13:00:00", "end": "18:00:00", "price": -0.1},
+                {"start": "22:00:00", "end": "02:00:00", "price": 0.5},
+                {"start": "23:00:00", "end": "01:00:00", "price": 0.5}
+            ], "service_fee": -1.0,
+            "schedules": {"weekend": [
+                {"start": "00:00:00", "end": "00:00:00", "price": 0.3}
+            ]}}"#,
+        )
+        .unwrap();
+        let report = prices.validate();
+        assert!(!report.is_ok());
+        let reasons: Vec<String> = report.errors.iter().map(ToString::to_string).collect();
+        // 价格和标签相同的重叠时段（包括跨越 0 点的时段）不是错误
+        assert_eq!(
+            reasons,
+            [
+                "Service fee -1 of service_fee must be a non-negative number",
+                "2 time periods cross midnight in periods: periods[4], periods[5]",
+                "Negative price in periods[3] 13:00-18:00 (price -0.1)",
+                "Overlapping time periods with different prices: periods[0] 00:00-08:00 (price 0.5) and periods[1] 07:00-12:00 (price 1)",
+                "Overlapping time periods with different labels: periods[1] 07:00-12:00 (price 1) and periods[2] 10:00-12:00 (price 1)",
+            ]
+        );
+        // 没有时段覆盖的时间只是警告，全天的日程没有空隙
+        let gaps: Vec<String> = report.warnings.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            gaps,
+            [
+                "No period in periods covers 12:00-13:00, charging then is free",
+                "No period in periods covers 18:00-22:00, charging then is free",
+            ]
+        );
+
+        // 优化失败时返回第一个错误
+        let mut invalid = prices.clone();
+        assert_eq!(invalid.optimize().err().as_ref(), report.errors.first());
+
+        // 只有空隙的价格表可以加载，空隙按 0 元计价
+        let mut gapped: Prices = serde_json::from_str(
+            r#"{"periods": [{"start": "08:00:00", "end": "20:00:00", "price": 1.0}]}"#,
+        )
+        .unwrap();
+        let report = gapped.validate();
+        assert!(report.is_ok());
+        assert_eq!(
+            report.warnings,
+            [
+                PriceWarning::Gap {
+                    table: "periods".to_string(),
+                    start: MIDNIGHT,
+                    end: NaiveTime::from_hms_opt(8, 0, 0).unwrap(),
+                },
+                PriceWarning::Gap {
+                    table: "periods".to_string(),
+                    start: NaiveTime::from_hms_opt(20, 0, 0).unwrap(),
+                    end: MIDNIGHT,
+                },
+            ]
+        );
+        assert!(gapped.optimize().is_ok());
+        assert!(gapped.validate().warnings.is_empty());
+    }
+
+    #[test]
+    fn test_weekend_and_holiday_schedules() {
+        use super::*;
+        let mut value = serde_json::to_value(Prices::default()).unwrap();
+        // 旧格式的价格表不受影响
+        let legacy: Prices = serde_json::from_value(value.clone()).unwrap();
+        assert!(legacy.schedules.is_empty() && legacy.holidays.is_empty());
+        value["schedules"] = serde_json::json!({
+            "weekend": [{"start": "00:00:00", "end": "00:00:00", "price": 0.5, "label": "weekend"}],
+            "holiday": [{"start": "00:00:00", "end": "00:00:00", "price": 0.1}],
+        });
+        value["weekdays"] = serde_json::json!({"weekend": ["Sat", "Sun"]});
+        value["holidays"] = serde_json::json!({"2025-06-09": "holiday"});
+        let prices: Prices = value.to_string().parse().unwrap();
+        let time = |s| NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M").unwrap();
+
+        // 周五 22:00 到周六 02:00，0 点前按工作日价格，0 点后按周末价格
+        let (cost, fee) = prices
+            .calc_price(time("2025-06-06 22:00"), time("2025-06-07 02:00"), 1.0, 0.0)
+            .unwrap();
+        assert_eq!(cost, round_to_precision(0.7 + 0.4 + 2.0 * 0.5, 2));
+        assert_eq!(fee, 3.2);
+        let breakdown = prices
+            .calc_price_breakdown(time("2025-06-06 22:00"), time("2025-06-07 02:00"), 1.0, 0.0)
+            .unwrap();
+        let labels: Vec<&str> = breakdown.iter().map(|u| u.label.as_str()).collect();
+        assert_eq!(labels, ["flat", "valley", "weekend"]);
+        assert_eq!(breakdown[2].kwh, 2.0);
+
+        // 周日晚上到周一节假日
+        let (cost, _) = prices
+            .calc_price(time("2025-06-08 23:00"), time("2025-06-09 01:00"), 1.0, 0.0)
+            .unwrap();
+        assert_eq!(cost, 0.6);
+
+        // 引用不存在的日程或重复分配星期时报错
+        let mut invalid = value.clone();
+        invalid["holidays"] = serde_json::json!({"2025-06-09": "missing"});
+        let mut invalid: Prices = serde_json::from_value(invalid).unwrap();
+        assert_eq!(
+            invalid.optimize().err(),
+            Some(PriceError::UnknownSchedule("missing".to_string()))
+        );
+        let mut invalid = value.clone();
+        invalid["weekdays"] = serde_json::json!({"weekend": ["Sat"], "holiday": ["Sat"]});
+        let mut invalid: Prices = serde_json::from_value(invalid).unwrap();
+        assert_eq!(
+            invalid.optimize().err(),
+            Some(PriceError::WeekdayReused(Weekday::Sat))
+        );
+        // 每个日程都会检查
+        let mut invalid = value;
+        invalid["schedules"]["weekend"] = serde_json::json!([
+            {"start": "08:00:00", "end": "12:00:00", "price": 0.5},
+            {"start": "10:00:00", "end": "14:00:00", "price": 0.6},
+        ]);
+        let mut invalid: Prices = serde_json::from_value(invalid).unwrap();
+        assert!(matches!(
+            invalid.optimize(),
+            Err(PriceError::OverlappingPeriods { .. })
+        ));
+    }
+
+    #[test]
+    fn test_seasonal_prices() {
+        use super::*;
+        let flat = |price: f64, label: &str| serde_json::json!([{"start": "00:00:00", "end": "00:00:00", "price": price, "label": label}]);
+        let value = serde_json::json!({
+            "seasons": [
+                {"name": "summer", "from": "06-01", "to": "09-30", "periods": flat(1.0, "summer"), "service_fee": 0.5},
+                {"name": "winter", "from": "10-01", "to": "05-31", "periods": flat(0.6, "winter"), "service_fee": 0.8},
+            ],
+        });
+        let prices: Prices = value.to_string().parse().unwrap();
+        let time = |s| NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M").unwrap();
+
+        // 9 月 30 日 22:00 到 10 月 1 日 02:00，0 点前后分别使用两个季节的电价和服务费
+        let (cost, fee) = prices
+            .calc_price(time("2025-09-30 22:00"), time("2025-10-01 02:00"), 1.0, 0.0)
+            .unwrap();
+        assert_eq!(cost, round_to_precision(2.0 * 1.0 + 2.0 * 0.6, 2));
+        assert_eq!(fee, round_to_precision(2.0 * 0.5 + 2.0 * 0.8, 2));
+        let breakdown = prices
+            .calc_price_breakdown(time("2025-09-30 22:00"), time("2025-10-01 02:00"), 1.0, 0.0)
+            .unwrap();
+        let labels: Vec<&str> = breakdown.iter().map(|u| u.label.as_str()).collect();
+        assert_eq!(labels, ["summer", "winter"]);
+        let itemized = prices
+            .calc_price_itemized(
+                time("2025-09-30 22:00").and_utc(),
+                time("2025-10-01 02:00").and_utc(),
+                1.0,
+                0.0,
+                &chrono_tz::UTC,
+            )
+            .unwrap();
+        assert_eq!(itemized.items.len(), 2);
+        assert_eq!(itemized.items[1].fee, 1.6);
+        // 跨越年末的季节
+        let (cost, _) = prices
+            .calc_price(time("2025-12-31 23:00"), time("2026-01-01 01:00"), 1.0, 0.0)
+            .unwrap();
+        assert_eq!(cost, 1.2);
+
+        // 日期范围重叠、没有默认时段时未覆盖全年（包括 2 月 29 日）都会报错
+        let mut invalid = value.clone();
+        invalid["seasons"][0]["to"] = "10-01".into();
+        let mut invalid: Prices = serde_json::from_value(invalid).unwrap();
+        let err = invalid.optimize().err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "Price seasons summer and winter overlap on 10-01"
+        );
+        let mut invalid = value.clone();
+        invalid["seasons"][1]["to"] = "02-28".into();
+        let mut gap: Prices = serde_json::from_value(invalid.clone()).unwrap();
+        assert_eq!(
+            gap.optimize().err().map(|e| e.to_string()),
+            Some(
+                "02-29 is not covered by any price season and there are no default periods"
+                    .to_string()
+            )
+        );
+        // 有默认时段时，不属于任何季节的日期使用默认时段和服务费
+        invalid["periods"] = flat(0.3, "default");
+        invalid["service_fee"] = 0.1.into();
+        let prices: Prices = invalid.to_string().parse().unwrap();
+        let (cost, fee) = prices
+            .calc_price(time("2024-02-29 10:00"), time("2024-02-29 12:00"), 1.0, 0.0)
+            .unwrap();
+        assert_eq!((cost, fee), (0.6, 0.2));
+        // 季节的时段同样会检查
+        let mut invalid = value.clone();
+        invalid["seasons"][0]["periods"] = serde_json::json!([]);
+        let mut invalid: Prices = serde_json::from_value(invalid).unwrap();
+        assert_eq!(
+            invalid.optimize().err(),
+            Some(PriceError::EmptySeason("summer".to_string()))
+        );
+        let mut invalid = value.clone();
+        invalid["seasons"][0]["from"] = "02-30".into();
+        assert!(serde_json::from_value::<Prices>(invalid).is_err());
+
+        // 季节不同的价格表在比较时报告季节差异
+        let ours: Prices = value.to_string().parse().unwrap();
+        let mut other = value;
+        other["seasons"][0]["service_fee"] = 0.6.into();
+        let other: Prices = other.to_string().parse().unwrap();
+        assert_eq!(
+            ours.diff(&other).unwrap(),
+            vec![PriceDiff::Season {
+                name: "summer".to_string()
+            }]
+        );
+    }
+
+    #[test]
+    fn test_tiered_prices() {
+        use super::*;
+        let value = serde_json::json!({
+            "periods": [
+                {"start": "00:00:00", "end": "12:00:00", "price": 1.0, "label": "valley"},
+                {"start": "12:00:00", "end": "00:00:00", "price": 0.8, "label": "peak"},
+            ],
+            "service_fee": 0.5,
+            "tiers": [
+                {"above_kwh": 10.0, "multiplier": 1.5},
+                {"above_kwh": 15.0, "price": 2.0},
+            ],
+        });
+        let prices: Prices = value.to_string().parse().unwrap();
+        let time = |s| NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M").unwrap();
+        let start = time("2025-06-01 11:00");
+        let end = time("2025-06-01 14:00");
+
+        // 6 kW 充电：12:40 在峰时段中间超过 10 度，13:30 超过 15 度，服务费不受阶梯影响
+        // 6 * 1.0 + 4 * 0.8 + 5 * 0.8 * 1.5 + 3 * 2.0
+        let whole = prices.calc_price(start, end, 6.0, 0.0).unwrap();
+        assert_eq!(whole, (21.2, 9.0));
+
+        // 每次更新都从开始时间计算，费用单调不减，最后一次等于整段计算的结果
+        let mut last = (0.0, 0.0);
+        let mut now = start;
+        while now < end {
+            now = (now + chrono::Duration::minutes(7)).min(end);
+            let cost = prices.calc_price(start, now, 6.0, 0.0).unwrap();
+            assert!(
+                cost.0 >= last.0 && cost.1 >= last.1,
+                "{:?} < {:?}",
+                cost,
+                last
+            );
+            last = cost;
+        }
+        assert_eq!(last, whole);
+
+        // 分段计算时传入之前已经充电的度数，两段相加等于整段计算的结果
+        let half = time("2025-06-01 12:00");
+        let first = prices.calc_price(start, half, 6.0, 0.0).unwrap();
+        let second = prices.calc_price(half, end, 6.0, 6.0).unwrap();
+        assert_eq!(second, (15.2, 6.0));
+        assert_eq!(add_money(first.0, second.0), whole.0);
+
+        // 按时段统计时阶梯计入所在的时段，明细在阶梯阈值处拆分
+        let breakdown = prices.calc_price_breakdown(start, end, 6.0, 0.0).unwrap();
+        let costs: Vec<(&str, f64)> = breakdown
+            .iter()
+            .map(|u| (u.label.as_str(), u.cost))
+            .collect();
+        assert_eq!(costs, [("valley", 6.0), ("peak", 15.2)]);
+        let itemized = prices
+            .calc_price_itemized(start.and_utc(), end.and_utc(), 6.0, 0.0, &chrono_tz::UTC)
+            .unwrap();
+        let items: Vec<(String, f64, f64)> = itemized
+            .items
+            .iter()
+            .map(|item| {
+                (
+                    item.end.format("%H:%M").to_string(),
+                    item.unit_price,
+                    item.cost,
+                )
+            })
+            .collect();
+        assert_eq!(
+            items,
+            [
+                ("12:00".to_string(), 1.0, 6.0),
+                ("12:40".to_string(), 0.8, 3.2),
+                ("13:30".to_string(), 1.2, 6.0),
+                ("14:00".to_string(), 2.0, 6.0),
+            ]
+        );
+        assert_eq!(itemized.charge_cost, whole.0);
+
+        // 免费充电的度数同样计入阶梯
+        let pricing = Pricing::new(prices.clone(), chrono_tz::UTC);
+        let free = [(start.and_utc(), Some(half.and_utc()))];
+        let rated = calc_rated_price(
+            Some(&pricing),
+            &free,
+            &[(start.and_utc(), end.and_utc(), 6.0)],
+            0.0,
+        )
+        .unwrap();
+        assert_eq!(rated, second);
+
+        // 阈值需要递增，倍数和固定电价只能有一个
+        let mut invalid = value.clone();
+        invalid["tiers"][1]["above_kwh"] = 10.0.into();
+        let mut invalid: Prices = serde_json::from_value(invalid).unwrap();
+        assert_eq!(invalid.optimize().err(), Some(PriceError::InvalidTier(1)));
+        let mut invalid = value.clone();
+        invalid["tiers"][0]["price"] = 1.0.into();
+        let mut invalid: Prices = serde_json::from_value(invalid).unwrap();
+        assert_eq!(invalid.optimize().err(), Some(PriceError::InvalidTier(0)));
+
+        // 阶梯不同的价格表在比较时报告阶梯差异
+        let mut other = value;
+        other["tiers"][0]["multiplier"] = 1.2.into();
+        let other: Prices = other.to_string().parse().unwrap();
+        assert_eq!(prices.diff(&other).unwrap(), vec![PriceDiff::Tiers]);
+    }
+
     #[test]
     fn test_calc_price() {
         use super::*;
@@ -483,24 +2972,534 @@ mod tests {
         let end =
             NaiveDateTime::parse_from_str("2023-10-01 20:00:00", "%Y-%m-%d %H:%M:%S").unwrap();
         let power = 1.0; // 假设功率为 1.0
-        let result = prices.calc_price(start, end, power).unwrap();
+        let result = prices.calc_price(start, end, power, 0.0).unwrap();
         println!("Calculated price: {}", result.0 + result.1);
         assert_eq!(result.0 + result.1, 20.1);
         let start =
             NaiveDateTime::parse_from_str("2023-10-01 08:00:00", "%Y-%m-%d %H:%M:%S").unwrap();
         let end =
             NaiveDateTime::parse_from_str("2023-10-03 08:00:00", "%Y-%m-%d %H:%M:%S").unwrap();
-        let result1 = prices.calc_price(start, end, power).unwrap();
+        let result1 = prices.calc_price(start, end, power, 0.0).unwrap();
         println!("Calculated price for two days: {}", result1.0 + result1.1);
         let start =
             NaiveDateTime::parse_from_str("2023-10-01 00:00:00", "%Y-%m-%d %H:%M:%S").unwrap();
         let end =
             NaiveDateTime::parse_from_str("2023-10-03 00:00:00", "%Y-%m-%d %H:%M:%S").unwrap();
-        let result2 = prices.calc_price(start, end, power).unwrap();
+        let result2 = prices.calc_price(start, end, power, 0.0).unwrap();
         println!(
             "Calculated price for two days with midnight: {}",
             result2.0 + result2.1
         );
         assert_eq!(result1, result2);
     }
+
+    #[test]
+    fn test_calc_price_breakdown() {
+        use super::*;
+        let prices = Prices::default();
+        // 跨越谷时、平时和峰时
+        let start =
+            NaiveDateTime::parse_from_str("2023-10-01 05:00:00", "%Y-%m-%d %H:%M:%S").unwrap();
+        let end =
+            NaiveDateTime::parse_from_str("2023-10-01 12:00:00", "%Y-%m-%d %H:%M:%S").unwrap();
+        let power = 30.0;
+        let usages = prices.calc_price_breakdown(start, end, power, 0.0).unwrap();
+        let labels: Vec<&str> = usages.iter().map(|u| u.label.as_str()).collect();
+        assert_eq!(labels, vec!["valley", "flat", "peak"]);
+        assert_eq!(usages[0].kwh, 60.0);
+        assert_eq!(usages[1].kwh, 90.0);
+        assert_eq!(usages[2].kwh, 60.0);
+
+        let (cost, fee) = prices.calc_price(start, end, power, 0.0).unwrap();
+        let sum = |f: fn(&PeriodUsage) -> f64| usages.iter().map(f).sum::<f64>();
+        assert_eq!(round_to_precision(sum(|u| u.kwh), 2), 7.0 * power);
+        assert_eq!(round_to_precision(sum(|u| u.cost), 2), cost);
+        assert_eq!(round_to_precision(sum(|u| u.fee), 2), fee);
+
+        // 跨天时相同标签合并统计
+        let end =
+            NaiveDateTime::parse_from_str("2023-10-02 06:00:00", "%Y-%m-%d %H:%M:%S").unwrap();
+        let usages = prices.calc_price_breakdown(start, end, power, 0.0).unwrap();
+        assert_eq!(usages.len(), 3);
+        let (cost, fee) = prices.calc_price(start, end, power, 0.0).unwrap();
+        let sum = |f: fn(&PeriodUsage) -> f64| usages.iter().map(f).sum::<f64>();
+        assert_eq!(round_to_precision(sum(|u| u.cost), 2), cost);
+        assert_eq!(round_to_precision(sum(|u| u.fee), 2), fee);
+    }
+
+    #[test]
+    fn test_long_charge_cost_is_exact() {
+        use super::*;
+        use crate::detail::ChargingDetail;
+        let mut prices = Prices::new();
+        for (start, end, price) in [(0, 8, 0.3512), (8, 18, 1.0633), (18, 0, 0.7181)] {
+            prices.add_period(
+                NaiveTime::from_hms_opt(start, 0, 0).unwrap(),
+                NaiveTime::from_hms_opt(end, 0, 0).unwrap(),
+                price,
+            );
+        }
+        prices.service_fee = FeeModel::per_kwh(0.4321);
+        prices.optimize().unwrap();
+
+        // 72 小时每个时刻恰好经过三次：
+        // 充电费 3 * 7 * (8 * 0.3512 + 10 * 1.0633 + 6 * 0.7181) = 372.7752
+        // 服务费 72 * 7 * 0.4321 = 217.7784
+        let start =
+            NaiveDateTime::parse_from_str("2025-01-01 10:20:30", "%Y-%m-%d %H:%M:%S").unwrap();
+        let end = start + chrono::Duration::hours(72);
+        assert_eq!(
+            prices.calc_price(start, end, 7.0, 0.0).unwrap(),
+            (372.78, 217.78)
+        );
+        let usages = prices.calc_price_breakdown(start, end, 7.0, 0.0).unwrap();
+        // 3 * 10 * 7 * 1.0633 = 223.293，3 * 6 * 7 * 0.7181 = 90.4806，3 * 8 * 7 * 0.3512 = 59.0016
+        let costs: Vec<f64> = usages.iter().map(|u| u.cost).collect();
+        assert_eq!(costs, [223.29, 90.48, 59.0]);
+
+        // 每次更新的充电费加服务费都按分精确等于总费用
+        let mut detail = ChargingDetail::test_new(1);
+        let utc = |t: NaiveDateTime| t.and_utc();
+        detail.start(utc(start), 7.0, &crate::curve::PowerCurve::default());
+        for minutes in (7..=72 * 60).step_by(7) {
+            let now = start + chrono::Duration::minutes(minutes);
+            let (cost, fee) = prices.calc_price(start, now, 7.0, 0.0).unwrap();
+            detail.update_state(7.0 * minutes as f64 / 60.0, cost, fee, utc(now));
+            let json = serde_json::to_value(&detail).unwrap();
+            let cents = |key: &str| {
+                let value = json[key].as_f64().unwrap();
+                assert_eq!(value, from_cents(to_cents(value)), "{} = {}", key, value);
+                to_cents(value)
+            };
+            assert_eq!(
+                cents("charge_cost") + cents("service_fee"),
+                cents("total_cost")
+            );
+        }
+    }
+
+    #[test]
+    fn test_itemized_breakdown() {
+        use super::*;
+        let prices: Prices = r#"{"periods": [
+            {"start": "08:00:00", "end": "20:00:00", "price": 1.0, "label": "peak"},
+            {"start": "20:00:00", "end": "08:00:00", "price": 0.5, "label": "valley"}
+        ], "service_fee": 0.2}"#
+            .parse()
+            .unwrap();
+        let at = |s: &str| DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc);
+
+        // 跨越两个时段
+        let breakdown = prices
+            .calc_price_itemized(
+                at("2025-01-01T07:00:00Z"),
+                at("2025-01-01T09:30:00Z"),
+                10.0,
+                0.0,
+                &chrono_tz::UTC,
+            )
+            .unwrap();
+        let items: Vec<_> = breakdown
+            .items
+            .iter()
+            .map(|i| (i.label.as_str(), i.unit_price, i.kwh, i.cost, i.fee))
+            .collect();
+        assert_eq!(
+            items,
+            [
+                ("valley", 0.5, 10.0, 5.0, 2.0),
+                ("peak", 1.0, 15.0, 15.0, 3.0)
+            ]
+        );
+        assert_eq!(breakdown.items[0].end, at("2025-01-01T08:00:00Z"));
+        assert_eq!(breakdown.items[1].start, at("2025-01-01T08:00:00Z"));
+        assert_eq!((breakdown.charge_cost, breakdown.service_fee), (20.0, 5.0));
+        assert_eq!(breakdown.total_cost, 25.0);
+
+        // 跨越 0 点的谷时合并为一项，按上海时间计价时明细项为 UTC 时间
+        let breakdown = prices
+            .calc_price_itemized(
+                at("2025-01-01T22:00:00+08:00"),
+                at("2025-01-02T09:00:00+08:00"),
+                10.0,
+                0.0,
+                &chrono_tz::Asia::Shanghai,
+            )
+            .unwrap();
+        assert_eq!(breakdown.items.len(), 2);
+        let valley = &breakdown.items[0];
+        assert_eq!(valley.start, at("2025-01-01T14:00:00Z"));
+        assert_eq!(valley.end, at("2025-01-02T00:00:00Z"));
+        assert_eq!((valley.kwh, valley.cost, valley.fee), (100.0, 50.0, 20.0));
+        assert_eq!(breakdown.items[1].end, at("2025-01-02T01:00:00Z"));
+        assert_eq!(breakdown.total_cost, 82.0);
+    }
+
+    #[test]
+    fn test_profile_prices_follow_power() {
+        use super::*;
+        let prices: Prices = r#"{"periods": [
+            {"start": "08:00:00", "end": "20:00:00", "price": 1.0, "label": "peak"},
+            {"start": "20:00:00", "end": "08:00:00", "price": 0.5, "label": "valley"}
+        ], "service_fee": 0.2}"#
+            .parse()
+            .unwrap();
+        let at = |s: &str| DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc);
+        let tz = &chrono_tz::UTC;
+
+        // 功率在谷时内从 10kW 降到 4kW，跨越到峰时：谷时 10 + 4 度，峰时 6 度
+        let profile = [
+            (at("2025-01-01T06:00:00Z"), at("2025-01-01T07:00:00Z"), 10.0),
+            (at("2025-01-01T07:00:00Z"), at("2025-01-01T09:30:00Z"), 4.0),
+        ];
+        assert_eq!(
+            prices.calc_profile_price_in(&profile, 0.0, tz).unwrap(),
+            (13.0, 4.0)
+        );
+        let usages = prices.calc_profile_breakdown_in(&profile, 0.0, tz).unwrap();
+        let usages: Vec<_> = usages.iter().map(|u| (u.label.as_str(), u.kwh)).collect();
+        assert_eq!(usages, [("valley", 14.0), ("peak", 6.0)]);
+
+        // 同一时段内功率变化不拆分明细项
+        let breakdown = prices.calc_profile_itemized(&profile, 0.0, tz).unwrap();
+        let items: Vec<_> = breakdown
+            .items
+            .iter()
+            .map(|i| (i.label.as_str(), i.kwh, i.cost))
+            .collect();
+        assert_eq!(items, [("valley", 14.0, 7.0), ("peak", 6.0, 6.0)]);
+        assert_eq!(breakdown.items[0].start, at("2025-01-01T06:00:00Z"));
+
+        // 只有一段时与恒定功率的计算相同
+        let constant = [(at("2025-01-01T07:00:00Z"), at("2025-01-01T09:30:00Z"), 10.0)];
+        assert_eq!(
+            prices.calc_profile_itemized(&constant, 0.0, tz).unwrap(),
+            prices
+                .calc_price_itemized(constant[0].0, constant[0].1, 10.0, 0.0, tz)
+                .unwrap()
+        );
+        assert_eq!(
+            prices.calc_profile_price_in(&[], 0.0, tz),
+            Err(PriceError::StartAfterEnd)
+        );
+    }
+
+    #[test]
+    fn test_dst_days_use_real_durations() {
+        use super::*;
+        let prices: Prices = r#"{"periods": [
+            {"start": "08:00:00", "end": "20:00:00", "price": 1.0, "label": "peak"},
+            {"start": "20:00:00", "end": "08:00:00", "price": 0.5, "label": "valley"}
+        ], "service_fee": 0.2}"#
+            .parse()
+            .unwrap();
+        let at = |s: &str| DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc);
+        let berlin = chrono_tz::Europe::Berlin;
+
+        // 夏令时开始的夜里从 20:00 充到 08:00 只经过 11 小时
+        let (start, end) = (
+            at("2025-03-29T20:00:00+01:00"),
+            at("2025-03-30T08:00:00+02:00"),
+        );
+        assert_eq!(
+            prices
+                .calc_price_in(start, end, 10.0, 0.0, &berlin)
+                .unwrap(),
+            (55.0, 22.0)
+        );
+        // 夏令时结束的夜里经过 13 小时
+        let (start, end) = (
+            at("2025-10-25T20:00:00+02:00"),
+            at("2025-10-26T08:00:00+01:00"),
+        );
+        assert_eq!(
+            prices
+                .calc_price_in(start, end, 10.0, 0.0, &berlin)
+                .unwrap(),
+            (65.0, 26.0)
+        );
+
+        // 夏令时开始的一整天只有 23 小时，谷时少一小时
+        let (start, end) = (
+            at("2025-03-30T00:00:00+01:00"),
+            at("2025-03-31T00:00:00+02:00"),
+        );
+        assert_eq!(
+            prices
+                .calc_price_in(start, end, 10.0, 0.0, &berlin)
+                .unwrap(),
+            (175.0, 46.0)
+        );
+        let usages = prices
+            .calc_price_breakdown_in(start, end, 10.0, 0.0, &berlin)
+            .unwrap();
+        let kwh: Vec<_> = usages.iter().map(|u| (u.label.as_str(), u.kwh)).collect();
+        assert_eq!(kwh, [("valley", 110.0), ("peak", 120.0)]);
+        let breakdown = prices
+            .calc_price_itemized(start, end, 10.0, 0.0, &berlin)
+            .unwrap();
+        assert_eq!(breakdown.items[0].end, at("2025-03-30T08:00:00+02:00"));
+        assert_eq!(breakdown.total_cost, 221.0);
+
+        // 没有夏令时的时区每天都是 24 小时，与按本地时间计算的结果相同
+        let shanghai = chrono_tz::Asia::Shanghai;
+        let (start, end) = (
+            at("2025-03-29T20:00:00+08:00"),
+            at("2025-03-30T08:00:00+08:00"),
+        );
+        let naive = |t: DateTime<Utc>| t.with_timezone(&shanghai).naive_local();
+        assert_eq!(
+            prices
+                .calc_price_in(start, end, 10.0, 0.0, &shanghai)
+                .unwrap(),
+            (60.0, 24.0)
+        );
+        assert_eq!(
+            prices
+                .calc_price(naive(start), naive(end), 10.0, 0.0)
+                .unwrap(),
+            (60.0, 24.0)
+        );
+    }
+
+    #[test]
+    fn test_local_instant_in_skipped_hour() {
+        use super::*;
+        let berlin = chrono_tz::Europe::Berlin;
+        let local = |s: &str| NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S").unwrap();
+        let at = |s: &str| DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc);
+        // 跳过的本地时间取跳变后的第一个时刻
+        assert_eq!(
+            local_instant(&berlin, local("2025-03-30 02:30:15")),
+            at("2025-03-30T03:00:00+02:00")
+        );
+        // 重复的本地时间取第一次出现的时刻
+        assert_eq!(
+            local_instant(&berlin, local("2025-10-26 02:30:00")),
+            at("2025-10-26T02:30:00+02:00")
+        );
+    }
+
+    #[test]
+    fn test_unlabeled_breakdown_uses_time_range() {
+        use super::*;
+        let mut prices = Prices::new();
+        prices.add_period(
+            NaiveTime::from_hms_opt(8, 0, 0).unwrap(),
+            NaiveTime::from_hms_opt(20, 0, 0).unwrap(),
+            1.0,
+        );
+        prices.optimize().unwrap();
+        let start =
+            NaiveDateTime::parse_from_str("2023-10-01 07:00:00", "%Y-%m-%d %H:%M:%S").unwrap();
+        let end =
+            NaiveDateTime::parse_from_str("2023-10-01 09:00:00", "%Y-%m-%d %H:%M:%S").unwrap();
+        let usages = prices.calc_price_breakdown(start, end, 1.0, 0.0).unwrap();
+        let labels: Vec<&str> = usages.iter().map(|u| u.label.as_str()).collect();
+        assert_eq!(labels, vec!["00:00-08:00", "08:00-20:00"]);
+    }
+
+    #[test]
+    fn test_load_rejects_invalid_file() {
+        use super::*;
+        let dir = std::env::temp_dir().join(format!("taranis-prices-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let good = dir.join("good.json");
+        std::fs::write(&good, serde_json::to_string(&Prices::default()).unwrap()).unwrap();
+        let bad = dir.join("bad.json");
+        std::fs::write(&bad, "{ not json").unwrap();
+
+        assert!(Prices::from_path(good.to_str().unwrap()).is_ok());
+        assert!(Prices::from_path(bad.to_str().unwrap()).is_err());
+        assert!(Prices::from_path(dir.join("missing.json").to_str().unwrap()).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_reload_keeps_table_on_failure() {
+        use super::*;
+        let dir = std::env::temp_dir().join(format!("taranis-reload-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("prices.json");
+        let path = path.to_str().unwrap();
+        // 其他测试使用全局价格表，这里只换成内容相同的价格表
+        std::fs::write(path, serde_json::to_string(&Prices::default()).unwrap()).unwrap();
+        reload_prices(path).unwrap();
+        let before = PRICESS.read().unwrap().fingerprint().unwrap();
+
+        // 重叠时段价格不一致时保留原价格表
+        let mut invalid = Prices::new();
+        invalid.add_period(
+            NaiveTime::from_hms_opt(9, 0, 0).unwrap(),
+            NaiveTime::from_hms_opt(12, 0, 0).unwrap(),
+            1.0,
+        );
+        invalid.add_period(
+            NaiveTime::from_hms_opt(11, 0, 0).unwrap(),
+            NaiveTime::from_hms_opt(13, 0, 0).unwrap(),
+            2.0,
+        );
+        std::fs::write(path, serde_json::to_string(&invalid).unwrap()).unwrap();
+        let err = reload_current_prices().unwrap_err();
+        assert!(err.contains("different prices"), "{}", err);
+        std::fs::write(path, "{ not json").unwrap();
+        assert!(reload_current_prices().is_err());
+        assert_eq!(PRICESS.read().unwrap().fingerprint().unwrap(), before);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_fingerprint_check() {
+        use super::*;
+        let reference = Prices::default();
+        let mut altered = Prices::default();
+        altered.periods[2].price = 1.2;
+        let same = fingerprint_hex(&reference).unwrap();
+        let different = fingerprint_hex(&altered).unwrap();
+        assert_eq!(same.len(), 16);
+        let (a, b) = (Uuid::new_v4(), Uuid::new_v4());
+
+        // 没有参考价格表时以第一个充电桩为基准
+        let mut check = FingerprintCheck::default();
+        assert_eq!(check.check(a, &same), None);
+        assert_eq!(check.check(a, &same), None);
+        let warning = check.check(b, &different).unwrap();
+        assert!(warning.contains(&b.to_string()) && warning.contains(&a.to_string()));
+
+        let mut check = FingerprintCheck::with_reference(&altered).unwrap();
+        assert_eq!(check.check(b, &different), None);
+        assert!(check.check(a, &same).unwrap().contains("reference table"));
+    }
+
+    #[test]
+    fn test_diff_reports_altered_peak() {
+        use super::*;
+        let reference = Prices::default();
+        assert!(reference.diff(&Prices::default()).unwrap().is_empty());
+
+        let mut altered = Prices::default();
+        altered.periods[2].price = 1.2;
+        let diffs = reference.diff(&altered).unwrap();
+        assert_eq!(
+            diffs,
+            vec![PriceDiff::Price {
+                start: NaiveTime::from_hms_opt(10, 0, 0).unwrap(),
+                end: NaiveTime::from_hms_opt(15, 0, 0).unwrap(),
+                ours: 1.0,
+                theirs: 1.2,
+            }]
+        );
+        assert!((diffs[0].magnitude() - 0.2).abs() < 1e-9);
+        assert_ne!(
+            reference.fingerprint().unwrap(),
+            altered.fingerprint().unwrap()
+        );
+
+        // 缺失的时段和服务费差异
+        let mut partial = Prices::new();
+        partial.add_period(
+            NaiveTime::from_hms_opt(0, 0, 0).unwrap(),
+            NaiveTime::from_hms_opt(12, 0, 0).unwrap(),
+            1.0,
+        );
+        let diffs = partial.diff(&Prices::new()).unwrap();
+        assert_eq!(
+            diffs,
+            vec![PriceDiff::OnlyInOurs {
+                start: NaiveTime::from_hms_opt(0, 0, 0).unwrap(),
+                end: NaiveTime::from_hms_opt(12, 0, 0).unwrap(),
+            }]
+        );
+        let diffs = Prices::new().diff(&reference).unwrap();
+        assert_eq!(
+            diffs[0],
+            PriceDiff::ServiceFee {
+                ours: 0.0,
+                theirs: 0.8
+            }
+        );
+    }
+
+    #[test]
+    fn test_service_fee_models() {
+        use super::*;
+        let parse = |fee: &str| -> Prices {
+            format!(
+                r#"{{"periods": [{{"start": "00:00:00", "end": "00:00:00", "price": 1.0}}], "service_fee": {}}}"#,
+                fee
+            )
+            .parse()
+            .unwrap()
+        };
+        let date = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+        // 数字和只写 per_kwh 的对象等价，序列化时仍写成数字
+        let legacy = parse("0.8");
+        assert_eq!(legacy.service_fee_on(date), FeeModel::per_kwh(0.8));
+        assert_eq!(
+            parse(r#"{"per_kwh": 0.8}"#).service_fee_on(date),
+            FeeModel::per_kwh(0.8)
+        );
+        assert_eq!(serde_json::to_value(&legacy).unwrap()["service_fee"], 0.8);
+
+        let combined = parse(r#"{"per_session": 3.0, "per_minute_after_complete": 0.1}"#);
+        let fee = combined.service_fee_on(date);
+        assert_eq!((fee.per_kwh, fee.per_session), (0.0, 3.0));
+        assert_eq!(
+            serde_json::to_value(&combined).unwrap()["service_fee"],
+            serde_json::json!({"per_session": 3.0, "per_minute_after_complete": 0.1})
+        );
+        // 按次和占位服务费不计入时段费用
+        let start = Utc.with_ymd_and_hms(2024, 1, 1, 8, 0, 0).unwrap();
+        let end = start + chrono::Duration::hours(1);
+        assert_eq!(
+            combined
+                .calc_price_in(start, end, 10.0, 0.0, &chrono_tz::UTC)
+                .unwrap(),
+            (10.0, 0.0)
+        );
+        assert_eq!(
+            legacy
+                .calc_price_in(start, end, 10.0, 0.0, &chrono_tz::UTC)
+                .unwrap(),
+            (10.0, 8.0)
+        );
+        // 占位费按秒折算
+        assert_eq!(fee.idle_fee(chrono::Duration::seconds(90)), 0.15);
+        assert_eq!(fee.idle_fee(chrono::Duration::seconds(-60)), 0.0);
+
+        // 拼错的名称和负数服务费都是错误
+        assert!(
+            r#"{"periods": [], "service_fee": {"per_sesion": 3.0}}"#
+                .parse::<Prices>()
+                .is_err()
+        );
+        let mut negative = combined.clone();
+        negative.service_fee.per_session = -1.0;
+        assert_eq!(
+            negative.validate().errors,
+            [PriceError::InvalidServiceFee {
+                table: "service_fee.per_session".to_string(),
+                fee: -1.0
+            }]
+        );
+        assert_eq!(
+            legacy.diff(&combined).unwrap(),
+            [
+                PriceDiff::ServiceFee {
+                    ours: 0.8,
+                    theirs: 0.0
+                },
+                PriceDiff::FeeModel {
+                    item: "per_session".to_string(),
+                    ours: 0.0,
+                    theirs: 3.0
+                },
+                PriceDiff::FeeModel {
+                    item: "per_minute_after_complete".to_string(),
+                    ours: 0.0,
+                    theirs: 0.1
+                },
+            ]
+        );
+    }
 }
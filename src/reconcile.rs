@@ -0,0 +1,292 @@
+//! 充电会话对账
+//!
+//! 按充电桩 ID 和详单 ID 匹配充电桩记录的会话和服务器导出的会话，
+//! 报告度数或费用超过允许偏差的会话、只在一方出现的会话，以及会话总度数与电表读数是否一致。
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+/// 一次充电会话的记录
+/// 可以直接读取详单，`already_charged` 和 `total_cost` 分别作为度数和费用
+pub struct SessionRecord {
+    /// 充电详单 ID
+    pub id: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// 充电桩 ID
+    pub charge_id: Option<Uuid>,
+    #[serde(alias = "already_charged")]
+    /// 充电度数，单位为kWh
+    pub energy_kwh: f64,
+    #[serde(alias = "total_cost")]
+    /// 总费用
+    pub cost: f64,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+/// 会话的匹配键
+pub struct SessionKey {
+    /// 充电桩 ID
+    pub charge_id: Option<Uuid>,
+    /// 充电详单 ID
+    pub id: u32,
+}
+
+impl SessionRecord {
+    fn key(&self) -> SessionKey {
+        SessionKey {
+            charge_id: self.charge_id,
+            id: self.id,
+        }
+    }
+}
+
+impl fmt::Display for SessionKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.charge_id {
+            Some(charge_id) => write!(f, "{}/{}", charge_id, self.id),
+            None => write!(f, "-/{}", self.id),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+/// 不一致的字段
+pub enum Field {
+    /// 充电度数
+    Energy,
+    /// 总费用
+    Cost,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+/// 两边都有但数值不一致的会话
+pub struct Mismatch {
+    /// 会话
+    pub session: SessionKey,
+    /// 不一致的字段
+    pub field: Field,
+    /// 充电桩记录的值
+    pub archive: f64,
+    /// 服务器记录的值
+    pub server: f64,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+/// 会话总度数与电表读数的比较
+pub struct MeterCheck {
+    /// 充电桩记录的会话总度数
+    pub archive_kwh: f64,
+    /// 电表读数
+    pub meter_kwh: f64,
+    /// 是否在允许偏差内
+    pub ok: bool,
+}
+
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+/// 对账结果
+pub struct ReconcileReport {
+    /// 两边都有的会话数量
+    pub matched: usize,
+    /// 数值不一致的会话
+    pub mismatches: Vec<Mismatch>,
+    /// 只在充电桩记录中出现的会话
+    pub only_in_archive: Vec<SessionKey>,
+    /// 只在服务器记录中出现的会话
+    pub only_in_server: Vec<SessionKey>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// 电表读数比较，没有给出电表读数时为 `None`
+    pub meter: Option<MeterCheck>,
+}
+
+impl ReconcileReport {
+    /// 是否没有任何不一致
+    pub fn is_consistent(&self) -> bool {
+        self.mismatches.is_empty()
+            && self.only_in_archive.is_empty()
+            && self.only_in_server.is_empty()
+            && self.meter.as_ref().is_none_or(|meter| meter.ok)
+    }
+}
+
+impl fmt::Display for ReconcileReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "matched sessions: {}", self.matched)?;
+        if !self.mismatches.is_empty() {
+            writeln!(
+                f,
+                "{:<48} {:<8} {:>12} {:>12}",
+                "session", "field", "archive", "server"
+            )?;
+            for m in &self.mismatches {
+                let field = match m.field {
+                    Field::Energy => "energy",
+                    Field::Cost => "cost",
+                };
+                writeln!(
+                    f,
+                    "{:<48} {:<8} {:>12.2} {:>12.2}",
+                    m.session.to_string(),
+                    field,
+                    m.archive,
+                    m.server
+                )?;
+            }
+        }
+        for key in &self.only_in_archive {
+            writeln!(f, "only in archive: {}", key)?;
+        }
+        for key in &self.only_in_server {
+            writeln!(f, "only in server dump: {}", key)?;
+        }
+        if let Some(meter) = &self.meter {
+            writeln!(
+                f,
+                "meter: archive {:.2} kWh, meter {:.2} kWh ({})",
+                meter.archive_kwh,
+                meter.meter_kwh,
+                if meter.ok { "ok" } else { "mismatch" }
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// 读取充电桩记录，每行一个会话
+pub fn parse_archive(content: &str) -> Result<Vec<SessionRecord>, String> {
+    content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| serde_json::from_str(line).map_err(|e| format!("line {}: {}", i + 1, e)))
+        .collect()
+}
+
+/// 读取服务器导出的会话，格式为会话数组
+pub fn parse_server_dump(content: &str) -> Result<Vec<SessionRecord>, String> {
+    serde_json::from_str(content).map_err(|e| e.to_string())
+}
+
+/// 比较充电桩记录和服务器记录，`meter_kwh` 为电表读数
+/// 同一个会话在一方出现多次时以最后一次为准
+pub fn reconcile(
+    archive: &[SessionRecord],
+    server: &[SessionRecord],
+    meter_kwh: Option<f64>,
+    tolerance: f64,
+) -> ReconcileReport {
+    let ours: BTreeMap<SessionKey, &SessionRecord> = archive.iter().map(|r| (r.key(), r)).collect();
+    let theirs: BTreeMap<SessionKey, &SessionRecord> =
+        server.iter().map(|r| (r.key(), r)).collect();
+    let mut report = ReconcileReport::default();
+    for (key, ours) in &ours {
+        let Some(theirs) = theirs.get(key) else {
+            report.only_in_archive.push(*key);
+            continue;
+        };
+        report.matched += 1;
+        for (field, a, b) in [
+            (Field::Energy, ours.energy_kwh, theirs.energy_kwh),
+            (Field::Cost, ours.cost, theirs.cost),
+        ] {
+            if (a - b).abs() > tolerance {
+                report.mismatches.push(Mismatch {
+                    session: *key,
+                    field,
+                    archive: a,
+                    server: b,
+                });
+            }
+        }
+    }
+    report.only_in_server = theirs
+        .keys()
+        .filter(|key| !ours.contains_key(key))
+        .copied()
+        .collect();
+    report.meter = meter_kwh.map(|meter_kwh| {
+        let archive_kwh: f64 = ours.values().map(|r| r.energy_kwh).sum();
+        MeterCheck {
+            archive_kwh,
+            meter_kwh,
+            ok: (archive_kwh - meter_kwh).abs() <= tolerance,
+        }
+    });
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(id: u32, energy_kwh: f64, cost: f64) -> SessionRecord {
+        SessionRecord {
+            id,
+            charge_id: Some(Uuid::from_u128(1)),
+            energy_kwh,
+            cost,
+        }
+    }
+
+    #[test]
+    fn test_matching_sources() {
+        let archive = parse_archive(
+            &[record(1, 10.0, 12.5), record(2, 5.0, 6.0)]
+                .iter()
+                .map(|r| serde_json::to_string(r).unwrap())
+                .collect::<Vec<_>>()
+                .join("\n"),
+        )
+        .unwrap();
+        // 服务器导出可以直接使用详单字段名
+        let dump = serde_json::json!([
+            {"id": 2, "charge_id": Uuid::from_u128(1), "already_charged": 5.0, "total_cost": 6.004},
+            {"id": 1, "charge_id": Uuid::from_u128(1), "energy_kwh": 10.0, "cost": 12.5},
+        ]);
+        let server = parse_server_dump(&dump.to_string()).unwrap();
+        let report = reconcile(&archive, &server, Some(15.0), 0.01);
+        assert!(report.is_consistent(), "{}", report);
+        assert_eq!(report.matched, 2);
+        assert!(report.meter.unwrap().ok);
+    }
+
+    #[test]
+    fn test_mismatching_sources() {
+        let archive = vec![
+            record(1, 10.0, 12.5),
+            record(2, 5.0, 6.0),
+            record(3, 1.0, 1.0),
+        ];
+        let mut other_pile = record(4, 2.0, 2.0);
+        other_pile.charge_id = Some(Uuid::from_u128(2));
+        let server = vec![record(1, 10.0, 13.0), record(2, 5.0, 6.0), other_pile];
+        let report = reconcile(&archive, &server, Some(20.0), 0.01);
+        assert!(!report.is_consistent());
+        assert_eq!(report.matched, 2);
+        assert_eq!(
+            report.mismatches,
+            vec![Mismatch {
+                session: record(1, 0.0, 0.0).key(),
+                field: Field::Cost,
+                archive: 12.5,
+                server: 13.0,
+            }]
+        );
+        assert_eq!(report.only_in_archive, vec![record(3, 0.0, 0.0).key()]);
+        assert_eq!(report.only_in_server.len(), 1);
+        assert_eq!(report.only_in_server[0].charge_id, Some(Uuid::from_u128(2)));
+        let meter = report.meter.clone().unwrap();
+        assert_eq!(meter.archive_kwh, 16.0);
+        assert!(!meter.ok);
+
+        let text = report.to_string();
+        assert!(text.contains("only in archive"));
+        assert!(text.contains("meter: archive 16.00 kWh, meter 20.00 kWh (mismatch)"));
+        let value = serde_json::to_value(&report).unwrap();
+        assert_eq!(value["mismatches"][0]["field"], "cost");
+    }
+}
@@ -0,0 +1,301 @@
+//! 配置热重载
+//!
+//! 重新读取配置文件后与当前生效的配置比较，只应用允许在运行时修改的字段：
+//! 价格表路径（重新加载价格表）、WebSocket 地址（迁移连接）、加速倍数、更新间隔、队列大小，
+//! 以及空闲时的充电功率。
+//! 修改不可变字段会被拒绝，并在日志中列出被忽略的字段。
+//! 配置了多个充电桩时不能修改队列大小和充电功率。
+
+use crate::conf::Conf;
+
+#[derive(Debug, Default, PartialEq)]
+/// 配置重载计划
+pub struct ReloadPlan {
+    /// 新的价格表路径
+    pub price_path: Option<String>,
+    /// 新的 WebSocket 地址列表，迁移到其中的第一个地址
+    pub websocket_urls: Option<Vec<String>>,
+    /// 新的加速倍数
+    pub speed: Option<f64>,
+    /// 新的更新间隔，单位为毫秒
+    pub update_interval: Option<u64>,
+    /// 新的队列大小
+    pub queue_size: Option<u32>,
+    /// 新的充电功率，单位为kW，只在没有进行中的充电会话时修改
+    pub power: Option<f64>,
+    /// 被拒绝的字段及原因
+    pub ignored: Vec<String>,
+}
+
+impl ReloadPlan {
+    /// 是否没有需要应用的修改
+    pub fn is_empty(&self) -> bool {
+        self.price_path.is_none()
+            && self.websocket_urls.is_none()
+            && self.speed.is_none()
+            && self.update_interval.is_none()
+            && self.queue_size.is_none()
+            && self.power.is_none()
+    }
+}
+
+/// 比较当前生效的配置和新配置，生成重载计划
+/// `session_active` 表示当前是否有正在进行的充电会话
+pub fn plan(current: &Conf, new: &Conf, session_active: bool) -> ReloadPlan {
+    let mut plan = ReloadPlan::default();
+    if new.price.path != current.price.path {
+        plan.price_path = Some(new.price.path.clone());
+    }
+    if new.websocket.endpoints() != current.websocket.endpoints() {
+        if current.websocket.enabled {
+            plan.websocket_urls = Some(new.websocket.endpoints());
+        } else {
+            plan.ignored
+                .push("websocket.url (not connected in standalone mode)".to_string());
+        }
+    }
+    if new.websocket.enabled != current.websocket.enabled {
+        plan.ignored
+            .push("websocket.enabled (restart required)".to_string());
+    }
+    if new.standalone != current.standalone {
+        plan.ignored
+            .push("standalone (restart required)".to_string());
+    }
+    if new.time.speed != current.time.speed {
+        plan.speed = Some(new.time.speed);
+    }
+    if new.time.update_interval != current.time.update_interval {
+        plan.update_interval = Some(new.time.update_interval);
+    }
+    let multi_pile = current.charge.is_multi_pile();
+    if new.charge.piles != current.charge.piles {
+        plan.ignored
+            .push("charge.piles (restart required)".to_string());
+    }
+    if new.charge.update_interval != current.charge.update_interval {
+        plan.ignored
+            .push("charge.update_interval (restart required)".to_string());
+    }
+    let time_sync = |conf: &Conf| (conf.time.server_sync, conf.time.max_sync_offset_s);
+    if time_sync(new) != time_sync(current) {
+        plan.ignored
+            .push("time.server_sync (restart required)".to_string());
+    }
+    let skip_idle = |conf: &Conf| (conf.time.skip_idle, conf.time.skip_idle_after_ms);
+    if skip_idle(new) != skip_idle(current) {
+        plan.ignored
+            .push("time.skip_idle (restart required)".to_string());
+    }
+    if new.audit != current.audit {
+        plan.ignored.push("audit (restart required)".to_string());
+    }
+    if new.websocket.failover != current.websocket.failover {
+        plan.ignored
+            .push("websocket.failover (restart required)".to_string());
+    }
+    if new.trace != current.trace {
+        plan.ignored.push("trace (restart required)".to_string());
+    }
+    if new.metrics != current.metrics {
+        plan.ignored.push("metrics (restart required)".to_string());
+    }
+    let heartbeat = |conf: &Conf| {
+        (
+            conf.websocket.heartbeat_interval,
+            conf.websocket.heartbeat_mode,
+            conf.websocket.heartbeat_max_missed,
+        )
+    };
+    if heartbeat(new) != heartbeat(current) {
+        plan.ignored
+            .push("websocket.heartbeat (restart required)".to_string());
+    }
+    let handshake = |conf: &Conf| {
+        (
+            conf.websocket.register_ack_timeout_s,
+            conf.websocket.abort_on_version_mismatch,
+        )
+    };
+    if handshake(new) != handshake(current) {
+        plan.ignored
+            .push("websocket.register_ack (restart required)".to_string());
+    }
+    if new.websocket.encoding != current.websocket.encoding {
+        plan.ignored
+            .push("websocket.encoding (restart required)".to_string());
+    }
+    if new.websocket.min_update_gap_ms != current.websocket.min_update_gap_ms {
+        plan.ignored
+            .push("websocket.min_update_gap_ms (restart required)".to_string());
+    }
+    if new.websocket.max_message_size != current.websocket.max_message_size {
+        plan.ignored
+            .push("websocket.max_message_size (restart required)".to_string());
+    }
+    if new.log != current.log {
+        plan.ignored.push("log (restart required)".to_string());
+    }
+    if new.charge.connectors != current.charge.connectors {
+        plan.ignored
+            .push("charge.connectors (restart required)".to_string());
+    }
+    if new.charge.pending_buffer_size != current.charge.pending_buffer_size {
+        plan.ignored
+            .push("charge.pending_buffer_size (restart required)".to_string());
+    }
+    if new.charge.overflow_policy != current.charge.overflow_policy
+        || new.charge.overflow_cap != current.charge.overflow_cap
+    {
+        plan.ignored
+            .push("charge.overflow_policy (restart required)".to_string());
+    }
+    if new.charge.power_curve != current.charge.power_curve {
+        plan.ignored
+            .push("charge.power_curve (restart required)".to_string());
+    }
+    if new.charge.state_path != current.charge.state_path {
+        plan.ignored
+            .push("charge.state_path (restart required)".to_string());
+    }
+    if new.charge.size != current.charge.size {
+        if multi_pile {
+            plan.ignored
+                .push("charge.size (multiple piles configured)".to_string());
+        } else {
+            plan.queue_size = Some(new.charge.size);
+        }
+    }
+    if new.charge.charge_type != current.charge.charge_type {
+        plan.ignored
+            .push("charge.charge_type (cannot change at runtime)".to_string());
+    }
+    if new.charge.power != current.charge.power {
+        if multi_pile {
+            plan.ignored
+                .push("charge.power (multiple piles configured)".to_string());
+        } else if session_active {
+            plan.ignored
+                .push("charge.power (a charging session is active)".to_string());
+        } else {
+            plan.power = Some(new.charge.power);
+        }
+    }
+    plan
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conf::ChargeType;
+
+    #[test]
+    fn test_plan_migrations() {
+        let current = Conf::default();
+        let mut new = current.clone();
+        assert!(plan(&current, &new, false).is_empty());
+
+        new.price.path = "prices-summer.json".to_string();
+        new.websocket.url = "ws://backup:8080/ws".to_string();
+        let plan = plan(&current, &new, true);
+        assert_eq!(plan.price_path.as_deref(), Some("prices-summer.json"));
+        assert_eq!(
+            plan.websocket_urls.as_deref(),
+            Some(&["ws://backup:8080/ws".to_string()][..])
+        );
+        assert!(plan.ignored.is_empty());
+
+        // 地址列表代替单个地址
+        let mut failover = current.clone();
+        failover.websocket.urls = vec![
+            "ws://primary:8080/ws".to_string(),
+            "ws://standby:8080/ws".to_string(),
+        ];
+        let plan = super::plan(&current, &failover, false);
+        assert_eq!(plan.websocket_urls, Some(failover.websocket.urls.clone()));
+        // 地址列表已经生效时单个地址的修改不影响连接
+        let mut new = failover.clone();
+        new.websocket.url = "ws://backup:8080/ws".to_string();
+        assert!(super::plan(&failover, &new, false).is_empty());
+
+        // 独立运行时没有连接可以迁移
+        let mut standalone = Conf::default();
+        standalone.websocket.enabled = false;
+        let mut new = standalone.clone();
+        new.websocket.url = "ws://backup:8080/ws".to_string();
+        let plan = super::plan(&standalone, &new, false);
+        assert!(plan.websocket_urls.is_none());
+        assert_eq!(
+            plan.ignored,
+            ["websocket.url (not connected in standalone mode)"]
+        );
+    }
+
+    #[test]
+    fn test_plan_rejects_immutable_fields() {
+        let current = Conf::default();
+        let mut new = current.clone();
+        new.charge.charge_type = ChargeType::Slow;
+        new.charge.power = 7.0;
+        new.time.speed = 10.0;
+        let plan = plan(&current, &new, true);
+        assert_eq!(plan.speed, Some(10.0));
+        assert_eq!(plan.ignored.len(), 2);
+        assert!(plan.ignored[0].starts_with("charge.charge_type"));
+        assert!(plan.ignored[1].contains("session is active"));
+        assert!(plan.power.is_none());
+
+        // 空闲时允许修改功率
+        new.charge.charge_type = current.charge.charge_type;
+        let plan = super::plan(&current, &new, false);
+        assert_eq!(plan.power, Some(7.0));
+        assert!(plan.ignored.is_empty());
+
+        // 日志配置在启动时生效
+        new.log.rotation = crate::conf::LogRotation::Hourly;
+        let plan = super::plan(&current, &new, false);
+        assert_eq!(plan.ignored, ["log (restart required)"]);
+        new.log = current.log.clone();
+        new.time.max_sync_offset_s = 60;
+        let plan = super::plan(&current, &new, false);
+        assert_eq!(plan.ignored, ["time.server_sync (restart required)"]);
+        new.time = current.time.clone();
+        new.time.skip_idle = true;
+        let plan = super::plan(&current, &new, false);
+        assert_eq!(plan.ignored, ["time.skip_idle (restart required)"]);
+        new.time = current.time.clone();
+        new.charge.overflow_policy = crate::conf::OverflowPolicy::Grow;
+        let plan = super::plan(&current, &new, false);
+        assert_eq!(plan.ignored, ["charge.overflow_policy (restart required)"]);
+        new.charge.overflow_policy = current.charge.overflow_policy;
+        new.charge.power_curve =
+            crate::curve::PowerCurve::new(vec![(0.0, 1.0), (0.8, 1.0), (1.0, 0.3)]).unwrap();
+        let plan = super::plan(&current, &new, false);
+        assert_eq!(plan.ignored, ["charge.power_curve (restart required)"]);
+        new.charge.power_curve = current.charge.power_curve.clone();
+        new.websocket.min_update_gap_ms = 500;
+        let plan = super::plan(&current, &new, false);
+        assert_eq!(
+            plan.ignored,
+            ["websocket.min_update_gap_ms (restart required)"]
+        );
+        new.websocket = current.websocket.clone();
+        new.websocket.max_message_size = 1 << 20;
+        let plan = super::plan(&current, &new, false);
+        assert_eq!(
+            plan.ignored,
+            ["websocket.max_message_size (restart required)"]
+        );
+        new.websocket = current.websocket.clone();
+
+        // 多个充电桩时不修改队列大小和功率
+        let mut current = Conf::default();
+        current.charge.piles = current.charge.pile_specs();
+        let mut new = current.clone();
+        new.charge.power = 7.0;
+        new.charge.size = 4;
+        let plan = super::plan(&current, &new, false);
+        assert!(plan.is_empty());
+        assert_eq!(plan.ignored.len(), 2);
+    }
+}
@@ -0,0 +1,311 @@
+//! 运行时可修改的配置
+
+use std::sync::LazyLock;
+use std::sync::atomic::{AtomicI64, AtomicU32, AtomicU64, Ordering};
+use std::time::Duration;
+
+use tokio::sync::watch;
+
+use crate::conf::{CONF, Conf};
+use crate::time::MockClock;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// 运行时配置快照
+pub struct RuntimeValues {
+    /// 加速倍数
+    pub speed: f64,
+    /// 更新间隔，单位为毫秒
+    pub update_interval: u64,
+    /// 队列大小
+    pub queue_size: u32,
+    /// 虚拟时钟是否暂停
+    pub paused: bool,
+    /// 按服务器时间同步时累计调整的虚拟时间，单位为毫秒
+    pub clock_offset_ms: i64,
+}
+
+#[derive(Debug)]
+/// 运行时配置
+/// 启动时的配置保存在不可变的 `CONF` 中，这里只保存运行时允许修改的值，
+/// 修改后通过 watch 通道通知计时器等组件
+pub struct RuntimeConf {
+    /// 加速倍数，按位保存的 `f64`
+    speed: AtomicU64,
+    /// 更新间隔，单位为毫秒
+    update_interval: AtomicU64,
+    /// 队列大小
+    queue_size: AtomicU32,
+    /// 虚拟时钟
+    clock: MockClock,
+    /// 按服务器时间同步时累计调整的虚拟时间，单位为毫秒
+    clock_offset_ms: AtomicI64,
+    /// 变更通知
+    notify: watch::Sender<RuntimeValues>,
+}
+
+impl RuntimeConf {
+    /// 从启动配置创建运行时配置
+    pub fn new(conf: &Conf) -> Self {
+        let values = RuntimeValues {
+            speed: conf.time.speed,
+            update_interval: conf.time.update_interval,
+            queue_size: conf.charge.size,
+            paused: false,
+            clock_offset_ms: 0,
+        };
+        RuntimeConf {
+            speed: AtomicU64::new(values.speed.to_bits()),
+            update_interval: AtomicU64::new(values.update_interval),
+            queue_size: AtomicU32::new(values.queue_size),
+            clock: MockClock::new(conf.time.start_time, values.speed),
+            clock_offset_ms: AtomicI64::new(0),
+            notify: watch::Sender::new(values),
+        }
+    }
+
+    /// 加速倍数
+    pub fn speed(&self) -> f64 {
+        f64::from_bits(self.speed.load(Ordering::Acquire))
+    }
+
+    /// 虚拟时长对应的真实时长
+    pub fn real_duration(&self, virtual_duration: Duration) -> Duration {
+        virtual_duration.div_f64(self.speed())
+    }
+
+    /// 更新间隔，单位为毫秒
+    pub fn update_interval(&self) -> u64 {
+        self.update_interval.load(Ordering::Acquire)
+    }
+
+    /// 更新间隔
+    pub fn update_interval_duration(&self) -> Duration {
+        Duration::from_millis(self.update_interval())
+    }
+
+    /// 队列大小
+    pub fn queue_size(&self) -> u32 {
+        self.queue_size.load(Ordering::Acquire)
+    }
+
+    /// 虚拟时钟
+    pub fn clock(&self) -> &MockClock {
+        &self.clock
+    }
+
+    /// 当前配置快照
+    pub fn values(&self) -> RuntimeValues {
+        RuntimeValues {
+            speed: self.speed(),
+            update_interval: self.update_interval(),
+            queue_size: self.queue_size(),
+            paused: self.clock.is_paused(),
+            clock_offset_ms: self.clock_offset_ms.load(Ordering::Acquire),
+        }
+    }
+
+    /// 订阅运行时配置变更
+    pub fn subscribe(&self) -> watch::Receiver<RuntimeValues> {
+        self.notify.subscribe()
+    }
+
+    /// 修改加速倍数，虚拟时钟会以当前时刻为锚点重新计算
+    pub fn set_speed(&self, speed: f64) -> Result<(), String> {
+        if !(speed.is_finite() && speed > 0.0) {
+            tracing::warn!("拒绝将时间加速比修改为 {}", speed);
+            return Err("speed must be a positive number".to_string());
+        }
+        let old = f64::from_bits(self.speed.swap(speed.to_bits(), Ordering::AcqRel));
+        let now = self.clock.rebase(speed);
+        tracing::info!(virtual_time = %now, "时间加速比修改: {} -> {}", old, speed);
+        self.publish();
+        Ok(())
+    }
+
+    /// 修改更新间隔，单位为毫秒
+    pub fn set_update_interval(&self, update_interval: u64) -> Result<(), String> {
+        if update_interval == 0 {
+            tracing::warn!("拒绝将时间更新间隔修改为 0");
+            return Err("update interval must be greater than 0".to_string());
+        }
+        if update_interval < 100 {
+            tracing::warn!(
+                "时间更新间隔过短: {} 毫秒，可能会导致性能问题",
+                update_interval
+            );
+        }
+        let old = self.update_interval.swap(update_interval, Ordering::AcqRel);
+        tracing::info!("时间更新间隔修改: {} -> {} 毫秒", old, update_interval);
+        self.publish();
+        Ok(())
+    }
+
+    /// 修改队列大小
+    pub fn set_queue_size(&self, queue_size: u32) -> Result<(), String> {
+        if queue_size == 0 {
+            tracing::warn!("拒绝将队列大小修改为 0");
+            return Err("queue size must be greater than 0".to_string());
+        }
+        let old = self.queue_size.swap(queue_size, Ordering::AcqRel);
+        tracing::info!("队列大小修改: {} -> {}", old, queue_size);
+        self.publish();
+        Ok(())
+    }
+
+    /// 把虚拟时钟调整到服务器的虚拟时间，返回调整的偏移量
+    /// `max_offset_s` 不为 0 时拒绝偏移量绝对值超过该秒数的调整
+    pub fn sync_clock(
+        &self,
+        server_time: chrono::DateTime<chrono::Utc>,
+        max_offset_s: u64,
+    ) -> Result<chrono::Duration, String> {
+        let offset = server_time - self.clock.now();
+        if max_offset_s > 0 && offset.abs() > chrono::Duration::seconds(max_offset_s as i64) {
+            tracing::warn!(
+                "拒绝按服务器时间 {} 调整虚拟时钟，偏移 {} 毫秒超过上限 {} 秒",
+                server_time,
+                offset.num_milliseconds(),
+                max_offset_s
+            );
+            return Err(format!(
+                "clock offset of {} ms exceeds {} s",
+                offset.num_milliseconds(),
+                max_offset_s
+            ));
+        }
+        let offset = self.clock.jump_to(server_time);
+        self.clock_offset_ms
+            .fetch_add(offset.num_milliseconds(), Ordering::AcqRel);
+        tracing::info!(
+            virtual_time = %server_time,
+            "虚拟时钟已按服务器时间调整 {} 毫秒",
+            offset.num_milliseconds()
+        );
+        self.publish();
+        Ok(offset)
+    }
+
+    /// 跳过空闲时间，把虚拟时钟向前推进到 `until`，返回跳过的时长
+    /// 不计入 `clock_offset_ms`，调用方需要保证此时没有需要平移时间的详单；`until` 不晚于当前虚拟时间时不调整
+    pub fn skip_to(&self, until: chrono::DateTime<chrono::Utc>) -> Option<chrono::Duration> {
+        let skipped = self.clock.advance_to(until)?;
+        tracing::info!(
+            virtual_time = %until,
+            "虚拟时钟跳过空闲时间 {} 秒",
+            skipped.num_seconds()
+        );
+        self.publish();
+        Some(skipped)
+    }
+
+    /// 暂停或继续虚拟时钟，返回状态是否变化
+    pub fn set_paused(&self, paused: bool) -> bool {
+        let changed = if paused {
+            self.clock.pause()
+        } else {
+            self.clock.resume()
+        };
+        if changed {
+            let now = self.clock.now();
+            if paused {
+                tracing::info!(virtual_time = %now, "虚拟时钟已暂停");
+            } else {
+                tracing::info!(virtual_time = %now, "虚拟时钟已继续");
+            }
+            self.publish();
+        }
+        changed
+    }
+
+    /// 通知订阅者
+    fn publish(&self) {
+        self.notify.send_replace(self.values());
+    }
+}
+
+/// 全局运行时配置实例
+pub static RUNTIME: LazyLock<RuntimeConf> = LazyLock::new(|| RuntimeConf::new(&CONF));
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::time::{Instant, interval_at};
+
+    #[test]
+    fn test_setters_validate() {
+        let runtime = RuntimeConf::new(&Conf::default());
+        assert!(runtime.set_speed(0.0).is_err());
+        assert!(runtime.set_speed(f64::NAN).is_err());
+        assert!(runtime.set_update_interval(0).is_err());
+        assert!(runtime.set_queue_size(0).is_err());
+        assert_eq!(runtime.values().speed, 1.0);
+        runtime.set_queue_size(5).unwrap();
+        assert_eq!(runtime.queue_size(), 5);
+    }
+
+    #[test]
+    fn test_sync_clock_limits_offset() {
+        let runtime = RuntimeConf::new(&Conf::default());
+        let mut rx = runtime.subscribe();
+        let ahead = runtime.clock().now() + chrono::Duration::hours(1);
+        // 超过上限时不调整
+        assert!(runtime.sync_clock(ahead, 60).is_err());
+        assert!(!rx.has_changed().unwrap());
+        assert!(runtime.clock().now() < ahead);
+
+        let offset = runtime.sync_clock(ahead, 0).unwrap();
+        assert!(offset > chrono::Duration::minutes(59));
+        assert!(runtime.clock().now() >= ahead);
+        assert!(rx.has_changed().unwrap());
+        assert_eq!(
+            rx.borrow_and_update().clock_offset_ms,
+            offset.num_milliseconds()
+        );
+    }
+
+    #[test]
+    fn test_skip_to_only_moves_forward() {
+        let runtime = RuntimeConf::new(&Conf::default());
+        let mut rx = runtime.subscribe();
+        assert!(runtime.skip_to(runtime.clock().now()).is_none());
+        assert!(!rx.has_changed().unwrap());
+
+        let until = runtime.clock().now() + chrono::Duration::hours(3);
+        let skipped = runtime.skip_to(until).unwrap();
+        assert!(skipped > chrono::Duration::minutes(179));
+        assert!(runtime.clock().now() >= until);
+        // 跳过空闲时间不是时钟校准，详单不需要平移
+        assert!(rx.has_changed().unwrap());
+        assert_eq!(rx.borrow_and_update().clock_offset_ms, 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_runtime_change_adjusts_clock_and_ticker() {
+        let runtime = RuntimeConf::new(&Conf::default());
+        let mut rx = runtime.subscribe();
+
+        // 修改加速倍数后虚拟时钟按新倍数流逝
+        runtime.set_speed(1000.0).unwrap();
+        assert!(rx.has_changed().unwrap());
+        assert_eq!(rx.borrow_and_update().speed, 1000.0);
+        let before = runtime.clock().now();
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        assert!(runtime.clock().now() - before >= chrono::Duration::seconds(20));
+
+        // 计时器按更新间隔触发，收到变更通知后重新设置
+        let period = runtime.update_interval_duration();
+        let mut ticker = interval_at(Instant::now() + period, period);
+        let start = Instant::now();
+        ticker.tick().await;
+        assert_eq!(start.elapsed(), std::time::Duration::from_millis(5000));
+
+        runtime.set_update_interval(1000).unwrap();
+        assert!(rx.has_changed().unwrap());
+        let period = std::time::Duration::from_millis(rx.borrow_and_update().update_interval);
+        ticker = interval_at(Instant::now() + period, period);
+        let start = Instant::now();
+        ticker.tick().await;
+        ticker.tick().await;
+        assert_eq!(start.elapsed(), std::time::Duration::from_millis(2000));
+    }
+}
@@ -0,0 +1,610 @@
+//! 测试服务器的场景脚本
+//!
+//! 场景由按顺序执行的步骤组成，每一步发送一条消息（`send`）、发送原始文本帧（`raw`）或等待充电桩发送的消息（`expect`）。
+//! 发送步骤的 `at` 为相对于充电桩注册的时间，等待步骤在 `within` 内没有收到匹配的消息时场景失败。
+//! 场景文件可以是 TOML 或 JSON（按 `.json` 扩展名区分），格式见 README。
+//! 测试服务器和独立运行模式都通过 [`run_steps`] 执行场景，执行过程通过 `report` 回调输出。
+//! 等待下一个定时步骤时收到充电桩的 `idle_skip_request`，会同意跳过到该步骤对应的虚拟时间并立即执行该步骤。
+
+use std::collections::VecDeque;
+use std::path::Path;
+
+use futures_util::{SinkExt, StreamExt};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use tokio::time::{Duration, Instant, timeout_at};
+use tokio_tungstenite::tungstenite::{Error as WsError, Message};
+
+use crate::detail::ChargingDetail;
+use crate::message::{IdleSkipGrantData, IdleSkipRequestData, MSG, MessageType, MsgAckData};
+use crate::outbox;
+
+/// 等待步骤默认的超时时间
+pub const DEFAULT_WITHIN: Duration = Duration::from_secs(30);
+
+/// 解析时间长度，支持 `500ms`、`3s`、`2m`、`1h` 以及不带单位的秒数
+pub fn parse_duration(text: &str) -> Result<Duration, String> {
+    let text = text.trim();
+    let split = text
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let value: f64 = number
+        .parse()
+        .map_err(|_| format!("invalid duration: {:?}", text))?;
+    let scale = match unit.trim() {
+        "ms" => 0.001,
+        "" | "s" => 1.0,
+        "m" => 60.0,
+        "h" => 3600.0,
+        _ => return Err(format!("invalid duration unit in {:?}", text)),
+    };
+    Duration::try_from_secs_f64(value * scale)
+        .map_err(|e| format!("invalid duration {:?}: {}", text, e))
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+/// 配置文件中的时间长度，可以是秒数或带单位的字符串
+enum DurationConf {
+    Seconds(f64),
+    Text(String),
+}
+
+impl DurationConf {
+    fn parse(&self) -> Result<Duration, String> {
+        match self {
+            DurationConf::Seconds(secs) => Duration::try_from_secs_f64(*secs)
+                .map_err(|e| format!("invalid duration {}: {}", secs, e)),
+            DurationConf::Text(text) => parse_duration(text),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+/// 场景文件的内容
+struct ScenarioConf {
+    #[serde(default)]
+    /// 场景名称
+    name: Option<String>,
+    #[serde(default)]
+    /// 整个场景的超时时间，从开始监听算起，包括等待充电桩连接的时间
+    timeout: Option<DurationConf>,
+    #[serde(default)]
+    /// 按顺序执行的步骤
+    steps: Vec<StepConf>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+/// 场景文件中的一个步骤，`send`、`raw`、`expect` 必须且只能设置一个
+struct StepConf {
+    #[serde(default)]
+    /// 执行的时间，相对于充电桩注册
+    at: Option<DurationConf>,
+    #[serde(default)]
+    /// 发送的消息类型
+    send: Option<MessageType>,
+    #[serde(default)]
+    /// 原样发送的文本帧，用于测试格式错误的消息
+    raw: Option<String>,
+    #[serde(default)]
+    /// 等待的消息类型
+    expect: Option<MessageType>,
+    #[serde(default)]
+    /// 发送的消息数据，或等待的消息需要包含的字段
+    data: Option<Value>,
+    #[serde(default)]
+    /// 发送的次数
+    repeat: Option<u32>,
+    #[serde(default)]
+    /// 等待的超时时间
+    within: Option<DurationConf>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// 步骤的动作
+pub enum Action {
+    /// 发送 `repeat` 条消息
+    Send {
+        type_: MessageType,
+        data: Option<Value>,
+        repeat: u32,
+    },
+    /// 原样发送文本帧
+    Raw(String),
+    /// 等待充电桩发送匹配的消息
+    Expect(Expectation),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// 场景中的一个步骤
+pub struct Step {
+    /// 执行的时间，相对于充电桩注册，不设置时紧接上一步执行
+    pub at: Option<Duration>,
+    /// 动作
+    pub action: Action,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// 等待的消息
+pub struct Expectation {
+    /// 消息类型
+    pub type_: MessageType,
+    /// 消息数据需要包含的字段，数值按浮点数比较
+    pub data: Option<Value>,
+    /// 超时时间，从上一步结束算起
+    pub within: Duration,
+}
+
+impl Expectation {
+    /// 消息是否匹配
+    pub fn matches(&self, msg: &MSG) -> bool {
+        if msg.type_ != self.type_ {
+            return false;
+        }
+        let Some(expected) = &self.data else {
+            return true;
+        };
+        contains(&msg.data, expected)
+    }
+}
+
+impl std::fmt::Display for Expectation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self.type_)?;
+        if let Some(data) = &self.data {
+            write!(f, " {}", data)?;
+        }
+        write!(f, " within {:?}", self.within)
+    }
+}
+
+/// `actual` 是否包含 `expected` 中的所有字段
+fn contains(actual: &Value, expected: &Value) -> bool {
+    match (actual, expected) {
+        (Value::Object(actual), Value::Object(expected)) => expected.iter().all(|(key, value)| {
+            actual
+                .get(key)
+                .is_some_and(|actual| contains(actual, value))
+        }),
+        (Value::Number(actual), Value::Number(expected)) => actual.as_f64() == expected.as_f64(),
+        _ => actual == expected,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// 测试服务器执行的场景
+pub struct Scenario {
+    /// 场景名称
+    pub name: String,
+    /// 整个场景的超时时间
+    pub timeout: Option<Duration>,
+    /// 按顺序执行的步骤
+    pub steps: Vec<Step>,
+}
+
+impl Scenario {
+    /// 读取场景文件，`.json` 文件按 JSON 解析，其他按 TOML 解析
+    pub fn load(path: impl AsRef<Path>) -> Result<Scenario, String> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("failed to read scenario {}: {}", path.display(), e))?;
+        let conf: ScenarioConf = if path.extension().is_some_and(|ext| ext == "json") {
+            serde_json::from_str(&content).map_err(|e| e.to_string())
+        } else {
+            toml::from_str(&content).map_err(|e: toml::de::Error| e.to_string())
+        }
+        .map_err(|e| format!("invalid scenario {}: {}", path.display(), e))?;
+        let name = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        Scenario::from_conf(conf, name)
+            .map_err(|e| format!("invalid scenario {}: {}", path.display(), e))
+    }
+
+    /// 解析 TOML 格式的场景
+    pub fn from_toml(content: &str) -> Result<Scenario, String> {
+        let conf: ScenarioConf =
+            toml::from_str(content).map_err(|e: toml::de::Error| e.to_string())?;
+        Scenario::from_conf(conf, String::new())
+    }
+
+    fn from_conf(conf: ScenarioConf, default_name: String) -> Result<Scenario, String> {
+        let steps = conf
+            .steps
+            .into_iter()
+            .enumerate()
+            .map(|(index, step)| {
+                step.build()
+                    .map_err(|e| format!("step {}: {}", index + 1, e))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut last_at = Duration::ZERO;
+        for (index, step) in steps.iter().enumerate() {
+            if let Some(at) = step.at {
+                if at < last_at {
+                    return Err(format!(
+                        "step {}: at is earlier than a previous step",
+                        index + 1
+                    ));
+                }
+                last_at = at;
+            }
+        }
+        Ok(Scenario {
+            name: conf.name.unwrap_or(default_name),
+            timeout: conf.timeout.map(|timeout| timeout.parse()).transpose()?,
+            steps,
+        })
+    }
+}
+
+impl StepConf {
+    fn build(self) -> Result<Step, String> {
+        let at = self.at.map(|at| at.parse()).transpose()?;
+        let action = match (self.send, self.raw, self.expect) {
+            (Some(type_), None, None) => {
+                if self.within.is_some() {
+                    return Err("within is only valid for expect".to_string());
+                }
+                let repeat = self.repeat.unwrap_or(1);
+                if repeat == 0 {
+                    return Err("repeat must be at least 1".to_string());
+                }
+                if repeat > 1
+                    && self
+                        .data
+                        .as_ref()
+                        .is_some_and(|data| data.get("id").is_some())
+                {
+                    return Err("repeat cannot be used with an explicit id".to_string());
+                }
+                Action::Send {
+                    type_,
+                    data: self.data,
+                    repeat,
+                }
+            }
+            (None, Some(raw), None) => {
+                if self.data.is_some() || self.repeat.is_some() || self.within.is_some() {
+                    return Err("raw cannot be combined with data, repeat or within".to_string());
+                }
+                Action::Raw(raw)
+            }
+            (None, None, Some(type_)) => {
+                if self.repeat.is_some() {
+                    return Err("repeat is only valid for send".to_string());
+                }
+                Action::Expect(Expectation {
+                    type_,
+                    data: self.data,
+                    within: match self.within {
+                        Some(within) => within.parse()?,
+                        None => DEFAULT_WITHIN,
+                    },
+                })
+            }
+            _ => return Err("exactly one of send, raw or expect is required".to_string()),
+        };
+        Ok(Step { at, action })
+    }
+}
+
+/// 生成发送步骤的消息，`next_id` 为下一个自动分配的详单 ID
+/// 新详单和取消消息以 [`ChargingDetail::test_new`] 为基础，覆盖 `data` 中的字段，没有指定 `id` 时自动分配
+/// 其他消息的数据为 `data` 的 JSON，没有 `data` 时为空字符串
+pub fn build_messages(
+    type_: MessageType,
+    data: Option<&Value>,
+    repeat: u32,
+    next_id: &mut u32,
+) -> Result<Vec<MSG>, String> {
+    (0..repeat)
+        .map(|_| {
+            let data = match type_ {
+                MessageType::New | MessageType::Cancel => {
+                    let overrides = match data {
+                        Some(Value::Object(overrides)) => overrides.clone(),
+                        Some(_) => return Err(format!("{:?} data must be a table", type_)),
+                        None => Map::new(),
+                    };
+                    let id = match overrides.get("id") {
+                        Some(id) => id
+                            .as_u64()
+                            .and_then(|id| u32::try_from(id).ok())
+                            .ok_or_else(|| format!("invalid detail id: {}", id))?,
+                        None => {
+                            let id = *next_id;
+                            *next_id += 1;
+                            id
+                        }
+                    };
+                    let mut detail = serde_json::to_value(ChargingDetail::test_new(id))
+                        .map_err(|e| e.to_string())?;
+                    if let Value::Object(fields) = &mut detail {
+                        fields.extend(overrides);
+                    }
+                    detail
+                }
+                _ => data.cloned().unwrap_or(Value::Null),
+            };
+            Ok(MSG::new(type_, data))
+        })
+        .collect()
+}
+
+/// 接收充电桩的下一条消息，到达 `deadline` 时返回 `None`，带有消息 ID 的完成和故障消息自动回复 `ack` 确认
+pub async fn recv<W, R>(
+    outgoing: &mut W,
+    incoming: &mut R,
+    deadline: Option<Instant>,
+    report: &impl Fn(String),
+) -> Result<Option<MSG>, String>
+where
+    W: SinkExt<Message> + Unpin,
+    W::Error: std::fmt::Debug,
+    R: StreamExt<Item = Result<Message, WsError>> + Unpin,
+{
+    loop {
+        let next = match deadline {
+            Some(deadline) => match timeout_at(deadline, incoming.next()).await {
+                Ok(next) => next,
+                Err(_) => return Ok(None),
+            },
+            None => incoming.next().await,
+        };
+        let message = match next {
+            Some(Ok(message)) => message,
+            Some(Err(e)) => return Err(format!("connection error: {}", e)),
+            None => return Err("connection closed by pile".to_string()),
+        };
+        if message.is_close() {
+            return Err("connection closed by pile".to_string());
+        }
+        if message.is_ping() {
+            outgoing.send(Message::Pong("Pong!".into())).await.ok();
+            continue;
+        }
+        let msg: MSG = match &message {
+            Message::Text(text) => serde_json::from_str(text)
+                .map_err(|e| format!("failed to parse message {:?}: {}", text.as_str(), e))?,
+            Message::Binary(bytes) => MSG::from_msgpack(bytes)
+                .map_err(|e| format!("failed to parse binary message: {}", e))?,
+            _ => continue,
+        };
+        report(format!("Received {:?}: {}", msg.type_, msg.data));
+        if outbox::needs_ack(msg.type_)
+            && let Some(msg_id) = msg.msg_id
+        {
+            let ack = MSG::with_payload(MessageType::Ack, &MsgAckData { msg_id });
+            outgoing
+                .send(Message::Text(serde_json::to_string(&ack).unwrap().into()))
+                .await
+                .map_err(|e| format!("failed to send ack: {:?}", e))?;
+        }
+        return Ok(Some(msg));
+    }
+}
+
+/// 同意跳过空闲时间时虚拟时钟跳到的时间：充电桩的虚拟时间加上 `remaining` 真实时间按充电桩加速倍数对应的虚拟时长
+pub fn skip_until(
+    request: &IdleSkipRequestData,
+    remaining: Duration,
+) -> chrono::DateTime<chrono::Utc> {
+    let skipped = Duration::try_from_secs_f64(remaining.as_secs_f64() * request.speed)
+        .ok()
+        .and_then(|skipped| chrono::Duration::from_std(skipped).ok())
+        .unwrap_or_default();
+    request.virtual_time + skipped
+}
+
+/// 从充电桩注册后开始按顺序执行场景的步骤，等待的消息超时或连接断开时返回错误，执行完后关闭连接
+/// 等待步骤开始前收到的消息会保留下来，之后的等待步骤可以匹配这些消息
+pub async fn run_steps<W, R>(
+    outgoing: &mut W,
+    incoming: &mut R,
+    scenario: &Scenario,
+    report: &impl Fn(String),
+) -> Result<(), String>
+where
+    W: SinkExt<Message> + Unpin,
+    W::Error: std::fmt::Debug,
+    R: StreamExt<Item = Result<Message, WsError>> + Unpin,
+{
+    let start = Instant::now();
+    // 同意跳过空闲时间后提前执行的时长
+    let mut skipped = Duration::ZERO;
+    let mut received: VecDeque<MSG> = VecDeque::new();
+    let mut next_id = 0;
+    for (index, step) in scenario.steps.iter().enumerate() {
+        let number = index + 1;
+        if let Some(at) = step.at {
+            let deadline = start + at - skipped;
+            while let Some(msg) = recv(outgoing, incoming, Some(deadline), report).await? {
+                let request = (msg.type_ == MessageType::IdleSkipRequest)
+                    .then(|| msg.payload::<IdleSkipRequestData>().ok())
+                    .flatten();
+                received.push_back(msg);
+                if let Some(request) = request {
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+                    let until = skip_until(&request, remaining);
+                    report(format!(
+                        "Step {}: granting idle skip until {}",
+                        number, until
+                    ));
+                    let grant =
+                        MSG::with_payload(MessageType::IdleSkipGrant, &IdleSkipGrantData { until });
+                    outgoing
+                        .send(Message::Text(serde_json::to_string(&grant).unwrap().into()))
+                        .await
+                        .map_err(|e| format!("step {}: failed to send: {:?}", number, e))?;
+                    skipped += remaining;
+                    break;
+                }
+            }
+        }
+        match &step.action {
+            Action::Send {
+                type_,
+                data,
+                repeat,
+            } => {
+                let msgs = build_messages(*type_, data.as_ref(), *repeat, &mut next_id)
+                    .map_err(|e| format!("step {}: {}", number, e))?;
+                for msg in msgs {
+                    report(format!(
+                        "Step {}: sending {:?} {}",
+                        number, msg.type_, msg.data
+                    ));
+                    outgoing
+                        .send(Message::Text(serde_json::to_string(&msg).unwrap().into()))
+                        .await
+                        .map_err(|e| format!("step {}: failed to send: {:?}", number, e))?;
+                }
+            }
+            Action::Raw(text) => {
+                report(format!("Step {}: sending raw frame {:?}", number, text));
+                outgoing
+                    .send(Message::Text(text.clone().into()))
+                    .await
+                    .map_err(|e| format!("step {}: failed to send: {:?}", number, e))?;
+            }
+            Action::Expect(expectation) => {
+                if let Some(pos) = received.iter().position(|msg| expectation.matches(msg)) {
+                    received.remove(pos);
+                } else {
+                    let deadline = Instant::now() + expectation.within;
+                    loop {
+                        match recv(outgoing, incoming, Some(deadline), report).await? {
+                            Some(msg) if expectation.matches(&msg) => break,
+                            Some(msg) => received.push_back(msg),
+                            None => {
+                                return Err(format!(
+                                    "step {}: expected {} was not received",
+                                    number, expectation
+                                ));
+                            }
+                        }
+                    }
+                }
+                report(format!(
+                    "Step {}: received expected {}",
+                    number, expectation
+                ));
+            }
+        }
+    }
+    outgoing.send(Message::Close(None)).await.ok();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("500ms").unwrap(), Duration::from_millis(500));
+        assert_eq!(parse_duration("3s").unwrap(), Duration::from_secs(3));
+        assert_eq!(parse_duration("1.5").unwrap(), Duration::from_millis(1500));
+        assert_eq!(parse_duration("2m").unwrap(), Duration::from_secs(120));
+        assert!(parse_duration("3 days").is_err());
+        assert!(parse_duration("s").is_err());
+    }
+
+    #[test]
+    fn test_parse_scenario() {
+        let scenario = Scenario::from_toml(
+            r#"
+            name = "cancel"
+            timeout = "2m"
+
+            [[steps]]
+            at = "3s"
+            send = "new"
+            data = { id = 7, request_amount = 12.0 }
+
+            [[steps]]
+            at = 10
+            send = "cancel"
+            data = { id = 7 }
+
+            [[steps]]
+            raw = "{not json"
+
+            [[steps]]
+            expect = "error"
+            within = "5s"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(scenario.name, "cancel");
+        assert_eq!(scenario.timeout, Some(Duration::from_secs(120)));
+        assert_eq!(scenario.steps.len(), 4);
+        assert_eq!(scenario.steps[1].at, Some(Duration::from_secs(10)));
+        assert_eq!(
+            scenario.steps[2].action,
+            Action::Raw("{not json".to_string())
+        );
+        assert_eq!(
+            scenario.steps[3].action,
+            Action::Expect(Expectation {
+                type_: MessageType::Error,
+                data: None,
+                within: Duration::from_secs(5),
+            })
+        );
+
+        let example = Scenario::load("scenarios/cancel.toml").unwrap();
+        assert_eq!(example.name, "cancel");
+
+        for invalid in [
+            "[[steps]]\nsend = \"new\"\nexpect = \"complete\"",
+            "[[steps]]\nat = 1",
+            "[[steps]]\nsend = \"new\"\nrepeat = 2\ndata = { id = 1 }",
+            "[[steps]]\nat = 5\nsend = \"new\"\n[[steps]]\nat = 3\nsend = \"new\"",
+            "[[steps]]\nsend = \"nonsense\"",
+        ] {
+            assert!(Scenario::from_toml(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_build_and_match_messages() {
+        let mut next_id = 0;
+        let data = serde_json::json!({ "request_amount": 12 });
+        let msgs = build_messages(MessageType::New, Some(&data), 2, &mut next_id).unwrap();
+        assert_eq!(next_id, 2);
+        let detail: ChargingDetail = msgs[1].payload().unwrap();
+        assert_eq!(detail.get_id(), 1);
+        assert_eq!(detail.get_request_amount(), 12.0);
+
+        let close = build_messages(MessageType::Close, None, 1, &mut next_id).unwrap();
+        assert!(close[0].data.is_null());
+
+        let expectation = Expectation {
+            type_: MessageType::New,
+            data: Some(serde_json::json!({ "id": 1, "request_amount": 12.0 })),
+            within: DEFAULT_WITHIN,
+        };
+        assert!(expectation.matches(&msgs[1]));
+        assert!(!expectation.matches(&msgs[0]));
+        assert!(!expectation.matches(&close[0]));
+    }
+
+    #[test]
+    fn test_skip_until_scales_by_speed() {
+        let request = IdleSkipRequestData {
+            virtual_time: "2025-06-01T00:00:00Z".parse().unwrap(),
+            speed: 60.0,
+        };
+        assert_eq!(
+            skip_until(&request, Duration::from_secs(90)),
+            request.virtual_time + chrono::Duration::minutes(90)
+        );
+        assert_eq!(skip_until(&request, Duration::ZERO), request.virtual_time);
+    }
+}
@@ -0,0 +1,115 @@
+//! 跳过空闲时间
+//!
+//! 启用 `time.skip_idle` 时，充电桩空闲（没有任何详单）超过 `time.skip_idle_after_ms`（真实时间）后
+//! 向服务器发送 `idle_skip_request`，收到 `idle_skip_grant` 后把虚拟时钟推进到服务器给出的时间。
+//! 请求发出后、收到同意前有新详单到达时取消这次跳过，之后收到的同意被忽略。
+
+use tokio::time::{Duration, Instant};
+
+#[derive(Debug)]
+/// 跳过空闲时间的协商状态
+pub struct IdleSkip {
+    /// 空闲多久后请求跳过，为 `None` 时不启用
+    after: Option<Duration>,
+    /// 下一次发送请求的时间，不空闲时为 `None`
+    deadline: Option<Instant>,
+    /// 是否已发送请求，正在等待服务器同意
+    requested: bool,
+}
+
+impl IdleSkip {
+    /// 创建协商状态，`after` 为 `None` 时不启用
+    pub fn new(after: Option<Duration>) -> Self {
+        IdleSkip {
+            after,
+            deadline: None,
+            requested: false,
+        }
+    }
+
+    /// 是否启用
+    pub fn is_enabled(&self) -> bool {
+        self.after.is_some()
+    }
+
+    /// 记录充电桩当前是否空闲，刚开始空闲时开始计时，不空闲时取消计时和等待同意的请求
+    pub fn observe(&mut self, idle: bool, now: Instant) {
+        let Some(after) = self.after else {
+            return;
+        };
+        if !idle {
+            self.cancel();
+        } else if self.deadline.is_none() {
+            self.deadline = Some(now + after);
+        }
+    }
+
+    /// 下一次发送请求的时间
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// 空闲时间已到，发送请求后调用；没有收到同意时再空闲一段时间后重新请求
+    pub fn expire(&mut self, now: Instant) {
+        self.requested = true;
+        self.deadline = self.after.map(|after| now + after);
+    }
+
+    /// 有新详单到达，取消计时和等待同意的请求，返回是否取消了一个请求
+    pub fn cancel(&mut self) -> bool {
+        self.deadline = None;
+        std::mem::take(&mut self.requested)
+    }
+
+    /// 收到服务器同意，返回是否有等待同意的请求；之后仍然空闲时重新开始计时
+    pub fn grant(&mut self) -> bool {
+        self.cancel()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AFTER: Duration = Duration::from_millis(200);
+
+    #[test]
+    fn test_request_after_idle_period() {
+        let start = Instant::now();
+        let mut skip = IdleSkip::new(Some(AFTER));
+        skip.observe(true, start);
+        assert_eq!(skip.deadline(), Some(start + AFTER));
+        // 持续空闲时不会推迟计时
+        skip.observe(true, start + AFTER / 2);
+        assert_eq!(skip.deadline(), Some(start + AFTER));
+
+        skip.expire(start + AFTER);
+        assert_eq!(skip.deadline(), Some(start + AFTER * 2));
+        assert!(skip.grant());
+        assert_eq!(skip.deadline(), None);
+        // 同一个请求只能同意一次
+        assert!(!skip.grant());
+
+        let mut disabled = IdleSkip::new(None);
+        disabled.observe(true, start);
+        assert!(!disabled.is_enabled() && disabled.deadline().is_none());
+    }
+
+    #[test]
+    fn test_new_detail_cancels_request() {
+        let start = Instant::now();
+        let mut skip = IdleSkip::new(Some(AFTER));
+        skip.observe(true, start);
+        skip.expire(start + AFTER);
+        assert!(skip.cancel());
+        // 取消后收到的同意被忽略
+        assert!(!skip.grant());
+
+        // 不空闲时同样取消
+        skip.observe(true, start);
+        skip.expire(start + AFTER);
+        skip.observe(false, start + AFTER);
+        assert!(skip.deadline().is_none());
+        assert!(!skip.grant());
+    }
+}
@@ -0,0 +1,248 @@
+//! 独立运行
+//!
+//! `websocket.enabled = false`（或 `--standalone`）时充电桩不连接服务器，由本地驱动代替服务器：
+//! 回复注册确认和消息确认，按场景文件或内置生成器发送新详单，结束后关闭连接。
+//! 充电桩发送的消息按 JSON 行写入标准输出或 `standalone.output` 指定的文件，
+//! 配合固定的 `time.start_time` 和随机数种子可以重复得到相同的计费结果。
+
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use futures_util::SinkExt;
+use tokio_tungstenite::tungstenite::Message as WsMessage;
+
+use crate::conf::{ChargeType, StandaloneConf};
+use crate::detail::{ChargeStatus, ChargingDetail};
+use crate::message::{MSG, MessageType, RegisterAckData, RegisterPayload, RejectData};
+use crate::price::round_to_precision;
+use crate::scenario::{self, Scenario};
+use crate::transport::{Inlet, Outlet};
+
+/// 场景失败时的退出码
+pub const FAILED_EXIT_CODE: i32 = 1;
+
+/// 是否有场景执行失败
+static FAILED: AtomicBool = AtomicBool::new(false);
+
+/// 是否有场景执行失败，为真时主程序应当以 [`FAILED_EXIT_CODE`] 结束
+pub fn is_failed() -> bool {
+    FAILED.load(Ordering::Acquire)
+}
+
+#[derive(Debug, Clone)]
+/// 驱动充电桩的本地来源
+pub enum Source {
+    /// 按场景文件执行
+    Scenario(Scenario),
+    /// 内置生成器
+    Generator(StandaloneConf),
+}
+
+impl Source {
+    /// 按配置选择来源，场景文件无法读取或解析时返回错误
+    pub fn from_conf(conf: &StandaloneConf) -> Result<Source, String> {
+        match &conf.scenario {
+            Some(path) => Scenario::load(path).map(Source::Scenario),
+            None => Ok(Source::Generator(conf.clone())),
+        }
+    }
+}
+
+/// 可以用种子重现的伪随机数生成器（xorshift64*）
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        // 状态不能为 0
+        Rng(seed ^ 0x9E37_79B9_7F4A_7C15)
+    }
+
+    /// `[0, 1)` 之间的随机数
+    fn next_f64(&mut self) -> f64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        let value = self.0.wrapping_mul(0x2545_F491_4F6C_DD1D);
+        (value >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// 生成 `conf.details` 个指定类型的详单，ID 从 1 开始，请求充电量在配置的范围内均匀分布，保留两位小数
+/// `index` 为充电桩序号，不同充电桩使用不同的随机数序列
+pub fn generate(conf: &StandaloneConf, type_: ChargeType, index: usize) -> Vec<ChargingDetail> {
+    let mut rng = Rng::new(conf.seed.wrapping_add(index as u64));
+    (1..=conf.details)
+        .map(|id| {
+            let amount = conf.min_amount + (conf.max_amount - conf.min_amount) * rng.next_f64();
+            ChargingDetail::test_new(id)
+                .with_type(type_)
+                .with_request_amount(round_to_precision(amount, 2).max(conf.min_amount))
+        })
+        .collect()
+}
+
+/// 驱动一个充电桩直到来源结束，`index` 为充电桩序号
+/// 结束后关闭连接，充电桩随之结束运行
+pub async fn drive(mut outgoing: Outlet, mut incoming: Inlet, source: Source, index: usize) {
+    let result = match &source {
+        Source::Scenario(scenario) => run_scenario(&mut outgoing, &mut incoming, scenario).await,
+        Source::Generator(conf) => run_generator(&mut outgoing, &mut incoming, conf, index).await,
+    };
+    match (result, &source) {
+        (Ok(()), Source::Scenario(scenario)) => {
+            tracing::info!("场景 {} 执行完成", scenario.name);
+        }
+        (Ok(()), Source::Generator(conf)) => {
+            tracing::info!("已生成的 {} 个详单全部结束", conf.details);
+        }
+        (Err(e), Source::Scenario(scenario)) => {
+            FAILED.store(true, Ordering::Release);
+            tracing::error!("场景 {} 执行失败: {}", scenario.name, e);
+        }
+        (Err(e), Source::Generator(_)) => tracing::warn!("详单生成器提前结束: {}", e),
+    }
+    outgoing.send(WsMessage::Close(None)).await.ok();
+}
+
+/// 输出驱动收发的消息
+fn report(line: String) {
+    tracing::debug!("{}", line);
+}
+
+/// 发送一条消息，充电桩已经结束时返回错误
+async fn send(outgoing: &mut Outlet, msg: &MSG) -> Result<(), String> {
+    outgoing
+        .send(WsMessage::Text(serde_json::to_string(msg).unwrap().into()))
+        .await
+        .map_err(|e| format!("failed to send {:?}: {}", msg.type_, e))
+}
+
+/// 等待注册消息并回复注册确认
+async fn accept_register(
+    outgoing: &mut Outlet,
+    incoming: &mut Inlet,
+) -> Result<RegisterPayload, String> {
+    loop {
+        let Some(msg) = scenario::recv(outgoing, incoming, None, &report).await? else {
+            continue;
+        };
+        if msg.type_ != MessageType::Register {
+            continue;
+        }
+        let register: RegisterPayload = msg.payload()?;
+        let ack = RegisterAckData::accept(&register);
+        send(outgoing, &MSG::with_payload(MessageType::RegisterAck, &ack)).await?;
+        return Ok(register);
+    }
+}
+
+/// 按场景执行，超过场景的超时时间时失败
+async fn run_scenario(
+    outgoing: &mut Outlet,
+    incoming: &mut Inlet,
+    scenario: &Scenario,
+) -> Result<(), String> {
+    accept_register(outgoing, incoming).await?;
+    tracing::info!("充电桩已注册，开始执行场景 {}", scenario.name);
+    let run = scenario::run_steps(outgoing, incoming, scenario, &report);
+    match scenario.timeout {
+        Some(limit) => tokio::time::timeout(limit, run)
+            .await
+            .unwrap_or_else(|_| Err(format!("scenario timed out after {:?}", limit))),
+        None => run.await,
+    }
+}
+
+/// 先发送队列容量个详单，之后每结束一个详单再发送一个，直到所有详单都结束
+/// 充电桩故障时发送修复消息
+async fn run_generator(
+    outgoing: &mut Outlet,
+    incoming: &mut Inlet,
+    conf: &StandaloneConf,
+    index: usize,
+) -> Result<(), String> {
+    let register = accept_register(outgoing, incoming).await?;
+    let mut details = generate(conf, register.type_, index).into_iter();
+    let capacity = register
+        .size
+        .map_or(usize::MAX, |size| size.max(1) as usize);
+    tracing::info!("充电桩已注册，开始发送 {} 个生成的详单", conf.details);
+    for detail in details.by_ref().take(capacity) {
+        send(outgoing, &MSG::with_payload(MessageType::New, &detail)).await?;
+    }
+    let mut finished = HashSet::new();
+    while finished.len() < conf.details as usize {
+        let Some(msg) = scenario::recv(outgoing, incoming, None, &report).await? else {
+            continue;
+        };
+        let id = match msg.type_ {
+            MessageType::Reject => msg.payload::<RejectData>().ok().map(|reject| reject.id),
+            MessageType::Update | MessageType::Complete | MessageType::Fault => msg
+                .payload::<Option<ChargingDetail>>()
+                .ok()
+                .flatten()
+                .filter(|detail| {
+                    matches!(
+                        detail.get_status(),
+                        ChargeStatus::Completed
+                            | ChargeStatus::Interrupted
+                            | ChargeStatus::Canceled
+                    )
+                })
+                .map(|detail| detail.get_id()),
+            _ => None,
+        };
+        if msg.type_ == MessageType::Fault {
+            send(outgoing, &MSG::empty(MessageType::Repair)).await?;
+        }
+        if let Some(id) = id
+            && finished.insert(id)
+            && let Some(next) = details.next()
+        {
+            send(outgoing, &MSG::with_payload(MessageType::New, &next)).await?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generate_is_reproducible() {
+        let conf = StandaloneConf {
+            details: 20,
+            min_amount: 5.0,
+            max_amount: 30.0,
+            ..StandaloneConf::default()
+        };
+        let first = generate(&conf, ChargeType::Slow, 0);
+        assert_eq!(first.len(), 20);
+        assert!(first.iter().enumerate().all(|(i, detail)| {
+            detail.get_id() == i as u32 + 1
+                && detail.get_type() == ChargeType::Slow
+                && (5.0..=30.0).contains(&detail.get_request_amount())
+        }));
+        let amounts = |details: &[ChargingDetail]| {
+            details
+                .iter()
+                .map(|detail| detail.get_request_amount())
+                .collect::<Vec<_>>()
+        };
+        // 相同种子生成相同的详单，不同充电桩的序列不同
+        assert_eq!(
+            amounts(&first),
+            amounts(&generate(&conf, ChargeType::Slow, 0))
+        );
+        assert_ne!(
+            amounts(&first),
+            amounts(&generate(&conf, ChargeType::Slow, 1))
+        );
+        let other_seed = StandaloneConf { seed: 2, ..conf };
+        assert_ne!(
+            amounts(&first),
+            amounts(&generate(&other_seed, ChargeType::Slow, 0))
+        );
+    }
+}
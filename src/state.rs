@@ -0,0 +1,181 @@
+//! 充电桩状态机
+//!
+//! 充电桩在任意时刻处于 [`PileState`] 中的一个状态，状态只能按 [`PileState::next`]
+//! 中的转换表变化，不合法的转换返回 [`TransitionError`]，调用方据此拒绝或忽略请求。
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+/// 充电桩状态
+pub enum PileState {
+    #[default]
+    /// 没有正在充电的详单，可以接收新详单
+    Idle,
+    /// 至少有一个正在充电的详单
+    Charging,
+    /// 已被服务器关闭，拒绝新详单和取消请求，直到重新打开
+    Closed,
+    /// 处于故障状态，拒绝新详单和取消请求，直到修复
+    Faulted,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// 引起状态变化的事件
+pub enum Transition {
+    /// 开始为一个详单充电
+    Start,
+    /// 最后一个正在充电的详单结束
+    Finish,
+    /// 服务器关闭充电桩
+    Close,
+    /// 服务器重新打开充电桩
+    Open,
+    /// 充电桩损坏
+    Fault,
+    /// 充电桩修复
+    Repair,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// 当前状态下不允许的状态转换
+pub struct TransitionError {
+    /// 当前状态
+    pub state: PileState,
+    /// 被拒绝的事件
+    pub transition: Transition,
+}
+
+impl PileState {
+    /// 状态名称，与序列化的值相同
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PileState::Idle => "idle",
+            PileState::Charging => "charging",
+            PileState::Closed => "closed",
+            PileState::Faulted => "faulted",
+        }
+    }
+
+    /// 显示的状态名称
+    pub fn label(&self) -> &'static str {
+        match self {
+            PileState::Idle => "空闲",
+            PileState::Charging => "充电中",
+            PileState::Closed => "关闭",
+            PileState::Faulted => "故障",
+        }
+    }
+
+    /// 是否可以接收新详单和开始充电
+    pub fn is_operating(&self) -> bool {
+        matches!(self, PileState::Idle | PileState::Charging)
+    }
+
+    /// 按转换表计算事件发生后的状态
+    /// 关闭和损坏只能在营业状态下发生，关闭或故障期间只接受对应的打开或修复
+    pub fn next(self, transition: Transition) -> Result<PileState, TransitionError> {
+        use PileState::*;
+        use Transition::*;
+        match (self, transition) {
+            (Idle | Charging, Start) => Ok(Charging),
+            (Charging, Finish) => Ok(Idle),
+            (Idle | Charging, Close) => Ok(Closed),
+            (Closed, Open) => Ok(Idle),
+            (Idle | Charging, Fault) => Ok(Faulted),
+            (Faulted, Repair) => Ok(Idle),
+            (state, transition) => Err(TransitionError { state, transition }),
+        }
+    }
+}
+
+impl std::fmt::Display for PileState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl std::fmt::Display for TransitionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "cannot {:?} while {}", self.transition, self.state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STATES: [PileState; 4] = [
+        PileState::Idle,
+        PileState::Charging,
+        PileState::Closed,
+        PileState::Faulted,
+    ];
+    const TRANSITIONS: [Transition; 6] = [
+        Transition::Start,
+        Transition::Finish,
+        Transition::Close,
+        Transition::Open,
+        Transition::Fault,
+        Transition::Repair,
+    ];
+
+    #[test]
+    fn test_transition_table() {
+        use PileState::*;
+        // 每一行依次为 Start、Finish、Close、Open、Fault、Repair 之后的状态，None 表示不允许
+        let table = [
+            (
+                Idle,
+                [
+                    Some(Charging),
+                    None,
+                    Some(Closed),
+                    None,
+                    Some(Faulted),
+                    None,
+                ],
+            ),
+            (
+                Charging,
+                [
+                    Some(Charging),
+                    Some(Idle),
+                    Some(Closed),
+                    None,
+                    Some(Faulted),
+                    None,
+                ],
+            ),
+            (Closed, [None, None, None, Some(Idle), None, None]),
+            (Faulted, [None, None, None, None, None, Some(Idle)]),
+        ];
+        for (state, expected) in table {
+            for (transition, next) in TRANSITIONS.into_iter().zip(expected) {
+                assert_eq!(
+                    state.next(transition),
+                    next.ok_or(TransitionError { state, transition }),
+                    "{:?} + {:?}",
+                    state,
+                    transition
+                );
+            }
+        }
+        assert_eq!(table.map(|(state, _)| state), STATES);
+    }
+
+    #[test]
+    fn test_state_names() {
+        for state in STATES {
+            assert_eq!(serde_json::to_value(state).unwrap(), state.as_str());
+            assert_eq!(state.is_operating(), state.next(Transition::Start).is_ok());
+        }
+        assert_eq!(
+            PileState::Closed
+                .next(Transition::Close)
+                .unwrap_err()
+                .to_string(),
+            "cannot Close while closed"
+        );
+    }
+}
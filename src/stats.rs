@@ -0,0 +1,102 @@
+//! 运行统计
+
+use serde::Serialize;
+
+#[derive(Debug, Clone, Default)]
+/// 样本统计，单位为虚拟秒
+pub struct SampleStats {
+    /// 所有样本
+    samples: Vec<f64>,
+}
+
+/// 会话时长预测误差统计
+/// 误差为实际结束时间减去开始时预计的结束时间
+pub type EtaErrorStats = SampleStats;
+
+/// 排队等待时长统计
+/// 等待时长为开始充电时间减去加入队列时间
+pub type WaitTimeStats = SampleStats;
+
+/// 预测误差统计摘要
+pub type EtaErrorSummary = SampleSummary;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+/// 样本统计摘要
+pub struct SampleSummary {
+    /// 样本数
+    pub count: usize,
+    /// 平均值
+    pub mean: f64,
+    /// 中位数
+    pub p50: f64,
+    /// 90 分位数
+    pub p90: f64,
+    /// 99 分位数
+    pub p99: f64,
+    /// 最大值
+    pub max: f64,
+}
+
+impl SampleStats {
+    /// 记录一个样本
+    pub fn record(&mut self, secs: f64) {
+        self.samples.push(secs);
+    }
+
+    /// 样本数
+    pub fn count(&self) -> usize {
+        self.samples.len()
+    }
+
+    /// 平均值
+    pub fn mean(&self) -> Option<f64> {
+        if self.samples.is_empty() {
+            None
+        } else {
+            Some(self.samples.iter().sum::<f64>() / self.samples.len() as f64)
+        }
+    }
+
+    /// 计算分位数（最近秩法），`p` 取值范围为 [0, 100]
+    pub fn percentile(&self, p: f64) -> Option<f64> {
+        if self.samples.is_empty() {
+            return None;
+        }
+        let mut sorted = self.samples.clone();
+        sorted.sort_by(|a, b| a.total_cmp(b));
+        let rank = (p / 100.0 * sorted.len() as f64).ceil() as usize;
+        Some(sorted[rank.clamp(1, sorted.len()) - 1])
+    }
+
+    /// 获取统计摘要
+    pub fn summary(&self) -> Option<SampleSummary> {
+        Some(SampleSummary {
+            count: self.count(),
+            mean: self.mean()?,
+            p50: self.percentile(50.0)?,
+            p90: self.percentile(90.0)?,
+            p99: self.percentile(99.0)?,
+            max: self.percentile(100.0)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_eta_error_stats() {
+        let mut stats = EtaErrorStats::default();
+        assert!(stats.summary().is_none());
+        for error in [0.0, 10.0, 20.0, 30.0, 600.0] {
+            stats.record(error);
+        }
+        let summary = stats.summary().unwrap();
+        assert_eq!(summary.count, 5);
+        assert_eq!(summary.mean, 132.0);
+        assert_eq!(summary.p50, 20.0);
+        assert_eq!(summary.p90, 600.0);
+        assert_eq!(summary.max, 600.0);
+    }
+}
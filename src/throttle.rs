@@ -0,0 +1,210 @@
+//! 日志限流
+//!
+//! 同一类警告（按调用处的静态键区分）在一个时间窗口内只输出一次，
+//! 窗口内被省略的条数会附加在下一次输出的警告后面。
+
+use std::collections::HashMap;
+use std::fmt::{self, Display};
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::conf::CONF;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// 允许输出的警告附带的省略信息
+pub struct Digest {
+    /// 上次输出后被省略的条数
+    suppressed: u64,
+    /// 限流窗口
+    window: Duration,
+}
+
+impl Digest {
+    /// 上次输出后被省略的条数
+    pub fn suppressed(&self) -> u64 {
+        self.suppressed
+    }
+}
+
+impl Display for Digest {
+    /// 没有被省略的警告时输出为空
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.suppressed == 0 {
+            return Ok(());
+        }
+        write!(
+            f,
+            "（过去 {} 秒内另有 {} 条相同警告被省略）",
+            self.window.as_secs(),
+            self.suppressed
+        )
+    }
+}
+
+#[derive(Debug)]
+/// 单个警告类别的限流状态
+struct Entry {
+    /// 当前窗口的开始时间
+    window_start: Instant,
+    /// 当前窗口内被省略的条数
+    suppressed: u64,
+    /// 累计被省略的条数
+    total_suppressed: u64,
+}
+
+#[derive(Debug)]
+/// 日志限流器
+pub struct LogThrottle {
+    /// 限流窗口，为零时不限流
+    window: Duration,
+    /// 各类别的限流状态
+    entries: Mutex<HashMap<&'static str, Entry>>,
+}
+
+impl LogThrottle {
+    /// 创建限流器
+    pub fn new(window: Duration) -> Self {
+        LogThrottle {
+            window,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// 判断指定类别的警告现在是否可以输出
+    pub fn allow(&self, key: &'static str) -> Option<Digest> {
+        self.allow_at(key, Instant::now())
+    }
+
+    /// 判断指定类别的警告在指定时间是否可以输出
+    /// 每个窗口的第一条警告总是输出，并带上上一个窗口被省略的条数
+    pub fn allow_at(&self, key: &'static str, now: Instant) -> Option<Digest> {
+        if self.window.is_zero() {
+            return Some(Digest {
+                suppressed: 0,
+                window: self.window,
+            });
+        }
+        let mut entries = self.entries.lock().unwrap();
+        let Some(entry) = entries.get_mut(key) else {
+            // 第一次出现
+            entries.insert(
+                key,
+                Entry {
+                    window_start: now,
+                    suppressed: 0,
+                    total_suppressed: 0,
+                },
+            );
+            return Some(Digest {
+                suppressed: 0,
+                window: self.window,
+            });
+        };
+        if now.duration_since(entry.window_start) < self.window {
+            entry.suppressed += 1;
+            entry.total_suppressed += 1;
+            return None;
+        }
+        let digest = Digest {
+            suppressed: entry.suppressed,
+            window: self.window,
+        };
+        entry.window_start = now;
+        entry.suppressed = 0;
+        Some(digest)
+    }
+
+    /// 各类别累计被省略的条数
+    pub fn suppressed_totals(&self) -> Vec<(&'static str, u64)> {
+        let entries = self.entries.lock().unwrap();
+        let mut totals: Vec<_> = entries
+            .iter()
+            .filter(|(_, entry)| entry.total_suppressed > 0)
+            .map(|(key, entry)| (*key, entry.total_suppressed))
+            .collect();
+        totals.sort();
+        totals
+    }
+}
+
+/// 全局日志限流器
+pub static THROTTLE: LazyLock<LogThrottle> =
+    LazyLock::new(|| LogThrottle::new(Duration::from_secs(CONF.log.warn_throttle_s)));
+
+/// 使用全局限流器判断指定类别的警告是否可以输出
+pub fn allow(key: &'static str) -> Option<Digest> {
+    THROTTLE.allow(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::parse_frame;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tracing_subscriber::layer::{Context, SubscriberExt};
+    use tracing_subscriber::{Layer, Registry};
+
+    /// 统计日志条数
+    struct CountLayer(Arc<AtomicUsize>);
+
+    impl<S: tracing::Subscriber> Layer<S> for CountLayer {
+        fn on_event(&self, _event: &tracing::Event<'_>, _ctx: Context<'_, S>) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn test_bad_messages_are_throttled() {
+        let count = Arc::new(AtomicUsize::new(0));
+        let subscriber = Registry::default().with(CountLayer(count.clone()));
+        let throttle = LogThrottle::new(Duration::from_secs(30));
+        let start = Instant::now();
+
+        tracing::subscriber::with_default(subscriber, || {
+            // 100 秒内收到 1000 条相同的错误消息
+            for i in 0..1000 {
+                let (_, error) = parse_frame("{\"type\": bad}");
+                let error = error.unwrap();
+                let now = start + Duration::from_millis(i * 100);
+                if let Some(digest) = throttle.allow_at("test.parse", now) {
+                    tracing::warn!("消息解析失败: {}{}", error.reason, digest);
+                }
+            }
+            // 错误级别的日志不受限流影响
+            tracing::error!("错误");
+        });
+
+        // 窗口起点 0、30、60、90 秒各输出一次
+        assert_eq!(count.load(Ordering::Relaxed), 5);
+        assert_eq!(throttle.suppressed_totals(), vec![("test.parse", 996)]);
+    }
+
+    #[test]
+    fn test_digest_reports_suppressed() {
+        let throttle = LogThrottle::new(Duration::from_secs(30));
+        let start = Instant::now();
+        let first = throttle.allow_at("test.digest", start).unwrap();
+        assert_eq!(first.to_string(), "");
+        assert!(
+            throttle
+                .allow_at("test.digest", start + Duration::from_secs(1))
+                .is_none()
+        );
+        assert!(
+            throttle
+                .allow_at("test.other", start + Duration::from_secs(1))
+                .is_some()
+        );
+        let next = throttle
+            .allow_at("test.digest", start + Duration::from_secs(30))
+            .unwrap();
+        assert_eq!(next.suppressed(), 1);
+        assert_eq!(next.to_string(), "（过去 30 秒内另有 1 条相同警告被省略）");
+
+        // 不限流时总是输出
+        let disabled = LogThrottle::new(Duration::ZERO);
+        assert!(disabled.allow_at("test.digest", start).is_some());
+        assert!(disabled.allow_at("test.digest", start).is_some());
+    }
+}
@@ -1,40 +1,483 @@
-use std::sync::LazyLock;
+use std::sync::{OnceLock, RwLock};
 
-use chrono::{DateTime, Duration, Utc};
+use chrono::{DateTime, Duration, Local, NaiveDateTime, Utc};
+use chrono_tz::Tz;
+use tracing::field::Field;
+use tracing_subscriber::field::MakeExt;
+use tracing_subscriber::fmt::FormatFields;
+use tracing_subscriber::fmt::format::{Writer, debug_fn};
+use tracing_subscriber::fmt::time::FormatTime;
 
-use crate::conf::CONF;
+use crate::conf::{CONF, LogConf};
+use crate::runtime::RUNTIME;
 
-static REAL_START_TIME: LazyLock<DateTime<Utc>> = LazyLock::new(|| Utc::now());
+#[derive(Debug, Clone, Copy)]
+/// 时间锚点，虚拟时间从锚点开始按加速倍数流逝
+struct Anchor {
+    /// 锚点对应的真实时间
+    real: DateTime<Utc>,
+    /// 锚点对应的虚拟时间
+    mock: DateTime<Utc>,
+    /// 加速倍数，可以小于 1
+    speed: f64,
+    /// 是否暂停，暂停时虚拟时间停在锚点
+    paused: bool,
+}
 
-static MOCK_TIME: LazyLock<DateTime<Utc>> =
-    LazyLock::new(|| CONF.time.start_time.unwrap_or_else(|| Utc::now()));
+#[derive(Debug)]
+/// 虚拟时钟
+pub struct MockClock {
+    anchor: RwLock<Anchor>,
+}
 
-/// 获取当前时间(精确到毫秒)
-pub fn get_mock_now() -> DateTime<Utc> {
-    if CONF.time.speed == 1 {
-        // 如果加速倍数为1，直接返回当前时间
-        Utc::now()
+impl MockClock {
+    /// 创建一个虚拟时钟，不指定开始时间时从当前时间开始
+    pub fn new(start_time: Option<DateTime<Utc>>, speed: f64) -> Self {
+        let real = Utc::now();
+        MockClock {
+            anchor: RwLock::new(Anchor {
+                real,
+                mock: start_time.unwrap_or(real),
+                speed,
+                paused: false,
+            }),
+        }
+    }
+
+    /// 获取当前虚拟时间(精确到毫秒)
+    pub fn now(&self) -> DateTime<Utc> {
+        let anchor = *self.anchor.read().unwrap();
+        accelerated(anchor, Utc::now())
+    }
+
+    /// 以当前时刻为新的锚点修改加速倍数，保证虚拟时间连续
+    pub fn rebase(&self, speed: f64) -> DateTime<Utc> {
+        let mut anchor = self.anchor.write().unwrap();
+        let real = Utc::now();
+        let mock = accelerated(*anchor, real);
+        *anchor = Anchor {
+            real,
+            mock,
+            speed,
+            ..*anchor
+        };
+        mock
+    }
+
+    /// 把虚拟时间直接调整到 `mock`，之后按原来的加速倍数继续流逝，暂停时仍停在新的时间
+    /// 返回调整的偏移量，向后调整时为负数
+    pub fn jump_to(&self, mock: DateTime<Utc>) -> Duration {
+        let mut anchor = self.anchor.write().unwrap();
+        let real = Utc::now();
+        let offset = mock - accelerated(*anchor, real);
+        *anchor = Anchor {
+            real,
+            mock,
+            ..*anchor
+        };
+        offset
+    }
+
+    /// 把虚拟时间向前推进到 `mock`，与暂停和继续一样以当前时刻为新的锚点，之后按原来的加速倍数继续流逝
+    /// 虚拟时间不会倒退：`mock` 不晚于当前虚拟时间时不调整并返回 `None`，否则返回推进的时长
+    pub fn advance_to(&self, mock: DateTime<Utc>) -> Option<Duration> {
+        let mut anchor = self.anchor.write().unwrap();
+        let real = Utc::now();
+        let current = accelerated(*anchor, real);
+        if mock <= current {
+            return None;
+        }
+        *anchor = Anchor {
+            real,
+            mock,
+            ..*anchor
+        };
+        Some(mock - current)
+    }
+
+    /// 暂停虚拟时钟，返回是否从运行状态变为暂停
+    pub fn pause(&self) -> bool {
+        self.set_paused(true)
+    }
+
+    /// 继续虚拟时钟，暂停期间的真实时间不计入虚拟时间，返回是否从暂停状态变为运行
+    pub fn resume(&self) -> bool {
+        self.set_paused(false)
+    }
+
+    /// 是否已暂停
+    pub fn is_paused(&self) -> bool {
+        self.anchor.read().unwrap().paused
+    }
+
+    fn set_paused(&self, paused: bool) -> bool {
+        let mut anchor = self.anchor.write().unwrap();
+        if anchor.paused == paused {
+            return false;
+        }
+        let real = Utc::now();
+        let mock = accelerated(*anchor, real);
+        *anchor = Anchor {
+            real,
+            mock,
+            paused,
+            ..*anchor
+        };
+        true
+    }
+}
+
+/// 提供当前虚拟时间的时钟
+/// 运行时使用全局的加速时钟，测试可以使用 [`ManualClock`] 精确控制时间
+pub trait Clock: Send + Sync {
+    /// 当前虚拟时间
+    fn now(&self) -> DateTime<Utc>;
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> DateTime<Utc> {
+        MockClock::now(self)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+/// 全局虚拟时钟，与 [`get_mock_now`] 相同，跟随运行时的加速倍数、暂停和时钟同步
+pub struct GlobalClock;
+
+impl Clock for GlobalClock {
+    fn now(&self) -> DateTime<Utc> {
+        get_mock_now()
+    }
+}
+
+#[derive(Debug)]
+/// 手动推进的时钟，只有调用 [`ManualClock::advance`] 或 [`ManualClock::set`] 时才变化
+pub struct ManualClock {
+    now: RwLock<DateTime<Utc>>,
+}
+
+impl ManualClock {
+    /// 创建一个停在 `start` 的时钟
+    pub fn new(start: DateTime<Utc>) -> Self {
+        ManualClock {
+            now: RwLock::new(start),
+        }
+    }
+
+    /// 把时钟向前推进 `duration`，返回推进后的时间
+    pub fn advance(&self, duration: Duration) -> DateTime<Utc> {
+        let mut now = self.now.write().unwrap();
+        *now += duration;
+        *now
+    }
+
+    /// 把时钟设置为 `time`
+    pub fn set(&self, time: DateTime<Utc>) {
+        *self.now.write().unwrap() = time;
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> DateTime<Utc> {
+        *self.now.read().unwrap()
+    }
+}
+
+/// 计算锚点之后指定真实时间对应的虚拟时间
+fn accelerated(anchor: Anchor, real_now: DateTime<Utc>) -> DateTime<Utc> {
+    if anchor.paused {
+        return anchor.mock;
+    }
+    // 计算从锚点到现在的时间差
+    let elapsed = real_now.signed_duration_since(anchor.real);
+    if anchor.speed == 1.0 {
+        // 如果加速倍数为1，直接加上时间差
+        return anchor.mock + elapsed;
+    }
+    let duration_nanos = elapsed.num_nanoseconds();
+    if let Some(nanos) = duration_nanos {
+        // 计算加速后的时间(精确到纳秒)
+        let accelerated_duration = Duration::nanoseconds((nanos as f64 * anchor.speed) as i64);
+        anchor.mock + accelerated_duration
     } else {
-        // 计算从开始时间到现在的时间差
-        let elapsed = Utc::now().signed_duration_since(*REAL_START_TIME);
-        let duration_nanos = elapsed.num_nanoseconds();
-        if let Some(nanos) = duration_nanos {
-            // 计算加速后的时间(精确到纳秒)
-            let accelerated_duration = Duration::nanoseconds(nanos * CONF.time.speed as i64);
-            *MOCK_TIME + accelerated_duration
+        let duration_micros = elapsed.num_microseconds();
+        if let Some(micros) = duration_micros {
+            // 计算加速后的时间(精确到微秒)
+            let accelerated_duration =
+                Duration::microseconds((micros as f64 * anchor.speed) as i64);
+            anchor.mock + accelerated_duration
         } else {
-            let duration_micros = elapsed.num_microseconds();
-            if let Some(micros) = duration_micros {
-                // 计算加速后的时间(精确到微秒)
-                let accelerated_duration = Duration::microseconds(micros * CONF.time.speed as i64);
-                *MOCK_TIME + accelerated_duration
-            } else {
-                // 如果纳秒和微秒都为 None，使用毫秒
-                let duration_mullis = elapsed.num_milliseconds();
-                let accelerated_duration =
-                    Duration::milliseconds(duration_mullis * CONF.time.speed as i64);
-                *MOCK_TIME + accelerated_duration
+            // 如果纳秒和微秒都为 None，使用毫秒
+            let duration_mullis = elapsed.num_milliseconds();
+            let accelerated_duration =
+                Duration::milliseconds((duration_mullis as f64 * anchor.speed) as i64);
+            anchor.mock + accelerated_duration
+        }
+    }
+}
+
+/// 获取当前时间(精确到毫秒)
+pub fn get_mock_now() -> DateTime<Utc> {
+    RUNTIME.clock().now()
+}
+
+/// 暂停虚拟时钟，返回是否从运行状态变为暂停
+pub fn pause() -> bool {
+    RUNTIME.set_paused(true)
+}
+
+/// 继续虚拟时钟，返回是否从暂停状态变为运行
+pub fn resume() -> bool {
+    RUNTIME.set_paused(false)
+}
+
+/// 虚拟时钟是否已暂停
+pub fn is_paused() -> bool {
+    RUNTIME.clock().is_paused()
+}
+
+/// 获取配置时区下的当前时间
+pub fn get_mock_local_now() -> DateTime<Tz> {
+    get_mock_now().with_timezone(&CONF.time.tz)
+}
+
+/// 控制台日志默认时间格式
+const DEFAULT_CONSOLE_TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S%.3f";
+
+/// 按指定格式和时区格式化时间
+/// 不指定时区时使用 UTC，不指定格式时使用 `DateTime` 默认的显示格式
+pub fn format_time(time: DateTime<Utc>, format: Option<&str>, tz: Option<Tz>) -> String {
+    match (format, tz) {
+        (Some(format), Some(tz)) => time.with_timezone(&tz).format(format).to_string(),
+        (Some(format), None) => time.format(format).to_string(),
+        (None, Some(tz)) => time.with_timezone(&tz).to_string(),
+        (None, None) => time.to_string(),
+    }
+}
+
+/// 控制台时间配置，第一次使用时从配置文件读取
+static CONSOLE_TIME: OnceLock<LogConf> = OnceLock::new();
+
+/// 设置控制台时间配置，在配置加载后、输出虚拟时间前调用
+pub fn init_console_time(conf: &LogConf) {
+    let _ = CONSOLE_TIME.set(conf.clone());
+}
+
+/// 按控制台配置格式化虚拟时间，用于面向人的输出
+pub fn fmt_vt(time: DateTime<Utc>) -> String {
+    let conf = CONSOLE_TIME.get_or_init(|| CONF.log.clone());
+    format_time(
+        time,
+        conf.console_time_format.as_deref(),
+        conf.console_time_zone,
+    )
+}
+
+/// 日志中 `virtual_time` 字段的格式，与 `DateTime<Utc>` 的显示格式一致
+const VIRTUAL_TIME_FIELD_FORMAT: &str = "%Y-%m-%d %H:%M:%S%.f UTC";
+
+/// 控制台日志字段格式
+/// 日志中的 `virtual_time` 字段保持 UTC，控制台输出时按配置重新格式化，文件日志不受影响
+pub fn console_fields() -> impl for<'writer> FormatFields<'writer> + 'static {
+    debug_fn(
+        |w: &mut Writer<'_>, field: &Field, value: &dyn std::fmt::Debug| {
+            match field.name() {
+                "message" => write!(w, "{:?}", value),
+                "virtual_time" => {
+                    let raw = format!("{:?}", value);
+                    // 配置加载前不读取配置，避免在输出日志时加载配置
+                    match NaiveDateTime::parse_from_str(&raw, VIRTUAL_TIME_FIELD_FORMAT) {
+                        Ok(time) if CONSOLE_TIME.get().is_some() => {
+                            write!(w, "virtual_time={}", fmt_vt(time.and_utc()))
+                        }
+                        _ => write!(w, "virtual_time={}", raw),
+                    }
+                }
+                name => write!(w, "{}={:?}", name, value),
             }
+        },
+    )
+    .delimited(" ")
+}
+
+/// 控制台日志计时器，按日志配置输出真实时间
+pub struct ConsoleTimer;
+
+impl FormatTime for ConsoleTimer {
+    fn format_time(&self, w: &mut Writer<'_>) -> std::fmt::Result {
+        // 配置加载前使用默认格式
+        let conf = CONSOLE_TIME.get();
+        let format = conf
+            .and_then(|conf| conf.console_time_format.as_deref())
+            .unwrap_or(DEFAULT_CONSOLE_TIME_FORMAT);
+        match conf.and_then(|conf| conf.console_time_zone) {
+            Some(tz) => write!(w, "{}", Utc::now().with_timezone(&tz).format(format)),
+            None => write!(w, "{}", Local::now().format(format)),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rebase_keeps_time_continuous() {
+        let start = DateTime::parse_from_rfc3339("2025-06-01T00:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let clock = MockClock::new(Some(start), 1000.0);
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        let before = clock.now();
+        assert!(before - start >= Duration::seconds(20));
+
+        let rebased = clock.rebase(1.0);
+        assert!(rebased >= before);
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        // 降速后虚拟时间按真实时间流逝
+        let after = clock.now();
+        assert!(after - rebased >= Duration::milliseconds(20));
+        assert!(after - rebased < Duration::seconds(5));
+
+        // 加速倍数可以小于 1，此时虚拟时间比真实时间慢
+        let real = std::time::Instant::now();
+        let slowed = clock.rebase(0.5);
+        std::thread::sleep(std::time::Duration::from_millis(40));
+        let elapsed = clock.now() - slowed;
+        assert!(elapsed >= Duration::milliseconds(20));
+        assert!(elapsed.to_std().unwrap() < real.elapsed());
+    }
+
+    #[test]
+    fn test_pause_freezes_time() {
+        let start = DateTime::parse_from_rfc3339("2025-06-01T00:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let clock = MockClock::new(Some(start), 1000.0);
+        assert!(clock.pause());
+        assert!(!clock.pause());
+        let frozen = clock.now();
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        assert_eq!(clock.now(), frozen);
+        // 暂停期间修改加速倍数不会让时间前进
+        assert_eq!(clock.rebase(10.0), frozen);
+        assert!(clock.is_paused());
+
+        // 继续后暂停期间的时间不计入虚拟时间
+        assert!(clock.resume());
+        let resumed = clock.now();
+        assert!(resumed - frozen < Duration::seconds(1));
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        assert!(clock.now() - frozen >= Duration::milliseconds(200));
+    }
+
+    #[test]
+    fn test_jump_keeps_speed_and_pause() {
+        let start = DateTime::parse_from_rfc3339("2025-06-01T00:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let clock = MockClock::new(Some(start), 1000.0);
+        let target = start + Duration::hours(2);
+        let offset = clock.jump_to(target);
+        assert!(offset > Duration::minutes(119) && offset <= Duration::hours(2));
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        // 调整后仍按原来的加速倍数流逝
+        assert!(clock.now() - target >= Duration::seconds(20));
+
+        // 暂停时调整到更早的时间，仍保持暂停
+        clock.pause();
+        assert!(clock.jump_to(start) < -Duration::hours(2));
+        assert_eq!(clock.now(), start);
+        assert!(clock.is_paused());
+    }
+
+    #[test]
+    fn test_advance_never_goes_back() {
+        let start = DateTime::parse_from_rfc3339("2025-06-01T00:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let clock = MockClock::new(Some(start), 1000.0);
+        assert_eq!(clock.advance_to(start), None);
+        assert!(clock.now() >= start);
+
+        let target = start + Duration::hours(6);
+        let skipped = clock.advance_to(target).unwrap();
+        assert!(skipped > Duration::minutes(359) && skipped <= Duration::hours(6));
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        assert!(clock.now() - target >= Duration::seconds(20));
+
+        // 暂停时推进后仍停在新的时间
+        clock.pause();
+        let frozen = clock.now();
+        assert_eq!(clock.advance_to(frozen - Duration::seconds(1)), None);
+        clock.advance_to(frozen + Duration::hours(1)).unwrap();
+        assert_eq!(clock.now(), frozen + Duration::hours(1));
+        assert!(clock.is_paused());
+    }
+
+    #[test]
+    fn test_manual_clock_only_moves_when_stepped() {
+        let start = DateTime::parse_from_rfc3339("2025-06-01T00:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let clock = ManualClock::new(start);
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        assert_eq!(Clock::now(&clock), start);
+        assert_eq!(
+            clock.advance(Duration::minutes(30)),
+            start + Duration::minutes(30)
+        );
+        clock.set(start);
+        // 通过 trait 对象使用时行为相同
+        let clock: &dyn Clock = &clock;
+        assert_eq!(clock.now(), start);
+    }
+
+    #[test]
+    fn test_format_time() {
+        let time = DateTime::parse_from_rfc3339("2025-06-01T00:30:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        // 默认保持原有的 UTC 显示格式
+        assert_eq!(format_time(time, None, None), "2025-06-01 00:30:00 UTC");
+        // 控制台可以从日志字段中还原虚拟时间
+        let precise = time + Duration::milliseconds(123);
+        let parsed =
+            NaiveDateTime::parse_from_str(&precise.to_string(), VIRTUAL_TIME_FIELD_FORMAT).unwrap();
+        assert_eq!(parsed.and_utc(), precise);
+        assert_eq!(
+            format_time(
+                time,
+                Some("%Y-%m-%d %H:%M"),
+                Some(chrono_tz::Asia::Shanghai)
+            ),
+            "2025-06-01 08:30"
+        );
+        assert_eq!(
+            format_time(
+                time,
+                Some("%Y-%m-%d %H:%M"),
+                Some(chrono_tz::America::New_York)
+            ),
+            "2025-05-31 20:30"
+        );
+        assert_eq!(
+            format_time(
+                time,
+                Some("%d/%m %H:%M:%S %Z"),
+                Some(chrono_tz::Europe::Berlin)
+            ),
+            "01/06 02:30:00 CEST"
+        );
+    }
+
+    #[test]
+    fn test_invalid_format_rejected() {
+        let conf = LogConf {
+            console_time_format: Some("%Y-%Q".to_string()),
+            ..LogConf::default()
+        };
+        assert!(conf.validate().is_err());
+        assert!(LogConf::default().validate().is_ok());
+    }
+}
@@ -0,0 +1,204 @@
+//! WebSocket TLS 连接
+//!
+//! 按 `websocket.tls` 加载 CA 证书和客户端证书，生成 `wss://` 连接使用的 rustls 连接器。
+//! 测试服务器使用同一个模块中的 [`acceptor`] 监听 TLS 连接，可以要求客户端提供证书。
+
+use std::fs::File;
+use std::io::BufReader;
+use std::sync::Arc;
+
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{CryptoProvider, WebPkiSupportedAlgorithms};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{ClientConfig, DigitallySignedStruct, RootCertStore, ServerConfig, SignatureScheme};
+use tokio_rustls::TlsAcceptor;
+use tokio_tungstenite::Connector;
+
+use crate::conf::TlsConf;
+
+/// 使用的加密实现
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(rustls::crypto::ring::default_provider())
+}
+
+/// 读取 PEM 文件中的证书，`field` 为配置项名称，用于错误信息
+pub fn load_certs(path: &str, field: &str) -> Result<Vec<CertificateDer<'static>>, String> {
+    let file = File::open(path).map_err(|e| format!("failed to open {} {}: {}", field, path, e))?;
+    let certs = rustls_pemfile::certs(&mut BufReader::new(file))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("failed to parse {} {}: {}", field, path, e))?;
+    if certs.is_empty() {
+        return Err(format!("{} {} contains no certificates", field, path));
+    }
+    Ok(certs)
+}
+
+/// 读取 PEM 文件中的私钥
+pub fn load_key(path: &str, field: &str) -> Result<PrivateKeyDer<'static>, String> {
+    let file = File::open(path).map_err(|e| format!("failed to open {} {}: {}", field, path, e))?;
+    rustls_pemfile::private_key(&mut BufReader::new(file))
+        .map_err(|e| format!("failed to parse {} {}: {}", field, path, e))?
+        .ok_or_else(|| format!("{} {} contains no private key", field, path))
+}
+
+/// 读取 CA 证书作为根证书
+fn load_roots(path: &str, field: &str) -> Result<RootCertStore, String> {
+    let mut roots = RootCertStore::empty();
+    for cert in load_certs(path, field)? {
+        roots
+            .add(cert)
+            .map_err(|e| format!("invalid certificate in {} {}: {}", field, path, e))?;
+    }
+    Ok(roots)
+}
+
+/// 按配置生成 WebSocket 连接器，使用默认配置时返回 `None`，由 tokio-tungstenite 使用内置的根证书
+pub fn connector(conf: &TlsConf) -> Result<Option<Connector>, String> {
+    if conf.is_default() {
+        return Ok(None);
+    }
+    conf.validate()?;
+    let provider = provider();
+    let builder = ClientConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .map_err(|e| format!("failed to configure TLS: {}", e))?;
+    let builder = if conf.insecure_skip_verify {
+        tracing::warn!(
+            "websocket.tls.insecure_skip_verify 已开启，不验证服务器证书，仅用于测试环境"
+        );
+        builder
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(SkipVerification(
+                provider.signature_verification_algorithms,
+            )))
+    } else {
+        let roots = match &conf.ca_cert_path {
+            Some(path) => load_roots(path, "websocket.tls.ca_cert_path")?,
+            None => RootCertStore {
+                roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+            },
+        };
+        builder.with_root_certificates(roots)
+    };
+    let config = match (&conf.client_cert_path, &conf.client_key_path) {
+        (Some(cert), Some(key)) => builder
+            .with_client_auth_cert(
+                load_certs(cert, "websocket.tls.client_cert_path")?,
+                load_key(key, "websocket.tls.client_key_path")?,
+            )
+            .map_err(|e| format!("invalid client certificate or key: {}", e))?,
+        _ => builder.with_no_client_auth(),
+    };
+    Ok(Some(Connector::Rustls(Arc::new(config))))
+}
+
+/// 生成 TLS 监听器，设置了 `client_ca_path` 时要求客户端提供由该 CA 签发的证书
+pub fn acceptor(
+    cert_path: &str,
+    key_path: &str,
+    client_ca_path: Option<&str>,
+) -> Result<TlsAcceptor, String> {
+    let provider = provider();
+    let builder = ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .map_err(|e| format!("failed to configure TLS: {}", e))?;
+    let builder = match client_ca_path {
+        Some(path) => {
+            let verifier = WebPkiClientVerifier::builder_with_provider(
+                Arc::new(load_roots(path, "client CA")?),
+                provider,
+            )
+            .build()
+            .map_err(|e| format!("failed to build client verifier: {}", e))?;
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+    let config = builder
+        .with_single_cert(
+            load_certs(cert_path, "server certificate")?,
+            load_key(key_path, "server key")?,
+        )
+        .map_err(|e| format!("invalid server certificate or key: {}", e))?;
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+#[derive(Debug)]
+/// 不验证服务器证书，只检查握手签名
+struct SkipVerification(WebPkiSupportedAlgorithms);
+
+impl ServerCertVerifier for SkipVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(message, cert, dss, &self.0)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(message, cert, dss, &self.0)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.supported_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_connector_config_errors() {
+        assert!(connector(&TlsConf::default()).unwrap().is_none());
+
+        let missing = TlsConf {
+            ca_cert_path: Some("/nonexistent/ca.pem".to_string()),
+            ..TlsConf::default()
+        };
+        let err = connector(&missing).err().unwrap();
+        assert!(err.contains("websocket.tls.ca_cert_path"), "{}", err);
+
+        let half = TlsConf {
+            client_cert_path: Some("client.pem".to_string()),
+            ..TlsConf::default()
+        };
+        assert!(connector(&half).is_err());
+
+        let insecure = TlsConf {
+            insecure_skip_verify: true,
+            ..TlsConf::default()
+        };
+        assert!(connector(&insecure).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_empty_pem_rejected() {
+        let path = std::env::temp_dir().join(format!("taranis-tls-{}.pem", uuid::Uuid::new_v4()));
+        std::fs::write(&path, "not a certificate\n").unwrap();
+        let path_str = path.to_str().unwrap();
+        let err = load_certs(path_str, "websocket.tls.ca_cert_path").unwrap_err();
+        assert!(err.contains("contains no certificates"), "{}", err);
+        assert!(load_key(path_str, "websocket.tls.client_key_path").is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
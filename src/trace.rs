@@ -0,0 +1,220 @@
+//! 充电功率记录
+//!
+//! 按固定的虚拟时间间隔记录充电桩的瞬时功率，与计费更新无关，每个采样写入一行 JSON。
+
+use std::fs::File;
+use std::path::Path;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::charge::Charge;
+use crate::persist;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+/// 单次功率采样
+pub struct PowerSample {
+    /// 采样的虚拟时间
+    pub virtual_time: DateTime<Utc>,
+    /// 瞬时功率，单位为kW，空闲时为 0
+    pub power_kw: f64,
+    /// 正在充电的详单 ID
+    pub active_detail_ids: Vec<u32>,
+}
+
+impl PowerSample {
+    /// 对充电桩当前状态采样
+    pub fn of(charge: &Charge, virtual_time: DateTime<Utc>) -> Self {
+        PowerSample {
+            virtual_time,
+            power_kw: charge.current_power(),
+            active_detail_ids: charge.active_detail_ids(),
+        }
+    }
+}
+
+/// 虚拟采样间隔对应的真实时间间隔
+pub fn sample_period(sample_interval_s: u64, speed: f64) -> Duration {
+    Duration::from_secs(sample_interval_s)
+        .div_f64(speed)
+        .max(Duration::from_millis(1))
+}
+
+/// 功率记录文件
+pub struct PowerTrace {
+    file: File,
+}
+
+impl PowerTrace {
+    /// 打开记录文件，追加写入，截掉上次运行中断时留下的不完整行
+    pub fn open(path: &Path) -> std::io::Result<Self> {
+        let (file, _) = persist::open_jsonl(path)?;
+        Ok(PowerTrace { file })
+    }
+
+    /// 写入一次采样
+    pub fn record(&mut self, sample: &PowerSample) -> std::io::Result<()> {
+        persist::append_line(&mut self.file, &serde_json::to_string(sample).unwrap())
+    }
+}
+
+/// 读取功率记录文件
+pub fn read_trace(path: &Path) -> Result<Vec<PowerSample>, String> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| format!("failed to read trace file {}: {}", path.display(), e))?;
+    content
+        .lines()
+        .enumerate()
+        .map(|(line, row)| {
+            serde_json::from_str(row).map_err(|e| {
+                format!(
+                    "invalid trace row at {}:{}: {}",
+                    path.display(),
+                    line + 1,
+                    e
+                )
+            })
+        })
+        .collect()
+}
+
+/// 把多个充电桩的功率记录合并为整个充电站的功率
+/// 采样时间按间隔向下取整后对齐，同一时刻的功率相加，详单 ID 合并
+pub fn aggregate(traces: &[Vec<PowerSample>], sample_interval_s: u64) -> Vec<PowerSample> {
+    let step = (sample_interval_s.max(1) * 1000) as i64;
+    let mut buckets: std::collections::BTreeMap<i64, PowerSample> = Default::default();
+    for trace in traces {
+        for sample in trace {
+            let key = sample.virtual_time.timestamp_millis().div_euclid(step) * step;
+            let bucket = buckets.entry(key).or_insert_with(|| PowerSample {
+                virtual_time: DateTime::from_timestamp_millis(key).unwrap(),
+                power_kw: 0.0,
+                active_detail_ids: Vec::new(),
+            });
+            bucket.power_kw += sample.power_kw;
+            bucket.active_detail_ids.extend(&sample.active_detail_ids);
+        }
+    }
+    buckets.into_values().collect()
+}
+
+/// 读取多个充电桩的功率记录，合并为整个充电站的功率后写入 `out`，返回合并后的采样数
+pub fn merge_files(paths: &[String], sample_interval_s: u64, out: &Path) -> Result<usize, String> {
+    let traces = paths
+        .iter()
+        .map(|path| read_trace(Path::new(path)))
+        .collect::<Result<Vec<_>, _>>()?;
+    let station = aggregate(&traces, sample_interval_s);
+    let content: String = station
+        .iter()
+        .map(|sample| serde_json::to_string(sample).unwrap() + "\n")
+        .collect();
+    std::fs::write(out, content)
+        .map_err(|e| format!("failed to write station trace {}: {}", out.display(), e))?;
+    Ok(station.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::charge::FaultSource;
+    use crate::conf::CONF;
+    use crate::detail::ChargingDetail;
+
+    #[test]
+    fn test_trace_shows_pause() {
+        let path =
+            std::env::temp_dir().join(format!("taranis-trace-{}.jsonl", uuid::Uuid::new_v4()));
+        let mut trace = PowerTrace::open(&path).unwrap();
+        let mut charge =
+            Charge::new(CONF.charge.charge_type, 30.0, 2).with_requeue_after_repair(true);
+        charge.add_detail(ChargingDetail::test_new(1)).unwrap();
+        charge.start_charging();
+        let start = charge.get_charging_detail_ref().unwrap().clone_start_time();
+        let at = |minute| start + chrono::Duration::minutes(minute);
+
+        // 充电 3 分钟，故障暂停 2 分钟，修复后继续充电 3 分钟
+        for minute in 0..3 {
+            trace.record(&PowerSample::of(&charge, at(minute))).unwrap();
+        }
+        charge.breakdown(FaultSource::Internal).unwrap();
+        for minute in 3..5 {
+            trace.record(&PowerSample::of(&charge, at(minute))).unwrap();
+        }
+        assert!(!charge.repair().unwrap().is_empty());
+        for minute in 5..8 {
+            trace.record(&PowerSample::of(&charge, at(minute))).unwrap();
+        }
+        drop(trace);
+
+        let samples = read_trace(&path).unwrap();
+        assert_eq!(samples.len(), 8);
+        let powers: Vec<f64> = samples.iter().map(|s| s.power_kw).collect();
+        assert_eq!(powers, [30.0, 30.0, 30.0, 0.0, 0.0, 30.0, 30.0, 30.0]);
+        assert_eq!(samples[0].active_detail_ids, [1]);
+        assert!(samples[3].active_detail_ids.is_empty());
+        assert_eq!(samples[7].virtual_time, at(7));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_aggregate_station() {
+        let start = DateTime::parse_from_rfc3339("2025-06-01T00:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let sample = |offset_ms, power_kw, ids: &[u32]| PowerSample {
+            virtual_time: start + chrono::Duration::milliseconds(offset_ms),
+            power_kw,
+            active_detail_ids: ids.to_vec(),
+        };
+        // 两个充电桩的采样时间略有偏差
+        let first = vec![sample(0, 30.0, &[1]), sample(60_000, 30.0, &[1])];
+        let second = vec![sample(120, 7.0, &[2]), sample(60_150, 0.0, &[])];
+        let station = aggregate(&[first, second], 60);
+        assert_eq!(station.len(), 2);
+        assert_eq!(station[0].virtual_time, start);
+        assert_eq!(station[0].power_kw, 37.0);
+        assert_eq!(station[0].active_detail_ids, [1, 2]);
+        assert_eq!(station[1].power_kw, 30.0);
+        assert_eq!(sample_period(60, 1000.0), Duration::from_millis(60));
+        assert_eq!(sample_period(60, 0.5), Duration::from_secs(120));
+    }
+
+    #[test]
+    fn test_merge_files() {
+        let dir = std::env::temp_dir().join(format!("taranis-merge-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let start = DateTime::parse_from_rfc3339("2025-06-01T00:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let paths: Vec<String> = (0..2)
+            .map(|index| dir.join(format!("power.jsonl.{}", index)))
+            .map(|path| path.to_string_lossy().into_owned())
+            .collect();
+        for (index, path) in paths.iter().enumerate() {
+            let mut trace = PowerTrace::open(Path::new(path)).unwrap();
+            for minute in 0..3 {
+                let active = minute > index as i64;
+                trace
+                    .record(&PowerSample {
+                        virtual_time: start + chrono::Duration::minutes(minute),
+                        power_kw: if active { 30.0 } else { 0.0 },
+                        active_detail_ids: if active { vec![index as u32] } else { vec![] },
+                    })
+                    .unwrap();
+            }
+        }
+        let out = dir.join("power.jsonl.station");
+        assert_eq!(merge_files(&paths, 60, &out).unwrap(), 3);
+        let station = read_trace(&out).unwrap();
+        let powers: Vec<f64> = station.iter().map(|s| s.power_kw).collect();
+        assert_eq!(powers, [0.0, 30.0, 60.0]);
+        assert_eq!(station[2].active_detail_ids, [0, 1]);
+
+        // 缺少某个充电桩的记录时报告错误
+        let missing = vec![dir.join("missing").to_string_lossy().into_owned()];
+        assert!(merge_files(&missing, 60, &out).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
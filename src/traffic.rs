@@ -0,0 +1,192 @@
+//! 连接消息计数
+
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+
+use crate::message::{MessageType, TrafficSnapshot};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// 发送状态更新后需要执行的操作
+pub enum TrafficAction {
+    /// 正常
+    None,
+    /// 连续发送的状态更新没有得到任何响应，发送探测消息
+    Probe,
+    /// 探测后仍然没有响应，认为连接已断开
+    Teardown,
+}
+
+#[derive(Debug, Default)]
+/// 单个连接上的消息计数
+/// 记录每种消息的发送和接收次数，并检查是否只有发送没有接收（例如出站消息被中间设备丢弃）
+pub struct TrafficStats {
+    /// 每种消息的发送次数
+    sent: BTreeMap<MessageType, u64>,
+    /// 每种消息的接收次数
+    received: BTreeMap<MessageType, u64>,
+    /// 最后一次发送成功的真实时间
+    last_sent_real: Option<DateTime<Utc>>,
+    /// 最后一次发送成功的虚拟时间
+    last_sent_virtual: Option<DateTime<Utc>>,
+    /// 最多连续发送多少次状态更新而没有收到入站消息，为 0 时不检查
+    max_unacked_updates: u32,
+    /// 上次收到入站消息后连续发送的状态更新次数
+    unacked_updates: u32,
+    /// 是否已发送探测消息并在等待响应
+    probing: bool,
+}
+
+impl TrafficStats {
+    /// 创建消息计数
+    pub fn new(max_unacked_updates: u32) -> Self {
+        TrafficStats {
+            max_unacked_updates,
+            ..TrafficStats::default()
+        }
+    }
+
+    /// 记录发送成功的消息，返回需要执行的操作
+    pub fn record_sent(
+        &mut self,
+        type_: MessageType,
+        real: DateTime<Utc>,
+        virtual_time: DateTime<Utc>,
+    ) -> TrafficAction {
+        *self.sent.entry(type_).or_default() += 1;
+        self.last_sent_real = Some(real);
+        self.last_sent_virtual = Some(virtual_time);
+        if !matches!(type_, MessageType::Update | MessageType::Delta)
+            || self.max_unacked_updates == 0
+        {
+            return TrafficAction::None;
+        }
+        self.unacked_updates += 1;
+        if self.unacked_updates <= self.max_unacked_updates {
+            TrafficAction::None
+        } else if self.probing {
+            TrafficAction::Teardown
+        } else {
+            self.probing = true;
+            TrafficAction::Probe
+        }
+    }
+
+    /// 收到任意入站帧（包括 Pong 等控制帧）
+    pub fn on_inbound(&mut self) {
+        self.unacked_updates = 0;
+        self.probing = false;
+    }
+
+    /// 记录收到的应用消息
+    pub fn record_received(&mut self, type_: MessageType) {
+        *self.received.entry(type_).or_default() += 1;
+        self.on_inbound();
+    }
+
+    /// 每种消息的发送次数
+    pub fn sent(&self) -> &BTreeMap<MessageType, u64> {
+        &self.sent
+    }
+
+    /// 每种消息的接收次数
+    pub fn received(&self) -> &BTreeMap<MessageType, u64> {
+        &self.received
+    }
+
+    /// 最后一次发送成功的真实时间和虚拟时间
+    pub fn last_sent(&self) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+        self.last_sent_real.zip(self.last_sent_virtual)
+    }
+
+    /// 当前的消息计数
+    pub fn snapshot(&self) -> TrafficSnapshot {
+        TrafficSnapshot {
+            sent: self.sent.clone(),
+            received: self.received.clone(),
+            last_sent_real: self.last_sent_real,
+            last_sent_virtual: self.last_sent_virtual,
+        }
+    }
+
+    /// 连接断开后清空计数，检查阈值保持不变
+    pub fn reset(&mut self) {
+        *self = TrafficStats::new(self.max_unacked_updates);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::{SinkExt, StreamExt, sink, stream};
+
+    #[tokio::test]
+    async fn test_silent_stream_triggers_teardown() {
+        // 出站帧全部被接受，但入站方向一直没有消息
+        let mut sink = sink::drain::<MessageType>();
+        let mut inbound = stream::pending::<MessageType>();
+        let mut stats = TrafficStats::new(3);
+        let now = Utc::now();
+        let mut actions = Vec::new();
+        for _ in 0..5 {
+            sink.send(MessageType::Update).await.unwrap();
+            actions.push(stats.record_sent(MessageType::Update, now, now));
+            let poll = futures_util::poll!(inbound.next());
+            assert!(poll.is_pending());
+        }
+        assert_eq!(
+            actions,
+            vec![
+                TrafficAction::None,
+                TrafficAction::None,
+                TrafficAction::None,
+                TrafficAction::Probe,
+                TrafficAction::Teardown,
+            ]
+        );
+        assert_eq!(stats.sent()[&MessageType::Update], 5);
+        assert_eq!(stats.last_sent(), Some((now, now)));
+    }
+
+    #[test]
+    fn test_inbound_resets_guard() {
+        let mut stats = TrafficStats::new(1);
+        let now = Utc::now();
+        stats.record_sent(MessageType::Register, now, now);
+        assert_eq!(
+            stats.record_sent(MessageType::Update, now, now),
+            TrafficAction::None
+        );
+        assert_eq!(
+            stats.record_sent(MessageType::Update, now, now),
+            TrafficAction::Probe
+        );
+        // 探测得到响应后重新计数
+        stats.on_inbound();
+        assert_eq!(
+            stats.record_sent(MessageType::Update, now, now),
+            TrafficAction::None
+        );
+        stats.record_received(MessageType::New);
+        assert_eq!(stats.received()[&MessageType::New], 1);
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.sent[&MessageType::Update], 3);
+        assert_eq!(snapshot.last_sent_real, Some(now));
+        let value = serde_json::to_value(&snapshot).unwrap();
+        assert_eq!(value["sent"]["register"], 1);
+        assert_eq!(value["received"]["new"], 1);
+
+        stats.reset();
+        assert!(stats.sent().is_empty() && stats.received().is_empty());
+        assert!(stats.last_sent().is_none());
+
+        // 阈值为 0 时不检查
+        let mut stats = TrafficStats::new(0);
+        for _ in 0..10 {
+            assert_eq!(
+                stats.record_sent(MessageType::Update, now, now),
+                TrafficAction::None
+            );
+        }
+    }
+}
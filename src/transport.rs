@@ -0,0 +1,198 @@
+//! 充电桩的消息通道
+//!
+//! 充电桩的主循环通过 [`Outlet`] 发送消息、通过 [`Inlet`] 接收消息，
+//! 连接服务器时两者是 WebSocket 连接的两端，独立运行时是与本地驱动之间的内存通道，
+//! 此时发送的消息同时按 JSON 行写入 [`JsonLines`]，MessagePack 编码的消息转换为 JSON 后写入。
+
+use std::io::Write;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use futures_util::stream::{SplitSink, SplitStream};
+use futures_util::{Sink, Stream};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::{Error as WsError, Message as WsMessage};
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+
+use crate::message::MSG;
+
+type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+#[derive(Clone)]
+/// 按行写入消息的输出，多个充电桩共用时每条消息占完整的一行
+pub struct JsonLines(Arc<Mutex<Box<dyn Write + Send>>>);
+
+impl JsonLines {
+    /// 写入标准输出
+    pub fn stdout() -> Self {
+        JsonLines::new(Box::new(std::io::stdout()))
+    }
+
+    /// 创建或截断文件并写入
+    pub fn create(path: &str) -> Result<Self, String> {
+        let file = std::fs::File::create(path).map_err(|e| format!("{}: {}", path, e))?;
+        Ok(JsonLines::new(Box::new(std::io::BufWriter::new(file))))
+    }
+
+    /// 写入任意输出
+    pub fn new(writer: Box<dyn Write + Send>) -> Self {
+        JsonLines(Arc::new(Mutex::new(writer)))
+    }
+
+    /// 写入一行
+    fn write_line(&self, line: &str) -> std::io::Result<()> {
+        let mut writer = self.0.lock().unwrap();
+        writeln!(writer, "{}", line)
+    }
+
+    /// 把缓冲的内容写出
+    fn flush(&self) -> std::io::Result<()> {
+        self.0.lock().unwrap().flush()
+    }
+}
+
+/// 发送端
+pub enum Outlet {
+    /// WebSocket 连接
+    Socket(SplitSink<WsStream, WsMessage>),
+    /// 内存通道，对端关闭后发送失败
+    Channel(mpsc::UnboundedSender<WsMessage>),
+    /// 协议消息按 JSON 行写入输出后，所有消息转发到内存通道
+    Lines(JsonLines, mpsc::UnboundedSender<WsMessage>),
+}
+
+/// 接收端
+pub enum Inlet {
+    /// WebSocket 连接
+    Socket(SplitStream<WsStream>),
+    /// 内存通道
+    Channel(mpsc::UnboundedReceiver<WsMessage>),
+}
+
+/// 一对内存通道，分别交给充电桩和本地驱动，充电桩发送的协议消息写入 `lines`
+pub fn local_pair(lines: JsonLines) -> ((Outlet, Inlet), (Outlet, Inlet)) {
+    let (pile_tx, driver_rx) = mpsc::unbounded_channel();
+    let (driver_tx, pile_rx) = mpsc::unbounded_channel();
+    (
+        (Outlet::Lines(lines, pile_tx), Inlet::Channel(pile_rx)),
+        (Outlet::Channel(driver_tx), Inlet::Channel(driver_rx)),
+    )
+}
+
+impl Sink<WsMessage> for Outlet {
+    type Error = WsError;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        match self.get_mut() {
+            Outlet::Socket(sink) => Pin::new(sink).poll_ready(cx),
+            Outlet::Channel(_) | Outlet::Lines(..) => Poll::Ready(Ok(())),
+        }
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: WsMessage) -> Result<(), Self::Error> {
+        match self.get_mut() {
+            Outlet::Socket(sink) => Pin::new(sink).start_send(item),
+            Outlet::Channel(tx) => tx.send(item).map_err(|_| WsError::ConnectionClosed),
+            Outlet::Lines(lines, tx) => {
+                match &item {
+                    WsMessage::Text(text) => lines.write_line(text)?,
+                    WsMessage::Binary(bytes) => {
+                        if let Ok(msg) = MSG::from_msgpack(bytes) {
+                            lines.write_line(&serde_json::to_string(&msg).unwrap())?;
+                        }
+                    }
+                    _ => {}
+                }
+                tx.send(item).map_err(|_| WsError::ConnectionClosed)
+            }
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        match self.get_mut() {
+            Outlet::Socket(sink) => Pin::new(sink).poll_flush(cx),
+            Outlet::Channel(_) => Poll::Ready(Ok(())),
+            Outlet::Lines(lines, _) => Poll::Ready(lines.flush().map_err(WsError::Io)),
+        }
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        match self.get_mut() {
+            Outlet::Socket(sink) => Pin::new(sink).poll_close(cx),
+            Outlet::Channel(_) => Poll::Ready(Ok(())),
+            Outlet::Lines(lines, _) => Poll::Ready(lines.flush().map_err(WsError::Io)),
+        }
+    }
+}
+
+impl Stream for Inlet {
+    type Item = Result<WsMessage, WsError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        match self.get_mut() {
+            Inlet::Socket(stream) => Pin::new(stream).poll_next(cx),
+            Inlet::Channel(rx) => rx.poll_recv(cx).map(|message| message.map(Ok)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conf::WireEncoding;
+    use crate::message::MessageType;
+    use futures_util::{SinkExt, StreamExt};
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_local_pair_writes_text_lines() {
+        let buffer = Buffer::default();
+        let ((mut pile_tx, mut pile_rx), (mut driver_tx, mut driver_rx)) =
+            local_pair(JsonLines::new(Box::new(buffer.clone())));
+        pile_tx
+            .send(WsMessage::Text(r#"{"type":"register"}"#.into()))
+            .await
+            .unwrap();
+        pile_tx
+            .send(WsMessage::Ping(Vec::new().into()))
+            .await
+            .unwrap();
+        pile_tx
+            .send(MSG::empty(MessageType::Heartbeat).to_frame(WireEncoding::Msgpack))
+            .await
+            .unwrap();
+        // 所有消息都转发给驱动，只有协议消息写入输出，二进制消息转换为 JSON
+        assert!(driver_rx.next().await.unwrap().unwrap().is_text());
+        assert!(driver_rx.next().await.unwrap().unwrap().is_ping());
+        assert!(driver_rx.next().await.unwrap().unwrap().is_binary());
+        assert_eq!(
+            String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap(),
+            "{\"type\":\"register\"}\n{\"type\":\"heartbeat\",\"data\":null}\n"
+        );
+
+        driver_tx
+            .send(WsMessage::Pong(Vec::new().into()))
+            .await
+            .unwrap();
+        assert!(pile_rx.next().await.unwrap().unwrap().is_pong());
+        // 驱动结束后充电桩收到连接关闭
+        drop(driver_tx);
+        drop(driver_rx);
+        assert!(pile_rx.next().await.is_none());
+        assert!(pile_tx.send(WsMessage::Text("{}".into())).await.is_err());
+    }
+}
@@ -0,0 +1,261 @@
+//! 终端界面
+//!
+//! `--tui` 时在终端的备用屏幕中显示第一个充电桩的实时状态：充电桩信息、虚拟时间与真实时间、
+//! 正在充电的详单进度和费用，以及排队的详单和预计开始时间。
+//! 收到充电桩的状态变化事件（状态更新、队列变化等）时立即刷新，另外每秒刷新一次时间。
+//! 按键仍由键盘命令线程读取，此时日志只写入文件。
+
+use std::io::Write;
+
+use chrono::{DateTime, Utc};
+use crossterm::{cursor, execute, queue, style::Print, terminal};
+use tokio::sync::broadcast::{self, error::RecvError, error::TryRecvError};
+use tokio::task::JoinHandle;
+use tokio::time::{Duration, interval};
+
+use crate::charge::ChargeHandle;
+use crate::conf::CONF;
+use crate::detail::ChargingDetail;
+use crate::event::ChargeEvent;
+use crate::message::StatusData;
+use crate::runtime::RUNTIME;
+use crate::time::{self, fmt_vt, get_mock_now};
+
+/// 没有事件时的刷新间隔
+const REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// 进度条的宽度（字符数）
+const BAR_WIDTH: usize = 30;
+
+/// 终端界面，结束时恢复终端
+pub struct Dashboard {
+    task: JoinHandle<()>,
+}
+
+impl Dashboard {
+    /// 切换到备用屏幕并开始显示充电桩状态，终端不支持时返回错误
+    pub async fn start(charge: ChargeHandle) -> Result<Dashboard, String> {
+        let events = charge.lock().await.subscribe();
+        terminal::enable_raw_mode().map_err(|e| format!("failed to enable raw mode: {}", e))?;
+        if let Err(e) = execute!(
+            std::io::stdout(),
+            terminal::EnterAlternateScreen,
+            cursor::Hide
+        ) {
+            let _ = terminal::disable_raw_mode();
+            return Err(format!("failed to enter alternate screen: {}", e));
+        }
+        Ok(Dashboard {
+            task: tokio::spawn(run(charge, events)),
+        })
+    }
+}
+
+impl Drop for Dashboard {
+    fn drop(&mut self) {
+        self.task.abort();
+        let _ = execute!(
+            std::io::stdout(),
+            cursor::Show,
+            terminal::LeaveAlternateScreen
+        );
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+/// 按事件和刷新间隔重绘，充电桩的事件通道关闭时结束
+async fn run(charge: ChargeHandle, mut events: broadcast::Receiver<ChargeEvent>) {
+    let mut ticker = interval(REFRESH_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {}
+            event = events.recv() => match event {
+                Ok(_) => {}
+                // 丢失的事件不影响显示，重绘时读取最新的状态
+                Err(RecvError::Lagged(_)) => {}
+                Err(RecvError::Closed) => break,
+            },
+        }
+        // 连续到达的事件只重绘一次
+        while let Ok(_) | Err(TryRecvError::Lagged(_)) = events.try_recv() {}
+        let status = charge.lock().await.status_snapshot(get_mock_now());
+        // 无法取得终端大小时按 24 行显示
+        let height = match terminal::size() {
+            Ok((_, rows)) if rows > 0 => rows as usize,
+            _ => 24,
+        };
+        draw(&render(
+            &status,
+            time::is_paused(),
+            RUNTIME.speed(),
+            Utc::now(),
+            height,
+        ));
+    }
+}
+
+/// 从左上角开始逐行覆盖，清除上一次多出的内容
+fn draw(lines: &[String]) {
+    let mut stdout = std::io::stdout();
+    for (row, line) in lines.iter().enumerate() {
+        let _ = queue!(
+            stdout,
+            cursor::MoveTo(0, row as u16),
+            Print(line),
+            terminal::Clear(terminal::ClearType::UntilNewLine)
+        );
+    }
+    let _ = queue!(
+        stdout,
+        cursor::MoveTo(0, lines.len() as u16),
+        terminal::Clear(terminal::ClearType::FromCursorDown)
+    );
+    let _ = stdout.flush();
+}
+
+/// 宽度为 `width` 的进度条
+pub fn progress_bar(progress: f64, width: usize) -> String {
+    let filled = ((progress.clamp(0.0, 1.0) * width as f64).round() as usize).min(width);
+    format!("[{}{}]", "#".repeat(filled), "-".repeat(width - filled))
+}
+
+/// 正在充电的详单的一行
+fn charging_line(detail: &ChargingDetail) -> String {
+    format!(
+        "  详单 {} {} {:.1}% {:.2}/{:.2} kWh，费用 {:.2}",
+        detail.get_id(),
+        progress_bar(detail.get_progress(), BAR_WIDTH),
+        detail.get_progress() * 100.0,
+        detail.get_already_charged(),
+        detail.get_request_amount(),
+        detail.get_total_cost()
+    )
+}
+
+/// 生成界面的各行，最多 `height` 行，排队的详单放不下时只显示剩余的数量
+/// `paused` 为虚拟时钟是否暂停
+pub fn render(
+    status: &StatusData,
+    paused: bool,
+    speed: f64,
+    real_time: DateTime<Utc>,
+    height: usize,
+) -> Vec<String> {
+    let mut lines = vec![
+        format!(
+            "充电桩 {} [{:?}] {} kW，状态: {}",
+            status.charge_id,
+            status.type_,
+            status.power,
+            status.state.label()
+        ),
+        format!(
+            "虚拟时间: {}，真实时间: {}，加速倍数: {}{}",
+            fmt_vt(status.virtual_time),
+            fmt_vt(real_time),
+            speed,
+            if paused { "（已暂停）" } else { "" }
+        ),
+        String::new(),
+    ];
+    match &status.charging {
+        Some(detail) => {
+            lines.push("正在充电:".to_string());
+            lines.push(charging_line(detail));
+            lines.extend(status.also_charging.iter().map(charging_line));
+        }
+        None => lines.push("正在充电: 无".to_string()),
+    }
+    lines.push(String::new());
+    lines.push(format!("排队: {} 个详单", status.queue.len()));
+    let footer = format!(
+        "'q' 退出，'c' 立即完成，'+'/'-' 调整加速比，空格暂停{}",
+        if CONF.charge.manual_break {
+            "，'p' 模拟损坏"
+        } else {
+            ""
+        }
+    );
+    // 留出空行和按键说明
+    let room = height.saturating_sub(lines.len() + 2);
+    let shown = if status.queue.len() > room {
+        room.saturating_sub(1)
+    } else {
+        status.queue.len()
+    };
+    for (position, detail) in status.queue.iter().take(shown).enumerate() {
+        lines.push(format!(
+            "  {}. 详单 {} 请求 {:.2} kWh，预计开始: {}",
+            position + 1,
+            detail.get_id(),
+            detail.get_request_amount(),
+            detail
+                .get_schedule_estimate()
+                .map_or_else(|| "未知".to_string(), |(start, _)| fmt_vt(start))
+        ));
+    }
+    if shown < status.queue.len() {
+        lines.push(format!("  …… 还有 {} 个详单", status.queue.len() - shown));
+    }
+    lines.push(String::new());
+    lines.push(footer);
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::charge::Charge;
+
+    #[test]
+    fn test_progress_bar() {
+        assert_eq!(progress_bar(0.0, 4), "[----]");
+        assert_eq!(progress_bar(0.5, 4), "[##--]");
+        assert_eq!(progress_bar(1.5, 4), "[####]");
+    }
+
+    #[test]
+    fn test_render() {
+        let mut charge = Charge::new(CONF.charge.charge_type, 30.0, 10);
+        for id in 1..=6 {
+            charge
+                .add_detail(ChargingDetail::test_new(id).with_request_amount(10.0))
+                .unwrap();
+        }
+        charge.start_charging();
+        let status = charge.status_snapshot(get_mock_now());
+        let lines = render(&status, false, 10.0, Utc::now(), 40);
+        let text = lines.join("\n");
+        assert!(text.contains("状态: 充电中"), "{}", text);
+        assert!(text.contains("加速倍数: 10"), "{}", text);
+        assert!(text.contains("  详单 1 [---"), "{}", text);
+        assert!(text.contains("排队: 5 个详单"), "{}", text);
+        assert!(
+            text.contains("  1. 详单 2 请求 10.00 kWh，预计开始: "),
+            "{}",
+            text
+        );
+        assert!(!text.contains("预计开始: 未知"), "{}", text);
+
+        // 终端高度不够时省略排队的详单
+        let lines = render(&status, false, 10.0, Utc::now(), 12);
+        assert!(lines.len() <= 12, "{:?}", lines);
+        assert!(
+            lines.iter().any(|line| line.contains("还有")),
+            "{:?}",
+            lines
+        );
+
+        let mut closed = Charge::new(CONF.charge.charge_type, 30.0, 10);
+        closed.close_by_server().unwrap();
+        let text = render(
+            &closed.status_snapshot(get_mock_now()),
+            false,
+            1.0,
+            Utc::now(),
+            40,
+        )
+        .join("\n");
+        assert!(text.contains("状态: 关闭") && text.contains("正在充电: 无"));
+    }
+}
@@ -0,0 +1,152 @@
+//! 充电状态更新编码
+//!
+//! 完整模式下每次更新都发送完整详单。增量模式下定期更新只发送变化的字段，
+//! 每隔 `snapshot_every` 次更新以及开始、完成、中断时发送完整快照，方便服务器在丢包后重新同步。
+
+use std::collections::HashMap;
+
+use crate::conf::UpdateMode;
+use crate::detail::ChargingDetail;
+use crate::message::{MSG, MessageType};
+
+/// 充电状态更新编码器，多把充电枪同时充电时每个详单分别计算增量
+pub struct UpdateEncoder {
+    /// 更新方式
+    mode: UpdateMode,
+    /// 每隔多少次更新发送一次完整快照
+    snapshot_every: u32,
+    /// 每个详单上一次发送的内容和自上一次完整快照以来发送的增量数
+    last: HashMap<u32, (ChargingDetail, u64)>,
+}
+
+impl UpdateEncoder {
+    /// 创建编码器
+    pub fn new(mode: UpdateMode, snapshot_every: u32) -> Self {
+        UpdateEncoder {
+            mode,
+            snapshot_every: snapshot_every.max(1),
+            last: HashMap::new(),
+        }
+    }
+
+    /// 编码一次完整快照
+    pub fn snapshot(&mut self, detail: &ChargingDetail) -> MSG {
+        self.last.insert(detail.get_id(), (detail.clone(), 0));
+        MSG::with_payload(MessageType::Update, detail)
+    }
+
+    /// 下一次定期更新发送完整快照，用于增量更新被丢弃后重新同步
+    pub fn resync(&mut self) {
+        self.last.clear();
+    }
+
+    /// 只保留指定详单的记录，不再充电的详单下一次更新时发送完整快照
+    pub fn retain(&mut self, ids: &[u32]) {
+        self.last.retain(|id, _| ids.contains(id));
+    }
+
+    /// 编码一次定期更新，增量模式下按需发送增量或完整快照
+    pub fn progress(&mut self, detail: &ChargingDetail) -> MSG {
+        if self.mode.is_full() {
+            return self.snapshot(detail);
+        }
+        match self.last.get_mut(&detail.get_id()) {
+            Some((last, seq)) if *seq + 1 < self.snapshot_every as u64 => {
+                *seq += 1;
+                let delta = detail.delta_since(last, *seq);
+                *last = detail.clone();
+                MSG::with_payload(MessageType::Delta, &delta)
+            }
+            _ => self.snapshot(detail),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::detail::DetailDelta;
+    use chrono::{DateTime, Duration, Utc};
+
+    #[test]
+    fn test_full_mode_sends_snapshots() {
+        let mut encoder = UpdateEncoder::new(UpdateMode::Full, 10);
+        let detail = ChargingDetail::test_new(1);
+        let msg = encoder.progress(&detail);
+        assert_eq!(msg.type_, MessageType::Update);
+        assert_eq!(msg.data, serde_json::to_value(&detail).unwrap());
+    }
+
+    #[test]
+    fn test_deltas_reconstruct_snapshots() {
+        let start = DateTime::parse_from_rfc3339("2025-06-01T10:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let mut encoder = UpdateEncoder::new(UpdateMode::Delta, 4);
+        let mut detail = ChargingDetail::test_new(1);
+        detail.start(start, 30.0, &crate::curve::PowerCurve::default());
+
+        // 服务器端根据快照和增量重建的详单
+        let msg = encoder.snapshot(&detail);
+        let mut server: ChargingDetail = msg.payload().unwrap();
+        let mut kinds = Vec::new();
+        for i in 1..=9 {
+            let charged = i as f64;
+            detail.update_state(
+                charged,
+                charged * 0.5,
+                charged * 0.8,
+                start + Duration::minutes(i),
+            );
+            let msg = encoder.progress(&detail);
+            kinds.push(msg.type_);
+            match msg.type_ {
+                MessageType::Delta => {
+                    let delta: DetailDelta = msg.payload().unwrap();
+                    server.apply_delta(&delta).unwrap();
+                }
+                MessageType::Update => {
+                    server = msg.payload().unwrap();
+                }
+                _ => unreachable!(),
+            }
+            assert_eq!(
+                serde_json::to_string(&server).unwrap(),
+                serde_json::to_string(&detail).unwrap()
+            );
+        }
+        use MessageType::{Delta, Update};
+        assert_eq!(
+            kinds,
+            vec![
+                Delta, Delta, Delta, Update, Delta, Delta, Delta, Update, Delta
+            ]
+        );
+    }
+
+    #[test]
+    fn test_resync_sends_snapshot() {
+        let mut encoder = UpdateEncoder::new(UpdateMode::Delta, 10);
+        let detail = ChargingDetail::test_new(1);
+        encoder.snapshot(&detail);
+        assert_eq!(encoder.progress(&detail).type_, MessageType::Delta);
+        encoder.resync();
+        assert_eq!(encoder.progress(&detail).type_, MessageType::Update);
+        assert_eq!(encoder.progress(&detail).type_, MessageType::Delta);
+    }
+
+    #[test]
+    fn test_deltas_per_detail() {
+        let mut encoder = UpdateEncoder::new(UpdateMode::Delta, 10);
+        let first = ChargingDetail::test_new(1);
+        let second = ChargingDetail::test_new(2);
+        encoder.snapshot(&first);
+        encoder.snapshot(&second);
+        // 两个详单交替更新时各自发送增量
+        assert_eq!(encoder.progress(&first).type_, MessageType::Delta);
+        assert_eq!(encoder.progress(&second).type_, MessageType::Delta);
+        encoder.retain(&[2]);
+        assert_eq!(encoder.progress(&first).type_, MessageType::Update);
+        assert_eq!(encoder.progress(&second).type_, MessageType::Delta);
+    }
+}
@@ -0,0 +1,235 @@
+//! 连接看门狗
+
+use tokio::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// 连接健康状态
+pub enum ConnectionHealth {
+    /// 未连接
+    Disconnected,
+    /// 已注册，等待服务器的第一条消息
+    Registering,
+    /// 已连接但服务器一直没有响应
+    ConnectedIdle,
+    /// 正常
+    Healthy,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// 看门狗到期后需要执行的操作
+pub enum WatchdogAction {
+    /// 发送探测消息，并再等待一个窗口
+    Probe,
+    /// 只记录警告，并再等待一个窗口
+    Warn,
+    /// 断开连接
+    Teardown,
+}
+
+#[derive(Debug)]
+/// 注册后空闲看门狗
+/// 注册后在指定时间内没有收到任何应用消息时先警告（可选探测），再过一个窗口仍无消息则断开连接
+pub struct IdleWatchdog {
+    /// 等待窗口，为零时不启用
+    window: Duration,
+    /// 第一个窗口到期时是否发送探测消息
+    probe: bool,
+    /// 下一次到期时间
+    deadline: Option<Instant>,
+    /// 当前状态
+    health: ConnectionHealth,
+}
+
+impl IdleWatchdog {
+    /// 创建看门狗
+    pub fn new(window: Duration, probe: bool) -> Self {
+        IdleWatchdog {
+            window,
+            probe,
+            deadline: None,
+            health: ConnectionHealth::Disconnected,
+        }
+    }
+
+    /// 注册后开始计时
+    pub fn arm(&mut self, now: Instant) {
+        self.health = ConnectionHealth::Registering;
+        if !self.window.is_zero() {
+            self.deadline = Some(now + self.window);
+        }
+    }
+
+    /// 收到应用消息
+    pub fn on_inbound(&mut self) {
+        if self.health != ConnectionHealth::Healthy {
+            tracing::debug!("收到服务器消息，连接状态: {:?} -> Healthy", self.health);
+        }
+        self.deadline = None;
+        self.health = ConnectionHealth::Healthy;
+    }
+
+    /// 连接已断开
+    pub fn on_disconnect(&mut self) {
+        self.deadline = None;
+        self.health = ConnectionHealth::Disconnected;
+    }
+
+    /// 下一次到期时间
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// 当前连接健康状态
+    pub fn health(&self) -> ConnectionHealth {
+        self.health
+    }
+
+    /// 到期处理，返回需要执行的操作
+    pub fn expire(&mut self, now: Instant) -> WatchdogAction {
+        match self.health {
+            ConnectionHealth::Registering => {
+                self.health = ConnectionHealth::ConnectedIdle;
+                self.deadline = Some(now + self.window);
+                if self.probe {
+                    WatchdogAction::Probe
+                } else {
+                    WatchdogAction::Warn
+                }
+            }
+            _ => {
+                self.on_disconnect();
+                WatchdogAction::Teardown
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+/// 心跳计时
+/// 每个间隔发送一次心跳，连续 `max_missed` 个间隔没有收到任何入站消息时认为连接已断开
+pub struct Heartbeat {
+    /// 心跳间隔，为零时不启用
+    interval: Duration,
+    /// 最多允许连续多少个间隔没有入站消息，为 0 时不检查
+    max_missed: u32,
+    /// 最后一次收到入站消息的时间
+    last_heard: Instant,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// 心跳到期后需要执行的操作
+pub enum HeartbeatAction {
+    /// 发送心跳
+    Send,
+    /// 连接已断开
+    Dead,
+}
+
+impl Heartbeat {
+    /// 创建心跳计时，从 `now` 开始计算没有入站消息的时间
+    pub fn new(interval: Duration, max_missed: u32, now: Instant) -> Self {
+        Heartbeat {
+            interval,
+            max_missed,
+            last_heard: now,
+        }
+    }
+
+    /// 心跳间隔，不启用时为 `None`
+    pub fn interval(&self) -> Option<Duration> {
+        (!self.interval.is_zero()).then_some(self.interval)
+    }
+
+    /// 收到任何入站消息，包括 Ping 和 Pong
+    pub fn on_inbound(&mut self, now: Instant) {
+        self.last_heard = now;
+    }
+
+    /// 心跳间隔到期，返回需要执行的操作
+    pub fn tick(&self, now: Instant) -> HeartbeatAction {
+        if self.max_missed > 0
+            && now.duration_since(self.last_heard) >= self.interval * self.max_missed
+        {
+            HeartbeatAction::Dead
+        } else {
+            HeartbeatAction::Send
+        }
+    }
+}
+
+/// 等待看门狗到期，没有到期时间时永远等待
+pub async fn wait_deadline(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline).await,
+        None => futures_util::future::pending::<()>().await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_silent_server_is_torn_down() {
+        let mut watchdog = IdleWatchdog::new(Duration::from_secs(30), true);
+        assert_eq!(watchdog.health(), ConnectionHealth::Disconnected);
+        let start = Instant::now();
+        watchdog.arm(start);
+        assert_eq!(watchdog.health(), ConnectionHealth::Registering);
+
+        wait_deadline(watchdog.deadline()).await;
+        assert_eq!(start.elapsed(), Duration::from_secs(30));
+        assert_eq!(watchdog.expire(Instant::now()), WatchdogAction::Probe);
+        assert_eq!(watchdog.health(), ConnectionHealth::ConnectedIdle);
+
+        wait_deadline(watchdog.deadline()).await;
+        assert_eq!(start.elapsed(), Duration::from_secs(60));
+        assert_eq!(watchdog.expire(Instant::now()), WatchdogAction::Teardown);
+        assert_eq!(watchdog.health(), ConnectionHealth::Disconnected);
+        assert!(watchdog.deadline().is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_inbound_message_disarms() {
+        let mut watchdog = IdleWatchdog::new(Duration::from_secs(30), false);
+        watchdog.arm(Instant::now());
+        wait_deadline(watchdog.deadline()).await;
+        assert_eq!(watchdog.expire(Instant::now()), WatchdogAction::Warn);
+        assert_eq!(watchdog.health(), ConnectionHealth::ConnectedIdle);
+
+        watchdog.on_inbound();
+        assert_eq!(watchdog.health(), ConnectionHealth::Healthy);
+        assert!(watchdog.deadline().is_none());
+    }
+
+    #[test]
+    fn test_heartbeat_detects_silent_peer() {
+        let start = Instant::now();
+        let second = Duration::from_secs(1);
+        let mut heartbeat = Heartbeat::new(Duration::from_secs(10), 3, start);
+        assert_eq!(heartbeat.interval(), Some(Duration::from_secs(10)));
+        assert_eq!(heartbeat.tick(start + 10 * second), HeartbeatAction::Send);
+        assert_eq!(heartbeat.tick(start + 20 * second), HeartbeatAction::Send);
+        // 收到 Pong 后重新计算
+        heartbeat.on_inbound(start + 25 * second);
+        assert_eq!(heartbeat.tick(start + 30 * second), HeartbeatAction::Send);
+        assert_eq!(heartbeat.tick(start + 50 * second), HeartbeatAction::Send);
+        assert_eq!(heartbeat.tick(start + 55 * second), HeartbeatAction::Dead);
+
+        // 不检查时只发送心跳
+        let heartbeat = Heartbeat::new(Duration::from_secs(10), 0, start);
+        assert_eq!(heartbeat.tick(start + 3600 * second), HeartbeatAction::Send);
+        assert!(
+            Heartbeat::new(Duration::ZERO, 3, start)
+                .interval()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_disabled_watchdog() {
+        let mut watchdog = IdleWatchdog::new(Duration::ZERO, true);
+        watchdog.arm(Instant::now());
+        assert!(watchdog.deadline().is_none());
+    }
+}
@@ -0,0 +1,317 @@
+//! 生命周期 Webhook
+//!
+//! 订阅充电桩的生命周期事件，并以 HTTP POST 的方式发送到配置的 URL。
+//! 同一个详单的事件按发生顺序依次发送，不同详单之间互不阻塞。
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::sync::{broadcast, mpsc};
+use tokio::task::JoinHandle;
+use tokio::time::{Duration, sleep, timeout};
+
+use crate::conf::WebhookConf;
+use crate::event::{ChargeEvent, LifecycleEvent};
+
+/// 启动 Webhook 发送任务
+/// 只发送详单的生命周期事件，充电进度和充电桩事件被忽略
+pub fn spawn(conf: WebhookConf, rx: broadcast::Receiver<ChargeEvent>) -> JoinHandle<()> {
+    tokio::spawn(run(Arc::new(conf), rx))
+}
+
+/// 分发事件到每个详单各自的发送队列
+async fn run(conf: Arc<WebhookConf>, mut rx: broadcast::Receiver<ChargeEvent>) {
+    let mut workers: HashMap<u32, mpsc::UnboundedSender<LifecycleEvent>> = HashMap::new();
+    loop {
+        match rx.recv().await.map(|event| event.lifecycle()) {
+            Ok(None) => {}
+            Ok(Some(event)) => {
+                let id = event.detail.get_id();
+                let terminal = event.event.is_terminal();
+                let worker = workers
+                    .entry(id)
+                    .or_insert_with(|| spawn_worker(conf.clone()));
+                let _ = worker.send(event);
+                if terminal {
+                    // 详单结束后关闭其发送队列，发送任务处理完剩余事件后退出
+                    workers.remove(&id);
+                }
+            }
+            Err(broadcast::error::RecvError::Lagged(n)) => {
+                tracing::warn!("Webhook 处理过慢，丢失 {} 个生命周期事件", n);
+            }
+            Err(broadcast::error::RecvError::Closed) => break,
+        }
+    }
+}
+
+/// 启动单个详单的发送任务
+fn spawn_worker(conf: Arc<WebhookConf>) -> mpsc::UnboundedSender<LifecycleEvent> {
+    let (tx, mut rx) = mpsc::unbounded_channel::<LifecycleEvent>();
+    tokio::spawn(async move {
+        while let Some(event) = rx.recv().await {
+            deliver(&conf, &event).await;
+        }
+    });
+    tx
+}
+
+/// 发送一个事件，失败时按配置重试
+async fn deliver(conf: &WebhookConf, event: &LifecycleEvent) {
+    let Some(url) = conf.url_for(event.event) else {
+        return;
+    };
+    let body = serde_json::to_string(event).unwrap();
+    for attempt in 0..=conf.retries {
+        let result = timeout(
+            Duration::from_millis(conf.timeout),
+            post(url, &body, conf.auth_token.as_deref()),
+        )
+        .await
+        .unwrap_or_else(|_| Err("request timed out".to_string()));
+        match result {
+            Ok(status) if (200..300).contains(&status) => {
+                tracing::debug!(
+                    "Webhook {:?} 发送成功: 详单 {}",
+                    event.event,
+                    event.detail.get_id()
+                );
+                return;
+            }
+            Ok(status) => tracing::warn!(
+                "Webhook {:?} 发送失败（第 {} 次）: HTTP {}",
+                event.event,
+                attempt + 1,
+                status
+            ),
+            Err(e) => tracing::warn!(
+                "Webhook {:?} 发送失败（第 {} 次）: {}",
+                event.event,
+                attempt + 1,
+                e
+            ),
+        }
+        if attempt < conf.retries {
+            sleep(Duration::from_millis(conf.retry_delay)).await;
+        }
+    }
+    tracing::error!(
+        "Webhook {:?} 重试 {} 次后仍然失败，放弃发送: 详单 {}",
+        event.event,
+        conf.retries,
+        event.detail.get_id()
+    );
+}
+
+/// 解析 `http://host[:port][/path]` 格式的 URL，返回连接地址、Host 头和路径
+fn parse_http_url(url: &str) -> Result<(String, String, String), String> {
+    let rest = url.strip_prefix("http://").ok_or_else(|| {
+        format!(
+            "unsupported webhook URL (only http:// is supported): {}",
+            url
+        )
+    })?;
+    let (authority, path) = match rest.find('/') {
+        Some(pos) => (&rest[..pos], &rest[pos..]),
+        None => (rest, "/"),
+    };
+    if authority.is_empty() {
+        return Err(format!("missing host in webhook URL: {}", url));
+    }
+    let addr = if authority.contains(':') {
+        authority.to_string()
+    } else {
+        format!("{}:80", authority)
+    };
+    Ok((addr, authority.to_string(), path.to_string()))
+}
+
+/// 发送 HTTP POST 请求，返回响应状态码
+async fn post(url: &str, body: &str, auth_token: Option<&str>) -> Result<u16, String> {
+    let (addr, host, path) = parse_http_url(url)?;
+    let mut stream = TcpStream::connect(&addr)
+        .await
+        .map_err(|e| format!("failed to connect to {}: {}", addr, e))?;
+    let mut request = format!(
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n",
+        path,
+        host,
+        body.len()
+    );
+    if let Some(token) = auth_token {
+        request.push_str(&format!("Authorization: Bearer {}\r\n", token));
+    }
+    request.push_str("\r\n");
+    request.push_str(body);
+    stream
+        .write_all(request.as_bytes())
+        .await
+        .map_err(|e| format!("failed to send request: {}", e))?;
+
+    let mut status_line = String::new();
+    BufReader::new(stream)
+        .read_line(&mut status_line)
+        .await
+        .map_err(|e| format!("failed to read response: {}", e))?;
+    status_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|code| code.parse().ok())
+        .ok_or_else(|| format!("invalid HTTP response: {:?}", status_line.trim_end()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::detail::ChargingDetail;
+    use crate::event::LifecycleEventType;
+    use chrono::Utc;
+    use tokio::io::AsyncReadExt;
+    use tokio::net::TcpListener;
+
+    /// 启动本地 HTTP 服务器，把收到的请求头和请求体发送到通道
+    async fn start_server() -> (String, mpsc::UnboundedReceiver<(String, String)>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (tx, rx) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut data = Vec::new();
+                let mut buf = [0u8; 1024];
+                let (head, body_start, length) = loop {
+                    let n = stream.read(&mut buf).await.unwrap();
+                    data.extend_from_slice(&buf[..n]);
+                    let text = String::from_utf8_lossy(&data).to_string();
+                    if let Some(pos) = text.find("\r\n\r\n") {
+                        let head = text[..pos].to_string();
+                        let length: usize = head
+                            .lines()
+                            .find_map(|l| l.strip_prefix("Content-Length: "))
+                            .unwrap()
+                            .parse()
+                            .unwrap();
+                        break (head, pos + 4, length);
+                    }
+                };
+                while data.len() < body_start + length {
+                    let n = stream.read(&mut buf).await.unwrap();
+                    data.extend_from_slice(&buf[..n]);
+                }
+                let body = String::from_utf8_lossy(&data[body_start..]).to_string();
+                stream
+                    .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")
+                    .await
+                    .unwrap();
+                tx.send((head, body)).unwrap();
+            }
+        });
+        (format!("http://{}", addr), rx)
+    }
+
+    #[test]
+    fn test_parse_http_url() {
+        assert_eq!(
+            parse_http_url("http://example.com/hook").unwrap(),
+            (
+                "example.com:80".to_string(),
+                "example.com".to_string(),
+                "/hook".to_string()
+            )
+        );
+        assert_eq!(
+            parse_http_url("http://127.0.0.1:9000").unwrap().2,
+            "/".to_string()
+        );
+        assert!(parse_http_url("https://example.com/hook").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_events_delivered_in_order() {
+        let (base, mut requests) = start_server().await;
+        let conf = WebhookConf {
+            on_admitted: Some(format!("{}/admitted", base)),
+            on_started: Some(format!("{}/started", base)),
+            on_completed: Some(format!("{}/completed", base)),
+            on_interrupted: Some(format!("{}/interrupted", base)),
+            auth_token: Some("secret".to_string()),
+            ..WebhookConf::default()
+        };
+        let (tx, rx) = broadcast::channel(16);
+        let handle = spawn(conf, rx);
+
+        // 一个完成的会话和一个被中断的会话
+        let sessions = [
+            (
+                7,
+                vec![
+                    LifecycleEventType::Admitted,
+                    LifecycleEventType::Started,
+                    LifecycleEventType::Completed,
+                ],
+            ),
+            (
+                8,
+                vec![
+                    LifecycleEventType::Admitted,
+                    LifecycleEventType::Started,
+                    LifecycleEventType::Interrupted,
+                ],
+            ),
+        ];
+        for (id, events) in &sessions {
+            for event in events {
+                let (virtual_time, detail) = (Utc::now(), ChargingDetail::test_new(*id));
+                let event = match event {
+                    LifecycleEventType::Admitted => ChargeEvent::DetailQueued {
+                        virtual_time,
+                        detail,
+                    },
+                    LifecycleEventType::Started => ChargeEvent::ChargingStarted {
+                        virtual_time,
+                        detail,
+                    },
+                    LifecycleEventType::Completed => ChargeEvent::Completed {
+                        virtual_time,
+                        detail,
+                    },
+                    LifecycleEventType::Interrupted => ChargeEvent::Interrupted {
+                        virtual_time,
+                        detail,
+                    },
+                };
+                assert!(tx.send(event).is_ok());
+            }
+            // 充电进度和充电桩事件不发送
+            let virtual_time = Utc::now();
+            assert!(
+                tx.send(ChargeEvent::Progress {
+                    virtual_time,
+                    detail: ChargingDetail::test_new(*id),
+                })
+                .is_ok()
+            );
+            assert!(tx.send(ChargeEvent::PileClosed { virtual_time }).is_ok());
+        }
+
+        let mut received: HashMap<u32, Vec<LifecycleEventType>> = HashMap::new();
+        for _ in 0..6 {
+            let (head, body) = requests.recv().await.unwrap();
+            let event: LifecycleEvent = serde_json::from_str(&body).unwrap();
+            let path = serde_json::to_value(event.event).unwrap();
+            assert!(head.starts_with(&format!("POST /{} HTTP/1.1", path.as_str().unwrap())));
+            assert!(head.contains("Authorization: Bearer secret"));
+            received
+                .entry(event.detail.get_id())
+                .or_default()
+                .push(event.event);
+        }
+        for (id, events) in sessions {
+            assert_eq!(received[&id], events);
+        }
+
+        drop(tx);
+        handle.await.unwrap();
+    }
+}
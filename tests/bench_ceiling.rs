@@ -0,0 +1,32 @@
+//! 模拟速度回归测试：运行一段较短的合成充电会话，每次更新的耗时不能超过上限
+//!
+//! 耗时受主机负载影响，默认不运行，需要用 `cargo test --test bench_ceiling -- --ignored` 显式运行。
+//! 默认上限按未优化的测试构建留有充足余量，只用于发现数量级的退化；
+//! 较慢的 CI 主机可以用环境变量 `TARANIS_BENCH_MAX_US_PER_UPDATE` 放宽上限
+
+use taranis::bench;
+
+/// 默认的每次更新耗时上限，单位为微秒
+const DEFAULT_MAX_US_PER_UPDATE: f64 = 1000.0;
+
+/// 覆盖上限的环境变量
+const CEILING_ENV: &str = "TARANIS_BENCH_MAX_US_PER_UPDATE";
+
+#[test]
+#[ignore = "timing-sensitive, run with --ignored"]
+fn test_us_per_update_below_ceiling() {
+    let ceiling = match std::env::var(CEILING_ENV) {
+        Ok(value) => value
+            .parse()
+            .unwrap_or_else(|_| panic!("{} must be a number, got {:?}", CEILING_ENV, value)),
+        Err(_) => DEFAULT_MAX_US_PER_UPDATE,
+    };
+    // 先运行一小段预热，避免首次分配和懒加载的配置计入耗时
+    bench::run(chrono::Duration::minutes(10), chrono::Duration::seconds(5));
+    let report = bench::run(chrono::Duration::hours(2), chrono::Duration::seconds(5));
+    assert_eq!(report.updates, 1440);
+    println!("{}", report);
+    if let Err(e) = bench::check_ceiling(&report, ceiling) {
+        panic!("{} (set {} to adjust)", e, CEILING_ENV);
+    }
+}
@@ -0,0 +1,144 @@
+//! 集成测试共用的进程内服务器：收发消息、接受充电桩连接和注册，
+//! 写入临时配置文件并通过 `client::run_client` 运行充电桩
+//!
+//! 每个测试文件只使用其中一部分函数
+
+#![allow(dead_code)]
+
+use std::path::{Path, PathBuf};
+
+use futures_util::{SinkExt, StreamExt};
+use taranis::client;
+use taranis::conf::{self, CONF, ConfOverrides};
+use taranis::message::{MSG, MessageType, RegisterAckData, RegisterPayload, StatusData};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+use tokio::time::{Duration, timeout};
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{WebSocketStream, accept_async, connect_async_with_config};
+
+pub type Server = WebSocketStream<TcpStream>;
+
+/// 等待一条消息的最长时间
+pub const RECV_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// 临时配置文件，离开作用域时删除，测试失败时同样删除
+pub struct TempConfig {
+    path: PathBuf,
+}
+
+impl TempConfig {
+    /// 在临时目录中写入配置文件，文件名以 `taranis-{name}-` 开头
+    pub fn new(name: &str, content: &str) -> Self {
+        let path =
+            std::env::temp_dir().join(format!("taranis-{}-{}.toml", name, uuid::Uuid::new_v4()));
+        std::fs::write(&path, content).unwrap();
+        TempConfig { path }
+    }
+
+    /// 配置文件路径
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// 把临时配置设为本进程的配置，必须在第一次访问 `CONF` 之前调用
+    pub fn install(&self) {
+        conf::init_overrides(ConfOverrides {
+            config: Some(self.path.to_string_lossy().into_owned()),
+            ..ConfOverrides::default()
+        });
+    }
+}
+
+impl Drop for TempConfig {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// 在新任务中连接 `url`，按全局的 `CONF` 运行充电桩直到连接断开
+pub fn spawn_pile(url: String) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let (ws_stream, _) = connect_async_with_config(url, Some(client::ws_config(&CONF)), false)
+            .await
+            .unwrap();
+        client::run_client(&CONF, ws_stream).await;
+    })
+}
+
+/// 接受充电桩的连接
+pub async fn accept(listener: &TcpListener) -> Server {
+    let (stream, _) = timeout(RECV_TIMEOUT, listener.accept())
+        .await
+        .expect("timed out waiting for the pile")
+        .unwrap();
+    accept_async(stream).await.unwrap()
+}
+
+/// 接受充电桩的连接，接收注册消息并回复同意的注册确认
+pub async fn accept_register(listener: &TcpListener) -> (Server, RegisterPayload) {
+    accept_register_with(listener, RegisterAckData::accept).await
+}
+
+/// 接受充电桩的连接，接收注册消息并回复 `ack` 生成的注册确认
+pub async fn accept_register_with(
+    listener: &TcpListener,
+    ack: impl FnOnce(&RegisterPayload) -> RegisterAckData,
+) -> (Server, RegisterPayload) {
+    let mut server = accept(listener).await;
+    let payload: RegisterPayload = recv_type(&mut server, MessageType::Register)
+        .await
+        .payload()
+        .unwrap();
+    send(
+        &mut server,
+        &MSG::with_payload(MessageType::RegisterAck, &ack(&payload)),
+    )
+    .await;
+    (server, payload)
+}
+
+pub async fn send(server: &mut Server, msg: &MSG) {
+    server
+        .send(Message::Text(serde_json::to_string(msg).unwrap().into()))
+        .await
+        .unwrap();
+}
+
+/// 接收下一条文本消息，跳过 Ping、Pong 等其他帧
+pub async fn recv(server: &mut Server) -> MSG {
+    loop {
+        let message = timeout(RECV_TIMEOUT, server.next())
+            .await
+            .expect("timed out waiting for a message")
+            .expect("connection closed")
+            .unwrap();
+        if let Message::Text(text) = message {
+            return serde_json::from_str(&text).unwrap();
+        }
+    }
+}
+
+/// 接收消息直到 `done` 返回真，返回最后一条消息
+pub async fn recv_until(server: &mut Server, done: impl Fn(&MSG) -> bool) -> MSG {
+    loop {
+        let msg = recv(server).await;
+        if done(&msg) {
+            return msg;
+        }
+    }
+}
+
+/// 接收指定类型的消息
+pub async fn recv_type(server: &mut Server, type_: MessageType) -> MSG {
+    recv_until(server, |msg| msg.type_ == type_).await
+}
+
+/// 查询充电桩状态
+pub async fn query(server: &mut Server) -> StatusData {
+    send(server, &MSG::empty(MessageType::Query)).await;
+    recv_type(server, MessageType::Status)
+        .await
+        .payload()
+        .unwrap()
+}
@@ -0,0 +1,219 @@
+//! 端到端测试：在进程内启动 WebSocket 服务器，通过 `client::run_client` 运行充电桩，
+//! 按服务器收到的消息检查完整的充电流程
+
+mod common;
+
+use common::{RECV_TIMEOUT, Server};
+use futures_util::{SinkExt, StreamExt};
+use serde_json::Value;
+use taranis::conf::{self, CONF, ConfOverrides};
+use taranis::detail::{ChargeStatus, ChargingDetail};
+use taranis::message::{
+    MSG, MessageType, MsgAckData, PROTOCOL_VERSION, RegisterAckData, RegisterPayload, StatusData,
+};
+use taranis::outbox;
+use taranis::price;
+use tokio::net::TcpListener;
+use tokio::time::timeout;
+use tokio_tungstenite::tungstenite::Message;
+
+/// 测试使用的时间加速比，5 kWh 的详单在 30 kW 下约 1 秒充满
+const SPEED: f64 = 600.0;
+
+async fn send(server: &mut Server, type_: MessageType, data: Value) {
+    common::send(server, &MSG::new(type_, data)).await;
+}
+
+async fn send_detail(server: &mut Server, type_: MessageType, detail: &ChargingDetail) {
+    send(server, type_, serde_json::to_value(detail).unwrap()).await;
+}
+
+/// 接收下一条消息，带有消息 ID 的完成和故障消息回复确认
+async fn recv(server: &mut Server) -> MSG {
+    loop {
+        let message = timeout(RECV_TIMEOUT, server.next())
+            .await
+            .expect("timed out waiting for a message")
+            .expect("connection closed")
+            .unwrap();
+        let Ok(text) = message.to_text() else {
+            continue;
+        };
+        if !message.is_text() {
+            continue;
+        }
+        let msg: MSG = serde_json::from_str(text).unwrap();
+        if outbox::needs_ack(msg.type_)
+            && let Some(msg_id) = msg.msg_id
+        {
+            let ack = serde_json::to_value(MsgAckData { msg_id }).unwrap();
+            send(server, MessageType::Ack, ack).await;
+        }
+        return msg;
+    }
+}
+
+/// 接收消息直到 `done` 返回真，返回包括最后一条在内的所有消息
+async fn recv_until(server: &mut Server, done: impl Fn(&MSG) -> bool) -> Vec<MSG> {
+    let mut received = Vec::new();
+    loop {
+        let msg = recv(server).await;
+        let finished = done(&msg);
+        received.push(msg);
+        if finished {
+            return received;
+        }
+    }
+}
+
+/// 消息中的详单，不是详单时返回 `None`
+fn detail_of(msg: &MSG) -> Option<ChargingDetail> {
+    msg.payload().ok()
+}
+
+/// 是否为指定详单、指定状态的消息
+fn is_detail(msg: &MSG, type_: MessageType, id: u32, status: ChargeStatus) -> bool {
+    msg.type_ == type_
+        && detail_of(msg)
+            .is_some_and(|detail| detail.get_id() == id && detail.get_status() == status)
+}
+
+#[tokio::test]
+async fn test_charge_lifecycle() {
+    conf::init_overrides(ConfOverrides {
+        speed: Some(SPEED),
+        size: Some(2),
+        allow_default_config: true,
+        ..ConfOverrides::default()
+    });
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let pile = common::spawn_pile(format!("ws://{}", listener.local_addr().unwrap()));
+    let mut server = common::accept(&listener).await;
+
+    // 注册
+    let register = recv(&mut server).await;
+    assert_eq!(register.type_, MessageType::Register);
+    assert_eq!(register.data["power"].as_f64(), Some(CONF.charge.power));
+    let payload: RegisterPayload = register.payload().unwrap();
+    assert_eq!(payload.protocol_version, PROTOCOL_VERSION);
+    assert_eq!(payload.speed, SPEED);
+    let ack = RegisterAckData::accept(&payload);
+    send(
+        &mut server,
+        MessageType::RegisterAck,
+        serde_json::to_value(ack).unwrap(),
+    )
+    .await;
+
+    // 服务器发送的 Ping 得到 Pong 回复
+    server
+        .send(Message::Ping(b"probe".to_vec().into()))
+        .await
+        .unwrap();
+    loop {
+        let message = timeout(RECV_TIMEOUT, server.next())
+            .await
+            .expect("timed out waiting for pong")
+            .expect("connection closed")
+            .unwrap();
+        if let Message::Pong(payload) = message {
+            assert_eq!(&payload[..], b"probe");
+            break;
+        }
+    }
+
+    // 新详单充电到完成，期间的状态更新已充电度数单调不减
+    // 服务器附加的字段在状态更新和完成消息中原样回传
+    let first = ChargingDetail::test_new(1).with_request_amount(5.0);
+    let mut value = serde_json::to_value(&first).unwrap();
+    value["user_id"] = "u-42".into();
+    value["order_no"] = "A-1001".into();
+    send(&mut server, MessageType::New, value).await;
+    let received = recv_until(&mut server, |msg| msg.type_ == MessageType::Complete).await;
+    assert!(
+        received
+            .iter()
+            .filter(|msg| matches!(msg.type_, MessageType::Update | MessageType::Complete))
+            .all(|msg| msg.data["user_id"] == "u-42" && msg.data["order_no"] == "A-1001")
+    );
+    let updates: Vec<ChargingDetail> = received
+        .iter()
+        .filter(|msg| msg.type_ == MessageType::Update)
+        .filter_map(detail_of)
+        .collect();
+    assert!(
+        updates.len() >= 2,
+        "expected periodic updates, got {}",
+        updates.len()
+    );
+    assert!(
+        updates
+            .iter()
+            .all(|detail| detail.get_id() == 1 && detail.get_status() == ChargeStatus::Charging)
+    );
+    assert!(
+        updates
+            .windows(2)
+            .all(|pair| pair[0].get_already_charged() <= pair[1].get_already_charged())
+    );
+    let complete = detail_of(received.last().unwrap()).unwrap();
+    assert_eq!(complete.get_id(), 1);
+    assert_eq!(complete.get_status(), ChargeStatus::Completed);
+    assert_eq!(complete.get_user_id(), Some("u-42"));
+    // 完成时按结束时间结算，已充电度数不少于请求度数
+    assert!(complete.get_already_charged() >= 5.0 - 1e-6);
+    assert!(complete.get_already_charged() >= updates.last().unwrap().get_already_charged());
+    let (cost, fee) = price::calc_price_with_tz(
+        complete.get_start_time().unwrap(),
+        complete.get_end_time().unwrap(),
+        CONF.charge.power,
+        0.0,
+    )
+    .unwrap();
+    assert!(
+        (complete.get_total_cost() - (cost + fee)).abs() < 1e-6,
+        "total_cost {} != {} + {}",
+        complete.get_total_cost(),
+        cost,
+        fee
+    );
+
+    // 取消排队中的详单
+    let second = ChargingDetail::test_new(2).with_request_amount(100.0);
+    send_detail(&mut server, MessageType::New, &second).await;
+    recv_until(&mut server, |msg| {
+        is_detail(msg, MessageType::Update, 2, ChargeStatus::Charging)
+    })
+    .await;
+    let third = ChargingDetail::test_new(3);
+    send_detail(&mut server, MessageType::New, &third).await;
+    send_detail(&mut server, MessageType::Cancel, &third).await;
+    recv_until(&mut server, |msg| {
+        is_detail(msg, MessageType::Update, 3, ChargeStatus::Interrupted)
+    })
+    .await;
+
+    // 关闭时中断正在充电的详单并清空队列，重新打开后回复状态快照，之后可以接收新详单
+    send_detail(&mut server, MessageType::New, &ChargingDetail::test_new(4)).await;
+    send(&mut server, MessageType::Close, Value::Null).await;
+    let interrupted = recv_until(&mut server, |msg| {
+        is_detail(msg, MessageType::Update, 2, ChargeStatus::Interrupted)
+    })
+    .await;
+    let interrupted = detail_of(interrupted.last().unwrap()).unwrap();
+    assert!(interrupted.get_already_charged() > 0.0 && interrupted.get_already_charged() < 100.0);
+    assert!(interrupted.get_total_cost() > 0.0);
+    send(&mut server, MessageType::Open, Value::Null).await;
+    let status = recv_until(&mut server, |msg| msg.type_ == MessageType::Status).await;
+    let status: StatusData = status.last().unwrap().payload().unwrap();
+    assert!(!status.working && status.charging.is_none() && status.queue.is_empty());
+    send_detail(&mut server, MessageType::New, &ChargingDetail::test_new(5)).await;
+    recv_until(&mut server, |msg| {
+        is_detail(msg, MessageType::Update, 5, ChargeStatus::Charging)
+    })
+    .await;
+
+    // 服务器断开连接后充电桩结束运行
+    server.close(None).await.unwrap();
+    timeout(RECV_TIMEOUT, pile).await.unwrap().unwrap();
+}
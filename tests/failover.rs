@@ -0,0 +1,76 @@
+//! 故障切换测试：配置主备两个地址，主服务器在充电中断开后，
+//! 充电桩切换到备用服务器重新注册，正在充电的详单继续充电
+
+mod common;
+
+use common::{TempConfig, accept_register, recv_until, send};
+use taranis::conf::CONF;
+use taranis::detail::{ChargeStatus, ChargingDetail};
+use taranis::message::{MSG, MessageType};
+use tokio::net::TcpListener;
+use tokio::time::timeout;
+
+/// 正在充电的指定详单
+fn charging(msg: &MSG, id: u32) -> Option<ChargingDetail> {
+    let detail: ChargingDetail = msg.payload().ok()?;
+    (msg.type_ == MessageType::Update
+        && detail.get_id() == id
+        && detail.get_status() == ChargeStatus::Charging)
+        .then_some(detail)
+}
+
+#[tokio::test]
+async fn test_failover_keeps_charging_detail() {
+    let primary = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let standby = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let primary_url = format!("ws://{}", primary.local_addr().unwrap());
+    let standby_url = format!("ws://{}", standby.local_addr().unwrap());
+    let config = TempConfig::new(
+        "failover",
+        &format!(
+            "[websocket]\nurls = [{:?}, {:?}]\n[time]\nspeed = 600.0\n",
+            primary_url, standby_url
+        ),
+    );
+    config.install();
+    assert_eq!(
+        CONF.websocket.endpoints(),
+        [primary_url.clone(), standby_url.clone()]
+    );
+    let pile = common::spawn_pile(primary_url);
+
+    // 主服务器上开始充电
+    let (mut server, _) = accept_register(&primary).await;
+    let detail = ChargingDetail::test_new(1).with_request_amount(1000.0);
+    send(&mut server, &MSG::with_payload(MessageType::New, &detail)).await;
+    let before = recv_until(&mut server, |msg| charging(msg, 1).is_some()).await;
+    let before = charging(&before, 1).unwrap();
+
+    // 主服务器不关闭握手直接断开，并且不再接受连接
+    drop(server);
+    drop(primary);
+
+    // 切换到备用服务器后在注册消息中给出当前地址和正在充电的详单，之后继续更新
+    let (mut server, register) = accept_register(&standby).await;
+    assert_eq!(register.url.as_deref(), Some(standby_url.as_str()));
+    assert!(
+        register
+            .queue
+            .iter()
+            .any(|queued| queued.get_id() == 1 && queued.get_status() == ChargeStatus::Charging)
+    );
+    let after = recv_until(&mut server, |msg| {
+        charging(msg, 1)
+            .is_some_and(|after| after.get_already_charged() > before.get_already_charged())
+    })
+    .await;
+    assert_eq!(
+        charging(&after, 1).unwrap().get_start_time(),
+        before.get_start_time()
+    );
+
+    // 所有地址都无法连接时充电桩结束运行
+    drop(server);
+    drop(standby);
+    timeout(common::RECV_TIMEOUT, pile).await.unwrap().unwrap();
+}
@@ -0,0 +1,143 @@
+//! 大消息测试：分片发送的数兆字节新详单批次完整接收，二进制帧中的 JSON 按文本处理，
+//! 回复 Ping；超过 `websocket.max_message_size` 的消息被拒绝，连接继续可用，
+//! 协议层无法跳过的超大帧被拒绝后断开连接
+
+mod common;
+
+use common::{RECV_TIMEOUT, Server, TempConfig, accept_register, query, recv_type};
+use futures_util::{SinkExt, StreamExt};
+use taranis::detail::ChargingDetail;
+use taranis::message::{MSG, MessageType, RejectData, StatusData};
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpListener;
+use tokio::time::timeout;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::tungstenite::protocol::frame::Frame;
+use tokio_tungstenite::tungstenite::protocol::frame::coding::{Data, OpCode};
+
+/// 测试使用的消息大小上限
+const MAX_MESSAGE_SIZE: usize = 4 << 20;
+
+/// 接收帧直到 `done` 返回真，返回最后一帧
+async fn recv_frame_until(server: &mut Server, done: impl Fn(&Message) -> bool) -> Message {
+    loop {
+        let message = timeout(RECV_TIMEOUT, server.next())
+            .await
+            .expect("timed out waiting for a message")
+            .expect("connection closed")
+            .unwrap();
+        if done(&message) {
+            return message;
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_large_and_oversized_messages() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("ws://{}", listener.local_addr().unwrap());
+    let config = TempConfig::new(
+        "frames",
+        &format!(
+            "[websocket]\nmax_message_size = {}\n[charge]\nsize = 5\n",
+            MAX_MESSAGE_SIZE
+        ),
+    );
+    config.install();
+    let pile = common::spawn_pile(url);
+    let (mut server, _) = accept_register(&listener).await;
+
+    // 三个各带 1 MiB 附加字段的新详单放在一个文本消息中，分成 256 KiB 的帧发送
+    let note = "x".repeat(1 << 20);
+    let batch: String = (1..=3)
+        .map(|id| {
+            let mut detail = serde_json::to_value(ChargingDetail::test_new(id)).unwrap();
+            detail["note"] = note.clone().into();
+            let msg = MSG {
+                data: detail,
+                ..MSG::empty(MessageType::New)
+            };
+            serde_json::to_string(&msg).unwrap()
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    assert!(batch.len() > 3 << 20 && batch.len() < MAX_MESSAGE_SIZE);
+    let chunks: Vec<&[u8]> = batch.as_bytes().chunks(256 << 10).collect();
+    for (index, chunk) in chunks.iter().enumerate() {
+        let opcode = if index == 0 {
+            Data::Text
+        } else {
+            Data::Continue
+        };
+        let frame = Frame::message(
+            chunk.to_vec(),
+            OpCode::Data(opcode),
+            index + 1 == chunks.len(),
+        );
+        server.feed(Message::Frame(frame)).await.unwrap();
+    }
+    server.flush().await.unwrap();
+
+    // 二进制帧中的 JSON 按文本处理
+    server
+        .send(Message::Binary(br#"{"type": "query"}"#.to_vec().into()))
+        .await
+        .unwrap();
+    let status: StatusData = recv_type(&mut server, MessageType::Status)
+        .await
+        .payload()
+        .unwrap();
+    let charging = status.charging.unwrap();
+    assert_eq!(charging.get_id(), 1);
+    assert_eq!(
+        charging.get_extra()["note"].as_str().unwrap().len(),
+        1 << 20
+    );
+    let waiting: Vec<_> = status.queue.iter().map(|d| d.get_id()).collect();
+    assert_eq!(waiting, [2, 3]);
+
+    // 回复 Ping
+    server
+        .send(Message::Ping(b"probe".to_vec().into()))
+        .await
+        .unwrap();
+    let pong = recv_frame_until(&mut server, Message::is_pong).await;
+    assert_eq!(pong.into_data().as_ref(), b"probe");
+
+    // 超过上限的消息被拒绝，连接继续可用
+    let oversized = format!(
+        "{{\"type\": \"new\", \"data\": \"{}\"}}",
+        "y".repeat(5 << 20)
+    );
+    server
+        .send(Message::Text(oversized.clone().into()))
+        .await
+        .unwrap();
+    let reject: RejectData = recv_type(&mut server, MessageType::Reject)
+        .await
+        .payload()
+        .unwrap();
+    assert_eq!(reject.id, 0);
+    assert!(
+        reject.reason.contains(&oversized.len().to_string())
+            && reject.reason.contains(&MAX_MESSAGE_SIZE.to_string()),
+        "{}",
+        reject.reason
+    );
+    assert_eq!(query(&mut server).await.queue.len(), 2);
+
+    // 声明长度为 1 TiB 的文本帧无法跳过，拒绝后断开连接
+    let mut header = vec![0x81, 127];
+    header.extend_from_slice(&(1u64 << 40).to_be_bytes());
+    server.get_mut().write_all(&header).await.unwrap();
+    let reject: RejectData = recv_type(&mut server, MessageType::Reject)
+        .await
+        .payload()
+        .unwrap();
+    assert!(
+        reject.reason.contains(&(1u64 << 40).to_string()),
+        "{}",
+        reject.reason
+    );
+    timeout(RECV_TIMEOUT, pile).await.unwrap().unwrap();
+}
@@ -0,0 +1,72 @@
+//! 跳过空闲时间测试：充电桩空闲后请求跳过，场景服务器同意跳到下一个定时步骤，
+//! 虚拟时钟推进后立即收到该步骤的新详单，不需要等待真实时间
+
+mod common;
+
+use common::TempConfig;
+use futures_util::StreamExt;
+use taranis::conf::CONF;
+use taranis::message::{MSG, MessageType, RegisterAckData, RegisterPayload};
+use taranis::scenario::{self, Scenario};
+use tokio::net::TcpListener;
+use tokio::time::{Duration, Instant, timeout};
+use tokio_tungstenite::tungstenite::Message;
+
+#[tokio::test]
+async fn test_idle_skip_granted_until_next_step() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("ws://{}", listener.local_addr().unwrap());
+    let config = TempConfig::new(
+        "idle-skip",
+        "[time]\nspeed = 60.0\nstart_time = \"2025-06-01T00:00:00Z\"\nskip_idle = true\nskip_idle_after_ms = 200\n",
+    );
+    config.install();
+    let pile = common::spawn_pile(url);
+    let (mut outgoing, mut incoming) = common::accept(&listener).await.split();
+    let report = |_: String| {};
+    let register = loop {
+        let msg = scenario::recv(&mut outgoing, &mut incoming, None, &report)
+            .await
+            .unwrap()
+            .unwrap();
+        if msg.type_ == MessageType::Register {
+            break msg.payload::<RegisterPayload>().unwrap();
+        }
+    };
+    let ack = MSG::with_payload(
+        MessageType::RegisterAck,
+        &RegisterAckData::accept(&register),
+    );
+    futures_util::SinkExt::send(
+        &mut outgoing,
+        Message::Text(serde_json::to_string(&ack).unwrap().into()),
+    )
+    .await
+    .unwrap();
+
+    // 详单在一小时（真实时间）后发送，跳过空闲时间后立即发送
+    let scenario = Scenario::from_toml(
+        r#"
+[[steps]]
+at = "1h"
+send = "new"
+data = { id = 7, request_amount = 1.0 }
+[[steps]]
+expect = "update"
+data = { id = 7, status = "charging" }
+within = "10s"
+"#,
+    )
+    .unwrap();
+    let started = Instant::now();
+    scenario::run_steps(&mut outgoing, &mut incoming, &scenario, &report)
+        .await
+        .unwrap();
+    assert!(started.elapsed() < Duration::from_secs(30));
+
+    // 一小时真实时间按 60 倍加速对应 60 个虚拟小时
+    let skipped_to = CONF.time.start_time.unwrap() + chrono::Duration::hours(59);
+    assert!(taranis::time::get_mock_now() > skipped_to);
+    drop((outgoing, incoming));
+    timeout(common::RECV_TIMEOUT, pile).await.unwrap().unwrap();
+}
@@ -0,0 +1,50 @@
+//! 维护窗口测试：队列为空时，维护开始前到达、无法在维护开始前充完的新详单被拒绝，
+//! 能够充完的新详单照常开始充电
+
+mod common;
+
+use common::{TempConfig, accept_register, query, recv_type, send};
+use taranis::detail::ChargingDetail;
+use taranis::message::{MSG, MessageType, RejectData};
+use tokio::net::TcpListener;
+use tokio::time::timeout;
+
+#[tokio::test]
+async fn test_new_before_window_with_empty_queue() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("ws://{}", listener.local_addr().unwrap());
+    // 虚拟时间从维护开始前 5 分钟开始，充电桩功率 30kW
+    let config = TempConfig::new(
+        "maintenance",
+        "[time]\ntz = \"UTC\"\nspeed = 1.0\nstart_time = \"2025-06-01T17:55:00Z\"\n\
+         [charge]\npower = 30.0\nmaintenance_policy = \"drain\"\n\
+         [[charge.maintenance_windows]]\nstart = \"18:00:00\"\nend = \"18:30:00\"\n",
+    );
+    config.install();
+    let pile = common::spawn_pile(url);
+    let (mut server, _) = accept_register(&listener).await;
+
+    // 队列为空，1 小时的会话无法在 18:00 前充完
+    let long = ChargingDetail::test_new(1).with_request_amount(30.0);
+    send(&mut server, &MSG::with_payload(MessageType::New, &long)).await;
+    let reject: RejectData = recv_type(&mut server, MessageType::Reject)
+        .await
+        .payload()
+        .unwrap();
+    assert_eq!((reject.id, reject.reason.as_str()), (1, "maintenance"));
+
+    // 2 分钟的会话可以充完，照常开始充电
+    let short = ChargingDetail::test_new(2).with_request_amount(1.0);
+    send(&mut server, &MSG::with_payload(MessageType::New, &short)).await;
+    let update: ChargingDetail = recv_type(&mut server, MessageType::Update)
+        .await
+        .payload()
+        .unwrap();
+    assert_eq!(update.get_id(), 2);
+    let status = query(&mut server).await;
+    assert_eq!(status.charging.map(|d| d.get_id()), Some(2));
+    assert!(status.queue.is_empty());
+
+    drop(server);
+    timeout(common::RECV_TIMEOUT, pile).await.unwrap().unwrap();
+}
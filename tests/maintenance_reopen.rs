@@ -0,0 +1,57 @@
+//! 维护结束测试：维护时间结束后充电桩重新注册，并主动发送一次状态快照，之后照常接收新详单
+
+mod common;
+
+use chrono::{DateTime, Utc};
+use common::{TempConfig, accept_register, recv_type, send};
+use taranis::detail::ChargingDetail;
+use taranis::message::{MSG, MessageType, RegisterAckData, RegisterPayload, StatusData};
+use tokio::net::TcpListener;
+use tokio::time::timeout;
+
+#[tokio::test]
+async fn test_status_after_maintenance_ends() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("ws://{}", listener.local_addr().unwrap());
+    // 虚拟时间从维护开始前 30 秒开始，5 分钟的维护窗口按 600 倍加速约 0.5 秒
+    let config = TempConfig::new(
+        "maintenance-reopen",
+        "[time]\ntz = \"UTC\"\nspeed = 600.0\nstart_time = \"2025-06-01T17:59:30Z\"\n\
+         [charge]\nmaintenance_policy = \"drain\"\n\
+         [[charge.maintenance_windows]]\nstart = \"18:00:00\"\nend = \"18:05:00\"\n",
+    );
+    config.install();
+    let pile = common::spawn_pile(url);
+    let (mut server, _) = accept_register(&listener).await;
+
+    // 维护结束后重新注册，随后发送状态快照
+    let register: RegisterPayload = recv_type(&mut server, MessageType::Register)
+        .await
+        .payload()
+        .unwrap();
+    let ack = RegisterAckData::accept(&register);
+    send(
+        &mut server,
+        &MSG::with_payload(MessageType::RegisterAck, &ack),
+    )
+    .await;
+    let status: StatusData = recv_type(&mut server, MessageType::Status)
+        .await
+        .payload()
+        .unwrap();
+    let reopened: DateTime<Utc> = "2025-06-01T18:05:00Z".parse().unwrap();
+    assert!(status.virtual_time >= reopened, "{}", status.virtual_time);
+    assert!(!status.working && status.charging.is_none() && status.queue.is_empty());
+
+    // 恢复服务后照常开始充电
+    let detail = ChargingDetail::test_new(1).with_request_amount(1.0);
+    send(&mut server, &MSG::with_payload(MessageType::New, &detail)).await;
+    let update: ChargingDetail = recv_type(&mut server, MessageType::Update)
+        .await
+        .payload()
+        .unwrap();
+    assert_eq!(update.get_id(), 1);
+
+    drop(server);
+    timeout(common::RECV_TIMEOUT, pile).await.unwrap().unwrap();
+}
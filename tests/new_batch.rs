@@ -0,0 +1,96 @@
+//! 批量新详单测试：数组中间的详单无效时，其余详单按数组顺序加入队列，
+//! 被拒绝的详单汇总在一条拒绝消息中；数据不是数组时回复错误消息
+
+mod common;
+
+use common::{Server, TempConfig, accept_register, query, recv_type, send};
+use taranis::conf::{CONF, ChargeType};
+use taranis::detail::ChargingDetail;
+use taranis::message::{ErrorData, MSG, MessageType, RejectData};
+use tokio::net::TcpListener;
+use tokio::time::timeout;
+
+/// 查询充电桩状态，返回正在充电和等待中的详单 ID
+async fn queue_ids(server: &mut Server) -> Vec<u32> {
+    let status = query(server).await;
+    status
+        .charging
+        .iter()
+        .chain(&status.queue)
+        .map(|d| d.get_id())
+        .collect()
+}
+
+fn batch(details: &[ChargingDetail]) -> MSG {
+    MSG::with_payload(MessageType::NewBatch, &details)
+}
+
+#[tokio::test]
+async fn test_batch_with_invalid_middle_element() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("ws://{}", listener.local_addr().unwrap());
+    let config = TempConfig::new("new-batch", "[charge]\nsize = 5\n");
+    config.install();
+    let pile = common::spawn_pile(url);
+    let (mut server, register) = accept_register(&listener).await;
+    assert!(register.features.iter().any(|f| f == "new_batch"));
+
+    // 中间的详单充电类型与充电桩不符，前后两个详单按顺序加入队列
+    let other_type = match CONF.charge.charge_type {
+        ChargeType::Fast => ChargeType::Slow,
+        ChargeType::Slow => ChargeType::Fast,
+    };
+    let details = [
+        ChargingDetail::test_new(1),
+        ChargingDetail::test_new(2).with_type(other_type),
+        ChargingDetail::test_new(3),
+    ];
+    send(&mut server, &batch(&details)).await;
+    let reject = recv_type(&mut server, MessageType::Reject).await;
+    let reject: RejectData = reject.payload().unwrap();
+    assert_eq!((reject.id, reject.reason.as_str()), (2, "type_mismatch"));
+    assert_eq!(
+        reject.rejected,
+        [RejectData {
+            id: 2,
+            reason: "type_mismatch".to_string(),
+            rejected: Vec::new(),
+        }]
+    );
+    // 拒绝消息之后才开始充电
+    let update = recv_type(&mut server, MessageType::Update).await;
+    assert_eq!(update.payload::<ChargingDetail>().unwrap().get_id(), 1);
+    assert_eq!(queue_ids(&mut server).await, [1, 3]);
+
+    // 中间的详单已在队列中，其余详单依次排在后面
+    let details = [
+        ChargingDetail::test_new(4),
+        ChargingDetail::test_new(3),
+        ChargingDetail::test_new(5),
+    ];
+    send(&mut server, &batch(&details)).await;
+    let reject = recv_type(&mut server, MessageType::Reject).await;
+    let reject: RejectData = reject.payload().unwrap();
+    let rejected: Vec<_> = reject
+        .rejected
+        .iter()
+        .map(|r| (r.id, r.reason.as_str()))
+        .collect();
+    assert_eq!(rejected, [(3, "duplicate_id")]);
+    assert_eq!(queue_ids(&mut server).await, [1, 3, 4, 5]);
+
+    // 数据不是数组时回复错误消息，队列不变
+    let detail = ChargingDetail::test_new(6);
+    send(
+        &mut server,
+        &MSG::with_payload(MessageType::NewBatch, &detail),
+    )
+    .await;
+    let error = recv_type(&mut server, MessageType::Error).await;
+    let error: ErrorData = error.payload().unwrap();
+    assert!(error.reason.contains("array"), "{}", error.reason);
+    assert_eq!(queue_ids(&mut server).await, [1, 3, 4, 5]);
+
+    drop(server);
+    timeout(common::RECV_TIMEOUT, pile).await.unwrap().unwrap();
+}
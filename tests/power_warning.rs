@@ -0,0 +1,55 @@
+//! 功率不一致测试：非严格模式下期望功率与充电桩功率不符的新详单仍然加入队列，
+//! 即使没有配置 `websocket.ack_new` 也回复带有两个功率的警告确认消息
+
+mod common;
+
+use common::{TempConfig, accept_register, query, recv_type, send};
+use taranis::detail::ChargingDetail;
+use taranis::message::{AckData, MSG, MessageType, PowerWarning};
+use tokio::net::TcpListener;
+use tokio::time::timeout;
+
+#[tokio::test]
+async fn test_power_mismatch_warning_ack() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("ws://{}", listener.local_addr().unwrap());
+    let config = TempConfig::new(
+        "power-warning",
+        "[websocket]\nack_new = false\n[charge]\nsize = 5\npower = 30.0\n\
+         power_tolerance = 0.5\nstrict_power_match = false\n",
+    );
+    config.install();
+    let pile = common::spawn_pile(url);
+    let (mut server, _) = accept_register(&listener).await;
+
+    // 偏差在允许范围内时不回复确认，收到的第一条确认是第二个详单的
+    let matching = ChargingDetail::test_new(1).with_expected_power(30.5);
+    send(&mut server, &MSG::with_payload(MessageType::New, &matching)).await;
+    let mismatched = ChargingDetail::test_new(2).with_expected_power(60.0);
+    send(
+        &mut server,
+        &MSG::with_payload(MessageType::New, &mismatched),
+    )
+    .await;
+    let ack: AckData = recv_type(&mut server, MessageType::Ack)
+        .await
+        .payload()
+        .unwrap();
+    assert_eq!((ack.id, ack.position), (2, 1));
+    assert_eq!(
+        ack.warning,
+        Some(PowerWarning {
+            expected_power: 60.0,
+            pile_power_kw: 30.0,
+        })
+    );
+
+    // 两个详单都加入了队列
+    let status = query(&mut server).await;
+    assert_eq!(status.charging.map(|d| d.get_id()), Some(1));
+    let waiting: Vec<_> = status.queue.iter().map(|d| d.get_id()).collect();
+    assert_eq!(waiting, [2]);
+
+    drop(server);
+    timeout(common::RECV_TIMEOUT, pile).await.unwrap().unwrap();
+}
@@ -0,0 +1,89 @@
+//! 价格表重新加载测试：充电过程中更换价格表后，已经充电的部分保留原价格表的费用，
+//! 之后充电的部分按新价格表计费
+//!
+//! 全局价格表在进程内共享，因此放在单独的测试程序中
+
+mod common;
+
+use chrono::Duration;
+use common::TempConfig;
+use taranis::charge::Charge;
+use taranis::conf::{CONF, ChargeType};
+use taranis::detail::ChargingDetail;
+use taranis::price;
+
+/// 全天同一电价、不收服务费的价格表
+fn flat_prices(price: f64) -> String {
+    format!(
+        r#"{{"periods": [
+            {{"start": "00:00:00", "end": "12:00:00", "price": {price}, "label": "flat"}},
+            {{"start": "12:00:00", "end": "00:00:00", "price": {price}, "label": "flat"}}
+        ], "service_fee": 0.0}}"#
+    )
+}
+
+#[test]
+fn test_reload_keeps_accrued_cost() {
+    let prices = TempConfig::new("reload-prices", &flat_prices(1.0));
+    let config = TempConfig::new(
+        "reload-prices-conf",
+        &format!(
+            "[time]\ntz = \"UTC\"\nstart_time = \"2025-06-01T08:00:00Z\"\n[price]\npath = {:?}\n",
+            prices.path().display().to_string()
+        ),
+    );
+    config.install();
+    assert_eq!(CONF.price.path, prices.path().display().to_string());
+
+    let mut charge = Charge::new(ChargeType::Fast, 30.0, 1);
+    charge
+        .add_detail(ChargingDetail::test_new(1).with_request_amount(60.0))
+        .unwrap();
+    charge.start_charging();
+    let start = charge.get_charging_detail_ref().unwrap().clone_start_time();
+
+    // 第一个小时按 1.0 计费
+    charge.update_charging_at(start + Duration::hours(1));
+    let detail = charge.get_charging_detail_ref().unwrap();
+    assert_eq!(detail.get_already_charged(), 30.0);
+    assert_eq!(detail.get_total_cost(), 30.0);
+
+    // 无法解析的价格表被拒绝，保留原价格表
+    std::fs::write(prices.path(), "{ not json").unwrap();
+    let path = prices.path().to_str().unwrap();
+    assert!(price::reload_prices_at(path, start + Duration::hours(1)).is_err());
+    charge.update_charging_at(start + Duration::minutes(90));
+    assert_eq!(
+        charge.get_charging_detail_ref().unwrap().get_total_cost(),
+        45.0
+    );
+
+    // 充电 90 分钟后电价改为 2.0，之前的 45 度仍按 1.0 计费
+    std::fs::write(prices.path(), flat_prices(2.0)).unwrap();
+    price::reload_prices_at(path, start + Duration::minutes(90)).unwrap();
+    charge.update_charging_at(start + Duration::minutes(90));
+    assert_eq!(
+        charge.get_charging_detail_ref().unwrap().get_total_cost(),
+        45.0
+    );
+    charge.update_charging_at(start + Duration::minutes(100));
+    assert_eq!(
+        charge.get_charging_detail_ref().unwrap().get_total_cost(),
+        55.0
+    );
+
+    let completed = charge
+        .complete_charging_at(1, start + Duration::hours(2))
+        .unwrap();
+    assert_eq!(completed.get_already_charged(), 60.0);
+    assert_eq!(completed.get_total_cost(), 45.0 + 15.0 * 2.0);
+    // 按时段统计时两个价格表的同名时段合并，账单明细在更换价格表的时刻分开
+    let per_period = completed.get_per_period();
+    assert_eq!(per_period.len(), 1);
+    assert_eq!((per_period[0].kwh, per_period[0].cost), (60.0, 75.0));
+    let breakdown = completed.get_breakdown().unwrap();
+    assert_eq!(breakdown.charge_cost, 75.0);
+    let unit_prices: Vec<f64> = breakdown.items.iter().map(|item| item.unit_price).collect();
+    assert_eq!(unit_prices, [1.0, 2.0]);
+    assert_eq!(breakdown.items[1].start, start + Duration::minutes(90));
+}
@@ -0,0 +1,102 @@
+//! 配置重载测试：充电中修改配置文件并发送 SIGHUP，充电桩从新路径加载价格表，
+//! 迁移到新的 WebSocket 地址后继续充电，不允许在运行时修改的字段被忽略
+
+#![cfg(unix)]
+
+mod common;
+
+use common::{TempConfig, accept_register, recv_type, recv_until, send};
+use futures_util::StreamExt;
+use taranis::conf::{CONF, ChargeType};
+use taranis::detail::{ChargeStatus, ChargingDetail};
+use taranis::message::{MSG, MessageType};
+use taranis::price::{self, Prices};
+use tokio::net::TcpListener;
+use tokio::time::timeout;
+use tokio_tungstenite::tungstenite::Message;
+
+/// 全天同一电价的价格表
+fn flat_prices(price: f64) -> String {
+    format!(
+        r#"{{"periods": [
+            {{"start": "00:00:00", "end": "12:00:00", "price": {price}}},
+            {{"start": "12:00:00", "end": "00:00:00", "price": {price}}}
+        ]}}"#
+    )
+}
+
+/// 配置文件内容
+fn config(url: &str, prices: &TempConfig, charge_type: &str) -> String {
+    format!(
+        "[websocket]\nurl = {:?}\n[price]\npath = {:?}\n[charge]\ncharge_type = {:?}\n",
+        url,
+        prices.path().display().to_string(),
+        charge_type
+    )
+}
+
+#[tokio::test]
+async fn test_reload_migrates_prices_and_url() {
+    let old = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let new = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let old_url = format!("ws://{}", old.local_addr().unwrap());
+    let new_url = format!("ws://{}", new.local_addr().unwrap());
+    let old_prices = TempConfig::new("reload-old-prices", &flat_prices(1.0));
+    let new_prices = TempConfig::new("reload-new-prices", &flat_prices(2.0));
+    let conf = TempConfig::new("reload", &config(&old_url, &old_prices, "F"));
+    conf.install();
+    assert_eq!(CONF.charge.charge_type, ChargeType::Fast);
+    let pile = common::spawn_pile(old_url);
+
+    // 旧地址上开始充电
+    let (mut server, register) = accept_register(&old).await;
+    let old_fingerprint =
+        price::fingerprint_hex(&Prices::from_path(old_prices.path().to_str().unwrap()).unwrap())
+            .unwrap();
+    assert_eq!(register.price_fingerprint, Some(old_fingerprint));
+    let detail = ChargingDetail::test_new(1).with_request_amount(1000.0);
+    send(&mut server, &MSG::with_payload(MessageType::New, &detail)).await;
+    recv_type(&mut server, MessageType::Update).await;
+
+    // 修改价格表路径、WebSocket 地址和不允许在运行时修改的充电类型
+    std::fs::write(conf.path(), config(&new_url, &new_prices, "T")).unwrap();
+    let status = std::process::Command::new("kill")
+        .args(["-HUP", &std::process::id().to_string()])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    // 充电桩先完成与新地址的握手，再关闭旧连接
+    let migrated = tokio::spawn(async move { accept_register(&new).await });
+
+    // 旧连接以 reconfiguring 关闭
+    let reason = timeout(common::RECV_TIMEOUT, async {
+        loop {
+            match server.next().await {
+                Some(Ok(Message::Close(frame))) => {
+                    return frame.map(|frame| frame.reason.to_string());
+                }
+                Some(Ok(_)) => continue,
+                other => panic!("connection ended without a close frame: {:?}", other),
+            }
+        }
+    })
+    .await
+    .expect("timed out waiting for the close frame");
+    assert_eq!(reason.as_deref(), Some("reconfiguring"));
+
+    // 新地址上重新注册，使用新价格表，充电类型保持不变，正在充电的详单继续充电
+    let (mut server, register) = migrated.await.unwrap();
+    let new_fingerprint =
+        price::fingerprint_hex(&Prices::from_path(new_prices.path().to_str().unwrap()).unwrap())
+            .unwrap();
+    assert_eq!(register.price_fingerprint, Some(new_fingerprint));
+    assert_eq!(register.url.as_deref(), Some(new_url.as_str()));
+    assert_eq!(register.type_, ChargeType::Fast);
+    let update = recv_until(&mut server, |msg| msg.type_ == MessageType::Update).await;
+    let update: ChargingDetail = update.payload().unwrap();
+    assert_eq!(update.get_id(), 1);
+    assert_eq!(update.get_status(), ChargeStatus::Charging);
+
+    drop(server);
+    timeout(common::RECV_TIMEOUT, pile).await.unwrap().unwrap();
+}
@@ -0,0 +1,75 @@
+//! 时间同步测试：充电桩按注册确认中的服务器虚拟时间调整虚拟时钟，
+//! 重新连接后向后调整时，正在充电的详单的时间同样平移，已充电度数不会倒退
+
+mod common;
+
+use common::{TempConfig, accept_register_with, recv_until, send};
+use taranis::conf::CONF;
+use taranis::detail::{ChargeStatus, ChargingDetail};
+use taranis::message::{MSG, MessageType, RegisterAckData};
+use tokio::net::TcpListener;
+use tokio::time::timeout;
+
+/// 正在充电的指定详单
+fn charging(msg: &MSG, id: u32) -> Option<ChargingDetail> {
+    let detail: ChargingDetail = msg.payload().ok()?;
+    (msg.type_ == MessageType::Update
+        && detail.get_id() == id
+        && detail.get_status() == ChargeStatus::Charging)
+        .then_some(detail)
+}
+
+#[tokio::test]
+async fn test_register_ack_syncs_clock() {
+    let primary = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let standby = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let primary_url = format!("ws://{}", primary.local_addr().unwrap());
+    let standby_url = format!("ws://{}", standby.local_addr().unwrap());
+    let config = TempConfig::new(
+        "time-sync",
+        &format!(
+            "[websocket]\nurls = [{:?}, {:?}]\n[time]\nspeed = 600.0\nstart_time = \"2025-06-01T00:00:00Z\"\n",
+            primary_url, standby_url
+        ),
+    );
+    config.install();
+    let pile = common::spawn_pile(primary_url);
+
+    // 服务器时间比充电桩晚一天，充电桩调整后开始充电
+    let server_time = CONF.time.start_time.unwrap() + chrono::Duration::days(1);
+    let (mut server, _) = accept_register_with(&primary, |payload| RegisterAckData {
+        server_time: Some(server_time),
+        ..RegisterAckData::accept(payload)
+    })
+    .await;
+    let detail = ChargingDetail::test_new(1).with_request_amount(1000.0);
+    send(&mut server, &MSG::with_payload(MessageType::New, &detail)).await;
+    let before = recv_until(&mut server, |msg| charging(msg, 1).is_some()).await;
+    let before = charging(&before, 1).unwrap();
+    let start = before.get_start_time().unwrap();
+    assert!(start >= server_time);
+    assert!(start - server_time < chrono::Duration::hours(1));
+    drop(server);
+    drop(primary);
+
+    // 备用服务器的时间早一天，正在充电的详单的开始时间同样提前，已充电度数继续增加
+    let standby_time = CONF.time.start_time.unwrap();
+    let (mut server, _) = accept_register_with(&standby, |payload| RegisterAckData {
+        server_time: Some(standby_time),
+        ..RegisterAckData::accept(payload)
+    })
+    .await;
+    let after = recv_until(&mut server, |msg| {
+        charging(msg, 1).is_some_and(|after| after.get_start_time() != Some(start))
+    })
+    .await;
+    let after = charging(&after, 1).unwrap();
+    let shifted = start - after.get_start_time().unwrap();
+    assert!(shifted >= chrono::Duration::days(1) && shifted < chrono::Duration::hours(25));
+    assert!(after.get_already_charged() >= before.get_already_charged());
+    assert!(after.get_last_update_time().unwrap() < server_time);
+
+    drop(server);
+    drop(standby);
+    timeout(common::RECV_TIMEOUT, pile).await.unwrap().unwrap();
+}
@@ -0,0 +1,79 @@
+//! 状态更新限速测试：高加速比下充电循环产生大量状态更新，
+//! 设置了最小更新间隔时发送频率不超过上限，完成消息立即发送并带有正确的充电量
+
+mod common;
+
+use common::{TempConfig, accept_register, recv, recv_type, send};
+use taranis::detail::{ChargeStatus, ChargingDetail};
+use taranis::message::{MSG, MessageType, StatusData};
+use tokio::net::TcpListener;
+use tokio::time::{Instant, timeout};
+
+/// 同一详单两次状态更新之间的最小间隔（真实时间）
+const MIN_GAP_MS: u64 = 250;
+
+#[tokio::test]
+async fn test_updates_are_rate_limited() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("ws://{}", listener.local_addr().unwrap());
+    // 每 50 毫秒（真实时间）产生一次状态更新，10 度电需要 2 秒（真实时间）
+    let config = TempConfig::new(
+        "update-rate",
+        &format!(
+            "[websocket]\nmin_update_gap_ms = {}\n[time]\nspeed = 600.0\nupdate_interval = 30000\n",
+            MIN_GAP_MS
+        ),
+    );
+    config.install();
+    let pile = common::spawn_pile(url);
+    let (mut server, _) = accept_register(&listener).await;
+
+    let detail = ChargingDetail::test_new(1).with_request_amount(10.0);
+    send(&mut server, &MSG::with_payload(MessageType::New, &detail)).await;
+    let started = Instant::now();
+    let mut updates = 0;
+    let complete = loop {
+        let msg = recv(&mut server).await;
+        match msg.type_ {
+            MessageType::Update => updates += 1,
+            MessageType::Complete => break msg.payload::<ChargingDetail>().unwrap(),
+            _ => {}
+        }
+    };
+    let elapsed = started.elapsed();
+
+    // 第一条更新立即发送，之后每个间隔最多发送一条
+    assert!(updates >= 2, "only {} updates", updates);
+    let cap = elapsed.as_millis() as u64 / MIN_GAP_MS + 2;
+    assert!(
+        updates <= cap,
+        "{} updates in {:?} exceeds {}",
+        updates,
+        elapsed,
+        cap
+    );
+    assert_eq!(complete.get_status(), ChargeStatus::Completed);
+    assert!((complete.get_already_charged() - 10.0).abs() < 1e-6);
+
+    // 状态快照中产生的更新多于发送的更新
+    send(&mut server, &MSG::empty(MessageType::Query)).await;
+    let status: StatusData = recv_type(&mut server, MessageType::Status)
+        .await
+        .payload()
+        .unwrap();
+    assert_eq!(status.updates_sent, updates);
+    // 当前连接的消息计数与服务器收到的消息一致，查询本身也已计入
+    let traffic = status.traffic.unwrap();
+    assert_eq!(traffic.sent[&MessageType::Update], updates);
+    assert_eq!(traffic.received[&MessageType::Query], 1);
+    assert!(traffic.last_sent_real.is_some() && traffic.last_sent_virtual.is_some());
+    assert!(
+        status.updates_generated > status.updates_sent,
+        "{} generated, {} sent",
+        status.updates_generated,
+        status.updates_sent
+    );
+
+    drop(server);
+    timeout(common::RECV_TIMEOUT, pile).await.unwrap().unwrap();
+}